@@ -5,11 +5,15 @@ use color_eyre::Result;
 use crossterm::event::KeyEvent;
 use rat_focus::{Focus, FocusBuilder};
 use ratatui::layout::Rect;
-use roxy_proxy::flow::FlowStore;
+use roxy_proxy::flow::{Flow, FlowStore, id::FlowId};
 use roxy_proxy::proxy::ProxyManager;
+use roxy_shared::upstream::UpstreamProxy;
+use time::OffsetDateTime;
 use tokio::sync::mpsc;
 
-use crate::config::ConfigManager;
+use crate::ca;
+use crate::command::{Command, FlowFormat};
+use crate::config::{ConfigManager, ScriptConfig};
 use crate::event::{Action, Mode};
 use crate::tui::{Event, Tui};
 use crate::ui::framework::component::{ActionResult, Component, KeyEventResult};
@@ -17,11 +21,14 @@ use crate::ui::framework::notify::Notifier;
 use crate::ui::framework::theme::set_theme;
 use crate::ui::home::HomeComponent;
 use crate::ui::log::LogLine;
+use crate::{notify_error, notify_info};
 
 pub const ITEM_HEIGHT: usize = 4;
 
+type ExportCommand = fn(&Flow, Option<&UpstreamProxy>) -> Option<String>;
+
 pub struct App {
-    _proxy_manager: ProxyManager,
+    proxy_manager: ProxyManager,
     config_manager: ConfigManager,
     home: HomeComponent,
     should_quit: bool,
@@ -44,11 +51,12 @@ impl App {
         let home = HomeComponent::new(
             config_manager.clone(),
             flow_store.clone(),
+            proxy_manager.cxt(),
             log_buffer.clone(),
             notifier,
         );
         Self {
-            _proxy_manager: proxy_manager,
+            proxy_manager,
             config_manager,
             home,
             should_quit: false,
@@ -150,6 +158,29 @@ impl App {
                 Action::FocusPrev => {
                     focus.prev();
                 }
+                Action::ReplayFlow(id) => self.replay(id),
+                Action::ReplayFlows(ref ids) => ids.iter().for_each(|id| self.replay(*id)),
+                Action::SaveFlows(ref ids) => self.save_flows(ids.clone()),
+                Action::SaveWsMessages(id) => self.save_ws_messages(id),
+                Action::SaveRaw(id) => self.save_raw(id),
+                Action::ToggleCapture => {
+                    let capturing = !self.proxy_manager.capturing();
+                    self.proxy_manager.set_capturing(capturing);
+                    if capturing {
+                        notify_info!("Capture resumed");
+                    } else {
+                        notify_info!("Capture paused, traffic is still proxied");
+                    }
+                }
+                Action::CycleCaptureProfile => self.cycle_capture_profile(),
+                Action::RunCommand(ref line) => self.run_command(line)?,
+                Action::CopyCurlFlow(id) => self.copy_flow(id, Flow::to_curl),
+                Action::CopyHttpieFlow(id) => self.copy_flow(id, Flow::to_httpie),
+                Action::CopyReqwestFlow(id) => self.copy_flow(id, Flow::to_reqwest),
+                Action::CopyToClipboard(ref text) => match tui.copy_to_clipboard(text) {
+                    Ok(()) => notify_info!("Copied to clipboard"),
+                    Err(err) => notify_error!("Copy failed {err}"),
+                },
                 _ => {}
             }
             if let ActionResult::Action(action) = self.home.update(action.clone()) {
@@ -159,6 +190,273 @@ impl App {
         Ok(())
     }
 
+    fn replay(&self, id: FlowId) {
+        let replay = self.proxy_manager.replay(id);
+        tokio::spawn(async move {
+            if let Err(err) = replay.await {
+                notify_error!("Replay failed {err}");
+            }
+        });
+    }
+
+    /// Writes the flows in `ids` to a new session file in the data directory,
+    /// it is loaded again with `--session`.
+    fn save_flows(&self, ids: Vec<FlowId>) {
+        let flow_store = self.proxy_manager.cxt().flow_store;
+        let dir = self.config_manager.rx.borrow().app.data_dir.join("exports");
+        tokio::spawn(async move {
+            if let Err(err) = tokio::fs::create_dir_all(&dir).await {
+                notify_error!("Export failed {err}");
+                return;
+            }
+            let stamp = OffsetDateTime::now_utc().unix_timestamp();
+            let path = dir.join(format!("flows-{stamp}.jsonl"));
+            match flow_store.save_flows(&path, &ids).await {
+                Ok(count) => notify_info!("Exported {count} flows to {}", path.display()),
+                Err(err) => notify_error!("Export failed {err}"),
+            }
+        });
+    }
+
+    fn save_ws_messages(&self, id: FlowId) {
+        let flow_store = self.proxy_manager.cxt().flow_store;
+        let dir = self.config_manager.rx.borrow().app.data_dir.join("exports");
+        tokio::spawn(async move {
+            if let Err(err) = tokio::fs::create_dir_all(&dir).await {
+                notify_error!("Export failed {err}");
+                return;
+            }
+            let stamp = OffsetDateTime::now_utc().unix_timestamp();
+            let path = dir.join(format!("ws-{id}-{stamp}.jsonl"));
+            match flow_store.export_ws_messages(id, &path).await {
+                Ok(count) => notify_info!("Exported {count} messages to {}", path.display()),
+                Err(err) => notify_error!("Export failed {err}"),
+            }
+        });
+    }
+
+    fn save_raw(&self, id: FlowId) {
+        let flow_store = self.proxy_manager.cxt().flow_store;
+        let dir = self.config_manager.rx.borrow().app.data_dir.join("exports");
+        tokio::spawn(async move {
+            if let Err(err) = tokio::fs::create_dir_all(&dir).await {
+                notify_error!("Export failed {err}");
+                return;
+            }
+            let stamp = OffsetDateTime::now_utc().unix_timestamp();
+            let path = dir.join(format!("raw-{id}-{stamp}.txt"));
+            match flow_store.export_raw(id, &path).await {
+                Ok(count) => notify_info!("Exported {count} bytes to {}", path.display()),
+                Err(err) => notify_error!("Export failed {err}"),
+            }
+        });
+    }
+
+    /// Switches to the next capture profile in the config, going back to
+    /// recording everything after the last one.
+    fn cycle_capture_profile(&self) {
+        let mut cfg = self.config_manager.rx.borrow().clone();
+        let proxy = &mut cfg.app.proxy;
+        let next = match &proxy.capture_profile {
+            None => proxy.capture_profiles.first(),
+            Some(name) => proxy
+                .capture_profiles
+                .iter()
+                .skip_while(|p| &p.name != name)
+                .nth(1),
+        };
+        proxy.capture_profile = next.map(|p| p.name.clone());
+        match &proxy.capture_profile {
+            Some(name) => notify_info!("Recording flows matching capture profile {name}"),
+            None if proxy.capture_profiles.is_empty() => {
+                notify_info!("No capture profiles in the config");
+                return;
+            }
+            None => notify_info!("Recording every flow"),
+        }
+        if let Err(err) = self.config_manager.update(cfg) {
+            notify_error!("Failed to save the capture profile {err}");
+        }
+    }
+
+    /// Runs a line typed into the command palette, commands that touch a
+    /// component are sent on as actions.
+    fn run_command(&self, line: &str) -> Result<()> {
+        let command = match line.parse::<Command>() {
+            Ok(command) => command,
+            Err(err) => {
+                notify_error!("{err}");
+                return Ok(());
+            }
+        };
+        let cxt = self.proxy_manager.cxt();
+        match command {
+            Command::Filter(filter) => self.action_tx.send(Action::FilterFlows(filter))?,
+            Command::Action(action) => self.action_tx.send(action)?,
+            Command::Export(format, path) => {
+                tokio::spawn(async move {
+                    let flow_store = cxt.flow_store;
+                    let exported = match format {
+                        FlowFormat::Har => flow_store
+                            .export_har(&path)
+                            .await
+                            .map_err(|e| e.to_string()),
+                        FlowFormat::Mitmproxy => flow_store
+                            .export_mitmproxy(&path)
+                            .await
+                            .map_err(|e| e.to_string()),
+                        FlowFormat::Pcapng => flow_store
+                            .export_pcapng(&path)
+                            .await
+                            .map_err(|e| e.to_string()),
+                        FlowFormat::Metrics(format) => flow_store
+                            .export_metrics(&path, format)
+                            .await
+                            .map_err(|e| e.to_string()),
+                        FlowFormat::Session => {
+                            let ids = flow_store.ordered_ids.read().await.clone();
+                            flow_store
+                                .save_flows(&path, &ids)
+                                .await
+                                .map_err(|e| e.to_string())
+                        }
+                    };
+                    match exported {
+                        Ok(count) => notify_info!("Exported {count} flows to {}", path.display()),
+                        Err(err) => notify_error!("Export failed {err}"),
+                    }
+                });
+            }
+            Command::Import(format, path) => {
+                tokio::spawn(async move {
+                    let flow_store = cxt.flow_store;
+                    let imported = match format {
+                        FlowFormat::Har => flow_store
+                            .import_har(&path)
+                            .await
+                            .map_err(|e| e.to_string()),
+                        FlowFormat::Mitmproxy => flow_store
+                            .import_mitmproxy(&path)
+                            .await
+                            .map_err(|e| e.to_string()),
+                        FlowFormat::Session => flow_store
+                            .load_session(&path)
+                            .await
+                            .map_err(|e| e.to_string()),
+                        FlowFormat::Pcapng => Err("pcap files can not be imported".to_string()),
+                        FlowFormat::Metrics(_) => Err("metrics can not be imported".to_string()),
+                    };
+                    match imported {
+                        Ok(count) => notify_info!("Imported {count} flows from {}", path.display()),
+                        Err(err) => notify_error!("Import failed {err}"),
+                    }
+                });
+            }
+            Command::LoadScript(path) => {
+                let script = ScriptConfig {
+                    path,
+                    enabled: true,
+                    decode_bodies: true,
+                };
+                tokio::spawn(async move {
+                    if script.load(&cxt.script_engine).await {
+                        notify_info!("Loaded script {}", script.name());
+                    }
+                });
+            }
+            Command::EnableScript(name, enabled) => {
+                tokio::spawn(async move {
+                    if !cxt.script_engine.set_enabled(&name, enabled).await {
+                        notify_error!("No script {name}");
+                    } else if enabled {
+                        notify_info!("Enabled script {name}");
+                    } else {
+                        notify_info!("Disabled script {name}");
+                    }
+                });
+            }
+            Command::UnloadScript(name) => {
+                tokio::spawn(async move {
+                    if cxt.script_engine.remove_script(&name).await {
+                        notify_info!("Unloaded script {name}");
+                    } else {
+                        notify_error!("No script {name}");
+                    }
+                });
+            }
+            Command::Intercept(enabled) => {
+                cxt.host_filter.set_paused(!enabled);
+                if enabled {
+                    notify_info!("Interception resumed");
+                } else {
+                    notify_info!("Interception paused, connections are tunneled untouched");
+                }
+            }
+            Command::Capture(enabled) => {
+                self.proxy_manager.set_capturing(enabled);
+                if enabled {
+                    notify_info!("Capture resumed");
+                } else {
+                    notify_info!("Capture paused, traffic is still proxied");
+                }
+            }
+            Command::RawCapture(enabled) => {
+                self.proxy_manager.cxt().flow_store.set_raw_capture(enabled);
+                if enabled {
+                    notify_info!("Keeping the raw bytes of new HTTP/1 flows");
+                } else {
+                    notify_info!("Raw capture off");
+                }
+            }
+            Command::Ca(install) => {
+                tokio::task::spawn_blocking(move || {
+                    // sudo can not ask for a password under the TUI
+                    let outcomes = if install {
+                        ca::install(&cxt.ca, false)
+                    } else {
+                        ca::uninstall(&cxt.ca, false)
+                    };
+                    for outcome in outcomes {
+                        match outcome.result {
+                            Ok(()) if install => {
+                                notify_info!("Trusted the CA in {}", outcome.store)
+                            }
+                            Ok(()) => notify_info!("Removed the CA from {}", outcome.store),
+                            Err(err) => notify_error!("{} {err}", outcome.store),
+                        }
+                    }
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders flow `id` as a command or code and copies it once the flow is read.
+    fn copy_flow(&self, id: FlowId, command: ExportCommand) {
+        let cxt = self.proxy_manager.cxt();
+        let action_tx = self.action_tx.clone();
+        tokio::spawn(async move {
+            let Some(flow) = cxt.flow_store.get_flow_by_id(id).await else {
+                notify_error!("Flow {id} not found");
+                return;
+            };
+            let guard = flow.read().await;
+            let host = guard
+                .request
+                .as_ref()
+                .map(|r| r.uri.host().to_string())
+                .unwrap_or_default();
+            match command(&guard, cxt.upstream.resolve(&host).as_ref()) {
+                Some(text) => {
+                    if let Err(err) = action_tx.send(Action::CopyToClipboard(text)) {
+                        notify_error!("Copy failed {err}");
+                    }
+                }
+                None => notify_error!("Flow {id} has no request"),
+            }
+        });
+    }
+
     fn handle_resize(&mut self, tui: &mut Tui, w: u16, h: u16) -> Result<()> {
         tui.resize(Rect::new(0, 0, w, h))?;
         self.render(tui)?;
@@ -0,0 +1,8 @@
+use clap::Parser;
+use roxy_cli::rurl::{RurlArgs, run};
+
+#[tokio::main]
+async fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+    run(RurlArgs::parse()).await
+}
@@ -0,0 +1,359 @@
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use clap::{Args, Subcommand};
+use color_eyre::eyre::{Result, eyre};
+use roxy_proxy::cert_portal;
+use roxy_shared::{CaError, RoxyCA};
+use tempfile::NamedTempFile;
+
+use crate::config::{ProxyConfig, RoxyConfig};
+
+/// Nickname of the CA in NSS databases.
+const NSS_NAME: &str = "Roxy CA";
+
+/// Adds the root CA Roxy signs with to the trust stores of this machine, or
+/// removes it again, so clients accept intercepted connections.
+#[derive(Args, Debug, Clone)]
+pub struct CaArgs {
+    #[command(subcommand)]
+    command: CaCommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum CaCommand {
+    /// Trust the CA in the system store and the Firefox and Chrome NSS databases found.
+    Install,
+    /// Remove the CA from every store `install` puts it in.
+    Uninstall,
+}
+
+/// What happened to the CA in one trust store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreOutcome {
+    pub store: String,
+    pub result: Result<(), String>,
+}
+
+/// The configured CA, or the one generated under `~/.roxy`.
+pub fn load_ca(proxy: &ProxyConfig) -> Result<RoxyCA, CaError> {
+    match proxy.ca_source() {
+        Some(source) => {
+            roxy_shared::import_roxy_root_ca(&source, proxy.key_algorithm.unwrap_or_default())
+        }
+        None => roxy_shared::generate_roxy_root_ca_with_algorithm(None, proxy.key_algorithm),
+    }
+}
+
+pub fn run(args: CaArgs) -> Result<()> {
+    let proxy = RoxyConfig::new()
+        .map(|cfg| cfg.app.proxy)
+        .unwrap_or_default();
+    let ca = load_ca(&proxy).map_err(|e| eyre!("Failed to load the Roxy CA: {e}"))?;
+    let (outcomes, done) = match args.command {
+        CaCommand::Install => (install(&ca, true), "installed"),
+        CaCommand::Uninstall => (uninstall(&ca, true), "removed"),
+    };
+    for outcome in &outcomes {
+        match &outcome.result {
+            Ok(()) => println!("{}: {done}", outcome.store),
+            Err(err) => eprintln!("{}: {err}", outcome.store),
+        }
+    }
+    if outcomes.iter().all(|outcome| outcome.result.is_err()) {
+        return Err(eyre!("The CA was not {done} anywhere"));
+    }
+    Ok(())
+}
+
+/// Trusts `ca` in the system store and every NSS database found. Without
+/// `interactive` nothing asks for a password, stores needing root fail
+/// unless sudo is allowed without one.
+pub fn install(ca: &RoxyCA, interactive: bool) -> Vec<StoreOutcome> {
+    let pem = match write_pem(ca) {
+        Ok(pem) => pem,
+        Err(err) => {
+            return vec![StoreOutcome {
+                store: "CA certificate".to_string(),
+                result: Err(err),
+            }];
+        }
+    };
+    // The file is removed when `pem` drops, after every store has read it
+    let mut outcomes = vec![system_install(pem.path(), interactive)];
+    outcomes.extend(nss_dbs().iter().map(|db| {
+        StoreOutcome {
+            store: format!("NSS {}", db.display()),
+            result: exec(
+                Command::new("certutil")
+                    .args(["-A", "-n", NSS_NAME, "-t", "C,,", "-d"])
+                    .arg(format!("sql:{}", db.display()))
+                    .arg("-i")
+                    .arg(pem.path()),
+            ),
+        }
+    }));
+    outcomes
+}
+
+/// Removes `ca` from every store [`install`] puts it in.
+pub fn uninstall(ca: &RoxyCA, interactive: bool) -> Vec<StoreOutcome> {
+    let mut outcomes = vec![system_uninstall(ca, interactive)];
+    outcomes.extend(nss_dbs().iter().map(|db| {
+        StoreOutcome {
+            store: format!("NSS {}", db.display()),
+            result: exec(
+                Command::new("certutil")
+                    .args(["-D", "-n", NSS_NAME, "-d"])
+                    .arg(format!("sql:{}", db.display())),
+            ),
+        }
+    }));
+    outcomes
+}
+
+/// Writes the certificate of `ca` without its key to a new temporary file
+/// the store tools can read, which is deleted when dropped.
+fn write_pem(ca: &RoxyCA) -> Result<NamedTempFile, String> {
+    let mut file = tempfile::Builder::new()
+        .prefix("roxy-ca-")
+        .suffix(".pem")
+        .tempfile()
+        .map_err(|e| format!("Failed to create a temporary file {e}"))?;
+    file.write_all(cert_portal::ca_pem(ca).as_bytes())
+        .and_then(|()| file.flush())
+        .map_err(|e| format!("Failed to write {} {e}", file.path().display()))?;
+    Ok(file)
+}
+
+/// The anchor directory of a Linux distribution, the file name the CA gets
+/// in it and the command rebuilding the bundle from it.
+type LinuxAnchor = (&'static str, &'static str, &'static [&'static str]);
+
+const LINUX_ANCHORS: &[LinuxAnchor] = &[
+    (
+        "/usr/local/share/ca-certificates",
+        "roxy-ca.crt",
+        &["update-ca-certificates", "--fresh"],
+    ),
+    (
+        "/etc/pki/ca-trust/source/anchors",
+        "roxy-ca.pem",
+        &["update-ca-trust", "extract"],
+    ),
+    (
+        "/etc/ca-certificates/trust-source/anchors",
+        "roxy-ca.crt",
+        &["update-ca-trust", "extract"],
+    ),
+];
+
+fn system_install(pem: &Path, interactive: bool) -> StoreOutcome {
+    if cfg!(target_os = "macos") {
+        let keychain = login_keychain();
+        StoreOutcome {
+            store: "macOS login keychain".to_string(),
+            result: keychain.and_then(|keychain| {
+                exec(
+                    Command::new("security")
+                        .args(["add-trusted-cert", "-r", "trustRoot", "-k"])
+                        .arg(keychain)
+                        .arg(pem),
+                )
+            }),
+        }
+    } else if cfg!(windows) {
+        StoreOutcome {
+            store: "Windows user root store".to_string(),
+            result: exec(
+                Command::new("certutil")
+                    .args(["-user", "-f", "-addstore", "Root"])
+                    .arg(pem),
+            ),
+        }
+    } else if cfg!(target_os = "linux") {
+        let Some((dir, name, refresh)) = linux_anchors() else {
+            return no_linux_store();
+        };
+        let target = Path::new(dir).join(name);
+        StoreOutcome {
+            store: format!("System store {}", target.display()),
+            result: exec(
+                privileged(interactive, "install")
+                    .args(["-m", "644"])
+                    .arg(pem)
+                    .arg(&target),
+            )
+            .and_then(|()| exec(privileged(interactive, refresh[0]).args(&refresh[1..]))),
+        }
+    } else {
+        unsupported()
+    }
+}
+
+fn system_uninstall(ca: &RoxyCA, interactive: bool) -> StoreOutcome {
+    if cfg!(target_os = "macos") {
+        StoreOutcome {
+            store: "macOS login keychain".to_string(),
+            result: login_keychain().and_then(|keychain| {
+                exec(
+                    Command::new("security")
+                        .args(["delete-certificate", "-t", "-Z"])
+                        .arg(ca.ca_sha1())
+                        .arg(keychain),
+                )
+            }),
+        }
+    } else if cfg!(windows) {
+        StoreOutcome {
+            store: "Windows user root store".to_string(),
+            result: exec(
+                Command::new("certutil")
+                    .args(["-user", "-delstore", "Root"])
+                    .arg(ca.ca_sha1()),
+            ),
+        }
+    } else if cfg!(target_os = "linux") {
+        let Some((dir, name, refresh)) = linux_anchors() else {
+            return no_linux_store();
+        };
+        let target = Path::new(dir).join(name);
+        StoreOutcome {
+            store: format!("System store {}", target.display()),
+            result: exec(privileged(interactive, "rm").arg("-f").arg(&target))
+                .and_then(|()| exec(privileged(interactive, refresh[0]).args(&refresh[1..]))),
+        }
+    } else {
+        unsupported()
+    }
+}
+
+fn linux_anchors() -> Option<LinuxAnchor> {
+    first_anchor(LINUX_ANCHORS, |dir| dir.is_dir())
+}
+
+/// The first of `anchors` whose directory `exists`.
+fn first_anchor(anchors: &[LinuxAnchor], exists: impl Fn(&Path) -> bool) -> Option<LinuxAnchor> {
+    anchors
+        .iter()
+        .find(|(dir, _, _)| exists(Path::new(dir)))
+        .copied()
+}
+
+fn no_linux_store() -> StoreOutcome {
+    StoreOutcome {
+        store: "System store".to_string(),
+        result: Err("No ca-certificates anchor directory found".to_string()),
+    }
+}
+
+fn unsupported() -> StoreOutcome {
+    StoreOutcome {
+        store: "System store".to_string(),
+        result: Err(format!("Not supported on {}", env::consts::OS)),
+    }
+}
+
+fn login_keychain() -> Result<PathBuf, String> {
+    dirs::home_dir()
+        .map(|home| home.join("Library/Keychains/login.keychain-db"))
+        .ok_or_else(|| "No home directory".to_string())
+}
+
+/// NSS databases of the Firefox profiles and of Chrome on Linux. Firefox on
+/// Windows reads the system store.
+fn nss_dbs() -> Vec<PathBuf> {
+    let Some(home) = dirs::home_dir() else {
+        return vec![];
+    };
+    let mut dbs = vec![];
+    let profile_dirs = if cfg!(target_os = "macos") {
+        vec![home.join("Library/Application Support/Firefox/Profiles")]
+    } else if cfg!(target_os = "linux") {
+        dbs.push(home.join(".pki/nssdb"));
+        vec![
+            home.join(".mozilla/firefox"),
+            home.join("snap/firefox/common/.mozilla/firefox"),
+        ]
+    } else {
+        vec![]
+    };
+    for dir in profile_dirs {
+        if let Ok(entries) = fs::read_dir(dir) {
+            dbs.extend(entries.flatten().map(|entry| entry.path()));
+        }
+    }
+    dbs.retain(|db| db.join("cert9.db").is_file());
+    dbs
+}
+
+/// `program` run as root, through sudo unless Roxy already is root.
+fn privileged(interactive: bool, program: &str) -> Command {
+    let is_root = Command::new("id")
+        .arg("-u")
+        .output()
+        .is_ok_and(|output| output.stdout.trim_ascii() == b"0");
+    if is_root {
+        return Command::new(program);
+    }
+    let mut command = Command::new("sudo");
+    if !interactive {
+        command.arg("-n");
+    }
+    command.arg(program);
+    command
+}
+
+fn exec(command: &mut Command) -> Result<(), String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    match command.output() {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(format!(
+            "{program} failed {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Err(err) => Err(format!("Failed to run {program} {err}")),
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_the_pem_to_a_new_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca =
+            roxy_shared::generate_roxy_root_ca_with_path(Some(dir.path().to_path_buf())).unwrap();
+
+        let first = write_pem(&ca).unwrap();
+        let second = write_pem(&ca).unwrap();
+        assert_ne!(first.path(), second.path());
+        let pem = fs::read_to_string(first.path()).unwrap();
+        assert_eq!(pem, cert_portal::ca_pem(&ca));
+        assert!(!pem.contains("PRIVATE KEY"));
+
+        let path = first.path().to_path_buf();
+        drop(first);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn picks_the_first_existing_anchor_dir() {
+        let (dir, name, refresh) =
+            first_anchor(LINUX_ANCHORS, |dir| dir.starts_with("/etc")).unwrap();
+        assert_eq!(dir, "/etc/pki/ca-trust/source/anchors");
+        assert_eq!(name, "roxy-ca.pem");
+        assert_eq!(refresh, ["update-ca-trust", "extract"]);
+
+        let (dir, ..) = first_anchor(LINUX_ANCHORS, |_| true).unwrap();
+        assert_eq!(dir, "/usr/local/share/ca-certificates");
+
+        assert!(first_anchor(LINUX_ANCHORS, |_| false).is_none());
+    }
+}
@@ -0,0 +1,164 @@
+use std::{path::PathBuf, str::FromStr};
+
+use roxy_proxy::flow::{filter::FlowFilter, metrics::MetricsFormat};
+
+use crate::event::Action;
+
+/// The commands of the command palette with their usage, in the order they
+/// are suggested.
+pub const COMMANDS: &[(&str, &str)] = &[
+    (
+        "filter",
+        "filter [expression]  show only matching flows, all without one",
+    ),
+    (
+        "export",
+        "export <har|mitm|pcap|session|csv|json> <path>  write every flow to a file",
+    ),
+    (
+        "import",
+        "import <har|mitm|session> <path>  load flows from a file",
+    ),
+    (
+        "script",
+        "script <load <path>|enable|disable|unload <name>>",
+    ),
+    (
+        "intercept",
+        "intercept <on|off>  tunnel connections untouched while off",
+    ),
+    (
+        "capture",
+        "capture <on|off>  proxy without recording flows while off",
+    ),
+    (
+        "raw",
+        "raw <on|off>  keep the bytes of HTTP/1 flows as read",
+    ),
+    (
+        "ca",
+        "ca <install|uninstall>  trust the root CA on this machine",
+    ),
+];
+
+/// The file formats flows are exported to and imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowFormat {
+    Har,
+    Mitmproxy,
+    Pcapng,
+    Session,
+    /// Timings and sizes of each flow, for spreadsheets.
+    Metrics(MetricsFormat),
+}
+
+impl FromStr for FlowFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "har" => Ok(FlowFormat::Har),
+            "mitm" | "mitmproxy" => Ok(FlowFormat::Mitmproxy),
+            "pcap" | "pcapng" => Ok(FlowFormat::Pcapng),
+            "session" | "jsonl" => Ok(FlowFormat::Session),
+            "csv" => Ok(FlowFormat::Metrics(MetricsFormat::Csv)),
+            "json" => Ok(FlowFormat::Metrics(MetricsFormat::Json)),
+            other => Err(format!("Unknown format {other}")),
+        }
+    }
+}
+
+/// A line typed into the command palette, e.g. `filter ~m POST`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// `None` shows every flow again.
+    Filter(Option<String>),
+    Export(FlowFormat, PathBuf),
+    Import(FlowFormat, PathBuf),
+    LoadScript(PathBuf),
+    EnableScript(String, bool),
+    UnloadScript(String),
+    Intercept(bool),
+    Capture(bool),
+    RawCapture(bool),
+    /// `true` installs the root CA into the trust stores, `false` removes it.
+    Ca(bool),
+    /// Any action without arguments by name, e.g. `StatsView`.
+    Action(Action),
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim().trim_start_matches(':');
+        let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        match (name, args.as_slice()) {
+            ("filter", []) => Ok(Command::Filter(None)),
+            ("filter", _) => match FlowFilter::parse(rest) {
+                Ok(_) => Ok(Command::Filter(Some(rest.to_string()))),
+                Err(err) => Err(format!("Invalid filter {err}")),
+            },
+            ("export", [format, path]) => Ok(Command::Export(format.parse()?, path.into())),
+            ("import", [format, path]) => match format.parse()? {
+                FlowFormat::Pcapng => Err("pcap files can not be imported".to_string()),
+                FlowFormat::Metrics(_) => Err("metrics can not be imported".to_string()),
+                format => Ok(Command::Import(format, path.into())),
+            },
+            ("script", ["load", path]) => Ok(Command::LoadScript(path.into())),
+            ("script", ["enable", name]) => Ok(Command::EnableScript(name.to_string(), true)),
+            ("script", ["disable", name]) => Ok(Command::EnableScript(name.to_string(), false)),
+            ("script", ["unload", name]) => Ok(Command::UnloadScript(name.to_string())),
+            ("intercept", [state]) => Ok(Command::Intercept(on_off(state)?)),
+            ("capture", [state]) => Ok(Command::Capture(on_off(state)?)),
+            ("raw", [state]) => Ok(Command::RawCapture(on_off(state)?)),
+            ("ca", ["install"]) => Ok(Command::Ca(true)),
+            ("ca", ["uninstall"]) => Ok(Command::Ca(false)),
+            (name, args) => {
+                if let Some((_, usage)) = COMMANDS.iter().find(|(command, _)| *command == name) {
+                    return Err(format!("Usage: {usage}"));
+                }
+                match Action::from_str(name) {
+                    Ok(action) if args.is_empty() && is_bindable(&action) => {
+                        Ok(Command::Action(action))
+                    }
+                    _ => Err(format!("Unknown command {name}")),
+                }
+            }
+        }
+    }
+}
+
+fn on_off(state: &str) -> Result<bool, String> {
+    match state {
+        "on" | "true" => Ok(true),
+        "off" | "false" => Ok(false),
+        other => Err(format!("Expected on or off, not {other}")),
+    }
+}
+
+/// Whether `action` is one a key can be bound to, the ones carrying flows
+/// or sent by the app itself are not run from the palette.
+fn is_bindable(action: &Action) -> bool {
+    !matches!(
+        action,
+        Action::Tick
+            | Action::Render
+            | Action::Resize(..)
+            | Action::Error(_)
+            | Action::ReplayFlow(_)
+            | Action::ReplayFlows(_)
+            | Action::CopyCurlFlow(_)
+            | Action::CopyHttpieFlow(_)
+            | Action::CopyReqwestFlow(_)
+            | Action::CopyToClipboard(_)
+            | Action::DiffFlows(..)
+            | Action::SaveFlows(_)
+            | Action::SaveWsMessages(_)
+            | Action::SaveRaw(_)
+            | Action::RunCommand(_)
+            | Action::FilterFlows(_)
+    )
+}
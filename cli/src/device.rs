@@ -0,0 +1,191 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+use clap::Args;
+use color_eyre::eyre::{Result, eyre};
+use qrcode::{Color, QrCode};
+use roxy_proxy::cert_portal::PORTAL_HOST;
+
+use crate::config::{ProxyConfig, RoxyConfig};
+
+/// Light modules around the code, scanners need some to find it.
+const QUIET_ZONE: usize = 2;
+
+/// Shows what to set on a phone or tablet to use Roxy as its proxy, and a QR
+/// code to the page it installs the CA from.
+#[derive(Args, Debug, Clone)]
+pub struct DeviceArgs {
+    /// Host name or address the device reaches Roxy by, instead of the detected one.
+    #[arg(long)]
+    host: Option<String>,
+    /// Draw the light modules of the QR code instead of the dark ones, for
+    /// terminals with light text on a dark background.
+    #[arg(long)]
+    invert: bool,
+}
+
+pub fn run(args: DeviceArgs) -> Result<()> {
+    let proxy = RoxyConfig::new()
+        .map(|cfg| cfg.app.proxy)
+        .unwrap_or_default();
+    let host = args
+        .host
+        .or_else(|| advertised_host(&proxy))
+        .ok_or_else(|| eyre!("No address to reach Roxy by was found, pass one with --host"))?;
+    let portal = format!("http://{PORTAL_HOST}");
+
+    println!("Set the Wi-Fi proxy of the device to manual:\n");
+    println!("  Server  {host}");
+    println!("  Port    {}\n", proxy.port);
+    println!("Then scan the code or open {portal} on the device to install the CA:\n");
+    for line in qr_lines(&portal, args.invert)? {
+        println!("  {line}");
+    }
+    let server = match host.parse::<Ipv6Addr>() {
+        Ok(ip) => format!("[{ip}]"),
+        Err(_) => host,
+    };
+    println!(
+        "\nBefore the proxy is set the CA is at http://{server}:{}/roxy-ca.pem",
+        proxy.port
+    );
+    let bind = proxy.bind_addr();
+    if bind.is_loopback() {
+        eprintln!(
+            "\nRoxy only accepts clients on {bind}, start it with --bind 0.0.0.0 to let devices \
+             connect"
+        );
+    }
+    Ok(())
+}
+
+/// The host devices reach Roxy by: the first advertised host, the bind
+/// address when it names one interface, or else the address of the interface
+/// the default route leaves by.
+pub fn advertised_host(proxy: &ProxyConfig) -> Option<String> {
+    proxy
+        .local_names()
+        .into_iter()
+        .next()
+        .or_else(|| default_route_addr().map(|ip| ip.to_string()))
+}
+
+/// Connecting a UDP socket picks the route and its source address without
+/// sending anything, IPv4 is tried first as more devices are set up for it.
+fn default_route_addr() -> Option<IpAddr> {
+    let probes = [
+        (
+            IpAddr::from(Ipv4Addr::UNSPECIFIED),
+            IpAddr::from(Ipv4Addr::new(192, 0, 2, 1)),
+        ),
+        (
+            IpAddr::from(Ipv6Addr::UNSPECIFIED),
+            IpAddr::from(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+        ),
+    ];
+    probes.into_iter().find_map(|(local, remote)| {
+        let socket = UdpSocket::bind(SocketAddr::new(local, 0)).ok()?;
+        socket.connect(SocketAddr::new(remote, 9)).ok()?;
+        let ip = socket.local_addr().ok()?.ip();
+        (!ip.is_unspecified() && !ip.is_loopback()).then_some(ip)
+    })
+}
+
+/// `data` as a QR code of half blocks, two rows of modules a line. Dark
+/// modules are drawn and light ones left to the background of the terminal,
+/// `invert` draws the light modules instead.
+fn qr_lines(data: &str, invert: bool) -> Result<Vec<String>> {
+    let code = QrCode::new(data).map_err(|e| eyre!("Failed to encode {data} {e}"))?;
+    let width = code.width();
+    let size = width + 2 * QUIET_ZONE;
+    let drawn = |x: usize, y: usize| {
+        let inside = (QUIET_ZONE..width + QUIET_ZONE).contains(&x)
+            && (QUIET_ZONE..width + QUIET_ZONE).contains(&y);
+        let dark = inside && code[(x - QUIET_ZONE, y - QUIET_ZONE)] == Color::Dark;
+        dark != invert
+    };
+    let lines = (0..size)
+        .step_by(2)
+        .map(|y| {
+            (0..size)
+                .map(|x| match (drawn(x, y), y + 1 < size && drawn(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                })
+                .collect()
+        })
+        .collect();
+    Ok(lines)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the module at `x`, `y` of the quiet zone padded code is drawn.
+    fn drawn(lines: &[String], x: usize, y: usize) -> bool {
+        let cell = lines[y / 2].chars().nth(x).unwrap();
+        matches!((cell, y % 2), ('█', _) | ('▀', 0) | ('▄', 1))
+    }
+
+    #[test]
+    fn draws_dark_modules() {
+        let code = QrCode::new("http://roxy.it").unwrap();
+        let size = code.width() + 2 * QUIET_ZONE;
+        let lines = qr_lines("http://roxy.it", false).unwrap();
+        assert_eq!(lines.len(), size.div_ceil(2));
+        assert!(lines.iter().all(|line| line.chars().count() == size));
+        // The quiet zone is left blank
+        assert!(lines[0].chars().all(|c| c == ' '));
+
+        for y in 0..code.width() {
+            for x in 0..code.width() {
+                let dark = code[(x, y)] == Color::Dark;
+                assert_eq!(drawn(&lines, x + QUIET_ZONE, y + QUIET_ZONE), dark);
+            }
+        }
+    }
+
+    #[test]
+    fn inverts_modules() {
+        let size = QrCode::new("http://roxy.it").unwrap().width() + 2 * QUIET_ZONE;
+        let lines = qr_lines("http://roxy.it", false).unwrap();
+        let inverted = qr_lines("http://roxy.it", true).unwrap();
+        assert!(inverted[0].chars().all(|c| c == '█'));
+        for y in 0..size {
+            for x in 0..size {
+                assert_ne!(drawn(&lines, x, y), drawn(&inverted, x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn advertises_configured_hosts_first() {
+        let proxy = ProxyConfig {
+            advertised_hosts: vec!["roxy.lan".to_string()],
+            bind_address: Some(IpAddr::from([192, 168, 1, 20])),
+            ..ProxyConfig::default()
+        };
+        assert_eq!(advertised_host(&proxy).unwrap(), "roxy.lan");
+
+        let proxy = ProxyConfig {
+            bind_address: Some(IpAddr::from([192, 168, 1, 20])),
+            ..ProxyConfig::default()
+        };
+        assert_eq!(advertised_host(&proxy).unwrap(), "192.168.1.20");
+
+        // Loopback and wildcard binds fall back to the default route, if any
+        for bind in [Ipv4Addr::LOCALHOST, Ipv4Addr::UNSPECIFIED] {
+            let proxy = ProxyConfig {
+                bind_address: Some(bind.into()),
+                ..ProxyConfig::default()
+            };
+            assert!(advertised_host(&proxy).is_none_or(|host| {
+                host.parse::<IpAddr>()
+                    .is_ok_and(|ip| !ip.is_loopback() && !ip.is_unspecified())
+            }));
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use roxy_proxy::flow::id::FlowId;
 use serde::{Deserialize, Serialize};
 use strum::{Display, EnumString};
 
@@ -29,6 +30,44 @@ pub enum Action {
     EditConfig,
     LogView,
     FpsView,
+
+    Replay,
+    ReplayFlow(FlowId),
+    ReplayFlows(Vec<FlowId>),
+    CopyCurl,
+    CopyCurlFlow(FlowId),
+    CopyHttpie,
+    CopyHttpieFlow(FlowId),
+    CopyReqwest,
+    CopyReqwestFlow(FlowId),
+    CopyToClipboard(String),
+    MarkDiff,
+    Diff,
+    DiffFlows(FlowId, FlowId),
+    JsonQuery,
+    Search,
+    SearchNext,
+    SearchPrev,
+    StatsView,
+    ProblemsView,
+    EditRequest,
+    PendingView,
+    DropFlow,
+    GroupFlows,
+    ToggleStar,
+    ToggleSelect,
+    DeleteFlows,
+    ExportFlows,
+    SaveFlows(Vec<FlowId>),
+    SaveWsMessages(FlowId),
+    SaveRaw(FlowId),
+    ComposeWsMessage,
+    ToggleCapture,
+    CycleCaptureProfile,
+    CommandPalette,
+    RunCommand(String),
+    FilterFlows(Option<String>),
+    ScriptEditor,
 }
 
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -1,7 +1,12 @@
 #![deny(clippy::unwrap_used, clippy::expect_used, clippy::panic)]
 pub mod app;
+pub mod ca;
+pub mod command;
 pub mod config;
+pub mod device;
 pub mod event;
 pub mod logging;
+pub mod rurl;
+pub mod script_test;
 pub mod tui;
 pub mod ui;
@@ -3,27 +3,55 @@
 
 use std::{
     collections::VecDeque,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
     sync::{Arc, Mutex},
 };
 
+use clap::Parser;
 use roxy_cli::{
-    app,
-    config::ConfigManager,
-    logging, notify_debug, notify_error, notify_info, notify_trace, notify_warn,
+    app, ca,
+    config::{ConfigManager, ProxyConfig, RoxyArgs, RoxyCommand},
+    device, logging, notify_debug, notify_error, notify_info, notify_trace, notify_warn,
+    script_test,
     ui::{framework::notify::Notifier, log::UiLogLayer},
 };
 
 use roxy_proxy::{
-    flow::FlowStore,
+    acl::ClientAcl,
+    auth::ProxyAuth,
+    breakpoint::Breakpoints,
+    cache::ResponseCache,
+    filter::HostFilter,
+    flow::{FlowStore, spill::BodySpill},
+    flow_log::FlowLog,
+    hook::Hooks,
     interceptor::{self, FlowNotifyLevel, ScriptEngine},
+    limits::ProxyLimits,
     proxy::ProxyManager,
+    retry::Retries,
+    rewrite::Rewrites,
+    version::Versions,
+};
+use roxy_shared::{io::local_tcp_listener, upstream::UpstreamConfig};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, watch},
 };
-use roxy_shared::tls::TlsConfig;
-use tokio::sync::mpsc;
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
+    let mut args = RoxyArgs::parse();
+    if let Some(command) = args.command.take() {
+        return match command {
+            RoxyCommand::ScriptTest(args) => script_test::run(args).await,
+            RoxyCommand::Ca(args) => ca::run(args),
+            RoxyCommand::Device(args) => device::run(args),
+        };
+    }
+
     let log_buffer = Arc::new(Mutex::new(VecDeque::new()));
     let log_layer = UiLogLayer::new(log_buffer.clone());
 
@@ -33,7 +61,7 @@ async fn main() -> color_eyre::Result<()> {
         eprintln!("Err {e}");
         return Ok(());
     }
-    let config_manager = match ConfigManager::new() {
+    let config_manager = match ConfigManager::new(args) {
         Ok(config) => config,
         Err(err) => {
             eprintln!("Err {err}");
@@ -41,7 +69,8 @@ async fn main() -> color_eyre::Result<()> {
         }
     };
 
-    let roxy_certs = match roxy_shared::generate_roxy_root_ca() {
+    let roxy_certs = ca::load_ca(&config_manager.rx.borrow().app.proxy);
+    let roxy_certs = match roxy_certs {
         Ok(certs) => certs,
         Err(err) => {
             eprintln!("{err}");
@@ -49,8 +78,30 @@ async fn main() -> color_eyre::Result<()> {
         }
     };
 
-    let flow_store = FlowStore::new();
     let cfg = config_manager.rx.borrow();
+    roxy_certs.set_leaf_cache_dir(cfg.app.proxy.leaf_cache_dir.clone());
+    if let Err(err) = roxy_certs.set_local_names(&cfg.app.proxy.local_names()) {
+        notify_error!("Failed to sign the local certificate {err}");
+    }
+    let session_path = cfg.app.proxy.session_path.clone();
+    let flow_store = FlowStore::new_with_session(session_path.clone());
+    if let Some(threshold) = cfg.app.proxy.body_spill_threshold {
+        flow_store.set_body_spill(Some(BodySpill::default().with_threshold(threshold)));
+    }
+    flow_store.set_raw_capture(cfg.app.proxy.raw_capture);
+    flow_store.set_retention(cfg.app.proxy.retention()).await;
+    if let Some(path) = session_path.as_ref().filter(|p| p.is_file()) {
+        match flow_store.load_session(path).await {
+            Ok(count) => notify_info!("Loaded {count} flows from {}", path.display()),
+            Err(err) => notify_error!("Failed to load session {err}"),
+        }
+    }
+    if let Some(path) = &cfg.app.proxy.import_har {
+        match flow_store.import_har(path).await {
+            Ok(count) => notify_info!("Imported {count} flows from {}", path.display()),
+            Err(err) => notify_error!("Failed to import {} {err}", path.display()),
+        }
+    }
 
     let (notify_tx, mut notify_rx) = mpsc::channel::<interceptor::FlowNotify>(16);
 
@@ -65,34 +116,133 @@ async fn main() -> color_eyre::Result<()> {
             }
         }
     });
-    let mut script_engine = ScriptEngine::new_notify(notify_tx);
-
-    if let Some(path) = cfg.app.proxy.script_path.clone() {
-        let script = tokio::fs::read_to_string(&path).await?;
-        if let Err(e) = script_engine
-            .set_script(&script, interceptor::ScriptType::Lua)
-            .await
-        {
-            notify_error!("Failed to load script {e}");
-        }
+    let store_path = cfg.app.proxy.store_path();
+    if store_path.is_none() {
+        notify_warn!("Missing home dir, script store is not saved");
+    }
+    interceptor::store::set_store_path(store_path);
+    interceptor::python_env::set_python_env(cfg.app.proxy.python_env());
+    interceptor::js_modules::set_module_root(cfg.app.proxy.js_module_root());
+    interceptor::lua_path::set_lua_path(cfg.app.proxy.lua_path());
+    let script_engine = ScriptEngine::new_notify(notify_tx);
+    for script in cfg.app.proxy.all_scripts() {
+        script.load(&script_engine).await;
     }
 
-    let tls_config = TlsConfig::default();
+    let tls_config = cfg.app.proxy.tls_config();
     let mut proxy_manager = ProxyManager::new(
         cfg.app.proxy.port,
         roxy_certs,
         script_engine,
-        tls_config,
+        tls_config.clone(),
         flow_store.clone(),
-    );
+    )
+    .with_bind_addr(cfg.app.proxy.bind_addr())
+    .with_upstream(UpstreamConfig::new(cfg.app.proxy.upstream_rules()))
+    .with_dns(cfg.app.proxy.dns_config())
+    .with_host_filter(HostFilter::new(cfg.app.proxy.host_filter_rules()))
+    .with_acl(ClientAcl::new(cfg.app.proxy.acl_rules()))
+    .with_auth(ProxyAuth::new(cfg.app.proxy.auth_credentials()))
+    .with_limits(ProxyLimits::new(cfg.app.proxy.limits()))
+    .with_retries(Retries::new(cfg.app.proxy.retry_policy()))
+    .with_versions(
+        Versions::new(cfg.app.proxy.version_rules())
+            .with_alt_svc_upgrade(cfg.app.proxy.alt_svc_upgrade),
+    )
+    .with_breakpoints(Breakpoints::new(cfg.app.proxy.breakpoint_rules()))
+    .with_rewrites(Rewrites::new(cfg.app.proxy.rewrite_rules()))
+    .with_hooks(Hooks::new(cfg.app.proxy.hook_rules()));
+    for listener in cfg.app.proxy.listener_configs(&tls_config) {
+        proxy_manager = proxy_manager.with_listener(listener);
+    }
+
+    match cfg.app.proxy.cache_dir() {
+        Some(dir) => {
+            proxy_manager =
+                proxy_manager.with_cache(ResponseCache::new(dir, cfg.app.proxy.cache_mode));
+        }
+        None => notify_warn!("Missing home dir, response cache disabled"),
+    }
 
     if let Err(err) = proxy_manager.start_all().await {
         eprintln!("{err}");
         return Ok(());
     }
+    proxy_manager.start_hooks();
+    if let Some(flow_log) = &cfg.app.proxy.flow_log {
+        match FlowLog::file(&flow_log.path).await {
+            Ok(log) => proxy_manager.start_flow_log(log.with_max_body(flow_log.max_body())),
+            Err(err) => notify_error!("Failed to open flow log {} {err}", flow_log.path.display()),
+        }
+    }
+
+    if let Some(port) = cfg.app.proxy.socks_port {
+        // Clients reach the SOCKS5 port on the same address as the main port
+        let addr = SocketAddr::new(cfg.app.proxy.bind_addr(), port);
+        match TcpListener::bind(addr).await {
+            Ok(listener) => {
+                if let Err(err) = proxy_manager.start_socks(listener).await {
+                    notify_error!("Failed to start SOCKS5 listener {err}");
+                }
+            }
+            Err(err) => notify_error!("Failed to bind SOCKS5 port {addr} {err}"),
+        }
+    }
 
+    if let Some(port) = cfg.app.proxy.metrics_port {
+        match local_tcp_listener(Some(port)).await {
+            Ok(listener) => {
+                if let Err(err) = proxy_manager.start_metrics(listener).await {
+                    notify_error!("Failed to start metrics server {err}");
+                }
+            }
+            Err(err) => notify_error!("Failed to bind metrics port {port} {err}"),
+        }
+    }
+
+    if let Some(port) = cfg.app.proxy.api_port {
+        match (
+            api_token(&cfg.app.proxy),
+            local_tcp_listener(Some(port)).await,
+        ) {
+            (Ok(token), Ok(listener)) => {
+                if let Err(err) = proxy_manager.start_api(listener, token).await {
+                    notify_error!("Failed to start API server {err}");
+                }
+            }
+            (Err(err), _) => notify_error!("Failed to set up the API token {err}"),
+            (_, Err(err)) => notify_error!("Failed to bind API port {port} {err}"),
+        }
+    }
+
+    let (settings_tx, settings_rx) = watch::channel(cfg.app.proxy.settings());
     drop(cfg);
+    proxy_manager.watch_settings(settings_rx);
+
+    let mut config_rx = config_manager.rx.clone();
+    let filter_handle = tokio::spawn(async move {
+        while config_rx.changed().await.is_ok() {
+            let settings = config_rx.borrow_and_update().app.proxy.settings();
+            settings_tx.send_replace(settings);
+        }
+    });
 
+    let breakpoints = proxy_manager.breakpoints();
+    let mut pending_rx = breakpoints.subscribe();
+    let breakpoint_handle = tokio::spawn(async move {
+        let mut paused = 0;
+        while pending_rx.changed().await.is_ok() {
+            let pending = breakpoints.pending();
+            if pending.len() > paused
+                && let Some((id, request)) = pending.last()
+            {
+                notify_warn!("Breakpoint hit {id} {}", request.line_pretty());
+            }
+            paused = pending.len();
+        }
+    });
+
+    let shutdown_requested = proxy_manager.shutdown_requested();
     let mut app = app::App::new(
         proxy_manager,
         config_manager,
@@ -100,10 +250,49 @@ async fn main() -> color_eyre::Result<()> {
         log_buffer,
         notifier,
     );
-    if let Err(err) = app.run().await {
-        eprintln!("{err:?}");
+    tokio::select! {
+        result = app.run() => {
+            if let Err(err) = result {
+                eprintln!("{err:?}");
+            }
+        }
+        _ = shutdown_requested => {}
+    }
+    // Evicted flows are only in the session file, saving the store would drop them
+    if let Some(path) = session_path
+        && flow_store.evicted() == 0
+        && let Err(err) = flow_store.save_session(&path).await
+    {
+        eprintln!("Failed to save session {err}");
     }
     notify_handle.abort();
+    filter_handle.abort();
+    breakpoint_handle.abort();
     ratatui::restore();
     Ok(())
 }
+
+/// The configured control API token, else a new one written where clients
+/// can read it and other users can not.
+fn api_token(proxy: &ProxyConfig) -> io::Result<String> {
+    if let Some(token) = proxy.api_token.clone().filter(|token| !token.is_empty()) {
+        return Ok(token);
+    }
+    let token = roxy_shared::random_hex(32).ok_or_else(|| io::Error::other("No random bytes"))?;
+    let path = proxy
+        .api_token_path()
+        .ok_or_else(|| io::Error::other("No home directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(token.as_bytes())?;
+    notify_info!("API token written to {}", path.display());
+    Ok(token)
+}
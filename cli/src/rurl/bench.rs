@@ -0,0 +1,169 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use futures_util::future::join_all;
+use roxy_shared::client::ClientContext;
+use tokio::time::{Instant, timeout_at};
+use tracing::error;
+
+use super::RequestSpec;
+
+/// When a benchmark stops, whichever of `count` and `duration` is reached first.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BenchOptions {
+    pub count: Option<u64>,
+    pub concurrency: usize,
+    pub duration: Option<Duration>,
+}
+
+/// What a benchmark measured, latencies are of successful requests only.
+#[derive(Debug, Default)]
+pub(crate) struct BenchReport {
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<u16, u64>,
+    /// Failed requests by error message.
+    errors: BTreeMap<String, u64>,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl BenchReport {
+    fn record(&mut self, latency: Duration, status: u16, bytes: usize) {
+        self.latencies.push(latency);
+        *self.statuses.entry(status).or_default() += 1;
+        self.bytes += bytes as u64;
+    }
+
+    fn fail(&mut self, error: String) {
+        *self.errors.entry(error).or_default() += 1;
+    }
+
+    fn merge(&mut self, other: BenchReport) {
+        self.latencies.extend(other.latencies);
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_default() += count;
+        }
+        for (error, count) in other.errors {
+            *self.errors.entry(error).or_default() += count;
+        }
+        self.bytes += other.bytes;
+    }
+
+    fn failed(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    /// The latency `p` percent of requests were faster than, `latencies` must
+    /// be sorted.
+    fn percentile(&self, p: f64) -> Option<Duration> {
+        let last = self.latencies.len().checked_sub(1)?;
+        let index = ((p / 100.0) * last as f64).round() as usize;
+        self.latencies.get(index.min(last)).copied()
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        let total = self.latencies.len() as u64 + self.failed();
+        writeln!(f, "Requests:   {total} ({} failed)", self.failed())?;
+        writeln!(f, "Duration:   {seconds:.2} s")?;
+        writeln!(
+            f,
+            "Throughput: {:.1} req/s, {:.1} KiB/s",
+            self.latencies.len() as f64 / seconds,
+            self.bytes as f64 / 1024.0 / seconds
+        )?;
+        let ms = |d: Option<Duration>| {
+            d.map(|d| format!("{:.2} ms", d.as_secs_f64() * 1000.0))
+                .unwrap_or_else(|| "-".to_string())
+        };
+        writeln!(
+            f,
+            "Latency:    min {}, p50 {}, p90 {}, p99 {}, max {}",
+            ms(self.latencies.first().copied()),
+            ms(self.percentile(50.0)),
+            ms(self.percentile(90.0)),
+            ms(self.percentile(99.0)),
+            ms(self.latencies.last().copied()),
+        )?;
+        for (status, count) in &self.statuses {
+            writeln!(f, "Status {status}: {count}")?;
+        }
+        for (error, count) in &self.errors {
+            writeln!(f, "Error {error}: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Sends `spec` from `concurrency` workers sharing `client`, so connections
+/// are reused like a real client would.
+pub(crate) async fn bench(
+    client: Arc<ClientContext>,
+    spec: Arc<RequestSpec>,
+    options: BenchOptions,
+) -> BenchReport {
+    let started = Instant::now();
+    let deadline = options.duration.map(|duration| started + duration);
+    let sent = Arc::new(AtomicU64::new(0));
+
+    let workers = (0..options.concurrency).map(|_| {
+        let client = client.clone();
+        let spec = spec.clone();
+        let sent = sent.clone();
+        tokio::spawn(async move {
+            let mut report = BenchReport::default();
+            loop {
+                if options
+                    .count
+                    .is_some_and(|count| sent.fetch_add(1, Ordering::Relaxed) >= count)
+                    || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+                {
+                    break;
+                }
+                let request = match spec.request() {
+                    Ok(request) => request,
+                    Err(err) => {
+                        report.fail(err.to_string());
+                        break;
+                    }
+                };
+                let start = Instant::now();
+                let result = match deadline {
+                    Some(deadline) => timeout_at(deadline, client.request(request)).await,
+                    None => Ok(client.request(request).await),
+                };
+                match result {
+                    Ok(Ok(response)) => report.record(
+                        start.elapsed(),
+                        response.parts.status.as_u16(),
+                        response.body.len(),
+                    ),
+                    Ok(Err(err)) => report.fail(err.to_string()),
+                    // Cut off by the end of the run, not a failure
+                    Err(_) => break,
+                }
+            }
+            report
+        })
+    });
+
+    let mut report = BenchReport::default();
+    for worker in join_all(workers).await {
+        match worker {
+            Ok(worker) => report.merge(worker),
+            Err(err) => error!("Benchmark worker failed {err}"),
+        }
+    }
+    report.latencies.sort();
+    report.elapsed = started.elapsed();
+    report
+}
@@ -0,0 +1,113 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use color_eyre::eyre::{Result, eyre};
+
+/// One `-F` field, `name=value`, `name=@file` or `name=@file;type=image/png`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FormPart {
+    Text {
+        name: String,
+        value: String,
+    },
+    File {
+        name: String,
+        path: PathBuf,
+        content_type: String,
+    },
+}
+
+impl FormPart {
+    fn parse(field: &str) -> Result<Self> {
+        let (name, value) = field
+            .split_once('=')
+            .ok_or_else(|| eyre!("Form field {field} is not name=value"))?;
+        let name = name.to_string();
+        let Some(file) = value.strip_prefix('@') else {
+            return Ok(FormPart::Text {
+                name,
+                value: value.to_string(),
+            });
+        };
+        let (path, content_type) = match file.split_once(";type=") {
+            Some((path, content_type)) => (path, content_type.to_string()),
+            None => (file, "application/octet-stream".to_string()),
+        };
+        Ok(FormPart::File {
+            name,
+            path: PathBuf::from(path),
+            content_type,
+        })
+    }
+}
+
+/// A `multipart/form-data` body built from `-F` fields, files are read once
+/// up front.
+#[derive(Debug)]
+pub(crate) struct Form {
+    pub boundary: String,
+    pub body: Bytes,
+}
+
+impl Form {
+    pub(crate) fn build(fields: &[String]) -> Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let boundary = format!("------------------------rurl{nanos:x}");
+
+        let mut body = Vec::new();
+        for field in fields {
+            body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+            match FormPart::parse(field)? {
+                FormPart::Text { name, value } => {
+                    body.extend_from_slice(
+                        format!("Content-Disposition: form-data; name=\"{name}\"\r\n\r\n")
+                            .as_bytes(),
+                    );
+                    body.extend_from_slice(value.as_bytes());
+                }
+                FormPart::File {
+                    name,
+                    path,
+                    content_type,
+                } => {
+                    let data = read_file(&path)?;
+                    let filename = path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    body.extend_from_slice(
+                        format!(
+                            "Content-Disposition: form-data; name=\"{name}\"; \
+                             filename=\"{filename}\"\r\nContent-Type: {content_type}\r\n\r\n"
+                        )
+                        .as_bytes(),
+                    );
+                    body.extend_from_slice(&data);
+                }
+            }
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+
+        Ok(Self {
+            boundary,
+            body: Bytes::from(body),
+        })
+    }
+
+    pub(crate) fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+}
+
+pub(crate) fn read_file(path: &Path) -> Result<Bytes> {
+    std::fs::read(path)
+        .map(Bytes::from)
+        .map_err(|e| eyre!("Failed to read {}: {e}", path.display()))
+}
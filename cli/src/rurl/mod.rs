@@ -0,0 +1,353 @@
+//! `rurl`, a small curl-like client built on Roxy's own HTTP client. It trusts
+//! the Roxy CA, so requests sent through the proxy work without extra setup.
+
+mod bench;
+mod form;
+pub(crate) mod pretty;
+
+use std::{
+    io::{IsTerminal, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use clap::Parser;
+use color_eyre::eyre::{Result, eyre};
+use cow_utils::CowUtils;
+use hyper::{
+    HeaderMap, Method, Request, Version,
+    header::{ACCEPT_ENCODING, CONTENT_TYPE, HOST, HeaderName, HeaderValue},
+};
+use roxy_shared::{
+    alpn::AlpnProtocol,
+    body::{BytesBody, create_http_body},
+    client::ClientContext,
+    generate_roxy_root_ca,
+    http::{HttpEmitter, HttpEvent},
+    load_ca_bundle,
+    pool::ConnectionPool,
+    redirect::RedirectPolicy,
+    retry::RetryPolicy,
+    tls::TlsConfig,
+    uri::RUri,
+};
+
+use bench::{BenchOptions, bench};
+use form::{Form, read_file};
+use pretty::write_pretty;
+
+#[derive(Parser, Debug, Clone)]
+#[command(
+    name = "rurl",
+    version,
+    about = "Sends HTTP requests with Roxy's client, trusting the Roxy CA."
+)]
+pub struct RurlArgs {
+    #[arg(short, long)]
+    url: String,
+
+    /// GET, or POST when a body is given.
+    #[arg(short = 'X', long = "request")]
+    method: Option<String>,
+
+    /// Extra request header, `Name: value`. Repeat for more headers.
+    #[arg(short = 'H', long = "header")]
+    headers: Vec<String>,
+
+    /// Request body, sent as is.
+    #[arg(short, long, conflicts_with_all = ["data_binary", "form"])]
+    data: Option<String>,
+
+    /// Request body, `@path` sends the contents of a file byte for byte.
+    #[arg(long, conflicts_with = "form")]
+    data_binary: Option<String>,
+
+    /// Multipart form field, `name=value` or `name=@path[;type=mime]` to
+    /// upload a file. Repeat for more fields.
+    #[arg(short = 'F', long = "form")]
+    form: Vec<String>,
+
+    /// Write the response body to this file instead of stdout.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Decode the response body and format it by its content type, with
+    /// colours when stdout is a terminal.
+    #[arg(long, conflicts_with = "output")]
+    pretty: bool,
+
+    /// Send requests through this proxy, e.g. `http://127.0.0.1:6969` for Roxy.
+    #[arg(short = 'x', long)]
+    proxy: Option<String>,
+
+    /// Only offer HTTP/1.1.
+    #[arg(long = "http1.1", group = "http_version")]
+    http1: bool,
+
+    /// Only offer HTTP/2, http urls are sent as h2c with prior knowledge.
+    #[arg(long, group = "http_version")]
+    http2: bool,
+
+    /// Send over QUIC with HTTP/3, needs an https url.
+    #[arg(long, group = "http_version")]
+    http3: bool,
+
+    /// Do not verify server certificates.
+    #[arg(short = 'k', long)]
+    insecure: bool,
+
+    /// Also trust the certificates in this PEM file.
+    #[arg(long)]
+    cacert: Option<PathBuf>,
+
+    /// Follow redirects, each hop is printed to stderr.
+    #[arg(short = 'L', long)]
+    location: bool,
+
+    /// Redirects followed at most with `--location`.
+    #[arg(long, default_value_t = 10)]
+    max_redirs: usize,
+
+    /// Send idempotent requests again this many times after a connection
+    /// error or a 5xx response.
+    #[arg(long, default_value_t = 0)]
+    retry: u32,
+
+    /// Benchmark: send this many requests, then report latency and throughput.
+    #[arg(long)]
+    count: Option<u64>,
+
+    /// Benchmark: requests in flight at once.
+    #[arg(long, default_value_t = 1)]
+    concurrency: usize,
+
+    /// Benchmark: keep sending for this many seconds.
+    #[arg(long)]
+    duration: Option<u64>,
+}
+
+impl RurlArgs {
+    fn benchmark(&self) -> bool {
+        self.count.is_some() || self.duration.is_some()
+    }
+}
+
+/// The request to send, built again for every request of a benchmark.
+#[derive(Debug, Clone)]
+pub(crate) struct RequestSpec {
+    method: Method,
+    uri: RUri,
+    version: Version,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl RequestSpec {
+    fn from_args(args: &RurlArgs) -> Result<Self> {
+        let uri = RUri::from_str(&args.url).map_err(|e| eyre!("Invalid url {}: {e}", args.url))?;
+        if !uri.valid_authority() {
+            return Err(eyre!("Url {} has no host", args.url));
+        }
+        let version = if args.http2 {
+            Version::HTTP_2
+        } else if args.http3 {
+            Version::HTTP_3
+        } else {
+            Version::HTTP_11
+        };
+        if version == Version::HTTP_3 && !uri.is_tls() {
+            return Err(eyre!("{version:?} is only offered over https"));
+        }
+
+        let mut headers = HeaderMap::new();
+        for header in &args.headers {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| eyre!("Header {header} is not Name: value"))?;
+            let name = HeaderName::from_str(name.trim())
+                .map_err(|e| eyre!("Invalid header name {name}: {e}"))?;
+            let value = HeaderValue::from_str(value.trim())
+                .map_err(|e| eyre!("Invalid header value {value}: {e}"))?;
+            headers.append(name, value);
+        }
+        if args.pretty && !headers.contains_key(ACCEPT_ENCODING) {
+            headers.insert(
+                ACCEPT_ENCODING,
+                HeaderValue::from_static("gzip, deflate, br, zstd"),
+            );
+        }
+
+        let (body, content_type) = if !args.form.is_empty() {
+            let form = Form::build(&args.form)?;
+            let content_type = form.content_type();
+            (Some(form.body), content_type)
+        } else {
+            let body = match (&args.data, &args.data_binary) {
+                (Some(data), _) => Some(Bytes::copy_from_slice(data.as_bytes())),
+                (None, Some(data)) => Some(match data.strip_prefix('@') {
+                    Some(path) => read_file(path.as_ref())?,
+                    None => Bytes::copy_from_slice(data.as_bytes()),
+                }),
+                (None, None) => None,
+            };
+            (body, "application/x-www-form-urlencoded".to_string())
+        };
+        if body.is_some()
+            && !headers.contains_key(CONTENT_TYPE)
+            && let Ok(content_type) = HeaderValue::from_str(&content_type)
+        {
+            headers.insert(CONTENT_TYPE, content_type);
+        }
+
+        let method = match &args.method {
+            Some(method) => Method::from_bytes(method.cow_to_ascii_uppercase().as_bytes())
+                .map_err(|e| eyre!("Invalid method {method}: {e}"))?,
+            None if body.is_some() => Method::POST,
+            None => Method::GET,
+        };
+        Ok(Self {
+            method,
+            uri,
+            version,
+            headers,
+            body: body.unwrap_or_default(),
+        })
+    }
+
+    pub(crate) fn request(&self) -> Result<Request<BytesBody>, hyper::http::Error> {
+        let mut builder = Request::builder()
+            .method(self.method.clone())
+            .uri(self.uri.inner())
+            .version(self.version);
+        if let Some(headers) = builder.headers_mut() {
+            headers.extend(self.headers.clone());
+            if self.version < Version::HTTP_2
+                && !headers.contains_key(HOST)
+                && let Ok(host) = HeaderValue::from_str(&self.uri.host_port())
+            {
+                headers.insert(HOST, host);
+            }
+        }
+        builder.body(create_http_body(self.body.clone(), None, None))
+    }
+}
+
+fn client(args: &RurlArgs) -> Result<ClientContext> {
+    let roxy_ca = generate_roxy_root_ca().map_err(|e| eyre!("Failed to load the Roxy CA: {e}"))?;
+
+    let mut tls_config = TlsConfig::default();
+    if let Some(path) = &args.cacert {
+        let certs =
+            load_ca_bundle(path).map_err(|e| eyre!("Failed to load {}: {e}", path.display()))?;
+        tls_config = tls_config.with_extra_roots(certs);
+    }
+    if args.insecure {
+        tls_config = tls_config.with_insecure_hosts(vec!["*".to_string()]);
+    }
+
+    let mut builder = ClientContext::builder()
+        .with_roxy_ca(roxy_ca)
+        .with_tls_config(tls_config)
+        .with_pool(ConnectionPool::new());
+    if args.http1 {
+        builder = builder.with_alpns(vec![AlpnProtocol::Http1]);
+    } else if args.http2 {
+        builder = builder.with_alpns(vec![AlpnProtocol::Http2]);
+    } else if args.http3 {
+        builder = builder.with_alpns(vec![AlpnProtocol::Http3]);
+    }
+    if let Some(proxy) = &args.proxy {
+        let proxy = RUri::from_str(proxy).map_err(|e| eyre!("Invalid proxy {proxy}: {e}"))?;
+        builder = builder.with_proxy(proxy);
+    }
+    if args.retry > 0 {
+        builder = builder.with_retries(RetryPolicy {
+            max_retries: args.retry,
+            ..Default::default()
+        });
+    }
+    if args.location {
+        builder = builder.with_redirects(RedirectPolicy {
+            max_hops: args.max_redirs,
+            ..Default::default()
+        });
+        if !args.benchmark() {
+            builder = builder.with_emitter(Box::new(RedirectPrinter));
+        }
+    }
+    Ok(builder.build())
+}
+
+#[derive(Debug)]
+struct RedirectPrinter;
+
+impl HttpEmitter for RedirectPrinter {
+    fn emit(&self, event: HttpEvent) {
+        if let HttpEvent::Redirected(redirect) = event {
+            eprintln!("{} {} -> {}", redirect.status, redirect.from, redirect.to);
+        }
+    }
+}
+
+/// Sends the request `args` describe and prints the response, or runs a
+/// benchmark when `--count` or `--duration` is given.
+pub async fn run(args: RurlArgs) -> Result<()> {
+    let spec = RequestSpec::from_args(&args)?;
+    let client = client(&args)?;
+
+    if args.benchmark() {
+        let options = BenchOptions {
+            count: args.count,
+            concurrency: args.concurrency.max(1),
+            duration: args.duration.map(Duration::from_secs),
+        };
+        let report = bench(Arc::new(client), Arc::new(spec), options).await;
+        print!("{report}");
+        return Ok(());
+    }
+
+    let response = client.request_streaming(spec.request()?).await?;
+    let (parts, mut body) = response.into_parts();
+    {
+        let mut stderr = std::io::stderr().lock();
+        writeln!(stderr, "{:?} {}", parts.version, parts.status)?;
+        for (name, value) in &parts.headers {
+            writeln!(
+                stderr,
+                "{name}: {}",
+                String::from_utf8_lossy(value.as_bytes())
+            )?;
+        }
+        writeln!(stderr)?;
+    }
+    if args.pretty {
+        let mut collected = BytesMut::new();
+        while let Some(data) = body.data().await {
+            collected.extend_from_slice(&data?);
+        }
+        let body = collected.freeze();
+        let mut stdout = std::io::stdout().lock();
+        let ansi = stdout.is_terminal();
+        write_pretty(&mut stdout, &parts.headers, body, ansi)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    // Anything else is written as it arrives, so big downloads are never held
+    // in memory whole
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(output) => Box::new(
+            std::fs::File::create(output)
+                .map_err(|e| eyre!("Failed to write {}: {e}", output.display()))?,
+        ),
+        None => Box::new(std::io::stdout()),
+    };
+    while let Some(data) = body.data().await {
+        out.write_all(&data?)?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use std::io::{self, Write};
+
+use bytes::Bytes;
+use hyper::HeaderMap;
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::Line,
+};
+use roxy_shared::content::{content_type, decode_body_opt, get_content_encoding};
+use tracing::warn;
+
+use crate::{config::SyntaxColors, ui::flow::render_text_body};
+
+/// Decodes `body` and prints it with the body tab's renderers, with colours
+/// when `ansi` is set. Bodies that are not text are written as they are.
+pub(crate) fn write_pretty(
+    out: &mut impl Write,
+    headers: &HeaderMap,
+    body: Bytes,
+    ansi: bool,
+) -> io::Result<()> {
+    let body = match decode_body_opt(body.clone(), &get_content_encoding(headers)) {
+        Ok(decoded) => decoded,
+        Err(err) => {
+            warn!("Failed to decode body {err}");
+            body
+        }
+    };
+    let Some(lines) =
+        content_type(headers).and_then(|ct| render_text_body(&ct, &body, &SyntaxColors::default()))
+    else {
+        return out.write_all(&body);
+    };
+    for line in lines {
+        writeln!(out, "{}", line_to_string(&line, ansi))?;
+    }
+    Ok(())
+}
+
+fn line_to_string(line: &Line, ansi: bool) -> String {
+    let mut out = String::new();
+    for span in &line.spans {
+        let style = line.style.patch(span.style);
+        let codes = if ansi { sgr_codes(style) } else { vec![] };
+        if codes.is_empty() {
+            out.push_str(&span.content);
+        } else {
+            out.push_str(&format!("\x1b[{}m{}\x1b[0m", codes.join(";"), span.content));
+        }
+    }
+    out
+}
+
+/// Select Graphic Rendition parameters for `style`.
+fn sgr_codes(style: Style) -> Vec<String> {
+    let mut codes = vec![];
+    for (modifier, code) in [
+        (Modifier::BOLD, "1"),
+        (Modifier::DIM, "2"),
+        (Modifier::ITALIC, "3"),
+        (Modifier::UNDERLINED, "4"),
+        (Modifier::REVERSED, "7"),
+        (Modifier::CROSSED_OUT, "9"),
+    ] {
+        if style.add_modifier.contains(modifier) {
+            codes.push(code.to_string());
+        }
+    }
+    if let Some(fg) = style.fg.and_then(|c| color_code(c, false)) {
+        codes.push(fg);
+    }
+    if let Some(bg) = style.bg.and_then(|c| color_code(c, true)) {
+        codes.push(bg);
+    }
+    codes
+}
+
+fn color_code(color: Color, background: bool) -> Option<String> {
+    let base = |code: u8| Some((if background { code + 10 } else { code }).to_string());
+    match color {
+        Color::Reset => None,
+        Color::Black => base(30),
+        Color::Red => base(31),
+        Color::Green => base(32),
+        Color::Yellow => base(33),
+        Color::Blue => base(34),
+        Color::Magenta => base(35),
+        Color::Cyan => base(36),
+        Color::Gray => base(37),
+        Color::DarkGray => base(90),
+        Color::LightRed => base(91),
+        Color::LightGreen => base(92),
+        Color::LightYellow => base(93),
+        Color::LightBlue => base(94),
+        Color::LightMagenta => base(95),
+        Color::LightCyan => base(96),
+        Color::White => base(97),
+        Color::Rgb(r, g, b) => Some(format!(
+            "{};2;{r};{g};{b}",
+            if background { 48 } else { 38 }
+        )),
+        Color::Indexed(i) => Some(format!("{};5;{i}", if background { 48 } else { 38 })),
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    io::{IsTerminal, Write},
+    path::PathBuf,
+};
+
+use clap::Args;
+use color_eyre::eyre::{Result, eyre};
+use hyper::HeaderMap;
+use roxy_proxy::{
+    flow::{
+        Flow,
+        diff::{DiffLine, HeaderChange},
+        id::FlowId,
+        session::read_session,
+    },
+    interceptor::{self, FlowNotify, ScriptEngine, ScriptType},
+};
+use tokio::sync::mpsc;
+
+use crate::{config::RoxyConfig, rurl::pretty::write_pretty};
+
+/// Notifications kept per flow before the rest are dropped.
+const NOTIFY_CAPACITY: usize = 256;
+
+/// Runs a script's request and response handlers over the flows of a saved
+/// session, without proxying anything, and prints the flows as the script
+/// left them.
+#[derive(Args, Debug, Clone)]
+pub struct ScriptTestArgs {
+    /// Lua, JavaScript or Python script, Lua when the extension is unknown.
+    script: PathBuf,
+
+    /// Session file with the recorded flows, e.g. one written with `--session`.
+    flows: PathBuf,
+
+    /// Only run the flow with this id.
+    #[arg(long)]
+    id: Option<FlowId>,
+
+    /// Print what the script changed instead of the whole flow.
+    #[arg(long)]
+    diff: bool,
+}
+
+pub async fn run(args: ScriptTestArgs) -> Result<()> {
+    let source = std::fs::read_to_string(&args.script)
+        .map_err(|e| eyre!("Failed to read {} {e}", args.script.display()))?;
+    let script_type = ScriptType::from_path(&args.script).unwrap_or(ScriptType::Lua);
+    let mut flows = recorded_flows(&args)?;
+    if flows.is_empty() {
+        return Err(eyre!("No flows to run in {}", args.flows.display()));
+    }
+    let recorded = if args.diff {
+        recorded_flows(&args)?
+    } else {
+        vec![]
+    };
+
+    // Scripts find their helpers as they would in the proxy, but the store
+    // starts empty and nothing they put in it is saved
+    let mut proxy = RoxyConfig::new()
+        .map(|cfg| cfg.app.proxy)
+        .unwrap_or_default();
+    proxy.script_path = Some(args.script.clone());
+    interceptor::store::set_store_path(None);
+    interceptor::python_env::set_python_env(proxy.python_env());
+    interceptor::js_modules::set_module_root(proxy.js_module_root());
+    interceptor::lua_path::set_lua_path(proxy.lua_path());
+
+    let (notify_tx, mut notify_rx) = mpsc::channel::<FlowNotify>(NOTIFY_CAPACITY);
+    let engine = ScriptEngine::new_notify(notify_tx);
+    let name = args.script.display().to_string();
+    engine
+        .add_script(&name, &source, script_type)
+        .await
+        .map_err(|e| eyre!("Failed to load {name} {e}"))?;
+
+    let ansi = std::io::stdout().is_terminal();
+    let mut out = std::io::stdout();
+    let mut failed = 0;
+    for (i, flow) in flows.iter_mut().enumerate() {
+        let result = engine.run_flow(flow).await;
+        while let Ok(notify) = notify_rx.try_recv() {
+            eprintln!("flow {} {:?}: {}", flow.id, notify.level, notify.msg);
+        }
+        let answered = match result {
+            Ok(answered) => answered,
+            Err(err) => {
+                failed += 1;
+                writeln!(out, "flow {}: {name} failed {err}\n", flow.id)?;
+                continue;
+            }
+        };
+        match recorded.get(i) {
+            Some(before) => write_diff(&mut out, before, flow)?,
+            None => write_flow(&mut out, flow, answered, ansi)?,
+        }
+    }
+    engine.clear_scripts().await;
+
+    if failed > 0 {
+        return Err(eyre!("{name} failed on {failed} of {} flows", flows.len()));
+    }
+    Ok(())
+}
+
+/// The flows of the session with a request, only `--id` when given.
+fn recorded_flows(args: &ScriptTestArgs) -> Result<Vec<Flow>> {
+    let flows = read_session(&args.flows)
+        .map_err(|e| eyre!("Failed to read {} {e}", args.flows.display()))?;
+    Ok(flows
+        .into_iter()
+        .filter(|flow| flow.request.is_some())
+        .filter(|flow| args.id.is_none_or(|id| id == flow.id))
+        .collect())
+}
+
+fn write_flow(out: &mut impl Write, flow: &Flow, answered: bool, ansi: bool) -> Result<()> {
+    writeln!(out, "flow {}", flow.id)?;
+    if let Some(req) = &flow.request {
+        writeln!(
+            out,
+            "{} {} {:?}",
+            req.method,
+            req.line_pretty(),
+            req.version
+        )?;
+        write_headers(out, &req.headers)?;
+        writeln!(out)?;
+        write_pretty(out, &req.headers, req.read_body()?, ansi)?;
+        writeln!(out)?;
+    }
+    if let Some(res) = &flow.response {
+        let source = if answered {
+            " (answered by the script)"
+        } else {
+            ""
+        };
+        writeln!(out, "{:?} {}{source}", res.version, res.status)?;
+        write_headers(out, &res.headers)?;
+        writeln!(out)?;
+        write_pretty(out, &res.headers, res.read_body()?, ansi)?;
+        writeln!(out)?;
+    }
+    let tags: Vec<&str> = flow
+        .request
+        .iter()
+        .flat_map(|r| &r.tags)
+        .chain(flow.response.iter().flat_map(|r| &r.tags))
+        .map(String::as_str)
+        .collect();
+    if !tags.is_empty() {
+        writeln!(out, "tags: {}", tags.join(", "))?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+fn write_headers(out: &mut impl Write, headers: &HeaderMap) -> Result<()> {
+    for (name, value) in headers {
+        writeln!(out, "{name}: {}", String::from_utf8_lossy(value.as_bytes()))?;
+    }
+    Ok(())
+}
+
+fn write_diff(out: &mut impl Write, before: &Flow, after: &Flow) -> Result<()> {
+    let diff = before.diff(after);
+    if diff.is_empty() {
+        writeln!(out, "flow {}: unchanged\n", after.id)?;
+        return Ok(());
+    }
+    writeln!(out, "flow {}", after.id)?;
+    for field in &diff.fields {
+        writeln!(out, "{}: {} -> {}", field.name, field.left, field.right)?;
+    }
+    write_header_changes(out, "request", &diff.request_headers)?;
+    write_body_diff(out, "request", &diff.request_body)?;
+    write_header_changes(out, "response", &diff.response_headers)?;
+    write_body_diff(out, "response", &diff.response_body)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn write_header_changes(out: &mut impl Write, side: &str, changes: &[HeaderChange]) -> Result<()> {
+    for change in changes {
+        match change {
+            HeaderChange::Added(name, value) => writeln!(out, "{side} header + {name}: {value}")?,
+            HeaderChange::Removed(name, value) => writeln!(out, "{side} header - {name}: {value}")?,
+            HeaderChange::Changed { name, left, right } => {
+                writeln!(out, "{side} header ~ {name}: {left} -> {right}")?
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_body_diff(out: &mut impl Write, side: &str, lines: &[DiffLine]) -> Result<()> {
+    if lines.iter().all(|l| matches!(l, DiffLine::Same(_))) {
+        return Ok(());
+    }
+    writeln!(out, "{side} body")?;
+    for line in lines {
+        match line {
+            DiffLine::Same(text) => writeln!(out, "  {text}")?,
+            DiffLine::Added(text) => writeln!(out, "+ {text}")?,
+            DiffLine::Removed(text) => writeln!(out, "- {text}")?,
+        }
+    }
+    Ok(())
+}
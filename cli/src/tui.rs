@@ -4,6 +4,7 @@ use std::{
     time::Duration,
 };
 
+use base64::{Engine, prelude::BASE64_STANDARD};
 use color_eyre::Result;
 use crossterm::{
     cursor,
@@ -11,6 +12,7 @@ use crossterm::{
         DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
         Event as CrosstermEvent, EventStream, KeyEvent, KeyEventKind, MouseEvent,
     },
+    style::Print,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen},
 };
 use futures_util::{FutureExt, StreamExt};
@@ -205,6 +207,14 @@ impl Tui {
         Ok(())
     }
 
+    /// Copies `text` with an OSC 52 escape sequence, terminals that support it
+    /// put it on the system clipboard, also over SSH.
+    pub fn copy_to_clipboard(&self, text: &str) -> Result<()> {
+        let encoded = BASE64_STANDARD.encode(text);
+        crossterm::execute!(stdout(), Print(format!("\x1b]52;c;{encoded}\x07")))?;
+        Ok(())
+    }
+
     pub async fn next_event(&mut self) -> Option<Event> {
         self.event_rx.recv().await
     }
@@ -0,0 +1,163 @@
+use color_eyre::Result;
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::{Modifier, Style},
+    text::{Line, Span},
+    widgets::{Clear, Paragraph},
+};
+
+use crate::{
+    command::COMMANDS,
+    event::Action,
+    ui::framework::{
+        component::{Component, KeyEventResult},
+        theme::{themed_block, with_theme},
+    },
+};
+
+/// Commands remembered for stepping back through with up and down.
+const MAX_HISTORY: usize = 50;
+
+/// The `:` prompt along the bottom of the screen, the line typed is run by
+/// the app when enter is pressed.
+#[derive(Default)]
+pub struct CommandPalette {
+    open: bool,
+    input: String,
+    history: Vec<String>,
+    /// The entry of `history` shown, counted from the newest.
+    history_index: Option<usize>,
+}
+
+impl CommandPalette {
+    pub fn open(&mut self) {
+        self.open = true;
+        self.input.clear();
+        self.history_index = None;
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// The commands whose name starts with the word typed so far.
+    fn suggestions(&self) -> Vec<(&'static str, &'static str)> {
+        let word = self.input.split_whitespace().next().unwrap_or_default();
+        if self.input.contains(' ') {
+            return COMMANDS
+                .iter()
+                .filter(|(name, _)| *name == word)
+                .copied()
+                .collect();
+        }
+        COMMANDS
+            .iter()
+            .filter(|(name, _)| name.starts_with(word))
+            .copied()
+            .collect()
+    }
+
+    fn step_history(&mut self, older: bool) {
+        let index = match (self.history_index, older) {
+            (None, true) if !self.history.is_empty() => Some(0),
+            (Some(i), true) if i + 1 < self.history.len() => Some(i + 1),
+            (Some(i), true) => Some(i),
+            (Some(0), false) | (None, _) => None,
+            (Some(i), false) => Some(i - 1),
+        };
+        self.history_index = index;
+        self.input = index
+            .and_then(|i| self.history.iter().rev().nth(i))
+            .cloned()
+            .unwrap_or_default();
+    }
+
+    fn submit(&mut self) -> KeyEventResult {
+        self.open = false;
+        let line = self.input.trim().to_string();
+        if line.is_empty() {
+            return KeyEventResult::Consumed;
+        }
+        self.history.retain(|entry| *entry != line);
+        self.history.push(line.clone());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+        KeyEventResult::Action(Action::RunCommand(line))
+    }
+}
+
+impl Component for CommandPalette {
+    fn handle_key_event(&mut self, key: &KeyEvent) -> KeyEventResult {
+        if !self.open {
+            return KeyEventResult::Ignored;
+        }
+        match key.code {
+            KeyCode::Esc => self.open = false,
+            KeyCode::Enter => return self.submit(),
+            KeyCode::Tab => {
+                if let [(name, _)] = self.suggestions().as_slice()
+                    && !self.input.contains(' ')
+                {
+                    self.input = format!("{name} ");
+                }
+            }
+            KeyCode::Up => self.step_history(true),
+            KeyCode::Down => self.step_history(false),
+            KeyCode::Backspace if self.input.is_empty() => self.open = false,
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Char(c) => self.input.push(c),
+            _ => {}
+        }
+        KeyEventResult::Consumed
+    }
+
+    /// Draws the prompt over the last line of `area` with the matching
+    /// commands above it.
+    fn render(&mut self, f: &mut Frame, area: Rect) -> Result<()> {
+        if !self.open || area.height == 0 {
+            return Ok(());
+        }
+        let colors = with_theme(|t| t.colors.clone());
+        let prompt_area = Rect {
+            y: area.bottom() - 1,
+            height: 1,
+            ..area
+        };
+        let prompt = Line::from(vec![
+            Span::styled(":", Style::default().fg(colors.primary)),
+            Span::raw(self.input.as_str()),
+            Span::styled(" ", Style::default().add_modifier(Modifier::REVERSED)),
+        ]);
+        f.render_widget(Clear, prompt_area);
+        f.render_widget(
+            Paragraph::new(prompt).style(Style::default().bg(colors.surface)),
+            prompt_area,
+        );
+
+        let suggestions = self.suggestions();
+        let height = (suggestions.len() as u16 + 2).min(area.height - 1);
+        if suggestions.is_empty() || height < 3 {
+            return Ok(());
+        }
+        let list_area = Rect {
+            y: prompt_area.y - height,
+            height,
+            ..area
+        };
+        let lines: Vec<Line> = suggestions
+            .iter()
+            .map(|(_, usage)| Line::from(*usage))
+            .collect();
+        f.render_widget(Clear, list_area);
+        f.render_widget(
+            Paragraph::new(lines).block(themed_block(Some("Commands"), true)),
+            list_area,
+        );
+        Ok(())
+    }
+}
@@ -11,9 +11,15 @@ use ratatui::{
     text::{Line, Span},
     widgets::{Cell, Clear, Paragraph, Row, TableState},
 };
+use roxy_proxy::{
+    cache::CacheMode,
+    filter::{HostMismatch, UnknownProtocol},
+};
 
 use crate::{
-    config::{ConfigManager, RoxyConfig, key_event_to_string, parse_color, parse_key_event},
+    config::{
+        ConfigManager, RoxyConfig, ScriptConfig, key_event_to_string, parse_color, parse_key_event,
+    },
     event::{Action, Mode},
 };
 
@@ -23,6 +29,9 @@ use super::framework::{
     util::centered_rect,
 };
 
+/// Prefix of the enable toggle keys of `scripts` entries.
+const SCRIPT_KEY: &str = "script ";
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum ConfigTab {
     App,
@@ -77,6 +86,7 @@ enum ConfigValue {
     String(String),
     Path(PathBuf),
     Color(Color),
+    List(Vec<String>),
 }
 
 #[derive(Debug, Clone)]
@@ -169,6 +179,7 @@ impl ConfigEditor {
                 }
                 ConfigValue::Color(c) => c.to_string(),
                 ConfigValue::Path(p) => p.display().to_string(),
+                ConfigValue::List(l) => l.join(", "),
             };
             self.is_editing = true;
         } else {
@@ -184,6 +195,14 @@ impl ConfigEditor {
                 ConfigValue::Color(_) => parse_color(&new_val)
                     .map(ConfigValue::Color)
                     .unwrap_or(field.value.clone()),
+                ConfigValue::List(_) => ConfigValue::List(
+                    new_val
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|v| !v.is_empty())
+                        .map(String::from)
+                        .collect(),
+                ),
             };
 
             self.is_editing = false;
@@ -193,7 +212,9 @@ impl ConfigEditor {
 
     fn update_config(&mut self) {
         debug!("Writing config");
-        let cfg = RoxyConfig::try_from(self.fields.clone());
+        // Settings without an editor field keep their current value
+        let current = self.config_manager.rx.borrow().clone();
+        let cfg = RoxyConfig::try_from((current, self.fields.clone()));
         match cfg {
             Ok(cfg) => {
                 let _ = self.config_manager.update(cfg);
@@ -256,7 +277,7 @@ impl From<&RoxyConfig> for HashMap<ConfigTab, Vec<EditableConfigField>> {
         ];
         fields.insert(ConfigTab::App, app_fieldds);
 
-        let proxy_fields = vec![
+        let mut proxy_fields = vec![
             EditableConfigField {
                 key: "port".into(),
                 value: ConfigValue::U16(cfg.app.proxy.port),
@@ -270,7 +291,43 @@ impl From<&RoxyConfig> for HashMap<ConfigTab, Vec<EditableConfigField>> {
                 },
                 editing: false,
             },
+            EditableConfigField {
+                key: "intercept_hosts".into(),
+                value: ConfigValue::List(cfg.app.proxy.intercept_hosts.clone()),
+                editing: false,
+            },
+            EditableConfigField {
+                key: "passthrough_hosts".into(),
+                value: ConfigValue::List(cfg.app.proxy.passthrough_hosts.clone()),
+                editing: false,
+            },
+            EditableConfigField {
+                key: "skip_body_hosts".into(),
+                value: ConfigValue::List(cfg.app.proxy.skip_body_hosts.clone()),
+                editing: false,
+            },
+            EditableConfigField {
+                key: "unknown_protocol".into(),
+                value: ConfigValue::String(cfg.app.proxy.unknown_protocol.to_string()),
+                editing: false,
+            },
+            EditableConfigField {
+                key: "host_mismatch".into(),
+                value: ConfigValue::String(cfg.app.proxy.host_mismatch.to_string()),
+                editing: false,
+            },
+            EditableConfigField {
+                key: "cache_mode".into(),
+                value: ConfigValue::String(cfg.app.proxy.cache_mode.to_string()),
+                editing: false,
+            },
         ];
+        // One toggle per script, the path is part of the key
+        proxy_fields.extend(cfg.app.proxy.scripts.iter().map(|s| EditableConfigField {
+            key: format!("{SCRIPT_KEY}{}", s.path.display()),
+            value: ConfigValue::Bool(s.enabled),
+            editing: false,
+        }));
 
         fields.insert(ConfigTab::Proxy, proxy_fields);
 
@@ -374,11 +431,13 @@ fn gen_theme(cfg: &RoxyConfig) -> Vec<EditableConfigField> {
     ]
 }
 
-impl TryFrom<HashMap<ConfigTab, Vec<EditableConfigField>>> for RoxyConfig {
+/// `config` with the values of the editor fields in `map`.
+impl TryFrom<(RoxyConfig, HashMap<ConfigTab, Vec<EditableConfigField>>)> for RoxyConfig {
     type Error = String;
 
-    fn try_from(map: HashMap<ConfigTab, Vec<EditableConfigField>>) -> Result<Self, Self::Error> {
-        let mut config = RoxyConfig::default();
+    fn try_from(
+        (mut config, map): (RoxyConfig, HashMap<ConfigTab, Vec<EditableConfigField>>),
+    ) -> Result<Self, Self::Error> {
         debug!("Try from map");
 
         for (tab, fields) in map {
@@ -420,7 +479,61 @@ impl TryFrom<HashMap<ConfigTab, Vec<EditableConfigField>>> for RoxyConfig {
                                     config.app.proxy.ca_cert_path = Some(p);
                                 }
                             }
-                            _ => {}
+                            "intercept_hosts" => {
+                                if let ConfigValue::List(l) = field.value.clone() {
+                                    config.app.proxy.intercept_hosts = l;
+                                }
+                            }
+                            "passthrough_hosts" => {
+                                if let ConfigValue::List(l) = field.value.clone() {
+                                    config.app.proxy.passthrough_hosts = l;
+                                }
+                            }
+                            "skip_body_hosts" => {
+                                if let ConfigValue::List(l) = field.value.clone() {
+                                    config.app.proxy.skip_body_hosts = l;
+                                }
+                            }
+                            "unknown_protocol" => {
+                                if let ConfigValue::String(s) = field.value.clone() {
+                                    config.app.proxy.unknown_protocol =
+                                        UnknownProtocol::from_str(s.trim())
+                                            .map_err(|_| format!("Invalid unknown protocol {s}"))?;
+                                }
+                            }
+                            "host_mismatch" => {
+                                if let ConfigValue::String(s) = field.value.clone() {
+                                    config.app.proxy.host_mismatch =
+                                        HostMismatch::from_str(s.trim())
+                                            .map_err(|_| format!("Invalid host mismatch {s}"))?;
+                                }
+                            }
+                            "cache_mode" => {
+                                if let ConfigValue::String(s) = field.value.clone() {
+                                    config.app.proxy.cache_mode = CacheMode::from_str(s.trim())
+                                        .map_err(|_| format!("Invalid cache mode {s}"))?;
+                                }
+                            }
+                            key => {
+                                if let (Some(path), ConfigValue::Bool(enabled)) =
+                                    (key.strip_prefix(SCRIPT_KEY), &field.value)
+                                {
+                                    match config
+                                        .app
+                                        .proxy
+                                        .scripts
+                                        .iter_mut()
+                                        .find(|s| s.path.as_os_str() == path)
+                                    {
+                                        Some(script) => script.enabled = *enabled,
+                                        None => config.app.proxy.scripts.push(ScriptConfig {
+                                            path: PathBuf::from(path),
+                                            enabled: *enabled,
+                                            decode_bodies: true,
+                                        }),
+                                    }
+                                }
+                            }
                         }
                     }
                 }
@@ -538,6 +651,7 @@ impl Component for ConfigEditor {
                                 ConfigValue::U16(n) => n.to_string(),
                                 ConfigValue::Bool(b) => b.to_string(),
                                 ConfigValue::Path(p) => p.display().to_string(),
+                                ConfigValue::List(l) => l.join(", "),
                             }
                         };
 
@@ -584,3 +698,27 @@ impl Component for ConfigEditor {
         self.on_key(key)
     }
 }
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saving_keeps_settings_without_a_field() {
+        let mut current = RoxyConfig::default();
+        current.theme.syntax.key = Color::Magenta;
+        current.theme.syntax.comment = Color::Rgb(0x80, 0x80, 0x80);
+
+        let mut fields: HashMap<ConfigTab, Vec<EditableConfigField>> = (&current).into();
+        for field in fields.get_mut(&ConfigTab::Proxy).unwrap() {
+            if field.key == "port" {
+                field.value = ConfigValue::U16(9090);
+            }
+        }
+
+        let saved = RoxyConfig::try_from((current.clone(), fields)).unwrap();
+        assert_eq!(saved.app.proxy.port, 9090);
+        assert_eq!(saved.theme.syntax, current.theme.syntax);
+    }
+}
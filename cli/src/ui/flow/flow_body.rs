@@ -1,18 +1,25 @@
 use bytes::Bytes;
 use color_eyre::Result;
+use cow_utils::CowUtils;
+use crossterm::event::{KeyCode, KeyEvent};
 use rat_focus::{FocusFlag, HasFocus};
 use ratatui::{
     Frame,
-    layout::Rect,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
     text::Line,
-    widgets::{Block, Borders, Paragraph, Wrap},
+    widgets::{Paragraph, Wrap},
 };
-use ratatui_image::{Resize, StatefulImage, picker::Picker, protocol::StatefulProtocol};
-use roxy_shared::content::ContentType;
+use ratatui_image::{
+    Resize, StatefulImage,
+    picker::{Picker, ProtocolType},
+    protocol::StatefulProtocol,
+};
+use roxy_shared::content::{ContentType, parse_multipart};
+use serde_json::Value;
 use snowflake::SnowflakeIdGenerator;
 use tokio::sync::{mpsc, watch};
-use tracing::debug;
-use x509_parser::nom::HexDisplay;
+use tracing::{debug, error};
 
 use std::{
     collections::HashMap,
@@ -22,19 +29,26 @@ use std::{
 
 use super::{
     csv::{render_csv, render_tsv},
+    grpc::{GrpcMessage, ProtoSchema, render_grpc},
+    hex::{HEX_PAGE, hex_pages, render_hex},
     html::highlight_html_dom,
-    json::highlight_json,
+    json::{highlight_json, highlight_value},
+    json_query::JsonQuery,
     markdown::render_markdown,
+    multipart::render_multipart,
+    sse::render_event_stream,
+    syntax::{LAZY_HIGHLIGHT, Lang, highlight_line, source_lines},
     toml::highlight_toml,
     xml::pretty_print_xml,
     yaml::pretty_print_yaml,
 };
 
 use crate::{
+    config::SyntaxColors,
     event::Action,
     ui::framework::{
-        component::{ActionResult, Component},
-        theme::themed_block,
+        component::{ActionResult, Component, KeyEventResult},
+        theme::{themed_block, with_theme},
     },
 };
 
@@ -45,19 +59,41 @@ fn render_plain_text(body: &Bytes) -> Vec<Line<'static>> {
         .collect::<Vec<Line>>()
 }
 
+/// A request or response body to show, with what is needed to render it.
+pub(crate) struct BodyContent {
+    pub(crate) content_type: Option<ContentType>,
+    /// Set for `multipart/*` bodies, which are shown part by part.
+    pub(crate) boundary: Option<String>,
+    pub(crate) body: Bytes,
+    pub(crate) grpc: GrpcMessage,
+}
+
 struct UiState {
     data: Body,
+    /// The body grew since the last update, e.g. a streamed response, so the
+    /// scroll position is kept.
+    appended: bool,
+    /// The parsed body when it is JSON, for queries to run against.
+    json: Option<Arc<Value>>,
 }
 
 enum Body {
     None,
     Text(Vec<Line<'static>>), // HACK: yeah this needs to be done properly
-    Image(Option<i64>),
+    Image(ImagePreview),
+    /// Binary data, rendered a page at a time as a hex dump.
+    Hex(Bytes),
+    /// Source lines highlighted as they are scrolled into view.
+    Source(Lang, Vec<String>),
 }
 
 impl UiState {
     fn default() -> Self {
-        Self { data: Body::None }
+        Self {
+            data: Body::None,
+            appended: false,
+            json: None,
+        }
     }
 
     fn len(&self) -> u16 {
@@ -65,72 +101,178 @@ impl UiState {
             Body::None => 0,
             Body::Text(lines) => (lines.len() + 1) as u16,
             Body::Image(_) => 0,
+            Body::Hex(_) => (HEX_PAGE / 16 + 3) as u16,
+            Body::Source(_, lines) => (lines.len() + 1) as u16,
         }
     }
 }
 
+/// A JSONPath expression typed over a JSON body, see [`JsonQuery`].
+#[derive(Default)]
+struct Query {
+    input: String,
+    editing: bool,
+    /// The matches rendered for `input`, cleared when either changes.
+    lines: Option<Vec<Line<'static>>>,
+}
+
+impl Query {
+    fn is_active(&self) -> bool {
+        self.editing || !self.input.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.input.clear();
+        self.editing = false;
+        self.lines = None;
+    }
+}
+
+fn query_lines(json: &Value, input: &str, colors: &SyntaxColors) -> Vec<Line<'static>> {
+    let query = match JsonQuery::parse(input) {
+        Ok(query) => query,
+        Err(err) => return vec![Line::styled(err, Style::default().fg(Color::Red))],
+    };
+    let matches = query.select(json);
+    if matches.is_empty() {
+        return vec![Line::from("No matches")];
+    }
+    let mut lines = vec![];
+    for (path, value) in matches {
+        lines.push(Line::styled(
+            path,
+            Style::default().fg(colors.key).add_modifier(Modifier::BOLD),
+        ));
+        lines.extend(highlight_value(value, colors));
+    }
+    lines
+}
+
+fn render_content(
+    content: &BodyContent,
+    colors: &SyntaxColors,
+    proto_schema: &ProtoSchema,
+    image_cache: &mut ImageCache,
+) -> (Body, Option<Arc<Value>>) {
+    let BodyContent {
+        content_type,
+        boundary,
+        body,
+        grpc,
+    } = content;
+    if let Some(parts) = boundary
+        .as_ref()
+        .and_then(|boundary| parse_multipart(body, boundary))
+    {
+        return (Body::Text(render_multipart(&parts, colors)), None);
+    }
+    let Some(ct) = content_type else {
+        let data = if body.is_empty() {
+            Body::None
+        } else if std::str::from_utf8(body).is_err() {
+            Body::Hex(body.clone())
+        } else {
+            Body::Text(render_plain_text(body))
+        };
+        return (data, None);
+    };
+    if let Some(lang) = Lang::for_content_type(ct)
+        && (lang == Lang::Js || body.len() > LAZY_HIGHLIGHT)
+    {
+        return (Body::Source(lang, source_lines(body)), None);
+    }
+    let data = match ct {
+        ContentType::Json => match serde_json::from_slice::<Value>(body) {
+            Ok(value) => {
+                let lines = highlight_value(&value, colors);
+                return (Body::Text(lines), Some(Arc::new(value)));
+            }
+            Err(_) => Body::Text(highlight_json(body, colors)),
+        },
+        ContentType::Svg | ContentType::Xml => Body::Text(pretty_print_xml(body, colors)),
+        ContentType::Html => match highlight_html_dom(&mut Cursor::new(&body[..]), colors) {
+            Ok(lines) => Body::Text(lines),
+            Err(_) => Body::None,
+        },
+        ContentType::Toml => Body::Text(highlight_toml(body, colors)),
+        ContentType::Yaml => Body::Text(pretty_print_yaml(body, colors)),
+        ContentType::Js => Body::Source(Lang::Js, source_lines(body)),
+        ContentType::Csv => Body::Text(render_csv(body).unwrap_or(render_plain_text(body))),
+        ContentType::Tsv => Body::Text(render_tsv(body).unwrap_or(render_plain_text(body))),
+        ContentType::Md => Body::Text(render_markdown(body)),
+        ContentType::EventStream => Body::Text(render_event_stream(body, colors)),
+        ContentType::Png
+        | ContentType::Gif
+        | ContentType::Jpeg
+        | ContentType::Webp
+        | ContentType::XIcon
+        | ContentType::Bmp => Body::Image(image_cache.render_image(body)),
+        ContentType::OctetStream => Body::Hex(body.clone()),
+        ContentType::Text => Body::Text(render_plain_text(body)),
+        ContentType::Grpc => Body::Text(render_grpc(body, proto_schema, grpc)),
+    };
+    (data, None)
+}
+
 pub struct FlowDetailsBody {
     state: watch::Receiver<UiState>,
     image_cache: ImageCache,
     focus: FocusFlag,
     scroll: u16,
+    /// The hex dump page shown for binary bodies.
+    page: usize,
+    query: Query,
+    /// The theme's syntax colours, the body is rendered again when they change.
+    syntax_tx: watch::Sender<SyntaxColors>,
 }
 
 impl FlowDetailsBody {
-    pub fn new(mut body_rx: mpsc::Receiver<(Option<ContentType>, Bytes)>) -> Self {
+    pub(crate) fn new(
+        mut body_rx: mpsc::Receiver<BodyContent>,
+        proto_schema: ProtoSchema,
+        picker: Option<Picker>,
+    ) -> Self {
         let (ui_tx, ui_rx) = watch::channel(UiState::default());
 
-        let ic = ImageCache::new();
+        let ic = ImageCache::new(picker);
         let mut image_cache = ic.clone();
 
+        let (syntax_tx, mut syntax_rx) = watch::channel(with_theme(|t| t.syntax));
+
         tokio::spawn(async move {
-            while let Some((content_type, mut body)) = body_rx.recv().await {
-                let lines = match content_type {
-                    Some(ct) => match ct {
-                        ContentType::Json => Body::Text(highlight_json(&body)),
-                        ContentType::Svg | ContentType::Xml => Body::Text(pretty_print_xml(&body)),
-                        ContentType::Html => {
-                            let mut cursor = Cursor::new(&mut body);
-                            match highlight_html_dom(&mut cursor) {
-                                Ok(lines) => Body::Text(lines),
-                                Err(_) => Body::None,
-                            }
-                        }
-                        ContentType::Toml => Body::Text(highlight_toml(&body)),
-                        ContentType::Yaml => Body::Text(pretty_print_yaml(&body)),
-                        ContentType::Csv => {
-                            Body::Text(render_csv(&body).unwrap_or(render_plain_text(&body)))
-                        }
-                        ContentType::Tsv => {
-                            Body::Text(render_tsv(&body).unwrap_or(render_plain_text(&body)))
-                        }
-                        ContentType::Md => Body::Text(render_markdown(&body)),
-                        ContentType::Png => Body::Image(image_cache.render_image(&body)),
-                        ContentType::Gif => Body::Image(image_cache.render_image(&body)),
-                        ContentType::Jpeg => Body::Image(image_cache.render_image(&body)),
-                        ContentType::Webp => Body::Image(image_cache.render_image(&body)),
-                        ContentType::XIcon => Body::Image(image_cache.render_image(&body)),
-                        ContentType::Bmp => Body::Image(image_cache.render_image(&body)),
-                        ContentType::OctetStream => {
-                            let hex = body.to_hex(8);
-                            let line = vec![hex.into()];
-                            Body::Text(line)
-                        }
-                        ContentType::Text => Body::Text(render_plain_text(&body)),
+            let mut previous = Bytes::new();
+            let mut content = None;
+            loop {
+                tokio::select! {
+                    next = body_rx.recv() => match next {
+                        Some(next) => content = Some(next),
+                        None => break,
                     },
-                    None => {
-                        if body.is_empty() {
-                            Body::None
-                        } else {
-                            let lines = render_plain_text(&body);
-                            Body::Text(lines)
+                    changed = syntax_rx.changed() => {
+                        if changed.is_err() {
+                            break;
                         }
                     }
+                }
+                // A theme change renders the last body again
+                let Some(content) = &content else {
+                    continue;
                 };
+                let appended = !previous.is_empty() && content.body.starts_with(&previous);
+                previous = content.body.clone();
+                let colors = *syntax_rx.borrow_and_update();
+                let (data, json) =
+                    render_content(content, &colors, &proto_schema, &mut image_cache);
 
-                ui_tx.send(UiState { data: lines }).unwrap_or_else(|e| {
-                    debug!("Failed to send UI state update: {}", e);
-                });
+                ui_tx
+                    .send(UiState {
+                        data,
+                        appended,
+                        json,
+                    })
+                    .unwrap_or_else(|e| {
+                        debug!("Failed to send UI state update: {}", e);
+                    });
             }
         });
         Self {
@@ -138,6 +280,16 @@ impl FlowDetailsBody {
             image_cache: ic,
             focus: rat_focus::FocusFlag::new().with_name("FlowBody"),
             scroll: 0,
+            page: 0,
+            query: Query::default(),
+            syntax_tx,
+        }
+    }
+
+    fn len(&self) -> u16 {
+        match &self.query.lines {
+            Some(lines) if self.query.is_active() => (lines.len() + 1) as u16,
+            _ => self.state.borrow().len(),
         }
     }
 }
@@ -168,7 +320,7 @@ impl Component for FlowDetailsBody {
                     ActionResult::Consumed
                 }
                 Action::Down => {
-                    let len = self.state.borrow().len() + 5;
+                    let len = self.len() + 5;
 
                     self.scroll += 1;
                     if self.scroll > len {
@@ -176,6 +328,27 @@ impl Component for FlowDetailsBody {
                     }
                     ActionResult::Consumed
                 }
+                Action::Left | Action::Right => {
+                    let Body::Hex(body) = &self.state.borrow().data else {
+                        return ActionResult::Ignored;
+                    };
+                    let last = hex_pages(body.len()) - 1;
+                    self.page = match action {
+                        Action::Left => self.page.saturating_sub(1),
+                        _ => (self.page + 1).min(last),
+                    };
+                    self.scroll = 0;
+                    ActionResult::Consumed
+                }
+                Action::JsonQuery if self.state.borrow().json.is_some() => {
+                    self.query.editing = true;
+                    ActionResult::Consumed
+                }
+                Action::Back if self.query.is_active() => {
+                    self.query.clear();
+                    self.scroll = 0;
+                    ActionResult::Consumed
+                }
                 _ => ActionResult::Ignored,
             }
         } else {
@@ -183,11 +356,61 @@ impl Component for FlowDetailsBody {
         }
     }
 
+    fn handle_key_event(&mut self, key: &KeyEvent) -> KeyEventResult {
+        if !self.query.editing {
+            return KeyEventResult::Ignored;
+        }
+        match key.code {
+            KeyCode::Esc => self.query.clear(),
+            KeyCode::Enter => self.query.editing = false,
+            KeyCode::Char(c) => self.query.input.push(c),
+            KeyCode::Backspace => {
+                self.query.input.pop();
+            }
+            _ => return KeyEventResult::Consumed,
+        }
+        self.query.lines = None;
+        self.scroll = 0;
+        KeyEventResult::Consumed
+    }
+
     fn render(&mut self, f: &mut Frame, area: Rect) -> Result<()> {
+        // The theme is only known on the UI thread, the body is rendered on another
+        let colors = with_theme(|t| t.syntax);
+        self.syntax_tx.send_if_modified(|current| {
+            let changed = *current != colors;
+            *current = colors;
+            changed
+        });
         if self.state.has_changed().unwrap_or(true) {
-            self.scroll = 0;
+            if !self.state.borrow().appended {
+                self.scroll = 0;
+                self.page = 0;
+            }
+            self.query.lines = None;
         }
-        match self.state.borrow_and_update().data {
+        let state = self.state.borrow_and_update();
+        if let Some(json) = &state.json
+            && self.query.is_active()
+        {
+            let [input_area, results_area] =
+                Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).areas(area);
+            let input = Paragraph::new(self.query.input.as_str())
+                .block(themed_block(Some("Query"), self.query.editing));
+            f.render_widget(input, input_area);
+
+            let query = &mut self.query;
+            let lines = query
+                .lines
+                .get_or_insert_with(|| query_lines(json, &query.input, &colors));
+            let para = Paragraph::new(lines.to_owned())
+                .wrap(Wrap { trim: false })
+                .block(themed_block(Some("Matches"), self.focus.get()))
+                .scroll((self.scroll, 0));
+            f.render_widget(para, results_area);
+            return Ok(());
+        }
+        match state.data {
             Body::None => {
                 let para = Paragraph::new("No body")
                     .block(themed_block(Some("Body"), self.focus.get()))
@@ -201,15 +424,37 @@ impl Component for FlowDetailsBody {
                     .scroll((self.scroll, 0));
                 f.render_widget(para, area);
             }
-            Body::Image(ref id) => {
-                if let Some(id) = id {
-                    return self.image_cache.render(f, area, id);
-                } else {
-                    let para = Paragraph::new(Line::raw("Failed to render image"))
-                        .block(Block::default().title("Body").borders(Borders::ALL))
-                        .scroll((0, 0));
-                    f.render_widget(para, area);
-                }
+            Body::Source(lang, ref lines) => {
+                let visible = lines
+                    .iter()
+                    .skip(self.scroll as usize)
+                    .take(area.height as usize)
+                    .map(|line| highlight_line(lang, line, &colors))
+                    .collect::<Vec<_>>();
+                let para = Paragraph::new(visible)
+                    .wrap(Wrap { trim: false })
+                    .block(themed_block(Some("Body"), self.focus.get()));
+                f.render_widget(para, area);
+            }
+            Body::Hex(ref body) => {
+                let para = Paragraph::new(render_hex(body, self.page))
+                    .block(themed_block(Some("Body"), self.focus.get()))
+                    .scroll((self.scroll, 0));
+                f.render_widget(para, area);
+            }
+            Body::Image(ref preview) => {
+                let block = themed_block(Some("Body"), self.focus.get());
+                let inner = block.inner(area);
+                f.render_widget(block, area);
+                let info = Line::styled(preview.info.clone(), Style::default().fg(Color::DarkGray));
+                let Some(id) = preview.id else {
+                    f.render_widget(Paragraph::new(info), inner);
+                    return Ok(());
+                };
+                let [info_area, image_area] =
+                    Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(inner);
+                f.render_widget(Paragraph::new(info), info_area);
+                return self.image_cache.render(f, image_area, &id);
             }
         }
 
@@ -217,8 +462,33 @@ impl Component for FlowDetailsBody {
     }
 }
 
+/// Asks the terminal which image protocol it speaks, sixel, kitty or iTerm2.
+/// `None` when it does not answer or only manages half blocks, images then
+/// only show their metadata. Must run before the TUI starts reading input.
+pub(crate) fn query_image_picker() -> Option<Picker> {
+    match Picker::from_query_stdio() {
+        Ok(picker) if !matches!(picker.protocol_type(), ProtocolType::Halfblocks) => {
+            debug!("Terminal images use {:?}", picker.protocol_type());
+            Some(picker)
+        }
+        Ok(_) => None,
+        Err(err) => {
+            debug!("Terminal image query failed {err:?}");
+            None
+        }
+    }
+}
+
+/// An image body, its metadata and the preview when the terminal shows images.
+struct ImagePreview {
+    id: Option<i64>,
+    /// Format, dimensions and size, e.g. `PNG 640x480, 1024 bytes`.
+    info: String,
+}
+
 #[derive(Clone)]
 struct ImageCache {
+    picker: Option<Picker>,
     inner: Arc<Mutex<ImageCacheInner>>,
 }
 
@@ -228,8 +498,9 @@ struct ImageCacheInner {
 }
 
 impl ImageCache {
-    fn new() -> Self {
+    fn new(picker: Option<Picker>) -> Self {
         ImageCache {
+            picker,
             inner: Arc::new(Mutex::new(ImageCacheInner {
                 id_gen: SnowflakeIdGenerator::new(1, 1),
                 cache: HashMap::new(),
@@ -237,26 +508,46 @@ impl ImageCache {
         }
     }
 
-    fn render_image(&mut self, raw: &[u8]) -> Option<i64> {
-        if let Ok(image) = image::load_from_memory(raw) {
-            debug!("Loaded image with size: ");
-            // TODO: make this configurable
-            let mut picker = Picker::halfblocks();
-            picker.set_protocol_type(ratatui_image::picker::ProtocolType::Kitty);
-            let proto = picker.new_resize_protocol(image);
-
-            if let Ok(mut guard) = self.inner.lock() {
+    fn render_image(&mut self, raw: &[u8]) -> ImagePreview {
+        let format = image::guess_format(raw)
+            .ok()
+            .and_then(|format| format.extensions_str().first())
+            .map_or("Unknown".to_string(), |ext| {
+                ext.cow_to_ascii_uppercase().into_owned()
+            });
+        let image = match image::load_from_memory(raw) {
+            Ok(image) => image,
+            Err(err) => {
+                debug!("Failed to decode image {err}");
+                return ImagePreview {
+                    id: None,
+                    info: format!("{format} image, {} bytes, failed to decode", raw.len()),
+                };
+            }
+        };
+        let info = format!(
+            "{format} {}x{}, {} bytes",
+            image.width(),
+            image.height(),
+            raw.len()
+        );
+        let Some(picker) = &self.picker else {
+            return ImagePreview { id: None, info };
+        };
+        let proto = picker.new_resize_protocol(image);
+        let id = match self.inner.lock() {
+            Ok(mut guard) => {
                 let id = guard.id_gen.generate();
                 guard.cache.insert(id, Arc::new(Mutex::new(proto)));
                 Some(id)
-            } else {
+            }
+            Err(err) => {
+                error!("Image cache lock poisoned {err}");
                 None
             }
-        } else {
-            None
-        }
+        };
+        ImagePreview { id, info }
     }
-
     fn render(&mut self, f: &mut Frame, area: Rect, id: &i64) -> Result<()> {
         if let Ok(guard) = self.inner.lock()
             && let Some(proto_arc) = guard.cache.get(id)
@@ -272,10 +563,7 @@ impl ImageCache {
                 }
             }
         }
-        let para = Paragraph::new(Line::raw("Failed to render image"))
-            .block(Block::default().title("Body").borders(Borders::ALL))
-            .scroll((0, 0));
-        f.render_widget(para, area);
+        f.render_widget(Paragraph::new("Failed to render image"), area);
         Ok(())
     }
 }
@@ -1,4 +1,3 @@
-use bytes::Bytes;
 use ratatui::{
     Frame,
     layout::{Constraint, Layout, Rect},
@@ -8,7 +7,7 @@ use ratatui::{
 };
 use roxy_proxy::flow::FlowCerts;
 use roxy_shared::cert::{
-    ClientTlsConnectionData, ClientVerificationCapture, ServerTlsConnectionData,
+    ClientTlsConnectionData, ClientVerificationCapture, ParsedCert, ServerTlsConnectionData,
     ServerVerificationCapture, TlsVerify,
 };
 use strum::EnumIter;
@@ -17,7 +16,6 @@ use tokio::{
     task::JoinHandle,
 };
 use tracing::warn;
-use x509_parser::parse_x509_certificate;
 
 use crate::{
     event::Action,
@@ -30,63 +28,6 @@ use crate::{
     },
 };
 
-#[derive(Clone, Debug)]
-struct CertInfo {
-    version: u32,
-    serial: Vec<u8>,
-    signature_oid: String,
-    issuer_cn: Option<String>,
-    subject_cn: Option<String>,
-    san: Option<String>,
-    issuer: String,
-    subject: String,
-    not_before: String,
-    not_after: String,
-    public_key: Vec<u8>,
-    signature_value: Vec<u8>,
-}
-
-impl CertInfo {
-    pub fn from_der(cert: Bytes) -> Option<Self> {
-        let (_, cert) = parse_x509_certificate(cert.as_ref()).ok()?;
-        let tbs = &cert.tbs_certificate;
-
-        let subject_cn = cert
-            .subject
-            .iter_common_name()
-            .next()
-            .and_then(|cn| cn.as_str().ok())
-            .map(|s| s.to_string());
-        let issuer_cn = cert
-            .issuer
-            .iter_common_name()
-            .next()
-            .and_then(|cn| cn.as_str().ok())
-            .map(|s| s.to_string());
-
-        let san = tbs
-            .subject_alternative_name()
-            .ok()
-            .flatten()
-            .map(|ext| format!("{:?}", ext.value));
-
-        Some(Self {
-            version: tbs.version.0,
-            serial: tbs.serial.to_bytes_be(),
-            signature_oid: tbs.signature.algorithm.to_id_string(),
-            subject_cn,
-            issuer_cn,
-            san,
-            issuer: tbs.issuer.to_string(),
-            subject: tbs.subject.to_string(),
-            not_before: tbs.validity.not_before.to_datetime().to_string(),
-            not_after: tbs.validity.not_after.to_datetime().to_string(),
-            public_key: tbs.subject_pki.subject_public_key.data.to_vec(),
-            signature_value: cert.signature_value.data.to_vec(),
-        })
-    }
-}
-
 pub struct FlowDetailsCerts {
     state: watch::Receiver<UiState>,
     focus: rat_focus::FocusFlag,
@@ -256,6 +197,7 @@ struct ClientState {
 struct ServerState {
     resolve_client_cert: Option<String>,
     certs: Option<ServerVerificationCapture>,
+    chain: Vec<ParsedCert>,
     tls: Option<ClientTlsConnectionData>,
 }
 
@@ -272,6 +214,7 @@ impl FlowDetailsCerts {
                         tls: certs.client_tls,
                     };
                     let server = ServerState {
+                        chain: certs.parsed_server_chain(),
                         resolve_client_cert: certs.server_resolve_client_cert.map(|v| v.data),
                         certs: certs.server_verification,
                         tls: certs.server_tls,
@@ -342,19 +285,15 @@ impl FlowDetailsCerts {
             Some(capture) => {
                 lines.push("Capture".into());
                 match &capture.cert {
-                    Some(cert) => {
-                        lines.push("End entity".into());
-
-                        match CertInfo::from_der(cert.end_entity.clone()) {
-                            Some(ci) => {
-                                let para = render_cert(&ci);
-                                f.render_widget(para, area);
-                            }
-                            None => {
-                                lines.push("Failed to render cert".into());
-                            }
+                    Some(cert) => match ParsedCert::from_der(cert.end_entity.clone()) {
+                        Ok(cert) => {
+                            lines.push(heading("End entity".to_string()));
+                            lines.extend(cert_lines(&cert));
                         }
-                    }
+                        Err(err) => {
+                            lines.push(format!("Failed to parse cert {err}").into());
+                        }
+                    },
                     None => {
                         lines.push("No certs".into());
                     }
@@ -373,7 +312,8 @@ impl FlowDetailsCerts {
 
         let paragraph = Paragraph::new(lines)
             .block(themed_block(None, self.focus.get()))
-            .wrap(Wrap { trim: false });
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll_index as u16, 0));
         f.render_widget(paragraph, area);
     }
 
@@ -439,30 +379,37 @@ impl FlowDetailsCerts {
     }
 
     fn render_server_cert(&mut self, f: &mut Frame<'_>, area: Rect) {
-        let certs = &self.state.borrow().server.certs;
+        let state = self.state.borrow();
         let mut lines = vec![];
 
-        match certs {
-            Some(capture) => {
-                lines.push("Capture".into());
-                match &capture.cert {
-                    Some(cert) => {
-                        lines.push("End entity".into());
-                        match CertInfo::from_der(cert.end_entity.clone()) {
-                            Some(ci) => {
-                                let para = render_cert(&ci);
-                                f.render_widget(para, area);
-                            }
-                            None => {
-                                lines.push("Failed to render cert".into());
-                            }
-                        }
+        match &state.server.certs {
+            Some(capture) => match &capture.cert {
+                Some(cert) => {
+                    if let Some(err) = &cert.error {
+                        lines.push(Line::from(Span::styled(
+                            format!("Verification failed {err}"),
+                            Style::default().fg(Color::Red),
+                        )));
                     }
-                    None => {
-                        lines.push("No certs".into());
+                    for (index, parsed) in state.server.chain.iter().enumerate() {
+                        let title = if index == 0 {
+                            "End entity".to_string()
+                        } else {
+                            format!("Intermediate {index}")
+                        };
+                        lines.push(heading(title));
+                        lines.extend(cert_lines(parsed));
+                        lines.push(Line::default());
+                    }
+                    let unparsed = cert.intermediates.len() + 1 - state.server.chain.len();
+                    if unparsed > 0 {
+                        lines.push(format!("{unparsed} certs failed to parse").into());
                     }
                 }
-            }
+                None => {
+                    lines.push("No certs".into());
+                }
+            },
             None => {
                 lines.push("No data".into());
             }
@@ -470,9 +417,11 @@ impl FlowDetailsCerts {
 
         let paragraph = Paragraph::new(lines)
             .block(themed_block(None, self.focus.get()))
-            .wrap(Wrap { trim: false });
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll_index as u16, 0));
         f.render_widget(paragraph, area);
     }
+
     fn render_server_tls(&mut self, f: &mut Frame<'_>, area: Rect) {
         let tls = &self.state.borrow().server.tls;
         let mut lines = vec![];
@@ -497,73 +446,46 @@ impl FlowDetailsCerts {
     }
 }
 
-fn render_cert<'a>(cert: &'a CertInfo) -> Paragraph<'a> {
-    let mut lines = vec![
-        Line::from(vec![
-            Span::styled("Version: ", Style::default().fg(Color::Yellow)),
-            Span::raw(cert.version.to_string()),
-        ]),
-        Line::from(vec![
-            Span::styled("Serial: ", Style::default().fg(Color::Yellow)),
-            Span::raw(
-                cert.serial
-                    .iter()
-                    .map(|b| format!("{b:02x}"))
-                    .collect::<String>(),
-            ),
-        ]),
-        Line::from(vec![
-            Span::styled("Signature OID: ", Style::default().fg(Color::Yellow)),
-            Span::raw(&cert.signature_oid),
-        ]),
-        Line::from(vec![
-            Span::styled("Issuer: ", Style::default().fg(Color::Yellow)),
-            Span::raw(&cert.issuer),
-        ]),
-        Line::from(vec![
-            Span::styled("Subject: ", Style::default().fg(Color::Yellow)),
-            Span::raw(&cert.subject),
-        ]),
-        Line::from(vec![
-            Span::styled("Not Before: ", Style::default().fg(Color::Yellow)),
-            Span::raw(&cert.not_before),
-        ]),
-        Line::from(vec![
-            Span::styled("Not After: ", Style::default().fg(Color::Yellow)),
-            Span::raw(&cert.not_after),
-        ]),
-        Line::from(vec![
-            Span::styled("Public Key: ", Style::default().fg(Color::Yellow)),
-            Span::raw(format!("[{} bytes]", cert.public_key.len())),
-        ]),
-        Line::from(vec![
-            Span::styled("Signature: ", Style::default().fg(Color::Yellow)),
-            Span::raw(format!("[{} bytes]", cert.signature_value.len())),
-        ]),
-    ];
+fn heading(text: String) -> Line<'static> {
+    Line::from(Span::styled(text, Style::default().fg(Color::Blue)))
+}
 
-    if let Some(san) = &cert.san {
-        lines.push(Line::from(vec![
-            Span::styled("SAN: ", Style::default().fg(Color::Yellow)),
-            Span::raw(san),
-        ]))
-    }
-    if let Some(issuer_cn) = &cert.issuer_cn {
-        lines.push(Line::from(vec![
-            Span::styled("Iussuer: ", Style::default().fg(Color::Yellow)),
-            Span::raw(issuer_cn),
-        ]))
-    }
-    if let Some(subject_cn) = &cert.subject_cn {
-        lines.push(Line::from(vec![
-            Span::styled("Iussuer: ", Style::default().fg(Color::Yellow)),
-            Span::raw(subject_cn),
-        ]))
-    }
+fn field(name: &'static str, value: String) -> Line<'static> {
+    Line::from(vec![
+        Span::styled(format!("{name}: "), Style::default().fg(Color::Yellow)),
+        Span::raw(value),
+    ])
+}
 
-    Paragraph::new(lines)
-        .block(themed_block(Some("Info"), false))
-        .wrap(Wrap { trim: false })
+fn cert_lines(cert: &ParsedCert) -> Vec<Line<'static>> {
+    let mut lines = vec![
+        field("Subject", cert.subject.clone()),
+        field("Issuer", cert.issuer.clone()),
+    ];
+    if !cert.san.is_empty() {
+        lines.push(field("SAN", cert.san.join(", ")));
+    }
+    lines.extend([
+        field("Not Before", cert.not_before.to_string()),
+        field("Not After", cert.not_after.to_string()),
+        field("Key", cert.key_type.clone()),
+        field("CA", cert.is_ca.to_string()),
+        field("Version", cert.version.to_string()),
+        field(
+            "Serial",
+            cert.serial
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<String>(),
+        ),
+        field("Signature OID", cert.signature_oid.clone()),
+        field("Public Key", format!("[{} bytes]", cert.public_key.len())),
+        field(
+            "Signature",
+            format!("[{} bytes]", cert.signature_value.len()),
+        ),
+    ]);
+    lines
 }
 
 impl rat_focus::HasFocus for FlowDetailsCerts {
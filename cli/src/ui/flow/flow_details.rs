@@ -1,4 +1,5 @@
 use color_eyre::Result;
+use crossterm::event::KeyEvent;
 use rat_focus::HasFocus;
 use ratatui::{
     layout::{Constraint, Layout, Rect},
@@ -6,8 +7,13 @@ use ratatui::{
     widgets::Clear,
 };
 
-use roxy_proxy::flow::{
-    FlowCerts, FlowStore, InterceptedRequest, InterceptedResponse, Timing, WsMessage,
+use ratatui_image::picker::Picker;
+use roxy_proxy::{
+    flow::{
+        FlowCerts, FlowStore, InterceptedRequest, InterceptedResponse, ScriptLog, Timing,
+        WsMessage, error::FlowError, id::FlowId, tunnel::RawTunnel, view::FlowView, wire::FlowWire,
+    },
+    ws::WsSessions,
 };
 use tokio::{
     sync::{mpsc, watch},
@@ -18,15 +24,18 @@ use tracing::error;
 use crate::{
     event::Action,
     ui::framework::{
-        component::{ActionResult, Component},
+        component::{ActionResult, Component, KeyEventResult},
         theme::themed_tabs,
         util::centered_rect,
     },
 };
 
 use super::flow_response::FlowDetailsResponse;
+use super::grpc::ProtoSchema;
+use super::raw::FlowRaw;
+use super::script_log::FlowScriptLog;
 use super::{flow_certs::FlowDetailsCerts, flow_timing::FlowTiming};
-use super::{flow_request::FlowDetailsRequest, ws_details::FlowDetailsWs};
+use super::{flow_request::FlowDetailsRequest, tunnel::FlowTunnel, ws_details::FlowDetailsWs};
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 enum Tab {
@@ -36,6 +45,9 @@ enum Tab {
     Certs,
     Timing,
     Ws,
+    Tunnel,
+    Raw,
+    Scripts,
 }
 
 // TODO: strum this?
@@ -47,6 +59,9 @@ impl Tab {
             Self::Certs,
             Self::Timing,
             Self::Ws,
+            Self::Tunnel,
+            Self::Raw,
+            Self::Scripts,
         ]
     }
 
@@ -57,6 +72,9 @@ impl Tab {
             Tab::Certs => "Certs",
             Tab::Timing => "Timing",
             Tab::Ws => "Ws",
+            Tab::Tunnel => "Tunnel",
+            Tab::Raw => "Raw",
+            Tab::Scripts => "Scripts",
         }
     }
 
@@ -68,7 +86,7 @@ impl Tab {
         let all_tabs = Self::all();
         let index = self.index();
         if index == 0 {
-            *all_tabs.first().unwrap_or(&Self::Ws)
+            *all_tabs.first().unwrap_or(&Self::Scripts)
         } else {
             all_tabs[index - 1]
         }
@@ -88,49 +106,65 @@ impl Tab {
 pub struct FlowDetails {
     focus: rat_focus::FocusFlag,
     tabs: TabComponent,
-    selected_flow: Option<i64>,
+    selected_flow: Option<FlowId>,
     tab: Tab,
     listener_handle: JoinHandle<()>,
-    flow_id_tx: watch::Sender<Option<i64>>,
+    flow_id_tx: watch::Sender<Option<FlowId>>,
     request: FlowDetailsRequest,
     response: FlowDetailsResponse,
     certs: FlowDetailsCerts,
     timing: FlowTiming,
     ws: FlowDetailsWs,
+    tunnel: FlowTunnel,
+    raw: FlowRaw,
+    scripts: FlowScriptLog,
 }
 
 impl FlowDetails {
-    pub fn new(flow_store: FlowStore) -> Self {
-        let (tx, rx) = watch::channel(None::<i64>);
+    pub(crate) fn new(
+        flow_store: FlowStore,
+        proto_schema: ProtoSchema,
+        picker: Option<Picker>,
+        ws_sessions: WsSessions,
+    ) -> Self {
+        let (tx, rx) = watch::channel(None::<FlowId>);
 
         let (req_tx, req_rx) = mpsc::channel::<Option<InterceptedRequest>>(64);
-        let (resp_tx, resp_rx) = mpsc::channel::<Option<InterceptedResponse>>(64);
+        let (resp_tx, resp_rx) =
+            mpsc::channel::<(Option<InterceptedResponse>, Option<FlowError>, String)>(64);
         let (cert_tx, cert_rx) = mpsc::channel::<FlowCerts>(64);
         let (timing_tx, timing_rx) = mpsc::channel::<Timing>(64);
-        let (ws_tx, ws_rx) = mpsc::channel::<Vec<WsMessage>>(64);
+        let (ws_tx, ws_rx) = mpsc::channel::<(FlowId, Vec<WsMessage>)>(64);
+        let (script_tx, script_rx) = mpsc::channel::<Vec<ScriptLog>>(64);
+        let (tunnel_tx, tunnel_rx) = mpsc::channel::<Option<RawTunnel>>(64);
+        let (raw_tx, raw_rx) = mpsc::channel::<(FlowId, FlowWire)>(64);
 
-        let request = FlowDetailsRequest::new(req_rx);
-        let response = FlowDetailsResponse::new(resp_rx);
+        let request = FlowDetailsRequest::new(req_rx, proto_schema.clone(), picker.clone());
+        let response = FlowDetailsResponse::new(resp_rx, proto_schema, picker);
         let certs = FlowDetailsCerts::new(cert_rx);
         let timing = FlowTiming::new(timing_rx);
-        let ws = FlowDetailsWs::new(ws_rx);
+        let ws = FlowDetailsWs::new(ws_rx, ws_sessions);
+        let scripts = FlowScriptLog::new(script_rx);
+        let tunnel = FlowTunnel::new(tunnel_rx);
+        let raw = FlowRaw::new(raw_rx);
 
-        let task_flow_store = flow_store.clone();
+        let mut view_rx = flow_store.view();
         let handle = tokio::spawn(async move {
-            let mut current_flow_id: Option<i64> = None;
+            let mut current_flow_id: Option<FlowId> = None;
             let mut id_rx = rx;
 
-            let mut flow_rx = task_flow_store.subscribe();
             loop {
                 tokio::select! {
                     _ = id_rx.changed() => {
                         current_flow_id = *id_rx.borrow_and_update();
-                        update_flow_view(&task_flow_store, current_flow_id, &req_tx, &resp_tx, &ws_tx, &cert_tx, &timing_tx).await;
+                        let view = view_rx.borrow().clone();
+                        update_flow_view(&view, current_flow_id, &req_tx, &resp_tx, &ws_tx, &cert_tx, &timing_tx, &script_tx, &tunnel_tx, &raw_tx).await;
                     }
 
-                    _ = flow_rx.changed() => {
+                    _ = view_rx.changed() => {
+                        let view = view_rx.borrow_and_update().clone();
                         if let Some(flow_id) = current_flow_id {
-                            update_flow_view(&task_flow_store, Some(flow_id), &req_tx, &resp_tx, &ws_tx, &cert_tx, &timing_tx).await;
+                            update_flow_view(&view, Some(flow_id), &req_tx, &resp_tx, &ws_tx, &cert_tx, &timing_tx, &script_tx, &tunnel_tx, &raw_tx).await;
                         }
                     }
                 }
@@ -149,10 +183,13 @@ impl FlowDetails {
             certs,
             timing,
             ws,
+            tunnel,
+            raw,
+            scripts,
         }
     }
 
-    pub fn set_flow(&mut self, flow_id: i64) {
+    pub fn set_flow(&mut self, flow_id: FlowId) {
         self.focus();
 
         self.selected_flow = Some(flow_id);
@@ -170,26 +207,32 @@ impl FlowDetails {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn update_flow_view(
-    store: &FlowStore,
-    flow_id_opt: Option<i64>,
+    view: &FlowView,
+    flow_id_opt: Option<FlowId>,
     req_tx: &mpsc::Sender<Option<InterceptedRequest>>,
-    resp_tx: &mpsc::Sender<Option<InterceptedResponse>>,
-    ws_tx: &mpsc::Sender<Vec<WsMessage>>,
+    resp_tx: &mpsc::Sender<(Option<InterceptedResponse>, Option<FlowError>, String)>,
+    ws_tx: &mpsc::Sender<(FlowId, Vec<WsMessage>)>,
     cert_tx: &mpsc::Sender<FlowCerts>,
     timing_tx: &mpsc::Sender<Timing>,
+    script_tx: &mpsc::Sender<Vec<ScriptLog>>,
+    tunnel_tx: &mpsc::Sender<Option<RawTunnel>>,
+    raw_tx: &mpsc::Sender<(FlowId, FlowWire)>,
 ) {
     if let Some(flow_id) = flow_id_opt {
-        let maybe_entry = store.get_flow_by_id(flow_id).await;
-
-        if let Some(entry) = maybe_entry {
-            let flow = entry.read().await;
+        if let Some(flow) = view.get(flow_id) {
             req_tx.send(flow.request.clone()).await.unwrap_or_else(|e| {
                 error!("Failed to send request: {}", e);
             });
 
+            let path = flow
+                .request
+                .as_ref()
+                .map(|r| r.uri.path().to_string())
+                .unwrap_or_default();
             resp_tx
-                .send(flow.response.clone())
+                .send((flow.response.clone(), flow.error.clone(), path))
                 .await
                 .unwrap_or_else(|e| {
                     error!("Failed to send response: {}", e);
@@ -200,15 +243,36 @@ async fn update_flow_view(
             cert_tx.send(certs).await.unwrap_or_else(|e| {
                 error!("Failed to send certs: {}", e);
             });
-            ws_tx.send(flow.messages.clone()).await.unwrap_or_else(|e| {
-                error!("Failed to send WebSocket messages: {}", e);
-            });
+            ws_tx
+                .send((flow.id, flow.messages.clone()))
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Failed to send WebSocket messages: {}", e);
+                });
             timing_tx
                 .send(flow.timing.clone())
                 .await
                 .unwrap_or_else(|e| {
                     error!("Failed to send timing: {}", e);
                 });
+            script_tx
+                .send(flow.script_log.clone())
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Failed to send script log: {}", e);
+                });
+            tunnel_tx
+                .send(flow.tunnel.clone())
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Failed to send tunnel: {}", e);
+                });
+            raw_tx
+                .send((flow.id, flow.wire.clone()))
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Failed to send raw bytes: {}", e);
+                });
         }
     }
 }
@@ -259,6 +323,15 @@ impl HasFocus for FlowDetails {
             Tab::Ws => {
                 builder.widget(&self.ws);
             }
+            Tab::Tunnel => {
+                builder.widget(&self.tunnel);
+            }
+            Tab::Raw => {
+                builder.widget(&self.raw);
+            }
+            Tab::Scripts => {
+                builder.widget(&self.scripts);
+            }
         }
         builder.end(tag);
     }
@@ -293,6 +366,18 @@ impl Component for FlowDetails {
             Tab::Certs => self.certs.update(action),
             Tab::Timing => self.timing.update(action),
             Tab::Ws => self.ws.update(action),
+            Tab::Tunnel => self.tunnel.update(action),
+            Tab::Raw => self.raw.update(action),
+            Tab::Scripts => self.scripts.update(action),
+        }
+    }
+
+    fn handle_key_event(&mut self, key: &KeyEvent) -> KeyEventResult {
+        match self.tab {
+            Tab::Request => self.request.handle_key_event(key),
+            Tab::Response => self.response.handle_key_event(key),
+            Tab::Ws => self.ws.handle_key_event(key),
+            _ => KeyEventResult::Ignored,
         }
     }
 
@@ -330,6 +415,15 @@ impl Component for FlowDetails {
             Tab::Ws => {
                 self.ws.render(f, layout[1])?;
             }
+            Tab::Tunnel => {
+                self.tunnel.render(f, layout[1])?;
+            }
+            Tab::Raw => {
+                self.raw.render(f, layout[1])?;
+            }
+            Tab::Scripts => {
+                self.scripts.render(f, layout[1])?;
+            }
         }
 
         Ok(())
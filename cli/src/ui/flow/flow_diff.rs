@@ -0,0 +1,231 @@
+use std::time::Duration;
+
+use color_eyre::Result;
+use rat_focus::HasFocus;
+use ratatui::{
+    Frame,
+    layout::{Alignment, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Text},
+    widgets::{Clear, Paragraph},
+};
+use roxy_proxy::flow::{
+    FlowStore,
+    diff::{DiffLine, FlowDiff, HeaderChange},
+    id::FlowId,
+};
+use tokio::sync::watch;
+
+use crate::{
+    event::Action,
+    ui::framework::{
+        component::{ActionResult, Component},
+        theme::themed_block,
+        util::centered_rect,
+    },
+};
+
+#[derive(Default)]
+struct State {
+    ids: Option<(FlowId, FlowId)>,
+    diff: Option<FlowDiff>,
+}
+
+/// Differences between two flows, from the marked flow to the selected one.
+pub struct FlowDiffView {
+    focus: rat_focus::FocusFlag,
+    flow_store: FlowStore,
+    state_tx: watch::Sender<State>,
+    state_rx: watch::Receiver<State>,
+    v_scroll_offset: usize,
+}
+
+impl FlowDiffView {
+    pub fn new(flow_store: FlowStore) -> Self {
+        let (state_tx, state_rx) = watch::channel(State::default());
+        Self {
+            focus: rat_focus::FocusFlag::new().with_name("FlowDiff"),
+            flow_store,
+            state_tx,
+            state_rx,
+            v_scroll_offset: 0,
+        }
+    }
+
+    pub fn set_flows(&mut self, left: FlowId, right: FlowId) {
+        self.v_scroll_offset = 0;
+        self.state_tx.send_replace(State {
+            ids: Some((left, right)),
+            diff: None,
+        });
+        let flow_store = self.flow_store.clone();
+        let state_tx = self.state_tx.clone();
+        tokio::spawn(async move {
+            let (Some(a), Some(b)) = (
+                flow_store.get_flow_by_id(left).await,
+                flow_store.get_flow_by_id(right).await,
+            ) else {
+                return;
+            };
+            let diff = {
+                let (a, b) = (a.read().await, b.read().await);
+                a.diff(&b)
+            };
+            state_tx.send_if_modified(|state| {
+                // Skip a diff another pair of flows was selected over
+                if state.ids != Some((left, right)) {
+                    return false;
+                }
+                state.diff = Some(diff);
+                true
+            });
+        });
+    }
+}
+
+fn section(lines: &mut Vec<Line<'static>>, title: &str) {
+    lines.push(Line::from(""));
+    lines.push(Line::from(title.to_string()).style(Style::default().add_modifier(Modifier::BOLD)));
+}
+
+fn headers(lines: &mut Vec<Line<'static>>, changes: &[HeaderChange]) {
+    for change in changes {
+        match change {
+            HeaderChange::Added(name, value) => lines.push(added(format!("+ {name}: {value}"))),
+            HeaderChange::Removed(name, value) => lines.push(removed(format!("- {name}: {value}"))),
+            HeaderChange::Changed { name, left, right } => {
+                lines.push(removed(format!("- {name}: {left}")));
+                lines.push(added(format!("+ {name}: {right}")));
+            }
+        }
+    }
+}
+
+fn body(lines: &mut Vec<Line<'static>>, diff: &[DiffLine]) {
+    for line in diff {
+        lines.push(match line {
+            DiffLine::Same(text) => Line::from(format!("  {text}")),
+            DiffLine::Added(text) => added(format!("+ {text}")),
+            DiffLine::Removed(text) => removed(format!("- {text}")),
+        });
+    }
+}
+
+fn added(text: String) -> Line<'static> {
+    Line::from(text).style(Style::default().fg(Color::Green))
+}
+
+fn removed(text: String) -> Line<'static> {
+    Line::from(text).style(Style::default().fg(Color::Red))
+}
+
+fn millis(duration: Option<Duration>) -> String {
+    duration
+        .map(|d| format!("{}ms", d.as_millis()))
+        .unwrap_or_else(|| "N/A".to_string())
+}
+
+fn diff_lines(diff: &FlowDiff) -> Vec<Line<'static>> {
+    let mut lines = vec![];
+    if diff.is_empty() {
+        lines.push(Line::from("Requests and responses are identical"));
+    }
+    for field in &diff.fields {
+        lines.push(removed(format!("- {}: {}", field.name, field.left)));
+        lines.push(added(format!("+ {}: {}", field.name, field.right)));
+    }
+    if !diff.request_headers.is_empty() {
+        section(&mut lines, "Request headers");
+        headers(&mut lines, &diff.request_headers);
+    }
+    if diff
+        .request_body
+        .iter()
+        .any(|l| !matches!(l, DiffLine::Same(_)))
+    {
+        section(&mut lines, "Request body");
+        body(&mut lines, &diff.request_body);
+    }
+    if !diff.response_headers.is_empty() {
+        section(&mut lines, "Response headers");
+        headers(&mut lines, &diff.response_headers);
+    }
+    if diff
+        .response_body
+        .iter()
+        .any(|l| !matches!(l, DiffLine::Same(_)))
+    {
+        section(&mut lines, "Response body");
+        body(&mut lines, &diff.response_body);
+    }
+    if !diff.timings.is_empty() {
+        section(&mut lines, "Timings");
+        for timing in &diff.timings {
+            lines.push(Line::from(format!(
+                "  {}: {} -> {}",
+                timing.name,
+                millis(timing.left),
+                millis(timing.right)
+            )));
+        }
+    }
+    lines
+}
+
+impl HasFocus for FlowDiffView {
+    fn build(&self, builder: &mut rat_focus::FocusBuilder) {
+        builder.leaf_widget(self);
+    }
+
+    fn area(&self) -> Rect {
+        Rect::default()
+    }
+
+    fn focus(&self) -> rat_focus::FocusFlag {
+        self.focus.clone()
+    }
+}
+
+impl Component for FlowDiffView {
+    fn update(&mut self, action: Action) -> ActionResult {
+        match action {
+            Action::Top => {
+                self.v_scroll_offset = 0;
+                ActionResult::Consumed
+            }
+            Action::Up => {
+                self.v_scroll_offset = self.v_scroll_offset.saturating_sub(1);
+                ActionResult::Consumed
+            }
+            Action::Down => {
+                self.v_scroll_offset += 1;
+                ActionResult::Consumed
+            }
+            _ => ActionResult::Ignored,
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let popup_area = centered_rect(80, 80, area);
+        frame.render_widget(Clear, popup_area);
+
+        let state = self.state_rx.borrow();
+        let (title, lines) = match (&state.ids, &state.diff) {
+            (Some((left, right)), Some(diff)) => {
+                (format!("Diff {left} -> {right}"), diff_lines(diff))
+            }
+            (Some((left, right)), None) => (
+                format!("Diff {left} -> {right}"),
+                vec![Line::from("Loading...")],
+            ),
+            (None, _) => ("Diff".to_string(), vec![]),
+        };
+        self.v_scroll_offset = self.v_scroll_offset.min(lines.len().saturating_sub(1));
+        let paragraph = Paragraph::new(Text::from(lines))
+            .scroll((self.v_scroll_offset as u16, 0))
+            .alignment(Alignment::Left)
+            .block(themed_block(Some(&title), true));
+        frame.render_widget(paragraph, popup_area);
+        Ok(())
+    }
+}
@@ -19,14 +19,25 @@ use crate::{
     },
 };
 
+/// Header names and values in the order they are shown.
+pub type HeaderLines = Vec<(String, String)>;
+
+/// The lines of `headers` in map order.
+pub fn header_lines(headers: &HeaderMap) -> HeaderLines {
+    headers
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("error").to_string()))
+        .collect()
+}
+
 pub struct FlowDetailsHeaders {
-    headers: watch::Receiver<Option<HeaderMap>>,
+    headers: watch::Receiver<Option<HeaderLines>>,
     focus: rat_focus::FocusFlag,
     table_state: TableState,
 }
 
 impl FlowDetailsHeaders {
-    pub fn new(mut req_rx: mpsc::Receiver<HeaderMap>) -> Self {
+    pub fn new(mut req_rx: mpsc::Receiver<HeaderLines>) -> Self {
         let (headers_tx, headers_rx) = watch::channel(None);
 
         tokio::spawn(async move {
@@ -90,12 +101,10 @@ impl Component for FlowDetailsHeaders {
                 let header_style = Style::default().bold();
                 let mut rows = vec![];
                 for (k, v) in headers {
-                    let value = v.to_str().unwrap_or("error").to_string();
-
                     rows.push(
                         Row::new(vec![
-                            Cell::from(Span::styled(k.clone().to_string(), header_style)),
-                            Cell::from(value),
+                            Cell::from(Span::styled(k.clone(), header_style)),
+                            Cell::from(v.clone()),
                         ])
                         .height(1_u16),
                     );
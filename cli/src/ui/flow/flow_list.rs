@@ -1,32 +1,53 @@
+use std::collections::{HashMap, HashSet};
+
 use color_eyre::Result;
-use hyper::Method;
+use crossterm::event::{KeyCode, KeyEvent};
+use hyper::{Method, header::REFERER};
 use rat_focus::{FocusFlag, HasFocus};
 use ratatui::{
     Frame,
-    layout::{Constraint, Margin, Rect},
+    layout::{Constraint, Layout, Margin, Rect},
     style::{Color, Style},
     text::{Line, Span},
-    widgets::{Cell, Row, Scrollbar, ScrollbarOrientation, ScrollbarState, TableState},
+    widgets::{Cell, Paragraph, Row, Scrollbar, ScrollbarOrientation, ScrollbarState, TableState},
+};
+use roxy_proxy::flow::{
+    FlowStore,
+    error::FlowErrorKind,
+    filter::FlowFilter,
+    id::FlowId,
+    search::{FlowSearch, SearchHit},
 };
-use roxy_proxy::flow::FlowStore;
+use roxy_shared::content::{ContentType, content_type};
 use tokio::{sync::watch, task::JoinHandle};
 use tracing::error;
 
 use crate::{
     app::ITEM_HEIGHT,
     event::Action,
+    notify_info, notify_warn,
     ui::framework::{
-        component::{ActionResult, Component},
-        theme::themed_table,
+        component::{ActionResult, Component, KeyEventResult},
+        theme::{themed_block, themed_table},
     },
 };
 
 #[derive(Debug, Clone)]
 struct UiFlow {
-    id: i64,
+    id: FlowId,
     method: Method,
     uri: String,
     response: Option<UiResponse>,
+    error: Option<FlowErrorKind>,
+    passthrough: bool,
+    /// Set for raw tunnels, whether the client spoke TLS.
+    raw_tunnel: Option<bool>,
+    tags: Vec<String>,
+    host: String,
+    /// The page that loaded the flow, its `Referer` or, for HTML documents,
+    /// its own URL.
+    page: Option<String>,
+    starred: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -39,6 +60,113 @@ struct UiState {
     flows: Vec<UiFlow>,
 }
 
+/// Text searched for across every flow, the hits are stepped through in
+/// ranked order.
+#[derive(Default)]
+struct Search {
+    input: String,
+    editing: bool,
+    hits: Vec<SearchHit>,
+    hit_ids: HashSet<FlowId>,
+    /// The hit selected last, an index into `hits`.
+    current: usize,
+    status: Option<Result<(), String>>,
+}
+
+/// How the flow list nests flows, cycled through with the `GroupFlows` action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Grouping {
+    #[default]
+    None,
+    Host,
+    Page,
+}
+
+const NO_PAGE: &str = "(no page)";
+
+impl Grouping {
+    fn next(self) -> Self {
+        match self {
+            Grouping::None => Grouping::Host,
+            Grouping::Host => Grouping::Page,
+            Grouping::Page => Grouping::None,
+        }
+    }
+
+    fn key(self, flow: &UiFlow) -> &str {
+        match self {
+            Grouping::None => "",
+            Grouping::Host => &flow.host,
+            Grouping::Page => flow.page.as_deref().unwrap_or(NO_PAGE),
+        }
+    }
+}
+
+/// A row of the flow list, a group header or a flow as an index into the
+/// flows of the [`UiState`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ListRow {
+    Group {
+        key: String,
+        len: usize,
+        errors: usize,
+        collapsed: bool,
+    },
+    Flow(usize),
+}
+
+/// The rows for `flows` grouped by `grouping`, groups in the order their
+/// first flow arrived.
+fn list_rows(flows: &[UiFlow], grouping: Grouping, collapsed: &HashSet<String>) -> Vec<ListRow> {
+    if grouping == Grouping::None {
+        return (0..flows.len()).map(ListRow::Flow).collect();
+    }
+    let mut order = vec![];
+    let mut groups = HashMap::<&str, Vec<usize>>::new();
+    for (index, flow) in flows.iter().enumerate() {
+        let key = grouping.key(flow);
+        groups
+            .entry(key)
+            .or_insert_with(|| {
+                order.push(key);
+                vec![]
+            })
+            .push(index);
+    }
+    let mut rows = Vec::with_capacity(order.len() + flows.len());
+    for key in order {
+        let members = groups.remove(key).unwrap_or_default();
+        let collapsed = collapsed.contains(key);
+        rows.push(ListRow::Group {
+            key: key.to_string(),
+            len: members.len(),
+            errors: members
+                .iter()
+                .filter(|i| flows[**i].error.is_some())
+                .count(),
+            collapsed,
+        });
+        if !collapsed {
+            rows.extend(members.into_iter().map(ListRow::Flow));
+        }
+    }
+    rows
+}
+
+/// The page a `Referer` points at, without its fragment.
+fn referer_page(referer: &str) -> String {
+    referer.split('#').next().unwrap_or_default().to_string()
+}
+
+/// The hits of a search, with the input it ran for.
+type SearchResult = (String, Result<Vec<SearchHit>, String>);
+
+impl Search {
+    fn is_active(&self) -> bool {
+        self.editing || self.status.is_some()
+    }
+}
+
 pub struct FlowList {
     focus: FocusFlag,
     flow_store: FlowStore,
@@ -47,6 +175,19 @@ pub struct FlowList {
     ui_rx: watch::Receiver<UiState>,
     shutdown_tx: watch::Sender<()>,
     listener_handle: Option<JoinHandle<()>>,
+    /// Marked to be compared with the flow selected next.
+    diff_base: Option<FlowId>,
+    search: Search,
+    search_tx: watch::Sender<Option<SearchResult>>,
+    search_rx: watch::Receiver<Option<SearchResult>>,
+    grouping: Grouping,
+    /// Keys of the groups showing only their header.
+    collapsed: HashSet<String>,
+    rows: Vec<ListRow>,
+    /// Flows picked for bulk operations, the selected flow is used when empty.
+    picked: HashSet<FlowId>,
+    /// Only flows matching this are listed.
+    filter_tx: watch::Sender<Option<FlowFilter>>,
 }
 
 impl HasFocus for FlowList {
@@ -68,6 +209,8 @@ impl FlowList {
         let (shutdown_tx, shutdown_rx) = watch::channel(());
 
         let (ui_tx, ui_rx) = watch::channel(UiState::default());
+        let (search_tx, search_rx) = watch::channel(None);
+        let (filter_tx, filter_rx) = watch::channel(None);
 
         let mut instance = Self {
             focus: FocusFlag::new().with_name("FlowList"),
@@ -77,9 +220,18 @@ impl FlowList {
             ui_rx,
             listener_handle: None,
             shutdown_tx,
+            diff_base: None,
+            search: Search::default(),
+            search_tx,
+            search_rx,
+            grouping: Grouping::default(),
+            collapsed: HashSet::new(),
+            rows: vec![],
+            picked: HashSet::new(),
+            filter_tx,
         };
 
-        let handle = instance.start_listener(ui_tx, shutdown_rx);
+        let handle = instance.start_listener(ui_tx, filter_rx, shutdown_rx);
         instance.listener_handle = Some(handle);
 
         instance
@@ -88,69 +240,116 @@ impl FlowList {
     fn start_listener(
         &self,
         ui_tx: watch::Sender<UiState>,
+        mut filter_rx: watch::Receiver<Option<FlowFilter>>,
         mut shutdown_rx: watch::Receiver<()>,
     ) -> tokio::task::JoinHandle<()> {
         let flow_store = self.flow_store.clone();
 
         tokio::spawn(async move {
-            let mut flow_rx = flow_store.subscribe();
+            let mut view_rx = flow_store.view();
 
             loop {
                 tokio::select! {
-                    _ = flow_rx.changed() => {
-                        let ids = flow_store.ordered_ids.read().await;
-
-                        let mut flows = Vec::new();
-                        for id in ids.iter() {
-                            if let Some(entry) = flow_store.flows.get(id) {
-
-                                let flow = entry.value().read().await;
-
-                                let response = flow.response.as_ref()
-                                    .map(|r| UiResponse{
-                                    code: r.status.as_u16(),
-                                });
-
-                                let (method, line) = match flow.request.as_ref() {
-                                    Some(req) => {
-                                        (req.method.clone(), req.line_pretty())
-                                    },
-                                    None => {
-                                        (Method::GET, "?????".to_string())
-                                    }
-                                };
-
-                                flows.push(UiFlow {
-                                    id: *id,
-                                    method,
-                                    uri: line,
-                                    response
-                                });
-                            }
-                        }
-                        if let Err(e) = ui_tx.send(UiState{ flows }) {
-                            error!("error posting ui state {e}");
-
-                        }
-                    }
+                    _ = view_rx.changed() => {}
+                    _ = filter_rx.changed() => {}
                     _ = shutdown_rx.changed() => {
                         break;
                     }
                 }
+                let filter = filter_rx.borrow_and_update().clone();
+                let view = view_rx.borrow_and_update().clone();
+
+                let mut flows = Vec::new();
+                for flow in view.flows.iter() {
+                    if filter.as_ref().is_some_and(|f| !f.matches(flow)) {
+                        continue;
+                    }
+
+                    let response = flow.response.as_ref().map(|r| UiResponse {
+                        code: r.status.as_u16(),
+                    });
+
+                    let (method, line) = match flow.request.as_ref() {
+                        Some(req) => (req.method.clone(), req.line_pretty()),
+                        None => (Method::GET, "?????".to_string()),
+                    };
+                    let host = flow
+                        .request
+                        .as_ref()
+                        .map_or("?????".to_string(), |req| req.uri.host().to_string());
+                    let referer = flow
+                        .request
+                        .as_ref()
+                        .and_then(|req| req.headers.get(REFERER))
+                        .and_then(|v| v.to_str().ok())
+                        .map(referer_page);
+                    let document = flow
+                        .response
+                        .as_ref()
+                        .is_some_and(|r| content_type(&r.headers) == Some(ContentType::Html));
+                    let page = referer.or_else(|| document.then(|| line.clone()));
+
+                    flows.push(UiFlow {
+                        id: flow.id,
+                        method,
+                        uri: line,
+                        response,
+                        error: flow.error.as_ref().map(|e| e.kind),
+                        passthrough: flow.passthrough,
+                        raw_tunnel: flow.tunnel.as_ref().map(|t| t.tls),
+                        tags: flow.tags.clone(),
+                        host,
+                        page,
+                        starred: flow.starred,
+                    });
+                }
+                if let Err(e) = ui_tx.send(UiState { flows }) {
+                    error!("error posting ui state {e}");
+                }
             }
         })
     }
 
+    /// Lists only the flows matching `filter`, all of them for `None`.
+    fn set_filter(&mut self, filter: Option<String>) {
+        let filter = match filter.as_deref().map(FlowFilter::parse).transpose() {
+            Ok(filter) => filter,
+            Err(err) => {
+                notify_warn!("Invalid filter {err}");
+                return;
+            }
+        };
+        self.filter_tx.send_replace(filter);
+        self.select_row(0);
+    }
+
+    fn refresh_rows(&mut self) {
+        let state = self.ui_rx.borrow_and_update();
+        self.rows = list_rows(&state.flows, self.grouping, &self.collapsed);
+        // Forget picks of flows that were removed
+        if !self.picked.is_empty() {
+            let ids: HashSet<FlowId> = state.flows.iter().map(|f| f.id).collect();
+            self.picked.retain(|id| ids.contains(id));
+        }
+    }
+
+    fn select_row(&mut self, i: usize) {
+        self.state.select(Some(i));
+        self.scroll_state = self.scroll_state.position(i * ITEM_HEIGHT);
+    }
+
     fn next_row(&mut self) {
         let i = match self.state.selected() {
             Some(i) => {
-                let len = self.ui_rx.borrow().flows.len();
-                if i + 1 < len { i + 1 } else { i }
+                if i + 1 < self.rows.len() {
+                    i + 1
+                } else {
+                    i
+                }
             }
             None => 0,
         };
-        self.state.select(Some(i));
-        self.scroll_state = self.scroll_state.position(i * ITEM_HEIGHT);
+        self.select_row(i);
     }
 
     fn previous_row(&mut self) {
@@ -164,22 +363,221 @@ impl FlowList {
             }
             None => 0,
         };
-        self.state.select(Some(i));
-        self.scroll_state = self.scroll_state.position(i * ITEM_HEIGHT);
+        self.select_row(i);
     }
 
-    pub fn selected_id(&self) -> Option<i64> {
-        if let Some(selected) = self.state.selected() {
-            let state = self.ui_rx.borrow();
-            if selected < state.flows.len() {
-                Some(state.flows[selected].id)
-            } else {
-                None
+    fn select_flow(&mut self, id: FlowId) {
+        let index = self.ui_rx.borrow().flows.iter().position(|f| f.id == id);
+        let Some(index) = index else {
+            return;
+        };
+        // Open the group the flow is hidden in
+        if self.grouping != Grouping::None {
+            let key = self
+                .grouping
+                .key(&self.ui_rx.borrow().flows[index])
+                .to_string();
+            if self.collapsed.remove(&key) {
+                self.refresh_rows();
             }
+        }
+        if let Some(i) = self.rows.iter().position(|r| *r == ListRow::Flow(index)) {
+            self.select_row(i);
+        }
+    }
+
+    /// The key of the group of the selected row, the group itself or the
+    /// group of the selected flow, and the index of its header.
+    fn selected_group(&self) -> Option<(String, usize)> {
+        let selected = self.state.selected()?;
+        self.rows[..=selected.min(self.rows.len().checked_sub(1)?)]
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, row)| match row {
+                ListRow::Group { key, .. } => Some((key.clone(), i)),
+                ListRow::Flow(_) => None,
+            })
+    }
+
+    /// Collapses or expands the group of the selected row, selecting its header.
+    fn set_collapsed(&mut self, collapse: bool) {
+        let Some((key, header)) = self.selected_group() else {
+            return;
+        };
+        if collapse {
+            self.collapsed.insert(key);
         } else {
-            None
+            self.collapsed.remove(&key);
+        }
+        self.refresh_rows();
+        self.select_row(header);
+    }
+
+    fn cycle_grouping(&mut self) {
+        let selected = self.selected_id();
+        self.grouping = self.grouping.next();
+        self.collapsed.clear();
+        self.refresh_rows();
+        match selected {
+            Some(id) => self.select_flow(id),
+            None => self.select_row(0),
         }
     }
+
+    fn run_search(&mut self) {
+        let input = self.search.input.clone();
+        let search = match FlowSearch::parse(&input) {
+            Ok(search) => search,
+            Err(err) => {
+                self.search_tx
+                    .send_replace(Some((input, Err(err.to_string()))));
+                return;
+            }
+        };
+        let flow_store = self.flow_store.clone();
+        let search_tx = self.search_tx.clone();
+        tokio::spawn(async move {
+            let hits = flow_store.search(&search).await;
+            search_tx.send_replace(Some((input, Ok(hits))));
+        });
+    }
+
+    fn clear_search(&mut self) {
+        self.search = Search::default();
+        self.search_tx.send_replace(None);
+    }
+
+    /// Moves `step` hits from the current one, wrapping around at either end.
+    fn step_hit(&mut self, step: isize) {
+        let len = self.search.hits.len();
+        if len == 0 {
+            notify_warn!("No search hits, press s to search");
+            return;
+        }
+        self.search.current =
+            (self.search.current as isize + step).rem_euclid(len as isize) as usize;
+        let id = self.search.hits[self.search.current].flow_id;
+        self.select_flow(id);
+    }
+
+    fn search_line(&self) -> Line<'_> {
+        let mut spans = vec![Span::raw(self.search.input.as_str())];
+        match &self.search.status {
+            Some(Err(err)) => {
+                spans.push(Span::styled(
+                    format!("  {err}"),
+                    Style::default().fg(Color::Red),
+                ));
+            }
+            Some(Ok(())) if self.search.hits.is_empty() => spans.push(Span::raw("  No matches")),
+            Some(Ok(())) => {
+                let hit = &self.search.hits[self.search.current];
+                spans.push(Span::styled(
+                    format!("  {}/{}", self.search.current + 1, self.search.hits.len()),
+                    Style::default().fg(Color::Yellow),
+                ));
+                if let Some(found) = hit.matches.first() {
+                    spans.push(Span::styled(
+                        format!("  {}: ", found.field),
+                        Style::default().fg(Color::DarkGray),
+                    ));
+                    spans.push(Span::raw(found.snippet.as_str()));
+                }
+            }
+            None => {}
+        }
+        Line::from(spans)
+    }
+
+    /// The id of the selected flow, `None` when a group header is selected.
+    pub fn selected_id(&self) -> Option<FlowId> {
+        let Some(ListRow::Flow(index)) = self.rows.get(self.state.selected()?) else {
+            return None;
+        };
+        self.ui_rx.borrow().flows.get(*index).map(|f| f.id)
+    }
+
+    /// The flows a bulk operation applies to, the picked ones in list order
+    /// or else the selected one.
+    fn targets(&self) -> Vec<FlowId> {
+        if self.picked.is_empty() {
+            return self.selected_id().into_iter().collect();
+        }
+        self.ui_rx
+            .borrow()
+            .flows
+            .iter()
+            .map(|f| f.id)
+            .filter(|id| self.picked.contains(id))
+            .collect()
+    }
+
+    /// Picks or unpicks the selected flow, or every flow of the selected
+    /// group, and moves on to the next row.
+    fn toggle_pick(&mut self) {
+        let ids: Vec<FlowId> = match self.state.selected().and_then(|i| self.rows.get(i)) {
+            Some(ListRow::Flow(_)) => self.selected_id().into_iter().collect(),
+            Some(ListRow::Group { key, .. }) => self
+                .ui_rx
+                .borrow()
+                .flows
+                .iter()
+                .filter(|f| self.grouping.key(f) == key)
+                .map(|f| f.id)
+                .collect(),
+            None => vec![],
+        };
+        if ids.iter().all(|id| self.picked.contains(id)) {
+            for id in &ids {
+                self.picked.remove(id);
+            }
+        } else {
+            self.picked.extend(ids);
+        }
+        self.next_row();
+    }
+
+    /// Stars the target flows, or unstars them when they all are already.
+    fn toggle_star(&self) {
+        let ids = self.targets();
+        if ids.is_empty() {
+            return;
+        }
+        let starred = {
+            let state = self.ui_rx.borrow();
+            state
+                .flows
+                .iter()
+                .filter(|f| ids.contains(&f.id))
+                .all(|f| f.starred)
+        };
+        let flow_store = self.flow_store.clone();
+        tokio::spawn(async move { flow_store.set_starred(&ids, !starred).await });
+    }
+
+    /// Removes the target flows, starred ones are kept until unstarred.
+    fn delete_targets(&mut self) {
+        let (ids, kept): (Vec<FlowId>, Vec<FlowId>) = {
+            let targets = self.targets();
+            let state = self.ui_rx.borrow();
+            targets
+                .into_iter()
+                .partition(|id| !state.flows.iter().any(|f| f.id == *id && f.starred))
+        };
+        if !kept.is_empty() {
+            notify_warn!("Kept {} starred flows, unstar them to delete", kept.len());
+        }
+        if ids.is_empty() {
+            return;
+        }
+        self.picked.clear();
+        let flow_store = self.flow_store.clone();
+        tokio::spawn(async move {
+            let removed = flow_store.remove_flows(&ids).await;
+            notify_info!("Deleted {removed} flows");
+        });
+    }
 }
 
 impl Drop for FlowList {
@@ -203,35 +601,271 @@ impl Component for FlowList {
                 self.previous_row();
                 ActionResult::Consumed
             }
+            Action::GroupFlows if self.focus.get() => {
+                self.cycle_grouping();
+                ActionResult::Consumed
+            }
+            Action::Left if self.focus.get() && self.grouping != Grouping::None => {
+                self.set_collapsed(true);
+                ActionResult::Consumed
+            }
+            Action::Right if self.focus.get() && self.grouping != Grouping::None => {
+                self.set_collapsed(false);
+                ActionResult::Consumed
+            }
+            Action::Select if self.focus.get() => {
+                match self.state.selected().and_then(|i| self.rows.get(i)) {
+                    Some(ListRow::Group { collapsed, .. }) => {
+                        let collapse = !collapsed;
+                        self.set_collapsed(collapse);
+                        ActionResult::Consumed
+                    }
+                    _ => ActionResult::Ignored,
+                }
+            }
+            Action::Replay => match self.targets().as_slice() {
+                [] => ActionResult::Consumed,
+                [id] => ActionResult::Action(Action::ReplayFlow(*id)),
+                ids => ActionResult::Action(Action::ReplayFlows(ids.to_vec())),
+            },
+            Action::ToggleStar if self.focus.get() => {
+                self.toggle_star();
+                ActionResult::Consumed
+            }
+            Action::ToggleSelect if self.focus.get() => {
+                self.toggle_pick();
+                ActionResult::Consumed
+            }
+            Action::DeleteFlows if self.focus.get() => {
+                self.delete_targets();
+                ActionResult::Consumed
+            }
+            Action::ExportFlows if self.focus.get() => match self.targets() {
+                ids if ids.is_empty() => ActionResult::Consumed,
+                ids => ActionResult::Action(Action::SaveFlows(ids)),
+            },
+            Action::MarkDiff => {
+                self.diff_base = match self.selected_id() {
+                    Some(id) if self.diff_base != Some(id) => Some(id),
+                    _ => None,
+                };
+                ActionResult::Consumed
+            }
+            Action::Diff => match (self.diff_base, self.selected_id()) {
+                (Some(base), Some(id)) if base != id => {
+                    ActionResult::Action(Action::DiffFlows(base, id))
+                }
+                _ => {
+                    notify_warn!("Mark a flow and select another one to compare them");
+                    ActionResult::Consumed
+                }
+            },
+            Action::CopyCurl => match self.selected_id() {
+                Some(id) => ActionResult::Action(Action::CopyCurlFlow(id)),
+                None => ActionResult::Consumed,
+            },
+            Action::CopyHttpie => match self.selected_id() {
+                Some(id) => ActionResult::Action(Action::CopyHttpieFlow(id)),
+                None => ActionResult::Consumed,
+            },
+            Action::CopyReqwest => match self.selected_id() {
+                Some(id) => ActionResult::Action(Action::CopyReqwestFlow(id)),
+                None => ActionResult::Consumed,
+            },
+            Action::Search if self.focus.get() => {
+                self.search.editing = true;
+                ActionResult::Consumed
+            }
+            Action::SearchNext => {
+                self.step_hit(1);
+                ActionResult::Consumed
+            }
+            Action::SearchPrev => {
+                self.step_hit(-1);
+                ActionResult::Consumed
+            }
+            Action::Back if self.focus.get() && self.search.is_active() => {
+                self.clear_search();
+                ActionResult::Consumed
+            }
+            Action::FilterFlows(filter) => {
+                self.set_filter(filter);
+                ActionResult::Consumed
+            }
+            Action::Back if self.focus.get() && !self.picked.is_empty() => {
+                self.picked.clear();
+                ActionResult::Consumed
+            }
             _ => ActionResult::Ignored,
         }
     }
 
+    fn handle_key_event(&mut self, key: &KeyEvent) -> KeyEventResult {
+        if !self.search.editing {
+            return KeyEventResult::Ignored;
+        }
+        match key.code {
+            KeyCode::Esc => self.clear_search(),
+            KeyCode::Enter => {
+                self.search.editing = false;
+                self.run_search();
+            }
+            KeyCode::Char(c) => {
+                self.search.input.push(c);
+                self.search.status = None;
+            }
+            KeyCode::Backspace => {
+                self.search.input.pop();
+                self.search.status = None;
+            }
+            _ => {}
+        }
+        KeyEventResult::Consumed
+    }
+
     fn render(&mut self, f: &mut Frame, area: Rect) -> Result<()> {
-        let guard = self.ui_rx.borrow_and_update();
+        if self.search_rx.has_changed().unwrap_or(false) {
+            let result = self.search_rx.borrow_and_update().clone();
+            // Skip the hits of a search that was cleared or edited since
+            if let Some((input, result)) = result
+                && input == self.search.input
+            {
+                let (hits, status) = match result {
+                    Ok(hits) => (hits, Ok(())),
+                    Err(err) => (vec![], Err(err)),
+                };
+                self.search.hit_ids = hits.iter().map(|h| h.flow_id).collect();
+                self.search.current = 0;
+                self.search.status = Some(status);
+                let first = hits.first().map(|h| h.flow_id);
+                self.search.hits = hits;
+                if let Some(id) = first {
+                    self.select_flow(id);
+                }
+            }
+        }
+
+        let area = if self.search.is_active() {
+            let [list_area, search_area] =
+                Layout::vertical([Constraint::Min(0), Constraint::Length(3)]).areas(area);
+            let search = Paragraph::new(self.search_line())
+                .block(themed_block(Some("Search"), self.search.editing));
+            f.render_widget(search, search_area);
+            list_area
+        } else {
+            area
+        };
+
+        if self.ui_rx.has_changed().unwrap_or(false) || self.rows.is_empty() {
+            self.refresh_rows();
+        }
+        let guard = self.ui_rx.borrow();
 
         let mut rows = vec![];
-        for flow in &guard.flows {
+        for row in &self.rows {
+            let flow = match row {
+                ListRow::Group {
+                    key,
+                    len,
+                    errors,
+                    collapsed,
+                } => {
+                    let mut spans = vec![
+                        Span::styled(
+                            if *collapsed { "▸ " } else { "▾ " },
+                            Style::default().fg(Color::DarkGray),
+                        ),
+                        Span::styled(key.as_str(), Style::default().fg(Color::Blue)),
+                        Span::styled(format!(" ({len})"), Style::default().fg(Color::DarkGray)),
+                    ];
+                    if *errors > 0 {
+                        spans.push(Span::styled(
+                            format!(" {errors} failed"),
+                            Style::default().fg(Color::Red),
+                        ));
+                    }
+                    rows.push(Row::new(vec![Cell::new(Line::from(spans))]));
+                    continue;
+                }
+                ListRow::Flow(index) => match guard.flows.get(*index) {
+                    Some(flow) => flow,
+                    None => continue,
+                },
+            };
             let status = match &flow.response {
                 Some(resp) => resp.code.to_string(),
                 None => "-".to_string(),
             };
-            let c = Line::from(vec![
+            let indent = if self.grouping == Grouping::None {
+                ""
+            } else {
+                "  "
+            };
+            let mut spans = vec![
+                Span::raw(indent),
+                if self.picked.contains(&flow.id) {
+                    Span::styled("● ", Style::default().fg(Color::Green))
+                } else {
+                    Span::raw("  ")
+                },
+                if flow.starred {
+                    Span::styled("★ ", Style::default().fg(Color::Yellow))
+                } else {
+                    Span::raw("  ")
+                },
                 Span::styled(
                     flow.method.to_string(),
                     Style::default().fg(method_color(&flow.method)),
                 ),
                 Span::styled("   ", Style::default()),
                 Span::styled(format!(" {status} "), Style::default()),
-                Span::styled(&flow.uri, Style::default().fg(Color::Cyan)),
-            ]);
+            ];
+            if self.search.hit_ids.contains(&flow.id) {
+                spans.push(Span::styled("[match] ", Style::default().fg(Color::Green)));
+            }
+            if self.diff_base == Some(flow.id) {
+                spans.push(Span::styled("[diff] ", Style::default().fg(Color::Blue)));
+            }
+            if let Some(kind) = flow.error {
+                spans.push(Span::styled(
+                    format!("[{kind}] "),
+                    Style::default().fg(Color::Red),
+                ));
+            }
+            if flow.passthrough {
+                spans.push(Span::styled(
+                    "[passthrough] ",
+                    Style::default().fg(Color::Yellow),
+                ));
+            }
+            if let Some(tls) = flow.raw_tunnel {
+                spans.push(Span::styled(
+                    if tls { "[raw tls] " } else { "[raw tcp] " },
+                    Style::default().fg(Color::Yellow),
+                ));
+            }
+            spans.push(Span::styled(&flow.uri, Style::default().fg(Color::Cyan)));
+            for tag in &flow.tags {
+                spans.push(Span::styled(
+                    format!(" #{tag}"),
+                    Style::default().fg(Color::Magenta),
+                ));
+            }
+            let c = Line::from(spans);
             rows.push(Row::new(vec![Cell::new(c)]));
         }
 
         let widths = [Constraint::Fill(1)];
+        let mut title = match self.picked.len() {
+            0 => "Flows".to_string(),
+            picked => format!("Flows ({picked} picked)"),
+        };
+        if let Some(filter) = self.filter_tx.borrow().as_ref() {
+            title.push_str(&format!(" {filter}"));
+        }
 
         f.render_stateful_widget(
-            themed_table(rows, widths, Some("Flows"), self.focus.get()),
+            themed_table(rows, widths, Some(&title), self.focus.get()),
             area,
             &mut self.state,
         );
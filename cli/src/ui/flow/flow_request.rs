@@ -1,26 +1,33 @@
+use bytes::Bytes;
+use crossterm::event::KeyEvent;
 use rat_focus::HasFocus;
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
     text::Span,
     widgets::{Clear, Paragraph, Wrap},
 };
+use ratatui_image::picker::Picker;
 use roxy_proxy::flow::InterceptedRequest;
-use roxy_shared::content::content_type;
+use roxy_shared::content::{content_type, multipart_boundary};
 use tokio::sync::{mpsc, watch};
-use tracing::{debug, trace};
+use tracing::{debug, trace, warn};
 
 use crate::{
     event::Action,
     ui::{
         flow::tab::LineComponent,
         framework::{
-            component::{ActionResult, Component},
+            component::{ActionResult, Component, KeyEventResult},
             theme::themed_block,
         },
     },
 };
 
-use super::{flow_body::FlowDetailsBody, flow_headers::FlowDetailsHeaders};
+use super::{
+    flow_body::{BodyContent, FlowDetailsBody},
+    flow_headers::FlowDetailsHeaders,
+    grpc::{GrpcMessage, ProtoSchema},
+};
 
 #[derive(Default, Clone)]
 struct UiState {
@@ -36,13 +43,17 @@ pub struct FlowDetailsRequest {
 }
 
 impl FlowDetailsRequest {
-    pub fn new(mut req_rx: tokio::sync::mpsc::Receiver<Option<InterceptedRequest>>) -> Self {
+    pub(crate) fn new(
+        mut req_rx: tokio::sync::mpsc::Receiver<Option<InterceptedRequest>>,
+        proto_schema: ProtoSchema,
+        picker: Option<Picker>,
+    ) -> Self {
         let (ui_tx, ui_rx) = watch::channel(UiState::default());
         let (headers_tx, headers_rx) = mpsc::channel(64);
         let (body_tx, body_rx) = mpsc::channel(64);
 
         let flow_headers = FlowDetailsHeaders::new(headers_rx);
-        let body = FlowDetailsBody::new(body_rx);
+        let body = FlowDetailsBody::new(body_rx, proto_schema, picker);
 
         let this = Self {
             focus: rat_focus::FocusFlag::new().with_name("FlowRequest"),
@@ -65,15 +76,26 @@ impl FlowDetailsRequest {
                             });
 
                         headers_tx
-                            .send(req.headers.clone())
+                            .send(req.header_lines())
                             .await
                             .unwrap_or_else(|e| {
                                 debug!("Failed to send headers: {}", e);
                             });
 
                         let content_type = content_type(&req.headers);
+                        let boundary = multipart_boundary(&req.headers);
+                        let grpc = GrpcMessage::Request(req.uri.path().to_string());
+                        let body = req.load_body().await.unwrap_or_else(|e| {
+                            warn!("Failed to read body: {}", e);
+                            Bytes::new()
+                        });
                         body_tx
-                            .send((content_type, req.body.clone()))
+                            .send(BodyContent {
+                                content_type,
+                                boundary,
+                                body,
+                                grpc,
+                            })
                             .await
                             .unwrap_or_else(|e| {
                                 debug!("Failed to send body: {}", e);
@@ -112,6 +134,10 @@ impl Component for FlowDetailsRequest {
         self.body.update(action)
     }
 
+    fn handle_key_event(&mut self, key: &KeyEvent) -> KeyEventResult {
+        self.body.handle_key_event(key)
+    }
+
     fn render(
         &mut self,
         f: &mut ratatui::Frame,
@@ -1,30 +1,39 @@
+use bytes::Bytes;
+use crossterm::event::KeyEvent;
 use rat_focus::HasFocus;
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
-    text::Span,
+    style::Style,
+    text::{Line, Span, Text},
     widgets::{Paragraph, Wrap},
 };
-use roxy_proxy::flow::InterceptedResponse;
-use roxy_shared::content::content_type;
+use ratatui_image::picker::Picker;
+use roxy_proxy::flow::{InterceptedResponse, error::FlowError};
+use roxy_shared::content::{content_type, multipart_boundary};
 use tokio::sync::{mpsc, watch};
-use tracing::debug;
+use tracing::{debug, warn};
 
 use crate::{
     event::Action,
     ui::{
         flow::tab::LineComponent,
         framework::{
-            component::{ActionResult, Component},
-            theme::themed_block,
+            component::{ActionResult, Component, KeyEventResult},
+            theme::{themed_block, with_theme},
         },
     },
 };
 
-use super::{flow_body::FlowDetailsBody, flow_headers::FlowDetailsHeaders};
+use super::{
+    flow_body::{BodyContent, FlowDetailsBody},
+    flow_headers::{FlowDetailsHeaders, header_lines},
+    grpc::{GrpcMessage, ProtoSchema},
+};
 
 #[derive(Default, Clone)]
 struct UiState {
     data: String,
+    error: Option<FlowError>,
 }
 
 pub struct FlowDetailsResponse {
@@ -36,13 +45,21 @@ pub struct FlowDetailsResponse {
 }
 
 impl FlowDetailsResponse {
-    pub fn new(mut req_rx: tokio::sync::mpsc::Receiver<Option<InterceptedResponse>>) -> Self {
+    pub(crate) fn new(
+        mut req_rx: tokio::sync::mpsc::Receiver<(
+            Option<InterceptedResponse>,
+            Option<FlowError>,
+            String,
+        )>,
+        proto_schema: ProtoSchema,
+        picker: Option<Picker>,
+    ) -> Self {
         let (ui_tx, ui_rx) = watch::channel(UiState::default());
         let (headers_tx, headers_rx) = mpsc::channel(64);
         let (body_tx, body_rx) = mpsc::channel(64);
 
         let flow_headers = FlowDetailsHeaders::new(headers_rx);
-        let body = FlowDetailsBody::new(body_rx);
+        let body = FlowDetailsBody::new(body_rx, proto_schema, picker);
 
         let this = Self {
             focus: rat_focus::FocusFlag::new().with_name("FlowResponse"),
@@ -54,32 +71,51 @@ impl FlowDetailsResponse {
 
         tokio::spawn({
             async move {
-                while let Some(req) = req_rx.recv().await {
+                while let Some((req, error, path)) = req_rx.recv().await {
                     if let Some(resp) = req {
                         ui_tx
                             .send(UiState {
                                 data: resp.request_line(),
+                                error,
                             })
                             .unwrap_or_else(|e| {
                                 debug!("Failed to send UI state update: {}", e);
                             });
 
                         headers_tx
-                            .send(resp.headers.clone())
+                            .send(header_lines(&resp.headers))
                             .await
                             .unwrap_or_else(|e| {
                                 debug!("Failed to send headers: {}", e);
                             });
 
                         let content_type = content_type(&resp.headers);
+                        let boundary = multipart_boundary(&resp.headers);
+                        let grpc = GrpcMessage::Response(path);
+                        let body = resp.load_body().await.unwrap_or_else(|e| {
+                            warn!("Failed to read body: {}", e);
+                            Bytes::new()
+                        });
                         body_tx
-                            .send((content_type, resp.body.clone()))
+                            .send(BodyContent {
+                                content_type,
+                                boundary,
+                                body,
+                                grpc,
+                            })
                             .await
                             .unwrap_or_else(|e| {
                                 debug!("Failed to send body: {}", e);
                             });
                     } else {
-                        debug!("Received None request");
+                        ui_tx
+                            .send(UiState {
+                                data: String::new(),
+                                error,
+                            })
+                            .unwrap_or_else(|e| {
+                                debug!("Failed to send UI state update: {}", e);
+                            });
                     }
                 }
             }
@@ -110,6 +146,10 @@ impl Component for FlowDetailsResponse {
         self.body.update(action)
     }
 
+    fn handle_key_event(&mut self, key: &KeyEvent) -> KeyEventResult {
+        self.body.handle_key_event(key)
+    }
+
     fn render(
         &mut self,
         f: &mut ratatui::Frame,
@@ -121,10 +161,12 @@ impl Component for FlowDetailsResponse {
             .block(themed_block(Some("Line"), self.line_component.focus.get()))
             .wrap(Wrap { trim: true });
 
+        let error_height = if state.error.is_some() { 4 } else { 0 };
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
                 Constraint::Length(3),
+                Constraint::Length(error_height),
                 Constraint::Length(7),
                 Constraint::Min(0),
             ])
@@ -132,8 +174,20 @@ impl Component for FlowDetailsResponse {
 
         f.render_widget(para, chunks[0]);
 
-        self.headers.render(f, chunks[1])?;
-        self.body.render(f, chunks[2])?;
+        if let Some(error) = &state.error {
+            let colors = with_theme(|t| t.colors.clone());
+            let text = Text::from(vec![
+                Line::styled(error.to_string(), Style::default().fg(colors.error)),
+                Line::from(format!("Hint: {}", error.kind.hint())),
+            ]);
+            let para = Paragraph::new(text)
+                .block(themed_block(Some("Error"), false))
+                .wrap(Wrap { trim: true });
+            f.render_widget(para, chunks[1]);
+        }
+
+        self.headers.render(f, chunks[2])?;
+        self.body.render(f, chunks[3])?;
         Ok(())
     }
 }
@@ -1,13 +1,28 @@
 use rat_focus::HasFocus;
-use ratatui::{Frame, layout::Rect, widgets::Paragraph};
-use roxy_proxy::flow::Timing;
+use ratatui::{
+    Frame,
+    layout::{Constraint, Layout, Rect},
+    style::Style,
+    text::{Line, Span},
+    widgets::Paragraph,
+};
+use roxy_proxy::flow::{Timing, TimingPhase};
 use time::OffsetDateTime;
 use tokio::sync::{mpsc, watch};
 
-use crate::ui::framework::{component::Component, theme::themed_block};
+use crate::ui::framework::{
+    component::Component,
+    theme::{themed_block, with_theme},
+};
+
+/// Columns taken by the phase names left of the bars.
+const LABEL_WIDTH: u16 = 10;
+/// Columns taken by the durations right of the bars.
+const DURATION_WIDTH: u16 = 10;
 
 struct State {
     lines: Vec<String>,
+    phases: Vec<TimingPhase>,
 }
 
 pub struct FlowTiming {
@@ -17,7 +32,10 @@ pub struct FlowTiming {
 
 impl FlowTiming {
     pub fn new(mut rx: mpsc::Receiver<Timing>) -> Self {
-        let (ui_tx, ui_rx) = watch::channel(State { lines: vec![] });
+        let (ui_tx, ui_rx) = watch::channel(State {
+            lines: vec![],
+            phases: vec![],
+        });
 
         tokio::spawn({
             async move {
@@ -25,6 +43,22 @@ impl FlowTiming {
                     let lines = vec![
                         timing_line(&timing.client_conn_established, "client_conn_established"),
                         timing_line(&timing.server_conn_initiated, "server_conn_initiated"),
+                        format!(
+                            "server_conn: {}",
+                            if timing.server_conn_reused {
+                                "reused"
+                            } else {
+                                "new"
+                            }
+                        ),
+                        timing_line(&timing.server_conn_dns_resolved, "server_conn_DNS_resolved"),
+                        format!(
+                            "server_conn_DNS_lookup: {}",
+                            timing
+                                .server_conn_dns_lookup
+                                .map(|d| format!("{}ms", d.as_millis()))
+                                .unwrap_or_else(|| "N/A".to_string())
+                        ),
                         timing_line(
                             &timing.server_conn_tcp_handshake,
                             "server_conn_TCP_handshake",
@@ -44,7 +78,8 @@ impl FlowTiming {
                         timing_line(&timing.client_conn_closed, "client_conn_closed"),
                         timing_line(&timing.server_conn_closed, "server_conn_closed"),
                     ];
-                    ui_tx.send(State { lines }).unwrap_or_else(|e| {
+                    let phases = timing.waterfall();
+                    ui_tx.send(State { lines, phases }).unwrap_or_else(|e| {
                         tracing::debug!("Failed to send UI state update: {}", e);
                     });
                 }
@@ -67,6 +102,58 @@ fn timing_line(time: &Option<OffsetDateTime>, key: &str) -> String {
     )
 }
 
+/// One row per phase, each bar placed by when the phase started and as long
+/// as it took relative to the whole exchange.
+fn waterfall_lines(phases: &[TimingPhase], width: u16) -> Vec<Line<'static>> {
+    let (Some(origin), Some(finish)) = (
+        phases.iter().map(|p| p.start).min(),
+        phases.iter().map(|p| p.end).max(),
+    ) else {
+        return vec![Line::from("No phase of this flow was timed")];
+    };
+    let colors = with_theme(|t| t.colors.clone());
+    let bar_width = width.saturating_sub(LABEL_WIDTH + DURATION_WIDTH).max(1) as f64;
+    let total = (finish - origin).as_seconds_f64();
+    let column = |at: OffsetDateTime| {
+        if total > 0.0 {
+            ((at - origin).as_seconds_f64() / total * bar_width).round() as usize
+        } else {
+            0
+        }
+    };
+    phases
+        .iter()
+        .map(|phase| {
+            let color = match phase.name {
+                "intercept" => colors.secondary,
+                "dns" => colors.debug,
+                "connect" => colors.warn,
+                "tls" => colors.trace,
+                "waiting" => colors.info,
+                _ => colors.success,
+            };
+            let offset = column(phase.start);
+            let length = column(phase.end).saturating_sub(offset).max(1);
+            let padding = (bar_width as usize).saturating_sub(offset + length);
+            Line::from(vec![
+                Span::raw(format!(
+                    "{:<width$}",
+                    phase.name,
+                    width = LABEL_WIDTH as usize
+                )),
+                Span::raw(" ".repeat(offset)),
+                Span::styled(" ".repeat(length), Style::default().bg(color)),
+                Span::raw(" ".repeat(padding)),
+                Span::raw(format!(
+                    "{:>width$}",
+                    format!("{:.1}ms", phase.duration().as_seconds_f64() * 1000.0),
+                    width = DURATION_WIDTH as usize
+                )),
+            ])
+        })
+        .collect()
+}
+
 impl HasFocus for FlowTiming {
     fn build(&self, builder: &mut rat_focus::FocusBuilder) {
         builder.leaf_widget(self);
@@ -83,10 +170,22 @@ impl HasFocus for FlowTiming {
 
 impl Component for FlowTiming {
     fn render(&mut self, f: &mut Frame, area: Rect) -> color_eyre::eyre::Result<()> {
+        let state = self.state.borrow();
+        let [waterfall_area, lines_area] = Layout::vertical([
+            Constraint::Length(state.phases.len().max(1) as u16 + 2),
+            Constraint::Min(0),
+        ])
+        .areas(area);
+        let block = themed_block(Some("Waterfall"), self.focus.get());
+        let inner = block.inner(waterfall_area);
+        f.render_widget(
+            Paragraph::new(waterfall_lines(&state.phases, inner.width)).block(block),
+            waterfall_area,
+        );
         f.render_widget(
-            Paragraph::new(self.state.borrow().lines.join("\n"))
+            Paragraph::new(state.lines.join("\n"))
                 .block(themed_block(Some("Timing"), self.focus.get())),
-            area,
+            lines_area,
         );
         Ok(())
     }
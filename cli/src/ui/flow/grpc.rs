@@ -0,0 +1,524 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use bytes::Bytes;
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+};
+use tracing::debug;
+
+use crate::notify_error;
+
+const FRAME_COMPRESSED: u8 = 0x01;
+const FRAME_TRAILERS: u8 = 0x80;
+
+const LABEL_REPEATED: u64 = 3;
+
+const TYPE_DOUBLE: u64 = 1;
+const TYPE_FLOAT: u64 = 2;
+const TYPE_INT64: u64 = 3;
+const TYPE_UINT64: u64 = 4;
+const TYPE_INT32: u64 = 5;
+const TYPE_FIXED64: u64 = 6;
+const TYPE_FIXED32: u64 = 7;
+const TYPE_BOOL: u64 = 8;
+const TYPE_STRING: u64 = 9;
+const TYPE_MESSAGE: u64 = 11;
+const TYPE_BYTES: u64 = 12;
+const TYPE_UINT32: u64 = 13;
+const TYPE_ENUM: u64 = 14;
+const TYPE_SFIXED32: u64 = 15;
+const TYPE_SFIXED64: u64 = 16;
+const TYPE_SINT32: u64 = 17;
+const TYPE_SINT64: u64 = 18;
+
+/// Which side of a gRPC call a body belongs to, along with the request path
+/// naming the method, e.g. `/helloworld.Greeter/SayHello`.
+#[derive(Debug, Clone)]
+pub(crate) enum GrpcMessage {
+    Request(String),
+    Response(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum WireValue<'a> {
+    Varint(u64),
+    I64(u64),
+    Len(&'a [u8]),
+    I32(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct WireField<'a> {
+    number: u64,
+    value: WireValue<'a>,
+}
+
+impl WireField<'_> {
+    fn string(&self, number: u64) -> Option<String> {
+        match self.value {
+            WireValue::Len(bytes) if self.number == number => {
+                Some(String::from_utf8_lossy(bytes).into_owned())
+            }
+            _ => None,
+        }
+    }
+
+    fn varint(&self, number: u64) -> Option<u64> {
+        match self.value {
+            WireValue::Varint(value) if self.number == number => Some(value),
+            _ => None,
+        }
+    }
+}
+
+fn read_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Splits a protobuf message into its fields, `None` when `buf` is not valid
+/// wire format. Groups are deprecated and treated as invalid.
+fn parse_message(mut buf: &[u8]) -> Option<Vec<WireField<'_>>> {
+    let mut fields = vec![];
+    while !buf.is_empty() {
+        let key = read_varint(&mut buf)?;
+        let number = key >> 3;
+        if number == 0 {
+            return None;
+        }
+        let value = match key & 0x07 {
+            0 => WireValue::Varint(read_varint(&mut buf)?),
+            1 => {
+                let (bytes, rest) = buf.split_first_chunk::<8>()?;
+                buf = rest;
+                WireValue::I64(u64::from_le_bytes(*bytes))
+            }
+            2 => {
+                let len = usize::try_from(read_varint(&mut buf)?).ok()?;
+                if len > buf.len() {
+                    return None;
+                }
+                let (bytes, rest) = buf.split_at(len);
+                buf = rest;
+                WireValue::Len(bytes)
+            }
+            5 => {
+                let (bytes, rest) = buf.split_first_chunk::<4>()?;
+                buf = rest;
+                WireValue::I32(u32::from_le_bytes(*bytes))
+            }
+            _ => return None,
+        };
+        fields.push(WireField { number, value });
+    }
+    Some(fields)
+}
+
+#[derive(Debug, Clone)]
+struct FieldDescriptor {
+    name: String,
+    kind: u64,
+    repeated: bool,
+    type_name: String,
+}
+
+#[derive(Debug, Default)]
+struct Schema {
+    messages: HashMap<String, HashMap<u64, FieldDescriptor>>,
+    /// Enum value names keyed by their number as encoded on the wire.
+    enums: HashMap<String, HashMap<u64, String>>,
+    /// Input and output message types keyed by request path.
+    methods: HashMap<String, (String, String)>,
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{scope}.{name}")
+    }
+}
+
+fn type_name(name: String) -> String {
+    name.trim_start_matches('.').to_string()
+}
+
+impl Schema {
+    /// Adds the files of a serialized `google.protobuf.FileDescriptorSet`.
+    fn add_file_set(&mut self, buf: &[u8]) -> Option<()> {
+        for field in parse_message(buf)? {
+            if let (1, WireValue::Len(file)) = (field.number, field.value) {
+                self.add_file(file)?;
+            }
+        }
+        Some(())
+    }
+
+    fn add_file(&mut self, buf: &[u8]) -> Option<()> {
+        let fields = parse_message(buf)?;
+        let package = fields.iter().find_map(|f| f.string(2)).unwrap_or_default();
+        for field in fields {
+            match (field.number, field.value) {
+                (4, WireValue::Len(message)) => self.add_message(&package, message)?,
+                (5, WireValue::Len(enumeration)) => self.add_enum(&package, enumeration)?,
+                (6, WireValue::Len(service)) => self.add_service(&package, service)?,
+                _ => {}
+            }
+        }
+        Some(())
+    }
+
+    fn add_message(&mut self, scope: &str, buf: &[u8]) -> Option<()> {
+        let fields = parse_message(buf)?;
+        let name = qualify(scope, &fields.iter().find_map(|f| f.string(1))?);
+        let mut descriptors = HashMap::new();
+        for field in fields {
+            match (field.number, field.value) {
+                (2, WireValue::Len(descriptor)) => {
+                    let parts = parse_message(descriptor)?;
+                    let number = parts.iter().find_map(|f| f.varint(3))?;
+                    descriptors.insert(
+                        number,
+                        FieldDescriptor {
+                            name: parts.iter().find_map(|f| f.string(1))?,
+                            kind: parts.iter().find_map(|f| f.varint(5))?,
+                            repeated: parts.iter().find_map(|f| f.varint(4))
+                                == Some(LABEL_REPEATED),
+                            type_name: type_name(
+                                parts.iter().find_map(|f| f.string(6)).unwrap_or_default(),
+                            ),
+                        },
+                    );
+                }
+                (3, WireValue::Len(nested)) => self.add_message(&name, nested)?,
+                (4, WireValue::Len(enumeration)) => self.add_enum(&name, enumeration)?,
+                _ => {}
+            }
+        }
+        self.messages.insert(name, descriptors);
+        Some(())
+    }
+
+    fn add_enum(&mut self, scope: &str, buf: &[u8]) -> Option<()> {
+        let fields = parse_message(buf)?;
+        let name = qualify(scope, &fields.iter().find_map(|f| f.string(1))?);
+        let mut values = HashMap::new();
+        for field in fields {
+            if let (2, WireValue::Len(value)) = (field.number, field.value) {
+                let parts = parse_message(value)?;
+                values.insert(
+                    parts.iter().find_map(|f| f.varint(2)).unwrap_or_default(),
+                    parts.iter().find_map(|f| f.string(1))?,
+                );
+            }
+        }
+        self.enums.insert(name, values);
+        Some(())
+    }
+
+    fn add_service(&mut self, scope: &str, buf: &[u8]) -> Option<()> {
+        let fields = parse_message(buf)?;
+        let service = qualify(scope, &fields.iter().find_map(|f| f.string(1))?);
+        for field in fields {
+            if let (2, WireValue::Len(method)) = (field.number, field.value) {
+                let parts = parse_message(method)?;
+                let name = parts.iter().find_map(|f| f.string(1))?;
+                let input = type_name(parts.iter().find_map(|f| f.string(2))?);
+                let output = type_name(parts.iter().find_map(|f| f.string(3))?);
+                self.methods
+                    .insert(format!("/{service}/{name}"), (input, output));
+            }
+        }
+        Some(())
+    }
+}
+
+/// Message types loaded from compiled descriptor sets, as written by
+/// `protoc --include_imports --descriptor_set_out`.
+///
+/// Without a matching method, messages are decoded from the wire format alone
+/// and fields are shown by number.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ProtoSchema {
+    inner: Arc<Schema>,
+}
+
+impl ProtoSchema {
+    pub(crate) fn load(paths: &[PathBuf]) -> Self {
+        let mut schema = Schema::default();
+        for path in paths {
+            match std::fs::read(path) {
+                Ok(bytes) => {
+                    if schema.add_file_set(&bytes).is_none() {
+                        notify_error!("Invalid descriptor set {}", path.display());
+                    }
+                }
+                Err(err) => {
+                    notify_error!("Failed to read descriptor set {} {err}", path.display());
+                }
+            }
+        }
+        debug!(
+            "Loaded {} proto messages, {} methods",
+            schema.messages.len(),
+            schema.methods.len()
+        );
+        Self {
+            inner: Arc::new(schema),
+        }
+    }
+
+    fn message_type(&self, message: &GrpcMessage) -> Option<&str> {
+        match message {
+            GrpcMessage::Request(path) => self.inner.methods.get(path).map(|(i, _)| i.as_str()),
+            GrpcMessage::Response(path) => self.inner.methods.get(path).map(|(_, o)| o.as_str()),
+        }
+    }
+
+    fn field(&self, message_type: Option<&str>, number: u64) -> Option<&FieldDescriptor> {
+        self.inner.messages.get(message_type?)?.get(&number)
+    }
+
+    fn enum_value(&self, enum_type: &str, value: u64) -> Option<&str> {
+        self.inner
+            .enums
+            .get(enum_type)?
+            .get(&value)
+            .map(String::as_str)
+    }
+}
+
+/// Renders a gRPC or gRPC-Web body, one section per length-prefixed message.
+pub(crate) fn render_grpc(
+    body: &Bytes,
+    schema: &ProtoSchema,
+    message: &GrpcMessage,
+) -> Vec<Line<'static>> {
+    let message_type = schema.message_type(message);
+    let mut lines = vec![];
+    let mut buf = &body[..];
+    let mut index = 0;
+    while !buf.is_empty() {
+        let Some((header, rest)) = buf.split_first_chunk::<5>() else {
+            lines.push(error_line(format!(
+                "Truncated frame header ({} bytes)",
+                buf.len()
+            )));
+            break;
+        };
+        let flags = header[0];
+        let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if len > rest.len() {
+            lines.push(error_line(format!(
+                "Truncated message, expected {len} bytes got {}",
+                rest.len()
+            )));
+            break;
+        }
+        let (frame, rest) = rest.split_at(len);
+        buf = rest;
+
+        if flags & FRAME_TRAILERS != 0 {
+            lines.push(heading("Trailers".to_string()));
+            for line in String::from_utf8_lossy(frame).lines() {
+                lines.push(Line::from(format!("  {}", line.trim_end())));
+            }
+        } else if flags & FRAME_COMPRESSED != 0 {
+            lines.push(heading(format!("Message {index} compressed ({len} bytes)")));
+            lines.push(Line::from(format!("  {}", hex(frame))));
+        } else {
+            lines.push(heading(format!(
+                "Message {index} {}",
+                message_type.unwrap_or("(unknown type)")
+            )));
+            match parse_message(frame) {
+                Some(fields) => render_fields(&mut lines, schema, message_type, &fields, 1),
+                None => {
+                    lines.push(error_line("  Not a protobuf message".to_string()));
+                    lines.push(Line::from(format!("  {}", hex(frame))));
+                }
+            }
+        }
+        index += 1;
+    }
+    lines
+}
+
+fn heading(text: String) -> Line<'static> {
+    Line::from(Span::styled(text, Style::default().fg(Color::Blue)))
+}
+
+fn error_line(text: String) -> Line<'static> {
+    Line::from(Span::styled(text, Style::default().fg(Color::Red)))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn as_text(bytes: &[u8]) -> Option<&str> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .filter(|s| !s.chars().any(|c| c.is_control() && !c.is_whitespace()))
+}
+
+fn render_fields(
+    lines: &mut Vec<Line<'static>>,
+    schema: &ProtoSchema,
+    message_type: Option<&str>,
+    fields: &[WireField],
+    indent: usize,
+) {
+    let indent_str = "  ".repeat(indent);
+    for field in fields {
+        let descriptor = schema.field(message_type, field.number);
+        let name = match descriptor {
+            Some(d) => d.name.clone(),
+            None => field.number.to_string(),
+        };
+        let label = vec![
+            Span::raw(indent_str.clone()),
+            Span::styled(name, Style::default().fg(Color::Cyan)),
+        ];
+
+        let nested = match (field.value, descriptor) {
+            (WireValue::Len(bytes), Some(d)) if d.kind == TYPE_MESSAGE => {
+                parse_message(bytes).map(|f| (f, Some(d.type_name.as_str())))
+            }
+            (WireValue::Len(bytes), None) if as_text(bytes).is_none() => {
+                parse_message(bytes).map(|f| (f, None))
+            }
+            _ => None,
+        };
+        if let Some((nested, nested_type)) = nested {
+            lines.push(Line::from(
+                [
+                    label,
+                    vec![Span::styled(" {", Style::default().fg(Color::DarkGray))],
+                ]
+                .concat(),
+            ));
+            render_fields(lines, schema, nested_type, &nested, indent + 1);
+            lines.push(Line::from(vec![
+                Span::raw(indent_str.clone()),
+                Span::styled("}", Style::default().fg(Color::DarkGray)),
+            ]));
+            continue;
+        }
+
+        let value = match descriptor {
+            Some(d) => typed_value(schema, d, field.value),
+            None => raw_value(field.value),
+        };
+        lines.push(Line::from(
+            [
+                label,
+                vec![
+                    Span::styled(": ", Style::default().fg(Color::DarkGray)),
+                    value,
+                ],
+            ]
+            .concat(),
+        ));
+    }
+}
+
+/// A value decoded without a schema, guessing length delimited values are text.
+fn raw_value(value: WireValue) -> Span<'static> {
+    match value {
+        WireValue::Varint(v) => Span::styled(v.to_string(), Style::default().fg(Color::Yellow)),
+        WireValue::I64(v) => Span::styled(format!("{v:#018x}"), Style::default().fg(Color::Yellow)),
+        WireValue::I32(v) => Span::styled(format!("{v:#010x}"), Style::default().fg(Color::Yellow)),
+        WireValue::Len(bytes) => match as_text(bytes) {
+            Some(s) => Span::styled(format!("\"{s}\""), Style::default().fg(Color::Green)),
+            None => Span::styled(hex(bytes), Style::default().fg(Color::Magenta)),
+        },
+    }
+}
+
+fn typed_value(
+    schema: &ProtoSchema,
+    descriptor: &FieldDescriptor,
+    value: WireValue,
+) -> Span<'static> {
+    let number = |s: String| Span::styled(s, Style::default().fg(Color::Yellow));
+    match (descriptor.kind, value) {
+        (TYPE_STRING, WireValue::Len(bytes)) => Span::styled(
+            format!("\"{}\"", String::from_utf8_lossy(bytes)),
+            Style::default().fg(Color::Green),
+        ),
+        (TYPE_BYTES, WireValue::Len(bytes)) => {
+            Span::styled(hex(bytes), Style::default().fg(Color::Magenta))
+        }
+        (TYPE_ENUM, WireValue::Varint(v)) => match schema.enum_value(&descriptor.type_name, v) {
+            Some(name) => Span::styled(name.to_string(), Style::default().fg(Color::Magenta)),
+            None => number((v as i32).to_string()),
+        },
+        (TYPE_BOOL, WireValue::Varint(v)) => {
+            Span::styled((v != 0).to_string(), Style::default().fg(Color::Magenta))
+        }
+        (kind, WireValue::Len(bytes)) if descriptor.repeated => match unpack(kind, bytes) {
+            Some(values) => number(format!("[{}]", values.join(", "))),
+            None => raw_value(value),
+        },
+        (kind, value) => match scalar(kind, value) {
+            Some(s) => number(s),
+            None => raw_value(value),
+        },
+    }
+}
+
+/// Decodes a packed repeated scalar field.
+fn unpack(kind: u64, mut bytes: &[u8]) -> Option<Vec<String>> {
+    let mut values = vec![];
+    while !bytes.is_empty() {
+        let value = match kind {
+            TYPE_DOUBLE | TYPE_FIXED64 | TYPE_SFIXED64 => {
+                let (chunk, rest) = bytes.split_first_chunk::<8>()?;
+                bytes = rest;
+                WireValue::I64(u64::from_le_bytes(*chunk))
+            }
+            TYPE_FLOAT | TYPE_FIXED32 | TYPE_SFIXED32 => {
+                let (chunk, rest) = bytes.split_first_chunk::<4>()?;
+                bytes = rest;
+                WireValue::I32(u32::from_le_bytes(*chunk))
+            }
+            _ => WireValue::Varint(read_varint(&mut bytes)?),
+        };
+        values.push(scalar(kind, value)?);
+    }
+    Some(values)
+}
+
+fn scalar(kind: u64, value: WireValue) -> Option<String> {
+    let s = match (kind, value) {
+        (TYPE_INT64, WireValue::Varint(v)) => (v as i64).to_string(),
+        (TYPE_INT32 | TYPE_ENUM, WireValue::Varint(v)) => (v as i32).to_string(),
+        (TYPE_UINT64 | TYPE_UINT32, WireValue::Varint(v)) => v.to_string(),
+        (TYPE_BOOL, WireValue::Varint(v)) => (v != 0).to_string(),
+        (TYPE_SINT32 | TYPE_SINT64, WireValue::Varint(v)) => {
+            ((v >> 1) as i64 ^ -((v & 1) as i64)).to_string()
+        }
+        (TYPE_DOUBLE, WireValue::I64(v)) => f64::from_bits(v).to_string(),
+        (TYPE_FIXED64, WireValue::I64(v)) => v.to_string(),
+        (TYPE_SFIXED64, WireValue::I64(v)) => (v as i64).to_string(),
+        (TYPE_FLOAT, WireValue::I32(v)) => f32::from_bits(v).to_string(),
+        (TYPE_FIXED32, WireValue::I32(v)) => v.to_string(),
+        (TYPE_SFIXED32, WireValue::I32(v)) => (v as i32).to_string(),
+        _ => return None,
+    };
+    Some(s)
+}
@@ -0,0 +1,161 @@
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+
+/// Bytes shown per page, bigger bodies are paged through instead of building
+/// millions of lines at once.
+pub const HEX_PAGE: usize = 4096;
+const ROW: usize = 16;
+
+pub fn hex_pages(len: usize) -> usize {
+    len.div_ceil(HEX_PAGE).max(1)
+}
+
+/// Renders page `page` of `body` as a hex dump: the offset, sixteen bytes in
+/// two groups of eight and an ascii gutter with `.` for anything unprintable.
+/// The first line names the format sniffed from the leading bytes.
+pub fn render_hex(body: &[u8], page: usize) -> Vec<Line<'static>> {
+    let dim = Style::default().fg(Color::DarkGray);
+    let pages = hex_pages(body.len());
+    let page = page.min(pages - 1);
+
+    let mut summary = vec![Span::styled(
+        sniff_format(body).unwrap_or("binary data"),
+        Style::default()
+            .fg(Color::Yellow)
+            .add_modifier(Modifier::BOLD),
+    )];
+    summary.push(Span::styled(format!(" {} bytes", body.len()), dim));
+    if pages > 1 {
+        summary.push(Span::styled(
+            format!(" page {}/{pages}, left and right to page", page + 1),
+            dim,
+        ));
+    }
+    let mut lines = vec![Line::from(summary), Line::default()];
+
+    let start = page * HEX_PAGE;
+    let end = (start + HEX_PAGE).min(body.len());
+    for (index, row) in body[start..end].chunks(ROW).enumerate() {
+        let mut hex = String::with_capacity(ROW * 3 + 1);
+        for i in 0..ROW {
+            if i == ROW / 2 {
+                hex.push(' ');
+            }
+            match row.get(i) {
+                Some(byte) => hex.push_str(&format!("{byte:02x} ")),
+                None => hex.push_str("   "),
+            }
+        }
+        let ascii = row
+            .iter()
+            .map(|b| {
+                if b.is_ascii_graphic() || *b == b' ' {
+                    *b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+        lines.push(Line::from(vec![
+            Span::styled(format!("{:08x}  ", start + index * ROW), dim),
+            Span::raw(hex),
+            Span::styled(format!(" |{ascii}|"), Style::default().fg(Color::Cyan)),
+        ]));
+    }
+    lines
+}
+
+/// Names the format of `body` from its magic bytes, falling back to a check
+/// whether the whole body parses as protobuf fields.
+fn sniff_format(body: &[u8]) -> Option<&'static str> {
+    const MAGIC: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "PNG image"),
+        (b"\xff\xd8\xff", "JPEG image"),
+        (b"GIF87a", "GIF image"),
+        (b"GIF89a", "GIF image"),
+        (b"BM", "BMP image"),
+        (b"\x00\x00\x01\x00", "ICO image"),
+        (b"%PDF-", "PDF document"),
+        (b"PK\x03\x04", "ZIP archive"),
+        (b"PK\x05\x06", "ZIP archive (empty)"),
+        (b"\x1f\x8b", "gzip data"),
+        (b"\x28\xb5\x2f\xfd", "zstd data"),
+        (b"BZh", "bzip2 data"),
+        (b"\xfd7zXZ\x00", "xz data"),
+        (b"7z\xbc\xaf\x27\x1c", "7z archive"),
+        (b"\x00asm", "WebAssembly module"),
+        (b"\x7fELF", "ELF binary"),
+        (b"wOFF", "WOFF font"),
+        (b"wOF2", "WOFF2 font"),
+        (b"SQLite format 3\x00", "SQLite database"),
+    ];
+    if let Some((_, name)) = MAGIC.iter().find(|(magic, _)| body.starts_with(magic)) {
+        return Some(name);
+    }
+    if body.len() >= 12 && body.starts_with(b"RIFF") {
+        return match &body[8..12] {
+            b"WEBP" => Some("WEBP image"),
+            b"WAVE" => Some("WAV audio"),
+            b"AVI " => Some("AVI video"),
+            _ => Some("RIFF data"),
+        };
+    }
+    if body.len() >= 12 && &body[4..8] == b"ftyp" {
+        return Some("MP4/ISO media");
+    }
+    if body.len() >= 5 && body[0] <= 1 {
+        let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+        if len + 5 == body.len() {
+            return Some("gRPC message");
+        }
+    }
+    looks_like_protobuf(body).then_some("protobuf message (probably)")
+}
+
+/// Whether `body` is a sequence of well formed protobuf fields. Short bodies
+/// pass this by chance too often to say anything.
+fn looks_like_protobuf(mut body: &[u8]) -> bool {
+    if body.len() < 4 {
+        return false;
+    }
+    while !body.is_empty() {
+        let Some(tag) = read_varint(&mut body) else {
+            return false;
+        };
+        if tag >> 3 == 0 {
+            return false;
+        }
+        let len = match tag & 0x7 {
+            0 => match read_varint(&mut body) {
+                Some(_) => 0,
+                None => return false,
+            },
+            1 => 8,
+            2 => match read_varint(&mut body) {
+                Some(len) => len as usize,
+                None => return false,
+            },
+            5 => 4,
+            _ => return false,
+        };
+        if len > body.len() {
+            return false;
+        }
+        body = &body[len..];
+    }
+    true
+}
+
+fn read_varint(body: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, byte) in body.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *body = &body[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
@@ -1,20 +1,23 @@
 use kuchiki::{NodeData, NodeRef, parse_html, traits::TendrilSink};
 use ratatui::{
-    style::{Color, Style},
+    style::Style,
     text::{Line, Span},
 };
 
+use crate::config::SyntaxColors;
+
 pub fn highlight_html_dom<'a, R: std::io::Read>(
     reader: &mut R,
+    colors: &SyntaxColors,
 ) -> Result<Vec<Line<'a>>, std::io::Error> {
     let parser = parse_html().from_utf8();
     let dom = parser.read_from(reader)?;
     let mut out = Vec::new();
-    walk_node(&dom, 0, &mut out);
+    walk_node(&dom, 0, &mut out, colors);
     Ok(out)
 }
 
-fn walk_node(node: &NodeRef, depth: usize, out: &mut Vec<Line>) {
+fn walk_node(node: &NodeRef, depth: usize, out: &mut Vec<Line>, colors: &SyntaxColors) {
     match &node.data() {
         NodeData::Text(contents) => {
             let text = contents.borrow();
@@ -22,7 +25,7 @@ fn walk_node(node: &NodeRef, depth: usize, out: &mut Vec<Line>) {
             if !trimmed.is_empty() {
                 out.push(Line::from(Span::styled(
                     format!("{:indent$}{}", "", trimmed, indent = depth * 2),
-                    Style::default().fg(Color::White),
+                    Style::default().fg(colors.text),
                 )));
             }
         }
@@ -32,29 +35,29 @@ fn walk_node(node: &NodeRef, depth: usize, out: &mut Vec<Line>) {
 
             spans.push(Span::styled(
                 format!("{:indent$}<", "", indent = depth * 2),
-                Style::default().fg(Color::DarkGray),
+                Style::default().fg(colors.punctuation),
             ));
 
             spans.push(Span::styled(
                 element_data.name.local.to_string(),
-                Style::default().fg(Color::Blue),
+                Style::default().fg(colors.tag),
             ));
 
             for attr in element_data.attributes.borrow().map.iter() {
                 spans.push(Span::raw(" "));
                 spans.push(Span::styled(
                     attr.0.local.to_string(),
-                    Style::default().fg(Color::Cyan),
+                    Style::default().fg(colors.attribute),
                 ));
-                spans.push(Span::styled("=\"", Style::default().fg(Color::DarkGray)));
+                spans.push(Span::styled("=\"", Style::default().fg(colors.punctuation)));
                 spans.push(Span::styled(
                     attr.1.value.to_string(),
-                    Style::default().fg(Color::Green),
+                    Style::default().fg(colors.string),
                 ));
-                spans.push(Span::styled("\"", Style::default().fg(Color::DarkGray)));
+                spans.push(Span::styled("\"", Style::default().fg(colors.punctuation)));
             }
 
-            spans.push(Span::styled(">", Style::default().fg(Color::DarkGray)));
+            spans.push(Span::styled(">", Style::default().fg(colors.punctuation)));
             out.push(Line::from(spans));
         }
 
@@ -66,7 +69,7 @@ fn walk_node(node: &NodeRef, depth: usize, out: &mut Vec<Line>) {
                     contents.borrow().clone(),
                     indent = depth * 2
                 ),
-                Style::default().fg(Color::Magenta),
+                Style::default().fg(colors.comment),
             )));
         }
 
@@ -74,20 +77,20 @@ fn walk_node(node: &NodeRef, depth: usize, out: &mut Vec<Line>) {
     }
 
     for child in node.children() {
-        walk_node(&child, depth + 1, out);
+        walk_node(&child, depth + 1, out, colors);
     }
 
     if let NodeData::Element(element_data) = &node.data() {
         out.push(Line::from(vec![
             Span::styled(
                 format!("{:indent$}</", "", indent = depth * 2),
-                Style::default().fg(Color::DarkGray),
+                Style::default().fg(colors.punctuation),
             ),
             Span::styled(
                 element_data.name.local.to_string(),
-                Style::default().fg(Color::Blue),
+                Style::default().fg(colors.tag),
             ),
-            Span::styled(">", Style::default().fg(Color::DarkGray)),
+            Span::styled(">", Style::default().fg(colors.punctuation)),
         ]));
     }
 }
@@ -1,21 +1,17 @@
 use bytes::Bytes;
 use cow_utils::CowUtils;
 use ratatui::{
-    style::{Color, Style},
+    style::Style,
     text::{Line, Span},
 };
 use serde_json::Value;
 use tracing::error;
 
-use crate::notify_error;
+use crate::{config::SyntaxColors, notify_error};
 
-pub fn highlight_json(raw: &Bytes) -> Vec<Line<'static>> {
+pub fn highlight_json(raw: &Bytes, colors: &SyntaxColors) -> Vec<Line<'static>> {
     match serde_json::from_str::<Value>(&String::from_utf8_lossy(raw)) {
-        Ok(json) => {
-            let mut lines: Vec<Line> = vec![];
-            walk(&json, &mut lines, 0);
-            lines
-        }
+        Ok(json) => highlight_value(&json, colors),
         Err(err) => {
             notify_error!("Json {}", err);
             let s = String::from_utf8_lossy(raw).to_string();
@@ -28,90 +24,96 @@ pub fn highlight_json(raw: &Bytes) -> Vec<Line<'static>> {
     }
 }
 
-fn walk(v: &Value, lines: &mut Vec<Line>, indent: usize) {
+pub fn highlight_value(json: &Value, colors: &SyntaxColors) -> Vec<Line<'static>> {
+    let mut lines: Vec<Line> = vec![];
+    walk(json, &mut lines, 0, colors);
+    lines
+}
+
+fn walk(v: &Value, lines: &mut Vec<Line>, indent: usize, colors: &SyntaxColors) {
     let indent_str = "  ".repeat(indent);
 
     match v {
         Value::Null => {
             lines.push(Line::from(vec![
                 Span::raw(indent_str),
-                Span::styled("null", Style::default().fg(Color::DarkGray)),
+                Span::styled("null", Style::default().fg(colors.literal)),
             ]));
         }
 
         Value::Bool(val) => {
             lines.push(Line::from(vec![
                 Span::raw(indent_str),
-                Span::styled(val.to_string(), Style::default().fg(Color::Magenta)),
+                Span::styled(val.to_string(), Style::default().fg(colors.literal)),
             ]));
         }
 
         Value::Number(number) => {
             lines.push(Line::from(vec![
                 Span::raw(indent_str),
-                Span::styled(number.to_string(), Style::default().fg(Color::Yellow)),
+                Span::styled(number.to_string(), Style::default().fg(colors.number)),
             ]));
         }
 
         Value::String(s) => {
             lines.push(Line::from(vec![
                 Span::raw(indent_str),
-                Span::styled(format!("\"{s}\""), Style::default().fg(Color::Green)),
+                Span::styled(format!("\"{s}\""), Style::default().fg(colors.string)),
             ]));
         }
 
         Value::Array(values) => {
             lines.push(Line::from(vec![
                 Span::raw(indent_str.clone()),
-                Span::styled("[", Style::default().fg(Color::DarkGray)),
+                Span::styled("[", Style::default().fg(colors.punctuation)),
             ]));
             for v in values {
-                walk(v, lines, indent + 1);
+                walk(v, lines, indent + 1, colors);
             }
             lines.push(Line::from(vec![
                 Span::raw(indent_str),
-                Span::styled("]", Style::default().fg(Color::DarkGray)),
+                Span::styled("]", Style::default().fg(colors.punctuation)),
             ]));
         }
 
         Value::Object(map) => {
             lines.push(Line::from(vec![
                 Span::raw(indent_str.clone()),
-                Span::styled("{", Style::default().fg(Color::DarkGray)),
+                Span::styled("{", Style::default().fg(colors.punctuation)),
             ]));
 
             for (key, value) in map {
                 let mut spans = vec![
                     Span::raw("  ".repeat(indent + 1)),
-                    Span::styled(format!("\"{key}\""), Style::default().fg(Color::Cyan)),
-                    Span::raw(": "),
+                    Span::styled(format!("\"{key}\""), Style::default().fg(colors.key)),
+                    Span::styled(": ", Style::default().fg(colors.punctuation)),
                 ];
 
                 match value {
                     Value::Null => {
-                        spans.push(Span::styled("null", Style::default().fg(Color::DarkGray)));
+                        spans.push(Span::styled("null", Style::default().fg(colors.literal)));
                     }
                     Value::Bool(val) => {
                         spans.push(Span::styled(
                             val.to_string(),
-                            Style::default().fg(Color::Magenta),
+                            Style::default().fg(colors.literal),
                         ));
                     }
                     Value::Number(num) => {
                         spans.push(Span::styled(
                             num.to_string(),
-                            Style::default().fg(Color::Yellow),
+                            Style::default().fg(colors.number),
                         ));
                     }
                     Value::String(s) => {
                         spans.push(Span::styled(
                             format!("\"{s}\""),
-                            Style::default().fg(Color::Green),
+                            Style::default().fg(colors.string),
                         ));
                     }
                     Value::Array(_) | Value::Object(_) => {
                         lines.push(Line::from(spans));
-                        walk(value, lines, indent + 2);
+                        walk(value, lines, indent + 2, colors);
                         continue;
                     }
                 }
@@ -121,7 +123,7 @@ fn walk(v: &Value, lines: &mut Vec<Line>, indent: usize) {
 
             lines.push(Line::from(vec![
                 Span::raw(indent_str),
-                Span::styled("}", Style::default().fg(Color::DarkGray)),
+                Span::styled("}", Style::default().fg(colors.punctuation)),
             ]));
         }
     }
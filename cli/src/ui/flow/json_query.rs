@@ -0,0 +1,340 @@
+use std::{cmp::Ordering, iter::Peekable, str::Chars};
+
+use cow_utils::CowUtils;
+use serde_json::Value;
+
+/// A JSONPath expression, also accepting jq's `.a.b[]` style.
+///
+/// | Syntax            | Selects                                    |
+/// |-------------------|--------------------------------------------|
+/// | `$` or `.`        | the whole document                         |
+/// | `.name`           | a field, `['name']` for any other name     |
+/// | `[0]`, `[-1]`     | an array element, negative from the end    |
+/// | `.*`, `[*]`, `[]` | every element or field value               |
+/// | `..name`          | `name` at any depth                        |
+/// | `[?(@.a == 1)]`   | elements whose `a` compares, or exists     |
+#[derive(Debug, Clone)]
+pub(crate) struct JsonQuery {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Child(String),
+    Index(i64),
+    Wildcard,
+    /// The selector applied to the node and every node below it.
+    Descendant(Box<Segment>),
+    Filter(Filter),
+}
+
+#[derive(Debug, Clone)]
+struct Filter {
+    path: Vec<Segment>,
+    compare: Option<(Op, Value)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl JsonQuery {
+    pub(crate) fn parse(source: &str) -> Result<Self, String> {
+        let source = source.trim();
+        let source = source.strip_prefix('$').unwrap_or(source);
+        let mut chars = source.chars().peekable();
+        let segments = parse_segments(&mut chars, false)?;
+        if let Some(c) = chars.next() {
+            return Err(format!("unexpected '{c}'"));
+        }
+        Ok(Self { segments })
+    }
+
+    /// Matching values along with their paths, in document order.
+    pub(crate) fn select<'a>(&self, root: &'a Value) -> Vec<(String, &'a Value)> {
+        select(&self.segments, vec![("$".to_string(), root)])
+    }
+}
+
+fn parse_segments(chars: &mut Peekable<Chars>, in_filter: bool) -> Result<Vec<Segment>, String> {
+    let mut segments = vec![];
+    while let Some(&c) = chars.peek() {
+        match c {
+            '.' => {
+                chars.next();
+                if chars.peek() == Some(&'.') {
+                    chars.next();
+                    let selector = match chars.peek() {
+                        Some('[') => {
+                            chars.next();
+                            parse_bracket(chars)?
+                        }
+                        _ => parse_dotted(chars)?
+                            .ok_or_else(|| "expected a name after '..'".to_string())?,
+                    };
+                    segments.push(Segment::Descendant(Box::new(selector)));
+                } else if let Some(segment) = parse_dotted(chars)? {
+                    segments.push(segment);
+                }
+            }
+            '[' => {
+                chars.next();
+                segments.push(parse_bracket(chars)?);
+            }
+            c if in_filter && (c.is_whitespace() || "=!<>)".contains(c)) => break,
+            c => return Err(format!("unexpected '{c}'")),
+        }
+    }
+    Ok(segments)
+}
+
+/// The name after a `.`, `None` for a lone `.` as in jq's `.` or `.[0]`.
+fn parse_dotted(chars: &mut Peekable<Chars>) -> Result<Option<Segment>, String> {
+    if chars.peek() == Some(&'*') {
+        chars.next();
+        return Ok(Some(Segment::Wildcard));
+    }
+    let mut name = String::new();
+    while let Some(&c) = chars.peek() {
+        if !(c.is_alphanumeric() || c == '_' || c == '-' || c == '$') {
+            break;
+        }
+        name.push(c);
+        chars.next();
+    }
+    Ok((!name.is_empty()).then_some(Segment::Child(name)))
+}
+
+fn parse_bracket(chars: &mut Peekable<Chars>) -> Result<Segment, String> {
+    let segment = match chars.peek() {
+        Some(']') => Segment::Wildcard,
+        Some('*') => {
+            chars.next();
+            Segment::Wildcard
+        }
+        Some(&quote @ ('\'' | '"')) => {
+            chars.next();
+            Segment::Child(parse_string(chars, quote)?)
+        }
+        Some('?') => {
+            chars.next();
+            Segment::Filter(parse_filter(chars)?)
+        }
+        _ => {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                if c != '-' && !c.is_ascii_digit() {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+            let index = number
+                .parse()
+                .map_err(|_| format!("invalid index '{number}'"))?;
+            Segment::Index(index)
+        }
+    };
+    match chars.next() {
+        Some(']') => Ok(segment),
+        _ => Err("missing ']'".to_string()),
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars>, quote: char) -> Result<String, String> {
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some(c) if c == quote => return Ok(value),
+            Some('\\') => match chars.next() {
+                Some(c) => value.push(c),
+                None => return Err("unterminated string".to_string()),
+            },
+            Some(c) => value.push(c),
+            None => return Err("unterminated string".to_string()),
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn parse_filter(chars: &mut Peekable<Chars>) -> Result<Filter, String> {
+    if chars.next() != Some('(') {
+        return Err("expected '(' after '?'".to_string());
+    }
+    skip_whitespace(chars);
+    if chars.next() != Some('@') {
+        return Err("filters start with '@'".to_string());
+    }
+    let path = parse_segments(chars, true)?;
+    skip_whitespace(chars);
+
+    let mut op = String::new();
+    while let Some(&c) = chars.peek() {
+        if !"=!<>".contains(c) {
+            break;
+        }
+        op.push(c);
+        chars.next();
+    }
+    let compare = if op.is_empty() {
+        None
+    } else {
+        let op = match op.as_str() {
+            "==" => Op::Eq,
+            "!=" => Op::Ne,
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            op => return Err(format!("unknown operator '{op}'")),
+        };
+        skip_whitespace(chars);
+        Some((op, parse_literal(chars)?))
+    };
+    skip_whitespace(chars);
+    if chars.next() != Some(')') {
+        return Err("missing ')'".to_string());
+    }
+    Ok(Filter { path, compare })
+}
+
+fn parse_literal(chars: &mut Peekable<Chars>) -> Result<Value, String> {
+    if let Some(&quote @ ('\'' | '"')) = chars.peek() {
+        chars.next();
+        return parse_string(chars, quote).map(Value::String);
+    }
+    let mut literal = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || c == ')' {
+            break;
+        }
+        literal.push(c);
+        chars.next();
+    }
+    serde_json::from_str(&literal).map_err(|_| format!("invalid value '{literal}'"))
+}
+
+fn child_path(path: &str, name: &str) -> String {
+    let plain = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if plain {
+        format!("{path}.{name}")
+    } else {
+        format!("{path}['{}']", name.cow_replace('\'', "\\'"))
+    }
+}
+
+fn children<'a>(path: &str, value: &'a Value) -> Vec<(String, &'a Value)> {
+    match value {
+        Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (format!("{path}[{i}]"), v))
+            .collect(),
+        Value::Object(map) => map.iter().map(|(k, v)| (child_path(path, k), v)).collect(),
+        _ => vec![],
+    }
+}
+
+fn descendants<'a>(path: String, value: &'a Value, nodes: &mut Vec<(String, &'a Value)>) {
+    let below = children(&path, value);
+    nodes.push((path, value));
+    for (path, value) in below {
+        descendants(path, value, nodes);
+    }
+}
+
+fn select<'a>(
+    segments: &[Segment],
+    mut nodes: Vec<(String, &'a Value)>,
+) -> Vec<(String, &'a Value)> {
+    for segment in segments {
+        nodes = nodes
+            .into_iter()
+            .flat_map(|(path, value)| apply(segment, path, value))
+            .collect();
+    }
+    nodes
+}
+
+fn apply<'a>(segment: &Segment, path: String, value: &'a Value) -> Vec<(String, &'a Value)> {
+    match segment {
+        Segment::Child(name) => value
+            .get(name)
+            .map(|v| (child_path(&path, name), v))
+            .into_iter()
+            .collect(),
+        Segment::Index(index) => {
+            let Value::Array(values) = value else {
+                return vec![];
+            };
+            let index = if *index < 0 {
+                values.len().checked_sub(index.unsigned_abs() as usize)
+            } else {
+                Some(*index as usize)
+            };
+            index
+                .and_then(|i| values.get(i).map(|v| (format!("{path}[{i}]"), v)))
+                .into_iter()
+                .collect()
+        }
+        Segment::Wildcard => children(&path, value),
+        Segment::Descendant(selector) => {
+            let mut nodes = vec![];
+            descendants(path, value, &mut nodes);
+            nodes
+                .into_iter()
+                .flat_map(|(path, value)| apply(selector, path, value))
+                .collect()
+        }
+        Segment::Filter(filter) => children(&path, value)
+            .into_iter()
+            .filter(|(_, v)| filter.matches(v))
+            .collect(),
+    }
+}
+
+impl Filter {
+    fn matches(&self, value: &Value) -> bool {
+        let found = select(&self.path, vec![(String::new(), value)]);
+        let Some((op, expected)) = &self.compare else {
+            return !found.is_empty();
+        };
+        found.iter().any(|(_, v)| {
+            let ordering = compare(v, expected);
+            match op {
+                Op::Eq => ordering == Some(Ordering::Equal),
+                Op::Ne => ordering != Some(Ordering::Equal),
+                Op::Lt => ordering == Some(Ordering::Less),
+                Op::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                Op::Gt => ordering == Some(Ordering::Greater),
+                Op::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            }
+        })
+    }
+}
+
+/// Numbers compare by value and strings lexically, other values are only
+/// equal or not.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (a, b) if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
+}
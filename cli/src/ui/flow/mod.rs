@@ -1,17 +1,65 @@
 mod csv;
-mod flow_body;
+pub(crate) mod flow_body;
 mod flow_certs;
 pub(crate) mod flow_details;
+pub(crate) mod flow_diff;
 mod flow_headers;
 pub(crate) mod flow_list;
 mod flow_request;
 mod flow_response;
 mod flow_timing;
+pub(crate) mod grpc;
+mod hex;
 mod html;
 mod json;
+mod json_query;
 mod markdown;
+mod multipart;
+pub(crate) mod pending_list;
+mod raw;
+pub(crate) mod request_editor;
+mod script_log;
+mod sse;
+pub(crate) mod syntax;
 mod tab;
 mod toml;
+mod tunnel;
 mod ws_details;
 mod xml;
 mod yaml;
+
+use std::io::Cursor;
+
+use bytes::Bytes;
+use ratatui::text::Line;
+use roxy_shared::content::ContentType;
+
+use crate::config::SyntaxColors;
+
+/// Renders a text body like the body tab does, for output outside the TUI.
+/// `None` when the content is not text, e.g. images or gRPC messages.
+pub(crate) fn render_text_body(
+    content_type: &ContentType,
+    body: &Bytes,
+    colors: &SyntaxColors,
+) -> Option<Vec<Line<'static>>> {
+    match content_type {
+        ContentType::Json => Some(json::highlight_json(body, colors)),
+        ContentType::Svg | ContentType::Xml => Some(xml::pretty_print_xml(body, colors)),
+        ContentType::Html => html::highlight_html_dom(&mut Cursor::new(body), colors).ok(),
+        ContentType::Toml => Some(toml::highlight_toml(body, colors)),
+        ContentType::Yaml => Some(yaml::pretty_print_yaml(body, colors)),
+        ContentType::Js => Some(syntax::highlight_source(syntax::Lang::Js, body, colors)),
+        ContentType::Csv => csv::render_csv(body).ok(),
+        ContentType::Tsv => csv::render_tsv(body).ok(),
+        ContentType::Md => Some(markdown::render_markdown(body)),
+        ContentType::EventStream => Some(sse::render_event_stream(body, colors)),
+        ContentType::Text => Some(
+            String::from_utf8_lossy(body)
+                .lines()
+                .map(|line| Line::from(line.to_string()))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
@@ -0,0 +1,69 @@
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+use roxy_shared::content::{MultipartPart, parse_content_type};
+
+use crate::config::SyntaxColors;
+
+use super::render_text_body;
+
+/// Renders a `multipart/form-data` body one part at a time, each part's body
+/// as its own content type. Binary parts only show their size.
+pub fn render_multipart(parts: &[MultipartPart], colors: &SyntaxColors) -> Vec<Line<'static>> {
+    let mut lines = vec![];
+    for (index, part) in parts.iter().enumerate() {
+        let mut header = vec![
+            Span::styled(format!("#{index} "), Style::default().fg(Color::DarkGray)),
+            Span::styled(
+                part.name.clone().unwrap_or("(unnamed)".to_string()),
+                Style::default()
+                    .fg(Color::Blue)
+                    .add_modifier(Modifier::BOLD),
+            ),
+        ];
+        if let Some(filename) = &part.filename {
+            header.push(Span::styled(
+                format!(" file: {filename}"),
+                Style::default().fg(Color::Yellow),
+            ));
+        }
+        if let Some(content_type) = &part.content_type {
+            header.push(Span::styled(
+                format!(" {content_type}"),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        header.push(Span::styled(
+            format!(" {} bytes", part.body.len()),
+            Style::default().fg(Color::DarkGray),
+        ));
+        lines.push(Line::from(header));
+
+        let rendered = part
+            .content_type
+            .as_deref()
+            .and_then(parse_content_type)
+            .and_then(|ct| render_text_body(&ct, &part.body, colors))
+            .or_else(|| {
+                std::str::from_utf8(&part.body).ok().map(|text| {
+                    text.lines()
+                        .map(|line| Line::from(line.to_string()))
+                        .collect()
+                })
+            });
+        match rendered {
+            Some(body) => {
+                for line in body {
+                    lines.push(Line::from([vec![Span::raw("  ")], line.spans].concat()));
+                }
+            }
+            None => lines.push(Line::styled(
+                "  binary data",
+                Style::default().fg(Color::DarkGray),
+            )),
+        }
+        lines.push(Line::default());
+    }
+    lines
+}
@@ -0,0 +1,130 @@
+use color_eyre::Result;
+use rat_focus::{FocusFlag, HasFocus};
+use ratatui::{
+    Frame,
+    layout::{Constraint, Rect},
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Cell, Clear, Row, TableState},
+};
+use roxy_proxy::{
+    breakpoint::Breakpoints,
+    flow::{InterceptedRequest, id::FlowId},
+};
+
+use crate::{
+    event::Action,
+    notify_info,
+    ui::framework::{
+        component::{ActionResult, Component},
+        theme::themed_table,
+        util::centered_rect,
+    },
+};
+
+/// Popup listing flows paused at a breakpoint.
+///
+/// Select resumes the flow unchanged, drop answers the client with a bad
+/// gateway response, editing is handled by the request editor.
+pub struct PendingList {
+    focus: FocusFlag,
+    breakpoints: Breakpoints,
+    pending: Vec<(FlowId, InterceptedRequest)>,
+    state: TableState,
+}
+
+impl HasFocus for PendingList {
+    fn build(&self, builder: &mut rat_focus::FocusBuilder) {
+        builder.leaf_widget(self);
+    }
+
+    fn area(&self) -> Rect {
+        Rect::default()
+    }
+
+    fn focus(&self) -> rat_focus::FocusFlag {
+        self.focus.clone()
+    }
+}
+
+impl PendingList {
+    pub fn new(breakpoints: Breakpoints) -> Self {
+        Self {
+            focus: FocusFlag::new().with_name("PendingList"),
+            breakpoints,
+            pending: vec![],
+            state: TableState::default().with_selected(0),
+        }
+    }
+
+    pub fn selected(&self) -> Option<(FlowId, InterceptedRequest)> {
+        self.state
+            .selected()
+            .and_then(|i| self.pending.get(i))
+            .cloned()
+    }
+
+    fn refresh(&mut self) {
+        self.pending = self.breakpoints.pending();
+        let selected = self.state.selected().unwrap_or(0);
+        self.state
+            .select(Some(selected.min(self.pending.len().saturating_sub(1))));
+    }
+}
+
+impl Component for PendingList {
+    fn update(&mut self, action: Action) -> ActionResult {
+        match action {
+            Action::Down => {
+                self.state.select_next();
+                ActionResult::Consumed
+            }
+            Action::Up => {
+                self.state.select_previous();
+                ActionResult::Consumed
+            }
+            Action::Select => {
+                if let Some((id, _)) = self.selected()
+                    && self.breakpoints.resume(id, None)
+                {
+                    notify_info!("Resumed flow {id}");
+                }
+                ActionResult::Consumed
+            }
+            Action::DropFlow => {
+                if let Some((id, _)) = self.selected()
+                    && self.breakpoints.drop_flow(id)
+                {
+                    notify_info!("Dropped flow {id}");
+                }
+                ActionResult::Consumed
+            }
+            _ => ActionResult::Ignored,
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame, area: Rect) -> Result<()> {
+        self.refresh();
+
+        let popup_area = centered_rect(80, 60, area);
+        f.render_widget(Clear, popup_area);
+
+        let rows = self.pending.iter().map(|(id, request)| {
+            Row::new(vec![Cell::new(Line::from(vec![
+                Span::styled(format!("{id:>4} "), Style::default()),
+                Span::styled(
+                    format!("{} ", request.method),
+                    Style::default().fg(Color::Green),
+                ),
+                Span::styled(request.line_pretty(), Style::default().fg(Color::Cyan)),
+            ]))])
+        });
+        let title = format!("Paused flows ({})", self.pending.len());
+        f.render_stateful_widget(
+            themed_table(rows, [Constraint::Fill(1)], Some(&title), true),
+            popup_area,
+            &mut self.state,
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use rat_focus::HasFocus;
+use ratatui::{
+    Frame,
+    layout::Rect,
+    text::Line,
+    widgets::{Paragraph, Wrap},
+};
+use roxy_proxy::flow::{WsDirection, id::FlowId, wire::FlowWire};
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+    event::Action,
+    ui::framework::{
+        component::{ActionResult, Component},
+        theme::themed_block,
+    },
+};
+
+use super::hex::{hex_pages, render_hex};
+
+#[derive(Debug, Clone, Default)]
+struct UiState {
+    flow_id: Option<FlowId>,
+    wire: FlowWire,
+}
+
+/// The request and response of a flow as read off the wire, one at a time as
+/// a hex dump.
+pub struct FlowRaw {
+    state: watch::Receiver<UiState>,
+    focus: rat_focus::FocusFlag,
+    direction: WsDirection,
+    page: usize,
+    scroll: u16,
+}
+
+impl FlowRaw {
+    pub fn new(mut rx: mpsc::Receiver<(FlowId, FlowWire)>) -> Self {
+        let (ui_tx, ui_rx) = watch::channel(UiState::default());
+
+        tokio::spawn(async move {
+            while let Some((flow_id, wire)) = rx.recv().await {
+                ui_tx
+                    .send(UiState {
+                        flow_id: Some(flow_id),
+                        wire,
+                    })
+                    .unwrap_or_else(|e| {
+                        tracing::debug!("Failed to send UI state update: {}", e);
+                    });
+            }
+        });
+
+        Self {
+            state: ui_rx,
+            focus: rat_focus::FocusFlag::new().with_name("FlowRaw"),
+            direction: WsDirection::Client,
+            page: 0,
+            scroll: 0,
+        }
+    }
+}
+
+impl HasFocus for FlowRaw {
+    fn build(&self, builder: &mut rat_focus::FocusBuilder) {
+        builder.leaf_widget(self);
+    }
+
+    fn area(&self) -> Rect {
+        Rect::default()
+    }
+
+    fn focus(&self) -> rat_focus::FocusFlag {
+        self.focus.clone()
+    }
+}
+
+impl Component for FlowRaw {
+    fn update(&mut self, action: Action) -> ActionResult {
+        if !self.focus.get() {
+            return ActionResult::Ignored;
+        }
+        match action {
+            Action::Up => {
+                self.scroll = self.scroll.saturating_sub(1);
+                ActionResult::Consumed
+            }
+            Action::Down => {
+                self.scroll = self.scroll.saturating_add(1);
+                ActionResult::Consumed
+            }
+            Action::Top => {
+                self.scroll = 0;
+                ActionResult::Consumed
+            }
+            Action::Select => {
+                self.direction = match self.direction {
+                    WsDirection::Client => WsDirection::Server,
+                    WsDirection::Server => WsDirection::Client,
+                };
+                self.page = 0;
+                self.scroll = 0;
+                ActionResult::Consumed
+            }
+            Action::Left | Action::Right => {
+                let Some(bytes) = self.state.borrow().wire.bytes(&self.direction) else {
+                    return ActionResult::Ignored;
+                };
+                let last = hex_pages(bytes.data.len()) - 1;
+                self.page = match action {
+                    Action::Left => self.page.saturating_sub(1),
+                    _ => (self.page + 1).min(last),
+                };
+                self.scroll = 0;
+                ActionResult::Consumed
+            }
+            Action::ExportFlows => {
+                let state = self.state.borrow();
+                match state.flow_id {
+                    Some(id) if !state.wire.is_empty() => ActionResult::Action(Action::SaveRaw(id)),
+                    _ => ActionResult::Consumed,
+                }
+            }
+            _ => ActionResult::Ignored,
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame, area: Rect) -> color_eyre::eyre::Result<()> {
+        let wire = self.state.borrow().wire.clone();
+        if wire.is_empty() {
+            let block = themed_block(Some("Raw"), self.focus.get());
+            f.render_widget(
+                Paragraph::new("No raw bytes, turn raw capture on for new HTTP/1 flows")
+                    .block(block),
+                area,
+            );
+            return Ok(());
+        }
+
+        let title = match self.direction {
+            WsDirection::Client => "Request as read, enter for the response",
+            WsDirection::Server => "Response as read, enter for the request",
+        };
+        let mut lines = vec![];
+        match wire.bytes(&self.direction) {
+            Some(bytes) => {
+                if bytes.truncated() {
+                    lines.push(Line::raw(format!(
+                        "Only the first bytes of {} were kept",
+                        bytes.total
+                    )));
+                }
+                lines.extend(render_hex(&bytes.data, self.page));
+            }
+            None => lines.push(Line::raw("Not captured, it was not read over HTTP/1")),
+        }
+
+        f.render_widget(
+            Paragraph::new(lines)
+                .wrap(Wrap { trim: false })
+                .scroll((self.scroll, 0))
+                .block(themed_block(Some(title), self.focus.get())),
+            area,
+        );
+        Ok(())
+    }
+}
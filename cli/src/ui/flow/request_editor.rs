@@ -0,0 +1,375 @@
+use std::str::FromStr;
+
+use bytes::Bytes;
+use color_eyre::Result;
+use crossterm::event::{KeyCode, KeyEvent};
+use hyper::{
+    HeaderMap, Method,
+    header::{HeaderName, HeaderValue},
+};
+use rat_focus::{FocusFlag, HasFocus};
+use ratatui::{
+    Frame,
+    layout::{Constraint, Flex, Layout, Rect},
+    widgets::{Clear, Paragraph, Wrap},
+};
+use roxy_proxy::{
+    flow::{FlowConnection, FlowStore, InterceptedRequest, id::FlowId},
+    proxy::ProxyContext,
+};
+use roxy_shared::{content::get_content_encoding, uri::RUri};
+use tokio::sync::watch;
+
+use crate::{
+    event::Action,
+    notify_error, notify_info,
+    ui::framework::{
+        component::{ActionResult, Component, KeyEventResult},
+        theme::{themed_block, themed_button},
+        util::centered_rect,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Method,
+    Url,
+    Headers,
+    Body,
+    Send,
+}
+
+impl Field {
+    fn all() -> &'static [Field] {
+        &[
+            Self::Method,
+            Self::Url,
+            Self::Headers,
+            Self::Body,
+            Self::Send,
+        ]
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Method => "Method",
+            Self::Url => "Url",
+            Self::Headers => "Headers",
+            Self::Body => "Body",
+            Self::Send => "Send",
+        }
+    }
+
+    fn index(&self) -> usize {
+        Self::all().iter().position(|&f| f == *self).unwrap_or(0)
+    }
+
+    fn prev(&self) -> Field {
+        let index = self.index();
+        if index == 0 {
+            *self
+        } else {
+            Self::all()[index - 1]
+        }
+    }
+
+    fn next(&self) -> Field {
+        Self::all().get(self.index() + 1).copied().unwrap_or(*self)
+    }
+
+    fn multi_line(&self) -> bool {
+        matches!(self, Self::Headers | Self::Body)
+    }
+}
+
+/// Where the edited request goes.
+#[derive(Debug, Clone, Copy)]
+enum EditTarget {
+    /// Sent as a new flow from the original client connection.
+    Resend(FlowConnection),
+    /// Forwarded in place of a flow paused at a breakpoint.
+    Resume(FlowId),
+}
+
+type Loaded = Option<(EditTarget, InterceptedRequest)>;
+
+/// Popup for editing the request of a captured flow and sending it as a new
+/// flow, or of a paused flow before resuming it.
+pub struct RequestEditor {
+    focus: FocusFlag,
+    flow_store: FlowStore,
+    proxy_cxt: ProxyContext,
+    loaded_tx: watch::Sender<Loaded>,
+    loaded_rx: watch::Receiver<Loaded>,
+    original: Loaded,
+    method: String,
+    url: String,
+    headers: String,
+    body: String,
+    selected: Field,
+    is_editing: bool,
+}
+
+impl HasFocus for RequestEditor {
+    fn build(&self, builder: &mut rat_focus::FocusBuilder) {
+        builder.leaf_widget(self);
+    }
+
+    fn area(&self) -> Rect {
+        Rect::default()
+    }
+
+    fn focus(&self) -> rat_focus::FocusFlag {
+        self.focus.clone()
+    }
+}
+
+impl RequestEditor {
+    pub fn new(flow_store: FlowStore, proxy_cxt: ProxyContext) -> Self {
+        let (loaded_tx, loaded_rx) = watch::channel(None);
+        Self {
+            focus: FocusFlag::new().with_name("RequestEditor"),
+            flow_store,
+            proxy_cxt,
+            loaded_tx,
+            loaded_rx,
+            original: None,
+            method: String::new(),
+            url: String::new(),
+            headers: String::new(),
+            body: String::new(),
+            selected: Field::Method,
+            is_editing: false,
+        }
+    }
+
+    pub fn set_flow(&mut self, flow_id: FlowId) {
+        self.original = None;
+        self.selected = Field::Method;
+        self.is_editing = false;
+        let flow_store = self.flow_store.clone();
+        let loaded_tx = self.loaded_tx.clone();
+        tokio::spawn(async move {
+            if let Some(flow) = flow_store.get_flow_by_id(flow_id).await {
+                let (request, client_connection) = {
+                    let flow = flow.read().await;
+                    (flow.request.clone(), flow.client_connection)
+                };
+                if let Some(mut request) = request {
+                    if let Err(err) = request.unspill().await {
+                        notify_error!("Failed to read request body {err}");
+                        return;
+                    }
+                    let target = EditTarget::Resend(client_connection);
+                    loaded_tx.send_replace(Some((target, request)));
+                }
+            }
+        });
+    }
+
+    /// Edits a flow paused at a breakpoint, sending resumes it with the edits.
+    pub fn set_pending(&mut self, flow_id: FlowId, request: InterceptedRequest) {
+        self.selected = Field::Method;
+        self.is_editing = false;
+        self.load(EditTarget::Resume(flow_id), request);
+    }
+
+    fn load(&mut self, target: EditTarget, request: InterceptedRequest) {
+        self.method = request.method.to_string();
+        self.url = request.uri.to_string();
+        self.headers = request
+            .headers
+            .iter()
+            .map(|(k, v)| format!("{}: {}", k, String::from_utf8_lossy(v.as_bytes())))
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.body = String::from_utf8_lossy(&request.body).to_string();
+        self.original = Some((target, request));
+    }
+
+    fn buffer(&mut self) -> Option<&mut String> {
+        match self.selected {
+            Field::Method => Some(&mut self.method),
+            Field::Url => Some(&mut self.url),
+            Field::Headers => Some(&mut self.headers),
+            Field::Body => Some(&mut self.body),
+            Field::Send => None,
+        }
+    }
+
+    fn build_request(&self) -> Result<(EditTarget, InterceptedRequest), String> {
+        let Some((target, original)) = self.original.clone() else {
+            return Err("No request loaded".into());
+        };
+        let method =
+            Method::from_str(self.method.trim()).map_err(|e| format!("Invalid method {e}"))?;
+        let uri = RUri::from_str(self.url.trim()).map_err(|e| format!("Invalid url {e}"))?;
+
+        let mut headers = HeaderMap::new();
+        for line in self.headers.lines().filter(|l| !l.trim().is_empty()) {
+            let Some((name, value)) = line.split_once(':') else {
+                return Err(format!("Invalid header '{line}'"));
+            };
+            let name = HeaderName::from_str(name.trim())
+                .map_err(|e| format!("Invalid header name '{name}' {e}"))?;
+            let value = HeaderValue::from_str(value.trim())
+                .map_err(|e| format!("Invalid header value '{value}' {e}"))?;
+            headers.append(name, value);
+        }
+
+        let request = InterceptedRequest {
+            method,
+            uri,
+            encoding: get_content_encoding(&headers),
+            headers,
+            body: Bytes::from(self.body.clone()),
+            body_file: None,
+            tls: None,
+            tags: vec![],
+            ..original
+        };
+        Ok((target, request))
+    }
+
+    fn send(&self) -> ActionResult {
+        let (target, request) = match self.build_request() {
+            Ok(request) => request,
+            Err(err) => {
+                notify_error!("{err}");
+                return ActionResult::Consumed;
+            }
+        };
+        let connection = match target {
+            EditTarget::Resend(connection) => connection,
+            EditTarget::Resume(id) => {
+                if !self.proxy_cxt.breakpoints.resume(id, Some(request)) {
+                    notify_error!("Flow {id} is no longer paused");
+                }
+                return ActionResult::Action(Action::Back);
+            }
+        };
+        let client = self.proxy_cxt.client_builder(request.uri.host());
+        let flow_store = self.flow_store.clone();
+        tokio::spawn(async move {
+            match flow_store.replay_request(connection, request, client).await {
+                Ok(id) => notify_info!("Sent edited request as flow {id}"),
+                Err(err) => notify_error!("Failed to send edited request {err}"),
+            }
+        });
+        ActionResult::Action(Action::Back)
+    }
+}
+
+impl Component for RequestEditor {
+    fn update(&mut self, action: Action) -> ActionResult {
+        if self.is_editing {
+            return ActionResult::Ignored;
+        }
+        match action {
+            Action::Up => {
+                self.selected = self.selected.prev();
+                ActionResult::Consumed
+            }
+            Action::Down => {
+                self.selected = self.selected.next();
+                ActionResult::Consumed
+            }
+            Action::Select => {
+                if self.selected == Field::Send {
+                    self.send()
+                } else {
+                    self.is_editing = true;
+                    ActionResult::Consumed
+                }
+            }
+            _ => ActionResult::Ignored,
+        }
+    }
+
+    fn handle_key_event(&mut self, key: &KeyEvent) -> KeyEventResult {
+        if !self.is_editing {
+            return KeyEventResult::Ignored;
+        }
+        let multi_line = self.selected.multi_line();
+        match key.code {
+            KeyCode::Esc => self.is_editing = false,
+            KeyCode::Enter if !multi_line => self.is_editing = false,
+            KeyCode::Enter => {
+                if let Some(buffer) = self.buffer() {
+                    buffer.push('\n');
+                }
+            }
+            KeyCode::Char(c) => {
+                if let Some(buffer) = self.buffer() {
+                    buffer.push(c);
+                }
+            }
+            KeyCode::Backspace => {
+                if let Some(buffer) = self.buffer() {
+                    buffer.pop();
+                }
+            }
+            _ => {}
+        }
+        KeyEventResult::Consumed
+    }
+
+    fn render(&mut self, f: &mut Frame, area: Rect) -> Result<()> {
+        if self.loaded_rx.has_changed().unwrap_or(false) {
+            let loaded = self.loaded_rx.borrow_and_update().clone();
+            if let Some((target, request)) = loaded {
+                self.load(target, request);
+            }
+        }
+
+        let popup_area = centered_rect(80, 80, area);
+        f.render_widget(Clear, popup_area);
+
+        let title = match self.original {
+            Some((EditTarget::Resume(id), _)) => format!("Edit paused request {id}"),
+            _ => "Edit request".to_string(),
+        };
+        let outer = themed_block(Some(&title), true);
+        let inner = outer.inner(popup_area);
+        f.render_widget(outer, popup_area);
+
+        let layout = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Percentage(40),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .split(inner);
+
+        for (field, value, chunk) in [
+            (Field::Method, &self.method, layout[0]),
+            (Field::Url, &self.url, layout[1]),
+            (Field::Headers, &self.headers, layout[2]),
+            (Field::Body, &self.body, layout[3]),
+        ] {
+            let selected = self.selected == field;
+            let title = if selected && self.is_editing {
+                format!("{} (editing, Esc to finish)", field.title())
+            } else {
+                field.title().to_string()
+            };
+            f.render_widget(
+                Paragraph::new(value.as_str())
+                    .wrap(Wrap { trim: false })
+                    .block(themed_block(Some(&title), selected)),
+                chunk,
+            );
+        }
+
+        let [send] = Layout::horizontal([Constraint::Length(10)])
+            .flex(Flex::Center)
+            .areas(layout[4]);
+        f.render_widget(
+            themed_button(Field::Send.title(), self.selected == Field::Send),
+            send,
+        );
+
+        Ok(())
+    }
+}
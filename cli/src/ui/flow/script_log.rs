@@ -0,0 +1,124 @@
+use rat_focus::HasFocus;
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::Style,
+    text::{Line, Span},
+    widgets::{Paragraph, Wrap},
+};
+use roxy_proxy::{flow::ScriptLog, interceptor::FlowNotifyLevel};
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+    event::Action,
+    ui::framework::{
+        component::{ActionResult, Component},
+        theme::{themed_block, with_theme},
+    },
+};
+
+/// What the scripts notified while intercepting the selected flow.
+pub struct FlowScriptLog {
+    state: watch::Receiver<Vec<ScriptLog>>,
+    focus: rat_focus::FocusFlag,
+    v_scroll_offset: u16,
+}
+
+impl FlowScriptLog {
+    pub fn new(mut rx: mpsc::Receiver<Vec<ScriptLog>>) -> Self {
+        let (ui_tx, ui_rx) = watch::channel(vec![]);
+
+        tokio::spawn(async move {
+            while let Some(log) = rx.recv().await {
+                ui_tx.send(log).unwrap_or_else(|e| {
+                    tracing::debug!("Failed to send UI state update: {}", e);
+                });
+            }
+        });
+
+        Self {
+            state: ui_rx,
+            focus: rat_focus::FocusFlag::new().with_name("FlowScriptLog"),
+            v_scroll_offset: 0,
+        }
+    }
+}
+
+impl HasFocus for FlowScriptLog {
+    fn build(&self, builder: &mut rat_focus::FocusBuilder) {
+        builder.leaf_widget(self);
+    }
+
+    fn area(&self) -> Rect {
+        Rect::default()
+    }
+
+    fn focus(&self) -> rat_focus::FocusFlag {
+        self.focus.clone()
+    }
+}
+
+impl Component for FlowScriptLog {
+    fn update(&mut self, action: Action) -> ActionResult {
+        match action {
+            Action::Up => {
+                self.v_scroll_offset = self.v_scroll_offset.saturating_sub(1);
+                ActionResult::Consumed
+            }
+            Action::Down => {
+                self.v_scroll_offset = self.v_scroll_offset.saturating_add(1);
+                ActionResult::Consumed
+            }
+            Action::Top => {
+                self.v_scroll_offset = 0;
+                ActionResult::Consumed
+            }
+            _ => ActionResult::Ignored,
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame, area: Rect) -> color_eyre::eyre::Result<()> {
+        let colors = with_theme(|t| t.colors.clone());
+        let block = themed_block(Some("Scripts"), self.focus.get());
+        let log = self.state.borrow();
+        if log.is_empty() {
+            f.render_widget(
+                Paragraph::new("No script notified while intercepting this flow").block(block),
+                area,
+            );
+            return Ok(());
+        }
+        let lines: Vec<Line> = log
+            .iter()
+            .map(|entry| {
+                let (label, color) = match entry.level {
+                    FlowNotifyLevel::Error => ("ERROR", colors.error),
+                    FlowNotifyLevel::Warn => ("WARN ", colors.warn),
+                    FlowNotifyLevel::Info => ("INFO ", colors.info),
+                    FlowNotifyLevel::Debug => ("DEBUG", colors.debug),
+                    FlowNotifyLevel::Trace => ("TRACE", colors.trace),
+                };
+                let time = entry.timestamp.time();
+                Line::from(vec![
+                    Span::raw(format!(
+                        "{:02}:{:02}:{:02}.{:03} ",
+                        time.hour(),
+                        time.minute(),
+                        time.second(),
+                        time.millisecond()
+                    )),
+                    Span::styled(label, Style::default().fg(color)),
+                    Span::raw(format!(" {}", entry.msg)),
+                ])
+            })
+            .collect();
+        f.render_widget(
+            Paragraph::new(lines)
+                .wrap(Wrap { trim: false })
+                .scroll((self.v_scroll_offset, 0))
+                .block(block),
+            area,
+        );
+        Ok(())
+    }
+}
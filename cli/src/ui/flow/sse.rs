@@ -0,0 +1,115 @@
+use bytes::Bytes;
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+use serde_json::Value;
+
+use crate::config::SyntaxColors;
+
+use super::json::highlight_json;
+
+#[derive(Default)]
+struct Event {
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<String>,
+    data: Vec<String>,
+}
+
+impl Event {
+    fn is_empty(&self) -> bool {
+        self.event.is_none() && self.id.is_none() && self.retry.is_none() && self.data.is_empty()
+    }
+}
+
+/// Renders a `text/event-stream` body one event at a time, an event still
+/// being received is shown last.
+pub fn render_event_stream(raw: &[u8], colors: &SyntaxColors) -> Vec<Line<'static>> {
+    let text = String::from_utf8_lossy(raw);
+    let mut lines = vec![];
+    let mut event = Event::default();
+    let mut count = 0;
+
+    for line in text.lines() {
+        if line.is_empty() {
+            if !event.is_empty() {
+                render_event(&mut lines, count, &event, false, colors);
+                count += 1;
+            }
+            event = Event::default();
+            continue;
+        }
+        if line.starts_with(':') {
+            continue;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => event.event = Some(value.to_string()),
+            "id" => event.id = Some(value.to_string()),
+            "retry" => event.retry = Some(value.to_string()),
+            "data" => event.data.push(value.to_string()),
+            _ => {}
+        }
+    }
+
+    if !event.is_empty() {
+        render_event(&mut lines, count, &event, true, colors);
+    }
+    lines
+}
+
+fn render_event(
+    lines: &mut Vec<Line<'static>>,
+    index: usize,
+    event: &Event,
+    partial: bool,
+    colors: &SyntaxColors,
+) {
+    let mut header = vec![
+        Span::styled(format!("#{index} "), Style::default().fg(Color::DarkGray)),
+        Span::styled(
+            event.event.clone().unwrap_or("message".to_string()),
+            Style::default()
+                .fg(Color::Blue)
+                .add_modifier(Modifier::BOLD),
+        ),
+    ];
+    if let Some(id) = &event.id {
+        header.push(Span::styled(
+            format!(" id: {id}"),
+            Style::default().fg(Color::DarkGray),
+        ));
+    }
+    if let Some(retry) = &event.retry {
+        header.push(Span::styled(
+            format!(" retry: {retry}"),
+            Style::default().fg(Color::DarkGray),
+        ));
+    }
+    if partial {
+        header.push(Span::styled(
+            " (receiving)",
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    lines.push(Line::from(header));
+
+    let data = event.data.join("\n");
+    if serde_json::from_str::<Value>(&data).is_ok() {
+        for line in highlight_json(&Bytes::from(data), colors) {
+            lines.push(Line::from([vec![Span::raw("  ")], line.spans].concat()));
+        }
+    } else {
+        for line in &event.data {
+            lines.push(Line::from(vec![
+                Span::raw("  "),
+                Span::styled(line.clone(), Style::default().fg(colors.string)),
+            ]));
+        }
+    }
+    lines.push(Line::default());
+}
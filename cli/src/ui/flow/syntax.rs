@@ -0,0 +1,318 @@
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+};
+use roxy_shared::content::ContentType;
+
+use crate::config::SyntaxColors;
+
+/// Bodies bigger than this are not parsed and pretty printed, their lines are
+/// highlighted as they are scrolled into view instead.
+pub const LAZY_HIGHLIGHT: usize = 256 * 1024;
+
+/// Lines are split into chunks of at most this many bytes, so a minified body
+/// on one line is only highlighted a screen at a time too.
+const MAX_CHUNK: usize = 4096;
+
+const JS_KEYWORDS: &[&str] = &[
+    "async",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "export",
+    "extends",
+    "finally",
+    "for",
+    "from",
+    "function",
+    "if",
+    "import",
+    "in",
+    "instanceof",
+    "let",
+    "new",
+    "of",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+/// The languages highlighted one line at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Js,
+    Json,
+    Markup,
+    Toml,
+    Yaml,
+}
+
+impl Lang {
+    pub fn for_content_type(content_type: &ContentType) -> Option<Self> {
+        match content_type {
+            ContentType::Js => Some(Lang::Js),
+            ContentType::Json => Some(Lang::Json),
+            ContentType::Html | ContentType::Svg | ContentType::Xml => Some(Lang::Markup),
+            ContentType::Toml => Some(Lang::Toml),
+            ContentType::Yaml => Some(Lang::Yaml),
+            _ => None,
+        }
+    }
+}
+
+/// Splits `body` into the lines [`highlight_line`] is run on.
+pub fn source_lines(body: &[u8]) -> Vec<String> {
+    let text = String::from_utf8_lossy(body);
+    let mut lines = vec![];
+    for mut line in text.lines() {
+        while line.len() > MAX_CHUNK {
+            let mut end = MAX_CHUNK;
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            lines.push(line[..end].to_string());
+            line = &line[end..];
+        }
+        lines.push(line.to_string());
+    }
+    lines
+}
+
+/// Highlights all of `body`, for output that is not scrolled through.
+pub fn highlight_source(lang: Lang, body: &[u8], colors: &SyntaxColors) -> Vec<Line<'static>> {
+    source_lines(body)
+        .iter()
+        .map(|line| highlight_line(lang, line, colors))
+        .collect()
+}
+
+/// Highlights a single line without knowing the ones before it, so tokens
+/// spanning lines such as block comments are only coloured on their first.
+pub fn highlight_line(lang: Lang, line: &str, colors: &SyntaxColors) -> Line<'static> {
+    let mut spans = Spans::default();
+    match lang {
+        Lang::Markup => markup_line(line, colors, &mut spans),
+        _ => code_line(lang, line, colors, &mut spans),
+    }
+    Line::from(spans.0)
+}
+
+#[derive(Default)]
+struct Spans(Vec<Span<'static>>);
+
+impl Spans {
+    fn push(&mut self, text: &str, color: Option<Color>) {
+        if text.is_empty() {
+            return;
+        }
+        let style = color.map_or(Style::default(), |color| Style::default().fg(color));
+        match self.0.last_mut() {
+            Some(last) if last.style == style => last.content.to_mut().push_str(text),
+            _ => self.0.push(Span::styled(text.to_string(), style)),
+        }
+    }
+}
+
+fn code_line(lang: Lang, line: &str, colors: &SyntaxColors, spans: &mut Spans) {
+    if lang == Lang::Toml && line.trim_start().starts_with('[') {
+        spans.push(line, Some(colors.tag));
+        return;
+    }
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &line[i..];
+        let c = bytes[i];
+        let start = i;
+        if c.is_ascii_whitespace() {
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            spans.push(&line[start..i], None);
+        } else if starts_comment(lang, line, i) {
+            let end = match rest.strip_prefix("/*").and_then(|r| r.find("*/")) {
+                Some(end) => i + end + 4,
+                None => bytes.len(),
+            };
+            spans.push(&line[start..end], Some(colors.comment));
+            i = end;
+        } else if c == b'"' || (c == b'\'' && lang != Lang::Json) || (c == b'`' && lang == Lang::Js)
+        {
+            i = string_end(bytes, i);
+            let color = if is_key(lang, &line[i..]) {
+                colors.key
+            } else {
+                colors.string
+            };
+            spans.push(&line[start..i], Some(color));
+        } else if c.is_ascii_digit()
+            || (c == b'-' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+        {
+            i += 1;
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric() || matches!(bytes[i], b'.' | b'_'))
+            {
+                i += 1;
+            }
+            spans.push(&line[start..i], Some(colors.number));
+        } else if c.is_ascii_alphabetic() || matches!(c, b'_' | b'$') {
+            while i < bytes.len() && is_word_byte(lang, bytes[i]) {
+                i += 1;
+            }
+            let word = &line[start..i];
+            spans.push(word, word_color(lang, word, &line[i..], colors));
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+            let color = if lang == Lang::Yaml && !c.is_ascii_punctuation() {
+                colors.string
+            } else {
+                colors.punctuation
+            };
+            spans.push(&line[start..i], Some(color));
+        }
+    }
+}
+
+fn starts_comment(lang: Lang, line: &str, i: usize) -> bool {
+    let rest = &line[i..];
+    match lang {
+        Lang::Js => rest.starts_with("//") || rest.starts_with("/*"),
+        Lang::Toml | Lang::Yaml => {
+            rest.starts_with('#') && (i == 0 || line.as_bytes()[i - 1].is_ascii_whitespace())
+        }
+        Lang::Json | Lang::Markup => false,
+    }
+}
+
+/// The end of the string starting at `start`, or of the line when it is not
+/// closed on it.
+fn string_end(bytes: &[u8], start: usize) -> usize {
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b if b == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+fn is_word_byte(lang: Lang, b: u8) -> bool {
+    b.is_ascii_alphanumeric()
+        || matches!(b, b'_' | b'$')
+        || (matches!(lang, Lang::Toml | Lang::Yaml) && matches!(b, b'-' | b'.'))
+}
+
+/// Whether the token before `rest` is a key, i.e. followed by `:` or, in
+/// TOML, `=`.
+fn is_key(lang: Lang, rest: &str) -> bool {
+    let next = rest.trim_start().chars().next();
+    match lang {
+        Lang::Json | Lang::Js | Lang::Yaml => next == Some(':'),
+        Lang::Toml => next == Some('='),
+        Lang::Markup => false,
+    }
+}
+
+fn word_color(lang: Lang, word: &str, rest: &str, colors: &SyntaxColors) -> Option<Color> {
+    if lang != Lang::Js && is_key(lang, rest) {
+        return Some(colors.key);
+    }
+    match (lang, word) {
+        (_, "true" | "false" | "null") => Some(colors.literal),
+        (Lang::Js, "undefined" | "NaN" | "Infinity") => Some(colors.literal),
+        (Lang::Js, word) if JS_KEYWORDS.contains(&word) => Some(colors.keyword),
+        (Lang::Js, _) => None,
+        (Lang::Yaml, "yes" | "no" | "on" | "off") => Some(colors.literal),
+        (Lang::Toml, "inf" | "nan") => Some(colors.number),
+        // Bare YAML scalars are strings
+        (Lang::Yaml, _) => Some(colors.string),
+        _ => None,
+    }
+}
+
+fn markup_line(line: &str, colors: &SyntaxColors, spans: &mut Spans) {
+    let mut rest = line;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            let end = comment.find("-->").map_or(rest.len(), |end| end + 7);
+            spans.push(&rest[..end], Some(colors.comment));
+            rest = &rest[end..];
+        } else if rest.starts_with('<') {
+            rest = markup_tag(rest, colors, spans);
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            spans.push(&rest[..end], Some(colors.text));
+            rest = &rest[end..];
+        }
+    }
+}
+
+/// Highlights the tag at the start of `tag` and returns what follows it.
+fn markup_tag<'a>(tag: &'a str, colors: &SyntaxColors, spans: &mut Spans) -> &'a str {
+    let open = tag
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| !matches!(c, '/' | '?' | '!'))
+        .map_or(tag.len(), |(i, _)| i);
+    spans.push(&tag[..open], Some(colors.punctuation));
+    let mut rest = &tag[open..];
+
+    let name = rest
+        .find(|c: char| c.is_whitespace() || matches!(c, '>' | '/'))
+        .unwrap_or(rest.len());
+    spans.push(&rest[..name], Some(colors.tag));
+    rest = &rest[name..];
+
+    while let Some(c) = rest.chars().next() {
+        let len = match c {
+            '>' => {
+                spans.push(">", Some(colors.punctuation));
+                return &rest[1..];
+            }
+            '"' | '\'' => {
+                let len = string_end(rest.as_bytes(), 0);
+                spans.push(&rest[..len], Some(colors.string));
+                len
+            }
+            '=' | '/' | '?' => {
+                spans.push(&rest[..1], Some(colors.punctuation));
+                1
+            }
+            c if c.is_whitespace() => {
+                spans.push(&rest[..c.len_utf8()], None);
+                c.len_utf8()
+            }
+            _ => {
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/'))
+                    .unwrap_or(rest.len());
+                spans.push(&rest[..len], Some(colors.attribute));
+                len
+            }
+        };
+        rest = &rest[len..];
+    }
+    rest
+}
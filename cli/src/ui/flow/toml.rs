@@ -2,13 +2,15 @@ use ratatui::style::{Color, Style};
 use ratatui::text::{Line, Span};
 use toml::{Table as TomlTable, Value as TomlValue};
 
-pub fn highlight_toml(src: &[u8]) -> Vec<Line<'static>> {
+use crate::config::SyntaxColors;
+
+pub fn highlight_toml(src: &[u8], colors: &SyntaxColors) -> Vec<Line<'static>> {
     let s = String::from_utf8_lossy(src);
     let mut lines = Vec::new();
 
     match s.parse::<TomlTable>() {
         Ok(root) => {
-            walk_toml_table(&root, &mut lines, 0);
+            walk_toml_table(&root, &mut lines, 0, colors);
         }
         Err(e) => {
             lines.push(Line::from(vec![Span::styled(
@@ -21,61 +23,73 @@ pub fn highlight_toml(src: &[u8]) -> Vec<Line<'static>> {
     lines
 }
 
-fn walk_toml_table(table: &TomlTable, lines: &mut Vec<Line<'static>>, indent: usize) {
+fn walk_toml_table(
+    table: &TomlTable,
+    lines: &mut Vec<Line<'static>>,
+    indent: usize,
+    colors: &SyntaxColors,
+) {
     for (key, val) in table.iter() {
         match val {
             TomlValue::Table(tbl) => {
                 lines.push(Line::from(vec![
                     Span::raw("  ".repeat(indent)),
-                    Span::styled("[", Style::default().fg(Color::DarkGray)),
-                    Span::styled(key.to_string(), Style::default().fg(Color::Blue)),
-                    Span::styled("]", Style::default().fg(Color::DarkGray)),
+                    Span::styled("[", Style::default().fg(colors.punctuation)),
+                    Span::styled(key.to_string(), Style::default().fg(colors.tag)),
+                    Span::styled("]", Style::default().fg(colors.punctuation)),
                 ]));
-                walk_toml_table(tbl, lines, indent + 1);
+                walk_toml_table(tbl, lines, indent + 1, colors);
             }
 
             TomlValue::Array(arr) if is_array_of_tables(arr) => {
                 for tbl in arr.iter().filter_map(|v| v.as_table()) {
                     lines.push(Line::from(vec![
                         Span::raw("  ".repeat(indent)),
-                        Span::styled("[[", Style::default().fg(Color::DarkGray)),
-                        Span::styled(key.to_string(), Style::default().fg(Color::Blue)),
-                        Span::styled("]]", Style::default().fg(Color::DarkGray)),
+                        Span::styled("[[", Style::default().fg(colors.punctuation)),
+                        Span::styled(key.to_string(), Style::default().fg(colors.tag)),
+                        Span::styled("]]", Style::default().fg(colors.punctuation)),
                     ]));
-                    walk_toml_table(tbl, lines, indent + 1);
+                    walk_toml_table(tbl, lines, indent + 1, colors);
                 }
             }
 
             _ => {
-                lines.push(toml_value_line(key, val, indent));
+                lines.push(toml_value_line(key, val, indent, colors));
             }
         }
     }
 }
 
-fn toml_value_line(key: &str, val: &TomlValue, indent: usize) -> Line<'static> {
+fn toml_value_line(
+    key: &str,
+    val: &TomlValue,
+    indent: usize,
+    colors: &SyntaxColors,
+) -> Line<'static> {
     let value_span = match val {
-        TomlValue::String(s) => Span::styled(format!("{s:?}"), Style::default().fg(Color::Green)),
-        TomlValue::Integer(i) => Span::styled(i.to_string(), Style::default().fg(Color::Magenta)),
-        TomlValue::Float(f) => Span::styled(f.to_string(), Style::default().fg(Color::Magenta)),
-        TomlValue::Boolean(b) => Span::styled(b.to_string(), Style::default().fg(Color::Yellow)),
-        TomlValue::Datetime(dt) => Span::styled(dt.to_string(), Style::default().fg(Color::Cyan)),
-        TomlValue::Array(arr) => highlight_toml_array(arr),
+        TomlValue::String(s) => Span::styled(format!("{s:?}"), Style::default().fg(colors.string)),
+        TomlValue::Integer(i) => Span::styled(i.to_string(), Style::default().fg(colors.number)),
+        TomlValue::Float(f) => Span::styled(f.to_string(), Style::default().fg(colors.number)),
+        TomlValue::Boolean(b) => Span::styled(b.to_string(), Style::default().fg(colors.literal)),
+        TomlValue::Datetime(dt) => {
+            Span::styled(dt.to_string(), Style::default().fg(colors.literal))
+        }
+        TomlValue::Array(arr) => highlight_toml_array(arr, colors),
         TomlValue::Table(tbl) => {
             let preview = inline_table_preview(tbl);
-            Span::styled(preview, Style::default().fg(Color::White))
+            Span::styled(preview, Style::default().fg(colors.text))
         }
     };
 
     Line::from(vec![
         Span::raw("  ".repeat(indent)),
-        Span::styled(key.to_string(), Style::default().fg(Color::Cyan)),
-        Span::styled(" = ", Style::default().fg(Color::DarkGray)),
+        Span::styled(key.to_string(), Style::default().fg(colors.key)),
+        Span::styled(" = ", Style::default().fg(colors.punctuation)),
         value_span,
     ])
 }
 
-fn highlight_toml_array(arr: &[TomlValue]) -> Span<'static> {
+fn highlight_toml_array(arr: &[TomlValue], colors: &SyntaxColors) -> Span<'static> {
     let content = arr
         .iter()
         .map(|v| match v {
@@ -90,7 +104,7 @@ fn highlight_toml_array(arr: &[TomlValue]) -> Span<'static> {
         .collect::<Vec<_>>()
         .join(", ");
 
-    Span::styled(format!("[{content}]"), Style::default().fg(Color::White))
+    Span::styled(format!("[{content}]"), Style::default().fg(colors.text))
 }
 
 fn inline_table_preview(tbl: &TomlTable) -> String {
@@ -0,0 +1,151 @@
+use rat_focus::HasFocus;
+use ratatui::{
+    Frame,
+    layout::Rect,
+    text::Line,
+    widgets::{Paragraph, Wrap},
+};
+use roxy_proxy::flow::{WsDirection, tunnel::RawTunnel};
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+    event::Action,
+    ui::framework::{
+        component::{ActionResult, Component},
+        theme::themed_block,
+    },
+};
+
+use super::hex::{hex_pages, render_hex};
+
+/// The bytes of a raw tunnel as a hex dump, one direction at a time.
+pub struct FlowTunnel {
+    state: watch::Receiver<Option<RawTunnel>>,
+    focus: rat_focus::FocusFlag,
+    direction: WsDirection,
+    page: usize,
+    scroll: u16,
+}
+
+impl FlowTunnel {
+    pub fn new(mut rx: mpsc::Receiver<Option<RawTunnel>>) -> Self {
+        let (ui_tx, ui_rx) = watch::channel(None);
+
+        tokio::spawn(async move {
+            while let Some(tunnel) = rx.recv().await {
+                ui_tx.send(tunnel).unwrap_or_else(|e| {
+                    tracing::debug!("Failed to send UI state update: {}", e);
+                });
+            }
+        });
+
+        Self {
+            state: ui_rx,
+            focus: rat_focus::FocusFlag::new().with_name("FlowTunnel"),
+            direction: WsDirection::Client,
+            page: 0,
+            scroll: 0,
+        }
+    }
+
+    fn bytes(&self) -> Option<Vec<u8>> {
+        self.state
+            .borrow()
+            .as_ref()
+            .map(|tunnel| tunnel.bytes(&self.direction))
+    }
+}
+
+impl HasFocus for FlowTunnel {
+    fn build(&self, builder: &mut rat_focus::FocusBuilder) {
+        builder.leaf_widget(self);
+    }
+
+    fn area(&self) -> Rect {
+        Rect::default()
+    }
+
+    fn focus(&self) -> rat_focus::FocusFlag {
+        self.focus.clone()
+    }
+}
+
+impl Component for FlowTunnel {
+    fn update(&mut self, action: Action) -> ActionResult {
+        if !self.focus.get() {
+            return ActionResult::Ignored;
+        }
+        match action {
+            Action::Up => {
+                self.scroll = self.scroll.saturating_sub(1);
+                ActionResult::Consumed
+            }
+            Action::Down => {
+                self.scroll = self.scroll.saturating_add(1);
+                ActionResult::Consumed
+            }
+            Action::Top => {
+                self.scroll = 0;
+                ActionResult::Consumed
+            }
+            Action::Select => {
+                self.direction = match self.direction {
+                    WsDirection::Client => WsDirection::Server,
+                    WsDirection::Server => WsDirection::Client,
+                };
+                self.page = 0;
+                self.scroll = 0;
+                ActionResult::Consumed
+            }
+            Action::Left | Action::Right => {
+                let Some(bytes) = self.bytes() else {
+                    return ActionResult::Ignored;
+                };
+                let last = hex_pages(bytes.len()) - 1;
+                self.page = match action {
+                    Action::Left => self.page.saturating_sub(1),
+                    _ => (self.page + 1).min(last),
+                };
+                self.scroll = 0;
+                ActionResult::Consumed
+            }
+            _ => ActionResult::Ignored,
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame, area: Rect) -> color_eyre::eyre::Result<()> {
+        let Some(tunnel) = self.state.borrow().clone() else {
+            let block = themed_block(Some("Tunnel"), self.focus.get());
+            f.render_widget(
+                Paragraph::new("Not a raw tunnel, its protocol was understood").block(block),
+                area,
+            );
+            return Ok(());
+        };
+
+        let title = match self.direction {
+            WsDirection::Client => "Client → server, enter for server → client",
+            WsDirection::Server => "Server → client, enter for client → server",
+        };
+        let mut lines = vec![];
+        if tunnel.tls {
+            lines.push(Line::raw("Decrypted from TLS"));
+        }
+        if tunnel.truncated(&self.direction) {
+            lines.push(Line::raw(format!(
+                "Only the first bytes of {} were kept",
+                tunnel.total(&self.direction)
+            )));
+        }
+        lines.extend(render_hex(&tunnel.bytes(&self.direction), self.page));
+
+        f.render_widget(
+            Paragraph::new(lines)
+                .wrap(Wrap { trim: false })
+                .scroll((self.scroll, 0))
+                .block(themed_block(Some(title), self.focus.get())),
+            area,
+        );
+        Ok(())
+    }
+}
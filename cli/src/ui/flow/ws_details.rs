@@ -1,46 +1,84 @@
+use cow_utils::CowUtils;
+use crossterm::event::{KeyCode, KeyEvent};
 use ratatui::{
-    layout::Constraint,
+    layout::{Constraint, Layout},
+    style::{Color, Style},
     text::{Line, Span},
     widgets::{Cell, Paragraph, Row, Wrap},
 };
-use roxy_proxy::flow::WsMessage;
+use roxy_proxy::{
+    flow::{WsDirection, WsMessage, id::FlowId},
+    ws::WsSessions,
+};
 use tokio::sync::{
     mpsc,
     watch::{self},
 };
+use tokio_tungstenite::tungstenite::Message;
 use tracing::debug;
 
-use crate::ui::framework::{
-    component::Component,
-    theme::{themed_block, themed_table},
+use crate::{
+    event::Action,
+    notify_info, notify_warn,
+    ui::framework::{
+        component::{ActionResult, Component, KeyEventResult},
+        theme::{themed_block, themed_table},
+    },
 };
 
+/// Characters of a message shown in its row, the export has all of it.
+const PREVIEW_LEN: usize = 200;
+
 pub struct FlowDetailsWs {
     state: watch::Receiver<UiState>,
     focus: rat_focus::FocusFlag,
     table_state: ratatui::widgets::TableState,
+    search: Search,
+    compose: Option<Compose>,
+    ws_sessions: WsSessions,
 }
 
 #[derive(Default, Clone)]
 struct UiState {
-    data: Vec<String>,
+    flow_id: Option<FlowId>,
+    messages: Vec<WsMessage>,
+}
+
+/// Text searched for in the messages of the flow, the matching ones are
+/// stepped through with next and previous.
+#[derive(Default)]
+struct Search {
+    input: String,
+    editing: bool,
+    hits: Vec<usize>,
+    current: usize,
+}
+
+/// A text message being typed to send over the live connection of the flow
+/// as if `from` had sent it, tab switches the side.
+struct Compose {
+    input: String,
+    from: WsDirection,
 }
 
 impl FlowDetailsWs {
-    pub fn new(mut cert_rx: mpsc::Receiver<Vec<WsMessage>>) -> Self {
+    pub fn new(
+        mut ws_rx: mpsc::Receiver<(FlowId, Vec<WsMessage>)>,
+        ws_sessions: WsSessions,
+    ) -> Self {
         let (ui_tx, ui_rx) = watch::channel(UiState::default());
 
         tokio::spawn({
             async move {
-                while let Some(messages) = cert_rx.recv().await {
-                    let messages: Vec<String> = messages
-                        .into_iter()
-                        .map(|msg| format!("{:?}: {}", msg.direction, msg.message))
-                        .collect();
-
-                    ui_tx.send(UiState { data: messages }).unwrap_or_else(|e| {
-                        debug!("Failed to send UI state update: {}", e);
-                    });
+                while let Some((flow_id, messages)) = ws_rx.recv().await {
+                    ui_tx
+                        .send(UiState {
+                            flow_id: Some(flow_id),
+                            messages,
+                        })
+                        .unwrap_or_else(|e| {
+                            debug!("Failed to send UI state update: {}", e);
+                        });
                 }
             }
         });
@@ -49,8 +87,137 @@ impl FlowDetailsWs {
             state: ui_rx,
             focus: rat_focus::FocusFlag::new().with_name("FlowWsDetails"),
             table_state: ratatui::widgets::TableState::default(),
+            search: Search::default(),
+            compose: None,
+            ws_sessions,
+        }
+    }
+
+    fn start_compose(&mut self) {
+        let Some(flow_id) = self.state.borrow().flow_id else {
+            return;
+        };
+        if !self.ws_sessions.is_live(flow_id) {
+            notify_warn!("The connection of flow {flow_id} is closed, press r to replay it");
+            return;
+        }
+        self.compose = Some(Compose {
+            input: String::new(),
+            from: WsDirection::Client,
+        });
+    }
+
+    fn send_compose(&mut self) {
+        let (Some(compose), Some(flow_id)) = (self.compose.take(), self.state.borrow().flow_id)
+        else {
+            return;
+        };
+        if self
+            .ws_sessions
+            .inject(flow_id, compose.from, Message::text(compose.input))
+        {
+            notify_info!("Sent message on flow {flow_id}");
+        } else {
+            notify_warn!("The connection of flow {flow_id} is closed");
+        }
+    }
+
+    fn compose_line(compose: &Compose) -> Line<'_> {
+        let (label, color) = match compose.from {
+            WsDirection::Client => ("→ to server ", Color::Cyan),
+            WsDirection::Server => ("← to client ", Color::Green),
+        };
+        Line::from(vec![
+            Span::styled(label, Style::default().fg(color)),
+            Span::raw(compose.input.as_str()),
+        ])
+    }
+
+    fn run_search(&mut self) {
+        let state = self.state.borrow();
+        let needle = self.search.input.cow_to_lowercase();
+        self.search.hits = state
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, msg)| msg.contains_lowercase(&needle))
+            .map(|(i, _)| i)
+            .collect();
+        drop(state);
+        self.search.current = 0;
+        if let Some(first) = self.search.hits.first() {
+            self.table_state.select(Some(*first));
         }
     }
+
+    /// Moves `step` hits from the current one, wrapping around at either end.
+    fn step_hit(&mut self, step: isize) {
+        let len = self.search.hits.len();
+        if len == 0 {
+            notify_warn!("No matching messages, press s to search");
+            return;
+        }
+        self.search.current =
+            (self.search.current as isize + step).rem_euclid(len as isize) as usize;
+        self.table_state
+            .select(Some(self.search.hits[self.search.current]));
+    }
+
+    fn search_line(&self) -> Line<'_> {
+        let mut spans = vec![
+            Span::styled("/", Style::default().fg(Color::DarkGray)),
+            Span::raw(self.search.input.as_str()),
+        ];
+        if !self.search.editing {
+            spans.push(match self.search.hits.len() {
+                0 => Span::raw("  No matches"),
+                len => Span::styled(
+                    format!("  {}/{}", self.search.current + 1, len),
+                    Style::default().fg(Color::Yellow),
+                ),
+            });
+        }
+        Line::from(spans)
+    }
+}
+
+fn message_row(index: usize, msg: &WsMessage, hit: bool) -> Row<'static> {
+    let time = msg.timestamp.time();
+    let (arrow, color) = match msg.direction {
+        WsDirection::Client => ("→", Color::Cyan),
+        WsDirection::Server => ("←", Color::Green),
+    };
+    let mut preview = match msg.text() {
+        Some(text) => text
+            .cow_replace('\n', " ")
+            .cow_replace('\r', " ")
+            .into_owned(),
+        None => format!("<{} bytes>", msg.size()),
+    };
+    if let Some((end, _)) = preview.char_indices().nth(PREVIEW_LEN) {
+        preview.truncate(end);
+        preview.push('…');
+    }
+    let row = Row::new(vec![
+        Cell::from(index.to_string()),
+        Cell::from(format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            time.hour(),
+            time.minute(),
+            time.second(),
+            time.millisecond()
+        )),
+        Cell::from(Span::styled(arrow, Style::default().fg(color))),
+        Cell::from(msg.opcode().to_string()),
+        Cell::from(msg.size().to_string()),
+        Cell::from(if msg.is_final() { "" } else { "frag" }),
+        Cell::from(preview),
+    ]);
+    if hit {
+        row.style(Style::default().fg(Color::Yellow))
+    } else {
+        row
+    }
 }
 
 impl rat_focus::HasFocus for FlowDetailsWs {
@@ -68,33 +235,147 @@ impl rat_focus::HasFocus for FlowDetailsWs {
 }
 
 impl Component for FlowDetailsWs {
+    fn update(&mut self, action: Action) -> ActionResult {
+        if !self.focus.get() {
+            return ActionResult::Ignored;
+        }
+        match action {
+            Action::Up => {
+                self.table_state.select_previous();
+                ActionResult::Consumed
+            }
+            Action::Down => {
+                self.table_state.select_next();
+                ActionResult::Consumed
+            }
+            Action::Top => {
+                self.table_state.select_first();
+                ActionResult::Consumed
+            }
+            Action::Bottom => {
+                self.table_state.select_last();
+                ActionResult::Consumed
+            }
+            Action::Search => {
+                self.search = Search {
+                    editing: true,
+                    ..Search::default()
+                };
+                ActionResult::Consumed
+            }
+            Action::SearchNext => {
+                self.step_hit(1);
+                ActionResult::Consumed
+            }
+            Action::SearchPrev => {
+                self.step_hit(-1);
+                ActionResult::Consumed
+            }
+            Action::Back if !self.search.input.is_empty() => {
+                self.search = Search::default();
+                ActionResult::Consumed
+            }
+            Action::ComposeWsMessage => {
+                self.start_compose();
+                ActionResult::Consumed
+            }
+            Action::ExportFlows => match self.state.borrow().flow_id {
+                Some(id) => ActionResult::Action(Action::SaveWsMessages(id)),
+                None => ActionResult::Consumed,
+            },
+            _ => ActionResult::Ignored,
+        }
+    }
+
+    fn handle_key_event(&mut self, key: &KeyEvent) -> KeyEventResult {
+        if let Some(compose) = &mut self.compose {
+            match key.code {
+                KeyCode::Esc => self.compose = None,
+                KeyCode::Enter => self.send_compose(),
+                KeyCode::Tab => {
+                    compose.from = match compose.from {
+                        WsDirection::Client => WsDirection::Server,
+                        WsDirection::Server => WsDirection::Client,
+                    }
+                }
+                KeyCode::Char(c) => compose.input.push(c),
+                KeyCode::Backspace => {
+                    compose.input.pop();
+                }
+                _ => {}
+            }
+            return KeyEventResult::Consumed;
+        }
+        if !self.search.editing {
+            return KeyEventResult::Ignored;
+        }
+        match key.code {
+            KeyCode::Esc => self.search = Search::default(),
+            KeyCode::Enter => {
+                self.search.editing = false;
+                self.run_search();
+            }
+            KeyCode::Char(c) => self.search.input.push(c),
+            KeyCode::Backspace => {
+                self.search.input.pop();
+            }
+            _ => {}
+        }
+        KeyEventResult::Consumed
+    }
+
     fn render(
         &mut self,
         f: &mut ratatui::Frame,
         area: ratatui::prelude::Rect,
     ) -> color_eyre::eyre::Result<()> {
-        let data = self.state.borrow_and_update().data.clone();
+        let state = self.state.borrow_and_update().clone();
 
-        if data.is_empty() {
+        if state.messages.is_empty() && self.compose.is_none() {
             let empty_text = vec![Line::raw("No messages")];
             let block = themed_block(Some("Messages"), self.focus.get());
             let paragraph = Paragraph::new(empty_text)
                 .block(block)
                 .wrap(Wrap { trim: false });
             f.render_widget(paragraph, area);
-        } else {
-            let rows: Vec<Row> = data
-                .iter()
-                .map(|field| Row::new(vec![Cell::from(Span::raw(field))]))
-                .collect();
-
-            let widths = [Constraint::Percentage(100)];
-
-            f.render_stateful_widget(
-                themed_table(rows, widths, None, true),
-                area,
-                &mut self.table_state,
-            );
+            return Ok(());
+        }
+
+        let searching = self.search.editing || !self.search.input.is_empty();
+        let [table_area, search_area, compose_area] = Layout::vertical([
+            Constraint::Min(0),
+            Constraint::Length(if searching { 1 } else { 0 }),
+            Constraint::Length(if self.compose.is_some() { 1 } else { 0 }),
+        ])
+        .areas(area);
+
+        let rows: Vec<Row> = state
+            .messages
+            .iter()
+            .enumerate()
+            .map(|(i, msg)| message_row(i, msg, self.search.hits.contains(&i)))
+            .collect();
+
+        let widths = [
+            Constraint::Length(4),
+            Constraint::Length(12),
+            Constraint::Length(1),
+            Constraint::Length(12),
+            Constraint::Length(8),
+            Constraint::Length(4),
+            Constraint::Fill(1),
+        ];
+
+        f.render_stateful_widget(
+            themed_table(rows, widths, Some("Messages"), self.focus.get()),
+            table_area,
+            &mut self.table_state,
+        );
+        if searching {
+            f.render_widget(Paragraph::new(self.search_line()), search_area);
+        }
+        if let Some(compose) = &self.compose {
+            f.render_widget(Paragraph::new(Self::compose_line(compose)), compose_area);
         }
 
         Ok(())
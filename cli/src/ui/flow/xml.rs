@@ -1,12 +1,14 @@
-use ratatui::style::{Color, Style};
+use ratatui::style::Style;
 use ratatui::text::{Line, Span};
 use xmltree::Element;
 
-pub fn pretty_print_xml(raw: &[u8]) -> Vec<Line<'static>> {
+use crate::config::SyntaxColors;
+
+pub fn pretty_print_xml(raw: &[u8], colors: &SyntaxColors) -> Vec<Line<'static>> {
     match xmltree::Element::parse(raw) {
         Ok(elem) => {
             let mut out = Vec::new();
-            walk_xml3(&elem, &mut out, 0);
+            walk_xml3(&elem, &mut out, 0, colors);
             out
         }
         Err(_) => vec![Line::from("<invalid xml>")],
@@ -14,22 +16,28 @@ pub fn pretty_print_xml(raw: &[u8]) -> Vec<Line<'static>> {
 }
 
 // HACK: lots of cloning here to get around lifetimes
-fn walk_xml3(elem: &Element, lines: &mut Vec<Line<'static>>, indent: usize) {
+fn walk_xml3(elem: &Element, lines: &mut Vec<Line<'static>>, indent: usize, colors: &SyntaxColors) {
     let indent_str = "  ".repeat(indent);
 
     let mut line = vec![
         Span::raw(indent_str.clone()),
-        Span::styled("<".to_string(), Style::default().fg(Color::DarkGray)),
-        Span::styled(elem.name.clone(), Style::default().fg(Color::Blue)),
+        Span::styled("<".to_string(), Style::default().fg(colors.punctuation)),
+        Span::styled(elem.name.clone(), Style::default().fg(colors.tag)),
     ];
 
     for (k, v) in &elem.attributes {
         line.push(Span::raw(" ".to_string()));
-        line.push(Span::styled(k.clone(), Style::default().fg(Color::Cyan)));
-        line.push(Span::raw("=".to_string()));
+        line.push(Span::styled(
+            k.clone(),
+            Style::default().fg(colors.attribute),
+        ));
+        line.push(Span::styled(
+            "=".to_string(),
+            Style::default().fg(colors.punctuation),
+        ));
         line.push(Span::styled(
             format!("\"{v}\""),
-            Style::default().fg(Color::Green),
+            Style::default().fg(colors.string),
         ));
     }
 
@@ -46,46 +54,46 @@ fn walk_xml3(elem: &Element, lines: &mut Vec<Line<'static>>, indent: usize) {
     if !has_elements && has_text {
         line.push(Span::styled(
             ">".to_string(),
-            Style::default().fg(Color::DarkGray),
+            Style::default().fg(colors.punctuation),
         ));
 
         if let Some(text) = elem.get_text() {
             let trimmed = text.trim().to_string();
-            line.push(Span::styled(trimmed, Style::default().fg(Color::White)));
+            line.push(Span::styled(trimmed, Style::default().fg(colors.text)));
         }
 
         line.push(Span::styled(
             "</".to_string(),
-            Style::default().fg(Color::DarkGray),
+            Style::default().fg(colors.punctuation),
         ));
         line.push(Span::styled(
             elem.name.clone(),
-            Style::default().fg(Color::Blue),
+            Style::default().fg(colors.tag),
         ));
         line.push(Span::styled(
             ">".to_string(),
-            Style::default().fg(Color::DarkGray),
+            Style::default().fg(colors.punctuation),
         ));
 
         lines.push(Line::from(line));
     } else {
         line.push(Span::styled(
             ">".to_string(),
-            Style::default().fg(Color::DarkGray),
+            Style::default().fg(colors.punctuation),
         ));
         lines.push(Line::from(line));
 
         for child in &elem.children {
             match child {
                 xmltree::XMLNode::Element(child_elem) => {
-                    walk_xml3(child_elem, lines, indent + 1);
+                    walk_xml3(child_elem, lines, indent + 1, colors);
                 }
                 xmltree::XMLNode::Text(t) => {
                     let trimmed = t.trim();
                     if !trimmed.is_empty() {
                         lines.push(Line::from(vec![
                             Span::raw("  ".repeat(indent + 1)),
-                            Span::styled(trimmed.to_string(), Style::default().fg(Color::White)),
+                            Span::styled(trimmed.to_string(), Style::default().fg(colors.text)),
                         ]));
                     }
                 }
@@ -95,9 +103,9 @@ fn walk_xml3(elem: &Element, lines: &mut Vec<Line<'static>>, indent: usize) {
 
         lines.push(Line::from(vec![
             Span::raw(indent_str.clone()),
-            Span::styled("</".to_string(), Style::default().fg(Color::DarkGray)),
-            Span::styled(elem.name.clone(), Style::default().fg(Color::Blue)),
-            Span::styled(">".to_string(), Style::default().fg(Color::DarkGray)),
+            Span::styled("</".to_string(), Style::default().fg(colors.punctuation)),
+            Span::styled(elem.name.clone(), Style::default().fg(colors.tag)),
+            Span::styled(">".to_string(), Style::default().fg(colors.punctuation)),
         ]));
     }
 }
@@ -1,15 +1,17 @@
 use std::io::Cursor;
 
-use ratatui::style::{Color, Style};
+use ratatui::style::Style;
 use ratatui::text::{Line, Span};
 use serde_yaml::Value;
 
-pub fn pretty_print_yaml(raw: &[u8]) -> Vec<Line<'static>> {
+use crate::config::SyntaxColors;
+
+pub fn pretty_print_yaml(raw: &[u8], colors: &SyntaxColors) -> Vec<Line<'static>> {
     let cursor = Cursor::new(raw);
     match serde_yaml::from_reader(cursor) {
         Ok(value) => {
             let mut lines = vec![];
-            walk_yaml(&value, &mut lines, 0);
+            walk_yaml(&value, &mut lines, 0, colors);
             lines
         }
         Err(_) => {
@@ -19,32 +21,32 @@ pub fn pretty_print_yaml(raw: &[u8]) -> Vec<Line<'static>> {
 }
 
 // TODO: we need to print out maps properly
-fn walk_yaml(value: &Value, lines: &mut Vec<Line>, indent: usize) {
+fn walk_yaml(value: &Value, lines: &mut Vec<Line>, indent: usize, colors: &SyntaxColors) {
     let indent_str = "  ".repeat(indent);
 
     match value {
         Value::Null => {
             lines.push(Line::from(vec![
                 Span::raw(indent_str),
-                Span::styled("null", Style::default().fg(Color::DarkGray)),
+                Span::styled("null", Style::default().fg(colors.literal)),
             ]));
         }
         Value::Bool(b) => {
             lines.push(Line::from(vec![
                 Span::raw(indent_str),
-                Span::styled(b.to_string(), Style::default().fg(Color::Magenta)),
+                Span::styled(b.to_string(), Style::default().fg(colors.literal)),
             ]));
         }
         Value::Number(n) => {
             lines.push(Line::from(vec![
                 Span::raw(indent_str),
-                Span::styled(n.to_string(), Style::default().fg(Color::Yellow)),
+                Span::styled(n.to_string(), Style::default().fg(colors.number)),
             ]));
         }
         Value::String(s) => {
             lines.push(Line::from(vec![
                 Span::raw(indent_str),
-                Span::styled(format!("\"{s}\""), Style::default().fg(Color::Green)),
+                Span::styled(format!("\"{s}\""), Style::default().fg(colors.string)),
             ]));
         }
         Value::Sequence(seq) => {
@@ -55,24 +57,24 @@ fn walk_yaml(value: &Value, lines: &mut Vec<Line>, indent: usize) {
                 match item {
                     Value::String(s) => lines.push(Line::from(vec![
                         Span::raw(prefix.clone()),
-                        Span::styled(format!("{s:?}"), Style::default().fg(Color::Green)),
+                        Span::styled(format!("{s:?}"), Style::default().fg(colors.string)),
                     ])),
                     Value::Number(n) => lines.push(Line::from(vec![
                         Span::raw(prefix.clone()),
-                        Span::styled(n.to_string(), Style::default().fg(Color::Yellow)),
+                        Span::styled(n.to_string(), Style::default().fg(colors.number)),
                     ])),
                     Value::Bool(b) => lines.push(Line::from(vec![
                         Span::raw(prefix.clone()),
-                        Span::styled(b.to_string(), Style::default().fg(Color::Magenta)),
+                        Span::styled(b.to_string(), Style::default().fg(colors.literal)),
                     ])),
                     Value::Null => lines.push(Line::from(vec![
                         Span::raw(prefix.clone()),
-                        Span::styled("null", Style::default().fg(Color::DarkGray)),
+                        Span::styled("null", Style::default().fg(colors.literal)),
                     ])),
                     _ => {
                         // Print just the dash for complex type
                         lines.push(Line::from(Span::raw(prefix)));
-                        walk_yaml(item, lines, indent + 1);
+                        walk_yaml(item, lines, indent + 1, colors);
                     }
                 }
             }
@@ -89,22 +91,22 @@ fn walk_yaml(value: &Value, lines: &mut Vec<Line>, indent: usize) {
                     Value::String(s) => {
                         lines.push(Line::from(vec![
                             Span::raw(indent_str.clone()),
-                            Span::styled(format!("{key}: "), Style::default().fg(Color::Blue)),
-                            Span::styled(format!("{s:?}"), Style::default().fg(Color::Green)),
+                            Span::styled(format!("{key}: "), Style::default().fg(colors.key)),
+                            Span::styled(format!("{s:?}"), Style::default().fg(colors.string)),
                         ]));
                     }
                     Value::Number(n) => {
                         lines.push(Line::from(vec![
                             Span::raw(indent_str.clone()),
-                            Span::styled(format!("{key}: "), Style::default().fg(Color::Blue)),
-                            Span::styled(n.to_string(), Style::default().fg(Color::Yellow)),
+                            Span::styled(format!("{key}: "), Style::default().fg(colors.key)),
+                            Span::styled(n.to_string(), Style::default().fg(colors.number)),
                         ]));
                     }
                     Value::Bool(b) => {
                         lines.push(Line::from(vec![
                             Span::raw(indent_str.clone()),
-                            Span::styled(format!("{key}: "), Style::default().fg(Color::Blue)),
-                            Span::styled(b.to_string(), Style::default().fg(Color::Magenta)),
+                            Span::styled(format!("{key}: "), Style::default().fg(colors.key)),
+                            Span::styled(b.to_string(), Style::default().fg(colors.literal)),
                         ]));
                     }
                     Value::Null => {
@@ -112,7 +114,7 @@ fn walk_yaml(value: &Value, lines: &mut Vec<Line>, indent: usize) {
                             Span::raw(indent_str.clone()),
                             Span::styled(
                                 format!("{key}: null"),
-                                Style::default().fg(Color::DarkGray),
+                                Style::default().fg(colors.literal),
                             ),
                         ]));
                     }
@@ -120,9 +122,9 @@ fn walk_yaml(value: &Value, lines: &mut Vec<Line>, indent: usize) {
                         // key first
                         lines.push(Line::from(vec![
                             Span::raw(indent_str.clone()),
-                            Span::styled(format!("{key}:"), Style::default().fg(Color::Blue)),
+                            Span::styled(format!("{key}:"), Style::default().fg(colors.key)),
                         ]));
-                        walk_yaml(v, lines, indent + 1);
+                        walk_yaml(v, lines, indent + 1, colors);
                     }
                 }
             }
@@ -133,10 +135,10 @@ fn walk_yaml(value: &Value, lines: &mut Vec<Line>, indent: usize) {
 
             lines.push(Line::from(vec![
                 Span::raw("  ".repeat(indent)),
-                Span::styled(format!("!{tag} "), Style::default().fg(Color::Cyan)),
+                Span::styled(format!("!{tag} "), Style::default().fg(colors.tag)),
             ]));
 
-            walk_yaml(inner, lines, indent + 1);
+            walk_yaml(inner, lines, indent + 1, colors);
         }
     }
 }
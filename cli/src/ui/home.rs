@@ -1,27 +1,40 @@
 use std::{
     collections::VecDeque,
+    net::SocketAddr,
     sync::{Arc, Mutex},
 };
 
 use crate::{config::ConfigManager, event::Action, tui::Event};
 
 use super::{
+    command_palette::CommandPalette,
     config_editor::ConfigEditor,
-    flow::{flow_details::FlowDetails, flow_list::FlowList},
+    flow::{
+        flow_body::query_image_picker, flow_details::FlowDetails, flow_diff::FlowDiffView,
+        flow_list::FlowList, grpc::ProtoSchema, pending_list::PendingList,
+        request_editor::RequestEditor,
+    },
     fps_counter::FpsCounter,
     framework::{
         component::{ActionResult, Component, KeyEventResult},
         notify::Notifier,
     },
     log::{LogLine, LogViewer},
+    problems::ProblemsView,
     quit_popup::QuitPopup,
+    script_editor::ScriptEditor,
     splash::Splash,
+    stats::StatsView,
+    status_bar::StatusBar,
 };
 
 use color_eyre::Result;
 use rat_focus::{FocusFlag, HasFocus};
-use ratatui::{Frame, layout::Rect};
-use roxy_proxy::flow::FlowStore;
+use ratatui::{
+    Frame,
+    layout::{Constraint, Layout, Rect},
+};
+use roxy_proxy::{flow::FlowStore, proxy::ProxyContext};
 
 pub struct HomeComponent {
     focus: FocusFlag,
@@ -31,10 +44,18 @@ pub struct HomeComponent {
     splash: Splash,
     flow_list: FlowList,
     flow_details: FlowDetails,
+    flow_diff: FlowDiffView,
+    stats: StatsView,
+    problems: ProblemsView,
     config_editor: ConfigEditor,
+    request_editor: RequestEditor,
+    script_editor: ScriptEditor,
+    pending_list: PendingList,
     quit_popup: QuitPopup,
     log_viewer: LogViewer,
     fps_counter: FpsCounter,
+    status_bar: StatusBar,
+    command_palette: CommandPalette,
     notifier: Notifier,
     config_manager: ConfigManager,
 }
@@ -43,12 +64,19 @@ impl HomeComponent {
     pub fn new(
         config_manager: ConfigManager,
         flow_store: FlowStore,
+        proxy_cxt: ProxyContext,
         log_buffer: Arc<Mutex<VecDeque<LogLine>>>,
         notifier: Notifier,
     ) -> Self {
-        let port = config_manager.rx.borrow().app.proxy.port;
-        let splash = Splash::new(port);
+        let addr = {
+            let config = config_manager.rx.borrow();
+            SocketAddr::new(config.app.proxy.bind_addr(), config.app.proxy.port)
+        };
+        let splash = Splash::new(addr);
         let flow_list = FlowList::new(flow_store.clone());
+        let proto_schema = ProtoSchema::load(&config_manager.rx.borrow().app.proto_descriptors);
+        let picker = query_image_picker();
+        let ws_sessions = proxy_cxt.ws_sessions.clone();
         Self {
             focus: FocusFlag::new().with_name("Home"),
             flow_store: flow_store.clone(),
@@ -57,8 +85,16 @@ impl HomeComponent {
             splash,
             flow_list,
             config_editor: ConfigEditor::new(config_manager.clone()),
+            pending_list: PendingList::new(proxy_cxt.breakpoints.clone()),
+            status_bar: StatusBar::new(flow_store.clone(), proxy_cxt.host_filter.clone()),
+            command_palette: CommandPalette::default(),
+            script_editor: ScriptEditor::new(proxy_cxt.script_engine.clone()),
+            problems: ProblemsView::new(proxy_cxt.diagnostics.clone()),
+            request_editor: RequestEditor::new(flow_store.clone(), proxy_cxt),
             quit_popup: QuitPopup::default(),
-            flow_details: FlowDetails::new(flow_store.clone()),
+            flow_details: FlowDetails::new(flow_store.clone(), proto_schema, picker, ws_sessions),
+            flow_diff: FlowDiffView::new(flow_store.clone()),
+            stats: StatsView::new(flow_store.clone()),
             log_viewer: LogViewer::new(log_buffer),
             fps_counter: FpsCounter::new(),
             notifier,
@@ -93,6 +129,24 @@ impl HasFocus for HomeComponent {
             Some(ActivePopup::LogViewer) => {
                 builder.widget(&self.log_viewer);
             }
+            Some(ActivePopup::RequestEditor) => {
+                builder.widget(&self.request_editor);
+            }
+            Some(ActivePopup::PendingList) => {
+                builder.widget(&self.pending_list);
+            }
+            Some(ActivePopup::FlowDiff) => {
+                builder.widget(&self.flow_diff);
+            }
+            Some(ActivePopup::Stats) => {
+                builder.widget(&self.stats);
+            }
+            Some(ActivePopup::Problems) => {
+                builder.widget(&self.problems);
+            }
+            Some(ActivePopup::ScriptEditor) => {
+                builder.widget(&self.script_editor);
+            }
             None => {}
         };
         builder.end(tag);
@@ -119,6 +173,12 @@ pub enum ActivePopup {
     QuitPopup,
     FlowDetails,
     LogViewer,
+    RequestEditor,
+    PendingList,
+    FlowDiff,
+    Stats,
+    Problems,
+    ScriptEditor,
 }
 
 impl Component for HomeComponent {
@@ -146,6 +206,12 @@ impl Component for HomeComponent {
             Some(ActivePopup::QuitPopup) => self.quit_popup.update(action.clone()),
             Some(ActivePopup::FlowDetails) => self.flow_details.update(action.clone()),
             Some(ActivePopup::LogViewer) => self.log_viewer.update(action.clone()),
+            Some(ActivePopup::RequestEditor) => self.request_editor.update(action.clone()),
+            Some(ActivePopup::PendingList) => self.pending_list.update(action.clone()),
+            Some(ActivePopup::FlowDiff) => self.flow_diff.update(action.clone()),
+            Some(ActivePopup::Stats) => self.stats.update(action.clone()),
+            Some(ActivePopup::Problems) => self.problems.update(action.clone()),
+            Some(ActivePopup::ScriptEditor) => self.script_editor.update(action.clone()),
             None => ActionResult::Ignored,
         };
 
@@ -171,6 +237,12 @@ impl Component for HomeComponent {
                 self.active_popup = Some(ActivePopup::ConfigEditor);
                 ActionResult::Consumed
             }
+            Action::CommandPalette => {
+                self.command_palette.open();
+                ActionResult::Consumed
+            }
+            // Sent from the palette while the splash is shown too
+            Action::FilterFlows(_) => self.flow_list.update(action),
             Action::Back => match self.active_popup {
                 Some(_) => {
                     self.active_popup = None;
@@ -195,12 +267,54 @@ impl Component for HomeComponent {
                     ActionResult::Ignored
                 }
             }
+            Action::DiffFlows(left, right) => {
+                self.flow_diff.set_flows(left, right);
+                self.active_popup = Some(ActivePopup::FlowDiff);
+                ActionResult::Consumed
+            }
+            Action::PendingView => {
+                self.active_popup = Some(ActivePopup::PendingList);
+                ActionResult::Consumed
+            }
+            Action::StatsView => {
+                self.active_popup = Some(ActivePopup::Stats);
+                ActionResult::Consumed
+            }
+            Action::ProblemsView => {
+                self.active_popup = Some(ActivePopup::Problems);
+                ActionResult::Consumed
+            }
+            Action::ScriptEditor => {
+                self.script_editor.open();
+                self.active_popup = Some(ActivePopup::ScriptEditor);
+                ActionResult::Consumed
+            }
+            Action::EditRequest if self.active_popup == Some(ActivePopup::PendingList) => {
+                if let Some((id, request)) = self.pending_list.selected() {
+                    self.request_editor.set_pending(id, request);
+                    self.active_popup = Some(ActivePopup::RequestEditor);
+                }
+                ActionResult::Consumed
+            }
+            Action::EditRequest => {
+                if let Some(id) = self.flow_list.selected_id() {
+                    self.request_editor.set_flow(id);
+                    self.active_popup = Some(ActivePopup::RequestEditor);
+                    ActionResult::Consumed
+                } else {
+                    ActionResult::Ignored
+                }
+            }
 
             _ => ActionResult::Ignored,
         }
     }
 
     fn render(&mut self, f: &mut Frame, area: Rect) -> Result<()> {
+        let full_area = area;
+        let [area, status_area] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(area);
+        self.status_bar.render(f, status_area)?;
         match self.active_view {
             ActiveView::Splash => self.splash.render(f, area)?,
             ActiveView::FlowList => self.flow_list.render(f, area)?,
@@ -212,19 +326,35 @@ impl Component for HomeComponent {
             Some(ActivePopup::QuitPopup) => self.quit_popup.render(f, area)?,
             Some(ActivePopup::FlowDetails) => self.flow_details.render(f, area)?,
             Some(ActivePopup::LogViewer) => self.log_viewer.render(f, area)?,
+            Some(ActivePopup::RequestEditor) => self.request_editor.render(f, area)?,
+            Some(ActivePopup::PendingList) => self.pending_list.render(f, area)?,
+            Some(ActivePopup::FlowDiff) => self.flow_diff.render(f, area)?,
+            Some(ActivePopup::Stats) => self.stats.render(f, area)?,
+            Some(ActivePopup::Problems) => self.problems.render(f, area)?,
+            Some(ActivePopup::ScriptEditor) => self.script_editor.render(f, area)?,
             None => {}
         };
 
+        self.command_palette.render(f, full_area)?;
         self.notifier.render(f, area);
         Ok(())
     }
 
     fn handle_key_event(&mut self, key: &crossterm::event::KeyEvent) -> KeyEventResult {
+        if self.command_palette.is_open() {
+            return self.command_palette.handle_key_event(key);
+        }
         let res = match self.active_popup {
             Some(ActivePopup::ConfigEditor) => self.config_editor.handle_key_event(key),
             Some(ActivePopup::QuitPopup) => self.quit_popup.handle_key_event(key),
             Some(ActivePopup::FlowDetails) => self.flow_details.handle_key_event(key),
             Some(ActivePopup::LogViewer) => self.log_viewer.handle_key_event(key),
+            Some(ActivePopup::RequestEditor) => self.request_editor.handle_key_event(key),
+            Some(ActivePopup::PendingList) => self.pending_list.handle_key_event(key),
+            Some(ActivePopup::FlowDiff) => self.flow_diff.handle_key_event(key),
+            Some(ActivePopup::Stats) => self.stats.handle_key_event(key),
+            Some(ActivePopup::Problems) => self.problems.handle_key_event(key),
+            Some(ActivePopup::ScriptEditor) => self.script_editor.handle_key_event(key),
             _ => KeyEventResult::Ignored,
         };
 
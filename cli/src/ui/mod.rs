@@ -1,8 +1,13 @@
+mod command_palette;
 pub mod config_editor;
 pub mod flow;
 mod fps_counter;
 pub mod framework;
 pub mod home;
 pub mod log;
+mod problems;
 pub mod quit_popup;
+mod script_editor;
 pub mod splash;
+mod stats;
+mod status_bar;
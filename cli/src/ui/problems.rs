@@ -0,0 +1,136 @@
+use color_eyre::Result;
+use rat_focus::{FocusFlag, HasFocus};
+use ratatui::{
+    Frame,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Cell, Clear, Paragraph, Row, Wrap},
+};
+use roxy_proxy::diagnostics::{Diagnostics, HostProblems};
+use time::OffsetDateTime;
+
+use crate::event::Action;
+
+use super::framework::{
+    component::{ActionResult, Component},
+    theme::{themed_block, themed_table},
+    util::centered_rect,
+};
+
+/// Hosts whose clients Roxy failed to intercept, the most recent first.
+pub struct ProblemsView {
+    focus: FocusFlag,
+    diagnostics: Diagnostics,
+    v_scroll_offset: usize,
+}
+
+impl ProblemsView {
+    pub fn new(diagnostics: Diagnostics) -> Self {
+        Self {
+            focus: FocusFlag::new().with_name("Problems"),
+            diagnostics,
+            v_scroll_offset: 0,
+        }
+    }
+}
+
+impl HasFocus for ProblemsView {
+    fn build(&self, builder: &mut rat_focus::FocusBuilder) {
+        builder.leaf_widget(self);
+    }
+
+    fn area(&self) -> Rect {
+        Rect::default()
+    }
+
+    fn focus(&self) -> rat_focus::FocusFlag {
+        self.focus.clone()
+    }
+}
+
+fn ago(at: OffsetDateTime) -> String {
+    let seconds = (OffsetDateTime::now_utc() - at).whole_seconds().max(0);
+    match seconds {
+        0..60 => format!("{seconds}s ago"),
+        60..3600 => format!("{}m ago", seconds / 60),
+        _ => format!("{}h ago", seconds / 3600),
+    }
+}
+
+fn row(problems: &HostProblems) -> Row<'static> {
+    let style = if problems.refuses_certificate() {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default()
+    };
+    Row::new(vec![
+        Cell::new(problems.host.clone()),
+        Cell::new(problems.certificate_rejected.to_string()),
+        Cell::new(problems.handshake_failed.to_string()),
+        Cell::new(problems.closed_after_handshake.to_string()),
+        Cell::new(ago(problems.last_seen)),
+        Cell::new(format!("{}: {}", problems.last_kind, problems.last_detail)),
+    ])
+    .style(style)
+}
+
+impl Component for ProblemsView {
+    fn update(&mut self, action: Action) -> ActionResult {
+        match action {
+            Action::Top => {
+                self.v_scroll_offset = 0;
+                ActionResult::Consumed
+            }
+            Action::Up => {
+                self.v_scroll_offset = self.v_scroll_offset.saturating_sub(1);
+                ActionResult::Consumed
+            }
+            Action::Down => {
+                self.v_scroll_offset += 1;
+                ActionResult::Consumed
+            }
+            Action::DeleteFlows => {
+                self.diagnostics.clear();
+                self.v_scroll_offset = 0;
+                ActionResult::Consumed
+            }
+            _ => ActionResult::Ignored,
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let popup_area = centered_rect(80, 80, area);
+        frame.render_widget(Clear, popup_area);
+
+        let problems = self.diagnostics.problems();
+        let [hint_area, hosts_area] =
+            Layout::vertical([Constraint::Length(4), Constraint::Min(0)]).areas(popup_area);
+        let hint = if problems.is_empty() {
+            "No interception failed so far"
+        } else {
+            "Hosts in yellow refused Roxy's certificate, their clients likely pin it or enforce \
+             HSTS. Add them to passthrough_hosts to let their traffic through untouched."
+        };
+        let hint = Paragraph::new(hint)
+            .wrap(Wrap { trim: true })
+            .block(themed_block(Some("Problems"), true));
+        frame.render_widget(hint, hint_area);
+
+        self.v_scroll_offset = self.v_scroll_offset.min(problems.len().saturating_sub(1));
+        let header = Row::new(["Host", "Rejected", "Failed", "Closed", "Last", "Last error"])
+            .style(Style::default().add_modifier(Modifier::BOLD));
+        let rows = problems.iter().skip(self.v_scroll_offset).map(row);
+        let widths = [
+            Constraint::Fill(1),
+            Constraint::Length(9),
+            Constraint::Length(7),
+            Constraint::Length(7),
+            Constraint::Length(9),
+            Constraint::Fill(2),
+        ];
+        let table =
+            themed_table(rows, widths, Some("Interception failures by host"), false).header(header);
+        frame.render_widget(table, hosts_area);
+        Ok(())
+    }
+}
@@ -0,0 +1,453 @@
+use std::path::Path;
+
+use color_eyre::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use rat_focus::{FocusFlag, HasFocus};
+use ratatui::{
+    Frame,
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Modifier, Style},
+    text::{Line, Span},
+    widgets::{Clear, Paragraph, Wrap},
+};
+use roxy_proxy::interceptor::{ScriptEngine, ScriptInfo, ScriptType};
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+    event::Action,
+    notify_error, notify_info,
+    ui::{
+        flow::syntax::{Lang, highlight_line},
+        framework::{
+            component::{ActionResult, Component, KeyEventResult},
+            theme::{themed_block, themed_info_block, with_theme},
+            util::centered_rect,
+        },
+    },
+};
+
+const TAB: &str = "    ";
+
+/// A loaded script being edited, with the edits not saved yet.
+struct Buffer {
+    name: String,
+    script_type: ScriptType,
+    lines: Vec<String>,
+    row: usize,
+    /// The char of `lines[row]` the cursor is before.
+    col: usize,
+    scroll: usize,
+    modified: bool,
+}
+
+impl Buffer {
+    fn new(name: String, script_type: ScriptType, source: &str) -> Self {
+        let mut lines: Vec<String> = source.lines().map(str::to_string).collect();
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        Self {
+            name,
+            script_type,
+            lines,
+            row: 0,
+            col: 0,
+            scroll: 0,
+            modified: false,
+        }
+    }
+
+    fn source(&self) -> String {
+        let mut source = self.lines.join("\n");
+        source.push('\n');
+        source
+    }
+
+    fn line_len(&self) -> usize {
+        self.lines[self.row].chars().count()
+    }
+
+    /// The byte offset of the cursor in its line.
+    fn offset(&self) -> usize {
+        let line = &self.lines[self.row];
+        line.char_indices()
+            .nth(self.col)
+            .map_or(line.len(), |(i, _)| i)
+    }
+
+    fn insert(&mut self, text: &str) {
+        let offset = self.offset();
+        self.lines[self.row].insert_str(offset, text);
+        self.col += text.chars().count();
+        self.modified = true;
+    }
+
+    fn new_line(&mut self) {
+        let offset = self.offset();
+        let rest = self.lines[self.row].split_off(offset);
+        let indent: String = self.lines[self.row]
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .collect();
+        self.col = indent.chars().count();
+        self.row += 1;
+        self.lines.insert(self.row, indent + &rest);
+        self.modified = true;
+    }
+
+    fn backspace(&mut self) {
+        if self.col > 0 {
+            self.col -= 1;
+            let offset = self.offset();
+            self.lines[self.row].remove(offset);
+        } else if self.row > 0 {
+            let line = self.lines.remove(self.row);
+            self.row -= 1;
+            self.col = self.line_len();
+            self.lines[self.row].push_str(&line);
+        } else {
+            return;
+        }
+        self.modified = true;
+    }
+
+    fn delete(&mut self) {
+        if self.col < self.line_len() {
+            let offset = self.offset();
+            self.lines[self.row].remove(offset);
+        } else if self.row + 1 < self.lines.len() {
+            let line = self.lines.remove(self.row + 1);
+            self.lines[self.row].push_str(&line);
+        } else {
+            return;
+        }
+        self.modified = true;
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.lines.len() - 1);
+        self.col = col.min(self.line_len());
+    }
+}
+
+/// Popup listing the loaded scripts and editing their source. Saving writes
+/// the script back to its file and reloads it, load and runtime errors are
+/// shown against the line they point at.
+pub struct ScriptEditor {
+    focus: FocusFlag,
+    script_engine: ScriptEngine,
+    scripts_tx: watch::Sender<Vec<ScriptInfo>>,
+    scripts_rx: watch::Receiver<Vec<ScriptInfo>>,
+    loaded_tx: mpsc::UnboundedSender<Buffer>,
+    loaded_rx: mpsc::UnboundedReceiver<Buffer>,
+    selected: usize,
+    buffer: Option<Buffer>,
+}
+
+impl HasFocus for ScriptEditor {
+    fn build(&self, builder: &mut rat_focus::FocusBuilder) {
+        builder.leaf_widget(self);
+    }
+
+    fn area(&self) -> Rect {
+        Rect::default()
+    }
+
+    fn focus(&self) -> rat_focus::FocusFlag {
+        self.focus.clone()
+    }
+}
+
+impl ScriptEditor {
+    pub fn new(script_engine: ScriptEngine) -> Self {
+        let (scripts_tx, scripts_rx) = watch::channel(vec![]);
+        let (loaded_tx, loaded_rx) = mpsc::unbounded_channel();
+        Self {
+            focus: FocusFlag::new().with_name("ScriptEditor"),
+            script_engine,
+            scripts_tx,
+            scripts_rx,
+            loaded_tx,
+            loaded_rx,
+            selected: 0,
+            buffer: None,
+        }
+    }
+
+    /// Shows the list of scripts, dropping any edits not saved.
+    pub fn open(&mut self) {
+        self.buffer = None;
+        self.refresh();
+    }
+
+    /// Fetches the scripts again, picking up errors raised since.
+    fn refresh(&self) {
+        let script_engine = self.script_engine.clone();
+        let scripts_tx = self.scripts_tx.clone();
+        tokio::spawn(async move {
+            scripts_tx.send_replace(script_engine.scripts().await);
+        });
+    }
+
+    fn edit_selected(&mut self) -> ActionResult {
+        let Some(info) = self.scripts_rx.borrow().get(self.selected).cloned() else {
+            return ActionResult::Ignored;
+        };
+        let script_engine = self.script_engine.clone();
+        let loaded_tx = self.loaded_tx.clone();
+        tokio::spawn(async move {
+            match script_engine.source(&info.name).await {
+                Some(source) => {
+                    let mut buffer = Buffer::new(info.name, info.script_type, &source);
+                    // Open at the error, it is what is most likely to be fixed
+                    if let Some(line) = info.error.and_then(|e| e.line) {
+                        buffer.move_to(line.saturating_sub(1), 0);
+                    }
+                    let _ = loaded_tx.send(buffer);
+                }
+                None => notify_error!("Script {} is no longer loaded", info.name),
+            }
+        });
+        ActionResult::Consumed
+    }
+
+    /// Writes the buffer to the script's file, when it was loaded from one,
+    /// and loads it in place of the running version.
+    fn save(&mut self) {
+        let Some(buffer) = &mut self.buffer else {
+            return;
+        };
+        buffer.modified = false;
+        let name = buffer.name.clone();
+        let script_type = buffer.script_type;
+        let source = buffer.source();
+        let script_engine = self.script_engine.clone();
+        let scripts_tx = self.scripts_tx.clone();
+        tokio::spawn(async move {
+            if Path::new(&name).is_file()
+                && let Err(err) = tokio::fs::write(&name, &source).await
+            {
+                notify_error!("Failed to write script {name} {err}");
+                return;
+            }
+            match script_engine.add_script(&name, &source, script_type).await {
+                Ok(()) => notify_info!("Reloaded script {name}"),
+                Err(_) => notify_error!("Script {name} failed to load, the old version still runs"),
+            }
+            scripts_tx.send_replace(script_engine.scripts().await);
+        });
+    }
+
+    fn handle_edit_key(&mut self, key: &KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            if key.code == KeyCode::Char('s') {
+                self.save();
+            }
+            return;
+        }
+        let Some(buffer) = &mut self.buffer else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => {
+                if buffer.modified {
+                    notify_info!("Dropped the unsaved edits to {}", buffer.name);
+                }
+                self.buffer = None;
+                self.refresh();
+            }
+            KeyCode::Enter => buffer.new_line(),
+            KeyCode::Tab => buffer.insert(TAB),
+            KeyCode::Backspace => buffer.backspace(),
+            KeyCode::Delete => buffer.delete(),
+            KeyCode::Left if buffer.col > 0 => buffer.col -= 1,
+            KeyCode::Left if buffer.row > 0 => {
+                buffer.row -= 1;
+                buffer.col = buffer.line_len();
+            }
+            KeyCode::Right if buffer.col < buffer.line_len() => buffer.col += 1,
+            KeyCode::Right if buffer.row + 1 < buffer.lines.len() => {
+                buffer.move_to(buffer.row + 1, 0);
+            }
+            KeyCode::Up => buffer.move_to(buffer.row.saturating_sub(1), buffer.col),
+            KeyCode::Down => buffer.move_to(buffer.row + 1, buffer.col),
+            KeyCode::PageUp => buffer.move_to(buffer.row.saturating_sub(20), buffer.col),
+            KeyCode::PageDown => buffer.move_to(buffer.row + 20, buffer.col),
+            KeyCode::Home => buffer.col = 0,
+            KeyCode::End => buffer.col = buffer.line_len(),
+            KeyCode::Char(c) => buffer.insert(c.encode_utf8(&mut [0; 4])),
+            _ => {}
+        }
+    }
+
+    fn render_list(&mut self, f: &mut Frame, area: Rect) {
+        let colors = with_theme(|t| t.colors.clone());
+        let scripts = self.scripts_rx.borrow();
+        if scripts.is_empty() {
+            f.render_widget(themed_info_block("No scripts loaded"), area);
+            return;
+        }
+        self.selected = self.selected.min(scripts.len() - 1);
+        let mut lines = vec![];
+        for (i, script) in scripts.iter().enumerate() {
+            let style = if i == self.selected {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default()
+            };
+            let mut spans = vec![Span::styled(
+                format!("{} ({})", script.name, script.script_type),
+                style,
+            )];
+            if !script.enabled {
+                spans.push(Span::styled(" disabled", Style::default().fg(colors.warn)));
+            }
+            if let Some(error) = &script.error {
+                let first = error.message.lines().next().unwrap_or_default();
+                spans.push(Span::styled(
+                    format!("  {first}"),
+                    Style::default().fg(colors.error),
+                ));
+            }
+            lines.push(Line::from(spans));
+        }
+        f.render_widget(
+            Paragraph::new(lines).block(
+                themed_block(Some("Scripts"), true)
+                    .title_bottom(" Enter to edit, Ctrl-s to save and reload "),
+            ),
+            area,
+        );
+    }
+
+    fn render_buffer(&mut self, f: &mut Frame, area: Rect) {
+        let colors = with_theme(|t| t.colors.clone());
+        let syntax = with_theme(|t| t.syntax);
+        let Some(buffer) = &mut self.buffer else {
+            return;
+        };
+        let error = self
+            .scripts_rx
+            .borrow()
+            .iter()
+            .find(|s| s.name == buffer.name)
+            .and_then(|s| s.error.clone());
+
+        let title = format!(
+            "{}{}",
+            buffer.name,
+            if buffer.modified { " [modified]" } else { "" }
+        );
+        let block = themed_block(Some(&title), true)
+            .title_bottom(" Ctrl-s to save and reload, Esc to go back ");
+        let error_height = if error.is_some() { 5 } else { 0 };
+        let [editor_area, error_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(error_height)])
+                .areas(block.inner(area));
+        f.render_widget(block, area);
+
+        let height = editor_area.height as usize;
+        if buffer.row < buffer.scroll {
+            buffer.scroll = buffer.row;
+        } else if height > 0 && buffer.row >= buffer.scroll + height {
+            buffer.scroll = buffer.row + 1 - height;
+        }
+        let gutter = buffer.lines.len().to_string().len();
+        let error_line = error.as_ref().and_then(|e| e.line);
+        let lang = (buffer.script_type == ScriptType::Js).then_some(Lang::Js);
+        let lines: Vec<Line> = buffer
+            .lines
+            .iter()
+            .enumerate()
+            .skip(buffer.scroll)
+            .take(height)
+            .map(|(i, text)| {
+                let number_style = if error_line == Some(i + 1) {
+                    Style::default()
+                        .fg(colors.surface)
+                        .bg(colors.error)
+                        .add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default().fg(colors.outline_unfocused)
+                };
+                let mut line = match lang {
+                    Some(lang) => highlight_line(lang, text, &syntax),
+                    None => Line::from(text.clone()),
+                };
+                line.spans.insert(
+                    0,
+                    Span::styled(format!("{:>gutter$} ", i + 1), number_style),
+                );
+                line
+            })
+            .collect();
+        f.render_widget(Paragraph::new(lines), editor_area);
+
+        let cursor_x = editor_area.x as usize + gutter + 1 + buffer.col;
+        let cursor_y = editor_area.y as usize + buffer.row - buffer.scroll;
+        if cursor_x < editor_area.right() as usize && cursor_y < editor_area.bottom() as usize {
+            f.set_cursor_position(Position::new(cursor_x as u16, cursor_y as u16));
+        }
+
+        if let Some(error) = error {
+            let title = match error.line {
+                Some(line) => format!("Error on line {line}"),
+                None => "Error".to_string(),
+            };
+            f.render_widget(
+                Paragraph::new(error.message)
+                    .style(Style::default().fg(colors.error))
+                    .wrap(Wrap { trim: false })
+                    .block(themed_block(Some(&title), false)),
+                error_area,
+            );
+        }
+    }
+}
+
+impl Component for ScriptEditor {
+    fn update(&mut self, action: Action) -> ActionResult {
+        if matches!(action, Action::Tick) {
+            self.refresh();
+            return ActionResult::Ignored;
+        }
+        if self.buffer.is_some() {
+            return ActionResult::Ignored;
+        }
+        match action {
+            Action::Up => {
+                self.selected = self.selected.saturating_sub(1);
+                ActionResult::Consumed
+            }
+            Action::Down => {
+                self.selected += 1;
+                ActionResult::Consumed
+            }
+            Action::Select => self.edit_selected(),
+            _ => ActionResult::Ignored,
+        }
+    }
+
+    fn handle_key_event(&mut self, key: &KeyEvent) -> KeyEventResult {
+        if self.buffer.is_none() {
+            return KeyEventResult::Ignored;
+        }
+        self.handle_edit_key(key);
+        KeyEventResult::Consumed
+    }
+
+    fn render(&mut self, f: &mut Frame, area: Rect) -> Result<()> {
+        while let Ok(buffer) = self.loaded_rx.try_recv() {
+            self.buffer = Some(buffer);
+        }
+
+        let popup_area = centered_rect(80, 80, area);
+        f.render_widget(Clear, popup_area);
+        if self.buffer.is_some() {
+            self.render_buffer(f, popup_area);
+        } else {
+            self.render_list(f, popup_area);
+        }
+        Ok(())
+    }
+}
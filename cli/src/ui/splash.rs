@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use color_eyre::Result;
 use rat_focus::{FocusFlag, HasFocus};
 use ratatui::{
@@ -10,13 +12,13 @@ use super::framework::{component::Component, theme::with_theme};
 
 pub struct Splash {
     focus: FocusFlag,
-    port: u16,
+    addr: SocketAddr,
 }
 
 impl Splash {
-    pub fn new(port: u16) -> Self {
+    pub fn new(addr: SocketAddr) -> Self {
         Self {
-            port,
+            addr,
             focus: FocusFlag::new().with_name("Splash"),
         }
     }
@@ -70,7 +72,7 @@ impl Component for Splash {
         let addr = BigText::builder()
             .pixel_size(PixelSize::Sextant)
             .centered()
-            .lines(vec![format!("127.0.0.1:{}", self.port).into()])
+            .lines(vec![self.addr.to_string().into()])
             .style(text_style)
             .build();
 
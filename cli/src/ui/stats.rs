@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use color_eyre::Result;
+use rat_focus::{FocusFlag, HasFocus};
+use ratatui::{
+    Frame,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Cell, Clear, Paragraph, Row},
+};
+use roxy_proxy::{flow::FlowStore, metrics::MetricsSnapshot};
+
+use crate::event::Action;
+
+use super::framework::{
+    component::{ActionResult, Component},
+    theme::{themed_block, themed_table},
+    util::centered_rect,
+};
+
+/// Totals, status codes and per host latencies of the traffic so far.
+pub struct StatsView {
+    focus: FocusFlag,
+    flow_store: FlowStore,
+    v_scroll_offset: usize,
+}
+
+impl StatsView {
+    pub fn new(flow_store: FlowStore) -> Self {
+        Self {
+            focus: FocusFlag::new().with_name("Stats"),
+            flow_store,
+            v_scroll_offset: 0,
+        }
+    }
+}
+
+impl HasFocus for StatsView {
+    fn build(&self, builder: &mut rat_focus::FocusBuilder) {
+        builder.leaf_widget(self);
+    }
+
+    fn area(&self) -> Rect {
+        Rect::default()
+    }
+
+    fn focus(&self) -> rat_focus::FocusFlag {
+        self.focus.clone()
+    }
+}
+
+fn bytes(count: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if count < 1024 {
+        return format!("{count} B");
+    }
+    let mut value = count as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{}ms", duration.as_millis())
+}
+
+fn status_color(code: u16) -> Color {
+    match code {
+        200..=299 => Color::Green,
+        300..=399 => Color::Cyan,
+        400..=499 => Color::Yellow,
+        _ => Color::Red,
+    }
+}
+
+fn summary_lines(snapshot: &MetricsSnapshot) -> Vec<Line<'static>> {
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let mut codes = vec![Span::styled("Status    ", bold)];
+    for (code, count) in &snapshot.status_codes {
+        codes.push(Span::styled(
+            format!("{code}"),
+            Style::default().fg(status_color(*code)),
+        ));
+        codes.push(Span::raw(format!(" {count}   ")));
+    }
+    let errors = if snapshot.errors > 0 {
+        Style::default().fg(Color::Red)
+    } else {
+        Style::default()
+    };
+    vec![
+        Line::from(vec![
+            Span::styled("Requests  ", bold),
+            Span::raw(snapshot.requests.to_string()),
+            Span::styled("   Errors  ", bold),
+            Span::styled(snapshot.errors.to_string(), errors),
+        ]),
+        Line::from(vec![
+            Span::styled("Bytes in  ", bold),
+            Span::raw(bytes(snapshot.request_bytes)),
+            Span::styled("   Bytes out  ", bold),
+            Span::raw(bytes(snapshot.response_bytes)),
+        ]),
+        Line::from(codes),
+    ]
+}
+
+impl Component for StatsView {
+    fn update(&mut self, action: Action) -> ActionResult {
+        match action {
+            Action::Top => {
+                self.v_scroll_offset = 0;
+                ActionResult::Consumed
+            }
+            Action::Up => {
+                self.v_scroll_offset = self.v_scroll_offset.saturating_sub(1);
+                ActionResult::Consumed
+            }
+            Action::Down => {
+                self.v_scroll_offset += 1;
+                ActionResult::Consumed
+            }
+            _ => ActionResult::Ignored,
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let popup_area = centered_rect(80, 80, area);
+        frame.render_widget(Clear, popup_area);
+
+        let snapshot = self.flow_store.metrics().snapshot();
+        let [summary_area, hosts_area] =
+            Layout::vertical([Constraint::Length(5), Constraint::Min(0)]).areas(popup_area);
+        let summary =
+            Paragraph::new(summary_lines(&snapshot)).block(themed_block(Some("Stats"), true));
+        frame.render_widget(summary, summary_area);
+
+        self.v_scroll_offset = self
+            .v_scroll_offset
+            .min(snapshot.hosts.len().saturating_sub(1));
+        let header = Row::new(["Host", "Requests", "p50", "p90", "p99"])
+            .style(Style::default().add_modifier(Modifier::BOLD));
+        let rows = snapshot
+            .hosts
+            .iter()
+            .skip(self.v_scroll_offset)
+            .map(|host| {
+                Row::new(vec![
+                    Cell::new(host.host.clone()),
+                    Cell::new(host.count.to_string()),
+                    Cell::new(millis(host.p50)),
+                    Cell::new(millis(host.p90)),
+                    Cell::new(millis(host.p99)),
+                ])
+            });
+        let widths = [
+            Constraint::Fill(1),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+        ];
+        let table = themed_table(rows, widths, Some("Latency by host"), false).header(header);
+        frame.render_widget(table, hosts_area);
+        Ok(())
+    }
+}
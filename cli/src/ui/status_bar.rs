@@ -0,0 +1,74 @@
+use color_eyre::Result;
+use ratatui::{
+    Frame,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::Paragraph,
+};
+use roxy_proxy::{filter::HostFilter, flow::FlowStore};
+
+use crate::ui::framework::{component::Component, theme::with_theme};
+
+/// The line along the bottom of the screen showing whether traffic is
+/// recorded, which capture profile picks the flows kept and whether traffic
+/// is intercepted.
+pub struct StatusBar {
+    flow_store: FlowStore,
+    host_filter: HostFilter,
+}
+
+impl StatusBar {
+    pub fn new(flow_store: FlowStore, host_filter: HostFilter) -> Self {
+        Self {
+            flow_store,
+            host_filter,
+        }
+    }
+}
+
+impl Component for StatusBar {
+    fn render(&mut self, f: &mut Frame, area: Rect) -> Result<()> {
+        let colors = with_theme(|t| t.colors.clone());
+        let base = Style::default().fg(colors.on_surface).bg(colors.surface);
+        let badge = |text: String, color: Color| {
+            Span::styled(
+                text,
+                Style::default()
+                    .fg(colors.surface)
+                    .bg(color)
+                    .add_modifier(Modifier::BOLD),
+            )
+        };
+
+        let mut spans = vec![if self.flow_store.capturing() {
+            badge(" ● REC ".to_string(), colors.success)
+        } else {
+            badge(" ⏸ PAUSED ".to_string(), colors.warn)
+        }];
+        if self.host_filter.paused() {
+            spans.push(Span::raw(" "));
+            spans.push(badge(" PASSTHROUGH ".to_string(), colors.error));
+        }
+        if let Some(profile) = self.flow_store.capture_profile() {
+            spans.push(Span::raw(" "));
+            spans.push(badge(format!(" {} ", profile.name), colors.primary));
+            spans.push(Span::raw(format!(" {}", profile.filter)));
+        }
+        spans.push(Span::raw(format!(" {} flows", self.flow_store.flows.len())));
+        let evicted = self.flow_store.evicted();
+        if evicted > 0 {
+            spans.push(Span::raw(format!(", {evicted} evicted")));
+        }
+        let dropped = self.flow_store.dropped();
+        if dropped > 0 {
+            spans.push(Span::raw(format!(", {dropped} dropped")));
+        }
+        if !self.flow_store.capturing() {
+            spans.push(Span::raw(", new traffic is proxied but not recorded"));
+        }
+
+        f.render_widget(Paragraph::new(Line::from(spans)).style(base), area);
+        Ok(())
+    }
+}
@@ -1,14 +1,19 @@
 use std::{
+    convert::Infallible,
+    hint::black_box,
     net::{SocketAddr, UdpSocket},
     process::Command,
     time::Duration,
 };
 
+use bytes::Bytes;
 use criterion::{Criterion, criterion_group, criterion_main};
-use http::{Method, Version};
-use http_body_util::{Empty, combinators::BoxBody};
+use http::{Method, Request, Response, Version};
+use http_body_util::{Empty, Full, combinators::BoxBody};
+use hyper::{body::Incoming, service::service_fn};
+use hyper_util::rt::TokioIo;
 use once_cell::sync::OnceCell;
-use roxy_proxy::{flow::FlowStore, interceptor, proxy::ProxyManager};
+use roxy_proxy::{filter::HostFilterRules, flow::FlowStore, interceptor, proxy::ProxyManager};
 use roxy_servers::{H11_BODY, h1::h1_server};
 use roxy_shared::{
     RoxyCA, client::ClientContext, crypto::init_crypto, generate_roxy_root_ca_with_path,
@@ -36,7 +41,7 @@ pub struct TestContext {
     proxy_addr: RUri,
     _temp_dir: TempDir,
     roxy_ca: RoxyCA,
-    _proxy_manager: ProxyManager,
+    proxy_manager: ProxyManager,
 }
 
 impl TestContext {
@@ -72,7 +77,7 @@ impl TestContext {
             _proxy_socket_addr: proxy_socket_addr,
             proxy_addr: proxy_uri,
             _temp_dir: temp_dir,
-            _proxy_manager: proxy_manager,
+            proxy_manager,
             roxy_ca,
         }
     }
@@ -315,10 +320,97 @@ fn criterion_benchmark_mitm_multi(c: &mut Criterion) {
     });
 }
 
+/// Sends the request body straight back, frame by frame.
+async fn echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let service = service_fn(|req: Request<Incoming>| async move {
+                    Ok::<_, Infallible>(Response::new(req.into_body()))
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    addr
+}
+
+/// A 1 MiB upload echoed back, once with bodies recorded and once through the
+/// unbuffered path for hosts whose bodies are skipped.
+fn criterion_benchmark_body_paths(c: &mut Criterion) {
+    const BODY_SIZE: usize = 1024 * 1024;
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let (cxt, target_uri) = rt.block_on(async {
+        let cxt = TestContext::new().await;
+        let server_addr = echo_server().await;
+        let target_uri: RUri = format!("http://{server_addr}").parse().unwrap();
+        (cxt, target_uri)
+    });
+    let body = Bytes::from(vec![b'r'; BODY_SIZE]);
+    let client = ClientContext::builder()
+        .with_proxy(cxt.proxy_addr.clone())
+        .with_roxy_ca(cxt.roxy_ca.clone())
+        .build();
+    let host_filter = cxt.proxy_manager.host_filter();
+
+    for (name, skip_bodies) in [
+        ("post 1MiB buffered", vec![]),
+        ("post 1MiB unbuffered", vec!["*".to_string()]),
+    ] {
+        host_filter.set_rules(HostFilterRules::default().with_skip_bodies(skip_bodies));
+        c.bench_function(name, |b| {
+            b.to_async(&rt).iter(|| async {
+                let req = http::Request::builder()
+                    .method(Method::POST)
+                    .version(Version::HTTP_11)
+                    .uri(target_uri.clone())
+                    .body(BoxBody::new(Full::new(body.clone())))
+                    .unwrap();
+                let response = timeout(Duration::from_secs(5), client.request(req))
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(response.parts.status, 200);
+                assert_eq!(response.body.len(), BODY_SIZE);
+            });
+        });
+    }
+}
+
+/// Flow ids handed out from several threads at once, as when many
+/// connections open flows together.
+fn criterion_benchmark_flow_ids(c: &mut Criterion) {
+    const THREADS: usize = 8;
+    const IDS_PER_THREAD: usize = 10_000;
+    let flow_store = FlowStore::new();
+    c.bench_function("flow ids 8 threads", |b| {
+        b.iter(|| {
+            std::thread::scope(|scope| {
+                for _ in 0..THREADS {
+                    scope.spawn(|| {
+                        for _ in 0..IDS_PER_THREAD {
+                            black_box(flow_store.next_flow_id());
+                        }
+                    });
+                }
+            });
+        });
+    });
+}
+
 criterion_group!(
     benches,
+    criterion_benchmark_flow_ids,
     criterion_benchmark_roxy,
     criterion_benchmark_roxy_multi,
+    criterion_benchmark_body_paths,
     criterion_benchmark_mitm,
     criterion_benchmark_mitm_multi
 );
@@ -2,6 +2,10 @@ declare global {
   interface Extensions {
     request?(flow: Flow): void;
     response?(flow: Flow): void;
+    /** Return `false` to reject the connection. */
+    on_client_connect?(conn: Connection): boolean | void;
+    on_server_connect?(conn: Connection): boolean | void;
+    on_tls_established?(conn: Connection): boolean | void;
   }
 
   interface Extension {
@@ -9,11 +13,39 @@ declare global {
     request(flow: Flow): void;
     response(flow: Flow): void;
     stop(): void;
+    on_client_connect?(conn: Connection): boolean | void;
+    on_server_connect?(conn: Connection): boolean | void;
+    on_tls_established?(conn: Connection): boolean | void;
+  }
+
+  interface Connection {
+    readonly client_addr: string;
+    readonly client_ip: string;
+    readonly host: string | null;
+    readonly port: number | null;
+    readonly tls: Tls | null;
   }
 
   interface Flow {
     request: Request;
     response: Response | undefined;
+    readonly tls: Tls | null;
+    readonly tags: Tags;
+  }
+
+  interface Tags {
+    readonly length: number;
+    add(tag: string): void;
+    has(tag: string): boolean;
+    list(): string[];
+  }
+
+  interface Tls {
+    readonly version: string | null;
+    readonly cipher: string | null;
+    readonly alpn: string | null;
+    readonly sni: string | null;
+    readonly peer_subject: string | null;
   }
 
   interface Request {
@@ -76,6 +108,19 @@ declare global {
     length: number;
     clear(): void;
     isEmpty(): boolean;
+    json(): any;
+    setJson(value: any): void;
+    form(): Record<string, string>;
+    multipart(boundary?: string): MultipartPart[] | null;
+  }
+
+  interface MultipartPart {
+    name: string | null;
+    filename: string | null;
+    contentType: string | null;
+    headers: Record<string, string>;
+    text: string;
+    raw: ArrayBuffer;
   }
 
   enum Version {
@@ -166,6 +211,40 @@ declare global {
     NETWORK_AUTHENTICATION_REQUIRED = 511,
   }
 
+  interface FetchOptions {
+    method?: string;
+    headers?: Record<string, string>;
+    body?: string | ArrayBuffer;
+    /** Milliseconds, 30 seconds when not set. */
+    timeout?: number;
+  }
+
+  /** Blocks until the response arrives, throws when the request fails. */
+  function fetch(url: string, options?: FetchOptions): Response;
+
+  /**
+   * Runs `callback` once `delay` milliseconds have passed, between intercepts
+   * rather than during one.
+   */
+  function setTimeout<A extends unknown[]>(
+    callback: (...args: A) => void,
+    delay?: number,
+    ...args: A
+  ): number;
+  function clearTimeout(id?: number): void;
+  /** Runs `callback` once the current handler returns. */
+  function queueMicrotask(callback: () => void): void;
+
+  /** Values kept across requests and restarts, shared by every script. */
+  interface Store {
+    get(key: string): string | null;
+    /** Values are stored as strings, `null` removes the key. */
+    set(key: string, value: unknown): void;
+    delete(key: string): void;
+  }
+
+  var store: Store;
+
   var extensions: Extensions[];
 }
 
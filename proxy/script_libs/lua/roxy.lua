@@ -9,10 +9,34 @@
 ---@field request fun(flow: Flow)?   # Optional request handler
 ---@field response fun(flow: Flow)?  # Optional response handler
 ---@field stop fun()?                # Optional stop handler
+---@field on_client_connect fun(conn: Connection): boolean?   # Return false to reject
+---@field on_server_connect fun(conn: Connection): boolean?   # Return false to reject
+---@field on_tls_established fun(conn: Connection): boolean?  # Return false to reject
+
+---@class Connection
+---@field client_addr string
+---@field client_ip string
+---@field host string?
+---@field port integer?
+---@field tls Tls?
 
 ---@class Flow
 ---@field request Request
 ---@field response Response?
+---@field tls Tls?
+---@field tags Tags
+
+---@class Tags
+---@field add fun(self: Tags, tag: string)
+---@field has fun(self: Tags, tag: string): boolean
+---@field list fun(self: Tags): string[]
+
+---@class Tls
+---@field version string?
+---@field cipher string?
+---@field alpn string?
+---@field sni string?
+---@field peer_subject string?
 
 ---@class Request
 ---@field url URL
@@ -226,6 +250,18 @@ Status = {
 ---@field raw string          # Raw bytes view (Lua string)
 ---@field clear fun()         # Clears body to empty
 ---@field is_empty boolean    # True if body length is zero
+---@field json fun(self: Body): any # Parses the body as JSON, null is a null light userdata
+---@field set_json fun(self: Body, value: any) # Replaces the body with value as JSON
+---@field form fun(self: Body): table<string, string> # Form fields, the last of a repeated name
+---@field multipart fun(self: Body, boundary: string?): MultipartPart[]? # nil unless multipart
+
+---@class MultipartPart
+---@field name string?
+---@field filename string?
+---@field content_type string?
+---@field headers table<string, string> # Lowercase names
+---@field text string
+---@field raw string
 
 ---@class Headers
 ---@field get fun(self: Headers, key: string): string|nil
@@ -238,8 +274,27 @@ Status = {
 ---@type Extension[]  # Global array discovered by Roxy
 Extensions = {}
 
+---@class FetchOptions
+---@field method string?
+---@field headers table<string, string>?
+---@field body string?
+---@field timeout integer?   # Milliseconds, 30 seconds when not set
+
+---@class Store
+---@field get fun(key: string): string?
+---@field set fun(key: string, value: string|number|boolean|nil)  # Stored as a string, nil removes the key
+---@field delete fun(key: string)
+
+---@class Json
+---@field encode fun(value: any, pretty: boolean?): string  # Raises on functions and other values JSON cannot hold
+---@field decode fun(text: string): any                     # Raises on invalid JSON
+---@field null lightuserdata                                 # What JSON null decodes to
+
 ---@class Roxy
 ---@field notify fun(severity: integer, message: string)
+---@field fetch fun(url: string, options: FetchOptions?): Response  # Raises when the request fails
+---@field store Store                                                 # Kept across requests and restarts
+---@field json Json
 
 ---@type Roxy
 Roxy = Roxy
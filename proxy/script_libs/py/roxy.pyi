@@ -1,11 +1,15 @@
 import enum
-from typing import Optional, Protocol as ProtocolType, runtime_checkable, List
+from typing import Any, Dict, Optional, Protocol as ProtocolType, runtime_checkable, List, Union
 
 class Body:
     text: str
     bytes: bytes
 
     def clear(self) -> None: ...
+    def json(self) -> Any: ...
+    def set_json(self, value: Any) -> None: ...
+    def form(self) -> Dict[str, str]: ...
+    def multipart(self, boundary: Optional[str] = None) -> Optional[List[Dict[str, Any]]]: ...
     def __len__(self) -> int: ...
     def __str__(self) -> str: ...
     def __repr__(self) -> str: ...
@@ -157,9 +161,47 @@ class Response:
     def __str__(self) -> str: ...
     def __repr__(self) -> str: ...
 
+class Tls:
+    @property
+    def version(self) -> Optional[str]: ...
+    @property
+    def cipher(self) -> Optional[str]: ...
+    @property
+    def alpn(self) -> Optional[str]: ...
+    @property
+    def sni(self) -> Optional[str]: ...
+    @property
+    def peer_subject(self) -> Optional[str]: ...
+    def __repr__(self) -> str: ...
+
+class Tags:
+    def add(self, tag: str) -> None: ...
+    def has(self, tag: str) -> bool: ...
+    def list(self) -> List[str]: ...
+    def __len__(self) -> int: ...
+    def __contains__(self, tag: str) -> bool: ...
+    def __repr__(self) -> str: ...
+
+class Connection:
+    @property
+    def client_addr(self) -> str: ...
+    @property
+    def client_ip(self) -> str: ...
+    @property
+    def host(self) -> Optional[str]: ...
+    @property
+    def port(self) -> Optional[int]: ...
+    @property
+    def tls(self) -> Optional[Tls]: ...
+    def __repr__(self) -> str: ...
+
 class Flow:
     request: Request
     response: Response
+    @property
+    def tls(self) -> Optional[Tls]: ...
+    @property
+    def tags(self) -> Tags: ...
     def __str__(self) -> str: ...
     def __repr__(self) -> str: ...
 
@@ -169,8 +211,26 @@ class Extension(ProtocolType):
     def stop(self) -> None: ...
     def request(self, flow: Flow) -> None: ...
     def response(self, flow: Flow) -> None: ...
+    def on_client_connect(self, conn: Connection) -> Optional[bool]: ...
+    def on_server_connect(self, conn: Connection) -> Optional[bool]: ...
+    def on_tls_established(self, conn: Connection) -> Optional[bool]: ...
 
 def notify(level: int, msg: str) -> None: ...
 
+class Store:
+    def get(self, key: str) -> Optional[str]: ...
+    def set(self, key: str, value: object) -> None: ...
+    def delete(self, key: str) -> None: ...
+
+store: Store
+
+def fetch(
+    url: str,
+    method: Optional[str] = None,
+    headers: Optional[Dict[str, str]] = None,
+    body: Union[str, bytes, None] = None,
+    timeout: Optional[int] = None,
+) -> Response: ...
+
 # Roxy discovers this global list at load
 Extensions: List[Extension]
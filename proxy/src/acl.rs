@@ -0,0 +1,231 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use tracing::{error, warn};
+
+use crate::flow::{
+    FlowStore,
+    error::{FlowError, FlowErrorKind},
+};
+
+#[derive(Debug)]
+pub struct IpRangeError(String);
+
+impl fmt::Display for IpRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for IpRangeError {}
+
+/// A single address or a CIDR block, e.g. `192.168.1.20`, `10.0.0.0/8` or
+/// `fd00::/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = IpRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| IpRangeError(format!("invalid address {addr}")))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| IpRangeError(format!("invalid prefix length {prefix}")))?,
+            None => max,
+        };
+        Ok(Self {
+            addr: addr.to_canonical(),
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Client addresses allowed to use the proxy, every client is when `allow` is
+/// empty. Rejected connections are recorded as error flows when
+/// `record_rejected` is set, they are always logged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientAclRules {
+    pub allow: Vec<IpRange>,
+    pub record_rejected: bool,
+}
+
+impl ClientAclRules {
+    pub fn new(allow: Vec<IpRange>) -> Self {
+        Self {
+            allow,
+            record_rejected: false,
+        }
+    }
+
+    pub fn with_record_rejected(mut self, record_rejected: bool) -> Self {
+        self.record_rejected = record_rejected;
+        self
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.allow.is_empty() || self.allow.iter().any(|r| r.contains(ip))
+    }
+}
+
+/// Shared handle to the active [`ClientAclRules`], updates apply to the next
+/// connection accepted.
+#[derive(Debug, Clone, Default)]
+pub struct ClientAcl {
+    rules: Arc<RwLock<ClientAclRules>>,
+}
+
+impl ClientAcl {
+    pub fn new(rules: ClientAclRules) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+        }
+    }
+
+    /// The active rules, `None` when the lock is poisoned.
+    pub fn rules(&self) -> Option<ClientAclRules> {
+        match self.rules.read() {
+            Ok(rules) => Some(rules.clone()),
+            Err(err) => {
+                error!("Client ACL lock poisoned {err}");
+                None
+            }
+        }
+    }
+
+    pub fn set_rules(&self, rules: ClientAclRules) {
+        match self.rules.write() {
+            Ok(mut guard) => *guard = rules,
+            Err(err) => error!("Client ACL lock poisoned {err}"),
+        }
+    }
+
+    /// Whether the client at `addr` may connect, rejections are logged and
+    /// recorded in `flow_store` when configured. Every client is rejected when
+    /// the rules can not be read.
+    pub(crate) async fn admit(&self, flow_store: &FlowStore, addr: SocketAddr) -> bool {
+        let Some(rules) = self.rules() else {
+            return false;
+        };
+        if rules.allows(addr.ip()) {
+            return true;
+        }
+        warn!("Rejected client {addr}, not in the allowed ranges");
+        if rules.record_rejected {
+            flow_store
+                .new_rejected_flow(
+                    addr,
+                    FlowError::new(
+                        FlowErrorKind::Rejected,
+                        format!("Client {} is not allowed", addr.ip()),
+                    ),
+                )
+                .await;
+        }
+        false
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(s: &str) -> IpRange {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(range("10.0.0.0/8").to_string(), "10.0.0.0/8");
+        assert_eq!(range(" 192.168.1.20 ").to_string(), "192.168.1.20/32");
+        assert_eq!(range("fd00::/8").to_string(), "fd00::/8");
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("10.0.0/8".parse::<IpRange>().is_err());
+        assert!("example.com".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn matches_addresses() {
+        let rules = ClientAclRules::new(vec![range("10.0.0.0/8"), range("fd00::/8")]);
+        assert!(rules.allows("10.1.2.3".parse().unwrap()));
+        assert!(rules.allows("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(rules.allows("fd12::1".parse().unwrap()));
+        assert!(!rules.allows("11.0.0.1".parse().unwrap()));
+        assert!(!rules.allows("fe80::1".parse().unwrap()));
+        assert!(ClientAclRules::default().allows("11.0.0.1".parse().unwrap()));
+        assert!(range("0.0.0.0/0").contains("8.8.8.8".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn records_rejected_clients() {
+        let flow_store = FlowStore::new();
+        let acl = ClientAcl::new(ClientAclRules::new(vec![range("127.0.0.1")]));
+        assert!(
+            acl.admit(&flow_store, "127.0.0.1:4000".parse().unwrap())
+                .await
+        );
+        assert!(
+            !acl.admit(&flow_store, "10.0.0.1:4000".parse().unwrap())
+                .await
+        );
+        assert!(flow_store.flows.is_empty());
+
+        acl.set_rules(acl.rules().unwrap().with_record_rejected(true));
+        assert!(
+            !acl.admit(&flow_store, "10.0.0.1:4000".parse().unwrap())
+                .await
+        );
+        assert_eq!(flow_store.flows.len(), 1);
+        let flow = flow_store.flows.iter().next().unwrap();
+        let flow = flow.value().read().await;
+        assert_eq!(
+            flow.error.as_ref().map(|e| e.message.as_str()),
+            Some("Client 10.0.0.1 is not allowed")
+        );
+    }
+}
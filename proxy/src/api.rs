@@ -0,0 +1,964 @@
+use std::{convert::Infallible, net::SocketAddr, path::Path, sync::Arc};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use http::{
+    HeaderMap, HeaderValue, Method, Request, Response, StatusCode,
+    header::{
+        AUTHORIZATION, CONNECTION, CONTENT_TYPE, HOST, ORIGIN, SEC_WEBSOCKET_ACCEPT,
+        SEC_WEBSOCKET_KEY, UPGRADE, WWW_AUTHENTICATE,
+    },
+};
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Incoming, service::service_fn, upgrade::Upgraded};
+use hyper_util::rt::TokioIo;
+use roxy_shared::http::HttpError;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tokio::{
+    net::TcpListener,
+    sync::{Notify, broadcast},
+    task::JoinHandle,
+};
+use tokio_tungstenite::{
+    WebSocketStream,
+    tungstenite::{Message, handshake::derive_accept_key, protocol::Role},
+};
+use tracing::{debug, error, trace, warn};
+
+use crate::{
+    filter::HostFilter,
+    flow::{
+        Flow, FlowStore, FlowUpdate, FlowUpdateKind, WsDirection,
+        filter::{CaptureProfile, FlowFilter},
+        id::FlowId,
+    },
+    hook::FlowSummary,
+    interceptor::{ScriptEngine, ScriptType},
+    supervisor::Supervisor,
+    ws::WsSessions,
+};
+
+/// What the control API acts on, the handles are shared with the running proxy.
+#[derive(Debug, Clone)]
+pub(crate) struct ApiState {
+    pub flow_store: FlowStore,
+    pub script_engine: ScriptEngine,
+    pub host_filter: HostFilter,
+    pub ws_sessions: WsSessions,
+    pub shutdown: Arc<Notify>,
+    pub supervisor: Supervisor,
+    /// Clients send it as `Authorization: Bearer <token>` on every request.
+    pub token: String,
+}
+
+/// A flow with its headers and bodies, bodies that are not UTF-8 are lossily
+/// converted.
+#[derive(Debug, Serialize)]
+struct FlowDetail {
+    #[serde(flatten)]
+    summary: FlowSummary,
+    request: Option<MessageDetail>,
+    response: Option<MessageDetail>,
+}
+
+#[derive(Debug, Serialize)]
+struct MessageDetail {
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ScriptEntry {
+    name: String,
+    #[serde(rename = "type")]
+    script_type: String,
+    enabled: bool,
+    decode_bodies: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Enabled {
+    enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Profile {
+    name: String,
+    filter: String,
+}
+
+/// A message to send over a live WebSocket connection, `binary` is base64.
+#[derive(Debug, Deserialize)]
+struct InjectedMessage {
+    from: MessageSender,
+    text: Option<String>,
+    binary: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MessageSender {
+    Client,
+    Server,
+}
+
+#[derive(Debug, Serialize)]
+struct ApiError {
+    error: String,
+}
+
+/// One message of the flow stream.
+#[derive(Debug, Serialize)]
+struct StreamEvent {
+    event: FlowUpdateKind,
+    flow: FlowSummary,
+}
+
+/// What a flow stream subscriber asked for.
+#[derive(Debug)]
+struct Subscription {
+    filter: Option<FlowFilter>,
+    /// Every kind when empty.
+    events: Vec<FlowUpdateKind>,
+}
+
+impl Subscription {
+    fn parse(query: Option<&str>) -> Result<Self, String> {
+        let filter = query_param(query, "filter")
+            .as_deref()
+            .map(FlowFilter::parse)
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        let events = match query_param(query, "events") {
+            Some(events) => events
+                .split(',')
+                .map(|event| match event.trim() {
+                    "created" => Ok(FlowUpdateKind::Created),
+                    "request_complete" => Ok(FlowUpdateKind::RequestComplete),
+                    "response_complete" => Ok(FlowUpdateKind::ResponseComplete),
+                    other => Err(format!("unknown event {other}")),
+                })
+                .collect::<Result<_, _>>()?,
+            None => vec![],
+        };
+        Ok(Self { filter, events })
+    }
+
+    fn wants(&self, kind: FlowUpdateKind, flow: &Flow) -> bool {
+        (self.events.is_empty() || self.events.contains(&kind))
+            && self.filter.as_ref().is_none_or(|f| f.matches(flow))
+    }
+}
+
+/// Serves the JSON control API on `listener`.
+pub(crate) async fn start_api(
+    state: ApiState,
+    listener: TcpListener,
+) -> Result<JoinHandle<()>, HttpError> {
+    let addr = listener.local_addr()?;
+    let handle = tokio::spawn(async move {
+        trace!("API listening on {addr}");
+        while let Ok((stream, _)) = listener.accept().await {
+            let state = state.clone();
+            tokio::task::spawn(async move {
+                let io = TokioIo::new(stream);
+                if let Err(err) = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(io, service_fn(|req| serve(state.clone(), addr, req)))
+                    .with_upgrades()
+                    .await
+                {
+                    error!("Failed to serve API connection: {:?}", err);
+                }
+            });
+        }
+        error!("API server finished");
+    });
+    Ok(handle)
+}
+
+async fn serve(
+    state: ApiState,
+    addr: SocketAddr,
+    req: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if let Err(response) = authorize(&state.token, addr, req.headers()) {
+        return Ok(response);
+    }
+    if req.method() == Method::GET && req.uri().path().trim_matches('/') == "flows/stream" {
+        return Ok(stream_flows(state, req));
+    }
+    let (parts, body) = req.into_parts();
+    let body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(err) => return Ok(error_response(StatusCode::BAD_REQUEST, err.to_string())),
+    };
+    Ok(handle(
+        &state,
+        &parts.method,
+        parts.uri.path(),
+        parts.uri.query(),
+        body,
+    )
+    .await)
+}
+
+/// Refuses requests without the token, and requests a browser could send on
+/// behalf of a page: any with an `Origin`, or naming another host than the
+/// loopback address the API is on, as a page rebinding its own name to
+/// `127.0.0.1` would.
+fn authorize(
+    token: &str,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+) -> Result<(), Response<Full<Bytes>>> {
+    if headers.contains_key(ORIGIN) {
+        return Err(error_response(
+            StatusCode::FORBIDDEN,
+            "cross-origin requests are refused".to_string(),
+        ));
+    }
+    let host = headers.get(HOST).and_then(|h| h.to_str().ok());
+    let is_local = host.is_some_and(|host| {
+        host == addr.to_string() || host.eq_ignore_ascii_case(&format!("localhost:{}", addr.port()))
+    });
+    if !is_local {
+        return Err(error_response(
+            StatusCode::FORBIDDEN,
+            format!("unexpected host {}", host.unwrap_or_default()),
+        ));
+    }
+    let presented = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.trim().split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+        .map(|(_, presented)| presented.trim());
+    match presented {
+        Some(presented) if bool::from(presented.as_bytes().ct_eq(token.as_bytes())) => Ok(()),
+        _ => {
+            let mut response = error_response(
+                StatusCode::UNAUTHORIZED,
+                "missing or wrong token".to_string(),
+            );
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            Err(response)
+        }
+    }
+}
+
+async fn handle(
+    state: &ApiState,
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    body: Bytes,
+) -> Response<Full<Bytes>> {
+    debug!("API {method} {path}");
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        (&Method::GET, ["flows"]) => list_flows(state, query_param(query, "filter")).await,
+        (&Method::GET, ["flows", id]) => match id.parse() {
+            Ok(id) => flow_detail(state, id).await,
+            Err(_) => error_response(StatusCode::BAD_REQUEST, format!("invalid flow id {id}")),
+        },
+        (&Method::POST, ["flows", id, "messages"]) => match id.parse() {
+            Ok(id) => inject_message(state, id, &body),
+            Err(_) => error_response(StatusCode::BAD_REQUEST, format!("invalid flow id {id}")),
+        },
+        (&Method::GET, ["scripts"]) => list_scripts(state).await,
+        (&Method::PUT, ["scripts", name]) => {
+            put_script(state, name, query_param(query, "type"), &body).await
+        }
+        (&Method::PATCH, ["scripts", name]) => match parse::<Enabled>(&body) {
+            Ok(enabled) => {
+                if state.script_engine.set_enabled(name, enabled.enabled).await {
+                    json_response(StatusCode::OK, &enabled)
+                } else {
+                    not_found(format!("no script {name}"))
+                }
+            }
+            Err(response) => response,
+        },
+        (&Method::DELETE, ["scripts", name]) => {
+            if state.script_engine.remove_script(name).await {
+                empty_response(StatusCode::NO_CONTENT)
+            } else {
+                not_found(format!("no script {name}"))
+            }
+        }
+        (&Method::GET, ["intercept"]) => json_response(
+            StatusCode::OK,
+            &Enabled {
+                enabled: !state.host_filter.paused(),
+            },
+        ),
+        (&Method::PUT, ["intercept"]) => match parse::<Enabled>(&body) {
+            Ok(enabled) => {
+                state.host_filter.set_paused(!enabled.enabled);
+                json_response(StatusCode::OK, &enabled)
+            }
+            Err(response) => response,
+        },
+        (&Method::GET, ["capture"]) => json_response(
+            StatusCode::OK,
+            &Enabled {
+                enabled: state.flow_store.capturing(),
+            },
+        ),
+        (&Method::PUT, ["capture"]) => match parse::<Enabled>(&body) {
+            Ok(enabled) => {
+                state.flow_store.set_capturing(enabled.enabled);
+                json_response(StatusCode::OK, &enabled)
+            }
+            Err(response) => response,
+        },
+        (&Method::GET, ["capture", "profile"]) => {
+            let profile = state.flow_store.capture_profile().map(|p| Profile {
+                filter: p.filter.to_string(),
+                name: p.name,
+            });
+            json_response(StatusCode::OK, &profile)
+        }
+        (&Method::PUT, ["capture", "profile"]) => match parse::<Option<Profile>>(&body) {
+            Ok(None) => {
+                state.flow_store.set_capture_profile(None);
+                json_response(StatusCode::OK, &None::<Profile>)
+            }
+            Ok(Some(profile)) => match FlowFilter::parse(&profile.filter) {
+                Ok(filter) => {
+                    state.flow_store.set_capture_profile(Some(CaptureProfile {
+                        name: profile.name.clone(),
+                        filter,
+                    }));
+                    json_response(StatusCode::OK, &profile)
+                }
+                Err(err) => error_response(StatusCode::BAD_REQUEST, err.to_string()),
+            },
+            Err(response) => response,
+        },
+        (&Method::GET, ["health"]) => {
+            let health = state.supervisor.health();
+            let status = if health.healthy() {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            json_response(status, &health)
+        }
+        (&Method::POST, ["shutdown"]) => {
+            debug!("Shutdown requested through the API");
+            state.shutdown.notify_one();
+            empty_response(StatusCode::ACCEPTED)
+        }
+        _ => not_found(format!("no route {method} {path}")),
+    }
+}
+
+/// Upgrades `req` to a WebSocket that is sent a [`StreamEvent`] for every
+/// flow update the subscription wants.
+fn stream_flows(state: ApiState, mut req: Request<Incoming>) -> Response<Full<Bytes>> {
+    let is_upgrade = req
+        .headers()
+        .get(UPGRADE)
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"websocket"));
+    let key = match req.headers().get(SEC_WEBSOCKET_KEY) {
+        Some(key) if is_upgrade => key.clone(),
+        _ => {
+            return error_response(
+                StatusCode::UPGRADE_REQUIRED,
+                "expected a WebSocket upgrade".to_string(),
+            );
+        }
+    };
+    let subscription = match Subscription::parse(req.uri().query()) {
+        Ok(subscription) => subscription,
+        Err(err) => return error_response(StatusCode::BAD_REQUEST, err),
+    };
+    // Subscribed before answering so no update is missed
+    let updates_rx = state.flow_store.subscribe_updates();
+    tokio::spawn(async move {
+        match hyper::upgrade::on(&mut req).await {
+            Ok(upgraded) => {
+                let ws =
+                    WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None)
+                        .await;
+                send_updates(state.flow_store, subscription, updates_rx, ws).await;
+            }
+            Err(err) => error!("API upgrade error: {err}"),
+        }
+    });
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(UPGRADE, "websocket")
+        .header(CONNECTION, "upgrade")
+        .header(SEC_WEBSOCKET_ACCEPT, derive_accept_key(key.as_bytes()))
+        .body(Full::new(Bytes::new()))
+        .unwrap_or_default()
+}
+
+async fn send_updates(
+    flow_store: FlowStore,
+    subscription: Subscription,
+    mut updates_rx: broadcast::Receiver<FlowUpdate>,
+    ws: WebSocketStream<TokioIo<Upgraded>>,
+) {
+    let (mut sink, mut stream) = ws.split();
+    loop {
+        let update = tokio::select! {
+            update = updates_rx.recv() => update,
+            message = stream.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        let update = match update {
+            Ok(update) => update,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Flow stream skipped {skipped} updates");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let Some(flow) = flow_store.get_flow_by_id(update.id).await else {
+            continue;
+        };
+        let event = {
+            let flow = flow.read().await;
+            if !subscription.wants(update.kind, &flow) {
+                continue;
+            }
+            StreamEvent {
+                event: update.kind,
+                flow: FlowSummary::new(&flow),
+            }
+        };
+        let json = match serde_json::to_string(&event) {
+            Ok(json) => json,
+            Err(err) => {
+                error!("Failed to encode flow update {err}");
+                continue;
+            }
+        };
+        if sink.send(Message::text(json)).await.is_err() {
+            break;
+        }
+    }
+    debug!("Flow stream closed");
+}
+
+async fn list_flows(state: &ApiState, filter: Option<String>) -> Response<Full<Bytes>> {
+    let filter = match filter.as_deref().map(FlowFilter::parse).transpose() {
+        Ok(filter) => filter,
+        Err(err) => return error_response(StatusCode::BAD_REQUEST, err.to_string()),
+    };
+    let mut summaries = vec![];
+    for flow in state.flow_store.ordered_flows().await {
+        let flow = flow.read().await;
+        if filter.as_ref().is_none_or(|f| f.matches(&flow)) {
+            summaries.push(FlowSummary::new(&flow));
+        }
+    }
+    json_response(StatusCode::OK, &summaries)
+}
+
+async fn flow_detail(state: &ApiState, id: FlowId) -> Response<Full<Bytes>> {
+    let Some(flow) = state.flow_store.get_flow_by_id(id).await else {
+        return not_found(format!("no flow {id}"));
+    };
+    let flow = flow.read().await;
+    json_response(StatusCode::OK, &detail(&flow).await)
+}
+
+async fn detail(flow: &Flow) -> FlowDetail {
+    let request = match &flow.request {
+        Some(request) => Some(MessageDetail {
+            headers: headers(&request.headers),
+            body: text(request.load_body().await),
+        }),
+        None => None,
+    };
+    let response = match &flow.response {
+        Some(response) => Some(MessageDetail {
+            headers: headers(&response.headers),
+            body: text(response.load_body().await),
+        }),
+        None => None,
+    };
+    FlowDetail {
+        summary: FlowSummary::new(flow),
+        request,
+        response,
+    }
+}
+
+fn headers(headers: &http::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(k, v)| {
+            (
+                k.to_string(),
+                String::from_utf8_lossy(v.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+fn text(body: std::io::Result<Bytes>) -> String {
+    match body {
+        Ok(body) => String::from_utf8_lossy(&body).into_owned(),
+        Err(err) => {
+            error!("Failed to load body {err}");
+            String::new()
+        }
+    }
+}
+
+/// Sends a message over the live WebSocket connection of flow `id`.
+fn inject_message(state: &ApiState, id: FlowId, body: &[u8]) -> Response<Full<Bytes>> {
+    let injected = match parse::<InjectedMessage>(body) {
+        Ok(injected) => injected,
+        Err(response) => return response,
+    };
+    let message = match (injected.text, injected.binary) {
+        (Some(text), None) => Message::text(text),
+        (None, Some(binary)) => match STANDARD.decode(binary) {
+            Ok(data) => Message::binary(data),
+            Err(err) => return error_response(StatusCode::BAD_REQUEST, err.to_string()),
+        },
+        _ => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "expected one of text or binary".to_string(),
+            );
+        }
+    };
+    let from = match injected.from {
+        MessageSender::Client => WsDirection::Client,
+        MessageSender::Server => WsDirection::Server,
+    };
+    if state.ws_sessions.inject(id, from, message) {
+        empty_response(StatusCode::ACCEPTED)
+    } else {
+        not_found(format!("no open WebSocket connection for flow {id}"))
+    }
+}
+
+async fn list_scripts(state: &ApiState) -> Response<Full<Bytes>> {
+    let scripts: Vec<ScriptEntry> = state
+        .script_engine
+        .scripts()
+        .await
+        .into_iter()
+        .map(|s| ScriptEntry {
+            name: s.name,
+            script_type: s.script_type.to_string(),
+            enabled: s.enabled,
+            decode_bodies: s.decode_bodies,
+            error: s.error.map(|e| e.message),
+        })
+        .collect();
+    json_response(StatusCode::OK, &scripts)
+}
+
+/// Loads the body as the script `name`, the type is taken from `script_type`,
+/// then the extension of `name`, falling back to Lua like configured scripts.
+async fn put_script(
+    state: &ApiState,
+    name: &str,
+    script_type: Option<String>,
+    body: &[u8],
+) -> Response<Full<Bytes>> {
+    let script_type = match script_type {
+        Some(ext) => match ScriptType::from_path(Path::new(&format!("script.{ext}"))) {
+            Some(script_type) => script_type,
+            None => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    format!("unknown script type {ext}"),
+                );
+            }
+        },
+        None => ScriptType::from_path(Path::new(name)).unwrap_or(ScriptType::Lua),
+    };
+    let Ok(source) = std::str::from_utf8(body) else {
+        return error_response(StatusCode::BAD_REQUEST, "script is not UTF-8".to_string());
+    };
+    match state
+        .script_engine
+        .add_script(name, source, script_type)
+        .await
+    {
+        Ok(()) => empty_response(StatusCode::NO_CONTENT),
+        Err(err) => error_response(StatusCode::UNPROCESSABLE_ENTITY, err.to_string()),
+    }
+}
+
+fn query_param(query: Option<&str>, name: &str) -> Option<String> {
+    url::form_urlencoded::parse(query?.as_bytes())
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.into_owned())
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(body: &[u8]) -> Result<T, Response<Full<Bytes>>> {
+    serde_json::from_slice(body).map_err(|e| error_response(StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+fn json_response(status: StatusCode, value: &impl Serialize) -> Response<Full<Bytes>> {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .unwrap_or_default(),
+        Err(err) => {
+            error!("Failed to encode API response {err}");
+            empty_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn error_response(status: StatusCode, error: String) -> Response<Full<Bytes>> {
+    json_response(status, &ApiError { error })
+}
+
+fn not_found(error: String) -> Response<Full<Bytes>> {
+    error_response(StatusCode::NOT_FOUND, error)
+}
+
+fn empty_response(status: StatusCode) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .body(Full::new(Bytes::new()))
+        .unwrap_or_default()
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use roxy_shared::uri::RUri;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    use crate::flow::{FlowConnection, FlowEvent, InterceptedRequest, InterceptedResponse};
+    use crate::metrics::Metrics;
+
+    use super::*;
+
+    const TOKEN: &str = "c0ffee";
+
+    async fn state() -> ApiState {
+        let flow_store = FlowStore::new();
+        for (id, path, status) in [
+            (FlowId(1), "/ok", StatusCode::OK),
+            (FlowId(2), "/missing", StatusCode::NOT_FOUND),
+        ] {
+            let request = InterceptedRequest {
+                uri: RUri::from_str(&format!("https://example.com{path}")).unwrap(),
+                ..Default::default()
+            };
+            let mut flow = Flow::new(
+                id,
+                FlowConnection {
+                    addr: ([127, 0, 0, 1], 0).into(),
+                },
+                Some(request),
+            );
+            flow.response = Some(InterceptedResponse {
+                status,
+                body: Bytes::from_static(b"hello"),
+                ..Default::default()
+            });
+            flow_store.insert_flows(vec![flow]).await;
+        }
+        ApiState {
+            flow_store,
+            script_engine: ScriptEngine::new(),
+            host_filter: HostFilter::default(),
+            ws_sessions: WsSessions::default(),
+            shutdown: Arc::new(Notify::new()),
+            supervisor: Supervisor::new(ScriptEngine::new(), Metrics::new()),
+            token: TOKEN.to_string(),
+        }
+    }
+
+    async fn call(
+        state: &ApiState,
+        method: Method,
+        path: &str,
+        query: Option<&str>,
+        body: &'static [u8],
+    ) -> (StatusCode, serde_json::Value) {
+        let response = handle(state, &method, path, query, Bytes::from_static(body)).await;
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json = if body.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_slice(&body).unwrap()
+        };
+        (status, json)
+    }
+
+    #[tokio::test]
+    async fn lists_and_fetches_flows() {
+        let state = state().await;
+        let (status, flows) = call(&state, Method::GET, "/flows", None, b"").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(flows.as_array().unwrap().len(), 2);
+
+        let (_, flows) = call(&state, Method::GET, "/flows", Some("filter=~c%20404"), b"").await;
+        let flows = flows.as_array().unwrap();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0]["url"], "https://example.com/missing");
+
+        let id = flows[0]["id"].as_i64().unwrap();
+        let (status, flow) = call(&state, Method::GET, &format!("/flows/{id}"), None, b"").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(flow["status"], 404);
+        assert_eq!(flow["response"]["body"], "hello");
+
+        let (status, _) = call(&state, Method::GET, "/flows/-1", None, b"").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&state, Method::GET, "/flows", Some("filter=~c"), b"").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn manages_scripts() {
+        let state = state().await;
+        let (status, _) = call(&state, Method::PUT, "/scripts/noop.lua", None, b"").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(&state, Method::PUT, "/scripts/x", Some("type=rb"), b"").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = call(
+            &state,
+            Method::PATCH,
+            "/scripts/noop.lua",
+            None,
+            b"{\"enabled\":false}",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, scripts) = call(&state, Method::GET, "/scripts", None, b"").await;
+        assert_eq!(
+            scripts,
+            serde_json::json!([{
+                "name": "noop.lua",
+                "type": "lua",
+                "enabled": false,
+                "decode_bodies": true
+            }])
+        );
+
+        let (status, _) = call(&state, Method::DELETE, "/scripts/noop.lua", None, b"").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(&state, Method::DELETE, "/scripts/noop.lua", None, b"").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn toggles_interception_and_shuts_down() {
+        let state = state().await;
+        let (status, _) = call(
+            &state,
+            Method::PUT,
+            "/intercept",
+            None,
+            b"{\"enabled\":false}",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!state.host_filter.should_intercept("example.com"));
+        let (_, intercept) = call(&state, Method::GET, "/intercept", None, b"").await;
+        assert_eq!(intercept["enabled"], false);
+
+        let (status, health) = call(&state, Method::GET, "/health", None, b"").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(health["tasks"], serde_json::json!([]));
+
+        let (status, _) = call(&state, Method::POST, "/shutdown", None, b"").await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        state.shutdown.notified().await;
+    }
+
+    #[tokio::test]
+    async fn injects_ws_messages() {
+        let state = state().await;
+        let body = b"{\"from\":\"client\",\"text\":\"hi\"}";
+        let (status, _) = call(&state, Method::POST, "/flows/3/messages", None, body).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (mut to_server, mut to_client) = state.ws_sessions.register(3);
+        let (status, _) = call(&state, Method::POST, "/flows/3/messages", None, body).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(to_server.recv().await.unwrap(), Message::text("hi"));
+
+        let body = b"{\"from\":\"server\",\"binary\":\"/w==\"}";
+        let (status, _) = call(&state, Method::POST, "/flows/3/messages", None, body).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(to_client.recv().await.unwrap(), Message::binary(vec![0xff]));
+
+        let body = b"{\"from\":\"server\"}";
+        let (status, _) = call(&state, Method::POST, "/flows/3/messages", None, body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn pauses_capture() {
+        let state = state().await;
+        let connection = FlowConnection {
+            addr: ([127, 0, 0, 1], 0).into(),
+        };
+        let (status, _) = call(
+            &state,
+            Method::PUT,
+            "/capture",
+            None,
+            b"{\"enabled\":false}",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, capture) = call(&state, Method::GET, "/capture", None, b"").await;
+        assert_eq!(capture["enabled"], false);
+        let id = state.flow_store.new_ws_flow(connection).await;
+        assert!(state.flow_store.get_flow_by_id(id).await.is_none());
+
+        call(&state, Method::PUT, "/capture", None, b"{\"enabled\":true}").await;
+        let id = state.flow_store.new_ws_flow(connection).await;
+        assert!(state.flow_store.get_flow_by_id(id).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn sets_capture_profile() {
+        let state = state().await;
+        let (status, _) = call(
+            &state,
+            Method::PUT,
+            "/capture/profile",
+            None,
+            b"{\"name\":\"api\",\"filter\":\"~d (\"}",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = call(
+            &state,
+            Method::PUT,
+            "/capture/profile",
+            None,
+            b"{\"name\":\"api\",\"filter\":\"~d api.myapp.com\"}",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, profile) = call(&state, Method::GET, "/capture/profile", None, b"").await;
+        assert_eq!(profile["name"], "api");
+        assert_eq!(profile["filter"], "~d api.myapp.com");
+
+        call(&state, Method::PUT, "/capture/profile", None, b"null").await;
+        assert!(state.flow_store.capture_profile().is_none());
+    }
+
+    async fn next_event(ws: &mut WebSocketStream<TcpStream>) -> serde_json::Value {
+        let message = ws.next().await.unwrap().unwrap();
+        serde_json::from_str(&message.into_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn streams_flow_updates() {
+        let state = state().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _handle = start_api(state.clone(), listener).await.unwrap();
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut request = format!(
+            "ws://{addr}/flows/stream?filter=~d%20example.org&events=created,response_complete"
+        )
+        .into_client_request()
+        .unwrap();
+        request.headers_mut().insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {TOKEN}")).unwrap(),
+        );
+        let (mut ws, _) = tokio_tungstenite::client_async(request, stream)
+            .await
+            .unwrap();
+
+        for host in ["example.com", "example.org"] {
+            let request = InterceptedRequest {
+                uri: RUri::from_str(&format!("https://{host}/")).unwrap(),
+                ..Default::default()
+            };
+            let connection = FlowConnection {
+                addr: ([127, 0, 0, 1], 0).into(),
+            };
+            let id = state.flow_store.new_flow(connection, request).await;
+            state.flow_store.post_event(id, FlowEvent::RequestComplete);
+            state
+                .flow_store
+                .post_event(id, FlowEvent::Response(InterceptedResponse::default()));
+        }
+
+        let created = next_event(&mut ws).await;
+        assert_eq!(created["event"], "created");
+        assert_eq!(created["flow"]["url"], "https://example.org/");
+        let completed = next_event(&mut ws).await;
+        assert_eq!(completed["event"], "response_complete");
+        assert_eq!(completed["flow"]["id"], created["flow"]["id"]);
+        assert_eq!(completed["flow"]["status"], 200);
+
+        assert!(Subscription::parse(Some("events=created,sent")).is_err());
+    }
+
+    /// The status line the API answers `request` with.
+    async fn status_line(addr: SocketAddr, request: String) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response);
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn refuses_unauthorized_clients() {
+        let state = state().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _handle = start_api(state.clone(), listener).await.unwrap();
+        let request = |host: &str, extra: &str| {
+            format!("POST /shutdown HTTP/1.1\r\nHost: {host}\r\n{extra}Connection: close\r\n\r\n")
+        };
+        let bearer = format!("Authorization: Bearer {TOKEN}\r\n");
+        let local = addr.to_string();
+
+        let status = status_line(addr, request(&local, "")).await;
+        assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+        let wrong = "Authorization: Bearer c0ffef\r\n";
+        let status = status_line(addr, request(&local, wrong)).await;
+        assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+        let status = status_line(addr, request("evil.example.com", &bearer)).await;
+        assert_eq!(status, "HTTP/1.1 403 Forbidden");
+        let origin = format!("{bearer}Origin: http://evil.example.com\r\n");
+        let status = status_line(addr, request(&local, &origin)).await;
+        assert_eq!(status, "HTTP/1.1 403 Forbidden");
+
+        let localhost = format!("localhost:{}", addr.port());
+        let status = status_line(addr, request(&localhost, &bearer)).await;
+        assert_eq!(status, "HTTP/1.1 202 Accepted");
+        state.shutdown.notified().await;
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::{Arc, RwLock};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use http::{HeaderMap, HeaderValue, header::PROXY_AUTHORIZATION};
+use subtle::ConstantTimeEq;
+use tracing::error;
+
+const REALM: &str = "roxy";
+
+/// A client allowed through the proxy, sent in `Proxy-Authorization`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyCredential {
+    Basic { user: String, password: String },
+    Bearer(String),
+}
+
+impl ProxyCredential {
+    fn scheme(&self) -> &'static str {
+        match self {
+            ProxyCredential::Basic { .. } => "Basic",
+            ProxyCredential::Bearer(_) => "Bearer",
+        }
+    }
+
+    fn matches(&self, scheme: &str, value: &str) -> bool {
+        if !scheme.eq_ignore_ascii_case(self.scheme()) {
+            return false;
+        }
+        match self {
+            ProxyCredential::Basic { user, password } => STANDARD
+                .decode(value)
+                .is_ok_and(|decoded| equal(&decoded, format!("{user}:{password}").as_bytes())),
+            ProxyCredential::Bearer(token) => equal(value.as_bytes(), token.as_bytes()),
+        }
+    }
+
+    fn matches_user(&self, name: &str, secret: &str) -> bool {
+        match self {
+            // Both are compared so the time taken does not tell which was wrong
+            ProxyCredential::Basic { user, password } => {
+                equal(user.as_bytes(), name.as_bytes())
+                    & equal(password.as_bytes(), secret.as_bytes())
+            }
+            ProxyCredential::Bearer(_) => false,
+        }
+    }
+}
+
+/// Compares secrets in a time that does not depend on where they differ.
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    bool::from(a.ct_eq(b))
+}
+
+/// Credentials clients must present, the proxy is open when there are none.
+/// Clones share the credentials so they can be changed while running.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyAuth {
+    credentials: Arc<RwLock<Vec<ProxyCredential>>>,
+}
+
+impl ProxyAuth {
+    pub fn new(credentials: Vec<ProxyCredential>) -> Self {
+        Self {
+            credentials: Arc::new(RwLock::new(credentials)),
+        }
+    }
+
+    pub fn set_credentials(&self, credentials: Vec<ProxyCredential>) {
+        match self.credentials.write() {
+            Ok(mut guard) => *guard = credentials,
+            Err(err) => error!("Proxy auth lock poisoned {err}"),
+        }
+    }
+
+    /// Whether a request with `headers` may use the proxy.
+    pub fn verify(&self, headers: &HeaderMap) -> bool {
+        let credentials = match self.credentials.read() {
+            Ok(credentials) => credentials,
+            Err(err) => {
+                error!("Proxy auth lock poisoned {err}");
+                return false;
+            }
+        };
+        if credentials.is_empty() {
+            return true;
+        }
+        let Some((scheme, value)) = headers
+            .get(PROXY_AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.trim().split_once(' '))
+        else {
+            return false;
+        };
+        let value = value.trim();
+        credentials.iter().any(|c| c.matches(scheme, value))
+    }
+
+    /// Whether clients must present credentials at all.
+    pub fn is_required(&self) -> bool {
+        match self.credentials.read() {
+            Ok(credentials) => !credentials.is_empty(),
+            Err(err) => {
+                error!("Proxy auth lock poisoned {err}");
+                true
+            }
+        }
+    }
+
+    /// Whether a SOCKS5 client with `user` and `password` may use the proxy,
+    /// only basic credentials can be presented that way.
+    pub fn verify_user(&self, user: &str, password: &str) -> bool {
+        let credentials = match self.credentials.read() {
+            Ok(credentials) => credentials,
+            Err(err) => {
+                error!("Proxy auth lock poisoned {err}");
+                return false;
+            }
+        };
+        credentials.is_empty() || credentials.iter().any(|c| c.matches_user(user, password))
+    }
+
+    /// `Proxy-Authenticate` values for a rejected client, one per scheme in use.
+    pub(crate) fn challenges(&self) -> Vec<HeaderValue> {
+        let credentials = match self.credentials.read() {
+            Ok(credentials) => credentials,
+            Err(err) => {
+                error!("Proxy auth lock poisoned {err}");
+                return vec![];
+            }
+        };
+        let mut schemes: Vec<_> = credentials.iter().map(ProxyCredential::scheme).collect();
+        schemes.sort_unstable();
+        schemes.dedup();
+        schemes
+            .into_iter()
+            .filter_map(|scheme| HeaderValue::from_str(&format!("{scheme} realm=\"{REALM}\"")).ok())
+            .collect()
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(PROXY_AUTHORIZATION, authorization.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn open_without_credentials() {
+        assert!(ProxyAuth::default().verify(&HeaderMap::new()));
+        assert!(ProxyAuth::default().challenges().is_empty());
+    }
+
+    #[test]
+    fn verifies_basic_and_bearer() {
+        let auth = ProxyAuth::new(vec![
+            ProxyCredential::Basic {
+                user: "alice".into(),
+                password: "secret".into(),
+            },
+            ProxyCredential::Bearer("token".into()),
+        ]);
+        let basic = STANDARD.encode("alice:secret");
+        assert!(auth.verify(&headers(&format!("Basic {basic}"))));
+        assert!(auth.verify(&headers(&format!("basic  {basic}"))));
+        assert!(auth.verify(&headers("Bearer token")));
+
+        let wrong = STANDARD.encode("alice:guess");
+        assert!(!auth.verify(&headers(&format!("Basic {wrong}"))));
+        assert!(!auth.verify(&headers(&format!("Bearer {basic}"))));
+        assert!(!auth.verify(&headers("Bearer")));
+        assert!(!auth.verify(&headers("Bearer toke")));
+        assert!(!auth.verify(&HeaderMap::new()));
+
+        assert_eq!(
+            auth.challenges(),
+            vec![
+                HeaderValue::from_static("Basic realm=\"roxy\""),
+                HeaderValue::from_static("Bearer realm=\"roxy\"")
+            ]
+        );
+
+        auth.set_credentials(vec![]);
+        assert!(auth.verify(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn verifies_socks_users() {
+        let auth = ProxyAuth::default();
+        assert!(!auth.is_required());
+        assert!(auth.verify_user("", ""));
+
+        auth.set_credentials(vec![
+            ProxyCredential::Basic {
+                user: "alice".into(),
+                password: "secret".into(),
+            },
+            ProxyCredential::Bearer("token".into()),
+        ]);
+        assert!(auth.is_required());
+        assert!(auth.verify_user("alice", "secret"));
+        assert!(!auth.verify_user("alice", "guess"));
+        assert!(!auth.verify_user("alice", "secre"));
+        assert!(!auth.verify_user("bob", "secret"));
+        assert!(!auth.verify_user("", "token"));
+    }
+}
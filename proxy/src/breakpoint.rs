@@ -0,0 +1,325 @@
+use std::sync::{Arc, RwLock};
+
+use dashmap::DashMap;
+use http::Method;
+use roxy_shared::upstream::host_matches;
+use tokio::sync::{oneshot, watch};
+use tracing::{debug, error, warn};
+
+use crate::flow::{
+    FlowEvent, FlowStore, InterceptedRequest,
+    error::{FlowError, FlowErrorKind},
+    id::FlowId,
+};
+
+/// Matches requests to pause, unset fields match anything.
+///
+/// `host` uses the upstream rule pattern syntax, `path` is a prefix match.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BreakpointRule {
+    pub method: Option<Method>,
+    pub host: Option<String>,
+    pub path: Option<String>,
+}
+
+impl BreakpointRule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_method(mut self, method: Method) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn matches(&self, request: &InterceptedRequest) -> bool {
+        self.method.as_ref().is_none_or(|m| *m == request.method)
+            && self
+                .host
+                .as_deref()
+                .is_none_or(|h| host_matches(h, request.uri.host()))
+            && self
+                .path
+                .as_deref()
+                .is_none_or(|p| request.uri.path().starts_with(p))
+    }
+}
+
+#[derive(Debug)]
+pub enum BreakpointDecision {
+    /// Forward the request, which may have been modified while paused.
+    Resume(InterceptedRequest),
+    Drop,
+}
+
+#[derive(Debug)]
+struct PendingFlow {
+    request: InterceptedRequest,
+    decision_tx: oneshot::Sender<BreakpointDecision>,
+}
+
+/// Breakpoint rules and the flows currently paused on them.
+///
+/// The proxy side pauses a flow until the UI side resumes or drops it, clones
+/// share both the rules and the pending flows.
+#[derive(Debug, Clone)]
+pub struct Breakpoints {
+    rules: Arc<RwLock<Vec<BreakpointRule>>>,
+    pending: Arc<DashMap<FlowId, PendingFlow>>,
+    notifier: watch::Sender<()>,
+}
+
+impl Default for Breakpoints {
+    fn default() -> Self {
+        Self::new(vec![])
+    }
+}
+
+impl Breakpoints {
+    pub fn new(rules: Vec<BreakpointRule>) -> Self {
+        let (notifier, _) = watch::channel(());
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+            pending: Arc::new(DashMap::new()),
+            notifier,
+        }
+    }
+
+    pub fn rules(&self) -> Vec<BreakpointRule> {
+        match self.rules.read() {
+            Ok(rules) => rules.clone(),
+            Err(err) => {
+                error!("Breakpoint lock poisoned {err}");
+                vec![]
+            }
+        }
+    }
+
+    pub fn set_rules(&self, rules: Vec<BreakpointRule>) {
+        match self.rules.write() {
+            Ok(mut guard) => *guard = rules,
+            Err(err) => error!("Breakpoint lock poisoned {err}"),
+        }
+    }
+
+    pub fn matches(&self, request: &InterceptedRequest) -> bool {
+        match self.rules.read() {
+            Ok(rules) => rules.iter().any(|r| r.matches(request)),
+            Err(_) => false,
+        }
+    }
+
+    /// Notified whenever a flow is paused or released.
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.notifier.subscribe()
+    }
+
+    /// Paused flows, oldest first.
+    pub fn pending(&self) -> Vec<(FlowId, InterceptedRequest)> {
+        let mut pending: Vec<_> = self
+            .pending
+            .iter()
+            .map(|p| (*p.key(), p.value().request.clone()))
+            .collect();
+        pending.sort_by_key(|(id, _)| *id);
+        pending
+    }
+
+    /// Forwards paused flow `flow_id`, replacing its request when `request` is set.
+    pub fn resume(&self, flow_id: FlowId, request: Option<InterceptedRequest>) -> bool {
+        let Some((_, pending)) = self.pending.remove(&flow_id) else {
+            return false;
+        };
+        let request = request.unwrap_or(pending.request);
+        self.decide(
+            flow_id,
+            pending.decision_tx,
+            BreakpointDecision::Resume(request),
+        )
+    }
+
+    /// Drops paused flow `flow_id`, the client receives a bad gateway response.
+    pub fn drop_flow(&self, flow_id: FlowId) -> bool {
+        let Some((_, pending)) = self.pending.remove(&flow_id) else {
+            return false;
+        };
+        self.decide(flow_id, pending.decision_tx, BreakpointDecision::Drop)
+    }
+
+    fn decide(
+        &self,
+        flow_id: FlowId,
+        decision_tx: oneshot::Sender<BreakpointDecision>,
+        decision: BreakpointDecision,
+    ) -> bool {
+        self.notify();
+        if decision_tx.send(decision).is_err() {
+            warn!("Breakpoint flow {flow_id} already closed");
+            return false;
+        }
+        true
+    }
+
+    fn notify(&self) {
+        self.notifier.send_replace(());
+    }
+
+    /// Waits for a decision on `request` if any rule matches it.
+    ///
+    /// A modified request replaces the flow's recorded request. Returns `false`
+    /// when the flow was dropped.
+    pub(crate) async fn hold(
+        &self,
+        flow_store: &FlowStore,
+        flow_id: FlowId,
+        request: &mut InterceptedRequest,
+    ) -> bool {
+        if !self.matches(request) {
+            return true;
+        }
+        debug!("Breakpoint hit {flow_id} {}", request.line_pretty());
+        let (decision_tx, decision_rx) = oneshot::channel();
+        self.pending.insert(
+            flow_id,
+            PendingFlow {
+                request: request.clone(),
+                decision_tx,
+            },
+        );
+        let _guard = PendingGuard {
+            breakpoints: self,
+            flow_id,
+        };
+        self.notify();
+
+        match decision_rx.await {
+            Ok(BreakpointDecision::Resume(resumed)) => {
+                if resumed != *request {
+                    *request = resumed;
+                    flow_store.post_event(flow_id, FlowEvent::Request(request.clone()));
+                }
+                true
+            }
+            Ok(BreakpointDecision::Drop) => {
+                flow_store.post_event(
+                    flow_id,
+                    FlowEvent::Error(FlowError::new(
+                        FlowErrorKind::Rejected,
+                        "Dropped at breakpoint",
+                    )),
+                );
+                false
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+/// Releases a pending flow when the connection holding it goes away.
+struct PendingGuard<'a> {
+    breakpoints: &'a Breakpoints,
+    flow_id: FlowId,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if self.breakpoints.pending.remove(&self.flow_id).is_some() {
+            self.breakpoints.notify();
+        }
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::FlowConnection;
+
+    fn request(method: Method, uri: &str) -> InterceptedRequest {
+        InterceptedRequest {
+            method,
+            uri: uri.parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rule_matching() {
+        let rule = BreakpointRule::new()
+            .with_method(Method::POST)
+            .with_host("*.example.com")
+            .with_path("/api");
+        assert!(rule.matches(&request(Method::POST, "https://www.example.com/api/login")));
+        assert!(!rule.matches(&request(Method::GET, "https://www.example.com/api/login")));
+        assert!(!rule.matches(&request(Method::POST, "https://example.com/api")));
+        assert!(!rule.matches(&request(Method::POST, "https://www.example.com/static")));
+        assert!(BreakpointRule::new().matches(&request(Method::GET, "http://a.b/")));
+    }
+
+    async fn new_flow(flow_store: &FlowStore, request: &InterceptedRequest) -> FlowId {
+        let connection = FlowConnection {
+            addr: "127.0.0.1:1234".parse().unwrap(),
+        };
+        flow_store.new_flow(connection, request.clone()).await
+    }
+
+    #[tokio::test]
+    async fn hold_until_resumed() {
+        let flow_store = FlowStore::new();
+        let breakpoints = Breakpoints::new(vec![BreakpointRule::new().with_path("/pause")]);
+        let mut passed = request(Method::GET, "http://example.com/other");
+        let passed_id = new_flow(&flow_store, &passed).await;
+        assert!(breakpoints.hold(&flow_store, passed_id, &mut passed).await);
+
+        let mut paused = request(Method::GET, "http://example.com/pause");
+        let paused_id = new_flow(&flow_store, &paused).await;
+        let held = breakpoints.clone();
+        let held_store = flow_store.clone();
+        let handle = tokio::spawn(async move {
+            let resumed = held.hold(&held_store, paused_id, &mut paused).await;
+            (resumed, paused)
+        });
+        let mut rx = breakpoints.subscribe();
+        while breakpoints.pending().is_empty() {
+            rx.changed().await.unwrap();
+        }
+        let (id, mut edited) = breakpoints.pending().remove(0);
+        assert_eq!(id, paused_id);
+        edited.method = Method::PUT;
+        assert!(breakpoints.resume(id, Some(edited)));
+
+        let (resumed, paused) = handle.await.unwrap();
+        assert!(resumed);
+        assert_eq!(paused.method, Method::PUT);
+        assert!(breakpoints.pending().is_empty());
+    }
+
+    #[tokio::test]
+    async fn hold_until_dropped() {
+        let flow_store = FlowStore::new();
+        let breakpoints = Breakpoints::new(vec![BreakpointRule::new()]);
+        let mut paused = request(Method::GET, "http://example.com/");
+        let paused_id = new_flow(&flow_store, &paused).await;
+        let held = breakpoints.clone();
+        let held_store = flow_store.clone();
+        let handle =
+            tokio::spawn(async move { held.hold(&held_store, paused_id, &mut paused).await });
+        let mut rx = breakpoints.subscribe();
+        while breakpoints.pending().is_empty() {
+            rx.changed().await.unwrap();
+        }
+        assert!(breakpoints.drop_flow(paused_id));
+        assert!(!handle.await.unwrap());
+        assert!(!breakpoints.drop_flow(paused_id));
+    }
+}
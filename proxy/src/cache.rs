@@ -0,0 +1,404 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use cow_utils::CowUtils;
+use http::header::VARY;
+use roxy_shared::{
+    content::{ContentType, content_type},
+    http::HttpError,
+};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+use time::OffsetDateTime;
+use tracing::{debug, error, warn};
+
+use crate::flow::{
+    InterceptedRequest, InterceptedResponse,
+    session::{ResponseRecord, SessionError},
+};
+
+/// How the proxy uses the response cache.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Display, EnumString,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum CacheMode {
+    #[default]
+    Off,
+    /// Responses are stored and served when the upstream is unreachable.
+    Fallback,
+    /// Responses are only served from the cache, the upstream is never contacted.
+    Offline,
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Session(SessionError),
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<std::io::Error> for CacheError {
+    fn from(value: std::io::Error) -> Self {
+        CacheError::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for CacheError {
+    fn from(value: serde_json::Error) -> Self {
+        CacheError::Json(value)
+    }
+}
+
+impl From<SessionError> for CacheError {
+    fn from(value: SessionError) -> Self {
+        CacheError::Session(value)
+    }
+}
+
+/// Every cached variant of one method and url.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    method: String,
+    uri: String,
+    entries: Vec<CacheEntry>,
+}
+
+/// A response along with the request header values its `Vary` header names.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    vary: Vec<(String, Vec<Vec<u8>>)>,
+    response: ResponseRecord,
+}
+
+/// Responses stored on disk, one file per method and url, keyed further by
+/// the request headers listed in each response's `Vary` header.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    mode: Arc<RwLock<CacheMode>>,
+}
+
+impl ResponseCache {
+    pub fn new(dir: impl Into<PathBuf>, mode: CacheMode) -> Self {
+        Self {
+            dir: dir.into(),
+            mode: Arc::new(RwLock::new(mode)),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn mode(&self) -> CacheMode {
+        match self.mode.read() {
+            Ok(mode) => *mode,
+            Err(err) => {
+                error!("Cache lock poisoned {err}");
+                CacheMode::Off
+            }
+        }
+    }
+
+    pub fn set_mode(&self, mode: CacheMode) {
+        match self.mode.write() {
+            Ok(mut guard) => *guard = mode,
+            Err(err) => error!("Cache lock poisoned {err}"),
+        }
+    }
+
+    /// Stores `response` for `request`, replacing a response with the same vary values.
+    ///
+    /// Responses with `Vary: *` can never match a later request and are skipped.
+    pub async fn store(
+        &self,
+        request: &InterceptedRequest,
+        response: &InterceptedResponse,
+    ) -> Result<(), CacheError> {
+        // An event stream is never complete when it is stored
+        if content_type(&response.headers) == Some(ContentType::EventStream) {
+            return Ok(());
+        }
+        let Some(vary) = vary_names(response) else {
+            return Ok(());
+        };
+        let vary = vary_values(request, vary);
+
+        let path = self.path(request);
+        let mut file = match self.read(&path).await? {
+            Some(file)
+                if file.method == request.method.as_str()
+                    && file.uri == request.uri.to_string() =>
+            {
+                file
+            }
+            _ => CacheFile {
+                method: request.method.to_string(),
+                uri: request.uri.to_string(),
+                entries: vec![],
+            },
+        };
+        file.entries.retain(|e| e.vary != vary);
+        file.entries.push(CacheEntry {
+            vary,
+            response: ResponseRecord::from(response),
+        });
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(&file)?).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        debug!("Cached {} {}", request.method, request.uri);
+        Ok(())
+    }
+
+    /// The cached response for `request`, if one was stored with matching vary values.
+    pub async fn lookup(
+        &self,
+        request: &InterceptedRequest,
+    ) -> Result<Option<InterceptedResponse>, CacheError> {
+        let Some(file) = self.read(&self.path(request)).await? else {
+            return Ok(None);
+        };
+        if file.method != request.method.as_str() || file.uri != request.uri.to_string() {
+            return Ok(None);
+        }
+        let Some(entry) = file.entries.into_iter().find(|e| {
+            e.vary
+                == vary_values(
+                    request,
+                    e.vary.iter().map(|(name, _)| name.clone()).collect(),
+                )
+        }) else {
+            return Ok(None);
+        };
+        let mut response = InterceptedResponse::try_from(entry.response)?;
+        response.timestamp = OffsetDateTime::now_utc();
+        Ok(Some(response))
+    }
+
+    /// Gets the response for `request` according to the current mode, `send`
+    /// is only called when the upstream may be contacted.
+    ///
+    /// Returns `None` when offline and nothing is cached for `request`.
+    pub(crate) async fn fetch<F>(
+        &self,
+        request: &InterceptedRequest,
+        send: impl FnOnce() -> F,
+    ) -> Result<Option<InterceptedResponse>, HttpError>
+    where
+        F: Future<Output = Result<InterceptedResponse, HttpError>>,
+    {
+        match self.mode() {
+            CacheMode::Off => send().await.map(Some),
+            CacheMode::Offline => Ok(self.lookup_logged(request).await),
+            CacheMode::Fallback => match send().await {
+                Ok(response) => {
+                    if let Err(err) = self.store(request, &response).await {
+                        warn!("Failed to cache {} {err}", request.uri);
+                    }
+                    Ok(Some(response))
+                }
+                Err(err) => match self.lookup_logged(request).await {
+                    Some(response) => {
+                        debug!("Upstream failed {err}, serving {} from cache", request.uri);
+                        Ok(Some(response))
+                    }
+                    None => Err(err),
+                },
+            },
+        }
+    }
+
+    async fn lookup_logged(&self, request: &InterceptedRequest) -> Option<InterceptedResponse> {
+        match self.lookup(request).await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("Failed to read cache for {} {err}", request.uri);
+                None
+            }
+        }
+    }
+
+    async fn read(&self, path: &Path) -> Result<Option<CacheFile>, CacheError> {
+        match tokio::fs::read(path).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn path(&self, request: &InterceptedRequest) -> PathBuf {
+        let key = format!("{} {}", request.method, request.uri);
+        self.dir
+            .join(format!("{:016x}.json", fnv1a(key.as_bytes())))
+    }
+}
+
+/// Header names listed in the response's `Vary` header, `None` for `Vary: *`.
+fn vary_names(response: &InterceptedResponse) -> Option<Vec<String>> {
+    let mut names = vec![];
+    for value in response.headers.get_all(VARY) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            if name == "*" {
+                return None;
+            }
+            names.push(name.cow_to_ascii_lowercase().to_string());
+        }
+    }
+    names.sort();
+    names.dedup();
+    Some(names)
+}
+
+fn vary_values(request: &InterceptedRequest, names: Vec<String>) -> Vec<(String, Vec<Vec<u8>>)> {
+    names
+        .into_iter()
+        .map(|name| {
+            let values = request
+                .headers
+                .get_all(name.as_str())
+                .iter()
+                .map(|v| v.as_bytes().to_vec())
+                .collect();
+            (name, values)
+        })
+        .collect()
+}
+
+/// Stable across builds, unlike the std hasher, so file names survive upgrades.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http::{HeaderValue, Method, StatusCode, header::ACCEPT_LANGUAGE};
+    use roxy_shared::uri::RUri;
+
+    use super::*;
+
+    fn request(method: Method, lang: Option<&'static str>) -> InterceptedRequest {
+        let mut request = InterceptedRequest {
+            method,
+            uri: RUri::new("https://example.com/a?b=c".parse().unwrap()),
+            ..Default::default()
+        };
+        if let Some(lang) = lang {
+            request
+                .headers
+                .insert(ACCEPT_LANGUAGE, HeaderValue::from_static(lang));
+        }
+        request
+    }
+
+    fn response(body: &'static str, vary: Option<&'static str>) -> InterceptedResponse {
+        let mut response = InterceptedResponse {
+            body: Bytes::from(body),
+            ..Default::default()
+        };
+        if let Some(vary) = vary {
+            response
+                .headers
+                .insert(VARY, HeaderValue::from_static(vary));
+        }
+        response
+    }
+
+    #[tokio::test]
+    async fn store_and_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(dir.path(), CacheMode::Fallback);
+        let get = request(Method::GET, None);
+        assert!(cache.lookup(&get).await.unwrap().is_none());
+
+        cache.store(&get, &response("one", None)).await.unwrap();
+        cache.store(&get, &response("two", None)).await.unwrap();
+        let cached = cache.lookup(&get).await.unwrap().unwrap();
+        assert_eq!(cached.body, "two");
+        assert_eq!(cached.status, StatusCode::OK);
+
+        assert!(
+            cache
+                .lookup(&request(Method::POST, None))
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn vary_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(dir.path(), CacheMode::Fallback);
+        let en = request(Method::GET, Some("en"));
+        let de = request(Method::GET, Some("de"));
+        cache
+            .store(&en, &response("hello", Some("Accept-Language")))
+            .await
+            .unwrap();
+        cache
+            .store(&de, &response("hallo", Some("accept-language")))
+            .await
+            .unwrap();
+
+        assert_eq!(cache.lookup(&en).await.unwrap().unwrap().body, "hello");
+        assert_eq!(cache.lookup(&de).await.unwrap().unwrap().body, "hallo");
+        let none = request(Method::GET, None);
+        assert!(cache.lookup(&none).await.unwrap().is_none());
+
+        cache
+            .store(&none, &response("any", Some("*")))
+            .await
+            .unwrap();
+        assert!(cache.lookup(&none).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn fetch_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(dir.path(), CacheMode::Offline);
+        let get = request(Method::GET, None);
+        let offline = || async { Err(HttpError::Timeout) };
+
+        assert!(cache.fetch(&get, offline).await.unwrap().is_none());
+
+        cache.set_mode(CacheMode::Fallback);
+        let fetched = cache
+            .fetch(&get, || async { Ok(response("live", None)) })
+            .await
+            .unwrap();
+        assert_eq!(fetched.unwrap().body, "live");
+        let fetched = cache.fetch(&get, offline).await.unwrap();
+        assert_eq!(fetched.unwrap().body, "live");
+
+        cache.set_mode(CacheMode::Offline);
+        let fetched = cache
+            .fetch(&get, || async { Ok(response("new", None)) })
+            .await
+            .unwrap();
+        assert_eq!(fetched.unwrap().body, "live");
+
+        cache.set_mode(CacheMode::Off);
+        assert!(cache.fetch(&get, offline).await.is_err());
+    }
+}
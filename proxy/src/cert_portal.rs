@@ -0,0 +1,286 @@
+//! The Roxy CA served by the proxy itself, so a device pointed at Roxy can
+//! install it from its browser instead of having the file copied over.
+//!
+//! Requests to `http://roxy.it` through the proxy, and requests for the CA
+//! files sent straight to the proxy port, are answered by Roxy and never
+//! reach a server or the flow store.
+
+use std::convert::Infallible;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::Bytes;
+use cow_utils::CowUtils;
+use http::{
+    HeaderValue, Method, Request, Response, StatusCode,
+    header::{ALLOW, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE, HOST},
+};
+use http_body_util::{Full, combinators::BoxBody};
+use roxy_shared::RoxyCA;
+
+/// Host the landing page is served on.
+pub const PORTAL_HOST: &str = "roxy.it";
+
+/// A file the portal serves, by path and MIME type.
+struct CaFile {
+    path: &'static str,
+    content_type: &'static str,
+    body: fn(&RoxyCA) -> Bytes,
+}
+
+const CA_FILES: &[CaFile] = &[
+    CaFile {
+        path: "/roxy-ca.pem",
+        content_type: "application/x-pem-file",
+        body: |ca| Bytes::from(ca_pem(ca)),
+    },
+    // Android's certificate installer and Windows take DER
+    CaFile {
+        path: "/roxy-ca.crt",
+        content_type: "application/x-x509-ca-cert",
+        body: |ca| Bytes::copy_from_slice(ca.ca_der()),
+    },
+    CaFile {
+        path: "/roxy-ca.cer",
+        content_type: "application/x-x509-ca-cert",
+        body: |ca| Bytes::copy_from_slice(ca.ca_der()),
+    },
+    // iOS only offers to install certificates wrapped in a profile
+    CaFile {
+        path: "/roxy-ca.mobileconfig",
+        content_type: "application/x-apple-aspen-config",
+        body: |ca| Bytes::from(mobileconfig(ca)),
+    },
+];
+
+/// The certificate of `ca` PEM encoded, without its key.
+pub fn ca_pem(ca: &RoxyCA) -> String {
+    let encoded = STANDARD.encode(ca.ca_der());
+    let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str("-----END CERTIFICATE-----\n");
+    pem
+}
+
+/// Whether Roxy answers `req` itself: anything for [`PORTAL_HOST`], or a CA
+/// file asked of the proxy as a server.
+pub(crate) fn is_portal_request<B>(req: &Request<B>) -> bool {
+    let host = match req.uri().host() {
+        Some(host) => Some(host),
+        None => req.headers().get(HOST).and_then(|host| host.to_str().ok()),
+    };
+    let is_portal_host = host.is_some_and(|host| {
+        let host = host.split(':').next().unwrap_or_default();
+        host.trim_end_matches('.').eq_ignore_ascii_case(PORTAL_HOST)
+    });
+    is_portal_host || (req.uri().host().is_none() && ca_file(req.uri().path()).is_some())
+}
+
+fn ca_file(path: &str) -> Option<&'static CaFile> {
+    CA_FILES.iter().find(|file| file.path == path)
+}
+
+/// The landing page or CA file `req` asks for.
+pub(crate) fn serve<B>(ca: &RoxyCA, req: &Request<B>) -> Response<BoxBody<Bytes, Infallible>> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        let mut response = respond(StatusCode::METHOD_NOT_ALLOWED, "text/plain", Bytes::new());
+        response
+            .headers_mut()
+            .insert(ALLOW, HeaderValue::from_static("GET, HEAD"));
+        return response;
+    }
+    let path = req.uri().path();
+    let mut response = match (path, ca_file(path)) {
+        ("/" | "/index.html", _) => respond(
+            StatusCode::OK,
+            "text/html; charset=utf-8",
+            Bytes::from(landing_page(ca)),
+        ),
+        (_, Some(file)) => {
+            let mut response = respond(StatusCode::OK, file.content_type, (file.body)(ca));
+            let name = file.path.trim_start_matches('/');
+            if let Ok(disposition) =
+                HeaderValue::from_str(&format!("attachment; filename=\"{name}\""))
+            {
+                response
+                    .headers_mut()
+                    .insert(CONTENT_DISPOSITION, disposition);
+            }
+            response
+        }
+        _ => respond(
+            StatusCode::NOT_FOUND,
+            "text/plain; charset=utf-8",
+            Bytes::from_static(b"Not found, the Roxy CA is at http://roxy.it\n"),
+        ),
+    };
+    if req.method() == Method::HEAD {
+        *response.body_mut() = BoxBody::new(Full::new(Bytes::new()));
+    }
+    response
+}
+
+fn respond(
+    status: StatusCode,
+    content_type: &'static str,
+    body: Bytes,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let mut response = Response::new(BoxBody::new(Full::new(body)));
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+fn landing_page(ca: &RoxyCA) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>Roxy CA</title>\n</head>\n<body>\n<h1>Roxy CA</h1>\n\
+         <p>Trust this certificate authority to let Roxy inspect HTTPS traffic from this \
+         device. Only install it on devices you use for debugging.</p>\n\
+         <h2>iOS</h2>\n<p><a href=\"/roxy-ca.mobileconfig\">Download the profile</a>, install it \
+         in Settings, General, VPN &amp; Device Management, then turn on full trust in Settings, \
+         General, About, Certificate Trust Settings.</p>\n\
+         <h2>Android</h2>\n<p><a href=\"/roxy-ca.crt\">Download the certificate</a> and install \
+         it in Settings, Security, Encryption &amp; credentials, Install a certificate, CA \
+         certificate. Apps only trust it when they opt in to user certificates.</p>\n\
+         <h2>Other devices</h2>\n<p><a href=\"/roxy-ca.pem\">PEM</a> or \
+         <a href=\"/roxy-ca.cer\">DER</a> encoded.</p>\n\
+         <h2>Fingerprint</h2>\n<p>SHA-256 <code>{fingerprint}</code></p>\n</body>\n</html>\n",
+        fingerprint = ca.ca_sha256(),
+    )
+}
+
+/// An iOS configuration profile with the CA as its only payload. Its UUIDs
+/// come from the certificate, so installing it again replaces the profile.
+fn mobileconfig(ca: &RoxyCA) -> String {
+    let sha1 = ca.ca_sha1();
+    let sha256 = ca.ca_sha256();
+    let sha256 = sha256.cow_replace(':', "");
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+         \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n<dict>\n\
+         \t<key>PayloadContent</key>\n\t<array>\n\t\t<dict>\n\
+         \t\t\t<key>PayloadCertificateFileName</key>\n\t\t\t<string>roxy-ca.cer</string>\n\
+         \t\t\t<key>PayloadContent</key>\n\t\t\t<data>{der}</data>\n\
+         \t\t\t<key>PayloadDisplayName</key>\n\t\t\t<string>Roxy CA</string>\n\
+         \t\t\t<key>PayloadIdentifier</key>\n\t\t\t<string>roxy.ca.{sha1}</string>\n\
+         \t\t\t<key>PayloadType</key>\n\t\t\t<string>com.apple.security.root</string>\n\
+         \t\t\t<key>PayloadUUID</key>\n\t\t\t<string>{cert_uuid}</string>\n\
+         \t\t\t<key>PayloadVersion</key>\n\t\t\t<integer>1</integer>\n\
+         \t\t</dict>\n\t</array>\n\
+         \t<key>PayloadDescription</key>\n\
+         \t<string>Lets Roxy inspect HTTPS traffic from this device.</string>\n\
+         \t<key>PayloadDisplayName</key>\n\t<string>Roxy CA</string>\n\
+         \t<key>PayloadIdentifier</key>\n\t<string>roxy.profile.{sha1}</string>\n\
+         \t<key>PayloadRemovalDisallowed</key>\n\t<false/>\n\
+         \t<key>PayloadType</key>\n\t<string>Configuration</string>\n\
+         \t<key>PayloadUUID</key>\n\t<string>{profile_uuid}</string>\n\
+         \t<key>PayloadVersion</key>\n\t<integer>1</integer>\n\
+         </dict>\n</plist>\n",
+        der = STANDARD.encode(ca.ca_der()),
+        cert_uuid = uuid(&sha256[..32]),
+        profile_uuid = uuid(&sha256[32..]),
+    )
+}
+
+/// 32 hex digits laid out as a UUID.
+fn uuid(hex: &str) -> String {
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    fn request(uri: &str, host: Option<&'static str>) -> Request<()> {
+        let mut req = Request::get(uri).body(()).unwrap();
+        if let Some(host) = host {
+            req.headers_mut()
+                .insert(HOST, HeaderValue::from_static(host));
+        }
+        req
+    }
+
+    fn ca() -> (tempfile::TempDir, RoxyCA) {
+        let dir = tempfile::tempdir().unwrap();
+        let ca =
+            roxy_shared::generate_roxy_root_ca_with_path(Some(dir.path().to_path_buf())).unwrap();
+        (dir, ca)
+    }
+
+    #[test]
+    fn recognizes_portal_requests() {
+        assert!(is_portal_request(&request("http://roxy.it/", None)));
+        assert!(is_portal_request(&request(
+            "http://ROXY.IT./roxy-ca.pem",
+            None
+        )));
+        assert!(is_portal_request(&request("/", Some("roxy.it:80"))));
+        assert!(is_portal_request(&request(
+            "/roxy-ca.pem",
+            Some("127.0.0.1:8080")
+        )));
+        assert!(!is_portal_request(&request(
+            "http://example.com/roxy-ca.pem",
+            None
+        )));
+        assert!(!is_portal_request(&request("/", Some("example.com"))));
+    }
+
+    #[tokio::test]
+    async fn serves_the_ca() {
+        let (_dir, ca) = ca();
+        let response = serve(&ca, &request("http://roxy.it/", None));
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let page = std::str::from_utf8(&body).unwrap();
+        assert!(page.contains(&ca.ca_sha256()));
+
+        let response = serve(&ca, &request("http://roxy.it/roxy-ca.crt", None));
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/x-x509-ca-cert"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], ca.ca_der());
+
+        let response = serve(&ca, &request("http://roxy.it/roxy-ca.mobileconfig", None));
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/x-apple-aspen-config"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let profile = std::str::from_utf8(&body).unwrap();
+        assert!(profile.contains("com.apple.security.root"));
+        assert!(profile.contains(&STANDARD.encode(ca.ca_der())));
+
+        let response = serve(&ca, &request("http://roxy.it/missing", None));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn encodes_pem() {
+        let (_dir, ca) = ca();
+        let pem = ca_pem(&ca);
+        assert!(pem.starts_with("-----BEGIN CERTIFICATE-----\n"));
+        assert!(pem.ends_with("-----END CERTIFICATE-----\n"));
+        assert!(pem.lines().all(|line| line.len() <= 64));
+    }
+}
@@ -0,0 +1,203 @@
+//! Connections Roxy failed to intercept, counted per host. Clients that pin
+//! certificates or enforce HSTS refuse Roxy's certificate, either with a TLS
+//! alert during the handshake or by closing the connection right after it,
+//! and their hosts usually need to be passthrough hosts.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{Arc, Mutex},
+};
+
+use rustls::AlertDescription;
+use strum::Display;
+use time::OffsetDateTime;
+use tracing::error;
+
+/// Hosts kept, the one seen least recently is dropped first.
+const MAX_HOSTS: usize = 1024;
+
+/// How an interception failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum ProblemKind {
+    /// The client answered Roxy's certificate with an alert refusing it.
+    #[strum(to_string = "certificate rejected")]
+    CertificateRejected,
+    /// The handshake failed for another reason, such as the client hanging
+    /// up halfway.
+    #[strum(to_string = "handshake failed")]
+    HandshakeFailed,
+    /// The handshake finished but the client left before sending a request,
+    /// how pinning checked after the handshake looks.
+    #[strum(to_string = "closed after handshake")]
+    ClosedAfterHandshake,
+}
+
+impl ProblemKind {
+    /// The kind of a failed client handshake from its error.
+    pub(crate) fn of_handshake(err: &io::Error) -> Self {
+        let alert = match err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<rustls::Error>())
+        {
+            Some(rustls::Error::AlertReceived(alert)) => *alert,
+            _ => return ProblemKind::HandshakeFailed,
+        };
+        match alert {
+            AlertDescription::BadCertificate
+            | AlertDescription::UnsupportedCertificate
+            | AlertDescription::CertificateRevoked
+            | AlertDescription::CertificateExpired
+            | AlertDescription::CertificateUnknown
+            | AlertDescription::UnknownCA => ProblemKind::CertificateRejected,
+            _ => ProblemKind::HandshakeFailed,
+        }
+    }
+}
+
+/// The failures seen for one host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostProblems {
+    pub host: String,
+    pub certificate_rejected: u64,
+    pub handshake_failed: u64,
+    pub closed_after_handshake: u64,
+    pub last_kind: ProblemKind,
+    /// The error of the last failure.
+    pub last_detail: String,
+    pub last_seen: OffsetDateTime,
+}
+
+impl HostProblems {
+    pub fn total(&self) -> u64 {
+        self.certificate_rejected + self.handshake_failed + self.closed_after_handshake
+    }
+
+    /// Whether the client refused Roxy's certificate, rather than the
+    /// connection just failing.
+    pub fn refuses_certificate(&self) -> bool {
+        self.certificate_rejected + self.closed_after_handshake > 0
+    }
+}
+
+/// Collects interception failures per host, shared by clones.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    hosts: Arc<Mutex<BTreeMap<String, HostProblems>>>,
+}
+
+impl Diagnostics {
+    pub fn record(&self, host: &str, kind: ProblemKind, detail: impl Into<String>) {
+        let mut hosts = match self.hosts.lock() {
+            Ok(hosts) => hosts,
+            Err(err) => {
+                error!("Diagnostics lock poisoned {err}");
+                return;
+            }
+        };
+        if !hosts.contains_key(host)
+            && hosts.len() >= MAX_HOSTS
+            && let Some(oldest) = hosts
+                .values()
+                .min_by_key(|problems| problems.last_seen)
+                .map(|problems| problems.host.clone())
+        {
+            hosts.remove(&oldest);
+        }
+        let problems = hosts
+            .entry(host.to_string())
+            .or_insert_with(|| HostProblems {
+                host: host.to_string(),
+                certificate_rejected: 0,
+                handshake_failed: 0,
+                closed_after_handshake: 0,
+                last_kind: kind,
+                last_detail: String::new(),
+                last_seen: OffsetDateTime::now_utc(),
+            });
+        match kind {
+            ProblemKind::CertificateRejected => problems.certificate_rejected += 1,
+            ProblemKind::HandshakeFailed => problems.handshake_failed += 1,
+            ProblemKind::ClosedAfterHandshake => problems.closed_after_handshake += 1,
+        }
+        problems.last_kind = kind;
+        problems.last_detail = detail.into();
+        problems.last_seen = OffsetDateTime::now_utc();
+    }
+
+    /// Every host with a failure, the most recent first.
+    pub fn problems(&self) -> Vec<HostProblems> {
+        let mut problems: Vec<_> = match self.hosts.lock() {
+            Ok(hosts) => hosts.values().cloned().collect(),
+            Err(err) => {
+                error!("Diagnostics lock poisoned {err}");
+                return vec![];
+            }
+        };
+        problems.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        problems
+    }
+
+    pub fn clear(&self) {
+        match self.hosts.lock() {
+            Ok(mut hosts) => hosts.clear(),
+            Err(err) => error!("Diagnostics lock poisoned {err}"),
+        }
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(alert: AlertDescription) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            rustls::Error::AlertReceived(alert),
+        )
+    }
+
+    #[test]
+    fn tells_refused_certificates_apart() {
+        assert_eq!(
+            ProblemKind::of_handshake(&alert(AlertDescription::UnknownCA)),
+            ProblemKind::CertificateRejected
+        );
+        assert_eq!(
+            ProblemKind::of_handshake(&alert(AlertDescription::ProtocolVersion)),
+            ProblemKind::HandshakeFailed
+        );
+        assert_eq!(
+            ProblemKind::of_handshake(&io::Error::from(io::ErrorKind::UnexpectedEof)),
+            ProblemKind::HandshakeFailed
+        );
+    }
+
+    #[test]
+    fn counts_per_host() {
+        let diagnostics = Diagnostics::default();
+        diagnostics.record("pinned.example.com", ProblemKind::CertificateRejected, "a");
+        diagnostics.record("flaky.example.com", ProblemKind::HandshakeFailed, "b");
+        diagnostics.record("pinned.example.com", ProblemKind::ClosedAfterHandshake, "c");
+
+        let problems = diagnostics.clone().problems();
+        assert_eq!(problems.len(), 2);
+        let pinned = problems
+            .iter()
+            .find(|p| p.host == "pinned.example.com")
+            .unwrap();
+        assert_eq!(pinned.total(), 2);
+        assert_eq!(pinned.last_kind, ProblemKind::ClosedAfterHandshake);
+        assert_eq!(pinned.last_detail, "c");
+        assert!(pinned.refuses_certificate());
+        let flaky = problems
+            .iter()
+            .find(|p| p.host == "flaky.example.com")
+            .unwrap();
+        assert!(!flaky.refuses_certificate());
+
+        diagnostics.clear();
+        assert!(diagnostics.problems().is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+//! The response a client gets when Roxy could not get one from the server,
+//! telling what failed instead of a bare status or a reset connection.
+//!
+//! The page is HTML, JSON or plain text depending on what the request
+//! accepts, and is recorded on the flow as its response.
+
+use bytes::Bytes;
+use http::{
+    HeaderValue, StatusCode,
+    header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE},
+};
+use serde_json::json;
+use time::OffsetDateTime;
+
+use crate::flow::{
+    InterceptedRequest, InterceptedResponse,
+    error::{FlowError, FlowErrorKind},
+    id::FlowId,
+};
+
+/// Names the kind of failure on pages Roxy answered with, so clients and
+/// scripts can tell them from the server's own errors.
+pub(crate) const ERROR_HEADER: &str = "x-roxy-error";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PageFormat {
+    Html,
+    Json,
+    Text,
+}
+
+impl PageFormat {
+    /// JSON when the client names it before HTML, HTML when it names that,
+    /// text for anything else such as `*/*`.
+    fn accepted(accept: Option<&HeaderValue>) -> Self {
+        let Some(accept) = accept.and_then(|a| a.to_str().ok()) else {
+            return PageFormat::Text;
+        };
+        let position = |wanted: fn(&str) -> bool| {
+            accept
+                .split(',')
+                .position(|media| wanted(media.split(';').next().unwrap_or_default().trim()))
+        };
+        let json = position(|media| media == "application/json" || media.ends_with("+json"));
+        let html = position(|media| media == "text/html" || media == "application/xhtml+xml");
+        match (json, html) {
+            (Some(json), Some(html)) if json < html => PageFormat::Json,
+            (Some(_), None) => PageFormat::Json,
+            (_, Some(_)) => PageFormat::Html,
+            (None, None) => PageFormat::Text,
+        }
+    }
+}
+
+/// `504 Gateway Timeout` when the server did not answer in time, `502 Bad
+/// Gateway` for every other failure to get a response.
+pub(crate) fn error_status(kind: FlowErrorKind) -> StatusCode {
+    match kind {
+        FlowErrorKind::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+/// The answer to `request` of flow `flow_id`, which failed with `error`.
+pub(crate) fn error_page(
+    flow_id: FlowId,
+    request: &InterceptedRequest,
+    error: &FlowError,
+) -> InterceptedResponse {
+    let status = error_status(error.kind);
+    let target = request.uri.to_string();
+    let elapsed = (OffsetDateTime::now_utc() - request.timestamp)
+        .whole_milliseconds()
+        .max(0);
+    let reason = status.canonical_reason().unwrap_or_default();
+    let (content_type, body) = match PageFormat::accepted(request.headers.get(ACCEPT)) {
+        PageFormat::Json => {
+            let body = json!({
+                "error": {
+                    "status": status.as_u16(),
+                    "kind": error.kind.as_str(),
+                    "message": error.message,
+                    "hint": error.kind.hint(),
+                    "method": request.method.as_str(),
+                    "target": target,
+                    "elapsed_ms": elapsed,
+                    "flow_id": flow_id,
+                }
+            });
+            ("application/json", body.to_string())
+        }
+        PageFormat::Html => (
+            "text/html; charset=utf-8",
+            format!(
+                "<!DOCTYPE html>\n<html>\n<head><title>{code} {reason}</title></head>\n<body>\n\
+                 <h1>{code} {reason}</h1>\n<p>Roxy could not get a response for {method} \
+                 {target}.</p>\n<dl>\n<dt>Error</dt><dd>{kind}</dd>\n\
+                 <dt>Message</dt><dd>{message}</dd>\n<dt>Hint</dt><dd>{hint}</dd>\n\
+                 <dt>Elapsed</dt><dd>{elapsed} ms</dd>\n<dt>Flow</dt><dd>{flow_id}</dd>\n\
+                 </dl>\n</body>\n</html>\n",
+                code = status.as_u16(),
+                method = escape_html(request.method.as_str()),
+                target = escape_html(&target),
+                kind = error.kind,
+                message = escape_html(&error.message),
+                hint = escape_html(error.kind.hint()),
+            ),
+        ),
+        PageFormat::Text => (
+            "text/plain; charset=utf-8",
+            format!(
+                "{code} {reason}\n\nRoxy could not get a response for {method} {target}.\n\n\
+                 Error: {kind}\nMessage: {message}\nHint: {hint}\nElapsed: {elapsed} ms\n\
+                 Flow: {flow_id}\n",
+                code = status.as_u16(),
+                method = request.method,
+                kind = error.kind,
+                message = error.message,
+                hint = error.kind.hint(),
+            ),
+        ),
+    };
+
+    let mut response = InterceptedResponse {
+        status,
+        body: Bytes::from(body),
+        ..Default::default()
+    };
+    let headers = &mut response.headers;
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers.insert(ERROR_HEADER, HeaderValue::from_static(error.kind.as_str()));
+    response
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use roxy_shared::uri::RUri;
+
+    use super::*;
+
+    fn request(accept: Option<&'static str>) -> InterceptedRequest {
+        let mut request = InterceptedRequest {
+            uri: RUri::new("http://example.com/a?b=<c>".parse().unwrap()),
+            ..Default::default()
+        };
+        if let Some(accept) = accept {
+            request
+                .headers
+                .insert(ACCEPT, HeaderValue::from_static(accept));
+        }
+        request
+    }
+
+    #[test]
+    fn picks_the_format_from_accept() {
+        let accepted = |accept| PageFormat::accepted(Some(&HeaderValue::from_static(accept)));
+        assert_eq!(PageFormat::accepted(None), PageFormat::Text);
+        assert_eq!(accepted("*/*"), PageFormat::Text);
+        assert_eq!(
+            accepted("text/html,application/xhtml+xml,*/*;q=0.8"),
+            PageFormat::Html
+        );
+        assert_eq!(accepted("application/json"), PageFormat::Json);
+        assert_eq!(
+            accepted("application/problem+json, text/html"),
+            PageFormat::Json
+        );
+    }
+
+    #[test]
+    fn describes_the_failure() {
+        let error = FlowError::new(FlowErrorKind::Connect, "Io error connection refused");
+        let page = error_page(FlowId(7), &request(Some("application/json")), &error);
+        assert_eq!(page.status, StatusCode::BAD_GATEWAY);
+        assert_eq!(page.headers.get(ERROR_HEADER).unwrap(), "connect");
+        let body: serde_json::Value = serde_json::from_slice(&page.body).unwrap();
+        assert_eq!(body["error"]["kind"], "connect");
+        assert_eq!(body["error"]["target"], "http://example.com/a?b=<c>");
+        assert_eq!(body["error"]["flow_id"], 7);
+        assert!(body["error"]["elapsed_ms"].as_i64().unwrap() >= 0);
+
+        let timeout = FlowError::new(FlowErrorKind::Timeout, "Down stream timeout");
+        let page = error_page(FlowId(7), &request(Some("text/html")), &timeout);
+        assert_eq!(page.status, StatusCode::GATEWAY_TIMEOUT);
+        let body = std::str::from_utf8(&page.body).unwrap();
+        assert!(body.contains("<h1>504 Gateway Timeout</h1>"));
+        assert!(body.contains("http://example.com/a?b=&lt;c&gt;"));
+
+        let page = error_page(FlowId(7), &request(None), &timeout);
+        assert!(
+            std::str::from_utf8(&page.body)
+                .unwrap()
+                .starts_with("504 Gateway Timeout\n")
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use std::sync::{
+    Arc, RwLock,
+    atomic::{AtomicBool, Ordering},
+};
+
+use roxy_shared::upstream::host_matches;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+use tracing::{error, trace};
+
+/// What happens to an intercepted connection that does not speak HTTP, told
+/// from the first bytes the client sends inside any TLS.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Display, EnumString,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum UnknownProtocol {
+    /// Relayed with the bytes each side sends recorded.
+    #[default]
+    Capture,
+    /// Relayed with only the connection recorded. TLS the client spoke is
+    /// still terminated, it was needed to see what is inside.
+    Passthrough,
+}
+
+/// What happens to a request whose `Host` names another host than the
+/// tunnel it came through or the SNI its client sent, as in domain fronting.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Display, EnumString,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum HostMismatch {
+    /// Not checked.
+    Allow,
+    /// Proxied with the flow tagged and a warning logged.
+    #[default]
+    Warn,
+    /// Answered `421 Misdirected Request` without reaching the server, the
+    /// flow is tagged and recorded with the reason.
+    Block,
+}
+
+/// Host patterns deciding which connections are intercepted.
+///
+/// Patterns use the same syntax as upstream rules: `*`, an exact host or
+/// `*.example.com`. A host matching `passthrough` is always tunneled untouched,
+/// when `intercept` is non-empty only hosts matching it are intercepted.
+/// Requests to hosts matching `skip_bodies` are intercepted and recorded, but
+/// without their bodies.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostFilterRules {
+    pub intercept: Vec<String>,
+    pub passthrough: Vec<String>,
+    pub skip_bodies: Vec<String>,
+    pub unknown_protocol: UnknownProtocol,
+    pub host_mismatch: HostMismatch,
+}
+
+impl HostFilterRules {
+    pub fn new(intercept: Vec<String>, passthrough: Vec<String>) -> Self {
+        Self {
+            intercept,
+            passthrough,
+            skip_bodies: vec![],
+            unknown_protocol: UnknownProtocol::default(),
+            host_mismatch: HostMismatch::default(),
+        }
+    }
+
+    pub fn with_unknown_protocol(mut self, unknown_protocol: UnknownProtocol) -> Self {
+        self.unknown_protocol = unknown_protocol;
+        self
+    }
+
+    pub fn with_host_mismatch(mut self, host_mismatch: HostMismatch) -> Self {
+        self.host_mismatch = host_mismatch;
+        self
+    }
+
+    pub fn with_skip_bodies(mut self, skip_bodies: Vec<String>) -> Self {
+        self.skip_bodies = skip_bodies;
+        self
+    }
+
+    pub fn skip_body(&self, host: &str) -> bool {
+        self.skip_bodies.iter().any(|p| host_matches(p, host))
+    }
+
+    pub fn should_intercept(&self, host: &str) -> bool {
+        if self.passthrough.iter().any(|p| host_matches(p, host)) {
+            return false;
+        }
+        self.intercept.is_empty() || self.intercept.iter().any(|p| host_matches(p, host))
+    }
+}
+
+/// Shared handle to the active [`HostFilterRules`], clones see updates made
+/// through any other clone so the rules can be changed while the proxy runs.
+#[derive(Debug, Clone, Default)]
+pub struct HostFilter {
+    rules: Arc<RwLock<HostFilterRules>>,
+    /// Tunnels every host untouched while set, regardless of the rules.
+    paused: Arc<AtomicBool>,
+}
+
+impl HostFilter {
+    pub fn new(rules: HostFilterRules) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+            paused: Arc::default(),
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn rules(&self) -> HostFilterRules {
+        match self.rules.read() {
+            Ok(rules) => rules.clone(),
+            Err(err) => {
+                error!("Host filter lock poisoned {err}");
+                HostFilterRules::default()
+            }
+        }
+    }
+
+    pub fn set_rules(&self, rules: HostFilterRules) {
+        match self.rules.write() {
+            Ok(mut guard) => *guard = rules,
+            Err(err) => error!("Host filter lock poisoned {err}"),
+        }
+    }
+
+    pub fn unknown_protocol(&self) -> UnknownProtocol {
+        match self.rules.read() {
+            Ok(rules) => rules.unknown_protocol,
+            Err(_) => UnknownProtocol::default(),
+        }
+    }
+
+    pub fn host_mismatch(&self) -> HostMismatch {
+        match self.rules.read() {
+            Ok(rules) => rules.host_mismatch,
+            Err(_) => HostMismatch::default(),
+        }
+    }
+
+    /// Whether flows to `host` are recorded without their bodies.
+    pub fn skip_body(&self, host: &str) -> bool {
+        match self.rules.read() {
+            Ok(rules) => rules.skip_body(host),
+            Err(_) => false,
+        }
+    }
+
+    pub fn should_intercept(&self, host: &str) -> bool {
+        if self.paused() {
+            trace!("Intercept {host} paused");
+            return false;
+        }
+        let intercept = match self.rules.read() {
+            Ok(rules) => rules.should_intercept(host),
+            Err(_) => true,
+        };
+        trace!("Intercept {host} {intercept}");
+        intercept
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(p: &[&str]) -> Vec<String> {
+        p.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn default_intercepts_everything() {
+        assert!(HostFilter::default().should_intercept("example.com"));
+    }
+
+    #[test]
+    fn passthrough_wins_over_intercept() {
+        let rules = HostFilterRules::new(
+            patterns(&["*.apple.com", "example.com"]),
+            patterns(&["*.apple.com"]),
+        );
+        assert!(!rules.should_intercept("api.apple.com"));
+        assert!(rules.should_intercept("example.com"));
+        assert!(!rules.should_intercept("other.com"));
+    }
+
+    #[test]
+    fn updates_are_shared() {
+        let filter = HostFilter::default();
+        let clone = filter.clone();
+        filter.set_rules(HostFilterRules::new(vec![], patterns(&["bank.com"])));
+        assert!(!clone.should_intercept("bank.com"));
+        assert!(clone.should_intercept("example.com"));
+    }
+
+    #[test]
+    fn paused_tunnels_everything() {
+        let filter = HostFilter::default();
+        filter.clone().set_paused(true);
+        assert!(!filter.should_intercept("example.com"));
+        filter.set_paused(false);
+        assert!(filter.should_intercept("example.com"));
+    }
+
+    #[test]
+    fn skips_bodies_of_matching_hosts() {
+        let rules = HostFilterRules::default().with_skip_bodies(patterns(&["*.cdn.example.com"]));
+        assert!(rules.skip_body("img.cdn.example.com"));
+        assert!(!rules.skip_body("api.example.com"));
+        assert!(rules.should_intercept("img.cdn.example.com"));
+    }
+
+    #[test]
+    fn host_mismatch_follows_the_rules() {
+        let filter = HostFilter::default();
+        assert_eq!(filter.host_mismatch(), HostMismatch::Warn);
+        filter.set_rules(HostFilterRules::default().with_host_mismatch(HostMismatch::Block));
+        assert_eq!(filter.host_mismatch(), HostMismatch::Block);
+        assert_eq!(
+            "allow".parse::<HostMismatch>().unwrap(),
+            HostMismatch::Allow
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::HeaderMap;
+use time::OffsetDateTime;
+
+use crate::flow::{Flow, Timing};
+
+/// Above this many line pairs the changed middle of two bodies is shown as
+/// removed and added whole instead of computing the smallest diff.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// The differences between two flows, from the left flow to the right one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowDiff {
+    /// Request line and status, only the fields that differ.
+    pub fields: Vec<FieldChange>,
+    pub request_headers: Vec<HeaderChange>,
+    pub request_body: Vec<DiffLine>,
+    pub response_headers: Vec<HeaderChange>,
+    pub response_body: Vec<DiffLine>,
+    /// Every phase either flow has a duration for, changed or not.
+    pub timings: Vec<TimingChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    pub name: &'static str,
+    pub left: String,
+    pub right: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderChange {
+    Added(String, String),
+    Removed(String, String),
+    Changed {
+        name: String,
+        left: String,
+        right: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Same(String),
+    Added(String),
+    Removed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimingChange {
+    pub name: &'static str,
+    pub left: Option<Duration>,
+    pub right: Option<Duration>,
+}
+
+impl FlowDiff {
+    /// Whether the flows sent and received the same thing, timings aside.
+    pub fn is_empty(&self) -> bool {
+        let same_body = |lines: &[DiffLine]| lines.iter().all(|l| matches!(l, DiffLine::Same(_)));
+        self.fields.is_empty()
+            && self.request_headers.is_empty()
+            && self.response_headers.is_empty()
+            && same_body(&self.request_body)
+            && same_body(&self.response_body)
+    }
+}
+
+impl Flow {
+    /// Compares this flow with `other`. Bodies are compared decoded, JSON is
+    /// pretty printed first so a changed field shows up on a line of its own.
+    pub fn diff(&self, other: &Flow) -> FlowDiff {
+        let mut fields = vec![];
+        let mut field = |name, left: Option<String>, right: Option<String>| {
+            let left = left.unwrap_or_default();
+            let right = right.unwrap_or_default();
+            if left != right {
+                fields.push(FieldChange { name, left, right });
+            }
+        };
+        let (req_a, req_b) = (self.request.as_ref(), other.request.as_ref());
+        let (resp_a, resp_b) = (self.response.as_ref(), other.response.as_ref());
+        field(
+            "method",
+            req_a.map(|r| r.method.to_string()),
+            req_b.map(|r| r.method.to_string()),
+        );
+        field(
+            "url",
+            req_a.map(|r| r.line_pretty()),
+            req_b.map(|r| r.line_pretty()),
+        );
+        field(
+            "request version",
+            req_a.map(|r| format!("{:?}", r.version)),
+            req_b.map(|r| format!("{:?}", r.version)),
+        );
+        field(
+            "status",
+            resp_a.map(|r| r.status.to_string()),
+            resp_b.map(|r| r.status.to_string()),
+        );
+        field(
+            "response version",
+            resp_a.map(|r| format!("{:?}", r.version)),
+            resp_b.map(|r| format!("{:?}", r.version)),
+        );
+        field(
+            "error",
+            self.error.as_ref().map(ToString::to_string),
+            other.error.as_ref().map(ToString::to_string),
+        );
+
+        FlowDiff {
+            fields,
+            request_headers: diff_headers(req_a.map(|r| &r.headers), req_b.map(|r| &r.headers)),
+            request_body: diff_lines(
+                &body_lines(req_a.map(|r| (&r.headers, r.read_body()))),
+                &body_lines(req_b.map(|r| (&r.headers, r.read_body()))),
+            ),
+            response_headers: diff_headers(resp_a.map(|r| &r.headers), resp_b.map(|r| &r.headers)),
+            response_body: diff_lines(
+                &body_lines(resp_a.map(|r| (&r.headers, r.read_body()))),
+                &body_lines(resp_b.map(|r| (&r.headers, r.read_body()))),
+            ),
+            timings: diff_timings(&self.timing, &other.timing),
+        }
+    }
+}
+
+fn header_values(headers: &HeaderMap, name: &http::HeaderName) -> String {
+    headers
+        .get_all(name)
+        .iter()
+        .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Changes in the order headers appear in, removed and changed ones first.
+fn diff_headers(left: Option<&HeaderMap>, right: Option<&HeaderMap>) -> Vec<HeaderChange> {
+    let empty = HeaderMap::new();
+    let (left, right) = (left.unwrap_or(&empty), right.unwrap_or(&empty));
+    let mut changes = vec![];
+    for name in left.keys() {
+        let value = header_values(left, name);
+        if !right.contains_key(name) {
+            changes.push(HeaderChange::Removed(name.to_string(), value));
+            continue;
+        }
+        let other = header_values(right, name);
+        if value != other {
+            changes.push(HeaderChange::Changed {
+                name: name.to_string(),
+                left: value,
+                right: other,
+            });
+        }
+    }
+    for name in right.keys().filter(|name| !left.contains_key(*name)) {
+        changes.push(HeaderChange::Added(
+            name.to_string(),
+            header_values(right, name),
+        ));
+    }
+    changes
+}
+
+fn body_lines(body: Option<(&HeaderMap, std::io::Result<Bytes>)>) -> Vec<String> {
+    let Some((headers, body)) = body else {
+        return vec![];
+    };
+    let body = match body {
+        Ok(body) => body,
+        Err(err) => return vec![format!("<failed to read body {err}>")],
+    };
+    if body.is_empty() {
+        return vec![];
+    }
+    let is_json = headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("json"));
+    if is_json
+        && let Ok(value) = serde_json::from_slice::<serde_json::Value>(&body)
+        && let Ok(pretty) = serde_json::to_string_pretty(&value)
+    {
+        return pretty.lines().map(str::to_string).collect();
+    }
+    match std::str::from_utf8(&body) {
+        Ok(text) => text.lines().map(str::to_string).collect(),
+        Err(_) => vec![format!("<{} bytes of binary data>", body.len())],
+    }
+}
+
+/// Line diff of `left` and `right`, the longest common subsequence of the
+/// lines left after the common prefix and suffix are kept as unchanged.
+pub fn diff_lines(left: &[String], right: &[String]) -> Vec<DiffLine> {
+    let prefix = left.iter().zip(right).take_while(|(a, b)| a == b).count();
+    let suffix = left[prefix..]
+        .iter()
+        .rev()
+        .zip(right[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &left[prefix..left.len() - suffix],
+        &right[prefix..right.len() - suffix],
+    );
+
+    let mut lines: Vec<DiffLine> = left[..prefix].iter().cloned().map(DiffLine::Same).collect();
+    if a.len().saturating_mul(b.len()) > MAX_DIFF_CELLS {
+        lines.extend(a.iter().cloned().map(DiffLine::Removed));
+        lines.extend(b.iter().cloned().map(DiffLine::Added));
+    } else {
+        // lcs[i][j] is the common subsequence length of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if a[i] == b[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                lines.push(DiffLine::Same(a[i].clone()));
+                i += 1;
+                j += 1;
+            } else if j < b.len()
+                && (i == a.len() || lcs[i * width + j + 1] >= lcs[(i + 1) * width + j])
+            {
+                lines.push(DiffLine::Added(b[j].clone()));
+                j += 1;
+            } else {
+                lines.push(DiffLine::Removed(a[i].clone()));
+                i += 1;
+            }
+        }
+    }
+    lines.extend(
+        left[left.len() - suffix..]
+            .iter()
+            .cloned()
+            .map(DiffLine::Same),
+    );
+    lines
+}
+
+fn between(start: Option<OffsetDateTime>, end: Option<OffsetDateTime>) -> Option<Duration> {
+    let elapsed = end? - start?;
+    elapsed.try_into().ok()
+}
+
+/// How long each phase of the exchange took.
+fn phases(t: &Timing) -> [(&'static str, Option<Duration>); 6] {
+    [
+        ("dns lookup", t.server_conn_dns_lookup),
+        (
+            "tcp connect",
+            between(
+                t.server_conn_dns_resolved.or(t.server_conn_initiated),
+                t.server_conn_tcp_handshake,
+            ),
+        ),
+        (
+            "tls handshake",
+            between(t.server_conn_tls_initiated, t.server_conn_tls_handshake),
+        ),
+        (
+            "waiting",
+            between(t.request_complete, t.first_response_bytes),
+        ),
+        (
+            "receiving",
+            between(t.first_response_bytes, t.response_complete),
+        ),
+        ("total", between(t.first_request_bytes, t.response_complete)),
+    ]
+}
+
+fn diff_timings(left: &Timing, right: &Timing) -> Vec<TimingChange> {
+    phases(left)
+        .into_iter()
+        .zip(phases(right))
+        .filter(|((_, a), (_, b))| a.is_some() || b.is_some())
+        .map(|((name, left), (_, right))| TimingChange { name, left, right })
+        .collect()
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::{HeaderValue, Method, StatusCode};
+
+    use crate::flow::test_flow;
+
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    fn flow(status: StatusCode, body: &'static str) -> Flow {
+        let mut flow = test_flow(
+            1,
+            Method::GET,
+            "https://example.com/items",
+            status,
+            body.as_bytes(),
+        );
+        flow.request
+            .as_mut()
+            .unwrap()
+            .headers
+            .insert("accept", HeaderValue::from_static("application/json"));
+        flow.response
+            .as_mut()
+            .unwrap()
+            .headers
+            .insert("content-type", HeaderValue::from_static("application/json"));
+        flow
+    }
+
+    #[test]
+    fn line_diff() {
+        assert_eq!(
+            diff_lines(&lines("a\nb\nc\nd"), &lines("a\nx\nc\nd\ne")),
+            vec![
+                DiffLine::Same("a".to_string()),
+                DiffLine::Added("x".to_string()),
+                DiffLine::Removed("b".to_string()),
+                DiffLine::Same("c".to_string()),
+                DiffLine::Same("d".to_string()),
+                DiffLine::Added("e".to_string()),
+            ]
+        );
+        assert_eq!(diff_lines(&[], &[]), vec![]);
+        assert_eq!(
+            diff_lines(&lines("a"), &[]),
+            vec![DiffLine::Removed("a".to_string())]
+        );
+    }
+
+    #[test]
+    fn flow_diff() {
+        let ok = flow(StatusCode::OK, r#"{"id":1,"name":"a"}"#);
+        assert!(ok.diff(&ok).is_empty());
+
+        let mut failed = flow(StatusCode::FORBIDDEN, r#"{"id":1,"name":"b"}"#);
+        let request = failed.request.as_mut().unwrap();
+        request.headers.remove("accept");
+        request
+            .headers
+            .insert("authorization", HeaderValue::from_static("Bearer x"));
+
+        let diff = ok.diff(&failed);
+        assert!(!diff.is_empty());
+        assert_eq!(
+            diff.fields,
+            vec![FieldChange {
+                name: "status",
+                left: "200 OK".to_string(),
+                right: "403 Forbidden".to_string(),
+            }]
+        );
+        assert_eq!(
+            diff.request_headers,
+            vec![
+                HeaderChange::Removed("accept".to_string(), "application/json".to_string()),
+                HeaderChange::Added("authorization".to_string(), "Bearer x".to_string()),
+            ]
+        );
+        assert!(diff.response_headers.is_empty());
+        // Pretty printed, only the name line differs
+        assert!(
+            diff.response_body
+                .contains(&DiffLine::Same("  \"id\": 1,".to_string()))
+        );
+        assert!(
+            diff.response_body
+                .contains(&DiffLine::Removed("  \"name\": \"a\"".to_string()))
+        );
+        assert!(
+            diff.response_body
+                .contains(&DiffLine::Added("  \"name\": \"b\"".to_string()))
+        );
+    }
+}
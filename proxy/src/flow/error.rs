@@ -0,0 +1,176 @@
+use std::fmt;
+
+use roxy_shared::http::HttpError;
+use serde::{Deserialize, Serialize};
+
+/// What part of handling a flow failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FlowErrorKind {
+    /// The server's host could not be looked up.
+    Dns,
+    /// No connection could be made to the server or upstream proxy.
+    Connect,
+    TlsHandshake,
+    /// A peer sent something that is not valid HTTP, or hung up mid message.
+    Protocol,
+    Timeout,
+    /// A request or response script failed.
+    Script,
+    /// Stopped by Roxy's own rules, e.g. access control, limits, a breakpoint
+    /// or the offline cache.
+    Rejected,
+    /// From a source that does not say what failed, e.g. an imported dump.
+    Other,
+}
+
+impl FlowErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlowErrorKind::Dns => "dns",
+            FlowErrorKind::Connect => "connect",
+            FlowErrorKind::TlsHandshake => "tls-handshake",
+            FlowErrorKind::Protocol => "protocol",
+            FlowErrorKind::Timeout => "timeout",
+            FlowErrorKind::Script => "script",
+            FlowErrorKind::Rejected => "rejected",
+            FlowErrorKind::Other => "other",
+        }
+    }
+
+    /// What to look at to fix this kind of error.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            FlowErrorKind::Dns => {
+                "Check the host name is spelled right, and the dns_overrides and dns_resolver \
+                 settings"
+            }
+            FlowErrorKind::Connect => {
+                "Check the server is up and reachable from this machine, and that any upstream \
+                 proxy it is sent through is running"
+            }
+            FlowErrorKind::TlsHandshake => {
+                "Check the server certificate, add its CA to ca_bundles or the host to \
+                 insecure_hosts when it is self-signed"
+            }
+            FlowErrorKind::Protocol => {
+                "The peer closed the connection or sent invalid HTTP, try the request without \
+                 the proxy to see which side is at fault"
+            }
+            FlowErrorKind::Timeout => {
+                "The server is slow or not answering, raise the timeouts under limits if it is \
+                 expected"
+            }
+            FlowErrorKind::Script => "Check the log for the script error and the line it was on",
+            FlowErrorKind::Rejected => {
+                "Check the access control, limits, breakpoints and cache mode settings"
+            }
+            FlowErrorKind::Other => "See the message for details",
+        }
+    }
+
+    /// Whether a request failing this way can be sent again unchanged, the
+    /// server never saw or never answered it.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            FlowErrorKind::Dns | FlowErrorKind::Connect | FlowErrorKind::Timeout
+        )
+    }
+}
+
+impl fmt::Display for FlowErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Why a flow failed, recorded on the flow in place of a response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowError {
+    pub kind: FlowErrorKind,
+    pub message: String,
+}
+
+impl FlowError {
+    pub fn new(kind: FlowErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+impl From<&HttpError> for FlowError {
+    fn from(error: &HttpError) -> Self {
+        let (kind, message) = match error {
+            HttpError::Io(error) => (FlowErrorKind::Connect, format!("Io error {error}")),
+            HttpError::Alpn => (FlowErrorKind::TlsHandshake, "Invalid ALPN".to_string()),
+            HttpError::Hyper(error) if error.is_timeout() => {
+                (FlowErrorKind::Timeout, format!("Hyper error {error}"))
+            }
+            HttpError::Hyper(error) => (FlowErrorKind::Protocol, format!("Hyper error {error}")),
+            HttpError::HyperUpgrade => (
+                FlowErrorKind::Protocol,
+                "Hyper failed to upgrade down stream connection".to_string(),
+            ),
+            HttpError::Http(error) => (FlowErrorKind::Protocol, format!("HTTP error {error}")),
+            HttpError::Uri => (FlowErrorKind::Protocol, "Invalid uri".to_string()),
+            HttpError::InvalidDnsName => (FlowErrorKind::Dns, "Invalid DNS name".to_string()),
+            HttpError::Timeout => (FlowErrorKind::Timeout, "Down stream timeout".to_string()),
+            HttpError::ConnectTimeout => (
+                FlowErrorKind::Timeout,
+                "Down stream connect timeout".to_string(),
+            ),
+            HttpError::BodyTooLarge(max) => (
+                FlowErrorKind::Rejected,
+                format!("Response body larger than {max} bytes"),
+            ),
+            HttpError::ProxyConnect => (
+                FlowErrorKind::Connect,
+                "Proxy Connection failed".to_string(),
+            ),
+            HttpError::TlsError(error) => {
+                (FlowErrorKind::TlsHandshake, format!("TLS failed {error}"))
+            }
+            HttpError::BadHost => (FlowErrorKind::Protocol, "Bad host".to_string()),
+            HttpError::Dns(error) => (FlowErrorKind::Dns, format!("DNS failed {error}")),
+        };
+        FlowError::new(kind, message)
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_http_errors() {
+        let error = FlowError::from(&HttpError::Dns("no addresses for example.com".into()));
+        assert_eq!(error.kind, FlowErrorKind::Dns);
+        assert_eq!(
+            error.to_string(),
+            "dns: DNS failed no addresses for example.com"
+        );
+        assert_eq!(
+            FlowError::from(&HttpError::ConnectTimeout).kind,
+            FlowErrorKind::Timeout
+        );
+        assert_eq!(
+            FlowError::from(&HttpError::TlsError(std::io::Error::other("bad cert"))).kind,
+            FlowErrorKind::TlsHandshake
+        );
+        assert!(FlowErrorKind::Connect.is_transient());
+        assert!(!FlowErrorKind::Protocol.is_transient());
+
+        let json = serde_json::to_string(&FlowErrorKind::TlsHandshake).unwrap();
+        assert_eq!(json, "\"tls-handshake\"");
+    }
+}
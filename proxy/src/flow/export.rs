@@ -0,0 +1,335 @@
+use std::borrow::Cow;
+
+use cow_utils::CowUtils;
+use http::{
+    Method, Version,
+    header::{CONTENT_LENGTH, HOST},
+};
+use roxy_shared::{socks::SOCKS_DEFAULT_PORT, upstream::UpstreamProxy};
+
+use crate::flow::{Flow, InterceptedRequest};
+
+impl Flow {
+    /// A `curl` command sending the recorded request again, through `upstream`
+    /// when it went through one. `None` when the flow has no request.
+    pub fn to_curl(&self, upstream: Option<&UpstreamProxy>) -> Option<String> {
+        self.request.as_ref().map(|r| r.to_curl(upstream))
+    }
+
+    /// Like [`Self::to_curl`] for HTTPie's `http` command.
+    pub fn to_httpie(&self, upstream: Option<&UpstreamProxy>) -> Option<String> {
+        self.request.as_ref().map(|r| r.to_httpie(upstream))
+    }
+
+    /// A Rust program sending the recorded request with reqwest and tokio.
+    pub fn to_reqwest(&self, upstream: Option<&UpstreamProxy>) -> Option<String> {
+        self.request.as_ref().map(|r| r.to_reqwest(upstream))
+    }
+}
+
+/// Where the body of a command comes from.
+enum Body<'a> {
+    None,
+    Text(&'a str),
+    /// Spilled to disk, the command reads it from the file.
+    File(String),
+    /// Not valid as a command line argument, piped in with `printf`.
+    Binary(&'a [u8]),
+}
+
+impl InterceptedRequest {
+    fn export_body(&self) -> Body<'_> {
+        if let Some(file) = &self.body_file {
+            return Body::File(file.path().display().to_string());
+        }
+        if self.body.is_empty() {
+            return Body::None;
+        }
+        match std::str::from_utf8(&self.body) {
+            Ok(text) if !text.contains('\0') => Body::Text(text),
+            _ => Body::Binary(&self.body),
+        }
+    }
+
+    /// Headers worth repeating, `Host` is left to the URL when they agree and
+    /// the length is recomputed by the tool.
+    fn export_headers(&self) -> impl Iterator<Item = (&str, Cow<'_, str>)> {
+        let authority = self.uri.inner.authority().map(|a| a.as_str());
+        self.headers
+            .iter()
+            .filter(move |(name, value)| {
+                !(**name == HOST && value.to_str().ok() == authority) && **name != CONTENT_LENGTH
+            })
+            .map(|(name, value)| (name.as_str(), String::from_utf8_lossy(value.as_bytes())))
+    }
+
+    pub fn to_curl(&self, upstream: Option<&UpstreamProxy>) -> String {
+        let body = self.export_body();
+        let mut args = vec!["curl".to_string()];
+        let implied = match body {
+            Body::None => Method::GET,
+            _ => Method::POST,
+        };
+        if self.method != implied {
+            args.push(format!("-X {}", quote(self.method.as_str())));
+        }
+        match self.version.0 {
+            Version::HTTP_2 => args.push("--http2".to_string()),
+            Version::HTTP_3 => args.push("--http3".to_string()),
+            _ => {}
+        }
+        if let Some(upstream) = upstream {
+            args.push(format!("--proxy {}", quote(&proxy_url(upstream))));
+        }
+        args.push(quote(&self.line_pretty()).into_owned());
+        for (name, value) in self.export_headers() {
+            args.push(format!("-H {}", quote(&format!("{name}: {value}"))));
+        }
+        let mut pipe = String::new();
+        match body {
+            Body::None => {}
+            Body::Text(text) => args.push(format!("--data-binary {}", quote(text))),
+            Body::File(path) => args.push(format!("--data-binary {}", quote(&format!("@{path}")))),
+            Body::Binary(bytes) => {
+                pipe = printf(bytes);
+                args.push("--data-binary @-".to_string());
+            }
+        }
+        pipe + &args.join(" \\\n  ")
+    }
+
+    pub fn to_httpie(&self, upstream: Option<&UpstreamProxy>) -> String {
+        let mut args = vec!["http".to_string()];
+        if let Some(upstream) = upstream {
+            let url = proxy_url(upstream);
+            args.push(format!("--proxy {}", quote(&format!("http:{url}"))));
+            args.push(format!("--proxy {}", quote(&format!("https:{url}"))));
+        }
+        let body = self.export_body();
+        if let Body::Text(text) = body {
+            args.push(format!("--raw {}", quote(text)));
+        }
+        args.push(quote(self.method.as_str()).into_owned());
+        args.push(quote(&self.line_pretty()).into_owned());
+        for (name, value) in self.export_headers() {
+            // An empty value is written `Name;` as `Name:` would drop the header
+            if value.is_empty() {
+                args.push(quote(&format!("{name};")).into_owned());
+            } else {
+                args.push(quote(&format!("{name}:{value}")).into_owned());
+            }
+        }
+        let command = args.join(" \\\n  ");
+        match body {
+            Body::File(path) => format!("{command} \\\n  < {}", quote(&path)),
+            Body::Binary(bytes) => printf(bytes) + &command,
+            Body::None | Body::Text(_) => command,
+        }
+    }
+
+    pub fn to_reqwest(&self, upstream: Option<&UpstreamProxy>) -> String {
+        let mut code = vec![
+            "#[tokio::main]".to_string(),
+            "async fn main() -> Result<(), Box<dyn std::error::Error>> {".to_string(),
+            "    let client = reqwest::Client::builder()".to_string(),
+        ];
+        if let Some(upstream) = upstream {
+            code.push(format!(
+                "        .proxy(reqwest::Proxy::all({:?})?)",
+                proxy_url(upstream)
+            ));
+        }
+        code.push("        .build()?;".to_string());
+        code.push("    let response = client".to_string());
+
+        let url = self.line_pretty();
+        let method = self.method.as_str();
+        code.push(match self.method {
+            Method::GET
+            | Method::POST
+            | Method::PUT
+            | Method::PATCH
+            | Method::DELETE
+            | Method::HEAD => format!("        .{}({url:?})", method.cow_to_ascii_lowercase()),
+            _ => format!(
+                "        .request(reqwest::Method::from_bytes({:?}.as_bytes())?, {url:?})",
+                method
+            ),
+        });
+        for (name, value) in self.export_headers() {
+            code.push(format!("        .header({name:?}, {value:?})"));
+        }
+        match self.export_body() {
+            Body::None => {}
+            Body::Text(text) => code.push(format!("        .body({text:?})")),
+            Body::File(path) => code.push(format!("        .body(std::fs::read({path:?})?)")),
+            Body::Binary(bytes) => code.push(format!(
+                "        .body(b\"{}\".as_slice())",
+                bytes.escape_ascii()
+            )),
+        }
+        code.extend(
+            [
+                "        .send()",
+                "        .await?;",
+                "    println!(\"{}\", response.status());",
+                "    println!(\"{}\", response.text().await?);",
+                "    Ok(())",
+                "}",
+            ]
+            .map(String::from),
+        );
+        code.join("\n")
+    }
+}
+
+fn proxy_url(upstream: &UpstreamProxy) -> String {
+    match upstream {
+        UpstreamProxy::Http(uri) => format!("http://{}", uri.host_port()),
+        UpstreamProxy::Socks5(uri) => format!(
+            "{}://{}:{}",
+            uri.scheme_str().unwrap_or("socks5h"),
+            uri.host(),
+            uri.port_or_none().unwrap_or(SOCKS_DEFAULT_PORT)
+        ),
+    }
+}
+
+/// `printf` writing `bytes` into a pipe, every byte escaped so none of them
+/// is taken as a format directive.
+fn printf(bytes: &[u8]) -> String {
+    let escaped: String = bytes.iter().map(|b| format!("\\x{b:02x}")).collect();
+    format!("printf '{escaped}' | ")
+}
+
+/// Single quotes `arg` for POSIX shells unless it is made of safe characters only.
+fn quote(arg: &str) -> Cow<'_, str> {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c));
+    if safe {
+        Cow::Borrowed(arg)
+    } else {
+        Cow::Owned(format!("'{}'", arg.cow_replace('\'', "'\\''")))
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bytes::Bytes;
+    use http::HeaderValue;
+    use roxy_shared::{uri::RUri, version::HttpVersion};
+
+    use super::*;
+
+    fn request() -> InterceptedRequest {
+        let mut request = InterceptedRequest {
+            method: Method::POST,
+            uri: RUri::from_str("https://api.example.com/v1/login?user=a").unwrap(),
+            body: Bytes::from_static(b"{\"name\":\"it's me\"}"),
+            ..Default::default()
+        };
+        request
+            .headers
+            .insert(HOST, HeaderValue::from_static("api.example.com"));
+        request
+            .headers
+            .insert("content-type", HeaderValue::from_static("application/json"));
+        request
+            .headers
+            .insert("content-length", HeaderValue::from_static("18"));
+        request
+    }
+
+    #[test]
+    fn curl_command() {
+        assert_eq!(
+            request().to_curl(None),
+            "curl \\\n  \
+             'https://api.example.com/v1/login?user=a' \\\n  \
+             -H 'content-type: application/json' \\\n  \
+             --data-binary '{\"name\":\"it'\\''s me\"}'"
+        );
+
+        let mut get = request();
+        get.method = Method::GET;
+        get.body = Bytes::new();
+        get.version = HttpVersion(Version::HTTP_2);
+        let upstream = UpstreamProxy::from_str("socks5://127.0.0.1:1080").unwrap();
+        assert_eq!(
+            get.to_curl(Some(&upstream)),
+            "curl \\\n  \
+             --http2 \\\n  \
+             --proxy socks5://127.0.0.1:1080 \\\n  \
+             'https://api.example.com/v1/login?user=a' \\\n  \
+             -H 'content-type: application/json'"
+        );
+
+        let mut put = request();
+        put.method = Method::PUT;
+        put.body = Bytes::from_static(&[0, 0xff]);
+        assert!(
+            put.to_curl(None)
+                .starts_with("printf '\\x00\\xff' | curl \\\n  -X PUT")
+        );
+        assert!(put.to_curl(None).ends_with("--data-binary @-"));
+    }
+
+    #[test]
+    fn httpie_command() {
+        let upstream = UpstreamProxy::from_str("http://127.0.0.1:8888").unwrap();
+        assert_eq!(
+            request().to_httpie(Some(&upstream)),
+            "http \\\n  \
+             --proxy http:http://127.0.0.1:8888 \\\n  \
+             --proxy https:http://127.0.0.1:8888 \\\n  \
+             --raw '{\"name\":\"it'\\''s me\"}' \\\n  \
+             POST \\\n  \
+             'https://api.example.com/v1/login?user=a' \\\n  \
+             content-type:application/json"
+        );
+
+        let mut request = request();
+        request
+            .headers
+            .insert("x-empty", HeaderValue::from_static(""));
+        request.body = Bytes::new();
+        assert!(request.to_httpie(None).ends_with("'x-empty;'"));
+    }
+
+    #[test]
+    fn reqwest_code() {
+        let upstream = UpstreamProxy::from_str("http://127.0.0.1:8888").unwrap();
+        assert_eq!(
+            request().to_reqwest(Some(&upstream)),
+            r#"#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let client = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::all("http://127.0.0.1:8888")?)
+        .build()?;
+    let response = client
+        .post("https://api.example.com/v1/login?user=a")
+        .header("content-type", "application/json")
+        .body("{\"name\":\"it's me\"}")
+        .send()
+        .await?;
+    println!("{}", response.status());
+    println!("{}", response.text().await?);
+    Ok(())
+}"#
+        );
+
+        let mut request = request();
+        request.method = Method::from_bytes(b"PURGE").unwrap();
+        request.body = Bytes::from_static(&[0, b'"', 0xff]);
+        let code = request.to_reqwest(None);
+        assert!(code.contains(
+            ".request(reqwest::Method::from_bytes(\"PURGE\".as_bytes())?, \"https://api.example.com/v1/login?user=a\")"
+        ));
+        assert!(code.contains(r#".body(b"\x00\"\xff".as_slice())"#));
+    }
+}
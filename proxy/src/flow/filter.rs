@@ -0,0 +1,384 @@
+use std::fmt;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::flow::Flow;
+
+/// A flow filter in the style of mitmproxy's filter expressions.
+///
+/// | Expression  | Matches                                        |
+/// |-------------|------------------------------------------------|
+/// | `~m regex`  | request method                                 |
+/// | `~d regex`  | request host                                   |
+/// | `~u regex`  | request url, also what a bare word matches     |
+/// | `~c code`   | response status code                           |
+/// | `~h regex`  | a request or response header, as `name: value` |
+/// | `~tag name` | a tag added by a script                        |
+/// | `~e`        | flows that failed                              |
+/// | `~star`     | starred flows                                  |
+///
+/// Expressions combine with `!`, `&`, `|` and parentheses, `&` binds tighter
+/// than `|` and two expressions next to each other are joined with `&`.
+/// Regexes are case-insensitive, values with spaces can be double quoted.
+#[derive(Debug, Clone)]
+pub struct FlowFilter {
+    source: String,
+    expr: Expr,
+}
+
+/// A named [`FlowFilter`] choosing which intercepted flows are recorded.
+/// Flows not matching it are still proxied, they are dropped once they finish.
+#[derive(Debug, Clone)]
+pub struct CaptureProfile {
+    pub name: String,
+    pub filter: FlowFilter,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError(String);
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Method(Regex),
+    Domain(Regex),
+    Url(Regex),
+    Code(u16),
+    Header(Regex),
+    Tag(String),
+    Error,
+    Starred,
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+    Word(String),
+}
+
+impl FlowFilter {
+    pub fn parse(source: &str) -> Result<Self, FilterError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(FilterError(format!("unexpected {token:?}")));
+        }
+        Ok(Self {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    /// The expression as it was written.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, flow: &Flow) -> bool {
+        self.expr.matches(flow)
+    }
+}
+
+impl fmt::Display for FlowFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Expr {
+    fn matches(&self, flow: &Flow) -> bool {
+        let request = flow.request.as_ref();
+        let response = flow.response.as_ref();
+        match self {
+            Expr::Method(re) => request.is_some_and(|r| re.is_match(r.method.as_str())),
+            Expr::Domain(re) => request.is_some_and(|r| re.is_match(r.uri.host())),
+            Expr::Url(re) => request.is_some_and(|r| re.is_match(&r.line_pretty())),
+            Expr::Code(code) => response.is_some_and(|r| r.status.as_u16() == *code),
+            Expr::Header(re) => {
+                let headers = request
+                    .map(|r| &r.headers)
+                    .into_iter()
+                    .chain(response.map(|r| &r.headers));
+                headers.flat_map(|h| h.iter()).any(|(name, value)| {
+                    re.is_match(&format!(
+                        "{}: {}",
+                        name.as_str(),
+                        String::from_utf8_lossy(value.as_bytes())
+                    ))
+                })
+            }
+            Expr::Tag(tag) => flow.has_tag(tag),
+            Expr::Error => flow.error.is_some(),
+            Expr::Starred => flow.starred,
+            Expr::Not(expr) => !expr.matches(flow),
+            Expr::And(a, b) => a.matches(flow) && b.matches(flow),
+            Expr::Or(a, b) => a.matches(flow) || b.matches(flow),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, FilterError> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '!' => tokens.push(Token::Not),
+            '&' => tokens.push(Token::And),
+            '|' => tokens.push(Token::Or),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => word.push(c),
+                            None => return Err(FilterError("unterminated quote".to_string())),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(FilterError("unterminated quote".to_string())),
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '&' | '|' | '(' | ')') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.not()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.pos += 1,
+                Some(Token::Not | Token::Open | Token::Word(_)) => {}
+                _ => return Ok(expr),
+            }
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+    }
+
+    fn not(&mut self) -> Result<Expr, FilterError> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Expr, FilterError> {
+        match self.next() {
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(FilterError("missing )".to_string())),
+                }
+            }
+            Some(Token::Word(word)) => match word.as_str() {
+                "~m" => Ok(Expr::Method(self.regex("~m")?)),
+                "~d" => Ok(Expr::Domain(self.regex("~d")?)),
+                "~u" => Ok(Expr::Url(self.regex("~u")?)),
+                "~h" => Ok(Expr::Header(self.regex("~h")?)),
+                "~c" => {
+                    let code = self.argument("~c")?;
+                    code.parse()
+                        .map(Expr::Code)
+                        .map_err(|_| FilterError(format!("invalid status code {code}")))
+                }
+                "~tag" => Ok(Expr::Tag(self.argument("~tag")?)),
+                "~e" => Ok(Expr::Error),
+                "~star" => Ok(Expr::Starred),
+                word if word.starts_with('~') => Err(FilterError(format!("unknown filter {word}"))),
+                _ => Ok(Expr::Url(regex(&word)?)),
+            },
+            Some(token) => Err(FilterError(format!("unexpected {token:?}"))),
+            None => Err(FilterError("expected an expression".to_string())),
+        }
+    }
+
+    fn argument(&mut self, filter: &str) -> Result<String, FilterError> {
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word),
+            _ => Err(FilterError(format!("{filter} needs a value"))),
+        }
+    }
+
+    fn regex(&mut self, filter: &str) -> Result<Regex, FilterError> {
+        regex(&self.argument(filter)?)
+    }
+}
+
+fn regex(pattern: &str) -> Result<Regex, FilterError> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| FilterError(format!("invalid regex {pattern}: {e}")))
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, time::Duration};
+
+    use http::{HeaderValue, Method, StatusCode};
+    use roxy_shared::uri::RUri;
+
+    use crate::flow::{
+        FlowConnection, FlowEvent, FlowStore, InterceptedRequest, InterceptedResponse, test_flow,
+    };
+
+    use super::*;
+
+    fn flow() -> Flow {
+        let mut flow = test_flow(
+            1,
+            Method::POST,
+            "https://api.example.com/v1/login?user=a",
+            StatusCode::UNAUTHORIZED,
+            b"",
+        );
+        flow.request
+            .as_mut()
+            .unwrap()
+            .headers
+            .insert("content-type", HeaderValue::from_static("application/json"));
+        flow.tags = vec!["auth".to_string()];
+        flow
+    }
+
+    fn matches(source: &str) -> bool {
+        FlowFilter::parse(source).unwrap().matches(&flow())
+    }
+
+    #[test]
+    fn simple_filters() {
+        assert!(matches("~m post"));
+        assert!(!matches("~m GET"));
+        assert!(matches("~d example\\.com"));
+        assert!(!matches("~d other.com"));
+        assert!(matches("~u /v1/login"));
+        assert!(matches("login"));
+        assert!(matches("~c 401"));
+        assert!(!matches("~c 200"));
+        assert!(matches("~h \"content-type: application/json\""));
+        assert!(matches("~tag auth"));
+        assert!(!matches("~tag au"));
+        assert!(!matches("~e"));
+        assert!(!matches("~star"));
+    }
+
+    #[test]
+    fn combined_filters() {
+        assert!(matches("~m POST & ~tag auth"));
+        assert!(matches("~m POST ~tag auth"));
+        assert!(!matches("~m POST & !~tag auth"));
+        assert!(matches("~m GET | ~c 401"));
+        assert!(matches("!(~m GET | ~d other.com)"));
+        assert!(matches("~m GET | ~m POST & ~c 401"));
+        assert!(!matches("(~m GET | ~m POST) & ~c 200"));
+    }
+
+    #[test]
+    fn invalid_filters() {
+        assert!(FlowFilter::parse("").is_err());
+        assert!(FlowFilter::parse("~m").is_err());
+        assert!(FlowFilter::parse("~c abc").is_err());
+        assert!(FlowFilter::parse("~x foo").is_err());
+        assert!(FlowFilter::parse("(~m GET").is_err());
+        assert!(FlowFilter::parse("~m GET)").is_err());
+        assert!(FlowFilter::parse("~d \"open").is_err());
+        assert!(FlowFilter::parse("~u [").is_err());
+        assert_eq!(
+            FlowFilter::parse(" ~tag auth ").unwrap().to_string(),
+            "~tag auth"
+        );
+    }
+
+    #[tokio::test]
+    async fn capture_profile_drops_unmatched_flows() {
+        let store = FlowStore::new();
+        store.set_capture_profile(Some(CaptureProfile {
+            name: "api".to_string(),
+            filter: FlowFilter::parse("~d api\\.example\\.com").unwrap(),
+        }));
+        let mut rx = store.subscribe();
+
+        let client = FlowConnection {
+            addr: ([127, 0, 0, 1], 0).into(),
+        };
+        let mut ids = vec![];
+        for uri in ["https://api.example.com/a", "https://cdn.example.com/b"] {
+            let request = InterceptedRequest {
+                uri: RUri::from_str(uri).unwrap(),
+                ..Default::default()
+            };
+            let id = store.new_flow(client, request).await;
+            store.post_event(id, FlowEvent::Response(InterceptedResponse::default()));
+            ids.push(id);
+        }
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while store.dropped() == 0 {
+                rx.changed().await.unwrap();
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(*store.ordered_ids.read().await, vec![ids[0]]);
+        assert!(store.get_flow_by_id(ids[1]).await.is_none());
+    }
+}
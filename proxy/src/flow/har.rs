@@ -0,0 +1,651 @@
+//! HTTP Archive files, as saved from a browser's network panel. Entries are
+//! read into flows that can be inspected and replayed, and flows are written
+//! as entries for browsers and other tools to open.
+
+use std::{
+    io::{Read, Write},
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::Bytes;
+use cow_utils::CowUtils;
+use http::{
+    HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Version,
+    header::{CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING},
+};
+use roxy_shared::{
+    alpn::AlpnProtocol, content::get_content_encoding, uri::RUri, version::HttpVersion,
+};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime, format_description::well_known::Rfc3339};
+use tracing::{debug, warn};
+
+use crate::flow::{
+    Flow, FlowCerts, FlowConnection, InterceptedRequest, InterceptedResponse, Timing,
+    error::{FlowError, FlowErrorKind},
+    next_id,
+    wire::FlowWire,
+};
+
+#[derive(Debug)]
+pub enum HarError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Invalid(String),
+}
+
+impl std::fmt::Display for HarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for HarError {}
+
+impl From<std::io::Error> for HarError {
+    fn from(value: std::io::Error) -> Self {
+        HarError::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for HarError {
+    fn from(value: serde_json::Error) -> Self {
+        HarError::Json(value)
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> HarError {
+    HarError::Invalid(msg.into())
+}
+
+#[derive(Debug, Deserialize)]
+struct Har {
+    log: HarLog,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarLog {
+    #[serde(default)]
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarEntry {
+    started_date_time: String,
+    /// Milliseconds from the request to the end of the response.
+    #[serde(default)]
+    time: f64,
+    request: HarRequest,
+    response: Option<HarResponse>,
+    #[serde(rename = "serverIPAddress")]
+    server_ip_address: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarRequest {
+    method: String,
+    url: String,
+    #[serde(default)]
+    http_version: String,
+    #[serde(default)]
+    headers: Vec<HarHeader>,
+    post_data: Option<HarPostData>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarResponse {
+    status: u16,
+    #[serde(default)]
+    http_version: String,
+    #[serde(default)]
+    headers: Vec<HarHeader>,
+    content: Option<HarContent>,
+    /// Set by Chromium for requests that got no response, e.g.
+    /// `net::ERR_CONNECTION_REFUSED`.
+    #[serde(rename = "_error")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarPostData {
+    text: Option<String>,
+    /// Not in the spec but written by some tools for binary bodies.
+    encoding: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarContent {
+    text: Option<String>,
+    encoding: Option<String>,
+}
+
+/// HTTP/2 and HTTP/3 pseudo headers such as `:authority` are left out, the
+/// url already carries them.
+fn headers_from(headers: &[HarHeader]) -> Result<HeaderMap, HarError> {
+    let mut map = HeaderMap::new();
+    for header in headers.iter().filter(|h| !h.name.starts_with(':')) {
+        let name = HeaderName::from_str(&header.name)
+            .map_err(|e| invalid(format!("header name {} {e}", header.name)))?;
+        let value = HeaderValue::from_str(&header.value)
+            .map_err(|e| invalid(format!("header value {e}")))?;
+        map.append(name, value);
+    }
+    map.remove(CONTENT_LENGTH);
+    map.remove(TRANSFER_ENCODING);
+    Ok(map)
+}
+
+/// Bodies in a HAR file are already decoded, binary ones are base64.
+fn body_from(text: Option<&str>, encoding: Option<&str>) -> Result<Bytes, HarError> {
+    match (text, encoding) {
+        (None, _) => Ok(Bytes::new()),
+        (Some(text), Some("base64")) => STANDARD
+            .decode(text)
+            .map(Bytes::from)
+            .map_err(|e| invalid(format!("base64 body {e}"))),
+        (Some(text), _) => Ok(Bytes::copy_from_slice(text.as_bytes())),
+    }
+}
+
+/// Browsers write `h2` and `h3` as often as `HTTP/2.0`, in any case.
+fn version_from(version: &str) -> HttpVersion {
+    match version.cow_to_ascii_uppercase().as_ref() {
+        "H2" => HttpVersion(Version::HTTP_2),
+        "H3" => HttpVersion(Version::HTTP_3),
+        version => HttpVersion::from_str(version).unwrap_or_default(),
+    }
+}
+
+fn request_from(
+    request: &HarRequest,
+    timestamp: OffsetDateTime,
+) -> Result<InterceptedRequest, HarError> {
+    let uri = RUri::from_str(&request.url)
+        .map_err(|e| invalid(format!("request url {} {e}", request.url)))?;
+    let version = version_from(&request.http_version);
+    let alpn = match version.0 {
+        Version::HTTP_3 => AlpnProtocol::Http3,
+        Version::HTTP_2 => AlpnProtocol::Http2,
+        _ if uri.is_tls() => AlpnProtocol::Http1,
+        _ => AlpnProtocol::None,
+    };
+    let headers = headers_from(&request.headers)?;
+    let post_data = request.post_data.as_ref();
+    Ok(InterceptedRequest {
+        timestamp,
+        encoding: get_content_encoding(&headers),
+        alpn,
+        method: Method::from_bytes(request.method.as_bytes())
+            .map_err(|e| invalid(format!("request method {e}")))?,
+        version,
+        headers,
+        body: body_from(
+            post_data.and_then(|p| p.text.as_deref()),
+            post_data.and_then(|p| p.encoding.as_deref()),
+        )?,
+        uri,
+        ..Default::default()
+    })
+}
+
+fn response_from(
+    response: &HarResponse,
+    timestamp: OffsetDateTime,
+) -> Result<InterceptedResponse, HarError> {
+    let headers = headers_from(&response.headers)?;
+    let content = response.content.as_ref();
+    Ok(InterceptedResponse {
+        timestamp,
+        status: StatusCode::from_u16(response.status)
+            .map_err(|_| invalid(format!("response status {}", response.status)))?,
+        version: version_from(&response.http_version),
+        encoding: get_content_encoding(&headers),
+        headers,
+        body: body_from(
+            content.and_then(|c| c.text.as_deref()),
+            content.and_then(|c| c.encoding.as_deref()),
+        )?,
+        ..Default::default()
+    })
+}
+
+async fn flow_from(entry: HarEntry) -> Result<Flow, HarError> {
+    let started = OffsetDateTime::parse(&entry.started_date_time, &Rfc3339)
+        .map_err(|e| invalid(format!("startedDateTime {e}")))?;
+    let finished = started + Duration::microseconds((entry.time.max(0.0) * 1000.0) as i64);
+    let request = request_from(&entry.request, started)?;
+    let server_connection = entry
+        .server_ip_address
+        .as_deref()
+        .map(|ip| ip.trim_start_matches('[').trim_end_matches(']'))
+        .and_then(|ip| IpAddr::from_str(ip).ok())
+        .map(|ip| FlowConnection {
+            addr: SocketAddr::new(ip, request.uri.port()),
+        });
+    // A status of 0 is how browsers record a request that got no response.
+    let (response, error) = match entry.response {
+        Some(response) if response.status != 0 => (Some(response_from(&response, finished)?), None),
+        Some(response) => (
+            None,
+            Some(FlowError::new(
+                FlowErrorKind::Other,
+                response.error.unwrap_or_else(|| "No response".to_string()),
+            )),
+        ),
+        None => (None, None),
+    };
+    Ok(Flow {
+        id: next_id(),
+        timing: Timing {
+            request_complete: Some(started),
+            response_complete: Some(finished),
+            ..Default::default()
+        },
+        client_connection: FlowConnection {
+            addr: SocketAddr::from(([0, 0, 0, 0], 0)),
+        },
+        request: Some(request),
+        server_connection,
+        response,
+        error,
+        certs: FlowCerts::default(),
+        messages: vec![],
+        passthrough: false,
+        tunnel: None,
+        tags: vec![],
+        redirects: vec![],
+        interim_responses: vec![],
+        version_choice: None,
+        starred: false,
+        script_log: vec![],
+        wire: FlowWire::default(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct HarOut<'a> {
+    log: HarLogOut<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct HarLogOut<'a> {
+    version: &'static str,
+    creator: HarCreator,
+    entries: Vec<HarEntryOut<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct HarCreator {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarEntryOut<'a> {
+    started_date_time: String,
+    time: f64,
+    request: HarRequestOut,
+    response: HarResponseOut<'a>,
+    #[serde(rename = "serverIPAddress", skip_serializing_if = "Option::is_none")]
+    server_ip_address: Option<String>,
+    cache: HarCache,
+    timings: HarTimings,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarRequestOut {
+    method: String,
+    url: String,
+    http_version: String,
+    cookies: Vec<HarHeaderOut>,
+    headers: Vec<HarHeaderOut>,
+    query_string: Vec<HarHeaderOut>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    post_data: Option<HarContentOut>,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarResponseOut<'a> {
+    status: u16,
+    status_text: &'a str,
+    http_version: String,
+    cookies: Vec<HarHeaderOut>,
+    headers: Vec<HarHeaderOut>,
+    content: HarContentOut,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: i64,
+    #[serde(rename = "_error", skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct HarHeaderOut {
+    name: String,
+    value: String,
+}
+
+/// The content of a response, or the post data of a request.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarContentOut {
+    size: usize,
+    mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct HarCache {}
+
+/// Only the whole wait is known per flow, `send` and `receive` are left at 0.
+#[derive(Debug, Serialize)]
+struct HarTimings {
+    send: f64,
+    wait: f64,
+    receive: f64,
+}
+
+fn headers_out(headers: &HeaderMap) -> Vec<HarHeaderOut> {
+    headers
+        .iter()
+        .map(|(name, value)| HarHeaderOut {
+            name: name.to_string(),
+            value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+        })
+        .collect()
+}
+
+/// Bodies are written decoded, as text when they are UTF-8 and as base64
+/// otherwise.
+fn content_out(body: std::io::Result<Bytes>, headers: &HeaderMap) -> HarContentOut {
+    let body = body.unwrap_or_else(|err| {
+        warn!("Failed to read spilled body {err}");
+        Bytes::new()
+    });
+    let mime_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let (text, encoding) = match std::str::from_utf8(&body) {
+        Ok(_) if body.is_empty() => (None, None),
+        Ok(text) => (Some(text.to_string()), None),
+        Err(_) => (Some(STANDARD.encode(&body)), Some("base64")),
+    };
+    HarContentOut {
+        size: body.len(),
+        mime_type,
+        text,
+        encoding,
+    }
+}
+
+fn entry_out(flow: &Flow) -> Option<HarEntryOut<'_>> {
+    let request = flow.request.as_ref()?;
+    let started = request.timestamp;
+    let finished = flow
+        .timing
+        .response_complete
+        .or(flow.response.as_ref().map(|r| r.timestamp))
+        .unwrap_or(started);
+    let time = (finished - started).as_seconds_f64().max(0.0) * 1000.0;
+    let post_data = content_out(request.read_body(), &request.headers);
+    let request_out = HarRequestOut {
+        method: request.method.to_string(),
+        url: request.uri.to_string(),
+        http_version: request.version.to_string(),
+        cookies: vec![],
+        headers: headers_out(&request.headers),
+        query_string: url::form_urlencoded::parse(request.uri.query().as_bytes())
+            .map(|(name, value)| HarHeaderOut {
+                name: name.into_owned(),
+                value: value.into_owned(),
+            })
+            .collect(),
+        headers_size: -1,
+        body_size: post_data.size as i64,
+        post_data: post_data.text.is_some().then_some(post_data),
+    };
+    // Flows that got no response are written the way browsers record them
+    let response_out = match &flow.response {
+        Some(response) => {
+            let content = content_out(response.read_body(), &response.headers);
+            HarResponseOut {
+                status: response.status.as_u16(),
+                status_text: response.status.canonical_reason().unwrap_or_default(),
+                http_version: response.version.to_string(),
+                cookies: vec![],
+                headers: headers_out(&response.headers),
+                redirect_url: String::new(),
+                headers_size: -1,
+                body_size: content.size as i64,
+                content,
+                error: None,
+            }
+        }
+        None => HarResponseOut {
+            status: 0,
+            status_text: "",
+            http_version: String::new(),
+            cookies: vec![],
+            headers: vec![],
+            content: content_out(Ok(Bytes::new()), &HeaderMap::new()),
+            redirect_url: String::new(),
+            headers_size: -1,
+            body_size: -1,
+            error: Some(
+                flow.error
+                    .as_ref()
+                    .map_or("No response", |e| e.message.as_str()),
+            ),
+        },
+    };
+    Some(HarEntryOut {
+        started_date_time: started.format(&Rfc3339).unwrap_or_default(),
+        time,
+        request: request_out,
+        response: response_out,
+        server_ip_address: flow.server_connection.map(|c| c.addr.ip().to_string()),
+        cache: HarCache {},
+        timings: HarTimings {
+            send: 0.0,
+            wait: time,
+            receive: 0.0,
+        },
+    })
+}
+
+/// Writes the flows with a request as the entries of a HAR file, returning
+/// how many were written. Passthrough flows are left out, nothing of their
+/// requests was seen.
+pub(crate) fn write_har<'a>(
+    mut writer: impl Write,
+    flows: impl Iterator<Item = &'a Flow>,
+) -> Result<usize, HarError> {
+    let entries: Vec<_> = flows
+        .filter(|flow| !flow.passthrough && flow.tunnel.is_none())
+        .filter_map(entry_out)
+        .collect();
+    let count = entries.len();
+    let har = HarOut {
+        log: HarLogOut {
+            version: "1.2",
+            creator: HarCreator {
+                name: "roxy",
+                version: env!("CARGO_PKG_VERSION"),
+            },
+            entries,
+        },
+    };
+    serde_json::to_writer(&mut writer, &har)?;
+    writer.flush()?;
+    debug!("Wrote {count} HAR entries");
+    Ok(count)
+}
+
+/// Reads the entries of a HAR file as flows, in the order they were
+/// recorded. Each flow gets a new id.
+pub(crate) async fn read_har(mut reader: impl Read) -> Result<Vec<Flow>, HarError> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let har: Har = serde_json::from_slice(&data)?;
+    let mut flows = Vec::with_capacity(har.log.entries.len());
+    for entry in har.log.entries {
+        flows.push(flow_from(entry).await?);
+    }
+    debug!("Read {} HAR entries", flows.len());
+    Ok(flows)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HAR: &str = r#"{
+      "log": {
+        "version": "1.2",
+        "creator": {"name": "WebInspector", "version": "537.36"},
+        "entries": [
+          {
+            "startedDateTime": "2024-05-01T10:00:00.000Z",
+            "time": 120.5,
+            "serverIPAddress": "[2001:db8::1]",
+            "request": {
+              "method": "POST",
+              "url": "https://api.example.com/v1/orders?draft=1",
+              "httpVersion": "h2",
+              "headers": [
+                {"name": ":authority", "value": "api.example.com"},
+                {"name": "content-type", "value": "application/json"},
+                {"name": "content-length", "value": "13"}
+              ],
+              "postData": {"mimeType": "application/json", "text": "{\"id\":\"abc\"}"}
+            },
+            "response": {
+              "status": 201,
+              "httpVersion": "h2",
+              "headers": [{"name": "content-encoding", "value": "gzip"}],
+              "content": {"mimeType": "image/png", "text": "iVBORw==", "encoding": "base64"}
+            }
+          },
+          {
+            "startedDateTime": "2024-05-01T10:00:01.000+02:00",
+            "time": 3,
+            "request": {"method": "GET", "url": "http://localhost:8080/health", "headers": []},
+            "response": {"status": 0, "headers": [], "_error": "net::ERR_CONNECTION_REFUSED"}
+          }
+        ]
+      }
+    }"#;
+
+    #[tokio::test]
+    async fn reads_browser_entries() {
+        let flows = read_har(HAR.as_bytes()).await.unwrap();
+        assert_eq!(flows.len(), 2);
+
+        let flow = &flows[0];
+        let request = flow.request.as_ref().unwrap();
+        assert_eq!(request.method, Method::POST);
+        assert_eq!(
+            request.uri.to_string(),
+            "https://api.example.com/v1/orders?draft=1"
+        );
+        assert_eq!(request.version, HttpVersion(Version::HTTP_2));
+        assert_eq!(request.alpn, AlpnProtocol::Http2);
+        assert_eq!(request.body, Bytes::from_static(b"{\"id\":\"abc\"}"));
+        assert!(request.headers.get(":authority").is_none());
+        assert!(request.headers.get(CONTENT_LENGTH).is_none());
+        assert_eq!(
+            flow.server_connection.map(|c| c.addr),
+            Some("[2001:db8::1]:443".parse().unwrap())
+        );
+
+        let response = flow.response.as_ref().unwrap();
+        assert_eq!(response.status, StatusCode::CREATED);
+        assert_eq!(response.body, Bytes::from_static(&[0x89, b'P', b'N', b'G']));
+        assert!(response.encoding.is_some());
+        assert_eq!(
+            flow.timing.response_complete.unwrap() - flow.timing.request_complete.unwrap(),
+            Duration::microseconds(120_500)
+        );
+
+        let failed = &flows[1];
+        assert!(failed.response.is_none());
+        assert_eq!(
+            failed.error.as_ref().map(|e| e.message.as_str()),
+            Some("net::ERR_CONNECTION_REFUSED")
+        );
+        assert_ne!(flows[0].id, failed.id);
+    }
+
+    #[tokio::test]
+    async fn writes_entries_read_back() {
+        let flows = read_har(HAR.as_bytes()).await.unwrap();
+        let mut out = vec![];
+        assert_eq!(write_har(&mut out, flows.iter()).unwrap(), 2);
+
+        let har: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(har["log"]["version"], "1.2");
+        let entry = &har["log"]["entries"][0];
+        assert_eq!(entry["request"]["queryString"][0]["name"], "draft");
+        assert_eq!(entry["response"]["content"]["encoding"], "base64");
+
+        let read = read_har(out.as_slice()).await.unwrap();
+        assert_eq!(
+            read[0].request.as_ref().unwrap().uri,
+            flows[0].request.as_ref().unwrap().uri
+        );
+        assert_eq!(
+            read[0].request.as_ref().unwrap().body,
+            Bytes::from_static(b"{\"id\":\"abc\"}")
+        );
+        assert_eq!(
+            read[0].response.as_ref().unwrap().body,
+            Bytes::from_static(&[0x89, b'P', b'N', b'G'])
+        );
+        assert_eq!(
+            read[0].timing.response_complete.unwrap() - read[0].timing.request_complete.unwrap(),
+            Duration::microseconds(120_500)
+        );
+        assert!(read[1].response.is_none());
+        assert_eq!(
+            read[1].error.as_ref().map(|e| e.message.as_str()),
+            Some("net::ERR_CONNECTION_REFUSED")
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_files() {
+        assert!(matches!(
+            read_har("{}".as_bytes()).await,
+            Err(HarError::Json(_))
+        ));
+        let bad_url = HAR.cow_replace("http://localhost:8080/health", "not a url");
+        assert!(matches!(
+            read_har(bad_url.as_bytes()).await,
+            Err(HarError::Invalid(_))
+        ));
+    }
+}
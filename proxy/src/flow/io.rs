@@ -0,0 +1,935 @@
+//! mitmproxy's flow dump format, as written by `mitmdump -w` and read by
+//! `mitmproxy -r`: the state of each flow tnetstring encoded, one after the
+//! other.
+
+use std::{
+    io::{Read, Write},
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use bytes::Bytes;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use roxy_shared::{
+    alpn::AlpnProtocol,
+    content::{Encodings, decode_body, encode_body, get_content_encoding},
+    uri::RUri,
+    version::HttpVersion,
+};
+use time::OffsetDateTime;
+use tracing::{debug, warn};
+
+use crate::flow::{
+    Flow, FlowCerts, FlowConnection, InterceptedRequest, InterceptedResponse, Timing,
+    error::{FlowError, FlowErrorKind},
+    id::FlowId,
+    wire::FlowWire,
+};
+
+/// The flow state version written, mitmproxy migrates older versions on load.
+const FLOW_FORMAT_VERSION: i64 = 20;
+
+/// Digits allowed in a tnetstring length, a sanity limit on corrupt input.
+const MAX_LENGTH_DIGITS: usize = 12;
+
+#[derive(Debug)]
+pub enum DumpError {
+    Io(std::io::Error),
+    Invalid(String),
+}
+
+impl std::fmt::Display for DumpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for DumpError {}
+
+impl From<std::io::Error> for DumpError {
+    fn from(value: std::io::Error) -> Self {
+        DumpError::Io(value)
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> DumpError {
+    DumpError::Invalid(msg.into())
+}
+
+/// A tnetstring value. Python `bytes` and `str` are kept apart as mitmproxy
+/// relies on the difference when loading.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Bytes(Vec<u8>),
+    Str(String),
+    List(Vec<Value>),
+    Dict(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut nested = vec![];
+        let (payload, tag): (&[u8], u8) = match self {
+            Value::Null => (&[], b'~'),
+            Value::Bool(true) => (b"true".as_slice(), b'!'),
+            Value::Bool(false) => (b"false".as_slice(), b'!'),
+            Value::Int(i) => {
+                nested = i.to_string().into_bytes();
+                (nested.as_slice(), b'#')
+            }
+            Value::Float(f) => {
+                nested = format!("{f:?}").into_bytes();
+                (nested.as_slice(), b'^')
+            }
+            Value::Bytes(b) => (b.as_slice(), b','),
+            Value::Str(s) => (s.as_bytes(), b';'),
+            Value::List(items) => {
+                for item in items {
+                    item.encode(&mut nested);
+                }
+                (nested.as_slice(), b']')
+            }
+            Value::Dict(entries) => {
+                for (key, value) in entries {
+                    Value::Str(key.clone()).encode(&mut nested);
+                    value.encode(&mut nested);
+                }
+                (nested.as_slice(), b'}')
+            }
+        };
+        out.extend_from_slice(payload.len().to_string().as_bytes());
+        out.push(b':');
+        out.extend_from_slice(payload);
+        out.push(tag);
+    }
+
+    /// Decodes the first value in `data`, returning it with the rest of `data`.
+    fn decode(data: &[u8]) -> Result<(Value, &[u8]), DumpError> {
+        let colon = data
+            .iter()
+            .take(MAX_LENGTH_DIGITS + 1)
+            .position(|b| *b == b':')
+            .ok_or_else(|| invalid("missing tnetstring length"))?;
+        let len: usize = std::str::from_utf8(&data[..colon])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid("invalid tnetstring length"))?;
+        let rest = &data[colon + 1..];
+        if rest.len() <= len {
+            return Err(invalid("truncated tnetstring"));
+        }
+        let (payload, tag, rest) = (&rest[..len], rest[len], &rest[len + 1..]);
+        let text = || std::str::from_utf8(payload).map_err(|e| invalid(format!("{e}")));
+        let value = match tag {
+            b'~' if payload.is_empty() => Value::Null,
+            b'!' => match payload {
+                b"true" => Value::Bool(true),
+                b"false" => Value::Bool(false),
+                _ => return Err(invalid("invalid tnetstring bool")),
+            },
+            b'#' => Value::Int(
+                text()?
+                    .parse()
+                    .map_err(|e| invalid(format!("tnetstring int {e}")))?,
+            ),
+            b'^' => Value::Float(
+                text()?
+                    .parse()
+                    .map_err(|e| invalid(format!("tnetstring float {e}")))?,
+            ),
+            b',' => Value::Bytes(payload.to_vec()),
+            b';' => Value::Str(text()?.to_string()),
+            b']' => {
+                let mut items = vec![];
+                let mut remaining = payload;
+                while !remaining.is_empty() {
+                    let (item, next) = Value::decode(remaining)?;
+                    items.push(item);
+                    remaining = next;
+                }
+                Value::List(items)
+            }
+            b'}' => {
+                let mut entries = vec![];
+                let mut remaining = payload;
+                while !remaining.is_empty() {
+                    let (key, next) = Value::decode(remaining)?;
+                    let key = key
+                        .as_str()
+                        .ok_or_else(|| invalid("tnetstring dict key is not a string"))?
+                        .to_string();
+                    let (value, next) = Value::decode(next)?;
+                    entries.push((key, value));
+                    remaining = next;
+                }
+                Value::Dict(entries)
+            }
+            tag => return Err(invalid(format!("unknown tnetstring type {}", tag as char))),
+        };
+        Ok((value, rest))
+    }
+
+    fn get(&self, key: &str) -> Option<&Value> {
+        let value = match self {
+            Value::Dict(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        };
+        // Python's None is as good as a missing key
+        value.filter(|v| **v != Value::Null)
+    }
+
+    fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(b) => Some(b),
+            Value::Str(s) => Some(s.as_bytes()),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            Value::Bytes(b) => std::str::from_utf8(b).ok(),
+            _ => None,
+        }
+    }
+
+    fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn as_time(&self) -> Option<OffsetDateTime> {
+        let seconds = match self {
+            Value::Float(f) => *f,
+            Value::Int(i) => *i as f64,
+            _ => return None,
+        };
+        OffsetDateTime::from_unix_timestamp_nanos((seconds * 1e9) as i128).ok()
+    }
+}
+
+fn dict(entries: Vec<(&str, Value)>) -> Value {
+    Value::Dict(
+        entries
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+    )
+}
+
+fn string(s: impl Into<String>) -> Value {
+    Value::Str(s.into())
+}
+
+fn raw(b: impl Into<Vec<u8>>) -> Value {
+    Value::Bytes(b.into())
+}
+
+fn seconds(t: Option<OffsetDateTime>) -> Value {
+    match t {
+        Some(t) => Value::Float(t.unix_timestamp_nanos() as f64 / 1e9),
+        None => Value::Null,
+    }
+}
+
+fn address(addr: Option<SocketAddr>) -> Value {
+    match addr {
+        Some(addr) => Value::List(vec![
+            string(addr.ip().to_string()),
+            Value::Int(addr.port().into()),
+        ]),
+        None => Value::Null,
+    }
+}
+
+fn address_from(value: Option<&Value>) -> Option<SocketAddr> {
+    let parts = value?.as_list()?;
+    let ip: IpAddr = parts.first()?.as_str()?.parse().ok()?;
+    let port = u16::try_from(parts.get(1)?.as_int()?).ok()?;
+    Some(SocketAddr::new(ip, port))
+}
+
+/// mitmproxy ids are uuids, the last 16 hex digits hold the roxy id so it
+/// survives a round trip. `kind` tells flows and their connections apart.
+fn uuid(kind: u32, id: FlowId) -> String {
+    let id = id.0 as u64;
+    format!(
+        "{kind:08x}-0000-4000-{:04x}-{:012x}",
+        id >> 48,
+        id & 0xffff_ffff_ffff
+    )
+}
+
+fn id_from_uuid(uuid: &str) -> Option<FlowId> {
+    let hex: String = uuid.chars().filter(|c| *c != '-').collect();
+    let tail = hex.get(hex.len().checked_sub(16)?..)?;
+    u64::from_str_radix(tail, 16)
+        .ok()
+        .map(|id| FlowId(id as i64))
+}
+
+fn headers(headers: &HeaderMap) -> Value {
+    Value::List(
+        headers
+            .iter()
+            .map(|(k, v)| Value::List(vec![raw(k.as_str()), raw(v.as_bytes())]))
+            .collect(),
+    )
+}
+
+fn headers_from(value: Option<&Value>) -> Result<HeaderMap, DumpError> {
+    let mut map = HeaderMap::new();
+    for pair in value.and_then(Value::as_list).unwrap_or_default() {
+        let (Some(name), Some(value)) = (
+            pair.as_list()
+                .and_then(|p| p.first())
+                .and_then(Value::as_bytes),
+            pair.as_list()
+                .and_then(|p| p.get(1))
+                .and_then(Value::as_bytes),
+        ) else {
+            return Err(invalid("header is not a name and value pair"));
+        };
+        let name = HeaderName::from_bytes(name).map_err(|e| invalid(format!("header name {e}")))?;
+        let value =
+            HeaderValue::from_bytes(value).map_err(|e| invalid(format!("header value {e}")))?;
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+/// The body as it went over the wire, with its content encoding applied.
+fn content(body: std::io::Result<Bytes>, encoding: &Option<Vec<Encodings>>) -> Value {
+    let body = match body {
+        Ok(body) => body,
+        Err(err) => {
+            warn!("Failed to read spilled body {err}");
+            Bytes::new()
+        }
+    };
+    let body = match encoding {
+        Some(enc) => encode_body(&body, enc).unwrap_or_else(|err| {
+            warn!("Failed to encode body {err}");
+            body
+        }),
+        None => body,
+    };
+    raw(body.to_vec())
+}
+
+/// Removes the content encoding of a body read from a dump.
+fn content_from(value: Option<&Value>, encoding: &Option<Vec<Encodings>>) -> Bytes {
+    let body = Bytes::from(
+        value
+            .and_then(Value::as_bytes)
+            .map(<[u8]>::to_vec)
+            .unwrap_or_default(),
+    );
+    match encoding {
+        Some(enc) => decode_body(&body, enc).unwrap_or_else(|err| {
+            warn!("Failed to decode body {err}");
+            body
+        }),
+        None => body,
+    }
+}
+
+fn connection(
+    id: String,
+    peername: Option<SocketAddr>,
+    tls: bool,
+    sni: Option<&str>,
+    alpn: &AlpnProtocol,
+) -> Vec<(&'static str, Value)> {
+    vec![
+        ("id", string(id)),
+        ("peername", address(peername)),
+        ("error", Value::Null),
+        ("tls", Value::Bool(tls)),
+        ("certificate_list", Value::List(vec![])),
+        (
+            "alpn",
+            match alpn {
+                AlpnProtocol::None => Value::Null,
+                alpn => raw(alpn.to_bytes()),
+            },
+        ),
+        ("alpn_offers", Value::List(vec![])),
+        ("cipher", Value::Null),
+        ("cipher_list", Value::List(vec![])),
+        ("tls_version", Value::Null),
+        ("sni", sni.map(string).unwrap_or(Value::Null)),
+        // Closed
+        ("state", Value::Int(0)),
+        ("transport_protocol", string("tcp")),
+    ]
+}
+
+fn client_conn(flow: &Flow, request: &InterceptedRequest) -> Value {
+    let tls = request.uri.is_tls();
+    let sni = tls.then(|| request.uri.host());
+    let mut state = connection(
+        uuid(1, flow.id),
+        Some(flow.client_connection.addr),
+        tls,
+        sni,
+        &request.alpn,
+    );
+    state.extend([
+        (
+            "sockname",
+            address(Some(SocketAddr::from(([0, 0, 0, 0], 0)))),
+        ),
+        ("proxy_mode", string("regular")),
+        ("mitmcert", Value::Null),
+        (
+            "timestamp_start",
+            seconds(Some(
+                flow.timing
+                    .client_conn_established
+                    .unwrap_or(request.timestamp),
+            )),
+        ),
+        (
+            "timestamp_tls_setup",
+            seconds(flow.timing.client_conn_tls_handshake),
+        ),
+        ("timestamp_end", seconds(flow.timing.client_conn_closed)),
+    ]);
+    dict(state)
+}
+
+fn server_conn(flow: &Flow, request: &InterceptedRequest) -> Value {
+    let tls = request.uri.is_tls();
+    let sni = tls.then(|| request.uri.host());
+    let mut state = connection(
+        uuid(2, flow.id),
+        flow.server_connection.map(|c| c.addr),
+        tls,
+        sni,
+        &request.alpn,
+    );
+    state.extend([
+        ("sockname", Value::Null),
+        (
+            "address",
+            Value::List(vec![
+                string(request.uri.host()),
+                Value::Int(request.uri.port().into()),
+            ]),
+        ),
+        ("via", Value::Null),
+        (
+            "timestamp_start",
+            seconds(flow.timing.server_conn_initiated),
+        ),
+        (
+            "timestamp_tcp_setup",
+            seconds(flow.timing.server_conn_tcp_handshake),
+        ),
+        (
+            "timestamp_tls_setup",
+            seconds(flow.timing.server_conn_tls_handshake),
+        ),
+        ("timestamp_end", seconds(flow.timing.server_conn_closed)),
+    ]);
+    dict(state)
+}
+
+fn request_state(flow: &Flow, request: &InterceptedRequest) -> Value {
+    dict(vec![
+        ("host", string(request.uri.host())),
+        ("port", Value::Int(request.uri.port().into())),
+        ("method", raw(request.method.as_str())),
+        ("scheme", raw(request.scheme().to_string())),
+        ("authority", raw("")),
+        ("path", raw(request.uri.path_and_query())),
+        ("http_version", raw(request.version.to_string())),
+        ("headers", headers(&request.headers)),
+        ("content", content(request.read_body(), &request.encoding)),
+        (
+            "trailers",
+            request
+                .trailers
+                .as_ref()
+                .map(headers)
+                .unwrap_or(Value::Null),
+        ),
+        ("timestamp_start", seconds(Some(request.timestamp))),
+        (
+            "timestamp_end",
+            seconds(Some(
+                flow.timing.request_complete.unwrap_or(request.timestamp),
+            )),
+        ),
+    ])
+}
+
+fn response_state(flow: &Flow, response: &InterceptedResponse) -> Value {
+    dict(vec![
+        ("http_version", raw(response.version.to_string())),
+        ("status_code", Value::Int(response.status.as_u16().into())),
+        (
+            "reason",
+            raw(response.status.canonical_reason().unwrap_or_default()),
+        ),
+        ("headers", headers(&response.headers)),
+        ("content", content(response.read_body(), &response.encoding)),
+        (
+            "trailers",
+            response
+                .trailers
+                .as_ref()
+                .map(headers)
+                .unwrap_or(Value::Null),
+        ),
+        ("timestamp_start", seconds(Some(response.timestamp))),
+        (
+            "timestamp_end",
+            seconds(Some(
+                flow.timing.response_complete.unwrap_or(response.timestamp),
+            )),
+        ),
+    ])
+}
+
+/// The mitmproxy state of `flow`, `None` for flows without a request such as
+/// passthrough tunnels.
+fn flow_state(flow: &Flow) -> Option<Value> {
+    let request = flow.request.as_ref()?;
+    let error = match &flow.error {
+        Some(error) => dict(vec![
+            ("msg", string(error.message.as_str())),
+            (
+                "timestamp",
+                seconds(Some(
+                    flow.timing.response_complete.unwrap_or(request.timestamp),
+                )),
+            ),
+        ]),
+        None => Value::Null,
+    };
+    Some(dict(vec![
+        ("version", Value::Int(FLOW_FORMAT_VERSION)),
+        ("type", string("http")),
+        ("id", string(uuid(0, flow.id))),
+        ("error", error),
+        ("client_conn", client_conn(flow, request)),
+        ("server_conn", server_conn(flow, request)),
+        ("intercepted", Value::Bool(false)),
+        ("is_replay", Value::Null),
+        ("marked", string("")),
+        ("comment", string("")),
+        ("timestamp_created", seconds(Some(request.timestamp))),
+        ("request", request_state(flow, request)),
+        (
+            "response",
+            flow.response
+                .as_ref()
+                .map(|r| response_state(flow, r))
+                .unwrap_or(Value::Null),
+        ),
+        ("websocket", Value::Null),
+    ]))
+}
+
+fn request_from(state: &Value, alpn: AlpnProtocol) -> Result<InterceptedRequest, DumpError> {
+    let field = |name: &str| {
+        state
+            .get(name)
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid(format!("request {name}")))
+    };
+    let scheme = field("scheme")?;
+    let authority = match state.get("authority").and_then(Value::as_str) {
+        Some(authority) if !authority.is_empty() => authority.to_string(),
+        _ => {
+            let host = field("host")?;
+            let host = if host.contains(':') {
+                format!("[{host}]")
+            } else {
+                host.to_string()
+            };
+            match state.get("port").and_then(Value::as_int) {
+                Some(443) if scheme == "https" => host,
+                Some(80) if scheme == "http" => host,
+                Some(port) => format!("{host}:{port}"),
+                None => host,
+            }
+        }
+    };
+    let uri = format!("{scheme}://{authority}{}", field("path")?);
+    let headers = headers_from(state.get("headers"))?;
+    let encoding = get_content_encoding(&headers);
+    Ok(InterceptedRequest {
+        timestamp: state
+            .get("timestamp_start")
+            .and_then(Value::as_time)
+            .unwrap_or_else(OffsetDateTime::now_utc),
+        uri: RUri::from_str(&uri).map_err(|e| invalid(format!("request uri {uri} {e}")))?,
+        alpn,
+        method: Method::from_bytes(field("method")?.as_bytes())
+            .map_err(|e| invalid(format!("request method {e}")))?,
+        version: HttpVersion::from_str(field("http_version")?).unwrap_or_default(),
+        body: content_from(state.get("content"), &encoding),
+        encoding,
+        headers,
+        body_file: None,
+        trailers: state
+            .get("trailers")
+            .map(|t| headers_from(Some(t)))
+            .transpose()?,
+        tls: None,
+        tags: vec![],
+        raw_headers: None,
+    })
+}
+
+fn response_from(state: &Value) -> Result<InterceptedResponse, DumpError> {
+    let status = state
+        .get("status_code")
+        .and_then(Value::as_int)
+        .and_then(|code| u16::try_from(code).ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or_else(|| invalid("response status_code"))?;
+    let headers = headers_from(state.get("headers"))?;
+    let encoding = get_content_encoding(&headers);
+    Ok(InterceptedResponse {
+        timestamp: state
+            .get("timestamp_start")
+            .and_then(Value::as_time)
+            .unwrap_or_else(OffsetDateTime::now_utc),
+        status,
+        version: state
+            .get("http_version")
+            .and_then(Value::as_str)
+            .and_then(|v| HttpVersion::from_str(v).ok())
+            .unwrap_or_default(),
+        body: content_from(state.get("content"), &encoding),
+        encoding,
+        headers,
+        body_file: None,
+        trailers: state
+            .get("trailers")
+            .map(|t| headers_from(Some(t)))
+            .transpose()?,
+        tls: None,
+        tags: vec![],
+    })
+}
+
+/// The flow in a mitmproxy state, `None` for flows other than HTTP ones.
+fn flow_from(state: &Value) -> Result<Option<Flow>, DumpError> {
+    if state.get("version").and_then(Value::as_int).is_none() {
+        return Err(invalid("flow without a version"));
+    }
+    match state.get("type").and_then(Value::as_str) {
+        Some("http") => {}
+        kind => {
+            debug!("Skipping {kind:?} flow");
+            return Ok(None);
+        }
+    }
+    let id = state
+        .get("id")
+        .and_then(Value::as_str)
+        .and_then(id_from_uuid)
+        .ok_or_else(|| invalid("flow id"))?;
+    let client = state.get("client_conn");
+    let server = state.get("server_conn");
+    let conn_time =
+        |conn: Option<&Value>, name: &str| conn.and_then(|c| c.get(name)).and_then(Value::as_time);
+    let alpn = client
+        .and_then(|c| c.get("alpn"))
+        .and_then(Value::as_bytes)
+        .map(AlpnProtocol::from_bytes)
+        .unwrap_or(AlpnProtocol::None);
+    let request_state = state
+        .get("request")
+        .ok_or_else(|| invalid("flow without a request"))?;
+    let response_state = state.get("response");
+
+    let timing = Timing {
+        client_conn_established: conn_time(client, "timestamp_start"),
+        client_conn_tls_handshake: conn_time(client, "timestamp_tls_setup"),
+        server_conn_initiated: conn_time(server, "timestamp_start"),
+        server_conn_tcp_handshake: conn_time(server, "timestamp_tcp_setup"),
+        server_conn_tls_handshake: conn_time(server, "timestamp_tls_setup"),
+        request_complete: conn_time(Some(request_state), "timestamp_end"),
+        response_complete: conn_time(response_state, "timestamp_end"),
+        client_conn_closed: conn_time(client, "timestamp_end"),
+        server_conn_closed: conn_time(server, "timestamp_end"),
+        ..Default::default()
+    };
+    Ok(Some(Flow {
+        id,
+        timing,
+        client_connection: FlowConnection {
+            addr: address_from(client.and_then(|c| c.get("peername")))
+                .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0))),
+        },
+        request: Some(request_from(request_state, alpn)?),
+        server_connection: address_from(server.and_then(|c| c.get("peername")))
+            .map(|addr| FlowConnection { addr }),
+        response: response_state.map(response_from).transpose()?,
+        error: state
+            .get("error")
+            .and_then(|e| e.get("msg"))
+            .and_then(Value::as_str)
+            .map(|msg| FlowError::new(FlowErrorKind::Other, msg)),
+        certs: FlowCerts::default(),
+        messages: vec![],
+        passthrough: false,
+        tunnel: None,
+        tags: vec![],
+        redirects: vec![],
+        interim_responses: vec![],
+        version_choice: None,
+        starred: false,
+        script_log: vec![],
+        wire: FlowWire::default(),
+    }))
+}
+
+/// Writes the HTTP flows in `flows` as a mitmproxy dump, returning how many
+/// were written. WebSocket messages, tags and certificates are left out.
+pub(crate) fn write_dump<'a>(
+    mut writer: impl Write,
+    flows: impl Iterator<Item = &'a Flow>,
+) -> Result<usize, DumpError> {
+    let mut count = 0;
+    for state in flows.filter_map(flow_state) {
+        let mut out = vec![];
+        state.encode(&mut out);
+        writer.write_all(&out)?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Reads the HTTP flows of a mitmproxy dump, other flows such as TCP or DNS
+/// ones are skipped.
+pub(crate) fn read_dump(mut reader: impl Read) -> Result<Vec<Flow>, DumpError> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let mut remaining = data.as_slice();
+    let mut flows = vec![];
+    while !remaining.iter().all(u8::is_ascii_whitespace) {
+        let (state, rest) = Value::decode(remaining)?;
+        flows.extend(flow_from(&state)?);
+        remaining = rest;
+    }
+    Ok(flows)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn sample_flow(id: i64) -> Flow {
+        let mut headers = HeaderMap::new();
+        headers.append("x-test", "a".parse().unwrap());
+        headers.append("x-test", "b".parse().unwrap());
+        let request = InterceptedRequest {
+            uri: "https://example.com/path?q=1".parse().unwrap(),
+            alpn: AlpnProtocol::Http2,
+            method: Method::POST,
+            version: HttpVersion::from_str("HTTP/2.0").unwrap(),
+            headers: headers.clone(),
+            body: Bytes::from_static(b"hello"),
+            ..Default::default()
+        };
+        headers.insert("content-encoding", "gzip".parse().unwrap());
+        let response = InterceptedResponse {
+            status: StatusCode::CREATED,
+            encoding: get_content_encoding(&headers),
+            headers,
+            body: Bytes::from_static(b"{\"created\":true}"),
+            ..Default::default()
+        };
+        let mut flow = Flow::new(
+            FlowId(id),
+            FlowConnection {
+                addr: "127.0.0.1:1234".parse().unwrap(),
+            },
+            Some(request),
+        );
+        flow.server_connection = Some(FlowConnection {
+            addr: "[::1]:443".parse().unwrap(),
+        });
+        flow.response = Some(response);
+        flow
+    }
+
+    fn close(a: OffsetDateTime, b: OffsetDateTime) -> bool {
+        (a - b).unsigned_abs() < Duration::from_micros(1)
+    }
+
+    #[test]
+    fn tnetstring_round_trip() {
+        let value = dict(vec![
+            ("null", Value::Null),
+            ("bool", Value::Bool(true)),
+            ("int", Value::Int(-42)),
+            ("float", Value::Float(1.5)),
+            ("bytes", raw(&b"\x00\xff"[..])),
+            ("str", string("héllo")),
+            ("list", Value::List(vec![Value::Int(1), string("a")])),
+        ]);
+        let mut out = vec![];
+        value.encode(&mut out);
+        let (decoded, rest) = Value::decode(&out).unwrap();
+        assert_eq!(decoded, value);
+        assert!(rest.is_empty());
+
+        let mut out = vec![];
+        dict(vec![("a", Value::List(vec![Value::Int(1), raw("b")]))]).encode(&mut out);
+        assert_eq!(out, b"16:1:a;10:1:1#1:b,]}");
+
+        assert!(Value::decode(b"5:abc,").is_err());
+        assert!(Value::decode(b"3:abc?").is_err());
+        assert!(Value::decode(b"abc,").is_err());
+    }
+
+    #[test]
+    fn dump_round_trip() {
+        let mut failed = sample_flow(2);
+        failed.response = None;
+        failed.error = Some(FlowError::new(FlowErrorKind::Other, "connection refused"));
+        let mut passthrough = sample_flow(3);
+        passthrough.request = None;
+        passthrough.passthrough = true;
+        let flows = [sample_flow(i64::MAX - 1), failed, passthrough];
+
+        let mut dump = vec![];
+        assert_eq!(write_dump(&mut dump, flows.iter()).unwrap(), 2);
+        let read = read_dump(dump.as_slice()).unwrap();
+        assert_eq!(read.len(), 2);
+
+        for (original, read) in flows.iter().zip(&read) {
+            assert_eq!(read.id, original.id);
+            assert_eq!(read.client_connection.addr, original.client_connection.addr);
+            assert_eq!(
+                read.server_connection.map(|c| c.addr),
+                original.server_connection.map(|c| c.addr)
+            );
+            assert_eq!(read.error, original.error);
+
+            let (a, b) = (
+                original.request.as_ref().unwrap(),
+                read.request.as_ref().unwrap(),
+            );
+            assert_eq!(b.uri, a.uri);
+            assert_eq!(b.method, a.method);
+            assert_eq!(b.version, a.version);
+            assert_eq!(b.alpn, a.alpn);
+            assert_eq!(b.headers, a.headers);
+            assert_eq!(b.body, a.body);
+            assert!(close(b.timestamp, a.timestamp));
+
+            match (&original.response, &read.response) {
+                (Some(a), Some(b)) => {
+                    assert_eq!(b.status, a.status);
+                    assert_eq!(b.headers, a.headers);
+                    assert_eq!(b.encoding, a.encoding);
+                    assert_eq!(b.body, a.body);
+                    assert!(close(b.timestamp, a.timestamp));
+                }
+                (None, None) => {}
+                _ => unreachable!("response lost in the round trip"),
+            }
+        }
+    }
+
+    #[test]
+    fn reads_mitmproxy_flows() {
+        let request = dict(vec![
+            ("host", string("example.com")),
+            ("port", Value::Int(8080)),
+            ("method", raw("GET")),
+            ("scheme", raw("http")),
+            ("authority", raw("")),
+            ("path", raw("/a?b=c")),
+            ("http_version", raw("HTTP/1.1")),
+            (
+                "headers",
+                Value::List(vec![Value::List(vec![raw("host"), raw("example.com")])]),
+            ),
+            ("content", raw("")),
+            ("trailers", Value::Null),
+            ("timestamp_start", Value::Float(1700000000.25)),
+            ("timestamp_end", Value::Float(1700000000.5)),
+        ]);
+        let http = dict(vec![
+            ("version", Value::Int(21)),
+            ("type", string("http")),
+            ("id", string("6f1c2a3b-4d5e-4f60-8a7b-0c1d2e3f4a5b")),
+            (
+                "client_conn",
+                dict(vec![(
+                    "peername",
+                    Value::List(vec![string("10.0.0.2"), Value::Int(50000)]),
+                )]),
+            ),
+            ("request", request),
+            ("response", Value::Null),
+            (
+                "error",
+                dict(vec![
+                    ("msg", string("Connection killed.")),
+                    ("timestamp", Value::Float(1.0)),
+                ]),
+            ),
+        ]);
+        let tcp = dict(vec![
+            ("version", Value::Int(21)),
+            ("type", string("tcp")),
+            ("id", string("00000000-0000-4000-8000-000000000001")),
+        ]);
+        let mut dump = vec![];
+        tcp.encode(&mut dump);
+        http.encode(&mut dump);
+        dump.push(b'\n');
+
+        let flows = read_dump(dump.as_slice()).unwrap();
+        assert_eq!(flows.len(), 1);
+        let flow = &flows[0];
+        assert_eq!(flow.id, FlowId(0x8a7b_0c1d_2e3f_4a5b_u64 as i64));
+        assert_eq!(
+            flow.client_connection.addr,
+            "10.0.0.2:50000".parse().unwrap()
+        );
+        assert_eq!(
+            flow.error.as_ref().map(|e| e.message.as_str()),
+            Some("Connection killed.")
+        );
+        let request = flow.request.as_ref().unwrap();
+        assert_eq!(request.uri.to_string(), "http://example.com:8080/a?b=c");
+        assert_eq!(request.method, Method::GET);
+        assert_eq!(request.timestamp.unix_timestamp(), 1700000000);
+        assert!(flow.response.is_none());
+        assert!(flow.timing.request_complete.is_some());
+
+        let mut missing_version = vec![];
+        dict(vec![("type", string("http"))]).encode(&mut missing_version);
+        assert!(read_dump(missing_version.as_slice()).is_err());
+    }
+}
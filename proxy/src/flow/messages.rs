@@ -0,0 +1,225 @@
+//! The WebSocket messages of a flow, described and written out one JSON
+//! object per line.
+//!
+//! Messages are recorded as the proxy reads them, a message the peer split
+//! into fragments is recorded once reassembled. Only messages kept as raw
+//! frames can be fragments, their `final` flag is false until the last one.
+
+use std::io::Write;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use cow_utils::CowUtils;
+use serde::Serialize;
+use strum::Display;
+use time::format_description::well_known::Rfc3339;
+use tokio_tungstenite::tungstenite::{
+    Message,
+    protocol::frame::coding::{Control, Data, OpCode},
+};
+use tracing::debug;
+
+use crate::flow::{WsDirection, WsMessage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum WsOpcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+    Reserved,
+}
+
+impl From<OpCode> for WsOpcode {
+    fn from(value: OpCode) -> Self {
+        match value {
+            OpCode::Data(Data::Continue) => WsOpcode::Continuation,
+            OpCode::Data(Data::Text) => WsOpcode::Text,
+            OpCode::Data(Data::Binary) => WsOpcode::Binary,
+            OpCode::Control(Control::Close) => WsOpcode::Close,
+            OpCode::Control(Control::Ping) => WsOpcode::Ping,
+            OpCode::Control(Control::Pong) => WsOpcode::Pong,
+            OpCode::Data(Data::Reserved(_)) | OpCode::Control(Control::Reserved(_)) => {
+                WsOpcode::Reserved
+            }
+        }
+    }
+}
+
+impl WsMessage {
+    pub fn opcode(&self) -> WsOpcode {
+        match &self.message {
+            Message::Text(_) => WsOpcode::Text,
+            Message::Binary(_) => WsOpcode::Binary,
+            Message::Ping(_) => WsOpcode::Ping,
+            Message::Pong(_) => WsOpcode::Pong,
+            Message::Close(_) => WsOpcode::Close,
+            Message::Frame(frame) => frame.header().opcode.into(),
+        }
+    }
+
+    /// Payload bytes, for close messages the code and reason.
+    pub fn size(&self) -> usize {
+        self.message.len()
+    }
+
+    /// Whether this is the last fragment of its message, always for
+    /// reassembled messages.
+    pub fn is_final(&self) -> bool {
+        match &self.message {
+            Message::Frame(frame) => frame.header().is_final,
+            _ => true,
+        }
+    }
+
+    /// The payload as text, binary payloads that are not UTF-8 have none.
+    pub fn text(&self) -> Option<String> {
+        match &self.message {
+            Message::Text(text) => Some(text.to_string()),
+            Message::Close(frame) => Some(
+                frame
+                    .as_ref()
+                    .map(|f| format!("{} {}", u16::from(f.code), f.reason))
+                    .unwrap_or_default(),
+            ),
+            Message::Binary(data) | Message::Ping(data) | Message::Pong(data) => {
+                String::from_utf8(data.to_vec()).ok()
+            }
+            Message::Frame(frame) => String::from_utf8(frame.payload().to_vec()).ok(),
+        }
+    }
+
+    /// Whether the text of the message contains `needle`, ignoring case.
+    pub fn contains(&self, needle: &str) -> bool {
+        self.contains_lowercase(&needle.cow_to_lowercase())
+    }
+
+    /// Like [`WsMessage::contains`] for a `needle` that is already lowercase,
+    /// so searches over many messages lowercase it once.
+    pub fn contains_lowercase(&self, needle: &str) -> bool {
+        self.text()
+            .is_some_and(|text| text.cow_to_lowercase().contains(needle))
+    }
+}
+
+/// One message of an export.
+#[derive(Debug, Serialize)]
+struct WsMessageLine {
+    index: usize,
+    timestamp: String,
+    direction: &'static str,
+    opcode: WsOpcode,
+    size: usize,
+    #[serde(rename = "final")]
+    is_final: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    /// Base64 of payloads that are not text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+}
+
+impl WsMessageLine {
+    fn new(index: usize, msg: &WsMessage) -> Self {
+        let text = msg.text();
+        let data = match (&text, &msg.message) {
+            (None, Message::Frame(frame)) => Some(STANDARD.encode(frame.payload())),
+            (None, message) => Some(STANDARD.encode(message.clone().into_data())),
+            (Some(_), _) => None,
+        };
+        WsMessageLine {
+            index,
+            timestamp: msg.timestamp.format(&Rfc3339).unwrap_or_default(),
+            direction: match msg.direction {
+                WsDirection::Client => "client",
+                WsDirection::Server => "server",
+            },
+            opcode: msg.opcode(),
+            size: msg.size(),
+            is_final: msg.is_final(),
+            text,
+            data,
+        }
+    }
+}
+
+/// Writes `messages` as JSON lines, returning how many were written.
+pub(crate) fn write_ws_messages(
+    mut writer: impl Write,
+    messages: &[WsMessage],
+) -> std::io::Result<usize> {
+    for (index, msg) in messages.iter().enumerate() {
+        serde_json::to_writer(&mut writer, &WsMessageLine::new(index, msg))?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    debug!("Wrote {} WebSocket messages", messages.len());
+    Ok(messages.len())
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tokio_tungstenite::tungstenite::protocol::{
+        CloseFrame, frame::Frame, frame::coding::CloseCode,
+    };
+
+    use super::*;
+
+    #[test]
+    fn describes_messages() {
+        let text = WsMessage::client(Message::text("Hello"));
+        assert_eq!(text.opcode(), WsOpcode::Text);
+        assert_eq!(text.size(), 5);
+        assert!(text.is_final());
+        assert!(text.contains("hello"));
+
+        let binary = WsMessage::server(Message::binary(vec![0xff, 0x00]));
+        assert_eq!(binary.opcode(), WsOpcode::Binary);
+        assert_eq!(binary.text(), None);
+        assert!(!binary.contains("a"));
+
+        let fragment = WsMessage::server(Message::Frame(Frame::message(
+            Bytes::from_static(b"part"),
+            OpCode::Data(Data::Continue),
+            false,
+        )));
+        assert_eq!(fragment.opcode(), WsOpcode::Continuation);
+        assert!(!fragment.is_final());
+        assert!(fragment.contains("PAR"));
+
+        let close = WsMessage::client(Message::Close(Some(CloseFrame {
+            code: CloseCode::Normal,
+            reason: "bye".into(),
+        })));
+        assert_eq!(close.opcode(), WsOpcode::Close);
+        assert_eq!(close.text().unwrap(), "1000 bye");
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let messages = vec![
+            WsMessage::client(Message::text("hi")),
+            WsMessage::server(Message::binary(vec![0xff])),
+        ];
+        let mut out = vec![];
+        assert_eq!(write_ws_messages(&mut out, &messages).unwrap(), 2);
+        let lines: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["direction"], "client");
+        assert_eq!(lines[0]["opcode"], "text");
+        assert_eq!(lines[0]["text"], "hi");
+        assert_eq!(lines[0]["final"], true);
+        assert_eq!(lines[1]["index"], 1);
+        assert_eq!(lines[1]["opcode"], "binary");
+        assert_eq!(lines[1]["data"], "/w==");
+        assert!(lines[1].get("text").is_none());
+    }
+}
@@ -0,0 +1,273 @@
+//! Per-flow timings and sizes as CSV or JSON for spreadsheets and scripts.
+//!
+//! Every HTTP flow with a request becomes one row. Phase durations are the
+//! ones drawn in the timing waterfall, in milliseconds, and are empty when
+//! the phase did not happen.
+
+use std::{io::Write, str::FromStr};
+
+use cow_utils::CowUtils;
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use tracing::debug;
+
+use crate::flow::{Flow, Timing, id::FlowId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for MetricsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(MetricsFormat::Csv),
+            "json" => Ok(MetricsFormat::Json),
+            other => Err(format!("Unknown metrics format {other}")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MetricsError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for MetricsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for MetricsError {}
+
+impl From<std::io::Error> for MetricsError {
+    fn from(value: std::io::Error) -> Self {
+        MetricsError::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for MetricsError {
+    fn from(value: serde_json::Error) -> Self {
+        MetricsError::Json(value)
+    }
+}
+
+/// The columns of a CSV export, in the order of the fields of [`FlowMetrics`].
+const CSV_HEADER: &str = "id,started,method,url,status,version,server_conn_reused,\
+request_bytes,response_bytes,dns_ms,connect_ms,tls_ms,waiting_ms,download_ms,total_ms";
+
+/// The timings and sizes of one flow.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlowMetrics {
+    pub id: FlowId,
+    pub started: String,
+    pub method: String,
+    pub url: String,
+    pub status: Option<u16>,
+    pub version: Option<String>,
+    pub server_conn_reused: bool,
+    pub request_bytes: usize,
+    pub response_bytes: Option<usize>,
+    pub dns_ms: Option<f64>,
+    pub connect_ms: Option<f64>,
+    pub tls_ms: Option<f64>,
+    pub waiting_ms: Option<f64>,
+    pub download_ms: Option<f64>,
+    pub total_ms: Option<f64>,
+}
+
+impl FlowMetrics {
+    /// The metrics of `flow`, `None` for passthrough and raw tunnels and ones
+    /// without a request.
+    pub fn new(flow: &Flow) -> Option<Self> {
+        if flow.passthrough || flow.tunnel.is_some() {
+            return None;
+        }
+        let request = flow.request.as_ref()?;
+        let phase = |name: &str| phase_ms(&flow.timing, name);
+        let total = match (
+            flow.timing.first_request_bytes,
+            flow.timing.response_complete,
+        ) {
+            (Some(start), Some(end)) if end >= start => Some(millis(end - start)),
+            _ => None,
+        };
+        Some(FlowMetrics {
+            id: flow.id,
+            started: request.timestamp.format(&Rfc3339).unwrap_or_default(),
+            method: request.method.to_string(),
+            url: request.uri.to_string(),
+            status: flow.response.as_ref().map(|r| r.status.as_u16()),
+            version: flow.response.as_ref().map(|r| r.version.to_string()),
+            server_conn_reused: flow.timing.server_conn_reused,
+            request_bytes: request.body_len(),
+            response_bytes: flow.response.as_ref().map(|r| r.body_len()),
+            dns_ms: phase("dns"),
+            connect_ms: phase("connect"),
+            tls_ms: phase("tls"),
+            waiting_ms: phase("waiting"),
+            download_ms: phase("download"),
+            total_ms: total,
+        })
+    }
+
+    fn csv_row(&self) -> String {
+        let opt = |v: Option<f64>| v.map(|v| format!("{v:.3}")).unwrap_or_default();
+        [
+            self.id.to_string(),
+            self.started.clone(),
+            csv_field(&self.method),
+            csv_field(&self.url),
+            self.status.map(|s| s.to_string()).unwrap_or_default(),
+            self.version.clone().unwrap_or_default(),
+            self.server_conn_reused.to_string(),
+            self.request_bytes.to_string(),
+            self.response_bytes
+                .map(|b| b.to_string())
+                .unwrap_or_default(),
+            opt(self.dns_ms),
+            opt(self.connect_ms),
+            opt(self.tls_ms),
+            opt(self.waiting_ms),
+            opt(self.download_ms),
+            opt(self.total_ms),
+        ]
+        .join(",")
+    }
+}
+
+fn phase_ms(timing: &Timing, name: &str) -> Option<f64> {
+    timing
+        .waterfall()
+        .into_iter()
+        .find(|phase| phase.name == name)
+        .map(|phase| millis(phase.duration()))
+}
+
+fn millis(duration: time::Duration) -> f64 {
+    duration.whole_microseconds() as f64 / 1000.0
+}
+
+/// Quotes `value` when it holds a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.cow_replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes the metrics of the flows with a request, returning how many were
+/// written.
+pub(crate) fn write_metrics<'a>(
+    mut writer: impl Write,
+    flows: impl Iterator<Item = &'a Flow>,
+    format: MetricsFormat,
+) -> Result<usize, MetricsError> {
+    let rows: Vec<_> = flows.filter_map(FlowMetrics::new).collect();
+    match format {
+        MetricsFormat::Csv => {
+            writeln!(writer, "{CSV_HEADER}")?;
+            for row in &rows {
+                writeln!(writer, "{}", row.csv_row())?;
+            }
+        }
+        MetricsFormat::Json => serde_json::to_writer(&mut writer, &rows)?,
+    }
+    writer.flush()?;
+    debug!("Wrote metrics of {} flows", rows.len());
+    Ok(rows.len())
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::{Method, StatusCode};
+    use time::{Duration, OffsetDateTime};
+
+    use crate::flow::test_flow;
+
+    use super::*;
+
+    fn flow() -> Flow {
+        let start = OffsetDateTime::now_utc();
+        let mut flow = test_flow(
+            7,
+            Method::POST,
+            "https://example.com/a,b",
+            StatusCode::OK,
+            b"done",
+        );
+        let request = flow.request.as_mut().unwrap();
+        request.timestamp = start;
+        request.body = bytes::Bytes::from_static(b"hello");
+        let at = |ms| Some(start + Duration::milliseconds(ms));
+        flow.timing.first_request_bytes = at(0);
+        flow.timing.request_complete = at(1);
+        flow.timing.server_conn_initiated = at(1);
+        flow.timing.server_conn_dns_resolved = at(5);
+        flow.timing.server_conn_tcp_handshake = at(15);
+        flow.timing.first_response_bytes = at(40);
+        flow.timing.response_complete = at(50);
+        flow
+    }
+
+    #[test]
+    fn metrics_of_flow() {
+        let metrics = FlowMetrics::new(&flow()).unwrap();
+        assert_eq!(metrics.id, FlowId(7));
+        assert_eq!(metrics.method, "POST");
+        assert_eq!(metrics.status, Some(200));
+        assert_eq!(metrics.request_bytes, 5);
+        assert_eq!(metrics.response_bytes, Some(4));
+        assert_eq!(metrics.dns_ms, Some(4.0));
+        assert_eq!(metrics.connect_ms, Some(10.0));
+        assert_eq!(metrics.tls_ms, None);
+        assert_eq!(metrics.waiting_ms, Some(25.0));
+        assert_eq!(metrics.download_ms, Some(10.0));
+        assert_eq!(metrics.total_ms, Some(50.0));
+    }
+
+    #[test]
+    fn skips_passthrough() {
+        let mut flow = flow();
+        flow.passthrough = true;
+        assert_eq!(FlowMetrics::new(&flow), None);
+    }
+
+    #[test]
+    fn writes_csv() {
+        let mut out = vec![];
+        let count = write_metrics(&mut out, [flow()].iter(), MetricsFormat::Csv).unwrap();
+        assert_eq!(count, 1);
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1].split_once(',').unwrap().0,
+            "7",
+            "starts with the id"
+        );
+        assert!(lines[1].contains(",POST,\"https://example.com/a,b\",200,"));
+        assert!(lines[1].ends_with(",4.000,10.000,,25.000,10.000,50.000"));
+        assert_eq!(
+            lines[1].matches(',').count(),
+            CSV_HEADER.matches(',').count() + 1
+        );
+    }
+
+    #[test]
+    fn writes_json() {
+        let mut out = vec![];
+        write_metrics(&mut out, [flow()].iter(), MetricsFormat::Json).unwrap();
+        let rows: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(rows[0]["id"], 7);
+        assert_eq!(rows[0]["waiting_ms"], 25.0);
+        assert!(rows[0]["tls_ms"].is_null());
+    }
+}
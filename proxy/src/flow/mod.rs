@@ -0,0 +1,1916 @@
+pub mod diff;
+pub mod error;
+pub mod export;
+pub mod filter;
+pub mod har;
+pub mod id;
+pub mod io;
+pub mod messages;
+pub mod metrics;
+pub mod pcap;
+pub mod raw_headers;
+pub mod replay;
+pub mod retention;
+pub mod search;
+pub mod session;
+pub mod spill;
+pub mod tunnel;
+pub mod view;
+pub mod wire;
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufReader, BufWriter},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use dashmap::DashMap;
+
+use http::header::{CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, TE, TRANSFER_ENCODING, UPGRADE};
+use http::{StatusCode, Version};
+use roxy_shared::alpn::AlpnProtocol;
+
+use roxy_shared::body::create_http_body;
+use roxy_shared::cert::CapturedClientHello;
+use roxy_shared::cert::CapturedResolveClientCert;
+use roxy_shared::cert::ClientTlsConnectionData;
+use roxy_shared::cert::ClientVerificationCapture;
+use roxy_shared::cert::ParsedCert;
+use roxy_shared::cert::ServerTlsConnectionData;
+use roxy_shared::cert::ServerVerificationCapture;
+use roxy_shared::cert::VerifyServerCert;
+use roxy_shared::content::get_content_encoding;
+use roxy_shared::content::{Encodings, decode_body, encode_body};
+use roxy_shared::http::{HttpEmitter, HttpEvent};
+use roxy_shared::pool::ConnectionInfo;
+use roxy_shared::redirect::Redirect;
+use roxy_shared::uri::RUri;
+use roxy_shared::uri::Scheme;
+
+use http::{HeaderMap, HeaderName, HeaderValue};
+use roxy_shared::body::BytesBody;
+use roxy_shared::version::HttpVersion;
+use roxy_shared::wire::WireCapture;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{RwLock, broadcast, watch};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::error;
+use tracing::warn;
+
+use crate::{
+    flow::{
+        error::FlowError,
+        filter::CaptureProfile,
+        har::{HarError, read_har, write_har},
+        id::{FlowId, next_id},
+        io::{DumpError, read_dump, write_dump},
+        messages::write_ws_messages,
+        metrics::{MetricsError, MetricsFormat, write_metrics},
+        pcap::write_pcapng,
+        raw_headers::RawHeaders,
+        retention::{Retention, RetentionState, body_bytes, is_finished},
+        session::{
+            SessionError, append_session, blocking, encode_flow, read_session, remove_from_session,
+            write_session,
+        },
+        spill::{BodyFile, BodySpill},
+        tunnel::{RawTunnel, TunnelChunk},
+        view::{ChangedFlows, FlowView, ViewChange, view_proc},
+        wire::FlowWire,
+    },
+    interceptor::FlowNotifyLevel,
+    metrics::Metrics,
+    proxy::FlowContext,
+    version::VersionChoice,
+};
+
+#[derive(Debug, Clone)]
+pub struct FlowStore {
+    pub flows: Arc<DashMap<FlowId, Arc<RwLock<Flow>>>>,
+    pub ordered_ids: Arc<RwLock<Vec<FlowId>>>,
+    pub notifier: watch::Sender<()>,
+    pub notifier_new_flow: watch::Sender<()>,
+    pub event_tx: UnboundedSender<(FlowId, FlowEvent)>,
+    session: Option<Arc<PathBuf>>,
+    spill: Arc<std::sync::RwLock<Option<BodySpill>>>,
+    metrics: Metrics,
+    updates: broadcast::Sender<FlowUpdate>,
+    retention: RetentionState,
+    /// New intercepted flows are recorded while set, traffic is proxied either way.
+    capturing: Arc<AtomicBool>,
+    /// HTTP/1 flows keep the bytes of their messages as read while set.
+    raw_capture: Arc<AtomicBool>,
+    capture_profile: Arc<std::sync::RwLock<Option<CaptureProfile>>>,
+    /// Flows dropped for not matching the capture profile.
+    dropped: Arc<AtomicU64>,
+    /// Script notifications sent before their flow was recorded, request
+    /// scripts run before the flow exists.
+    pending_logs: Arc<DashMap<FlowId, Vec<ScriptLog>>>,
+    view: watch::Receiver<FlowView>,
+    changed: ChangedFlows,
+}
+
+/// Updates buffered per subscriber before the slowest one lags.
+const UPDATES_CAPACITY: usize = 1024;
+
+/// A step in the life of a flow, published as it happens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowUpdateKind {
+    /// The flow was recorded, loaded sessions and dumps are not published.
+    Created,
+    /// The request passed scripts and breakpoints and is sent to the server.
+    RequestComplete,
+    /// An intercepted flow finished with a response or an error.
+    ResponseComplete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowUpdate {
+    pub id: FlowId,
+    pub kind: FlowUpdateKind,
+}
+
+impl FlowStore {
+    pub fn new() -> Self {
+        Self::new_with_session(None)
+    }
+
+    /// Creates a store that appends every completed flow to `session`.
+    pub fn new_with_session(session: Option<PathBuf>) -> Self {
+        let (notifier, _) = watch::channel(());
+        let (notifier_new_flow, _) = watch::channel(()); // TODO: write this
+        let (event_tx, event_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (updates, _) = broadcast::channel(UPDATES_CAPACITY);
+        let (view_tx, view) = watch::channel(FlowView::default());
+        let s = Self {
+            flows: Arc::new(DashMap::new()),
+            ordered_ids: Arc::new(RwLock::new(Vec::new())),
+            notifier,
+            notifier_new_flow,
+            event_tx,
+            session: session.map(Arc::new),
+            spill: Arc::new(std::sync::RwLock::new(Some(BodySpill::default()))),
+            metrics: Metrics::new(),
+            updates,
+            retention: RetentionState::default(),
+            capturing: Arc::new(AtomicBool::new(true)),
+            raw_capture: Arc::new(AtomicBool::new(false)),
+            capture_profile: Arc::new(std::sync::RwLock::new(None)),
+            dropped: Arc::new(AtomicU64::new(0)),
+            pending_logs: Arc::new(DashMap::new()),
+            view,
+            changed: ChangedFlows::default(),
+        };
+
+        s.event_proc(event_rx);
+        view_proc(s.clone(), view_tx);
+        s
+    }
+
+    /// Sets where large bodies are written, `None` keeps every body in memory.
+    pub fn set_body_spill(&self, spill: Option<BodySpill>) {
+        match self.spill.write() {
+            Ok(mut guard) => *guard = spill,
+            Err(err) => error!("Spill lock poisoned {err}"),
+        }
+    }
+
+    pub fn body_spill(&self) -> Option<BodySpill> {
+        match self.spill.read() {
+            Ok(spill) => spill.clone(),
+            Err(err) => {
+                error!("Spill lock poisoned {err}");
+                None
+            }
+        }
+    }
+
+    /// Request counts, bytes and latencies of the flows completed so far.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    pub fn capturing(&self) -> bool {
+        self.capturing.load(Ordering::Relaxed)
+    }
+
+    /// Pauses or resumes recording intercepted traffic. Flows already recorded
+    /// are still completed, replays are recorded regardless.
+    pub fn set_capturing(&self, capturing: bool) {
+        self.capturing.store(capturing, Ordering::Relaxed);
+        self.notify();
+    }
+
+    pub fn raw_capture(&self) -> bool {
+        self.raw_capture.load(Ordering::Relaxed)
+    }
+
+    /// Keeps the bytes of HTTP/1 requests and responses exactly as read in
+    /// [`Flow::wire`], up to [`roxy_shared::wire::WIRE_CAPTURE_LIMIT`] per
+    /// message. Applies to requests read from then on.
+    pub fn set_raw_capture(&self, raw_capture: bool) {
+        self.raw_capture.store(raw_capture, Ordering::Relaxed);
+    }
+
+    pub fn capture_profile(&self) -> Option<CaptureProfile> {
+        match self.capture_profile.read() {
+            Ok(profile) => profile.clone(),
+            Err(err) => {
+                error!("Capture profile lock poisoned {err}");
+                None
+            }
+        }
+    }
+
+    /// Records only the flows matching `profile` from now on, `None` records
+    /// everything. Flows already recorded are kept.
+    pub fn set_capture_profile(&self, profile: Option<CaptureProfile>) {
+        match self.capture_profile.write() {
+            Ok(mut guard) => *guard = profile,
+            Err(err) => error!("Capture profile lock poisoned {err}"),
+        }
+        self.notify();
+    }
+
+    /// How many finished flows were dropped for not matching the capture
+    /// profile.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.retention.policy()
+    }
+
+    /// Bounds the flows kept in memory, evicting any over the new bounds.
+    pub async fn set_retention(&self, retention: Retention) {
+        self.retention.set_policy(retention);
+        self.enforce_retention().await;
+    }
+
+    /// Bytes of bodies and WebSocket messages held in memory by the flows.
+    pub fn body_bytes(&self) -> u64 {
+        self.retention.total()
+    }
+
+    /// How many flows were evicted to stay within the [`Retention`].
+    pub fn evicted(&self) -> u64 {
+        self.retention.evicted()
+    }
+
+    /// Flows as they are created, sent and completed.
+    pub fn subscribe_updates(&self) -> broadcast::Receiver<FlowUpdate> {
+        self.updates.subscribe()
+    }
+
+    fn publish(&self, id: FlowId, kind: FlowUpdateKind) {
+        // Nobody listening is fine
+        let _ = self.updates.send(FlowUpdate { id, kind });
+    }
+
+    /// Hands out the id of a flow ahead of [`FlowStore::new_flow_cxt`], so
+    /// what the request scripts notify can be kept with it.
+    pub fn next_flow_id(&self) -> FlowId {
+        next_id()
+    }
+
+    /// Records flow `id` for `req`. While capture is paused no flow is
+    /// recorded, events posted for it are dropped.
+    pub async fn new_flow_cxt(&self, cxt: &FlowContext, id: FlowId, mut req: InterceptedRequest) {
+        let script_log = self
+            .pending_logs
+            .remove(&id)
+            .map(|(_, log)| log)
+            .unwrap_or_default();
+        if !self.capturing() {
+            return;
+        }
+        if let Some(spill) = self.body_spill() {
+            req.spill(&spill).await;
+        }
+        let mut flow = Flow::new(
+            id,
+            FlowConnection {
+                addr: cxt.client_addr,
+            },
+            Some(req),
+        );
+
+        flow.certs = cxt.certs.clone();
+        flow.script_log = script_log;
+        flow.timing.client_conn_established = Some(cxt.connected);
+        flow.timing.client_conn_tls_handshake = cxt.tls_established;
+        flow.timing.first_request_bytes = flow.request.as_ref().map(|req| req.timestamp);
+        self.retention.track(id, body_bytes(&flow));
+
+        let flow = Arc::new(RwLock::new(flow));
+        self.flows.insert(id, flow.clone());
+        self.push_id(id).await;
+        self.publish(id, FlowUpdateKind::Created);
+    }
+
+    /// Keeps a notification a script sent while intercepting flow `id`.
+    pub(crate) fn log_script(&self, id: FlowId, log: ScriptLog) {
+        if self.flows.contains_key(&id) {
+            self.post_event(id, FlowEvent::ScriptLog(log));
+        } else {
+            self.pending_logs.entry(id).or_default().push(log);
+        }
+    }
+
+    pub(crate) async fn new_flow(
+        &self,
+        client_connection: FlowConnection,
+        mut req: InterceptedRequest,
+    ) -> FlowId {
+        if let Some(spill) = self.body_spill() {
+            req.spill(&spill).await;
+        }
+        let id = next_id();
+        let flow = Flow::new(id, client_connection, Some(req));
+        self.retention.track(id, body_bytes(&flow));
+        let flow = Arc::new(RwLock::new(flow));
+        self.flows.insert(id, flow);
+        self.push_id(id).await;
+        self.publish(id, FlowUpdateKind::Created);
+        id
+    }
+
+    pub async fn new_ws_flow(&self, client_connect: FlowConnection) -> FlowId {
+        let id = next_id();
+        if !self.capturing() {
+            return id;
+        }
+        let flow = Arc::new(RwLock::new(Flow::new(id, client_connect, None)));
+        self.flows.insert(id, flow.clone());
+        self.push_id(id).await;
+        self.publish(id, FlowUpdateKind::Created);
+        id
+    }
+
+    /// Records a connection to `cxt.target_uri` that is tunneled without interception.
+    pub async fn new_passthrough_flow(&self, cxt: &FlowContext) -> FlowId {
+        let id = next_id();
+        if !self.capturing() {
+            return id;
+        }
+        let request = InterceptedRequest {
+            uri: cxt.target_uri.clone(),
+            method: http::Method::CONNECT,
+            ..Default::default()
+        };
+        let mut flow = Flow::new(
+            id,
+            FlowConnection {
+                addr: cxt.client_addr,
+            },
+            Some(request),
+        );
+        flow.passthrough = true;
+        flow.timing.client_conn_established = Some(OffsetDateTime::now_utc());
+
+        self.flows.insert(id, Arc::new(RwLock::new(flow)));
+        self.push_id(id).await;
+        self.publish(id, FlowUpdateKind::Created);
+        id
+    }
+
+    /// Records an intercepted connection to `cxt.target_uri` whose protocol
+    /// is not HTTP, its bytes follow as `TunnelData` events. `tls` is whether
+    /// the client spoke TLS to Roxy.
+    pub async fn new_tunnel_flow(&self, cxt: &FlowContext, tls: bool) -> FlowId {
+        let id = next_id();
+        if !self.capturing() {
+            return id;
+        }
+        let request = InterceptedRequest {
+            uri: cxt.target_uri.clone(),
+            method: http::Method::CONNECT,
+            ..Default::default()
+        };
+        let mut flow = Flow::new(
+            id,
+            FlowConnection {
+                addr: cxt.client_addr,
+            },
+            Some(request),
+        );
+        flow.tunnel = Some(RawTunnel::new(tls));
+        flow.certs = cxt.certs.clone();
+        flow.timing.client_conn_established = Some(cxt.connected);
+        flow.timing.client_conn_tls_handshake = cxt.tls_established;
+
+        self.flows.insert(id, Arc::new(RwLock::new(flow)));
+        self.push_id(id).await;
+        self.publish(id, FlowUpdateKind::Created);
+        id
+    }
+
+    /// Records a client connection refused before any request was read.
+    pub(crate) async fn new_rejected_flow(
+        &self,
+        client_addr: SocketAddr,
+        error: FlowError,
+    ) -> FlowId {
+        let id = next_id();
+        if !self.capturing() {
+            return id;
+        }
+        let mut flow = Flow::new(id, FlowConnection { addr: client_addr }, None);
+        flow.error = Some(error);
+        flow.timing.client_conn_established = Some(OffsetDateTime::now_utc());
+        flow.timing.client_conn_closed = flow.timing.client_conn_established;
+
+        self.flows.insert(id, Arc::new(RwLock::new(flow)));
+        self.push_id(id).await;
+        self.publish(id, FlowUpdateKind::Created);
+        id
+    }
+
+    /// Every flow in the order they were recorded.
+    pub(crate) async fn ordered_flows(&self) -> Vec<Arc<RwLock<Flow>>> {
+        let ids = self.ordered_ids.read().await.clone();
+        let mut flows = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(flow) = self.get_flow_by_id(id).await {
+                flows.push(flow);
+            }
+        }
+        flows
+    }
+
+    /// The flows in `ids` that are in the store, in the order they were recorded.
+    pub(crate) async fn flows_by_ids(&self, ids: &[FlowId]) -> Vec<Arc<RwLock<Flow>>> {
+        let ids: HashSet<FlowId> = ids.iter().copied().collect();
+        let ordered = self.ordered_ids.read().await.clone();
+        let mut flows = Vec::with_capacity(ids.len());
+        for id in ordered.into_iter().filter(|id| ids.contains(id)) {
+            if let Some(flow) = self.get_flow_by_id(id).await {
+                flows.push(flow);
+            }
+        }
+        flows
+    }
+
+    /// Writes every flow in the store to `path`, replacing its contents.
+    pub async fn save_session(&self, path: &Path) -> Result<(), SessionError> {
+        let flows = copies(&self.ordered_flows().await).await;
+        let path = path.to_path_buf();
+        blocking(move || write_session(&path, flows.iter())).await
+    }
+
+    /// Writes the flows in `ids` to `path` as a session file that can be
+    /// loaded again, returning how many were written.
+    pub async fn save_flows(&self, path: &Path, ids: &[FlowId]) -> Result<usize, SessionError> {
+        let flows = copies(&self.flows_by_ids(ids).await).await;
+        let path = path.to_path_buf();
+        let count = flows.len();
+        blocking(move || write_session(&path, flows.iter())).await?;
+        Ok(count)
+    }
+
+    /// Stars or unstars the flows in `ids`, appending them to the session so
+    /// the marks survive a restart.
+    pub async fn set_starred(&self, ids: &[FlowId], starred: bool) {
+        for flow in self.flows_by_ids(ids).await {
+            let mut guard = flow.write().await;
+            if guard.starred == starred {
+                continue;
+            }
+            guard.starred = starred;
+            self.changed.push(ViewChange::Updated(guard.id));
+            let Some(path) = &self.session else {
+                continue;
+            };
+            let id = guard.id;
+            let line = encode_flow(&guard);
+            drop(guard);
+            match line {
+                Ok(line) => match append_session(path, &line).await {
+                    Ok(()) => self.retention.set_in_session(id, true),
+                    Err(err) => error!("Error appending flow {id} to session {err}"),
+                },
+                Err(err) => error!("Error encoding flow {id} {err}"),
+            }
+        }
+        self.notify();
+    }
+
+    /// Removes the flows in `ids` from the store and the session, returning
+    /// how many were removed. Events for removed flows still in flight are
+    /// dropped.
+    pub async fn remove_flows(&self, ids: &[FlowId]) -> usize {
+        let ids: HashSet<FlowId> = ids.iter().copied().collect();
+        self.remove_ids(&ids).await;
+        let removed = ids
+            .iter()
+            .filter(|id| {
+                self.retention.forget(**id);
+                self.flows.remove(*id).is_some()
+            })
+            .count();
+        if removed > 0
+            && let Some(path) = self.session.clone()
+            && let Err(err) = blocking(move || remove_from_session(&path, &ids)).await
+        {
+            error!("Error removing flows from session {err}");
+        }
+        self.notify();
+        removed
+    }
+
+    /// Loads the flows in `path` into the store, returning how many were read.
+    pub async fn load_session(&self, path: &Path) -> Result<usize, SessionError> {
+        let session = path.to_path_buf();
+        let flows = blocking(move || read_session(&session)).await?;
+        if self
+            .session
+            .as_deref()
+            .is_some_and(|session| session == path)
+        {
+            for flow in &flows {
+                self.retention.set_in_session(flow.id, true);
+            }
+        }
+        Ok(self.insert_flows(flows).await)
+    }
+
+    /// Writes the HTTP flows in the store to `path` as a mitmproxy dump,
+    /// returning how many were written.
+    pub async fn export_mitmproxy(&self, path: &Path) -> Result<usize, DumpError> {
+        let flows = copies(&self.ordered_flows().await).await;
+        let path = path.to_path_buf();
+        blocking(move || write_dump(BufWriter::new(File::create(&path)?), flows.iter())).await
+    }
+
+    /// Loads the HTTP flows of the mitmproxy dump at `path` into the store,
+    /// returning how many were read.
+    pub async fn import_mitmproxy(&self, path: &Path) -> Result<usize, DumpError> {
+        let path = path.to_path_buf();
+        let flows = blocking(move || read_dump(BufReader::new(File::open(&path)?))).await?;
+        Ok(self.insert_flows(flows).await)
+    }
+
+    /// Loads the entries of the HAR file at `path` into the store as new
+    /// flows, returning how many were read.
+    pub async fn import_har(&self, path: &Path) -> Result<usize, HarError> {
+        let data = tokio::fs::read(path).await?;
+        let flows = read_har(data.as_slice()).await?;
+        Ok(self.insert_flows(flows).await)
+    }
+
+    /// Writes the HTTP flows in the store to `path` as a HAR file, returning
+    /// how many were written.
+    pub async fn export_har(&self, path: &Path) -> Result<usize, HarError> {
+        let flows = copies(&self.ordered_flows().await).await;
+        let path = path.to_path_buf();
+        blocking(move || write_har(BufWriter::new(File::create(&path)?), flows.iter())).await
+    }
+
+    /// Writes the decrypted HTTP flows in the store to `path` as a pcapng
+    /// capture, returning how many were written.
+    pub async fn export_pcapng(&self, path: &Path) -> std::io::Result<usize> {
+        let flows = copies(&self.ordered_flows().await).await;
+        let path = path.to_path_buf();
+        blocking(move || write_pcapng(BufWriter::new(File::create(&path)?), flows.iter())).await
+    }
+
+    /// Writes the timings and sizes of the HTTP flows in the store to `path`
+    /// as CSV or JSON, returning how many were written.
+    pub async fn export_metrics(
+        &self,
+        path: &Path,
+        format: MetricsFormat,
+    ) -> Result<usize, MetricsError> {
+        let flows = copies(&self.ordered_flows().await).await;
+        let path = path.to_path_buf();
+        blocking(move || write_metrics(BufWriter::new(File::create(&path)?), flows.iter(), format))
+            .await
+    }
+
+    /// Writes the WebSocket messages of flow `id` to `path` as JSON lines,
+    /// returning how many were written.
+    pub async fn export_ws_messages(&self, id: FlowId, path: &Path) -> std::io::Result<usize> {
+        let Some(flow) = self.get_flow_by_id(id).await else {
+            return Err(std::io::Error::other(format!("No flow {id}")));
+        };
+        let messages = flow.read().await.messages.clone();
+        let writer = BufWriter::new(File::create(path)?);
+        write_ws_messages(writer, &messages)
+    }
+
+    /// Writes the raw bytes of flow `id` to `path`, the request followed by
+    /// the response, returning how many were written.
+    pub async fn export_raw(&self, id: FlowId, path: &Path) -> std::io::Result<usize> {
+        let Some(flow) = self.get_flow_by_id(id).await else {
+            return Err(std::io::Error::other(format!("No flow {id}")));
+        };
+        let wire = flow.read().await.wire.clone();
+        if wire.is_empty() {
+            return Err(std::io::Error::other(format!("No raw bytes for flow {id}")));
+        }
+        let transcript = wire.transcript();
+        std::fs::write(path, &transcript)?;
+        Ok(transcript.len())
+    }
+
+    pub(crate) async fn insert_flows(&self, flows: Vec<Flow>) -> usize {
+        let count = flows.len();
+        let mut ordered_ids = self.ordered_ids.write().await;
+        for flow in flows {
+            let id = flow.id;
+            self.retention.track(id, body_bytes(&flow));
+            if self.flows.insert(id, Arc::new(RwLock::new(flow))).is_none() {
+                ordered_ids.push(id);
+                self.changed.push(ViewChange::Added(id));
+            } else {
+                self.changed.push(ViewChange::Updated(id));
+            }
+        }
+        drop(ordered_ids);
+        self.notify();
+        self.enforce_retention().await;
+        count
+    }
+
+    /// Evicts the oldest finished flows until the store is within its
+    /// [`Retention`] again. Evicted flows stay in the session file, those
+    /// not in it yet, such as imported ones, are appended first.
+    async fn enforce_retention(&self) {
+        let policy = self.retention.policy();
+        if policy.is_unbounded() {
+            return;
+        }
+        let ordered = self.ordered_ids.read().await.clone();
+        let mut flows = ordered.len();
+        let mut bytes = self.retention.total();
+        let mut evicted = HashSet::new();
+        for id in ordered {
+            if !policy.exceeded(flows, bytes) {
+                break;
+            }
+            let Some(flow) = self.get_flow_by_id(id).await else {
+                continue;
+            };
+            // A flow locked for writing is being updated, so not finished
+            let Ok(guard) = flow.try_read() else {
+                continue;
+            };
+            if guard.starred || !is_finished(&guard) {
+                continue;
+            }
+            if let Some(path) = &self.session
+                && !self.retention.is_in_session(id)
+            {
+                let appended = match encode_flow(&guard) {
+                    Ok(line) => append_session(path, &line).await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = appended {
+                    error!("Error keeping evicted flow {id} in session {err}");
+                    continue;
+                }
+            }
+            flows -= 1;
+            bytes = bytes.saturating_sub(self.retention.size(id));
+            evicted.insert(id);
+        }
+        if evicted.is_empty() {
+            return;
+        }
+        self.remove_ids(&evicted).await;
+        for id in &evicted {
+            self.flows.remove(id);
+            self.retention.forget(*id);
+        }
+        self.retention.add_evicted(evicted.len());
+        self.notify();
+    }
+
+    pub async fn get_flow_by_id(&self, id: FlowId) -> Option<Arc<RwLock<Flow>>> {
+        self.flows.get(&id).map(|f| f.value().clone())
+    }
+
+    pub fn post_event(&self, flow_id: FlowId, event: FlowEvent) {
+        if let Err(err) = self.event_tx.send((flow_id, event)) {
+            error!("Error posting event {err} {flow_id}");
+        }
+    }
+
+    /// Notifies subscribers of a change to flow `id`, which the next
+    /// [`FlowView`] copies again.
+    fn flow_changed(&self, id: FlowId) {
+        self.changed.push(ViewChange::Updated(id));
+        self.notify();
+    }
+
+    /// Appends flow `id` to the recorded flows and notifies subscribers.
+    async fn push_id(&self, id: FlowId) {
+        let mut ordered_ids = self.ordered_ids.write().await;
+        ordered_ids.push(id);
+        // Under the lock, so the view appends flows in the same order
+        self.changed.push(ViewChange::Added(id));
+        drop(ordered_ids);
+        self.notify();
+    }
+
+    /// Drops the flows in `ids` from the recorded flows, the caller notifies.
+    async fn remove_ids(&self, ids: &HashSet<FlowId>) {
+        let mut ordered_ids = self.ordered_ids.write().await;
+        ordered_ids.retain(|id| !ids.contains(id));
+        for id in ids {
+            self.changed.push(ViewChange::Removed(*id));
+        }
+    }
+
+    fn notify(&self) {
+        self.notifier.send(()).unwrap_or_else(|_| {
+            warn!("Failed to notify subscribers, channel closed");
+        });
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.notifier.subscribe()
+    }
+
+    /// The flows as of the last change, rebuilt in the background so reading
+    /// them never waits on the proxy recording flows.
+    pub fn view(&self) -> watch::Receiver<FlowView> {
+        self.view.clone()
+    }
+
+    fn event_proc(&self, mut event_rx: UnboundedReceiver<(FlowId, FlowEvent)>) {
+        let fs = self.clone();
+        tokio::spawn(async move {
+            while let Some((flow_id, mut event)) = event_rx.recv().await {
+                let spill = fs.body_spill();
+                if let Some(spill) = &spill {
+                    event.spill(spill).await;
+                }
+                // The flow was removed while its request was still in flight
+                let Some(flow) = fs.flows.get(&flow_id) else {
+                    continue;
+                };
+
+                let mut guard = flow.write().await;
+                let mut persist = false;
+                let mut completed = false;
+                let mut request_complete = false;
+                match event {
+                    FlowEvent::HttpEvent(inner) => match inner {
+                        HttpEvent::ConnectStarted => {
+                            guard.timing.server_conn_initiated = Some(OffsetDateTime::now_utc());
+                        }
+                        HttpEvent::DnsResolved(elapsed) => {
+                            guard.timing.server_conn_dns_resolved = Some(OffsetDateTime::now_utc());
+                            guard.timing.server_conn_dns_lookup = Some(elapsed);
+                        }
+                        HttpEvent::TcpConnect(addr) => {
+                            guard.server_connection = Some(FlowConnection { addr });
+                            guard.timing.server_conn_tcp_handshake =
+                                Some(OffsetDateTime::now_utc());
+                        }
+                        HttpEvent::ClientHttpHandshakeStart => {
+                            guard.timing.server_conn_http_handshake =
+                                Some(OffsetDateTime::now_utc());
+                        }
+                        HttpEvent::ClientHttpHandshakeComplete => {}
+                        HttpEvent::ClientTlsConn(tls_conn_data, server_verification) => {
+                            guard.certs.server_tls = Some(tls_conn_data);
+                            guard.certs.server_verification = Some(server_verification);
+                            guard.timing.server_conn_tls_handshake =
+                                Some(OffsetDateTime::now_utc());
+                        }
+                        HttpEvent::ServerTlsConn(_server_tls_conn, _client_verification) => {
+                            // TODO: this is captured earlier in the flow
+                            // guard.certs.client_tls = Some(server_tls_conn);
+                            // guard.certs.client_verification = Some(client_verification);
+                        }
+                        HttpEvent::ServerTlsConnInitiated => {
+                            guard.timing.server_conn_tls_initiated = Some(OffsetDateTime::now_utc())
+                        }
+                        HttpEvent::ClientTlsHandshake => {
+                            guard.timing.server_conn_tls_initiated =
+                                Some(OffsetDateTime::now_utc());
+                        }
+                        HttpEvent::ConnectionReused(info) => {
+                            guard.server_connection = info.addr.map(|addr| FlowConnection { addr });
+                            if let Some((tls_conn_data, server_verification)) = info.tls {
+                                guard.certs.server_tls = Some(tls_conn_data);
+                                guard.certs.server_verification = Some(server_verification);
+                            }
+                            guard.timing.server_conn_reused = true;
+                        }
+                        HttpEvent::Redirected(redirect) => guard.redirects.push(redirect),
+                        HttpEvent::Informational(status, headers) => {
+                            guard.interim_responses.push(InterimResponse {
+                                status,
+                                headers,
+                                timestamp: OffsetDateTime::now_utc(),
+                            });
+                        }
+                    },
+                    FlowEvent::Response(resp) => {
+                        let now = OffsetDateTime::now_utc();
+                        guard.timing.first_response_bytes.get_or_insert(now);
+                        guard.timing.response_complete.get_or_insert(now);
+                        guard.add_tags(&resp.tags);
+                        guard.response = Some(resp);
+                        persist = true;
+                        completed = true;
+                    }
+                    FlowEvent::ResponseHead(resp) => {
+                        guard
+                            .timing
+                            .first_response_bytes
+                            .get_or_insert_with(OffsetDateTime::now_utc);
+                        guard.add_tags(&resp.tags);
+                        guard.response = Some(resp);
+                    }
+                    FlowEvent::ResponseChunk(chunk) => {
+                        guard
+                            .timing
+                            .first_response_bytes
+                            .get_or_insert_with(OffsetDateTime::now_utc);
+                        if let Some(resp) = guard.response.as_mut() {
+                            match &resp.body_file {
+                                Some(file) => match file.append(&chunk).await {
+                                    Ok(file) => resp.body_file = Some(file),
+                                    Err(err) => error!("Error appending to body file {err}"),
+                                },
+                                None => {
+                                    let mut body =
+                                        BytesMut::with_capacity(resp.body.len() + chunk.len());
+                                    body.extend_from_slice(&resp.body);
+                                    body.extend_from_slice(&chunk);
+                                    resp.body = body.freeze();
+                                    if let Some(spill) = &spill {
+                                        resp.spill(spill).await;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    FlowEvent::ResponseEnd(trailers) => {
+                        guard.timing.response_complete = Some(OffsetDateTime::now_utc());
+                        if let Some(resp) = guard.response.as_mut() {
+                            resp.trailers = trailers;
+                        }
+                        persist = true;
+                        completed = true;
+                    }
+                    FlowEvent::WsMessage(wsm) => {
+                        persist = matches!(wsm.message, Message::Close(_));
+                        guard.messages.push(wsm);
+                    }
+                    FlowEvent::Request(request) => {
+                        guard.add_tags(&request.tags);
+                        guard.request = Some(request);
+                    }
+                    FlowEvent::RequestComplete => {
+                        guard.timing.request_complete = Some(OffsetDateTime::now_utc());
+                        request_complete = true;
+                    }
+                    FlowEvent::Error(error) => {
+                        guard.error = Some(error);
+                        persist = true;
+                        // A streamed response is counted once it ends
+                        completed = guard.response.is_none();
+                    }
+                    FlowEvent::Failed(error, page) => {
+                        guard.timing.response_complete = Some(OffsetDateTime::now_utc());
+                        guard.error = Some(error);
+                        guard.response = Some(page);
+                        persist = true;
+                        completed = true;
+                    }
+                    FlowEvent::Version(choice) => guard.version_choice = Some(choice),
+                    FlowEvent::ScriptLog(log) => guard.script_log.push(log),
+                    FlowEvent::Wire(from, capture) => guard.wire.set(from, capture),
+                    FlowEvent::TunnelData(chunk) => {
+                        if let Some(tunnel) = guard.tunnel.as_mut() {
+                            tunnel.record(chunk);
+                        }
+                    }
+                    FlowEvent::TunnelClosed(error) => {
+                        let now = OffsetDateTime::now_utc();
+                        guard.timing.client_conn_closed = Some(now);
+                        guard.timing.server_conn_closed = Some(now);
+                        guard.error = error;
+                        persist = true;
+                    }
+                }
+                // Decided once the flow is finished, filters look at the response
+                if persist
+                    && !guard.starred
+                    && let Some(profile) = fs.capture_profile()
+                    && !profile.filter.matches(&guard)
+                {
+                    drop(guard);
+                    drop(flow);
+                    // Persisted before the profile was set
+                    if fs.retention.is_in_session(flow_id)
+                        && let Some(path) = fs.session.clone()
+                        && let Err(err) =
+                            blocking(move || remove_from_session(&path, &HashSet::from([flow_id])))
+                                .await
+                    {
+                        error!("Error removing flow {flow_id} from session {err}");
+                    }
+                    fs.remove_ids(&HashSet::from([flow_id])).await;
+                    fs.flows.remove(&flow_id);
+                    fs.retention.forget(flow_id);
+                    fs.dropped.fetch_add(1, Ordering::Relaxed);
+                    fs.notify();
+                    continue;
+                }
+                let completed = completed && !guard.passthrough;
+                if completed {
+                    fs.metrics.record(&guard);
+                }
+                fs.retention.track(flow_id, body_bytes(&guard));
+                fs.retention.set_in_session(flow_id, false);
+                let record = match (&fs.session, persist) {
+                    (Some(path), true) => Some((path.clone(), encode_flow(&guard))),
+                    _ => None,
+                };
+                drop(guard);
+                drop(flow);
+                if request_complete {
+                    fs.publish(flow_id, FlowUpdateKind::RequestComplete);
+                }
+                if completed {
+                    fs.publish(flow_id, FlowUpdateKind::ResponseComplete);
+                }
+
+                if let Some((path, line)) = record {
+                    match line {
+                        Ok(line) => match append_session(&path, &line).await {
+                            Ok(()) => fs.retention.set_in_session(flow_id, true),
+                            Err(err) => error!("Error appending flow {flow_id} to session {err}"),
+                        },
+                        Err(err) => error!("Error encoding flow {flow_id} {err}"),
+                    }
+                }
+
+                fs.flow_changed(flow_id);
+                if persist || completed {
+                    fs.enforce_retention().await;
+                }
+            }
+        });
+    }
+}
+
+#[derive(Debug)]
+pub struct FlowEventEmitter {
+    id: FlowId,
+    flow_store: FlowStore,
+    upstream_tls: UpstreamTls,
+}
+
+impl FlowEventEmitter {
+    pub fn new(id: FlowId, flow_store: FlowStore) -> Self {
+        Self {
+            id,
+            flow_store,
+            upstream_tls: UpstreamTls::default(),
+        }
+    }
+
+    /// Set once the upstream TLS handshake completes, events only reach the
+    /// flow asynchronously so this is read when the response is intercepted.
+    pub fn upstream_tls(&self) -> UpstreamTls {
+        self.upstream_tls.clone()
+    }
+}
+
+impl HttpEmitter for FlowEventEmitter {
+    fn emit(&self, event: roxy_shared::http::HttpEvent) {
+        match &event {
+            HttpEvent::ClientTlsConn(tls, verification)
+            | HttpEvent::ConnectionReused(ConnectionInfo {
+                tls: Some((tls, verification)),
+                ..
+            }) => self
+                .upstream_tls
+                .set(FlowTls::from_upstream(tls, verification)),
+            _ => {}
+        }
+        self.flow_store
+            .post_event(self.id, FlowEvent::HttpEvent(event));
+    }
+}
+
+/// The upstream connection of a request as seen by [`FlowEventEmitter`].
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamTls(Arc<std::sync::Mutex<Option<FlowTls>>>);
+
+impl UpstreamTls {
+    pub fn get(&self) -> Option<FlowTls> {
+        match self.0.lock() {
+            Ok(tls) => tls.clone(),
+            Err(err) => {
+                error!("Upstream tls lock poisoned {err}");
+                None
+            }
+        }
+    }
+
+    fn set(&self, tls: FlowTls) {
+        match self.0.lock() {
+            Ok(mut guard) => *guard = Some(tls),
+            Err(err) => error!("Upstream tls lock poisoned {err}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FlowEvent {
+    Response(InterceptedResponse),
+    /// Headers of a streamed response, its body follows as `ResponseChunk`s.
+    ResponseHead(InterceptedResponse),
+    ResponseChunk(Bytes),
+    /// A streamed response finished, with its trailers if any.
+    ResponseEnd(Option<HeaderMap>),
+    WsMessage(WsMessage),
+    HttpEvent(HttpEvent),
+    /// The request was modified after the flow was created.
+    Request(InterceptedRequest),
+    /// The request is being sent to the server.
+    RequestComplete,
+    Error(FlowError),
+    /// No response came from the server, the client was answered with a
+    /// page describing the error instead.
+    Failed(FlowError, InterceptedResponse),
+    /// Bytes one side of a raw tunnel sent.
+    TunnelData(TunnelChunk),
+    /// A passthrough or raw tunnel finished, with the error that ended it if
+    /// any.
+    TunnelClosed(Option<FlowError>),
+    /// The server answered, with the version it was spoken to with.
+    Version(VersionChoice),
+    /// A script sent a notification while intercepting the flow.
+    ScriptLog(ScriptLog),
+    /// Where the bytes one side sent are captured, while raw capture is on.
+    Wire(WsDirection, WireCapture),
+}
+
+impl FlowEvent {
+    async fn spill(&mut self, spill: &BodySpill) {
+        match self {
+            FlowEvent::Response(resp) | FlowEvent::ResponseHead(resp) => resp.spill(spill).await,
+            FlowEvent::Request(req) => req.spill(spill).await,
+            _ => {}
+        }
+    }
+}
+
+/// Removes the headers that only describe one connection: framing, which is
+/// redone whenever the message is sent again, and the ones of RFC 9110 7.6.1
+/// along with those `Connection` names. `TE` and `Upgrade` are kept, except
+/// an upgrade to `h2c` which RFC 9113 deprecates, those requests are answered
+/// over HTTP/1.1.
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let named = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .filter(|name| name != TE && name != UPGRADE)
+        .collect::<Vec<_>>();
+    for name in named {
+        headers.remove(name);
+    }
+    for name in [CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING] {
+        headers.remove(name);
+    }
+    headers.remove("keep-alive");
+    headers.remove("proxy-connection");
+    if headers
+        .get(UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case("h2c"))
+    {
+        headers.remove(UPGRADE);
+        headers.remove("http2-settings");
+    }
+}
+
+/// `body` without its content `encoding`. A body that fails to decode is
+/// kept as it came with no encoding, so it is not encoded a second time.
+fn decode_content(
+    body: Bytes,
+    encoding: Option<Vec<Encodings>>,
+) -> (Bytes, Option<Vec<Encodings>>) {
+    let Some(enc) = encoding else {
+        return (body, None);
+    };
+    match decode_body(&body, &enc) {
+        Ok(decoded) => (decoded, Some(enc)),
+        Err(e) => {
+            warn!("Failed to decode body encoding err: '{e}'");
+            (body, None)
+        }
+    }
+}
+
+/// What a body looked like before a script ran, see
+/// [`InterceptedResponse::before_script`].
+#[derive(Debug)]
+pub(crate) struct ScriptBody {
+    encoded: bool,
+    content_encoding: Option<HeaderValue>,
+}
+
+fn before_script(
+    headers: &HeaderMap,
+    encoding: &Option<Vec<Encodings>>,
+    body: &mut Bytes,
+    decoded: bool,
+) -> ScriptBody {
+    let encoded = !decoded && !body.is_empty() && encoding.is_some();
+    if encoded && let Some(enc) = encoding {
+        match encode_body(body, enc) {
+            Ok(encoded) => *body = encoded,
+            Err(e) => error!("Failed to encode body {e}"),
+        }
+    }
+    ScriptBody {
+        encoded,
+        content_encoding: headers.get(CONTENT_ENCODING).cloned(),
+    }
+}
+
+fn after_script(
+    headers: &mut HeaderMap,
+    encoding: &mut Option<Vec<Encodings>>,
+    body: &mut Bytes,
+    before: ScriptBody,
+) {
+    if before.encoded {
+        let (decoded, enc) = decode_content(std::mem::take(body), get_content_encoding(headers));
+        *body = decoded;
+        *encoding = enc;
+    } else if headers.get(CONTENT_ENCODING) != before.content_encoding.as_ref() {
+        *encoding = get_content_encoding(headers);
+    }
+    // Framing is redone for the body that is finally sent
+    headers.remove(CONTENT_LENGTH);
+}
+
+/// Copies of `flows` that can be written out without holding their locks.
+async fn copies(flows: &[Arc<RwLock<Flow>>]) -> Vec<Flow> {
+    let mut copies = Vec::with_capacity(flows.len());
+    for flow in flows {
+        copies.push(flow.read().await.clone());
+    }
+    copies
+}
+
+/// Moves `body` into a file when it is over the spill threshold.
+async fn spill_body(spill: &BodySpill, body: &mut Bytes, body_file: &mut Option<BodyFile>) {
+    if body_file.is_some() || !spill.exceeds(body.len()) {
+        return;
+    }
+    match spill.write(body).await {
+        Ok(file) => {
+            *body_file = Some(file);
+            *body = Bytes::new();
+        }
+        Err(err) => warn!("Failed to spill body, keeping it in memory {err}"),
+    }
+}
+
+impl Default for FlowStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Flow {
+    pub id: FlowId,
+    pub timing: Timing,
+
+    pub client_connection: FlowConnection,
+    pub request: Option<InterceptedRequest>,
+
+    pub server_connection: Option<FlowConnection>,
+    pub response: Option<InterceptedResponse>,
+
+    pub error: Option<FlowError>,
+
+    pub certs: FlowCerts,
+
+    pub messages: Vec<WsMessage>,
+
+    /// Tunneled byte-for-byte, only the connection itself is recorded.
+    pub passthrough: bool,
+
+    /// The bytes of an intercepted connection that did not speak HTTP.
+    pub tunnel: Option<RawTunnel>,
+
+    /// Added by scripts to the request or response, in the order they were added.
+    pub tags: Vec<String>,
+
+    /// Redirects Roxy's client followed before the response, e.g. for replays
+    /// sent with a redirect policy. The request is the one first sent.
+    pub redirects: Vec<Redirect>,
+
+    /// 1xx responses such as 103 Early Hints the server sent ahead of the
+    /// final response. They are recorded only, hyper can not send them on to
+    /// the client.
+    pub interim_responses: Vec<InterimResponse>,
+
+    /// The version the client asked for and the one the server answered
+    /// with, set once a response arrived from the server.
+    pub version_choice: Option<VersionChoice>,
+
+    /// Marked in the flow list to keep it at hand, persisted with the session.
+    pub starred: bool,
+
+    /// What scripts notified while intercepting the flow, oldest first.
+    pub script_log: Vec<ScriptLog>,
+
+    /// The HTTP/1 messages as read off the connections, when raw capture
+    /// was on.
+    pub wire: FlowWire,
+}
+
+/// A notification a script sent while intercepting a flow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptLog {
+    pub level: FlowNotifyLevel,
+    pub msg: String,
+    pub timestamp: OffsetDateTime,
+}
+
+impl ScriptLog {
+    pub(crate) fn new(level: FlowNotifyLevel, msg: String) -> Self {
+        Self {
+            level,
+            msg,
+            timestamp: OffsetDateTime::now_utc(),
+        }
+    }
+}
+
+/// A 1xx response received before the final one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterimResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub timestamp: OffsetDateTime,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct FlowCerts {
+    pub client_hello: Option<CapturedClientHello>,
+    pub client_verification: Option<ClientVerificationCapture>,
+    pub client_tls: Option<ServerTlsConnectionData>,
+
+    pub server_resolve_client_cert: Option<CapturedResolveClientCert>,
+    pub server_verification: Option<ServerVerificationCapture>,
+    pub server_tls: Option<ClientTlsConnectionData>,
+}
+
+impl FlowCerts {
+    /// The upstream certificate chain as DER, end entity first.
+    pub fn server_chain(&self) -> Vec<Bytes> {
+        self.server_certs()
+            .map(VerifyServerCert::chain)
+            .unwrap_or_default()
+    }
+
+    /// The upstream certificate chain parsed, end entity first.
+    pub fn parsed_server_chain(&self) -> Vec<ParsedCert> {
+        self.server_certs()
+            .map(VerifyServerCert::parsed_chain)
+            .unwrap_or_default()
+    }
+
+    fn server_certs(&self) -> Option<&VerifyServerCert> {
+        self.server_verification.as_ref()?.cert.as_ref()
+    }
+}
+
+/// What scripts see of the TLS connection a request or response used.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowTls {
+    /// e.g. `TLSv1_3`.
+    pub version: Option<String>,
+    /// e.g. `TLS13_AES_128_GCM_SHA256`.
+    pub cipher: Option<String>,
+    pub alpn: Option<String>,
+    pub sni: Option<String>,
+    /// Subject of the certificate the other side presented.
+    pub peer_subject: Option<String>,
+}
+
+impl FlowTls {
+    /// The connection from the client, its certificate is only known with mutual TLS.
+    pub fn from_client(
+        tls: &ServerTlsConnectionData,
+        verification: Option<&ClientVerificationCapture>,
+    ) -> Self {
+        Self {
+            version: tls.protocol_version.map(|v| format!("{v:?}")),
+            cipher: tls.cipher_suite.map(|c| format!("{:?}", c.suite())),
+            alpn: alpn_name(&tls.alpn),
+            sni: tls.sni.clone(),
+            peer_subject: verification
+                .and_then(|v| v.cert.as_ref())
+                .and_then(|c| ParsedCert::from_der(c.end_entity.clone()).ok())
+                .map(|c| c.subject),
+        }
+    }
+
+    /// The connection to the upstream server.
+    pub fn from_upstream(
+        tls: &ClientTlsConnectionData,
+        verification: &ServerVerificationCapture,
+    ) -> Self {
+        let cert = verification.cert.as_ref();
+        Self {
+            version: tls.protocol_version.map(|v| format!("{v:?}")),
+            cipher: tls.cipher_suite.map(|c| format!("{:?}", c.suite())),
+            alpn: alpn_name(&tls.alpn),
+            sni: cert.map(|c| c.server_name.to_str().to_string()),
+            peer_subject: cert
+                .and_then(|c| ParsedCert::from_der(c.end_entity.clone()).ok())
+                .map(|c| c.subject),
+        }
+    }
+}
+
+fn alpn_name(alpn: &AlpnProtocol) -> Option<String> {
+    match alpn {
+        AlpnProtocol::None => None,
+        alpn => Some(String::from_utf8_lossy(alpn.to_bytes()).into_owned()),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FlowConnection {
+    pub addr: SocketAddr,
+}
+
+impl Flow {
+    pub(crate) fn new(
+        id: FlowId,
+        client_connection: FlowConnection,
+        request: Option<InterceptedRequest>,
+    ) -> Self {
+        Self {
+            id,
+            timing: Timing::default(),
+            client_connection,
+            server_connection: None,
+            tags: request.as_ref().map(|r| r.tags.clone()).unwrap_or_default(),
+            request,
+            response: None,
+            certs: FlowCerts::default(),
+            error: None,
+            messages: vec![],
+            passthrough: false,
+            tunnel: None,
+            redirects: vec![],
+            interim_responses: vec![],
+            version_choice: None,
+            starred: false,
+            script_log: vec![],
+            wire: FlowWire::default(),
+        }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Adds the tags not on the flow yet, tags are never removed.
+    fn add_tags(&mut self, tags: &[String]) {
+        for tag in tags {
+            if !self.has_tag(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+    }
+}
+
+/// A flow from `127.0.0.1` for `method` `uri` answered with `status` and
+/// `body`, for tests to adjust.
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+pub(crate) fn test_flow(
+    id: i64,
+    method: http::Method,
+    uri: &str,
+    status: StatusCode,
+    body: &'static [u8],
+) -> Flow {
+    let request = InterceptedRequest {
+        method,
+        uri: uri.parse().unwrap(),
+        ..Default::default()
+    };
+    let mut flow = Flow::new(
+        FlowId(id),
+        FlowConnection {
+            addr: ([127, 0, 0, 1], 0).into(),
+        },
+        Some(request),
+    );
+    flow.response = Some(InterceptedResponse {
+        status,
+        body: Bytes::from_static(body),
+        ..Default::default()
+    });
+    flow
+}
+
+#[derive(Debug, Clone)]
+pub struct WsMessage {
+    pub message: Message,
+    pub direction: WsDirection,
+    pub timestamp: OffsetDateTime,
+}
+
+impl WsMessage {
+    pub fn client(message: Message) -> Self {
+        Self {
+            message,
+            direction: WsDirection::Client,
+            timestamp: OffsetDateTime::now_utc(),
+        }
+    }
+    pub fn server(message: Message) -> Self {
+        Self {
+            message,
+            direction: WsDirection::Server,
+            timestamp: OffsetDateTime::now_utc(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsDirection {
+    Client,
+    Server,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TlsMetadata {
+    pub sni: Option<String>,
+    pub alpn: Option<String>,
+    pub negotiated_cipher: Option<String>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Timing {
+    pub client_conn_established: Option<OffsetDateTime>,
+    pub client_conn_tls_handshake: Option<OffsetDateTime>,
+
+    pub server_conn_initiated: Option<OffsetDateTime>,
+    pub server_conn_dns_resolved: Option<OffsetDateTime>,
+    /// How long looking up the server's address took, the address itself is
+    /// the flow's `server_connection`.
+    pub server_conn_dns_lookup: Option<Duration>,
+    pub server_conn_tcp_handshake: Option<OffsetDateTime>,
+    /// The request went over a pooled connection opened for an earlier flow,
+    /// the other server connection timings are then empty.
+    pub server_conn_reused: bool,
+
+    pub server_conn_tls_initiated: Option<OffsetDateTime>,
+    pub server_conn_tls_handshake: Option<OffsetDateTime>,
+
+    pub server_conn_http_handshake: Option<OffsetDateTime>,
+
+    pub first_request_bytes: Option<OffsetDateTime>,
+    pub request_complete: Option<OffsetDateTime>,
+
+    pub first_response_bytes: Option<OffsetDateTime>,
+    pub response_complete: Option<OffsetDateTime>,
+
+    pub client_conn_closed: Option<OffsetDateTime>,
+    pub server_conn_closed: Option<OffsetDateTime>,
+}
+
+/// A span of the exchange drawn as one bar of the timing waterfall.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimingPhase {
+    pub name: &'static str,
+    pub start: OffsetDateTime,
+    pub end: OffsetDateTime,
+}
+
+impl TimingPhase {
+    pub fn duration(&self) -> time::Duration {
+        self.end - self.start
+    }
+}
+
+impl Timing {
+    /// The phases of the exchange that were timed, in the order they
+    /// happen. Waiting runs from the request being ready to go out, once
+    /// intercepted and connected, until the first response bytes.
+    pub fn waterfall(&self) -> Vec<TimingPhase> {
+        let sent = [
+            self.request_complete,
+            self.server_conn_tcp_handshake,
+            self.server_conn_tls_handshake,
+        ]
+        .into_iter()
+        .flatten()
+        .max();
+        let connect_start = self.server_conn_dns_resolved.or(self.server_conn_initiated);
+        [
+            ("intercept", self.first_request_bytes, self.request_complete),
+            (
+                "dns",
+                self.server_conn_initiated,
+                self.server_conn_dns_resolved,
+            ),
+            ("connect", connect_start, self.server_conn_tcp_handshake),
+            (
+                "tls",
+                self.server_conn_tls_initiated,
+                self.server_conn_tls_handshake,
+            ),
+            ("waiting", sent, self.first_response_bytes),
+            (
+                "download",
+                self.first_response_bytes,
+                self.response_complete,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(name, start, end)| match (start, end) {
+            (Some(start), Some(end)) if end >= start => Some(TimingPhase { name, start, end }),
+            _ => None,
+        })
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterceptedRequest {
+    pub timestamp: OffsetDateTime,
+    pub uri: RUri,
+    pub encoding: Option<Vec<Encodings>>,
+    pub alpn: AlpnProtocol,
+    pub method: http::Method,
+    pub version: HttpVersion,
+    pub headers: HeaderMap,
+    pub body: bytes::Bytes,
+    /// Set when the body was too large to keep in memory, `body` is then empty.
+    pub body_file: Option<BodyFile>,
+    pub trailers: Option<HeaderMap>,
+    /// The TLS connection this arrived on, `None` for plain HTTP.
+    pub tls: Option<FlowTls>,
+    /// Tags scripts added while handling this, copied onto the flow.
+    pub tags: Vec<String>,
+    /// The header lines as an HTTP/1 client wrote them, which name and order
+    /// `headers` when it is shown and sent on.
+    pub raw_headers: Option<RawHeaders>,
+}
+
+impl Default for InterceptedRequest {
+    fn default() -> Self {
+        Self {
+            timestamp: OffsetDateTime::now_utc(),
+            uri: RUri::default(),
+            encoding: None,
+            alpn: AlpnProtocol::None,
+            method: http::Method::GET,
+            version: HttpVersion(Version::HTTP_11),
+            headers: HeaderMap::new(),
+            body: bytes::Bytes::new(),
+            body_file: None,
+            trailers: None,
+            tls: None,
+            tags: vec![],
+            raw_headers: None,
+        }
+    }
+}
+
+impl InterceptedRequest {
+    pub fn from_http(
+        uri: RUri,
+        alpn: AlpnProtocol,
+        mut parts: http::request::Parts,
+        body_bytes: bytes::Bytes,
+        trailers: Option<HeaderMap>,
+    ) -> Self {
+        let (body, encoding) = decode_content(body_bytes, get_content_encoding(&parts.headers));
+        let raw_headers = parts.extensions.remove::<RawHeaders>().map(|raw| {
+            let mut case = parts.extensions.clone();
+            case.remove::<hyper::upgrade::OnUpgrade>();
+            // Captures belong to the client connection, the upstream one
+            // captures its own
+            case.remove::<WireCapture>();
+            raw.with_case(case)
+        });
+        let mut headers = parts.headers;
+        strip_hop_by_hop(&mut headers);
+
+        InterceptedRequest {
+            timestamp: OffsetDateTime::now_utc(),
+            uri: uri.clone(),
+            encoding,
+            alpn,
+            method: parts.method,
+            version: parts.version.into(),
+            headers,
+            body,
+            body_file: None,
+            trailers,
+            tls: None,
+            tags: vec![],
+            raw_headers,
+        }
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        if self.uri.scheme_str().is_some() {
+            return self.uri.scheme();
+        }
+        if self.alpn.is_tls() {
+            Scheme::Https
+        } else {
+            Scheme::Http
+        }
+    }
+
+    pub fn line_pretty(&self) -> String {
+        self.uri.inner.to_string()
+    }
+
+    /// The body, read back from disk when it was spilled.
+    pub async fn load_body(&self) -> std::io::Result<Bytes> {
+        match &self.body_file {
+            Some(file) => file.load().await,
+            None => Ok(self.body.clone()),
+        }
+    }
+
+    /// Blocking version of [`Self::load_body`].
+    pub fn read_body(&self) -> std::io::Result<Bytes> {
+        match &self.body_file {
+            Some(file) => file.read(),
+            None => Ok(self.body.clone()),
+        }
+    }
+
+    /// Brings a spilled body back into memory, needed before it is sent again.
+    pub async fn unspill(&mut self) -> std::io::Result<()> {
+        if let Some(file) = &self.body_file {
+            self.body = file.load().await?;
+            self.body_file = None;
+        }
+        Ok(())
+    }
+
+    pub fn body_len(&self) -> usize {
+        self.body_file
+            .as_ref()
+            .map_or(self.body.len(), BodyFile::len)
+    }
+
+    pub(crate) async fn spill(&mut self, spill: &BodySpill) {
+        spill_body(spill, &mut self.body, &mut self.body_file).await;
+    }
+
+    pub fn request_builder(&self) -> http::request::Builder {
+        let parts = format!(
+            "{}://{}:{}{}",
+            self.uri.scheme(),
+            self.uri.host(),
+            self.uri.port(),
+            self.uri.path_and_query()
+        );
+
+        let mut builder = http::Request::builder()
+            .method(self.method.clone())
+            .uri(parts)
+            .version(self.version.0);
+
+        match &self.raw_headers {
+            Some(raw) => {
+                for (key, value) in raw.order(&self.headers) {
+                    builder = builder.header(key, value);
+                }
+                if let Some(extensions) = builder.extensions_mut() {
+                    extensions.extend(raw.case().clone());
+                }
+            }
+            None => {
+                for (key, value) in self.headers.iter() {
+                    builder = builder.header(key, value);
+                }
+            }
+        }
+        builder
+    }
+
+    /// The headers named and ordered as the client sent them when it spoke
+    /// HTTP/1, as held in `headers` otherwise.
+    pub fn header_lines(&self) -> Vec<(String, String)> {
+        let lines: Vec<(&str, &HeaderValue)> = match &self.raw_headers {
+            Some(raw) => raw.order(&self.headers),
+            None => self
+                .headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value))
+                .collect(),
+        };
+        lines
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect()
+    }
+
+    /// See [`InterceptedResponse::before_script`].
+    pub(crate) fn before_script(&mut self, decoded: bool) -> ScriptBody {
+        before_script(&self.headers, &self.encoding, &mut self.body, decoded)
+    }
+
+    /// See [`InterceptedResponse::after_script`].
+    pub(crate) fn after_script(&mut self, before: ScriptBody) {
+        after_script(
+            &mut self.headers,
+            &mut self.encoding,
+            &mut self.body,
+            before,
+        );
+    }
+
+    pub fn request(&self) -> Result<http::Request<BytesBody>, http::Error> {
+        // Without chunked encoding HTTP/1.0 has nowhere to put trailers
+        let trailers = match self.version.0 {
+            Version::HTTP_09 | Version::HTTP_10 => None,
+            _ => self.trailers.clone(),
+        };
+        self.request_builder().body(create_http_body(
+            self.body.clone(),
+            self.encoding.clone(),
+            trailers,
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterceptedResponse {
+    pub timestamp: OffsetDateTime,
+    pub status: StatusCode,
+    pub version: HttpVersion,
+    pub headers: HeaderMap,
+    pub encoding: Option<Vec<Encodings>>,
+    pub body: bytes::Bytes,
+    /// Set when the body was too large to keep in memory, `body` is then empty.
+    pub body_file: Option<BodyFile>,
+    pub trailers: Option<HeaderMap>,
+    /// The TLS connection this arrived on, `None` for plain HTTP.
+    pub tls: Option<FlowTls>,
+    /// Tags scripts added while handling this, copied onto the flow.
+    pub tags: Vec<String>,
+}
+
+impl Default for InterceptedResponse {
+    fn default() -> Self {
+        Self {
+            timestamp: OffsetDateTime::now_utc(),
+            status: StatusCode::OK,
+            version: HttpVersion(Version::HTTP_11),
+            headers: HeaderMap::new(),
+            encoding: None,
+            body: bytes::Bytes::new(),
+            body_file: None,
+            trailers: None,
+            tls: None,
+            tags: vec![],
+        }
+    }
+}
+
+impl InterceptedResponse {
+    pub fn from_http(
+        parts: http::response::Parts,
+        body_bytes: bytes::Bytes,
+        trailers: Option<HeaderMap>,
+    ) -> Self {
+        let (body, encoding) = decode_content(body_bytes, get_content_encoding(&parts.headers));
+
+        let mut headers = parts.headers;
+        strip_hop_by_hop(&mut headers);
+
+        InterceptedResponse {
+            timestamp: OffsetDateTime::now_utc(),
+            status: parts.status,
+            version: parts.version.into(),
+            headers,
+            encoding,
+            body,
+            body_file: None,
+            trailers,
+            tls: None,
+            tags: vec![],
+        }
+    }
+
+    pub fn request_line(&self) -> String {
+        format!("{:?} {}", self.version, self.status)
+    }
+
+    /// The body, read back from disk when it was spilled.
+    pub async fn load_body(&self) -> std::io::Result<Bytes> {
+        match &self.body_file {
+            Some(file) => file.load().await,
+            None => Ok(self.body.clone()),
+        }
+    }
+
+    /// Blocking version of [`Self::load_body`].
+    pub fn read_body(&self) -> std::io::Result<Bytes> {
+        match &self.body_file {
+            Some(file) => file.read(),
+            None => Ok(self.body.clone()),
+        }
+    }
+
+    /// Brings a spilled body back into memory, needed before it is sent again.
+    pub async fn unspill(&mut self) -> std::io::Result<()> {
+        if let Some(file) = &self.body_file {
+            self.body = file.load().await?;
+            self.body_file = None;
+        }
+        Ok(())
+    }
+
+    pub fn body_len(&self) -> usize {
+        self.body_file
+            .as_ref()
+            .map_or(self.body.len(), BodyFile::len)
+    }
+
+    pub(crate) async fn spill(&mut self, spill: &BodySpill) {
+        spill_body(spill, &mut self.body, &mut self.body_file).await;
+    }
+
+    /// Encodes the body again for a script that reads bodies as they went
+    /// over the wire, unless `decoded`.
+    pub(crate) fn before_script(&mut self, decoded: bool) -> ScriptBody {
+        before_script(&self.headers, &self.encoding, &mut self.body, decoded)
+    }
+
+    /// Undoes [`Self::before_script`] and follows a `Content-Encoding` the
+    /// script changed, the body is encoded with it when sent.
+    pub(crate) fn after_script(&mut self, before: ScriptBody) {
+        after_script(
+            &mut self.headers,
+            &mut self.encoding,
+            &mut self.body,
+            before,
+        );
+    }
+
+    pub fn response_builder(&self) -> http::response::Builder {
+        let mut builder = http::Response::builder()
+            .status(self.status)
+            .version(self.version.0);
+
+        for (key, value) in self.headers.iter() {
+            builder = builder.header(key, value)
+        }
+        builder
+    }
+
+    pub fn response(&self) -> Result<http::Response<BytesBody>, http::Error> {
+        let builder = self.response_builder();
+
+        builder.body(create_http_body(
+            self.body.clone(),
+            self.encoding.clone(),
+            self.trailers.clone(),
+        ))
+    }
+}
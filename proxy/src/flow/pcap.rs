@@ -0,0 +1,484 @@
+//! Decrypted HTTP transactions as a pcapng capture for Wireshark.
+//!
+//! Each flow becomes its own TCP connection with made up IP and TCP framing:
+//! a handshake, the request and response as HTTP/1.1 text and a close. HTTP/2
+//! and HTTP/3 flows are written as HTTP/1.1 too since only their decoded
+//! messages are recorded. TLS ports are written as port 80 so Wireshark
+//! dissects the payload as HTTP rather than trying to decrypt it.
+
+use std::{
+    io::Write,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use bytes::Bytes;
+use http::{
+    HeaderMap,
+    header::{CONTENT_LENGTH, HOST},
+};
+use roxy_shared::content::{Encodings, encode_body};
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::flow::{Flow, InterceptedRequest, InterceptedResponse};
+
+const BLOCK_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const BLOCK_INTERFACE: u32 = 0x0000_0001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+/// Packets start with an IPv4 or IPv6 header, no link layer.
+const LINKTYPE_RAW: u16 = 101;
+
+/// Payload bytes per TCP segment.
+const MAX_SEGMENT: usize = 1460;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+const PROTOCOL_TCP: u8 = 6;
+
+/// Client side sequence numbers start here, the server's at twice this.
+const INITIAL_SEQUENCE: u32 = 1_000;
+
+/// Where a flow's server is drawn when its address was not recorded.
+const UNKNOWN_SERVER_V4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+const UNKNOWN_SERVER_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+
+/// Writes the flows with a request as a pcapng capture, returning how many
+/// were written.
+pub(crate) fn write_pcapng<'a>(
+    mut writer: impl Write,
+    flows: impl Iterator<Item = &'a Flow>,
+) -> std::io::Result<usize> {
+    writer.write_all(&section_header())?;
+    writer.write_all(&interface_description())?;
+    let mut count = 0;
+    for flow in flows {
+        let Some(request) = &flow.request else {
+            continue;
+        };
+        for (timestamp, packet) in connection(flow, request) {
+            writer.write_all(&enhanced_packet(timestamp, &packet))?;
+        }
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Wraps `body` in a pcapng block, padding it to 32 bits.
+fn block(kind: u32, body: &[u8]) -> Vec<u8> {
+    let padding = (4 - body.len() % 4) % 4;
+    let len = (12 + body.len() + padding) as u32;
+    let mut out = Vec::with_capacity(len as usize);
+    out.extend_from_slice(&kind.to_le_bytes());
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(body);
+    out.extend(std::iter::repeat_n(0, padding));
+    out.extend_from_slice(&len.to_le_bytes());
+    out
+}
+
+fn section_header() -> Vec<u8> {
+    let mut body = vec![];
+    body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    // Version 1.0
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    // Section length unknown
+    body.extend_from_slice(&(-1i64).to_le_bytes());
+    block(BLOCK_SECTION_HEADER, &body)
+}
+
+fn interface_description() -> Vec<u8> {
+    let mut body = vec![];
+    body.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    // Reserved
+    body.extend_from_slice(&0u16.to_le_bytes());
+    // No snap length
+    body.extend_from_slice(&0u32.to_le_bytes());
+    block(BLOCK_INTERFACE, &body)
+}
+
+fn enhanced_packet(timestamp: OffsetDateTime, packet: &[u8]) -> Vec<u8> {
+    // Microseconds, the default interface resolution
+    let micros = u64::try_from(timestamp.unix_timestamp_nanos() / 1_000).unwrap_or_default();
+    let mut body = vec![];
+    // Interface 0
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(micros as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(packet);
+    block(BLOCK_ENHANCED_PACKET, &body)
+}
+
+/// One direction of the made up TCP connection.
+struct Peer {
+    addr: SocketAddr,
+    seq: u32,
+}
+
+/// The packets of `flow` from the handshake to the close, with the time each
+/// is stamped with.
+fn connection(flow: &Flow, request: &InterceptedRequest) -> Vec<(OffsetDateTime, Vec<u8>)> {
+    let mut client = Peer {
+        addr: flow.client_connection.addr,
+        seq: INITIAL_SEQUENCE,
+    };
+    let mut server = Peer {
+        addr: server_addr(flow, request),
+        seq: INITIAL_SEQUENCE * 2,
+    };
+    let start = request.timestamp;
+    let end = flow
+        .timing
+        .response_complete
+        .or(flow.response.as_ref().map(|r| r.timestamp))
+        .unwrap_or(start);
+
+    let mut packets = vec![];
+    packets.push((start, segment(&mut client, &server, TCP_SYN, &[])));
+    packets.push((start, segment(&mut server, &client, TCP_SYN | TCP_ACK, &[])));
+    packets.push((start, segment(&mut client, &server, TCP_ACK, &[])));
+
+    send(
+        &mut packets,
+        start,
+        &mut client,
+        &mut server,
+        &request_bytes(request),
+    );
+    if let Some(response) = &flow.response {
+        send(
+            &mut packets,
+            response.timestamp,
+            &mut server,
+            &mut client,
+            &response_bytes(response),
+        );
+    }
+
+    packets.push((end, segment(&mut client, &server, TCP_FIN | TCP_ACK, &[])));
+    packets.push((end, segment(&mut server, &client, TCP_FIN | TCP_ACK, &[])));
+    packets.push((end, segment(&mut client, &server, TCP_ACK, &[])));
+    packets
+}
+
+/// The recorded server address in the client's address family, TLS ports
+/// moved to 80.
+fn server_addr(flow: &Flow, request: &InterceptedRequest) -> SocketAddr {
+    let port = match request.uri.port() {
+        443 => 80,
+        port => port,
+    };
+    let client_v4 = flow.client_connection.addr.is_ipv4();
+    let ip = match flow.server_connection.map(|c| c.addr.ip()) {
+        Some(ip) if ip.is_ipv4() == client_v4 => ip,
+        _ if client_v4 => IpAddr::V4(UNKNOWN_SERVER_V4),
+        _ => IpAddr::V6(UNKNOWN_SERVER_V6),
+    };
+    SocketAddr::new(ip, port)
+}
+
+/// Sends `data` from `from` in segments, each acknowledged by `to`.
+fn send(
+    packets: &mut Vec<(OffsetDateTime, Vec<u8>)>,
+    timestamp: OffsetDateTime,
+    from: &mut Peer,
+    to: &mut Peer,
+    data: &[u8],
+) {
+    for chunk in data.chunks(MAX_SEGMENT) {
+        packets.push((timestamp, segment(from, to, TCP_PSH | TCP_ACK, chunk)));
+        packets.push((timestamp, segment(to, from, TCP_ACK, &[])));
+    }
+}
+
+/// An IP packet carrying a TCP segment from `from` to `to`, advancing the
+/// sequence number of `from`.
+fn segment(from: &mut Peer, to: &Peer, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let ack = if flags & TCP_ACK != 0 { to.seq } else { 0 };
+    let mut tcp = Vec::with_capacity(20 + payload.len());
+    tcp.extend_from_slice(&from.addr.port().to_be_bytes());
+    tcp.extend_from_slice(&to.addr.port().to_be_bytes());
+    tcp.extend_from_slice(&from.seq.to_be_bytes());
+    tcp.extend_from_slice(&ack.to_be_bytes());
+    // 20 byte header, no options
+    tcp.push(5 << 4);
+    tcp.push(flags);
+    // Window
+    tcp.extend_from_slice(&u16::MAX.to_be_bytes());
+    // Checksum, filled in below
+    tcp.extend_from_slice(&[0, 0]);
+    // Urgent pointer
+    tcp.extend_from_slice(&[0, 0]);
+    tcp.extend_from_slice(payload);
+
+    let consumed = payload.len() as u32 + u32::from(flags & (TCP_SYN | TCP_FIN) != 0);
+    from.seq = from.seq.wrapping_add(consumed);
+
+    match (from.addr.ip(), to.addr.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => ipv4(src, dst, tcp),
+        (IpAddr::V6(src), IpAddr::V6(dst)) => ipv6(src, dst, tcp),
+        (src, dst) => ipv6(to_v6(src), to_v6(dst), tcp),
+    }
+}
+
+fn to_v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn ipv4(src: Ipv4Addr, dst: Ipv4Addr, mut tcp: Vec<u8>) -> Vec<u8> {
+    let mut pseudo = vec![];
+    pseudo.extend_from_slice(&src.octets());
+    pseudo.extend_from_slice(&dst.octets());
+    pseudo.extend_from_slice(&[0, PROTOCOL_TCP]);
+    pseudo.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+    set_tcp_checksum(&pseudo, &mut tcp);
+
+    let mut packet = Vec::with_capacity(20 + tcp.len());
+    // Version 4, 20 byte header
+    packet.push(0x45);
+    packet.push(0);
+    packet.extend_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+    // Identification, flags and fragment offset
+    packet.extend_from_slice(&[0, 0, 0x40, 0]);
+    // TTL
+    packet.push(64);
+    packet.push(PROTOCOL_TCP);
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    let checksum = internet_checksum(&packet);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(&tcp);
+    packet
+}
+
+fn ipv6(src: Ipv6Addr, dst: Ipv6Addr, mut tcp: Vec<u8>) -> Vec<u8> {
+    let mut pseudo = vec![];
+    pseudo.extend_from_slice(&src.octets());
+    pseudo.extend_from_slice(&dst.octets());
+    pseudo.extend_from_slice(&(tcp.len() as u32).to_be_bytes());
+    pseudo.extend_from_slice(&[0, 0, 0, PROTOCOL_TCP]);
+    set_tcp_checksum(&pseudo, &mut tcp);
+
+    let mut packet = Vec::with_capacity(40 + tcp.len());
+    // Version 6, no traffic class or flow label
+    packet.extend_from_slice(&[0x60, 0, 0, 0]);
+    packet.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+    packet.push(PROTOCOL_TCP);
+    // Hop limit
+    packet.push(64);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    packet.extend_from_slice(&tcp);
+    packet
+}
+
+fn set_tcp_checksum(pseudo_header: &[u8], tcp: &mut [u8]) {
+    let mut data = pseudo_header.to_vec();
+    data.extend_from_slice(tcp);
+    let checksum = internet_checksum(&data);
+    tcp[16..18].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// The ones' complement checksum of RFC 1071.
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// The body with its content encoding applied, as it went over the wire.
+fn wire_body(body: std::io::Result<Bytes>, encoding: &Option<Vec<Encodings>>) -> Bytes {
+    let body = body.unwrap_or_else(|err| {
+        warn!("Failed to read spilled body {err}");
+        Bytes::new()
+    });
+    match encoding {
+        Some(enc) => encode_body(&body, enc).unwrap_or_else(|err| {
+            warn!("Failed to encode body {err}");
+            body
+        }),
+        None => body,
+    }
+}
+
+fn write_message(out: &mut Vec<u8>, headers: &HeaderMap, body: &[u8]) {
+    for (name, value) in headers {
+        if name == CONTENT_LENGTH {
+            continue;
+        }
+        out.extend_from_slice(name.as_str().as_bytes());
+        out.extend_from_slice(b": ");
+        out.extend_from_slice(value.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+    if !body.is_empty() {
+        out.extend_from_slice(format!("content-length: {}\r\n", body.len()).as_bytes());
+    }
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(body);
+}
+
+fn request_bytes(request: &InterceptedRequest) -> Vec<u8> {
+    let mut out = format!(
+        "{} {} HTTP/1.1\r\n",
+        request.method,
+        request.uri.path_and_query()
+    )
+    .into_bytes();
+    if !request.headers.contains_key(HOST)
+        && let Some(authority) = request.uri.inner.authority()
+    {
+        out.extend_from_slice(format!("host: {authority}\r\n").as_bytes());
+    }
+    let body = wire_body(request.read_body(), &request.encoding);
+    write_message(&mut out, &request.headers, &body);
+    out
+}
+
+fn response_bytes(response: &InterceptedResponse) -> Vec<u8> {
+    let mut out = format!(
+        "HTTP/1.1 {} {}\r\n",
+        response.status.as_u16(),
+        response.status.canonical_reason().unwrap_or_default()
+    )
+    .into_bytes();
+    let body = wire_body(response.read_body(), &response.encoding);
+    write_message(&mut out, &response.headers, &body);
+    out
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::{Method, StatusCode};
+
+    use crate::flow::test_flow;
+
+    use super::*;
+
+    fn flow(id: i64, client: &str, body: Bytes) -> Flow {
+        let mut flow = test_flow(
+            id,
+            Method::POST,
+            "https://example.com/upload?x=1",
+            StatusCode::OK,
+            b"done",
+        );
+        flow.request.as_mut().unwrap().body = body;
+        flow.client_connection.addr = client.parse().unwrap();
+        flow
+    }
+
+    /// Splits a capture into its blocks' types and bodies.
+    fn blocks(mut data: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let mut blocks = vec![];
+        while !data.is_empty() {
+            let kind = u32::from_le_bytes(data[0..4].try_into().unwrap());
+            let len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+            assert_eq!(data[len - 4..len], data[4..8]);
+            blocks.push((kind, data[8..len - 4].to_vec()));
+            data = &data[len..];
+        }
+        blocks
+    }
+
+    /// The TCP payloads of the enhanced packet blocks, for IPv4 packets.
+    fn payloads(blocks: &[(u32, Vec<u8>)]) -> Vec<Vec<u8>> {
+        blocks
+            .iter()
+            .filter(|(kind, _)| *kind == BLOCK_ENHANCED_PACKET)
+            .map(|(_, body)| {
+                let len = u32::from_le_bytes(body[12..16].try_into().unwrap()) as usize;
+                let packet = &body[20..20 + len];
+                assert_eq!(internet_checksum(&packet[..20]), 0);
+                packet[40..].to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_capture() {
+        let large = Bytes::from(vec![b'a'; MAX_SEGMENT + 10]);
+        let flows = [
+            flow(1, "127.0.0.1:5000", Bytes::from_static(b"hi")),
+            flow(2, "127.0.0.1:5001", large),
+        ];
+        let mut out = vec![];
+        assert_eq!(write_pcapng(&mut out, flows.iter()).unwrap(), 2);
+
+        let blocks = blocks(&out);
+        assert_eq!(blocks[0].0, BLOCK_SECTION_HEADER);
+        assert_eq!(blocks[1].0, BLOCK_INTERFACE);
+        assert_eq!(blocks[1].1[0..2], LINKTYPE_RAW.to_le_bytes());
+
+        let payloads = payloads(&blocks);
+        // Handshake, request and its ack, response and its ack, close
+        assert_eq!(payloads.len(), 3 + 2 + 2 + 3 + 3 + 4 + 2 + 3);
+        let request = String::from_utf8(payloads[3].clone()).unwrap();
+        assert_eq!(
+            request,
+            "POST /upload?x=1 HTTP/1.1\r\nhost: example.com\r\ncontent-length: 2\r\n\r\nhi"
+        );
+        let response = String::from_utf8(payloads[5].clone()).unwrap();
+        assert_eq!(response, "HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\ndone");
+        let second_request: Vec<u8> = [&payloads[13], &payloads[15]].concat();
+        assert!(second_request.ends_with(&[b'a'; MAX_SEGMENT + 10]));
+    }
+
+    #[test]
+    fn sequences_follow_the_payload() {
+        let mut client = Peer {
+            addr: "10.0.0.1:5000".parse().unwrap(),
+            seq: INITIAL_SEQUENCE,
+        };
+        let server = Peer {
+            addr: "10.0.0.2:80".parse().unwrap(),
+            seq: INITIAL_SEQUENCE * 2,
+        };
+        let syn = segment(&mut client, &server, TCP_SYN, &[]);
+        assert_eq!(client.seq, INITIAL_SEQUENCE + 1);
+        let data = segment(&mut client, &server, TCP_PSH | TCP_ACK, b"abc");
+        assert_eq!(client.seq, INITIAL_SEQUENCE + 4);
+        assert_eq!(
+            u32::from_be_bytes(data[28..32].try_into().unwrap()),
+            INITIAL_SEQUENCE * 2
+        );
+
+        for packet in [syn, data] {
+            let mut pseudo = vec![];
+            pseudo.extend_from_slice(&packet[12..20]);
+            pseudo.extend_from_slice(&[0, PROTOCOL_TCP]);
+            pseudo.extend_from_slice(&((packet.len() - 20) as u16).to_be_bytes());
+            pseudo.extend_from_slice(&packet[20..]);
+            assert_eq!(internet_checksum(&pseudo), 0);
+        }
+    }
+
+    #[test]
+    fn ipv6_clients() {
+        let flow = flow(1, "[::1]:5000", Bytes::new());
+        let packets = connection(&flow, flow.request.as_ref().unwrap());
+        let (_, syn) = &packets[0];
+        assert_eq!(syn[0] >> 4, 6);
+        assert_eq!(
+            server_addr(&flow, flow.request.as_ref().unwrap()).port(),
+            80
+        );
+    }
+}
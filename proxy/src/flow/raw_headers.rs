@@ -0,0 +1,201 @@
+//! Header lines of HTTP/1 requests as the client wrote them, with the case
+//! and order that [`http::HeaderMap`] loses.
+//!
+//! Requests are still handled through their `HeaderMap`, the raw lines decide
+//! how its headers are named and ordered when the request is recorded and
+//! sent upstream. Headers scripts add come after the ones the client sent.
+
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use http::{HeaderMap, HeaderName, HeaderValue};
+
+/// One header line of a request head.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawHeader {
+    /// The name in the case the client wrote it.
+    pub name: String,
+    /// The value as received, the line breaks of folded lines included.
+    pub value: Bytes,
+    /// Whether the value was continued on further lines, the obsolete line
+    /// folding of RFC 9112 section 5.2.
+    pub folded: bool,
+}
+
+/// The header lines of a request head in the order they were received.
+#[derive(Debug, Clone, Default)]
+pub struct RawHeaders {
+    pub lines: Vec<RawHeader>,
+    /// Extensions hyper keeps the original case in, handed back to it when
+    /// the request is sent so the upstream sees the same names.
+    case: http::Extensions,
+}
+
+impl PartialEq for RawHeaders {
+    fn eq(&self, other: &Self) -> bool {
+        self.lines == other.lines
+    }
+}
+
+impl Eq for RawHeaders {}
+
+impl RawHeaders {
+    /// Reads the header lines of `head`, a request line followed by headers
+    /// up to the empty line. `None` when it is not a request head.
+    pub fn parse(head: &[u8]) -> Option<Self> {
+        let mut lines = head
+            .split(|b| *b == b'\n')
+            .map(|l| l.strip_suffix(b"\r").unwrap_or(l));
+        lines.next().filter(|line| !line.is_empty())?;
+
+        let mut headers: Vec<RawHeader> = Vec::new();
+        for line in lines.take_while(|line| !line.is_empty()) {
+            if matches!(line.first(), Some(b' ' | b'\t')) {
+                let last = headers.last_mut()?;
+                let mut value = Vec::with_capacity(last.value.len() + line.len() + 2);
+                value.extend_from_slice(&last.value);
+                value.extend_from_slice(b"\r\n");
+                value.extend_from_slice(line);
+                last.value = Bytes::from(value);
+                last.folded = true;
+                continue;
+            }
+            let colon = line.iter().position(|b| *b == b':')?;
+            let name = std::str::from_utf8(&line[..colon]).ok()?;
+            headers.push(RawHeader {
+                name: name.to_string(),
+                value: Bytes::copy_from_slice(line[colon + 1..].trim_ascii()),
+                folded: false,
+            });
+        }
+        Some(Self {
+            lines: headers,
+            case: http::Extensions::new(),
+        })
+    }
+
+    /// Keeps the extensions hyper recorded the header case in.
+    pub(crate) fn with_case(mut self, case: http::Extensions) -> Self {
+        self.case = case;
+        self
+    }
+
+    pub(crate) fn case(&self) -> &http::Extensions {
+        &self.case
+    }
+
+    /// Whether any header was folded over several lines.
+    pub fn folded(&self) -> bool {
+        self.lines.iter().any(|line| line.folded)
+    }
+
+    /// The lines as a map, each fold replaced by a space as RFC 9112 has
+    /// recipients of folded lines do. Lines that are not valid headers are
+    /// left out.
+    pub fn header_map(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for line in &self.lines {
+            let value = line
+                .value
+                .split(|b| *b == b'\n')
+                .map(|part| part.trim_ascii())
+                .collect::<Vec<_>>()
+                .join(&b' ');
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(line.name.as_bytes()),
+                HeaderValue::from_bytes(&value),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        headers
+    }
+
+    /// The values of `headers` in the order the client sent them, named as
+    /// it wrote them. Values of a name repeated on several lines keep their
+    /// places, headers the client did not send follow in map order.
+    pub fn order<'a>(&'a self, headers: &'a HeaderMap) -> Vec<(&'a str, &'a HeaderValue)> {
+        let mut taken: HashMap<HeaderName, usize> = HashMap::new();
+        let mut ordered = Vec::with_capacity(headers.len());
+        for line in &self.lines {
+            let Ok(name) = HeaderName::from_bytes(line.name.as_bytes()) else {
+                continue;
+            };
+            let index = taken.entry(name.clone()).or_default();
+            if let Some(value) = headers.get_all(&name).iter().nth(*index) {
+                ordered.push((line.name.as_str(), value));
+                *index += 1;
+            }
+        }
+        for name in headers.keys() {
+            let skip = taken.get(name).copied().unwrap_or_default();
+            for value in headers.get_all(name).iter().skip(skip) {
+                ordered.push((name.as_str(), value));
+            }
+        }
+        ordered
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::header::{ACCEPT, HOST};
+
+    use super::*;
+
+    const HEAD: &[u8] = b"GET / HTTP/1.1\r\n\
+        hOsT: example.com\r\n\
+        X-Custom: one\r\n\
+        Accept: text/html\r\n\
+        x-custom: two\r\n\r\n";
+
+    #[test]
+    fn parses_lines_as_written() {
+        let raw = RawHeaders::parse(HEAD).unwrap();
+        let names: Vec<_> = raw.lines.iter().map(|line| line.name.as_str()).collect();
+        assert_eq!(names, ["hOsT", "X-Custom", "Accept", "x-custom"]);
+        assert_eq!(raw.lines[0].value, "example.com");
+        assert!(!raw.folded());
+        assert!(RawHeaders::parse(b"").is_none());
+    }
+
+    #[test]
+    fn detects_folded_lines() {
+        let raw =
+            RawHeaders::parse(b"GET / HTTP/1.1\r\nX-Long: a\r\n \tb\r\nHost: c\r\n\r\n").unwrap();
+        assert!(raw.folded());
+        assert!(raw.lines[0].folded);
+        assert_eq!(raw.lines[0].value, "a\r\n \tb");
+        assert_eq!(raw.lines[1].name, "Host");
+        assert_eq!(raw.header_map().get("x-long").unwrap(), "a b");
+        assert!(RawHeaders::parse(b"GET / HTTP/1.1\r\n folded first\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn orders_a_header_map_as_sent() {
+        let raw = RawHeaders::parse(HEAD).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+        headers.append("x-custom", HeaderValue::from_static("one"));
+        headers.append("x-custom", HeaderValue::from_static("two"));
+        headers.insert(HOST, HeaderValue::from_static("example.com"));
+        headers.insert("x-added", HeaderValue::from_static("script"));
+
+        let ordered: Vec<_> = raw
+            .order(&headers)
+            .into_iter()
+            .map(|(name, value)| (name, value.to_str().unwrap()))
+            .collect();
+        assert_eq!(
+            ordered,
+            [
+                ("hOsT", "example.com"),
+                ("X-Custom", "one"),
+                ("Accept", "*/*"),
+                ("x-custom", "two"),
+                ("x-added", "script"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use bytes::Bytes;
+use http::{Request, header::CONTENT_ENCODING};
+use http_body_util::BodyExt;
+use roxy_shared::{
+    body::BytesBody,
+    client::{ClientContext, RClientBuilder},
+    http::HttpError,
+};
+use time::OffsetDateTime;
+use tracing::debug;
+
+use crate::flow::{
+    Flow, FlowConnection, FlowEvent, FlowEventEmitter, FlowStore, InterceptedRequest,
+    InterceptedResponse, error::FlowError, id::FlowId,
+};
+
+#[derive(Debug)]
+pub enum ReplayError {
+    NotFound(FlowId),
+    NoRequest,
+    Build(http::Error),
+    Http(HttpError),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<http::Error> for ReplayError {
+    fn from(value: http::Error) -> Self {
+        ReplayError::Build(value)
+    }
+}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(value: std::io::Error) -> Self {
+        ReplayError::Io(value)
+    }
+}
+
+impl From<HttpError> for ReplayError {
+    fn from(value: HttpError) -> Self {
+        ReplayError::Http(value)
+    }
+}
+
+impl Flow {
+    /// Re-sends the recorded request through `client`, recording the exchange as
+    /// a new flow in `flow_store`.
+    ///
+    /// The request is copied up front so the returned future does not hold on
+    /// to the flow while the request is in flight.
+    pub fn replay(
+        &self,
+        flow_store: &FlowStore,
+        client: RClientBuilder,
+    ) -> impl Future<Output = Result<FlowId, ReplayError>> + use<> {
+        let request = self.request.clone();
+        let client_connection = self.client_connection;
+        let flow_store = flow_store.clone();
+        async move {
+            let request = request.ok_or(ReplayError::NoRequest)?;
+            flow_store
+                .replay_request(client_connection, request, client)
+                .await
+        }
+    }
+}
+
+impl FlowStore {
+    /// Sends `request` as is, no scripts are run, and returns the id of the new flow.
+    ///
+    /// The response body is recorded as it arrives, so a large download is
+    /// moved to disk once it passes the spill threshold rather than held in
+    /// memory whole. Bodies with a content encoding are still read whole to
+    /// be decoded.
+    pub async fn replay_request(
+        &self,
+        client_connection: FlowConnection,
+        request: InterceptedRequest,
+        client: RClientBuilder,
+    ) -> Result<FlowId, ReplayError> {
+        let (id, http_request, client) = self
+            .start_replay(client_connection, request, client)
+            .await?;
+        let response = match client.request_streaming(http_request).await {
+            Ok(response) => response,
+            Err(err) => {
+                self.post_event(id, FlowEvent::Error(FlowError::from(&err)));
+                return Err(err.into());
+            }
+        };
+        let (parts, mut body) = response.into_parts();
+        if parts.headers.contains_key(CONTENT_ENCODING) {
+            // Encoded bodies are decoded whole, as the proxy does
+            return match body.collect().await {
+                Ok(collected) => {
+                    let trailers = collected.trailers().cloned();
+                    let response =
+                        InterceptedResponse::from_http(parts, collected.to_bytes(), trailers);
+                    self.post_event(id, FlowEvent::Response(response));
+                    Ok(id)
+                }
+                Err(err) => {
+                    self.post_event(id, FlowEvent::Error(FlowError::from(&err)));
+                    Err(err.into())
+                }
+            };
+        }
+        // The head is posted first so no chunk arrives before it
+        self.post_event(
+            id,
+            FlowEvent::ResponseHead(InterceptedResponse::from_http(parts, Bytes::new(), None)),
+        );
+        let mut trailers = None;
+        while let Some(frame) = body.frame().await {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(err) => {
+                    self.post_event(id, FlowEvent::Error(FlowError::from(&err)));
+                    return Err(err.into());
+                }
+            };
+            if let Some(data) = frame.data_ref() {
+                self.post_event(id, FlowEvent::ResponseChunk(data.clone()));
+            } else if let Some(frame_trailers) = frame.trailers_ref() {
+                trailers = Some(frame_trailers.clone());
+            }
+        }
+        self.post_event(id, FlowEvent::ResponseEnd(trailers));
+        Ok(id)
+    }
+
+    /// Like [`Self::replay_request`], but hands back the response as well. The
+    /// outer error is for requests that could not be sent, no flow is recorded
+    /// for those.
+    pub(crate) async fn send_replay(
+        &self,
+        client_connection: FlowConnection,
+        request: InterceptedRequest,
+        client: RClientBuilder,
+    ) -> Result<(FlowId, Result<InterceptedResponse, HttpError>), ReplayError> {
+        let (id, http_request, client) = self
+            .start_replay(client_connection, request, client)
+            .await?;
+        match client.request(http_request).await {
+            Ok(res) => {
+                let response = InterceptedResponse::from_http(res.parts, res.body, res.trailers);
+                self.post_event(id, FlowEvent::Response(response.clone()));
+                Ok((id, Ok(response)))
+            }
+            Err(err) => {
+                self.post_event(id, FlowEvent::Error(FlowError::from(&err)));
+                Ok((id, Err(err)))
+            }
+        }
+    }
+
+    /// Records the new flow for `request` and builds the client that sends it,
+    /// its events are posted to the flow.
+    async fn start_replay(
+        &self,
+        client_connection: FlowConnection,
+        mut request: InterceptedRequest,
+        client: RClientBuilder,
+    ) -> Result<(FlowId, Request<BytesBody>, ClientContext), ReplayError> {
+        debug!("Replay {}", request.line_pretty());
+        request.timestamp = OffsetDateTime::now_utc();
+        request.unspill().await?;
+        let http_request = request.request()?;
+        let id = self.new_flow(client_connection, request).await;
+
+        let client = client
+            .with_emitter(Box::new(FlowEventEmitter::new(id, self.clone())))
+            .build();
+        Ok((id, http_request, client))
+    }
+}
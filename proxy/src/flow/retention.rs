@@ -0,0 +1,239 @@
+use std::sync::{
+    Arc, RwLock,
+    atomic::{AtomicU64, Ordering},
+};
+
+use dashmap::{DashMap, DashSet};
+use http::StatusCode;
+use tracing::error;
+
+use crate::flow::{Flow, id::FlowId};
+
+/// How many flows the store keeps in memory, nothing is evicted by default.
+///
+/// Once either bound is exceeded the oldest finished flows are evicted until
+/// both hold again. Starred flows and flows still in flight are never evicted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    pub max_flows: Option<usize>,
+    /// Bytes of bodies and WebSocket messages held in memory, bodies spilled
+    /// to disk do not count.
+    pub max_body_bytes: Option<u64>,
+}
+
+impl Retention {
+    pub fn is_unbounded(&self) -> bool {
+        self.max_flows.is_none() && self.max_body_bytes.is_none()
+    }
+
+    pub(crate) fn exceeded(&self, flows: usize, body_bytes: u64) -> bool {
+        self.max_flows.is_some_and(|max| flows > max)
+            || self.max_body_bytes.is_some_and(|max| body_bytes > max)
+    }
+}
+
+/// The bytes `flow` holds in memory, as counted against
+/// [`Retention::max_body_bytes`].
+pub(crate) fn body_bytes(flow: &Flow) -> u64 {
+    let request = flow.request.as_ref().map_or(0, |r| r.body.len());
+    let response = flow.response.as_ref().map_or(0, |r| r.body.len());
+    let messages: usize = flow.messages.iter().map(|m| m.message.len()).sum();
+    (request + response + messages) as u64
+}
+
+/// Whether nothing more is coming for `flow`, so evicting it loses nothing
+/// that would still be recorded.
+pub(crate) fn is_finished(flow: &Flow) -> bool {
+    let upgraded = flow
+        .response
+        .as_ref()
+        .is_some_and(|r| r.status == StatusCode::SWITCHING_PROTOCOLS);
+    if upgraded {
+        return flow.error.is_some() || flow.messages.iter().any(|m| m.message.is_close());
+    }
+    let streaming =
+        flow.timing.first_response_bytes.is_some() && flow.timing.response_complete.is_none();
+    flow.error.is_some()
+        || flow.timing.client_conn_closed.is_some()
+        || (flow.response.is_some() && !streaming)
+}
+
+/// The active [`Retention`] and what it is measured against, shared by the
+/// clones of a store.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RetentionState {
+    policy: Arc<RwLock<Retention>>,
+    sizes: Arc<DashMap<FlowId, u64>>,
+    total: Arc<AtomicU64>,
+    /// Flows whose last state is in the session file.
+    in_session: Arc<DashSet<FlowId>>,
+    evicted: Arc<AtomicU64>,
+}
+
+impl RetentionState {
+    pub(crate) fn policy(&self) -> Retention {
+        match self.policy.read() {
+            Ok(policy) => *policy,
+            Err(err) => {
+                error!("Retention lock poisoned {err}");
+                Retention::default()
+            }
+        }
+    }
+
+    pub(crate) fn set_policy(&self, retention: Retention) {
+        match self.policy.write() {
+            Ok(mut policy) => *policy = retention,
+            Err(err) => error!("Retention lock poisoned {err}"),
+        }
+    }
+
+    /// Records that flow `id` now holds `bytes` in memory.
+    pub(crate) fn track(&self, id: FlowId, bytes: u64) {
+        // Added before the old size is taken away so the total never wraps
+        self.total.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(old) = self.sizes.insert(id, bytes) {
+            self.total.fetch_sub(old, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn forget(&self, id: FlowId) {
+        if let Some((_, old)) = self.sizes.remove(&id) {
+            self.total.fetch_sub(old, Ordering::Relaxed);
+        }
+        self.in_session.remove(&id);
+    }
+
+    pub(crate) fn size(&self, id: FlowId) -> u64 {
+        self.sizes.get(&id).map_or(0, |size| *size)
+    }
+
+    pub(crate) fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_in_session(&self, id: FlowId, in_session: bool) {
+        if in_session {
+            self.in_session.insert(id);
+        } else {
+            self.in_session.remove(&id);
+        }
+    }
+
+    pub(crate) fn is_in_session(&self, id: FlowId) -> bool {
+        self.in_session.contains(&id)
+    }
+
+    pub(crate) fn add_evicted(&self, count: usize) {
+        self.evicted.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::flow::{
+        FlowConnection, FlowStore, InterceptedRequest, InterceptedResponse, session::read_session,
+    };
+
+    use super::*;
+
+    fn finished_flow(id: i64, body: &'static [u8]) -> Flow {
+        let mut flow = Flow::new(
+            FlowId(id),
+            FlowConnection {
+                addr: ([127, 0, 0, 1], 0).into(),
+            },
+            Some(InterceptedRequest::default()),
+        );
+        flow.response = Some(InterceptedResponse {
+            body: Bytes::from_static(body),
+            ..Default::default()
+        });
+        flow
+    }
+
+    fn ids(ids: &[i64]) -> Vec<FlowId> {
+        ids.iter().copied().map(FlowId).collect()
+    }
+
+    #[test]
+    fn tracks_total_body_bytes() {
+        let state = RetentionState::default();
+        state.track(FlowId(1), 100);
+        state.track(FlowId(2), 50);
+        state.track(FlowId(1), 300);
+        assert_eq!(state.total(), 350);
+        state.forget(FlowId(1));
+        assert_eq!(state.total(), 50);
+        assert_eq!(state.size(FlowId(2)), 50);
+        state.forget(FlowId(3));
+        assert_eq!(state.total(), 50);
+    }
+
+    #[test]
+    fn exceeded_by_either_bound() {
+        assert!(!Retention::default().exceeded(usize::MAX, u64::MAX));
+        let flows = Retention {
+            max_flows: Some(2),
+            ..Default::default()
+        };
+        assert!(!flows.exceeded(2, u64::MAX));
+        assert!(flows.exceeded(3, 0));
+        let bytes = Retention {
+            max_body_bytes: Some(1024),
+            ..Default::default()
+        };
+        assert!(!bytes.exceeded(1000, 1024));
+        assert!(bytes.exceeded(0, 1025));
+    }
+
+    #[tokio::test]
+    async fn evicts_oldest_finished_flows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let store = FlowStore::new_with_session(Some(path.clone()));
+        let mut pinned = finished_flow(1, b"");
+        pinned.starred = true;
+        let mut in_flight = finished_flow(2, b"");
+        in_flight.response = None;
+        store
+            .insert_flows(vec![
+                pinned,
+                in_flight,
+                finished_flow(3, b"abc"),
+                finished_flow(4, b"defg"),
+                finished_flow(5, b"hi"),
+            ])
+            .await;
+        assert_eq!(store.body_bytes(), 9);
+
+        store
+            .set_retention(Retention {
+                max_flows: Some(4),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(*store.ordered_ids.read().await, ids(&[1, 2, 4, 5]));
+
+        store
+            .set_retention(Retention {
+                max_body_bytes: Some(2),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(*store.ordered_ids.read().await, ids(&[1, 2, 5]));
+        assert_eq!(store.body_bytes(), 2);
+        assert_eq!(store.evicted(), 2);
+
+        // Imported flows are not in the session yet, they are kept there
+        let kept: Vec<_> = read_session(&path).unwrap().iter().map(|f| f.id).collect();
+        assert_eq!(kept, ids(&[3, 4]));
+    }
+}
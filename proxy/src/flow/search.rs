@@ -0,0 +1,298 @@
+use std::fmt;
+
+use http::HeaderMap;
+use regex::{Regex, RegexBuilder};
+
+use crate::flow::{Flow, FlowStore, id::FlowId};
+
+/// Characters of context kept on either side of a match in its snippet.
+const SNIPPET_CONTEXT: usize = 32;
+
+/// Matches counted per field, a body repeating the text a thousand times
+/// doesn't outrank a match in the url.
+const MAX_COUNTED_MATCHES: usize = 10;
+
+/// Text searched for in the url, headers and bodies of flows.
+///
+/// A pattern written between slashes, as in `/user-\d+/`, is a regex, anything
+/// else is matched literally. Both are case-insensitive.
+#[derive(Debug, Clone)]
+pub struct FlowSearch {
+    source: String,
+    regex: Regex,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchError(String);
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+/// Where in a flow a match was found, from the most to the least telling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchField {
+    Url,
+    RequestHeader,
+    ResponseHeader,
+    RequestBody,
+    ResponseBody,
+}
+
+impl SearchField {
+    fn weight(&self) -> usize {
+        match self {
+            SearchField::Url => 8,
+            SearchField::RequestHeader | SearchField::ResponseHeader => 3,
+            SearchField::RequestBody | SearchField::ResponseBody => 1,
+        }
+    }
+}
+
+impl fmt::Display for SearchField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SearchField::Url => "url",
+            SearchField::RequestHeader => "request header",
+            SearchField::ResponseHeader => "response header",
+            SearchField::RequestBody => "request body",
+            SearchField::ResponseBody => "response body",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub field: SearchField,
+    /// The first match in the field with some text around it, on one line.
+    pub snippet: String,
+    pub count: usize,
+}
+
+/// A flow with at least one match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit {
+    pub flow_id: FlowId,
+    /// Higher for more matches and for matches in more telling fields.
+    pub score: usize,
+    pub matches: Vec<SearchMatch>,
+}
+
+impl FlowSearch {
+    pub fn parse(source: &str) -> Result<Self, SearchError> {
+        let source = source.trim();
+        if source.is_empty() {
+            return Err(SearchError("nothing to search for".to_string()));
+        }
+        let pattern = match source
+            .strip_prefix('/')
+            .and_then(|s| s.strip_suffix('/'))
+            .filter(|s| !s.is_empty())
+        {
+            Some(pattern) => pattern.to_string(),
+            None => regex::escape(source),
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| SearchError(format!("invalid regex {pattern}: {e}")))?;
+        Ok(Self {
+            source: source.to_string(),
+            regex,
+        })
+    }
+
+    /// The search as it was written.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn find(&self, field: SearchField, text: &str) -> Option<SearchMatch> {
+        let mut found = self.regex.find_iter(text);
+        let first = found.next()?;
+        Some(SearchMatch {
+            field,
+            snippet: snippet(text, first.start(), first.end()),
+            count: 1 + found.take(MAX_COUNTED_MATCHES - 1).count(),
+        })
+    }
+
+    fn find_headers(&self, field: SearchField, headers: &HeaderMap) -> Option<SearchMatch> {
+        let text = headers
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    "{}: {}",
+                    name.as_str(),
+                    String::from_utf8_lossy(value.as_bytes())
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.find(field, &text)
+    }
+}
+
+impl fmt::Display for FlowSearch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// The line around `start..end`, cut to [`SNIPPET_CONTEXT`] characters on
+/// either side of the match.
+fn snippet(text: &str, start: usize, end: usize) -> String {
+    let line_start = text[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line_end = text[end..]
+        .find('\n')
+        .map(|i| end + i)
+        .unwrap_or(text.len());
+
+    let before: Vec<char> = text[line_start..start].chars().collect();
+    let after: Vec<char> = text[end..line_end].chars().collect();
+    let mut snippet = String::new();
+    if before.len() > SNIPPET_CONTEXT {
+        snippet.push('…');
+    }
+    snippet.extend(&before[before.len().saturating_sub(SNIPPET_CONTEXT)..]);
+    snippet.push_str(&text[start..end]);
+    snippet.extend(after.iter().take(SNIPPET_CONTEXT));
+    if after.len() > SNIPPET_CONTEXT {
+        snippet.push('…');
+    }
+    snippet.trim().to_string()
+}
+
+impl Flow {
+    /// Where `search` matches this flow, bodies are searched as text after
+    /// their content encoding was removed.
+    pub fn search(&self, search: &FlowSearch) -> Vec<SearchMatch> {
+        let mut matches = vec![];
+        if let Some(request) = &self.request {
+            matches.extend(search.find(SearchField::Url, &request.line_pretty()));
+            matches.extend(search.find_headers(SearchField::RequestHeader, &request.headers));
+        }
+        if let Some(response) = &self.response {
+            matches.extend(search.find_headers(SearchField::ResponseHeader, &response.headers));
+        }
+        if let Some(body) = self.request.as_ref().and_then(|r| r.read_body().ok()) {
+            matches.extend(search.find(SearchField::RequestBody, &String::from_utf8_lossy(&body)));
+        }
+        if let Some(body) = self.response.as_ref().and_then(|r| r.read_body().ok()) {
+            matches.extend(search.find(SearchField::ResponseBody, &String::from_utf8_lossy(&body)));
+        }
+        matches
+    }
+}
+
+impl FlowStore {
+    /// Every flow `search` matches, best match first and in the order the
+    /// flows were recorded otherwise.
+    pub async fn search(&self, search: &FlowSearch) -> Vec<SearchHit> {
+        let ids = self.ordered_ids.read().await.clone();
+        let mut hits = vec![];
+        for id in ids {
+            let Some(flow) = self.get_flow_by_id(id).await else {
+                continue;
+            };
+            let matches = flow.read().await.search(search);
+            if matches.is_empty() {
+                continue;
+            }
+            hits.push(SearchHit {
+                flow_id: id,
+                score: matches.iter().map(|m| m.field.weight() * m.count).sum(),
+                matches,
+            });
+        }
+        hits.sort_by(|a, b| b.score.cmp(&a.score));
+        hits
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use http::{HeaderValue, Method, StatusCode};
+
+    use crate::flow::test_flow;
+
+    use super::*;
+
+    fn flow(id: i64, path: &str, body: &'static str) -> Flow {
+        let uri = format!("https://api.example.com{path}");
+        let mut flow = test_flow(id, Method::GET, &uri, StatusCode::OK, body.as_bytes());
+        flow.request
+            .as_mut()
+            .unwrap()
+            .headers
+            .insert("x-request-id", HeaderValue::from_static("abc-123"));
+        flow
+    }
+
+    #[test]
+    fn flow_matches() {
+        let flow = flow(1, "/users/42", "{\"name\":\"Ada\",\"friend\":\"ada\"}");
+
+        let matches = flow.search(&FlowSearch::parse("ADA").unwrap());
+        assert_eq!(
+            matches,
+            vec![SearchMatch {
+                field: SearchField::ResponseBody,
+                snippet: "{\"name\":\"Ada\",\"friend\":\"ada\"}".to_string(),
+                count: 2,
+            }]
+        );
+
+        let fields: Vec<SearchField> = flow
+            .search(&FlowSearch::parse("/(users/|abc-)\\d+/").unwrap())
+            .into_iter()
+            .map(|m| m.field)
+            .collect();
+        assert_eq!(fields, vec![SearchField::Url, SearchField::RequestHeader]);
+
+        assert!(
+            flow.search(&FlowSearch::parse("users.42").unwrap())
+                .is_empty()
+        );
+        assert!(FlowSearch::parse("/[/").is_err());
+        assert!(FlowSearch::parse("  ").is_err());
+    }
+
+    #[test]
+    fn long_lines_are_cut() {
+        let text = format!("{}needle{}", "a".repeat(100), "b".repeat(100));
+        let start = text.find("needle").unwrap();
+        assert_eq!(
+            snippet(&text, start, start + 6),
+            format!("…{}needle{}…", "a".repeat(32), "b".repeat(32))
+        );
+        assert_eq!(snippet("one\ntwo needle\nthree", 8, 14), "two needle");
+    }
+
+    #[tokio::test]
+    async fn ranks_hits() {
+        let store = FlowStore::new();
+        for flow in [
+            flow(1, "/a", "token"),
+            flow(2, "/b", "nothing"),
+            flow(3, "/token", ""),
+        ] {
+            let id = flow.id;
+            store.flows.insert(id, Arc::new(flow.into()));
+            store.ordered_ids.write().await.push(id);
+        }
+
+        let hits = store.search(&FlowSearch::parse("token").unwrap()).await;
+        let ids: Vec<FlowId> = hits.iter().map(|h| h.flow_id).collect();
+        assert_eq!(ids, vec![FlowId(3), FlowId(1)]);
+        assert!(hits[0].score > hits[1].score);
+    }
+}
@@ -0,0 +1,840 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, SeekFrom, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use roxy_shared::{
+    alpn::AlpnProtocol, content::get_content_encoding, uri::RUri, version::HttpVersion,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_tungstenite::tungstenite::{
+    Message,
+    protocol::{CloseFrame, frame::coding::CloseCode},
+};
+use tracing::{error, warn};
+
+use crate::flow::{
+    Flow, FlowCerts, FlowConnection, InterceptedRequest, InterceptedResponse, Timing, WsDirection,
+    WsMessage,
+    error::{FlowError, FlowErrorKind},
+    id::FlowId,
+    tunnel::{RawTunnel, TunnelChunk},
+    wire::FlowWire,
+};
+
+/// Bumped whenever the on-disk record layout changes.
+const SESSION_VERSION: u32 = 2;
+/// Oldest layout still read, version 1 wrote bytes as arrays of numbers.
+const MIN_SESSION_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum SessionError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Version(u32),
+    Invalid(String),
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl From<std::io::Error> for SessionError {
+    fn from(value: std::io::Error) -> Self {
+        SessionError::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for SessionError {
+    fn from(value: serde_json::Error) -> Self {
+        SessionError::Json(value)
+    }
+}
+
+/// One line of a session file. Certificates captured during the TLS handshake
+/// are not persisted, everything else needed to render a flow is.
+#[derive(Debug, Serialize, Deserialize)]
+struct FlowRecord {
+    version: u32,
+    id: FlowId,
+    client_addr: SocketAddr,
+    server_addr: Option<SocketAddr>,
+    timing: TimingRecord,
+    request: Option<RequestRecord>,
+    response: Option<ResponseRecord>,
+    error: Option<String>,
+    #[serde(default)]
+    error_kind: Option<FlowErrorKind>,
+    messages: Vec<WsMessageRecord>,
+    #[serde(default)]
+    passthrough: bool,
+    #[serde(default)]
+    tunnel: Option<TunnelRecord>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    starred: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RequestRecord {
+    timestamp: i128,
+    uri: String,
+    alpn: Vec<u8>,
+    method: String,
+    version: String,
+    headers: Vec<(String, Vec<u8>)>,
+    #[serde(with = "base64_bytes")]
+    body: Vec<u8>,
+    trailers: Option<Vec<(String, Vec<u8>)>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ResponseRecord {
+    timestamp: i128,
+    status: u16,
+    version: String,
+    headers: Vec<(String, Vec<u8>)>,
+    #[serde(with = "base64_bytes")]
+    body: Vec<u8>,
+    trailers: Option<Vec<(String, Vec<u8>)>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum WsKind {
+    Text,
+    Binary,
+    Ping,
+    Pong,
+    Close,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WsMessageRecord {
+    timestamp: i128,
+    client: bool,
+    kind: WsKind,
+    code: Option<u16>,
+    #[serde(with = "base64_bytes")]
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TunnelRecord {
+    tls: bool,
+    chunks: Vec<TunnelChunkRecord>,
+    client_bytes: usize,
+    server_bytes: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TunnelChunkRecord {
+    timestamp: i128,
+    client: bool,
+    #[serde(with = "base64_bytes")]
+    data: Vec<u8>,
+}
+
+/// Bodies and message payloads as base64 strings. Arrays of numbers, as
+/// version 1 records hold them, are read as well.
+mod base64_bytes {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Encoded {
+        Base64(String),
+        Numbers(Vec<u8>),
+    }
+
+    pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        match Encoded::deserialize(deserializer)? {
+            Encoded::Base64(encoded) => STANDARD.decode(encoded).map_err(D::Error::custom),
+            Encoded::Numbers(bytes) => Ok(bytes),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TimingRecord {
+    client_conn_established: Option<i128>,
+    client_conn_tls_handshake: Option<i128>,
+    server_conn_initiated: Option<i128>,
+    #[serde(default)]
+    server_conn_dns_resolved: Option<i128>,
+    #[serde(default)]
+    server_conn_dns_lookup_us: Option<u64>,
+    server_conn_tcp_handshake: Option<i128>,
+    #[serde(default)]
+    server_conn_reused: bool,
+    server_conn_tls_initiated: Option<i128>,
+    server_conn_tls_handshake: Option<i128>,
+    server_conn_http_handshake: Option<i128>,
+    first_request_bytes: Option<i128>,
+    request_complete: Option<i128>,
+    first_response_bytes: Option<i128>,
+    response_complete: Option<i128>,
+    client_conn_closed: Option<i128>,
+    server_conn_closed: Option<i128>,
+}
+
+/// Writes `flows` to `path`, one JSON record per line.
+pub(crate) fn write_session<'a>(
+    path: &Path,
+    flows: impl Iterator<Item = &'a Flow>,
+) -> Result<(), SessionError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for flow in flows {
+        serde_json::to_writer(&mut writer, &FlowRecord::from(flow))?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Serializes a single flow into a newline terminated session record.
+pub(crate) fn encode_flow(flow: &Flow) -> Result<Vec<u8>, SessionError> {
+    let mut line = serde_json::to_vec(&FlowRecord::from(flow))?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Appends an encoded record to the end of the session file at `path`. A last
+/// record cut short by a crash is ended first, so `line` starts its own line.
+pub(crate) async fn append_session(path: &Path, line: &[u8]) -> Result<(), SessionError> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+        .await?;
+    if file.metadata().await?.len() > 0 {
+        let mut last = [0];
+        file.seek(SeekFrom::End(-1)).await?;
+        file.read_exact(&mut last).await?;
+        if last != *b"\n" {
+            file.write_all(b"\n").await?;
+        }
+    }
+    file.write_all(line).await?;
+    Ok(())
+}
+
+/// Runs `f`, which does blocking file I/O, off the async runtime.
+pub(crate) async fn blocking<T, E, F>(f: F) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: From<std::io::Error> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| E::from(std::io::Error::other(err)))?
+}
+
+/// Drops the records of the flows in `ids` from the session file at `path`.
+/// Other records are copied as they are, without decoding their flows, and
+/// records that can not be read are kept.
+pub(crate) fn remove_from_session(path: &Path, ids: &HashSet<FlowId>) -> Result<(), SessionError> {
+    #[derive(Deserialize)]
+    struct RecordId {
+        id: FlowId,
+    }
+
+    let mut name = path.as_os_str().to_owned();
+    name.push(".tmp");
+    let tmp = PathBuf::from(name);
+    let reader = BufReader::new(File::open(path)?);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<RecordId>(&line) {
+            Ok(record) if ids.contains(&record.id) => continue,
+            Ok(_) => {}
+            Err(err) => warn!("Keeping unreadable session record {err}"),
+        }
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    drop(writer);
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Reads every flow from the session file at `path`.
+///
+/// Records are appended as flows complete, so the same id may appear more than
+/// once; the last record wins. Records that can not be read, e.g. the last
+/// one when Roxy stopped while writing it, are skipped with a warning.
+pub fn read_session(path: &Path) -> Result<Vec<Flow>, SessionError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut flows: Vec<Flow> = Vec::new();
+    let mut index: HashMap<FlowId, usize> = HashMap::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: FlowRecord = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(err) => {
+                warn!("Skipping session record {} {err}", number + 1);
+                continue;
+            }
+        };
+        if !(MIN_SESSION_VERSION..=SESSION_VERSION).contains(&record.version) {
+            return Err(SessionError::Version(record.version));
+        }
+        let flow = match Flow::try_from(record) {
+            Ok(flow) => flow,
+            Err(err) => {
+                warn!("Skipping session record {} {err}", number + 1);
+                continue;
+            }
+        };
+        match index.get(&flow.id) {
+            Some(&i) => flows[i] = flow,
+            None => {
+                index.insert(flow.id, flows.len());
+                flows.push(flow);
+            }
+        }
+    }
+    Ok(flows)
+}
+
+fn ts(t: OffsetDateTime) -> i128 {
+    t.unix_timestamp_nanos()
+}
+
+fn from_ts(nanos: i128) -> Result<OffsetDateTime, SessionError> {
+    OffsetDateTime::from_unix_timestamp_nanos(nanos)
+        .map_err(|e| SessionError::Invalid(format!("timestamp {e}")))
+}
+
+fn from_ts_opt(nanos: Option<i128>) -> Result<Option<OffsetDateTime>, SessionError> {
+    nanos.map(from_ts).transpose()
+}
+
+fn headers_to_record(headers: &HeaderMap) -> Vec<(String, Vec<u8>)> {
+    headers
+        .iter()
+        .map(|(k, v)| (k.as_str().to_string(), v.as_bytes().to_vec()))
+        .collect()
+}
+
+fn headers_from_record(headers: Vec<(String, Vec<u8>)>) -> Result<HeaderMap, SessionError> {
+    let mut map = HeaderMap::with_capacity(headers.len());
+    for (k, v) in headers {
+        let name = HeaderName::from_str(&k)
+            .map_err(|e| SessionError::Invalid(format!("header name {k} {e}")))?;
+        let value = HeaderValue::from_bytes(&v)
+            .map_err(|e| SessionError::Invalid(format!("header value {k} {e}")))?;
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+/// Bodies spilled to disk are read back in, a missing file leaves the body empty.
+fn stored_body(body: std::io::Result<Bytes>) -> Vec<u8> {
+    match body {
+        Ok(body) => body.to_vec(),
+        Err(err) => {
+            error!("Failed to read spilled body {err}");
+            vec![]
+        }
+    }
+}
+
+impl From<&Flow> for FlowRecord {
+    fn from(flow: &Flow) -> Self {
+        FlowRecord {
+            version: SESSION_VERSION,
+            id: flow.id,
+            client_addr: flow.client_connection.addr,
+            server_addr: flow.server_connection.map(|c| c.addr),
+            timing: TimingRecord::from(&flow.timing),
+            request: flow.request.as_ref().map(RequestRecord::from),
+            response: flow.response.as_ref().map(ResponseRecord::from),
+            error: flow.error.as_ref().map(|e| e.message.clone()),
+            error_kind: flow.error.as_ref().map(|e| e.kind),
+            messages: flow.messages.iter().map(WsMessageRecord::from).collect(),
+            passthrough: flow.passthrough,
+            tunnel: flow.tunnel.as_ref().map(TunnelRecord::from),
+            tags: flow.tags.clone(),
+            starred: flow.starred,
+        }
+    }
+}
+
+impl TryFrom<FlowRecord> for Flow {
+    type Error = SessionError;
+
+    fn try_from(record: FlowRecord) -> Result<Self, Self::Error> {
+        Ok(Flow {
+            id: record.id,
+            timing: Timing::try_from(record.timing)?,
+            client_connection: FlowConnection {
+                addr: record.client_addr,
+            },
+            request: record
+                .request
+                .map(InterceptedRequest::try_from)
+                .transpose()?,
+            server_connection: record.server_addr.map(|addr| FlowConnection { addr }),
+            response: record
+                .response
+                .map(InterceptedResponse::try_from)
+                .transpose()?,
+            error: record.error.map(|message| {
+                FlowError::new(record.error_kind.unwrap_or(FlowErrorKind::Other), message)
+            }),
+            certs: FlowCerts::default(),
+            messages: record
+                .messages
+                .into_iter()
+                .map(WsMessage::try_from)
+                .collect::<Result<Vec<_>, _>>()?,
+            passthrough: record.passthrough,
+            tunnel: record.tunnel.map(RawTunnel::try_from).transpose()?,
+            tags: record.tags,
+            redirects: vec![],
+            interim_responses: vec![],
+            version_choice: None,
+            starred: record.starred,
+            script_log: vec![],
+            wire: FlowWire::default(),
+        })
+    }
+}
+
+impl From<&InterceptedRequest> for RequestRecord {
+    fn from(req: &InterceptedRequest) -> Self {
+        RequestRecord {
+            timestamp: ts(req.timestamp),
+            uri: req.uri.to_string(),
+            alpn: req.alpn.to_bytes().to_vec(),
+            method: req.method.to_string(),
+            version: req.version.to_string(),
+            headers: headers_to_record(&req.headers),
+            body: stored_body(req.read_body()),
+            trailers: req.trailers.as_ref().map(headers_to_record),
+        }
+    }
+}
+
+impl TryFrom<RequestRecord> for InterceptedRequest {
+    type Error = SessionError;
+
+    fn try_from(record: RequestRecord) -> Result<Self, Self::Error> {
+        let headers = headers_from_record(record.headers)?;
+        Ok(InterceptedRequest {
+            timestamp: from_ts(record.timestamp)?,
+            uri: RUri::from_str(&record.uri)
+                .map_err(|e| SessionError::Invalid(format!("uri {e}")))?,
+            encoding: get_content_encoding(&headers),
+            alpn: if record.alpn.is_empty() {
+                AlpnProtocol::None
+            } else {
+                AlpnProtocol::from_bytes(&record.alpn)
+            },
+            method: Method::from_str(&record.method)
+                .map_err(|e| SessionError::Invalid(format!("method {e}")))?,
+            version: HttpVersion::from_str(&record.version)
+                .map_err(|_| SessionError::Invalid(format!("version {}", record.version)))?,
+            headers,
+            body: Bytes::from(record.body),
+            body_file: None,
+            trailers: record.trailers.map(headers_from_record).transpose()?,
+            tls: None,
+            tags: vec![],
+            raw_headers: None,
+        })
+    }
+}
+
+impl From<&InterceptedResponse> for ResponseRecord {
+    fn from(res: &InterceptedResponse) -> Self {
+        ResponseRecord {
+            timestamp: ts(res.timestamp),
+            status: res.status.as_u16(),
+            version: res.version.to_string(),
+            headers: headers_to_record(&res.headers),
+            body: stored_body(res.read_body()),
+            trailers: res.trailers.as_ref().map(headers_to_record),
+        }
+    }
+}
+
+impl TryFrom<ResponseRecord> for InterceptedResponse {
+    type Error = SessionError;
+
+    fn try_from(record: ResponseRecord) -> Result<Self, Self::Error> {
+        let headers = headers_from_record(record.headers)?;
+        Ok(InterceptedResponse {
+            timestamp: from_ts(record.timestamp)?,
+            status: StatusCode::from_u16(record.status)
+                .map_err(|e| SessionError::Invalid(format!("status {e}")))?,
+            version: HttpVersion::from_str(&record.version)
+                .map_err(|_| SessionError::Invalid(format!("version {}", record.version)))?,
+            encoding: get_content_encoding(&headers),
+            headers,
+            body: Bytes::from(record.body),
+            body_file: None,
+            trailers: record.trailers.map(headers_from_record).transpose()?,
+            tls: None,
+            tags: vec![],
+        })
+    }
+}
+
+impl From<&WsMessage> for WsMessageRecord {
+    fn from(msg: &WsMessage) -> Self {
+        let (kind, code, data) = match &msg.message {
+            Message::Text(t) => (WsKind::Text, None, t.as_bytes().to_vec()),
+            Message::Binary(b) => (WsKind::Binary, None, b.to_vec()),
+            Message::Ping(b) => (WsKind::Ping, None, b.to_vec()),
+            Message::Pong(b) => (WsKind::Pong, None, b.to_vec()),
+            Message::Close(frame) => match frame {
+                Some(frame) => (
+                    WsKind::Close,
+                    Some(u16::from(frame.code)),
+                    frame.reason.as_bytes().to_vec(),
+                ),
+                None => (WsKind::Close, None, vec![]),
+            },
+            Message::Frame(f) => (WsKind::Binary, None, f.payload().to_vec()),
+        };
+        WsMessageRecord {
+            timestamp: ts(msg.timestamp),
+            client: msg.direction == WsDirection::Client,
+            kind,
+            code,
+            data,
+        }
+    }
+}
+
+impl From<&RawTunnel> for TunnelRecord {
+    fn from(tunnel: &RawTunnel) -> Self {
+        TunnelRecord {
+            tls: tunnel.tls,
+            chunks: tunnel
+                .chunks
+                .iter()
+                .map(|chunk| TunnelChunkRecord {
+                    timestamp: ts(chunk.timestamp),
+                    client: chunk.direction == WsDirection::Client,
+                    data: chunk.data.to_vec(),
+                })
+                .collect(),
+            client_bytes: tunnel.client_bytes,
+            server_bytes: tunnel.server_bytes,
+        }
+    }
+}
+
+impl TryFrom<TunnelRecord> for RawTunnel {
+    type Error = SessionError;
+
+    fn try_from(record: TunnelRecord) -> Result<Self, Self::Error> {
+        let chunks = record
+            .chunks
+            .into_iter()
+            .map(|chunk| {
+                Ok(TunnelChunk {
+                    timestamp: from_ts(chunk.timestamp)?,
+                    direction: if chunk.client {
+                        WsDirection::Client
+                    } else {
+                        WsDirection::Server
+                    },
+                    data: Bytes::from(chunk.data),
+                })
+            })
+            .collect::<Result<Vec<_>, SessionError>>()?;
+        Ok(RawTunnel {
+            tls: record.tls,
+            chunks,
+            client_bytes: record.client_bytes,
+            server_bytes: record.server_bytes,
+        })
+    }
+}
+
+impl TryFrom<WsMessageRecord> for WsMessage {
+    type Error = SessionError;
+
+    fn try_from(record: WsMessageRecord) -> Result<Self, Self::Error> {
+        let text = |data: Vec<u8>| {
+            String::from_utf8(data).map_err(|e| SessionError::Invalid(format!("ws text {e}")))
+        };
+        let message = match record.kind {
+            WsKind::Text => Message::text(text(record.data)?),
+            WsKind::Binary => Message::binary(record.data),
+            WsKind::Ping => Message::Ping(Bytes::from(record.data)),
+            WsKind::Pong => Message::Pong(Bytes::from(record.data)),
+            WsKind::Close => Message::Close(match record.code {
+                Some(code) => Some(CloseFrame {
+                    code: CloseCode::from(code),
+                    reason: text(record.data)?.into(),
+                }),
+                None => None,
+            }),
+        };
+        Ok(WsMessage {
+            message,
+            direction: if record.client {
+                WsDirection::Client
+            } else {
+                WsDirection::Server
+            },
+            timestamp: from_ts(record.timestamp)?,
+        })
+    }
+}
+
+impl From<&Timing> for TimingRecord {
+    fn from(t: &Timing) -> Self {
+        TimingRecord {
+            client_conn_established: t.client_conn_established.map(ts),
+            client_conn_tls_handshake: t.client_conn_tls_handshake.map(ts),
+            server_conn_initiated: t.server_conn_initiated.map(ts),
+            server_conn_dns_resolved: t.server_conn_dns_resolved.map(ts),
+            server_conn_dns_lookup_us: t
+                .server_conn_dns_lookup
+                .map(|d| u64::try_from(d.as_micros()).unwrap_or(u64::MAX)),
+            server_conn_tcp_handshake: t.server_conn_tcp_handshake.map(ts),
+            server_conn_reused: t.server_conn_reused,
+            server_conn_tls_initiated: t.server_conn_tls_initiated.map(ts),
+            server_conn_tls_handshake: t.server_conn_tls_handshake.map(ts),
+            server_conn_http_handshake: t.server_conn_http_handshake.map(ts),
+            first_request_bytes: t.first_request_bytes.map(ts),
+            request_complete: t.request_complete.map(ts),
+            first_response_bytes: t.first_response_bytes.map(ts),
+            response_complete: t.response_complete.map(ts),
+            client_conn_closed: t.client_conn_closed.map(ts),
+            server_conn_closed: t.server_conn_closed.map(ts),
+        }
+    }
+}
+
+impl TryFrom<TimingRecord> for Timing {
+    type Error = SessionError;
+
+    fn try_from(t: TimingRecord) -> Result<Self, Self::Error> {
+        Ok(Timing {
+            client_conn_established: from_ts_opt(t.client_conn_established)?,
+            client_conn_tls_handshake: from_ts_opt(t.client_conn_tls_handshake)?,
+            server_conn_initiated: from_ts_opt(t.server_conn_initiated)?,
+            server_conn_dns_resolved: from_ts_opt(t.server_conn_dns_resolved)?,
+            server_conn_dns_lookup: t.server_conn_dns_lookup_us.map(Duration::from_micros),
+            server_conn_tcp_handshake: from_ts_opt(t.server_conn_tcp_handshake)?,
+            server_conn_reused: t.server_conn_reused,
+            server_conn_tls_initiated: from_ts_opt(t.server_conn_tls_initiated)?,
+            server_conn_tls_handshake: from_ts_opt(t.server_conn_tls_handshake)?,
+            server_conn_http_handshake: from_ts_opt(t.server_conn_http_handshake)?,
+            first_request_bytes: from_ts_opt(t.first_request_bytes)?,
+            request_complete: from_ts_opt(t.request_complete)?,
+            first_response_bytes: from_ts_opt(t.first_response_bytes)?,
+            response_complete: from_ts_opt(t.response_complete)?,
+            client_conn_closed: from_ts_opt(t.client_conn_closed)?,
+            server_conn_closed: from_ts_opt(t.server_conn_closed)?,
+        })
+    }
+}
+
+#[allow(clippy::unwrap_used, clippy::expect_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_flow(id: i64) -> Flow {
+        let mut headers = HeaderMap::new();
+        headers.append("x-test", "a".parse().unwrap());
+        headers.append("x-test", "b".parse().unwrap());
+        let request = InterceptedRequest {
+            uri: "https://example.com/path?q=1".parse().unwrap(),
+            alpn: AlpnProtocol::Http2,
+            method: Method::POST,
+            headers: headers.clone(),
+            body: Bytes::from_static(b"hello"),
+            ..Default::default()
+        };
+        let response = InterceptedResponse {
+            status: StatusCode::CREATED,
+            headers,
+            body: Bytes::from_static(&[0, 1, 2, 255]),
+            ..Default::default()
+        };
+        let mut flow = Flow::new(
+            FlowId(id),
+            FlowConnection {
+                addr: "127.0.0.1:1234".parse().unwrap(),
+            },
+            Some(request),
+        );
+        flow.response = Some(response);
+        flow.messages.push(WsMessage::client(Message::text("hi")));
+        flow.messages.push(WsMessage::server(Message::Close(None)));
+        flow.tags = vec!["auth".to_string()];
+        flow.starred = id % 2 == 1;
+        if id % 2 == 0 {
+            let mut tunnel = RawTunnel::new(true);
+            tunnel.record(TunnelChunk::server(Bytes::from_static(b"220 ready\r\n")));
+            flow.tunnel = Some(tunnel);
+        }
+        flow.timing.server_conn_dns_lookup = Some(Duration::from_millis(12));
+        flow.timing.server_conn_reused = id % 2 == 0;
+        flow
+    }
+
+    #[test]
+    fn session_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let flows = [sample_flow(1), sample_flow(2)];
+        write_session(&path, flows.iter()).unwrap();
+
+        let loaded = read_session(&path).unwrap();
+        assert_eq!(loaded.len(), 2);
+        for (a, b) in flows.iter().zip(loaded.iter()) {
+            assert_eq!(a.id, b.id);
+            assert_eq!(a.request, b.request);
+            assert_eq!(a.response, b.response);
+            assert_eq!(a.messages.len(), b.messages.len());
+            assert_eq!(a.messages[0].message, b.messages[0].message);
+            assert_eq!(a.messages[1].direction, b.messages[1].direction);
+            assert_eq!(a.tags, b.tags);
+            assert_eq!(a.starred, b.starred);
+            assert_eq!(
+                a.timing.server_conn_dns_lookup,
+                b.timing.server_conn_dns_lookup
+            );
+            assert_eq!(a.timing.server_conn_reused, b.timing.server_conn_reused);
+            assert_eq!(a.tunnel, b.tunnel);
+        }
+    }
+
+    #[tokio::test]
+    async fn session_append_last_record_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let mut flow = sample_flow(7);
+        append_session(&path, &encode_flow(&flow).unwrap())
+            .await
+            .unwrap();
+        flow.error = Some(FlowError::new(FlowErrorKind::Script, "boom"));
+        append_session(&path, &encode_flow(&flow).unwrap())
+            .await
+            .unwrap();
+
+        let loaded = read_session(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].error, flow.error);
+    }
+
+    #[tokio::test]
+    async fn session_skips_truncated_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        write_session(&path, [sample_flow(1), sample_flow(2)].iter()).unwrap();
+        let line = encode_flow(&sample_flow(3)).unwrap();
+        append_session(&path, &line[..line.len() / 2])
+            .await
+            .unwrap();
+
+        let ids: Vec<_> = read_session(&path).unwrap().iter().map(|f| f.id).collect();
+        assert_eq!(ids, vec![FlowId(1), FlowId(2)]);
+
+        append_session(&path, &encode_flow(&sample_flow(4)).unwrap())
+            .await
+            .unwrap();
+        let ids: Vec<_> = read_session(&path).unwrap().iter().map(|f| f.id).collect();
+        assert_eq!(ids, vec![FlowId(1), FlowId(2), FlowId(4)]);
+
+        remove_from_session(&path, &HashSet::from([FlowId(1)])).unwrap();
+        let ids: Vec<_> = read_session(&path).unwrap().iter().map(|f| f.id).collect();
+        assert_eq!(ids, vec![FlowId(2), FlowId(4)]);
+    }
+
+    #[test]
+    fn session_bodies_are_base64() {
+        let flow = sample_flow(1);
+        let line = encode_flow(&flow).unwrap();
+        let mut record: serde_json::Value = serde_json::from_slice(&line).unwrap();
+        assert_eq!(record["request"]["body"], "aGVsbG8=");
+        assert_eq!(record["response"]["body"], "AAEC/w==");
+
+        // Version 1 records hold bytes as arrays of numbers
+        record["version"] = 1.into();
+        record["request"]["body"] = serde_json::json!([104, 105]);
+        let record: FlowRecord = serde_json::from_value(record).unwrap();
+        let loaded = Flow::try_from(record).unwrap();
+        assert_eq!(loaded.request.unwrap().body, Bytes::from_static(b"hi"));
+        assert_eq!(loaded.response, flow.response);
+    }
+
+    #[tokio::test]
+    async fn store_stars_saves_and_removes_flows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let store = crate::flow::FlowStore::new_with_session(Some(path.clone()));
+        let mut flows = vec![sample_flow(1), sample_flow(2), sample_flow(3)];
+        for flow in &mut flows {
+            flow.starred = false;
+        }
+        store.insert_flows(flows).await;
+        store.save_session(&path).await.unwrap();
+
+        store.set_starred(&[FlowId(2)], true).await;
+        let loaded = read_session(&path).unwrap();
+        let starred: Vec<_> = loaded.iter().filter(|f| f.starred).map(|f| f.id).collect();
+        assert_eq!(starred, vec![FlowId(2)]);
+
+        let export = dir.path().join("export.jsonl");
+        assert_eq!(
+            store
+                .save_flows(&export, &[FlowId(3), FlowId(1), FlowId(42)])
+                .await
+                .unwrap(),
+            2
+        );
+        let exported: Vec<_> = read_session(&export)
+            .unwrap()
+            .iter()
+            .map(|f| f.id)
+            .collect();
+        assert_eq!(exported, vec![FlowId(1), FlowId(3)]);
+
+        assert_eq!(store.remove_flows(&[FlowId(1), FlowId(3)]).await, 2);
+        assert_eq!(*store.ordered_ids.read().await, vec![FlowId(2)]);
+        let loaded = read_session(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert!(loaded[0].starred);
+    }
+}
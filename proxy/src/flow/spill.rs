@@ -0,0 +1,220 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use bytes::Bytes;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, warn};
+
+/// Bodies larger than this are written to disk by default, 16 MiB.
+pub const DEFAULT_SPILL_THRESHOLD: usize = 16 * 1024 * 1024;
+
+static NEXT_FILE: AtomicU64 = AtomicU64::new(0);
+
+/// Removes the file once the last [`BodyFile`] pointing at it is dropped.
+#[derive(Debug)]
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.0) {
+            warn!("Failed to remove body file {} {err}", self.0.display());
+        }
+    }
+}
+
+/// A body kept on disk instead of in memory.
+///
+/// Clones share the file, a streamed body grows by appending, so each clone
+/// only reads the bytes that were written when it was taken.
+#[derive(Debug, Clone)]
+pub struct BodyFile {
+    file: Arc<TempFile>,
+    len: usize,
+}
+
+impl PartialEq for BodyFile {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.file, &other.file) && self.len == other.len
+    }
+}
+
+impl Eq for BodyFile {}
+
+impl BodyFile {
+    pub fn path(&self) -> &Path {
+        &self.file.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn read(&self) -> io::Result<Bytes> {
+        let mut bytes = std::fs::read(self.path())?;
+        bytes.truncate(self.len);
+        Ok(bytes.into())
+    }
+
+    pub async fn load(&self) -> io::Result<Bytes> {
+        let mut bytes = tokio::fs::read(self.path()).await?;
+        bytes.truncate(self.len);
+        Ok(bytes.into())
+    }
+
+    /// Appends `chunk` to the file, returning a handle that covers it.
+    pub(crate) async fn append(&self, chunk: &[u8]) -> io::Result<BodyFile> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(self.path())
+            .await?;
+        file.write_all(chunk).await?;
+        Ok(BodyFile {
+            file: self.file.clone(),
+            len: self.len + chunk.len(),
+        })
+    }
+}
+
+/// Where and above which size flow bodies are moved out of memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BodySpill {
+    dir: PathBuf,
+    threshold: usize,
+}
+
+impl Default for BodySpill {
+    fn default() -> Self {
+        Self::new(
+            std::env::temp_dir().join("roxy-bodies"),
+            DEFAULT_SPILL_THRESHOLD,
+        )
+    }
+}
+
+impl BodySpill {
+    pub fn new(dir: impl Into<PathBuf>, threshold: usize) -> Self {
+        Self {
+            dir: dir.into(),
+            threshold,
+        }
+    }
+
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn exceeds(&self, len: usize) -> bool {
+        len > self.threshold
+    }
+
+    /// Writes `body` to a new file in the spill directory.
+    pub(crate) async fn write(&self, body: &[u8]) -> io::Result<BodyFile> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(format!(
+            "{}-{}.body",
+            std::process::id(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::write(&path, body).await?;
+        debug!("Spilled {} bytes to {}", body.len(), path.display());
+        Ok(BodyFile {
+            file: Arc::new(TempFile(path)),
+            len: body.len(),
+        })
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::flow::{
+        FlowConnection, FlowEvent, FlowStore, InterceptedRequest, InterceptedResponse,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn write_append_and_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let spill = BodySpill::new(dir.path(), 4);
+        assert!(!spill.exceeds(4));
+        assert!(spill.exceeds(5));
+
+        let file = spill.write(b"hello").await.unwrap();
+        assert_eq!(file.len(), 5);
+        assert_eq!(file.load().await.unwrap(), "hello");
+
+        let grown = file.append(b" world").await.unwrap();
+        assert_eq!(grown.read().unwrap(), "hello world");
+        assert_eq!(file.read().unwrap(), "hello");
+        assert_ne!(file, grown);
+
+        let path = grown.path().to_path_buf();
+        drop(file);
+        assert!(path.exists());
+        drop(grown);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn flow_store_spills_large_bodies() {
+        let dir = tempfile::tempdir().unwrap();
+        let flow_store = FlowStore::new();
+        flow_store.set_body_spill(Some(BodySpill::new(dir.path(), 4)));
+        let mut rx = flow_store.subscribe();
+
+        let request = InterceptedRequest {
+            body: Bytes::from("hello world"),
+            ..Default::default()
+        };
+        let client = FlowConnection {
+            addr: ([127, 0, 0, 1], 0).into(),
+        };
+        let id = flow_store.new_flow(client, request).await;
+        flow_store.post_event(id, FlowEvent::ResponseHead(InterceptedResponse::default()));
+        flow_store.post_event(id, FlowEvent::ResponseChunk(Bytes::from("abc")));
+        flow_store.post_event(id, FlowEvent::ResponseChunk(Bytes::from("def")));
+        flow_store.post_event(id, FlowEvent::ResponseEnd(None));
+
+        let flow = flow_store.get_flow_by_id(id).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while flow.read().await.timing.response_complete.is_none() {
+                rx.changed().await.unwrap();
+            }
+        })
+        .await
+        .unwrap();
+
+        let flow = flow.read().await;
+        let request = flow.request.as_ref().unwrap();
+        assert!(request.body.is_empty());
+        assert_eq!(request.body_len(), 11);
+        assert_eq!(request.load_body().await.unwrap(), "hello world");
+
+        let response = flow.response.as_ref().unwrap();
+        assert!(response.body.is_empty());
+        assert!(response.body_file.is_some());
+        assert_eq!(response.read_body().unwrap(), "abcdef");
+    }
+}
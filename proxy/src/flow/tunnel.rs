@@ -0,0 +1,137 @@
+//! Byte streams of tunnels whose protocol Roxy does not speak, such as SMTP
+//! or a custom TCP protocol, recorded in the order each side sent them.
+//!
+//! The bytes are kept as read, after TLS is terminated when the client spoke
+//! it. Each direction keeps up to [`TUNNEL_CAPTURE_LIMIT`] bytes, the rest is
+//! forwarded and counted only.
+
+use bytes::Bytes;
+use time::OffsetDateTime;
+
+use crate::flow::WsDirection;
+
+/// Bytes kept of each direction of a tunnel.
+pub const TUNNEL_CAPTURE_LIMIT: usize = 1024 * 1024;
+
+/// Bytes one side of a tunnel sent in a single read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TunnelChunk {
+    pub timestamp: OffsetDateTime,
+    pub direction: WsDirection,
+    pub data: Bytes,
+}
+
+impl TunnelChunk {
+    pub fn client(data: Bytes) -> Self {
+        Self {
+            timestamp: OffsetDateTime::now_utc(),
+            direction: WsDirection::Client,
+            data,
+        }
+    }
+
+    pub fn server(data: Bytes) -> Self {
+        Self {
+            timestamp: OffsetDateTime::now_utc(),
+            direction: WsDirection::Server,
+            data,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RawTunnel {
+    /// Whether the client spoke TLS, the chunks are what it sent inside it.
+    pub tls: bool,
+    pub chunks: Vec<TunnelChunk>,
+    /// Bytes the client sent, including ones past the capture limit.
+    pub client_bytes: usize,
+    /// Bytes the server sent, including ones past the capture limit.
+    pub server_bytes: usize,
+}
+
+impl RawTunnel {
+    pub fn new(tls: bool) -> Self {
+        Self {
+            tls,
+            ..Default::default()
+        }
+    }
+
+    /// Keeps `chunk`, cut short once its direction reached the capture limit.
+    pub(crate) fn record(&mut self, mut chunk: TunnelChunk) {
+        let total = match chunk.direction {
+            WsDirection::Client => &mut self.client_bytes,
+            WsDirection::Server => &mut self.server_bytes,
+        };
+        let room = TUNNEL_CAPTURE_LIMIT.saturating_sub(*total);
+        *total += chunk.data.len();
+        if room == 0 || chunk.data.is_empty() {
+            return;
+        }
+        chunk.data.truncate(room);
+        self.chunks.push(chunk);
+    }
+
+    /// The captured bytes `from` sent, in order.
+    pub fn bytes(&self, from: &WsDirection) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.direction == *from)
+            .flat_map(|chunk| chunk.data.iter().copied())
+            .collect()
+    }
+
+    /// Bytes `from` sent, including ones past the capture limit.
+    pub fn total(&self, from: &WsDirection) -> usize {
+        match from {
+            WsDirection::Client => self.client_bytes,
+            WsDirection::Server => self.server_bytes,
+        }
+    }
+
+    /// Whether `from` sent more than was kept.
+    pub fn truncated(&self, from: &WsDirection) -> bool {
+        self.total(from) > TUNNEL_CAPTURE_LIMIT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_each_direction() {
+        let mut tunnel = RawTunnel::new(false);
+        tunnel.record(TunnelChunk::server(Bytes::from_static(b"220 ready\r\n")));
+        tunnel.record(TunnelChunk::client(Bytes::from_static(b"EHLO a\r\n")));
+        tunnel.record(TunnelChunk::server(Bytes::from_static(b"250 ok\r\n")));
+
+        assert_eq!(
+            tunnel.bytes(&WsDirection::Server),
+            b"220 ready\r\n250 ok\r\n"
+        );
+        assert_eq!(tunnel.bytes(&WsDirection::Client), b"EHLO a\r\n");
+        assert_eq!(tunnel.total(&WsDirection::Server), 19);
+        assert!(!tunnel.truncated(&WsDirection::Client));
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        let mut tunnel = RawTunnel::new(true);
+        let big = Bytes::from(vec![7u8; TUNNEL_CAPTURE_LIMIT - 2]);
+        tunnel.record(TunnelChunk::client(big));
+        tunnel.record(TunnelChunk::client(Bytes::from_static(b"abcd")));
+        tunnel.record(TunnelChunk::client(Bytes::from_static(b"efgh")));
+
+        assert_eq!(tunnel.chunks.len(), 2);
+        assert_eq!(tunnel.chunks[1].data, Bytes::from_static(b"ab"));
+        assert_eq!(
+            tunnel.bytes(&WsDirection::Client).len(),
+            TUNNEL_CAPTURE_LIMIT
+        );
+        assert_eq!(tunnel.total(&WsDirection::Client), TUNNEL_CAPTURE_LIMIT + 6);
+        assert!(tunnel.truncated(&WsDirection::Client));
+        assert!(!tunnel.truncated(&WsDirection::Server));
+    }
+}
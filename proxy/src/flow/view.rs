@@ -0,0 +1,281 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::watch;
+use tracing::error;
+
+use crate::flow::{Flow, FlowStore, id::FlowId};
+
+/// The recorded flows as of the last change, for readers such as the UI that
+/// must never hold up the proxy. Flows in it are copies, so reading them
+/// takes no lock; changes arrive as a new view on [`FlowStore::view`].
+#[derive(Debug, Clone, Default)]
+pub struct FlowView {
+    /// Every flow in the order it was recorded.
+    pub flows: Arc<Vec<Arc<Flow>>>,
+    /// Position of each flow in `flows` by id.
+    index: Arc<HashMap<FlowId, usize>>,
+}
+
+impl FlowView {
+    pub fn get(&self, id: FlowId) -> Option<&Arc<Flow>> {
+        self.index.get(&id).and_then(|&i| self.flows.get(i))
+    }
+
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+
+    /// Applies `changes` in the order they were made. Only the flows added
+    /// or updated are copied, the others are shared with the view before.
+    async fn apply(&mut self, fs: &FlowStore, changes: Vec<ViewChange>) {
+        let flows = Arc::make_mut(&mut self.flows);
+        let index = Arc::make_mut(&mut self.index);
+        let mut updated = HashSet::new();
+        let mut removed = HashSet::new();
+        for change in changes {
+            match change {
+                ViewChange::Added(id) => {
+                    // A copy taken now already has every update made so far
+                    updated.remove(&id);
+                    if let Some(flow) = copy(fs, id).await {
+                        index.insert(id, flows.len());
+                        flows.push(flow);
+                    }
+                }
+                ViewChange::Updated(id) => {
+                    updated.insert(id);
+                }
+                ViewChange::Removed(id) => {
+                    if let Some(i) = index.remove(&id) {
+                        removed.insert(i);
+                    }
+                }
+            }
+        }
+        for id in updated {
+            if let Some(&i) = index.get(&id)
+                && let Some(flow) = copy(fs, id).await
+            {
+                flows[i] = flow;
+            }
+        }
+        if !removed.is_empty() {
+            let mut i = 0;
+            flows.retain(|_| {
+                let keep = !removed.contains(&i);
+                i += 1;
+                keep
+            });
+            *index = flows
+                .iter()
+                .enumerate()
+                .map(|(i, flow)| (flow.id, i))
+                .collect();
+        }
+    }
+}
+
+async fn copy(fs: &FlowStore, id: FlowId) -> Option<Arc<Flow>> {
+    let flow = fs.get_flow_by_id(id).await?;
+    Some(Arc::new(flow.read().await.clone()))
+}
+
+/// A change to the recorded flows, for the next view to apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ViewChange {
+    /// The flow was appended to the recorded flows.
+    Added(FlowId),
+    Updated(FlowId),
+    Removed(FlowId),
+}
+
+/// Changes made since the last view was built, in the order they were made.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChangedFlows(Arc<Mutex<Vec<ViewChange>>>);
+
+impl ChangedFlows {
+    pub(crate) fn push(&self, change: ViewChange) {
+        match self.0.lock() {
+            Ok(mut changes) => changes.push(change),
+            Err(err) => error!("Changed flows lock poisoned {err}"),
+        }
+    }
+
+    fn take(&self) -> Vec<ViewChange> {
+        match self.0.lock() {
+            Ok(mut changes) => std::mem::take(&mut *changes),
+            Err(err) => {
+                error!("Changed flows lock poisoned {err}");
+                vec![]
+            }
+        }
+    }
+}
+
+/// Applies the changes made since the last view each time the store
+/// notifies. Changes made while a view is built are folded into the next
+/// one.
+pub(crate) fn view_proc(fs: FlowStore, view_tx: watch::Sender<FlowView>) {
+    let mut changed_rx = fs.subscribe();
+    tokio::spawn(async move {
+        let mut view = FlowView::default();
+        while changed_rx.changed().await.is_ok() {
+            let changes = fs.changed.take();
+            if !changes.is_empty() {
+                view.apply(&fs, changes).await;
+            }
+            view_tx.send_replace(view.clone());
+        }
+    });
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::StatusCode;
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::flow::{FlowConnection, FlowEvent, InterceptedRequest, InterceptedResponse};
+
+    async fn wait_for(
+        view_rx: &mut watch::Receiver<FlowView>,
+        done: impl Fn(&FlowView) -> bool,
+    ) -> FlowView {
+        timeout(Duration::from_secs(5), view_rx.wait_for(|view| done(view)))
+            .await
+            .unwrap()
+            .unwrap()
+            .clone()
+    }
+
+    #[tokio::test]
+    async fn follows_the_store() {
+        let store = FlowStore::new();
+        let mut view_rx = store.view();
+        let connection = FlowConnection {
+            addr: "127.0.0.1:1".parse().unwrap(),
+        };
+        let first = store
+            .new_flow(connection.clone(), InterceptedRequest::default())
+            .await;
+        let second = store
+            .new_flow(connection, InterceptedRequest::default())
+            .await;
+        let view = wait_for(&mut view_rx, |view| view.len() == 2).await;
+        assert_eq!(view.flows[0].id, first);
+        assert!(view.get(second).unwrap().response.is_none());
+        let before = view.flows[0].clone();
+
+        store.post_event(
+            second,
+            FlowEvent::Response(InterceptedResponse {
+                status: StatusCode::CREATED,
+                ..Default::default()
+            }),
+        );
+        let view = wait_for(&mut view_rx, |view| {
+            view.get(second).is_some_and(|flow| flow.response.is_some())
+        })
+        .await;
+        // Unchanged flows are shared with the view before
+        assert!(Arc::ptr_eq(&before, &view.flows[0]));
+        assert_eq!(
+            view.get(second).unwrap().response.as_ref().unwrap().status,
+            StatusCode::CREATED
+        );
+
+        store.remove_flows(&[first]).await;
+        let view = wait_for(&mut view_rx, |view| view.len() == 1).await;
+        assert_eq!(view.flows[0].id, second);
+        assert!(view.get(first).is_none());
+        assert_eq!(view.get(second).unwrap().id, second);
+
+        let third = store
+            .new_flow(
+                FlowConnection {
+                    addr: "127.0.0.1:1".parse().unwrap(),
+                },
+                InterceptedRequest::default(),
+            )
+            .await;
+        let view = wait_for(&mut view_rx, |view| view.len() == 2).await;
+        assert_eq!(view.flows[1].id, third);
+        assert_eq!(view.get(third).unwrap().id, third);
+    }
+
+    /// A reader holding a view, as the UI does while it renders, does not
+    /// hold up flows being recorded and completed.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn readers_do_not_block_writers() {
+        const WRITERS: usize = 8;
+        const FLOWS_PER_WRITER: usize = 250;
+        let store = FlowStore::new();
+        let mut view_rx = store.view();
+        let held = view_rx.borrow().clone();
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let mut view_rx = store.view();
+                tokio::spawn(async move {
+                    let mut seen = 0;
+                    while view_rx.changed().await.is_ok() {
+                        let view = view_rx.borrow_and_update().clone();
+                        seen = seen.max(view.flows.iter().filter(|f| f.response.is_some()).count());
+                        if seen == WRITERS * FLOWS_PER_WRITER {
+                            break;
+                        }
+                    }
+                    seen
+                })
+            })
+            .collect();
+
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for _ in 0..FLOWS_PER_WRITER {
+                        let connection = FlowConnection {
+                            addr: "127.0.0.1:1".parse().unwrap(),
+                        };
+                        let id = store
+                            .new_flow(connection, InterceptedRequest::default())
+                            .await;
+                        store.post_event(id, FlowEvent::Response(InterceptedResponse::default()));
+                    }
+                })
+            })
+            .collect();
+        timeout(Duration::from_secs(10), async {
+            for writer in writers {
+                writer.await.unwrap();
+            }
+        })
+        .await
+        .unwrap();
+
+        let total = WRITERS * FLOWS_PER_WRITER;
+        wait_for(&mut view_rx, |view| {
+            view.flows.iter().filter(|f| f.response.is_some()).count() == total
+        })
+        .await;
+        for reader in readers {
+            let seen = timeout(Duration::from_secs(5), reader)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(seen, total);
+        }
+        assert!(held.is_empty());
+    }
+}
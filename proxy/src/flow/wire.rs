@@ -0,0 +1,80 @@
+//! The bytes of an HTTP/1 request and its response exactly as they were read
+//! off the connections, before hyper parsed them, kept while raw capture is
+//! on. See [`FlowStore::set_raw_capture`](crate::flow::FlowStore::set_raw_capture).
+//!
+//! Captures are filled while the messages are read, so a flow shows its
+//! bytes as they arrive. HTTP/2 and HTTP/3 flows have none.
+
+use roxy_shared::wire::{WireBytes, WireCapture};
+
+use crate::flow::WsDirection;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowWire {
+    /// The request as the client sent it.
+    pub client: Option<WireCapture>,
+    /// The response as the server sent it.
+    pub server: Option<WireCapture>,
+}
+
+impl FlowWire {
+    /// The bytes `from` sent so far.
+    pub fn bytes(&self, from: &WsDirection) -> Option<WireBytes> {
+        match from {
+            WsDirection::Client => self.client.as_ref(),
+            WsDirection::Server => self.server.as_ref(),
+        }
+        .map(WireCapture::bytes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.client.is_none() && self.server.is_none()
+    }
+
+    pub(crate) fn set(&mut self, from: WsDirection, capture: WireCapture) {
+        match from {
+            WsDirection::Client => self.client = Some(capture),
+            WsDirection::Server => self.server = Some(capture),
+        }
+    }
+
+    /// The request bytes followed by the response bytes, as exported.
+    pub fn transcript(&self) -> Vec<u8> {
+        [WsDirection::Client, WsDirection::Server]
+            .iter()
+            .filter_map(|from| self.bytes(from))
+            .flat_map(|bytes| bytes.data)
+            .collect()
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transcript_is_request_then_response() {
+        let mut wire = FlowWire::default();
+        assert!(wire.is_empty());
+        assert!(wire.bytes(&WsDirection::Client).is_none());
+
+        let server = WireCapture::default();
+        server.push(b"HTTP/1.1 204 No Content\r\n\r\n");
+        wire.set(WsDirection::Server, server);
+        let client = WireCapture::default();
+        wire.set(WsDirection::Client, client.clone());
+        // Bytes read after the capture was handed to the flow show up
+        client.push(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+
+        assert!(!wire.is_empty());
+        assert_eq!(
+            wire.bytes(&WsDirection::Client).unwrap().data,
+            "GET / HTTP/1.1\r\nHost: a\r\n\r\n"
+        );
+        assert_eq!(
+            wire.transcript(),
+            b"GET / HTTP/1.1\r\nHost: a\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use std::{io, path::Path, pin::Pin};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::Bytes;
+use http::HeaderMap;
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::broadcast,
+    task::JoinHandle,
+};
+use tracing::{debug, error, warn};
+
+use crate::{
+    flow::{Flow, FlowStore, FlowUpdate, FlowUpdateKind},
+    hook::FlowSummary,
+};
+
+/// Bytes of each body written when no limit is set.
+pub const DEFAULT_MAX_BODY: usize = 16 * 1024;
+
+/// Writes every completed flow as one line of JSON, for `jq` or a log
+/// shipper. Independent of the flows kept by the store.
+pub struct FlowLog {
+    writer: Pin<Box<dyn AsyncWrite + Send>>,
+    max_body: usize,
+}
+
+impl std::fmt::Debug for FlowLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FlowLog")
+            .field("max_body", &self.max_body)
+            .finish()
+    }
+}
+
+impl FlowLog {
+    /// Appends to the file at `path`, creating it when missing.
+    pub async fn file(path: &Path) -> io::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(Self::new(file))
+    }
+
+    /// Writes to stdout, only of use when nothing else draws on it.
+    pub fn stdout() -> Self {
+        Self::new(tokio::io::stdout())
+    }
+
+    pub fn new(writer: impl AsyncWrite + Send + 'static) -> Self {
+        Self {
+            writer: Box::pin(writer),
+            max_body: DEFAULT_MAX_BODY,
+        }
+    }
+
+    /// Cuts bodies after `max_body` bytes, `0` leaves them out.
+    pub fn with_max_body(mut self, max_body: usize) -> Self {
+        self.max_body = max_body;
+        self
+    }
+
+    fn encode(&self, flow: &Flow) -> io::Result<Vec<u8>> {
+        let mut line = serde_json::to_vec(&FlowLine::new(flow, self.max_body))?;
+        line.push(b'\n');
+        Ok(line)
+    }
+
+    async fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        self.writer.write_all(line).await?;
+        self.writer.flush().await
+    }
+}
+
+/// One line of the log, the hook summary with the messages themselves.
+#[derive(Debug, Serialize)]
+struct FlowLine {
+    #[serde(flatten)]
+    summary: FlowSummary,
+    timestamp: Option<String>,
+    request: Option<MessageLine>,
+    response: Option<MessageLine>,
+}
+
+#[derive(Debug, Serialize)]
+struct MessageLine {
+    version: String,
+    headers: Vec<(String, String)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<BodyLine>,
+}
+
+/// A body as text when it is UTF-8 and as base64 otherwise, `size` is that
+/// of the whole body.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+struct BodyLine {
+    size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    base64: Option<String>,
+    truncated: bool,
+}
+
+impl FlowLine {
+    fn new(flow: &Flow, max_body: usize) -> Self {
+        let request = flow.request.as_ref();
+        Self {
+            summary: FlowSummary::new(flow),
+            timestamp: request.and_then(|r| r.timestamp.format(&Rfc3339).ok()),
+            request: request.map(|r| {
+                MessageLine::new(r.version.to_string(), &r.headers, r.read_body(), max_body)
+            }),
+            response: flow.response.as_ref().map(|r| {
+                MessageLine::new(r.version.to_string(), &r.headers, r.read_body(), max_body)
+            }),
+        }
+    }
+}
+
+impl MessageLine {
+    fn new(version: String, headers: &HeaderMap, body: io::Result<Bytes>, max_body: usize) -> Self {
+        let body = body.unwrap_or_else(|err| {
+            warn!("Failed to read spilled body {err}");
+            Bytes::new()
+        });
+        Self {
+            version,
+            headers: headers
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                    )
+                })
+                .collect(),
+            body: (max_body > 0 && !body.is_empty()).then(|| BodyLine::new(&body, max_body)),
+        }
+    }
+}
+
+impl BodyLine {
+    fn new(body: &[u8], max_body: usize) -> Self {
+        let shown = &body[..body.len().min(max_body)];
+        let (text, base64) = match std::str::from_utf8(shown) {
+            Ok(text) => (Some(text.to_string()), None),
+            // Cut in the middle of a character
+            Err(err) if err.error_len().is_none() => (
+                Some(String::from_utf8_lossy(&shown[..err.valid_up_to()]).into_owned()),
+                None,
+            ),
+            Err(_) => (None, Some(STANDARD.encode(shown))),
+        };
+        Self {
+            size: body.len(),
+            text,
+            base64,
+            truncated: shown.len() < body.len(),
+        }
+    }
+}
+
+/// Writes flows to `log` as they complete in `flow_store`, until the store
+/// is dropped or writing fails.
+pub(crate) fn start_flow_log(flow_store: FlowStore, mut log: FlowLog) -> JoinHandle<()> {
+    let mut updates_rx = flow_store.subscribe_updates();
+    tokio::spawn(async move {
+        loop {
+            let id = match updates_rx.recv().await {
+                Ok(FlowUpdate {
+                    id,
+                    kind: FlowUpdateKind::ResponseComplete,
+                }) => id,
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Flow log skipped {skipped} flow updates");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let Some(flow) = flow_store.get_flow_by_id(id).await else {
+                continue;
+            };
+            let line = match log.encode(&*flow.read().await) {
+                Ok(line) => line,
+                Err(err) => {
+                    error!("Flow log failed to encode flow {id} {err}");
+                    continue;
+                }
+            };
+            if let Err(err) = log.write(&line).await {
+                error!("Flow log stopped {err}");
+                break;
+            }
+        }
+        debug!("Flow log finished");
+    })
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::{HeaderValue, Method, StatusCode};
+
+    use crate::flow::test_flow;
+
+    use super::*;
+
+    fn flow() -> Flow {
+        let mut flow = test_flow(
+            3,
+            Method::POST,
+            "https://api.example.com/orders",
+            StatusCode::CREATED,
+            &[0xff, 0x00, 0x01],
+        );
+        let request = flow.request.as_mut().unwrap();
+        request.body = Bytes::from_static("{\"name\":\"café\"}".as_bytes());
+        request
+            .headers
+            .insert("content-type", HeaderValue::from_static("application/json"));
+        flow
+    }
+
+    #[test]
+    fn cuts_bodies() {
+        let whole = BodyLine::new("café".as_bytes(), 100);
+        assert_eq!(whole.text.as_deref(), Some("café"));
+        assert!(!whole.truncated);
+
+        let cut = BodyLine::new("café".as_bytes(), 4);
+        assert_eq!(cut.text.as_deref(), Some("caf"));
+        assert_eq!(cut.size, 5);
+        assert!(cut.truncated);
+
+        let binary = BodyLine::new(&[0xff, 0x00, 0x01], 2);
+        assert_eq!(binary.base64.as_deref(), Some("/wA="));
+        assert!(binary.text.is_none());
+    }
+
+    #[test]
+    fn flow_line_json() {
+        let json = serde_json::to_value(FlowLine::new(&flow(), DEFAULT_MAX_BODY)).unwrap();
+        assert_eq!(json["id"], 3);
+        assert_eq!(json["method"], "POST");
+        assert_eq!(json["status"], 201);
+        assert_eq!(json["request"]["headers"][0][0], "content-type");
+        assert_eq!(json["request"]["body"]["text"], "{\"name\":\"café\"}");
+        assert_eq!(json["response"]["body"]["base64"], "/wAB");
+
+        let json = serde_json::to_value(FlowLine::new(&flow(), 0)).unwrap();
+        assert!(json["request"].get("body").is_none());
+    }
+
+    #[tokio::test]
+    async fn writes_completed_flows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("flows.jsonl");
+        let mut log = FlowLog::file(&path).await.unwrap().with_max_body(4);
+        for _ in 0..2 {
+            let line = log.encode(&flow()).unwrap();
+            log.write(&line).await.unwrap();
+        }
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["request"]["body"]["truncated"], true);
+    }
+}
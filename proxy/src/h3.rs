@@ -1,10 +1,10 @@
 use std::{error::Error, io, net::UdpSocket, sync::Arc};
 
 use bytes::{Buf, Bytes, BytesMut};
-use h3::{ext::Protocol, server::RequestResolver};
+use h3::{ext::Protocol, server::RequestStream};
 use http::{
     Method,
-    header::{CONTENT_TYPE, HOST},
+    header::{CONTENT_TYPE, HOST, PROXY_AUTHENTICATE},
 };
 use quinn::{
     EndpointConfig,
@@ -23,13 +23,20 @@ use tokio::task::JoinHandle;
 use tracing::{debug, error, trace, warn};
 
 use crate::{
-    flow::{FlowEvent, InterceptedRequest, InterceptedResponse},
+    error_page::error_page,
+    flow::{
+        FlowEvent, InterceptedRequest, InterceptedResponse,
+        error::{FlowError, FlowErrorKind},
+    },
+    http::{host_slot, upstream_request},
+    masque::{masque_target, serve_masque},
     proxy::{FlowContext, ProxyContext},
+    version::VersionChoice,
 };
 
-// TODO: handle this from https://www.ietf.org/archive/id/draft-schinazi-masque-connect-udp-00.html
-// If there are multiple proxies involved, proxies along the chain MUST check whether their upstream connection supports HTTP/3 datagrams. If it does not, that proxy MUST remove the "Datagram-Flow-Id" header before forwarding the CONNECT-UDP request.
-//
+// A CONNECT-UDP request with a `Host` header and no masque path switches the
+// connection to carrying proxied HTTP/3 requests directly. RFC 9298 tunnels
+// are handled in `masque`.
 
 pub enum H3Error {
     RustLs,
@@ -61,6 +68,9 @@ pub async fn start_h3(cxt: ProxyContext, udp_socket: UdpSocket) -> Result<JoinHa
         .with_single_cert(vec![leaf], kp)?;
 
     tls_config.alpn_protocols = alp_h3();
+    if let Some(key_log) = cxt.tls_config.key_log() {
+        tls_config.key_log = key_log;
+    }
 
     let runtime = default_runtime().ok_or_else(|| io::Error::other("no async runtime found"))?;
 
@@ -74,21 +84,38 @@ pub async fn start_h3(cxt: ProxyContext, udp_socket: UdpSocket) -> Result<JoinHa
         udp_socket,
         runtime,
     )?;
-    let handle = tokio::spawn(async move {
-        debug!("Accepting H3 on {}", addr);
-
-        while let Some(new_conn) = endpoint.accept().await {
-            let cxt = cxt.clone();
-            tokio::spawn(async {
-                if let Err(e) = do_conn(new_conn, cxt).await {
-                    error!("H3 conn err {e}");
-                }
-            });
-        }
-        error!("HTTP/3 server stopped accepting connections");
-    });
+    let supervisor = cxt.supervisor.clone();
+    Ok(supervisor.spawn(format!("h3 {addr}"), move || {
+        accept_h3(cxt.clone(), endpoint.clone())
+    }))
+}
 
-    Ok(handle)
+async fn accept_h3(cxt: ProxyContext, endpoint: quinn::Endpoint) -> io::Result<()> {
+    debug!("Accepting H3 on {:?}", endpoint.local_addr());
+    loop {
+        let Some(new_conn) = endpoint.accept().await else {
+            // Only closing the endpoint ends it, there is nothing to restart
+            return Ok(());
+        };
+        let cxt = cxt.clone();
+        tokio::spawn(async move {
+            if !cxt.admit(new_conn.remote_address()).await {
+                new_conn.refuse();
+                return;
+            }
+            let Some(_slot) = cxt.limits.client_slot() else {
+                debug!(
+                    "Too many client connections, refusing {}",
+                    new_conn.remote_address()
+                );
+                new_conn.refuse();
+                return;
+            };
+            if let Err(e) = do_conn(new_conn, cxt).await {
+                error!("H3 conn err {e}");
+            }
+        });
+    }
 }
 
 async fn do_conn(new_conn: quinn::Incoming, cxt: ProxyContext) -> Result<(), Box<dyn Error>> {
@@ -96,125 +123,278 @@ async fn do_conn(new_conn: quinn::Incoming, cxt: ProxyContext) -> Result<(), Box
         Ok(conn) => {
             let addr = conn.remote_address();
             trace!("H3 conn {addr}");
-            let mut h3_conn = h3::server::Connection::new(h3_quinn::Connection::new(conn)).await?;
+            let mut h3_conn = h3::server::builder()
+                .enable_extended_connect(true)
+                .enable_datagram(true)
+                .build(h3_quinn::Connection::new(conn.clone()))
+                .await?;
 
             let resolver = match h3_conn.accept().await? {
                 Some(res) => res,
                 None => return Err(Box::new(std::io::Error::other("Resolver was none"))),
             };
 
-            let target_uri = handle_connect(resolver).await?;
-            let flow_cxt = FlowContext::new(addr, target_uri, cxt);
-
-            loop {
-                match h3_conn.accept().await {
-                    Ok(Some(resolver)) => {
-                        let Ok((req, mut stream)) = resolver.resolve_request().await else {
-                            warn!("Failed to resolve_request");
-                            continue;
-                        };
-
-                        let mut bytes = BytesMut::new();
-                        while let Ok(Some(chunk)) = stream.recv_data().await {
-                            bytes.extend(chunk.chunk());
-                        }
-
-                        stream.recv_trailers().await?;
-
-                        let mut intercepted_request = InterceptedRequest::from_http(
-                            req.uri().into(),
-                            AlpnProtocol::Http3,
-                            req.into_parts().0,
-                            bytes.freeze(),
-                            None,
-                        );
-
-                        let response = flow_cxt
-                            .proxy_cxt
-                            .script_engine
-                            .intercept_request(&mut intercepted_request)
-                            .await?;
+            let (req, mut stream) = resolver.resolve_request().await?;
+            if !cxt.auth.verify(req.headers()) {
+                debug!("Proxy authentication failed {addr}");
+                let mut response = http::Response::builder()
+                    .status(http::StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+                for challenge in cxt.auth.challenges() {
+                    response = response.header(PROXY_AUTHENTICATE, challenge);
+                }
+                stream.send_response(response.body(())?).await?;
+                stream.finish().await?;
+                return Ok(());
+            }
+            if let Some(target_uri) = masque_target(&req) {
+                return serve_masque(h3_conn, conn, (req, stream), target_uri, cxt).await;
+            }
 
-                        let req = intercepted_request.request()?;
-                        let flow_id = flow_cxt
-                            .proxy_cxt
-                            .flow_store
-                            .new_flow_cxt(&flow_cxt, intercepted_request.clone())
-                            .await;
-
-                        if let Some(response) = response {
-                            flow_cxt
-                                .proxy_cxt
-                                .flow_store
-                                .post_event(flow_id, FlowEvent::Response(response.clone()));
-
-                            let resp = response.response_builder();
-                            stream.send_response(resp.body(())?).await?;
-                            stream.send_data(response.body).await?;
-                            if let Some(trailers) = response.trailers {
-                                stream.send_trailers(trailers).await?;
-                            }
-                            stream.finish().await?;
-                            continue;
-                        }
-
-                        let client = ClientContext::builder()
-                            .with_roxy_ca(flow_cxt.proxy_cxt.ca.clone())
-                            .build();
-                        let resp = client.request(req).await?;
+            let target_uri = handle_connect(req, stream).await?;
+            let flow_cxt = FlowContext::new(addr, target_uri, cxt);
+            serve_requests(h3_conn, flow_cxt).await
+        }
+        Err(err) => {
+            error!("accepting connection failed: {:?}", err);
+            Ok(())
+        }
+    }
+}
 
-                        let mut intercepted_response =
-                            InterceptedResponse::from_http(resp.parts, resp.body, resp.trailers);
+/// Intercepts every request on `h3_conn` as a flow of `flow_cxt`.
+pub(crate) async fn serve_requests(
+    mut h3_conn: h3::server::Connection<h3_quinn::Connection, Bytes>,
+    flow_cxt: FlowContext,
+) -> Result<(), Box<dyn Error>> {
+    loop {
+        match h3_conn.accept().await {
+            Ok(Some(resolver)) => {
+                let Ok((req, mut stream)) = resolver.resolve_request().await else {
+                    warn!("Failed to resolve_request");
+                    continue;
+                };
+
+                let limits = flow_cxt.proxy_cxt.limits.limits();
+                let mut bytes = BytesMut::new();
+                let mut too_large = None;
+                while let Ok(Some(chunk)) = stream.recv_data().await {
+                    bytes.extend(chunk.chunk());
+                    if let Some(max) = limits.max_body_size
+                        && bytes.len() > max
+                    {
+                        too_large = Some(max);
+                        break;
+                    }
+                }
 
-                        flow_cxt
-                            .proxy_cxt
-                            .script_engine
-                            .intercept_response(&intercepted_request, &mut intercepted_response)
-                            .await?;
+                if let Some(max) = too_large {
+                    let intercepted_request = InterceptedRequest::from_http(
+                        req.uri().into(),
+                        AlpnProtocol::Http3,
+                        req.into_parts().0,
+                        Bytes::new(),
+                        None,
+                    );
+                    let flow_store = &flow_cxt.proxy_cxt.flow_store;
+                    let flow_id = flow_store.next_flow_id();
+                    flow_store
+                        .new_flow_cxt(&flow_cxt, flow_id, intercepted_request)
+                        .await;
+                    let error = FlowError::new(
+                        FlowErrorKind::Rejected,
+                        format!("Request body larger than {max} bytes"),
+                    );
+                    flow_store.post_event(flow_id, FlowEvent::Error(error));
+                    let resp = http::Response::builder()
+                        .status(http::StatusCode::PAYLOAD_TOO_LARGE)
+                        .body(())?;
+                    stream.send_response(resp).await?;
+                    stream.finish().await?;
+                    continue;
+                }
 
-                        let resp = intercepted_response.response_builder();
-                        let body = encode_body_opt(
-                            intercepted_response.body.clone(),
-                            &intercepted_response.encoding,
-                        )?;
-                        let trailers = intercepted_response.trailers.clone();
+                stream.recv_trailers().await?;
+
+                let mut intercepted_request = InterceptedRequest::from_http(
+                    req.uri().into(),
+                    AlpnProtocol::Http3,
+                    req.into_parts().0,
+                    bytes.freeze(),
+                    None,
+                );
+                flow_cxt.proxy_cxt.rewrites.apply(&mut intercepted_request);
+
+                let flow_id = flow_cxt.proxy_cxt.flow_store.next_flow_id();
+                let response = flow_cxt
+                    .proxy_cxt
+                    .script_engine
+                    .intercept_request(flow_id, &mut intercepted_request)
+                    .await?;
+
+                flow_cxt
+                    .proxy_cxt
+                    .flow_store
+                    .new_flow_cxt(&flow_cxt, flow_id, intercepted_request.clone())
+                    .await;
+
+                if let Some(response) = response {
+                    flow_cxt
+                        .proxy_cxt
+                        .flow_store
+                        .post_event(flow_id, FlowEvent::Response(response.clone()));
+
+                    let resp = response.response_builder();
+                    stream.send_response(resp.body(())?).await?;
+                    stream.send_data(response.body).await?;
+                    if let Some(trailers) = response.trailers {
+                        stream.send_trailers(trailers).await?;
+                    }
+                    stream.finish().await?;
+                    continue;
+                }
 
+                if !flow_cxt
+                    .proxy_cxt
+                    .breakpoints
+                    .hold(
+                        &flow_cxt.proxy_cxt.flow_store,
+                        flow_id,
+                        &mut intercepted_request,
+                    )
+                    .await
+                {
+                    let resp = http::Response::builder()
+                        .status(http::StatusCode::BAD_GATEWAY)
+                        .body(())?;
+                    stream.send_response(resp).await?;
+                    stream.finish().await?;
+                    continue;
+                }
+                flow_cxt
+                    .proxy_cxt
+                    .flow_store
+                    .post_event(flow_id, FlowEvent::RequestComplete);
+
+                let Some(_host_slot) = host_slot(&flow_cxt, flow_id, &intercepted_request) else {
+                    let resp = http::Response::builder()
+                        .status(http::StatusCode::SERVICE_UNAVAILABLE)
+                        .body(())?;
+                    stream.send_response(resp).await?;
+                    stream.finish().await?;
+                    continue;
+                };
+                let versions = &flow_cxt.proxy_cxt.versions;
+                let negotiated =
+                    versions.negotiate(&intercepted_request.uri, intercepted_request.version.0);
+                let req = upstream_request(&intercepted_request, negotiated.version)?;
+                let mut builder = ClientContext::builder()
+                    .with_roxy_ca(flow_cxt.proxy_cxt.ca.clone())
+                    .with_dns(flow_cxt.proxy_cxt.dns.clone())
+                    .with_connect_timeout(limits.connect_timeout)
+                    .with_response_limits(limits.response_limits());
+                if let Some(alpns) = negotiated.alpns.clone() {
+                    builder = builder.with_alpns(alpns);
+                }
+                let client = builder.build();
+                let fetched = flow_cxt
+                    .proxy_cxt
+                    .cache
+                    .fetch(&intercepted_request, || async move {
+                        let resp = client.request(req).await?;
+                        Ok(InterceptedResponse::from_http(
+                            resp.parts,
+                            resp.body,
+                            resp.trailers,
+                        ))
+                    })
+                    .await;
+                let fetched = match fetched {
+                    Ok(fetched) => fetched,
+                    Err(err) => {
+                        let error = FlowError::from(&err);
+                        let page = error_page(flow_id, &intercepted_request, &error);
+                        let resp = page.response_builder();
+                        let body = page.body.clone();
                         flow_cxt
                             .proxy_cxt
                             .flow_store
-                            .post_event(flow_id, FlowEvent::Response(intercepted_response.clone()));
-
+                            .post_event(flow_id, FlowEvent::Failed(error, page));
                         stream.send_response(resp.body(())?).await?;
                         stream.send_data(body).await?;
-                        if let Some(trailers) = trailers {
-                            stream.send_trailers(trailers).await?;
-                        }
                         stream.finish().await?;
+                        continue;
                     }
+                };
+                let Some(mut intercepted_response) = fetched else {
+                    flow_cxt.proxy_cxt.flow_store.post_event(
+                        flow_id,
+                        FlowEvent::Error(FlowError::new(
+                            FlowErrorKind::Rejected,
+                            "Not in offline cache",
+                        )),
+                    );
+                    let resp = http::Response::builder()
+                        .status(http::StatusCode::GATEWAY_TIMEOUT)
+                        .body(())?;
+                    stream.send_response(resp).await?;
+                    stream.finish().await?;
+                    continue;
+                };
+                intercepted_response.tags = intercepted_request.tags.clone();
+                versions.learn_alt_svc(&intercepted_request.uri, &intercepted_response.headers);
+                flow_cxt.proxy_cxt.flow_store.post_event(
+                    flow_id,
+                    FlowEvent::Version(VersionChoice {
+                        preference: negotiated.preference,
+                        requested: intercepted_request.version,
+                        chosen: intercepted_response.version,
+                        alt_svc: negotiated.alt_svc,
+                    }),
+                );
+
+                flow_cxt
+                    .proxy_cxt
+                    .script_engine
+                    .intercept_response(flow_id, &intercepted_request, &mut intercepted_response)
+                    .await?;
+
+                let resp = intercepted_response.response_builder();
+                let body = encode_body_opt(
+                    intercepted_response.body.clone(),
+                    &intercepted_response.encoding,
+                )?;
+                let trailers = intercepted_response.trailers.clone();
+
+                flow_cxt
+                    .proxy_cxt
+                    .flow_store
+                    .post_event(flow_id, FlowEvent::Response(intercepted_response.clone()));
+
+                stream.send_response(resp.body(())?).await?;
+                stream.send_data(body).await?;
+                if let Some(trailers) = trailers {
+                    stream.send_trailers(trailers).await?;
+                }
+                stream.finish().await?;
+            }
 
-                    Ok(None) => {
-                        break;
-                    }
+            Ok(None) => {
+                break;
+            }
 
-                    Err(err) => {
-                        error!("error on accept {}", err);
-                        break;
-                    }
-                }
+            Err(err) => {
+                error!("error on accept {}", err);
+                break;
             }
         }
-        Err(err) => {
-            error!("accepting connection failed: {:?}", err);
-        }
     }
     Ok(())
 }
 
-async fn handle_connect<C>(resolver: RequestResolver<C, Bytes>) -> Result<RUri, Box<dyn Error>>
-where
-    C: h3::quic::Connection<Bytes>,
-{
-    let (req, mut stream) = resolver.resolve_request().await?;
+async fn handle_connect(
+    req: http::Request<()>,
+    mut stream: RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+) -> Result<RUri, Box<dyn Error>> {
     debug!(?req, "Received request");
     let req_host = req.headers().get(HOST);
 
@@ -0,0 +1,265 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use http::{Method, Request, Uri};
+use roxy_shared::wire::{WireCapture, WireTarget};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::error;
+
+use crate::flow::raw_headers::RawHeaders;
+
+/// Bytes of a connection kept to find request heads in, heads larger than
+/// this are handled without their raw lines.
+const HEAD_WINDOW: usize = 64 * 1024;
+
+/// Keeps the latest bytes a client sent on an HTTP/1 connection, so the head
+/// of each request hyper parsed can be read again as it was written.
+pub(crate) struct HeadRecorder<S> {
+    stream: S,
+    heads: RecordedHeads,
+}
+
+impl<S> HeadRecorder<S> {
+    pub(crate) fn new(stream: S) -> (Self, RecordedHeads) {
+        let heads = RecordedHeads::default();
+        (
+            Self {
+                stream,
+                heads: heads.clone(),
+            },
+            heads,
+        )
+    }
+}
+
+/// The bytes a [`HeadRecorder`] kept, shared with the service handling its
+/// requests.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RecordedHeads {
+    window: Arc<Mutex<Vec<u8>>>,
+    /// Where every byte read goes while raw capture is on, the capture of
+    /// the request last attached.
+    wire: WireTarget,
+}
+
+impl RecordedHeads {
+    fn record(&self, bytes: &[u8]) {
+        match self.window.lock() {
+            Ok(mut window) => {
+                window.extend_from_slice(bytes);
+                let over = window.len().saturating_sub(HEAD_WINDOW);
+                window.drain(..over);
+                self.wire.push(bytes);
+            }
+            Err(err) => error!("Recorded heads lock poisoned {err}"),
+        }
+    }
+
+    /// Adds the raw header lines of `req` to its extensions, where
+    /// [`crate::flow::InterceptedRequest::from_http`] picks them up. With
+    /// `raw_capture` a [`WireCapture`] of the request's bytes is added too.
+    pub(crate) fn attach<B>(&self, req: &mut Request<B>, raw_capture: bool) {
+        let (raw, capture) = match self.window.lock() {
+            Ok(mut window) => {
+                match find_head(&window, req.method(), req.uri()) {
+                    Some((start, end)) => {
+                        let capture = raw_capture.then(WireCapture::default);
+                        // What was read from this head on went to the request before
+                        if let Some(previous) = self.wire.set(capture.clone()) {
+                            previous.trim_end(window.len() - start);
+                        }
+                        if let Some(capture) = &capture {
+                            capture.push(&window[start..]);
+                        }
+                        let raw = RawHeaders::parse(&window[start..end]);
+                        window.drain(..end);
+                        (raw, capture)
+                    }
+                    // Too large to find, its bytes are not told apart
+                    None => {
+                        self.wire.set(None);
+                        (None, None)
+                    }
+                }
+            }
+            Err(err) => {
+                error!("Recorded heads lock poisoned {err}");
+                (None, None)
+            }
+        };
+        if let Some(raw) = raw {
+            req.extensions_mut().insert(raw);
+        }
+        if let Some(capture) = capture {
+            req.extensions_mut().insert(capture);
+        }
+    }
+
+    /// The first head not yet handed to a request that folds a header over
+    /// several lines, which hyper refuses without calling the service.
+    pub(crate) fn folded(&self) -> Option<(Method, Uri, RawHeaders)> {
+        let window = match self.window.lock() {
+            Ok(window) => window,
+            Err(err) => {
+                error!("Recorded heads lock poisoned {err}");
+                return None;
+            }
+        };
+        line_starts(&window).find_map(|start| {
+            let head = &window[start..];
+            let end = head_end(head)?;
+            let (method, uri) = request_line(head)?;
+            let raw = RawHeaders::parse(&head[..end])?;
+            raw.folded().then_some((method, uri, raw))
+        })
+    }
+}
+
+/// Where the head of the request `method` `uri` starts and ends in `window`.
+fn find_head(window: &[u8], method: &Method, uri: &Uri) -> Option<(usize, usize)> {
+    line_starts(window).find_map(|start| {
+        let head = &window[start..];
+        let (m, u) = request_line(head)?;
+        if m != method || u != *uri {
+            return None;
+        }
+        head_end(head).map(|end| (start, start + end))
+    })
+}
+
+fn line_starts(window: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    std::iter::once(0).chain(
+        window
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b == b'\n')
+            .map(|(i, _)| i + 1),
+    )
+}
+
+/// The method and target of the request line `head` starts with.
+fn request_line(head: &[u8]) -> Option<(Method, Uri)> {
+    let line = &head[..head.iter().position(|b| *b == b'\n')?];
+    let mut parts = line.trim_ascii_end().split(|b| *b == b' ');
+    let method = Method::from_bytes(parts.next()?).ok()?;
+    let uri = Uri::try_from(parts.next()?).ok()?;
+    parts
+        .next()
+        .filter(|version| version.starts_with(b"HTTP/1."))?;
+    Some((method, uri))
+}
+
+/// Length of the head `head` starts with, up to and including the empty
+/// line ending it.
+fn head_end(head: &[u8]) -> Option<usize> {
+    head.windows(2)
+        .enumerate()
+        .find_map(|(i, pair)| match pair {
+            b"\n\n" => Some(i + 2),
+            b"\n\r" if head.get(i + 2) == Some(&b'\n') => Some(i + 3),
+            _ => None,
+        })
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for HeadRecorder<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let polled = Pin::new(&mut self.stream).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = polled {
+            self.heads.record(&buf.filled()[before..]);
+        }
+        polled
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for HeadRecorder<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_each_head_after_the_last() {
+        let heads = RecordedHeads::default();
+        heads.record(b"POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\na=1");
+        heads.record(b"GET /b HTTP/1.1\r\nX-Second: yes\r\n\r\n");
+
+        let mut other = Request::get("/c").body(()).unwrap();
+        heads.attach(&mut other, false);
+        assert!(other.extensions().get::<RawHeaders>().is_none());
+
+        let mut first = Request::post("/a").body(()).unwrap();
+        heads.attach(&mut first, false);
+        let raw = first.extensions().get::<RawHeaders>().unwrap();
+        assert_eq!(raw.lines[0].name, "Content-Length");
+
+        let mut second = Request::get("/b").body(()).unwrap();
+        heads.attach(&mut second, false);
+        let raw = second.extensions().get::<RawHeaders>().unwrap();
+        assert_eq!(raw.lines[0].name, "X-Second");
+        assert!(heads.folded().is_none());
+    }
+
+    #[test]
+    fn captures_the_bytes_of_each_request() {
+        let heads = RecordedHeads::default();
+        heads.record(b"POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\n");
+
+        let mut first = Request::post("/a").body(()).unwrap();
+        heads.attach(&mut first, true);
+        let first = first.extensions().get::<WireCapture>().unwrap().clone();
+        // The body and the next request arrive in one read
+        heads.record(b"a=1GET /b HTTP/1.1\r\n\r\n");
+
+        let mut second = Request::get("/b").body(()).unwrap();
+        heads.attach(&mut second, true);
+        let second = second.extensions().get::<WireCapture>().unwrap().clone();
+        heads.record(b"GET /c HTTP/1.1\r\n\r\n");
+
+        let mut third = Request::get("/c").body(()).unwrap();
+        heads.attach(&mut third, false);
+        assert!(third.extensions().get::<WireCapture>().is_none());
+        heads.record(b"GET /d HTTP/1.1\r\n\r\n");
+
+        assert_eq!(
+            first.bytes().data,
+            "POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\na=1"
+        );
+        assert_eq!(second.bytes().data, "GET /b HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn finds_folded_heads() {
+        let heads = RecordedHeads::default();
+        heads.record(b"GET http://example.com/ HTTP/1.1\r\nX-A: 1\r\n 2\r\n\r\n");
+        let (method, uri, raw) = heads.folded().unwrap();
+        assert_eq!(method, Method::GET);
+        assert_eq!(uri, "http://example.com/");
+        assert!(raw.lines[0].folded);
+    }
+}
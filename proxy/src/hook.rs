@@ -0,0 +1,324 @@
+use std::{
+    process::Stdio,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http::{
+    Method, Request, Uri,
+    header::{CONTENT_TYPE, HOST},
+};
+use http_body_util::{BodyExt, Full};
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::{io::AsyncWriteExt, process::Command, sync::broadcast, task::JoinHandle};
+use tracing::{debug, error, warn};
+
+use crate::{
+    flow::{
+        Flow, FlowUpdate, FlowUpdateKind, error::FlowErrorKind, filter::FlowFilter, id::FlowId,
+    },
+    proxy::ProxyContext,
+};
+
+/// How long a webhook or command may take before it is given up on.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the summary of a matching flow is sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookTarget {
+    /// POSTed as JSON to the url.
+    Webhook(Uri),
+    /// Run through the shell with the JSON on stdin.
+    Command(String),
+}
+
+/// Notifies `target` of every completed flow `filter` matches.
+#[derive(Debug, Clone)]
+pub struct HookRule {
+    filter: FlowFilter,
+    target: HookTarget,
+}
+
+impl HookRule {
+    pub fn new(filter: FlowFilter, target: HookTarget) -> Self {
+        Self { filter, target }
+    }
+}
+
+/// What a hook or the control API tells about a flow.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FlowSummary {
+    pub id: FlowId,
+    /// The filter of the hook rule that matched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    pub method: Option<String>,
+    pub url: Option<String>,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<FlowErrorKind>,
+    pub tags: Vec<String>,
+    pub client: String,
+    pub request_bytes: usize,
+    pub response_bytes: usize,
+    /// From the request to the end of the response or the error.
+    pub duration_ms: Option<u64>,
+}
+
+impl FlowSummary {
+    pub fn new(flow: &Flow) -> Self {
+        let request = flow.request.as_ref();
+        let duration_ms = request.and_then(|r| {
+            let end = flow
+                .timing
+                .response_complete
+                .unwrap_or_else(OffsetDateTime::now_utc);
+            u64::try_from((end - r.timestamp).whole_milliseconds()).ok()
+        });
+        Self {
+            id: flow.id,
+            filter: None,
+            method: request.map(|r| r.method.to_string()),
+            url: request.map(|r| r.uri.to_string()),
+            status: flow.response.as_ref().map(|r| r.status.as_u16()),
+            error: flow.error.as_ref().map(|e| e.message.clone()),
+            error_kind: flow.error.as_ref().map(|e| e.kind),
+            tags: flow.tags.clone(),
+            client: flow.client_connection.addr.to_string(),
+            request_bytes: request.map(|r| r.body_len()).unwrap_or_default(),
+            response_bytes: flow
+                .response
+                .as_ref()
+                .map(|r| r.body_len())
+                .unwrap_or_default(),
+            duration_ms,
+        }
+    }
+}
+
+/// Shared handle to the active hook rules, every matching rule fires.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    rules: Arc<RwLock<Vec<HookRule>>>,
+}
+
+impl Hooks {
+    pub fn new(rules: Vec<HookRule>) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+        }
+    }
+
+    pub fn set_rules(&self, rules: Vec<HookRule>) {
+        match self.rules.write() {
+            Ok(mut guard) => *guard = rules,
+            Err(err) => error!("Hook lock poisoned {err}"),
+        }
+    }
+
+    /// The targets to notify of `flow` with its summary for each.
+    pub fn notifications(&self, flow: &Flow) -> Vec<(HookTarget, FlowSummary)> {
+        match self.rules.read() {
+            Ok(rules) => rules
+                .iter()
+                .filter(|r| r.filter.matches(flow))
+                .map(|r| {
+                    let summary = FlowSummary {
+                        filter: Some(r.filter.source().to_string()),
+                        ..FlowSummary::new(flow)
+                    };
+                    (r.target.clone(), summary)
+                })
+                .collect(),
+            Err(err) => {
+                error!("Hook lock poisoned {err}");
+                vec![]
+            }
+        }
+    }
+}
+
+/// Fires `hooks` for flows as they complete in the flow store of `cxt`.
+pub(crate) fn start_hooks(cxt: ProxyContext, hooks: Hooks) -> JoinHandle<()> {
+    let mut updates_rx = cxt.flow_store.subscribe_updates();
+    tokio::spawn(async move {
+        loop {
+            let id = match updates_rx.recv().await {
+                Ok(FlowUpdate {
+                    id,
+                    kind: FlowUpdateKind::ResponseComplete,
+                }) => id,
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Hooks skipped {skipped} flow updates");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let Some(flow) = cxt.flow_store.get_flow_by_id(id).await else {
+                continue;
+            };
+            let notifications = hooks.notifications(&*flow.read().await);
+            for (target, summary) in notifications {
+                let cxt = cxt.clone();
+                tokio::spawn(async move {
+                    let result =
+                        tokio::time::timeout(HOOK_TIMEOUT, notify(&cxt, &target, &summary))
+                            .await
+                            .unwrap_or_else(|_| Err("timed out".to_string()));
+                    if let Err(err) = result {
+                        warn!("Hook {target:?} for flow {} failed {err}", summary.id);
+                    }
+                });
+            }
+        }
+        debug!("Hooks finished");
+    })
+}
+
+async fn notify(
+    cxt: &ProxyContext,
+    target: &HookTarget,
+    summary: &FlowSummary,
+) -> Result<(), String> {
+    let body = serde_json::to_vec(summary).map_err(|e| e.to_string())?;
+    match target {
+        HookTarget::Webhook(uri) => post(cxt, uri, body).await,
+        HookTarget::Command(command) => run(command, &body).await,
+    }
+}
+
+/// Sends `body` directly, the webhook request is not recorded as a flow.
+async fn post(cxt: &ProxyContext, uri: &Uri, body: Vec<u8>) -> Result<(), String> {
+    let host = uri.host().ok_or("missing host")?;
+    let authority = uri.authority().ok_or("missing host")?.as_str();
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(uri.clone())
+        .header(HOST, authority)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body)).boxed())
+        .map_err(|e| e.to_string())?;
+    let response = cxt
+        .client_builder(host)
+        .build()
+        .request(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.parts.status.is_success() {
+        return Err(format!("status {}", response.parts.status));
+    }
+    Ok(())
+}
+
+async fn run(command: &str, body: &[u8]) -> Result<(), String> {
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(body).await.map_err(|e| e.to_string())?;
+    }
+    let status = child.wait().await.map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("exited with {status}"));
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use crate::flow::test_flow;
+
+    use super::*;
+
+    fn flow(status: StatusCode) -> Flow {
+        let mut flow = test_flow(
+            7,
+            Method::POST,
+            "https://api.example.com/orders",
+            status,
+            b"oops",
+        );
+        flow.request.as_mut().unwrap().body = Bytes::from_static(b"{}");
+        flow.client_connection.addr = ([127, 0, 0, 1], 5000).into();
+        flow
+    }
+
+    #[test]
+    fn notifies_matching_rules() {
+        let hooks = Hooks::new(vec![
+            HookRule::new(
+                FlowFilter::parse("~c 500").unwrap(),
+                HookTarget::Command("cat".to_string()),
+            ),
+            HookRule::new(
+                FlowFilter::parse("~m GET").unwrap(),
+                HookTarget::Command("true".to_string()),
+            ),
+        ]);
+
+        let notifications = hooks.notifications(&flow(StatusCode::INTERNAL_SERVER_ERROR));
+        assert_eq!(notifications.len(), 1);
+        let (target, summary) = &notifications[0];
+        assert_eq!(target, &HookTarget::Command("cat".to_string()));
+        assert_eq!(summary.filter.as_deref(), Some("~c 500"));
+        assert_eq!(summary.status, Some(500));
+        assert_eq!(summary.request_bytes, 2);
+        assert_eq!(summary.response_bytes, 4);
+
+        assert!(hooks.notifications(&flow(StatusCode::OK)).is_empty());
+        hooks.set_rules(vec![]);
+        assert!(
+            hooks
+                .notifications(&flow(StatusCode::INTERNAL_SERVER_ERROR))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn summary_json() {
+        let json = serde_json::to_value(FlowSummary::new(&flow(StatusCode::OK))).unwrap();
+        assert_eq!(json["id"], 7);
+        assert!(json.get("filter").is_none());
+        assert_eq!(json["method"], "POST");
+        assert_eq!(json["url"], "https://api.example.com/orders");
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["error"], serde_json::Value::Null);
+        assert_eq!(json["client"], "127.0.0.1:5000");
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn command_gets_summary_on_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("summary.json");
+        let command = format!("cat > {}", out.display());
+        run(&command, b"{\"id\":1}").await.unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "{\"id\":1}");
+
+        assert!(run("exit 3", b"").await.is_err());
+    }
+}
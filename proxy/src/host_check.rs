@@ -0,0 +1,125 @@
+//! Requests naming another host than the connection they came over was
+//! opened to, as with domain fronting: a tunnel to, or a TLS handshake for,
+//! one host carrying a `Host` header for another. The host filter's
+//! [`HostMismatch`] policy decides what happens to them.
+
+use std::net::IpAddr;
+
+use cow_utils::CowUtils;
+use http::{HeaderMap, Uri, header::HOST, request::Parts};
+
+use crate::{filter::HostMismatch, proxy::FlowContext};
+
+/// Tag of flows whose request named another host than its connection.
+pub(crate) const HOST_MISMATCH_TAG: &str = "host-mismatch";
+
+/// The policy and reason for a request on `flow_cxt` that names another host
+/// than its connection, none when it matches or the policy is to allow it.
+pub(crate) fn check_host(flow_cxt: &FlowContext, parts: &Parts) -> Option<(HostMismatch, String)> {
+    let policy = flow_cxt.proxy_cxt.host_filter.host_mismatch();
+    if policy == HostMismatch::Allow || flow_cxt.reverse {
+        return None;
+    }
+    // Absolute targets are the request's own, others the tunnel's
+    let target = flow_cxt.target_uri.inner();
+    let tunnel = target.scheme().is_none().then(|| target.host()).flatten();
+    let sni = flow_cxt
+        .certs
+        .client_tls
+        .as_ref()
+        .and_then(|tls| tls.sni.as_deref());
+    host_mismatch(&parts.headers, &parts.uri, tunnel, sni).map(|reason| (policy, reason))
+}
+
+/// Why the host `headers` or `uri` name is not the `sni` or `tunnel` one.
+/// IP addresses name no host and are not compared.
+fn host_mismatch(
+    headers: &HeaderMap,
+    uri: &Uri,
+    tunnel: Option<&str>,
+    sni: Option<&str>,
+) -> Option<String> {
+    let requested = match headers.get(HOST) {
+        Some(host) => host
+            .to_str()
+            .ok()
+            .and_then(|host| host.parse::<Uri>().ok())
+            .and_then(|host| host.host().map(normalize)),
+        None => uri.host().map(normalize),
+    }
+    .filter(|host| is_name(host))?;
+    [("TLS SNI", sni), ("tunnel target", tunnel)]
+        .into_iter()
+        .find_map(|(what, host)| {
+            let host = normalize(host?);
+            (is_name(&host) && host != requested)
+                .then(|| format!("Host {requested} does not match the {what} {host}"))
+        })
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.')
+        .cow_to_ascii_lowercase()
+        .into_owned()
+}
+
+fn is_name(host: &str) -> bool {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .is_err()
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn headers(host: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(HOST, HeaderValue::from_static(host));
+        headers
+    }
+
+    #[test]
+    fn matches_ignoring_case_ports_and_dots() {
+        let uri = Uri::from_static("/");
+        let sni = Some("api.example.com");
+        assert!(host_mismatch(&headers("API.example.com:443"), &uri, sni, sni).is_none());
+        assert!(host_mismatch(&headers("api.example.com."), &uri, None, sni).is_none());
+        assert!(host_mismatch(&HeaderMap::new(), &uri, None, sni).is_none());
+    }
+
+    #[test]
+    fn finds_fronted_hosts() {
+        let uri = Uri::from_static("/");
+        let reason = host_mismatch(
+            &headers("hidden.example.net"),
+            &uri,
+            Some("cdn.example.com"),
+            Some("cdn.example.com"),
+        )
+        .unwrap();
+        assert_eq!(
+            reason,
+            "Host hidden.example.net does not match the TLS SNI cdn.example.com"
+        );
+
+        // HTTP/2 names the host in the :authority of the target
+        let uri = Uri::from_static("https://hidden.example.net/");
+        let reason = host_mismatch(&HeaderMap::new(), &uri, Some("cdn.example.com"), None);
+        assert_eq!(
+            reason.unwrap(),
+            "Host hidden.example.net does not match the tunnel target cdn.example.com"
+        );
+    }
+
+    #[test]
+    fn ignores_addresses() {
+        let uri = Uri::from_static("/");
+        assert!(host_mismatch(&headers("10.0.0.1:8080"), &uri, Some("a.com"), None).is_none());
+        assert!(host_mismatch(&headers("a.com"), &uri, Some("[::1]"), None).is_none());
+    }
+}
@@ -1,33 +1,47 @@
 use std::convert::Infallible;
+use std::time::Instant;
 
 use bytes::Bytes;
 use http::StatusCode;
-use http::header::CONTENT_TYPE;
+use http::header::{CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, HOST};
 use http::uri::Scheme;
+use http::{HeaderValue, Uri, Version};
 use http_body_util::BodyExt;
 use http_body_util::Full;
 use http_body_util::combinators::BoxBody;
 use hyper::body::Incoming;
 use hyper::service::service_fn;
 use hyper::{Request, Response};
-use hyper_util::rt::TokioExecutor;
 use hyper_util::rt::TokioIo;
 use roxy_shared::alpn::AlpnProtocol;
-use roxy_shared::client::ClientContext;
+use roxy_shared::body::{BytesBody, ChannelBody, PassBody, PassEnd};
 use roxy_shared::content::ContentType;
-use roxy_shared::http::HttpError;
+use roxy_shared::http::{HttpError, HttpStreamResponse, collect_body};
+use roxy_shared::uri::RUri;
+use roxy_shared::wire::WireCapture;
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
 use tracing::debug;
 use tracing::trace;
+use tracing::warn;
 
-type H1ServerBuilder = hyper::server::conn::http1::Builder;
-type H2ServerBuilder<TokioIo> = hyper::server::conn::http2::Builder<TokioIo>;
-
+use crate::cache::CacheMode;
+use crate::error_page::error_page;
+use crate::filter::HostMismatch;
 use crate::flow::FlowEvent;
 use crate::flow::FlowEventEmitter;
+use crate::flow::FlowStore;
+use crate::flow::FlowTls;
 use crate::flow::InterceptedRequest;
 use crate::flow::InterceptedResponse;
+use crate::flow::WsDirection;
+use crate::flow::error::{FlowError, FlowErrorKind};
+use crate::flow::id::FlowId;
+use crate::head_recorder::{HeadRecorder, RecordedHeads};
+use crate::host_check::{HOST_MISMATCH_TAG, check_host};
+use crate::limits::{Deadline, Slot, Slots};
 use crate::proxy::FlowContext;
+use crate::version::VersionChoice;
 
 pub(crate) async fn handle_http(
     flow_cxt: FlowContext,
@@ -36,6 +50,36 @@ pub(crate) async fn handle_http(
     proxy(flow_cxt, AlpnProtocol::None, Scheme::HTTP, client_request).await
 }
 
+pub(crate) async fn handle_http_stream<S>(
+    flow_cxt: FlowContext,
+    client_stream: S,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    trace!("Spawning HTTP client connection handler");
+    let (client_stream, heads) = HeadRecorder::new(client_stream);
+    let served = flow_cxt
+        .proxy_cxt
+        .limits
+        .limits()
+        .h1_server()
+        .keep_alive(true)
+        .serve_connection(
+            TokioIo::new(client_stream),
+            service_fn(|mut req| {
+                heads.attach(&mut req, flow_cxt.proxy_cxt.flow_store.raw_capture());
+                proxy(flow_cxt.clone(), AlpnProtocol::None, Scheme::HTTP, req)
+            }),
+        )
+        .await;
+    if served.is_err() {
+        record_folded(&flow_cxt, Scheme::HTTP, &heads).await;
+    }
+    served?;
+    Ok(())
+}
+
 pub(crate) async fn handle_https<S>(
     flow_cxt: FlowContext,
     client_stream: S,
@@ -44,14 +88,25 @@ where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     trace!("Spawning HS client connection handler");
-    H1ServerBuilder::new()
-        .title_case_headers(true)
+    let (client_stream, heads) = HeadRecorder::new(client_stream);
+    let served = flow_cxt
+        .proxy_cxt
+        .limits
+        .limits()
+        .h1_server()
         .keep_alive(true)
         .serve_connection(
             TokioIo::new(client_stream),
-            service_fn(|req| proxy(flow_cxt.clone(), AlpnProtocol::Http1, Scheme::HTTPS, req)),
+            service_fn(|mut req| {
+                heads.attach(&mut req, flow_cxt.proxy_cxt.flow_store.raw_capture());
+                proxy(flow_cxt.clone(), AlpnProtocol::Http1, Scheme::HTTPS, req)
+            }),
         )
-        .await?;
+        .await;
+    if served.is_err() {
+        record_folded(&flow_cxt, Scheme::HTTPS, &heads).await;
+    }
+    served?;
     Ok(())
 }
 
@@ -63,50 +118,254 @@ where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     trace!("Spawning H2 client connection handler");
-    H2ServerBuilder::new(TokioExecutor::new())
+    let limits = flow_cxt.proxy_cxt.limits.limits();
+    let streams = Slots::default();
+    limits
+        .h2_server()
         .serve_connection(
             TokioIo::new(client_stream),
-            service_fn(|req| proxy(flow_cxt.clone(), AlpnProtocol::Http2, Scheme::HTTPS, req)),
+            service_fn(|req| {
+                capped(
+                    streams.clone(),
+                    limits.max_streams_per_connection,
+                    proxy(flow_cxt.clone(), AlpnProtocol::Http2, Scheme::HTTPS, req),
+                )
+            }),
         )
         .await?;
     Ok(())
 }
 
+/// Serves HTTP/2 without TLS to clients that start with the connection
+/// preface, knowing the server speaks it.
+pub(crate) async fn handle_h2c<S>(
+    flow_cxt: FlowContext,
+    client_stream: S,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    trace!("Spawning H2C client connection handler");
+    let limits = flow_cxt.proxy_cxt.limits.limits();
+    let streams = Slots::default();
+    limits
+        .h2_server()
+        .serve_connection(
+            TokioIo::new(client_stream),
+            service_fn(|req| {
+                capped(
+                    streams.clone(),
+                    limits.max_streams_per_connection,
+                    proxy(flow_cxt.clone(), AlpnProtocol::None, Scheme::HTTP, req),
+                )
+            }),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Runs `handle` for a request on a connection whose requests in flight are
+/// counted by `streams`, answering `503` instead once `max` are.
+pub(crate) async fn capped<F>(
+    streams: Slots,
+    max: Option<usize>,
+    handle: F,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError>
+where
+    F: Future<Output = Result<Response<BoxBody<Bytes, Infallible>>, HttpError>>,
+{
+    let Some(_slot) = streams.take(max) else {
+        debug!("Too many requests in flight on one connection");
+        return error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many requests in flight on this connection".to_string(),
+        );
+    };
+    handle.await
+}
+
+/// A slot for sending `intercepted` upstream, `None` with an error recorded on
+/// the flow when too many requests to its host are in flight.
+pub(crate) fn host_slot(
+    flow_cxt: &FlowContext,
+    flow_id: FlowId,
+    intercepted: &InterceptedRequest,
+) -> Option<Slot> {
+    let host = intercepted.uri.host();
+    let slot = flow_cxt.proxy_cxt.limits.host_slot(host);
+    if slot.is_none() {
+        let error = FlowError::new(
+            FlowErrorKind::Rejected,
+            format!("Too many requests in flight to {host}"),
+        );
+        flow_cxt
+            .proxy_cxt
+            .flow_store
+            .post_event(flow_id, FlowEvent::Error(error));
+    }
+    slot
+}
+
+/// The answer to a request [`host_slot`] turned away.
+fn host_busy() -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
+    error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Too many requests in flight to this host".to_string(),
+    )
+}
+
 async fn proxy(
     flow_cxt: FlowContext,
     alpn: AlpnProtocol,
     scheme: Scheme,
     req: Request<Incoming>,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
+    let version = req.version();
+    let mut resp = proxy_flow(flow_cxt, alpn, scheme, req).await?;
+    // Whatever the server's version, hyper answers an HTTP/1.0 request as
+    // HTTP/1.0 and keeps the client connection open only when asked to
+    if version < Version::HTTP_2 {
+        *resp.version_mut() = Version::HTTP_11;
+    }
+    Ok(resp)
+}
+
+async fn proxy_flow(
+    flow_cxt: FlowContext,
+    alpn: AlpnProtocol,
+    scheme: Scheme,
+    req: Request<Incoming>,
 ) -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
     debug!("Proxy {:?}", flow_cxt.target_uri);
-    let (parts, body) = req.into_parts();
-    let body = body.collect().await?;
-    let trailers = body.trailers().cloned();
-    let body_bytes = body.to_bytes();
+    let limits = flow_cxt.proxy_cxt.limits.limits();
+    let mut deadline = Deadline::new(limits.flow_timeout);
+    let (mut parts, body) = req.into_parts();
+    let client_wire = parts.extensions.get::<WireCapture>().cloned();
 
-    let uri = match flow_cxt.target_uri.and(&parts.uri, scheme) {
+    let uri = if flow_cxt.reverse {
+        reverse_uri(&flow_cxt.target_uri, &mut parts)
+    } else {
+        flow_cxt.target_uri.and(&parts.uri, scheme)
+    };
+    let uri = match uri {
         Ok(uri) => uri,
         Err(_) => return down_stream_error(HttpError::BadHost),
     };
 
+    let mismatch = check_host(&flow_cxt, &parts);
+    if let Some((policy, reason)) = &mismatch {
+        warn!("{reason} ({policy})");
+    }
+    let blocked = matches!(mismatch, Some((HostMismatch::Block, _)));
+
+    if !blocked
+        && let Some(mut intercepted) = unbuffered_request(&flow_cxt, alpn, &uri, &parts).await
+    {
+        if mismatch.is_some() {
+            intercepted.tags.push(HOST_MISMATCH_TAG.to_string());
+        }
+        return forward_unbuffered(&flow_cxt, intercepted, &parts, body, &mut deadline).await;
+    }
+
+    let (body_bytes, trailers, failure) = match mismatch.as_ref() {
+        Some((HostMismatch::Block, reason)) => (
+            Bytes::new(),
+            None,
+            Some((
+                StatusCode::MISDIRECTED_REQUEST,
+                FlowError::new(FlowErrorKind::Rejected, reason.clone()),
+            )),
+        ),
+        _ => match deadline.run(collect_body(body, limits.max_body_size)).await {
+            Ok(Ok(body)) => {
+                let trailers = body.trailers().cloned();
+                (body.to_bytes(), trailers, None)
+            }
+            Ok(Err(HttpError::BodyTooLarge(max))) => (
+                Bytes::new(),
+                None,
+                Some((
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    FlowError::new(
+                        FlowErrorKind::Rejected,
+                        format!("Request body larger than {max} bytes"),
+                    ),
+                )),
+            ),
+            Ok(Err(err)) => return Err(err),
+            Err(_) => (
+                Bytes::new(),
+                None,
+                Some((
+                    StatusCode::REQUEST_TIMEOUT,
+                    FlowError::new(
+                        FlowErrorKind::Timeout,
+                        "Flow timed out reading the request body",
+                    ),
+                )),
+            ),
+        },
+    };
+
     let mut intercepted = InterceptedRequest::from_http(uri, alpn, parts, body_bytes, trailers);
+    if mismatch.is_some() {
+        intercepted.tags.push(HOST_MISMATCH_TAG.to_string());
+    }
+    intercepted.tls = flow_cxt
+        .certs
+        .client_tls
+        .as_ref()
+        .map(|tls| FlowTls::from_client(tls, flow_cxt.certs.client_verification.as_ref()));
+    let flow_id = flow_cxt.proxy_cxt.flow_store.next_flow_id();
+    if let Some((status, error)) = failure {
+        return reject(&flow_cxt, flow_id, intercepted, status, error).await;
+    }
+    flow_cxt.proxy_cxt.rewrites.apply(&mut intercepted);
 
-    let response = match flow_cxt
+    let intercept = flow_cxt
         .proxy_cxt
         .script_engine
-        .intercept_request(&mut intercepted)
-        .await
-    {
-        Ok(resp) => resp,
-        Err(err) => return internal_error(format!("Intercept request error: {err}")),
+        .intercept_request(flow_id, &mut intercepted);
+    let response = match deadline.run(intercept).await {
+        Ok(Ok(resp)) => resp,
+        Ok(Err(err)) => {
+            let error = FlowError::new(
+                FlowErrorKind::Script,
+                format!("Intercept request error: {err}"),
+            );
+            return reject(
+                &flow_cxt,
+                flow_id,
+                intercepted,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                error,
+            )
+            .await;
+        }
+        Err(_) => {
+            let error = FlowError::new(FlowErrorKind::Timeout, "Flow timed out in request scripts");
+            return reject(
+                &flow_cxt,
+                flow_id,
+                intercepted,
+                StatusCode::GATEWAY_TIMEOUT,
+                error,
+            )
+            .await;
+        }
     };
 
-    let down_stream_req = intercepted.request()?;
-    let flow_id = flow_cxt
+    flow_cxt
         .proxy_cxt
         .flow_store
-        .new_flow_cxt(&flow_cxt, intercepted.clone())
+        .new_flow_cxt(&flow_cxt, flow_id, intercepted.clone())
         .await;
+    post_wire(
+        &flow_cxt.proxy_cxt.flow_store,
+        flow_id,
+        WsDirection::Client,
+        client_wire,
+    );
 
     if let Some(response) = response {
         let resp = response.response()?;
@@ -117,28 +376,181 @@ async fn proxy(
         return Ok(resp);
     }
 
+    let held = Instant::now();
+    if !flow_cxt
+        .proxy_cxt
+        .breakpoints
+        .hold(&flow_cxt.proxy_cxt.flow_store, flow_id, &mut intercepted)
+        .await
+    {
+        return dropped_response();
+    }
+    deadline.extend(held.elapsed());
+    flow_cxt
+        .proxy_cxt
+        .flow_store
+        .post_event(flow_id, FlowEvent::RequestComplete);
+
+    let Some(_host_slot) = host_slot(&flow_cxt, flow_id, &intercepted) else {
+        return host_busy();
+    };
+    match deadline
+        .run(forward(&flow_cxt, flow_id, &intercepted))
+        .await
+    {
+        Ok(resp) => resp,
+        Err(_) => {
+            let error = FlowError::new(
+                FlowErrorKind::Timeout,
+                "Flow timed out waiting for the response",
+            );
+            upstream_failure(&flow_cxt.proxy_cxt.flow_store, flow_id, &intercepted, error)
+        }
+    }
+}
+
+/// Sends `intercepted` on to the server and the response back to the client.
+async fn forward(
+    flow_cxt: &FlowContext,
+    flow_id: FlowId,
+    intercepted: &InterceptedRequest,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
     let emitter = FlowEventEmitter::new(flow_id, flow_cxt.proxy_cxt.flow_store.clone());
+    let upstream_tls = emitter.upstream_tls();
+    let versions = &flow_cxt.proxy_cxt.versions;
+    let negotiated = versions.negotiate(&intercepted.uri, intercepted.version.0);
+    let mut builder = flow_cxt
+        .proxy_cxt
+        .client_builder(intercepted.uri.host())
+        .with_emitter(Box::new(emitter));
+    if let Some(alpns) = negotiated.alpns.clone() {
+        builder = builder.with_alpns(alpns);
+    }
+    let client = builder.build();
 
-    let client = ClientContext::builder()
-        .with_roxy_ca(flow_cxt.proxy_cxt.ca.clone())
-        .with_tls_config(flow_cxt.proxy_cxt.tls_config.clone())
-        .with_emitter(Box::new(emitter))
-        .build();
+    let retry = flow_cxt.proxy_cxt.retries.policy();
 
-    let res = match client.request(down_stream_req).await {
-        Ok(res) => res,
-        Err(e) => return down_stream_error(e),
+    let mut event_stream = None;
+    let event_stream_slot = &mut event_stream;
+    let raw_capture = flow_cxt.proxy_cxt.flow_store.raw_capture();
+    let mut server_wire = None;
+    let server_wire_slot = &mut server_wire;
+    let mut alt_svc = negotiated.alt_svc;
+    let alt_svc_slot = &mut alt_svc;
+    let version = negotiated.version;
+    let request = intercepted;
+    let fetched = flow_cxt
+        .proxy_cxt
+        .cache
+        .fetch(intercepted, || async move {
+            let mut attempt = 0;
+            let mut version = version;
+            loop {
+                let mut upstream = upstream_request(request, version)?;
+                if raw_capture {
+                    upstream.extensions_mut().insert(WireCapture::default());
+                }
+                let error = match client.request_stream(upstream).await {
+                    Ok(HttpStreamResponse::Buffered(res)) => {
+                        *server_wire_slot = res.parts.extensions.get::<WireCapture>().cloned();
+                        return Ok(InterceptedResponse::from_http(
+                            res.parts,
+                            res.body,
+                            res.trailers,
+                        ));
+                    }
+                    Ok(
+                        HttpStreamResponse::EventStream(parts, body)
+                        | HttpStreamResponse::Streaming(parts, body),
+                    ) => {
+                        *server_wire_slot = parts.extensions.get::<WireCapture>().cloned();
+                        *event_stream_slot = Some(body);
+                        return Ok(InterceptedResponse::from_http(parts, Bytes::new(), None));
+                    }
+                    Err(err) => err,
+                };
+                if *alt_svc_slot {
+                    // The origin is tried once before counting a retry
+                    debug!("HTTP/3 to {} failed, falling back {error}", request.uri);
+                    versions.forget_alt_svc(&request.uri);
+                    *alt_svc_slot = false;
+                    version = request.version.0;
+                    continue;
+                }
+                let Some(delay) = retry.delay(&request.method, &FlowError::from(&error), attempt)
+                else {
+                    return Err(error);
+                };
+                attempt += 1;
+                debug!("Retrying {} after {error}, attempt {attempt}", request.uri);
+                tokio::time::sleep(delay).await;
+            }
+        })
+        .await;
+    let mut intercepted_resp = match fetched {
+        Ok(Some(resp)) => resp,
+        Ok(None) => {
+            flow_cxt.proxy_cxt.flow_store.post_event(
+                flow_id,
+                FlowEvent::Error(FlowError::new(
+                    FlowErrorKind::Rejected,
+                    "Not in offline cache",
+                )),
+            );
+            return offline_miss();
+        }
+        Err(e) => {
+            return upstream_failure(
+                &flow_cxt.proxy_cxt.flow_store,
+                flow_id,
+                intercepted,
+                FlowError::from(&e),
+            );
+        }
     };
-
-    let mut intercepted_resp = InterceptedResponse::from_http(res.parts, res.body, res.trailers);
+    post_wire(
+        &flow_cxt.proxy_cxt.flow_store,
+        flow_id,
+        WsDirection::Server,
+        server_wire,
+    );
+    intercepted_resp.tls = upstream_tls.get();
+    intercepted_resp.tags = intercepted.tags.clone();
+    versions.learn_alt_svc(&intercepted.uri, &intercepted_resp.headers);
+    flow_cxt.proxy_cxt.flow_store.post_event(
+        flow_id,
+        FlowEvent::Version(VersionChoice {
+            preference: negotiated.preference,
+            requested: intercepted.version,
+            chosen: intercepted_resp.version,
+            alt_svc,
+        }),
+    );
 
     if let Err(err) = flow_cxt
         .proxy_cxt
         .script_engine
-        .intercept_response(&intercepted, &mut intercepted_resp)
+        .intercept_response(flow_id, intercepted, &mut intercepted_resp)
         .await
     {
-        return internal_error(format!("Intercept response error: {err}"));
+        let error = FlowError::new(
+            FlowErrorKind::Script,
+            format!("Intercept response error: {err}"),
+        );
+        let resp = internal_error(error.message.clone());
+        flow_cxt
+            .proxy_cxt
+            .flow_store
+            .post_event(flow_id, FlowEvent::Error(error));
+        return resp;
+    }
+
+    if let Some(body) = event_stream {
+        // The head is posted first so no chunk arrives before it
+        let builder = intercepted_resp.response_builder();
+        let flow_store = flow_cxt.proxy_cxt.flow_store.clone();
+        flow_store.post_event(flow_id, FlowEvent::ResponseHead(intercepted_resp));
+        return Ok(builder.body(stream_body(flow_store, flow_id, body))?);
     }
 
     let resp = intercepted_resp.response()?;
@@ -149,6 +561,301 @@ async fn proxy(
     Ok(resp)
 }
 
+/// `intercepted` as sent to the server with `version`, HTTP/1.0 servers are
+/// asked to keep the connection open for the pool.
+pub(crate) fn upstream_request(
+    intercepted: &InterceptedRequest,
+    version: Version,
+) -> Result<Request<BytesBody>, http::Error> {
+    let mut request = intercepted.request()?;
+    upstream_head(intercepted, version, &mut request);
+    Ok(request)
+}
+
+fn upstream_head<B>(intercepted: &InterceptedRequest, version: Version, request: &mut Request<B>) {
+    *request.version_mut() = version;
+    if version == Version::HTTP_10 {
+        request
+            .headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("keep-alive"));
+    }
+    // HTTP/2 and HTTP/3 clients name the host in the request line instead
+    if intercepted.version.0 >= Version::HTTP_2
+        && version < Version::HTTP_2
+        && !request.headers().contains_key(HOST)
+        && let Ok(host) = HeaderValue::from_str(&intercepted.uri.host_port())
+    {
+        request.headers_mut().insert(HOST, host);
+    }
+}
+
+/// The head of a request whose bodies can be passed straight through: its
+/// host is one whose bodies are not recorded, and no script, cache or
+/// breakpoint needs to see them. `None` sends the flow down the buffered path.
+async fn unbuffered_request(
+    flow_cxt: &FlowContext,
+    alpn: AlpnProtocol,
+    uri: &RUri,
+    parts: &http::request::Parts,
+) -> Option<InterceptedRequest> {
+    let proxy_cxt = &flow_cxt.proxy_cxt;
+    if !proxy_cxt.host_filter.skip_body(uri.host())
+        || proxy_cxt.cache.mode() != CacheMode::Off
+        || proxy_cxt.script_engine.has_enabled_scripts().await
+    {
+        return None;
+    }
+    let mut intercepted =
+        InterceptedRequest::from_http(uri.clone(), alpn, parts.clone(), Bytes::new(), None);
+    proxy_cxt.rewrites.apply(&mut intercepted);
+    if proxy_cxt.breakpoints.matches(&intercepted) {
+        return None;
+    }
+    intercepted.tls = flow_cxt
+        .certs
+        .client_tls
+        .as_ref()
+        .map(|tls| FlowTls::from_client(tls, flow_cxt.certs.client_verification.as_ref()));
+    Some(intercepted)
+}
+
+/// Sends `intercepted` on with the client's `body` and returns the response
+/// with the server's, each passed on frame by frame as it arrives. Only the
+/// heads are recorded. A body can not be sent twice, so nothing is retried.
+async fn forward_unbuffered(
+    flow_cxt: &FlowContext,
+    intercepted: InterceptedRequest,
+    parts: &http::request::Parts,
+    body: Incoming,
+    deadline: &mut Deadline,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
+    let flow_store = flow_cxt.proxy_cxt.flow_store.clone();
+    let flow_id = flow_store.next_flow_id();
+    flow_store
+        .new_flow_cxt(flow_cxt, flow_id, intercepted.clone())
+        .await;
+    post_wire(
+        &flow_store,
+        flow_id,
+        WsDirection::Client,
+        parts.extensions.get::<WireCapture>().cloned(),
+    );
+    let Some(host_slot) = host_slot(flow_cxt, flow_id, &intercepted) else {
+        return host_busy();
+    };
+
+    let emitter = FlowEventEmitter::new(flow_id, flow_store.clone());
+    let upstream_tls = emitter.upstream_tls();
+    let versions = &flow_cxt.proxy_cxt.versions;
+    let negotiated = versions.negotiate(&intercepted.uri, intercepted.version.0);
+    let mut builder = flow_cxt
+        .proxy_cxt
+        .client_builder(intercepted.uri.host())
+        .with_emitter(Box::new(emitter));
+    if let Some(alpns) = negotiated.alpns.clone() {
+        builder = builder.with_alpns(alpns);
+    }
+    let client = builder.build();
+
+    let request_store = flow_store.clone();
+    let mut request = intercepted
+        .request_builder()
+        .body(PassBody::new(body, move |end| {
+            if let PassEnd::Complete(_) = end {
+                request_store.post_event(flow_id, FlowEvent::RequestComplete);
+            }
+        }))?;
+    upstream_head(&intercepted, negotiated.version, &mut request);
+    if flow_store.raw_capture() {
+        request.extensions_mut().insert(WireCapture::default());
+    }
+    // The body goes on as the client sent it, so its length still holds
+    if let Some(length) = parts.headers.get(CONTENT_LENGTH) {
+        request.headers_mut().insert(CONTENT_LENGTH, length.clone());
+    }
+
+    let response = match deadline.run(client.request_streaming(request)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(err)) => {
+            return upstream_failure(&flow_store, flow_id, &intercepted, FlowError::from(&err));
+        }
+        Err(_) => {
+            let error = FlowError::new(
+                FlowErrorKind::Timeout,
+                "Flow timed out waiting for the response",
+            );
+            return upstream_failure(&flow_store, flow_id, &intercepted, error);
+        }
+    };
+    let (parts, body) = response.into_parts();
+    post_wire(
+        &flow_store,
+        flow_id,
+        WsDirection::Server,
+        parts.extensions.get::<WireCapture>().cloned(),
+    );
+    let length = parts.headers.get(CONTENT_LENGTH).cloned();
+    let mut head = InterceptedResponse::from_http(parts, Bytes::new(), None);
+    head.tls = upstream_tls.get();
+    versions.learn_alt_svc(&intercepted.uri, &head.headers);
+    flow_store.post_event(
+        flow_id,
+        FlowEvent::Version(VersionChoice {
+            preference: negotiated.preference,
+            requested: intercepted.version,
+            chosen: head.version,
+            alt_svc: negotiated.alt_svc,
+        }),
+    );
+
+    let mut builder = head.response_builder();
+    if let Some(length) = length {
+        builder = builder.header(CONTENT_LENGTH, length);
+    }
+    // The head is posted first so the end does not arrive before it
+    flow_store.post_event(flow_id, FlowEvent::ResponseHead(head));
+    let body = PassBody::new(body, move |end| {
+        let trailers = match end {
+            PassEnd::Complete(trailers) => trailers,
+            PassEnd::Failed(err) => {
+                let error = FlowError::new(FlowErrorKind::Protocol, format!("Stream error {err}"));
+                flow_store.post_event(flow_id, FlowEvent::Error(error));
+                None
+            }
+        };
+        flow_store.post_event(flow_id, FlowEvent::ResponseEnd(trailers));
+        // The host's slot is held until the body has been passed on
+        drop(host_slot);
+    });
+    Ok(builder.body(body)?)
+}
+
+/// Records the request hyper refused for folding a header over several lines,
+/// which would otherwise leave no flow behind. The client was answered `400`.
+pub(crate) async fn record_folded(flow_cxt: &FlowContext, scheme: Scheme, heads: &RecordedHeads) {
+    let Some((method, uri, raw)) = heads.folded() else {
+        return;
+    };
+    debug!("Refused {method} {uri} for obsolete line folding");
+    let uri = flow_cxt
+        .target_uri
+        .and(&uri, scheme)
+        .unwrap_or_else(|_| RUri::new(uri));
+    let intercepted = InterceptedRequest {
+        uri,
+        method,
+        headers: raw.header_map(),
+        raw_headers: Some(raw),
+        ..Default::default()
+    };
+    let flow_store = &flow_cxt.proxy_cxt.flow_store;
+    let flow_id = flow_store.next_flow_id();
+    flow_store
+        .new_flow_cxt(flow_cxt, flow_id, intercepted)
+        .await;
+    let error = FlowError::new(
+        FlowErrorKind::Rejected,
+        "Obsolete line folding in the request headers",
+    );
+    flow_store.post_event(flow_id, FlowEvent::Error(error));
+}
+
+/// Answers the client with an [`error_page`] describing why no response came
+/// from the server, recorded on the flow along with `error`.
+fn upstream_failure(
+    flow_store: &FlowStore,
+    flow_id: FlowId,
+    intercepted: &InterceptedRequest,
+    error: FlowError,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
+    let page = error_page(flow_id, intercepted, &error);
+    let resp = page.response()?;
+    flow_store.post_event(flow_id, FlowEvent::Failed(error, page));
+    Ok(resp)
+}
+
+/// Hands the flow where the bytes `from` sent are captured, if they are.
+fn post_wire(
+    flow_store: &FlowStore,
+    flow_id: FlowId,
+    from: WsDirection,
+    capture: Option<WireCapture>,
+) {
+    if let Some(capture) = capture {
+        flow_store.post_event(flow_id, FlowEvent::Wire(from, capture));
+    }
+}
+
+/// Records `intercepted` as a flow that failed with `error`, answered with
+/// `status`.
+async fn reject(
+    flow_cxt: &FlowContext,
+    flow_id: FlowId,
+    intercepted: InterceptedRequest,
+    status: StatusCode,
+    error: FlowError,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
+    let flow_store = &flow_cxt.proxy_cxt.flow_store;
+    flow_store
+        .new_flow_cxt(flow_cxt, flow_id, intercepted)
+        .await;
+    let resp = error_response(status, error.message.clone());
+    flow_store.post_event(flow_id, FlowEvent::Error(error));
+    resp
+}
+
+/// Points a request made to a reverse proxy listener at `target`, keeping only
+/// its path and naming the target in the Host header.
+fn reverse_uri(target: &RUri, parts: &mut http::request::Parts) -> Result<RUri, http::Error> {
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    let path = Uri::builder().path_and_query(path).build()?;
+    if parts.headers.contains_key(HOST)
+        && let Some(authority) = target.inner().authority()
+        && let Ok(host) = HeaderValue::from_str(authority.as_str())
+    {
+        parts.headers.insert(HOST, host);
+    }
+    let scheme = match target.scheme() {
+        roxy_shared::uri::Scheme::Https => Scheme::HTTPS,
+        roxy_shared::uri::Scheme::Http => Scheme::HTTP,
+    };
+    target.and(&path, scheme)
+}
+
+/// Forwards `body` to the client as it arrives, appending each chunk to the flow.
+fn stream_body(flow_store: FlowStore, flow_id: FlowId, mut body: Incoming) -> BytesBody {
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut trailers = None;
+        while let Some(frame) = body.frame().await {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(err) => {
+                    let error =
+                        FlowError::new(FlowErrorKind::Protocol, format!("Stream error {err}"));
+                    flow_store.post_event(flow_id, FlowEvent::Error(error));
+                    break;
+                }
+            };
+            if let Some(data) = frame.data_ref() {
+                flow_store.post_event(flow_id, FlowEvent::ResponseChunk(data.clone()));
+            } else if let Some(frame_trailers) = frame.trailers_ref() {
+                trailers = Some(frame_trailers.clone());
+            }
+            if tx.send(frame).await.is_err() {
+                debug!("Client closed stream {flow_id}");
+                break;
+            }
+        }
+        flow_store.post_event(flow_id, FlowEvent::ResponseEnd(trailers));
+    });
+    ChannelBody::new(rx)
+}
+
 fn internal_error(msg: String) -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
     let body = BoxBody::new(Full::new(Bytes::from(msg)));
     let resp = Response::builder()
@@ -158,25 +865,41 @@ fn internal_error(msg: String) -> Result<Response<BoxBody<Bytes, Infallible>>, H
     Ok(resp)
 }
 
-fn down_stream_error(error: HttpError) -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
-    let body_text = match error {
-        HttpError::Io(error) => format!("Io error {error}"),
-        HttpError::Alpn => "Invalid ALPN".to_string(),
-        HttpError::Hyper(error) => format!("Hyper error {error}"),
-        HttpError::HyperUpgrade => "Hyper failed to upgrade down stream connection".to_string(),
-        HttpError::Http(error) => format!("HTTP error {error}"),
-        HttpError::Uri => "Invalid uri".to_string(),
-        HttpError::InvalidDnsName => "Invalid DNS name".to_string(),
-        HttpError::Timeout => "Down stream timeout".to_string(),
-        HttpError::ProxyConnect => "Proxy Connection failed".to_string(),
-        HttpError::TlsError(error) => format!("TLS failed {error}"),
-        HttpError::BadHost => "Bad host".to_string(),
-    };
+fn dropped_response() -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
+    let body = BoxBody::new(Full::new(Bytes::from("Dropped at breakpoint")));
+    let resp = Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .header(CONTENT_TYPE, ContentType::Text.to_default_str())
+        .body(body)?;
+    Ok(resp)
+}
 
-    let body = BoxBody::new(Full::new(Bytes::from(body_text)));
+fn offline_miss() -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
+    let body = BoxBody::new(Full::new(Bytes::from("Not in offline cache")));
     let resp = Response::builder()
-        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .status(StatusCode::GATEWAY_TIMEOUT)
+        .header(CONTENT_TYPE, ContentType::Text.to_default_str())
+        .body(body)?;
+    Ok(resp)
+}
+
+fn error_response(
+    status: StatusCode,
+    msg: String,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
+    let body = BoxBody::new(Full::new(Bytes::from(msg)));
+    let resp = Response::builder()
+        .status(status)
         .header(CONTENT_TYPE, ContentType::Text.to_default_str())
         .body(body)?;
     Ok(resp)
 }
+
+fn down_stream_error(error: HttpError) -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
+    let status = match error {
+        HttpError::Timeout | HttpError::ConnectTimeout => StatusCode::GATEWAY_TIMEOUT,
+        HttpError::BodyTooLarge(_) => StatusCode::BAD_GATEWAY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    error_response(status, FlowError::from(&error).message)
+}
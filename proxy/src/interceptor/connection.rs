@@ -0,0 +1,68 @@
+use std::{fmt::Display, net::SocketAddr};
+
+use crate::{
+    flow::FlowTls,
+    interceptor::{KEY_ON_CLIENT_CONNECT, KEY_ON_SERVER_CONNECT, KEY_ON_TLS_ESTABLISHED},
+};
+
+/// The points of a client's connection scripts can look at it and turn it
+/// away by returning `false`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionHook {
+    /// The client connected and passed the ACL, what it wants is not known yet.
+    ClientConnect,
+    /// The client asked for a host, before Roxy connects to it.
+    ServerConnect,
+    /// The client finished its TLS handshake with Roxy.
+    TlsEstablished,
+}
+
+impl ConnectionHook {
+    /// The name of the handler scripts define for this hook.
+    pub fn key(&self) -> &'static str {
+        match self {
+            ConnectionHook::ClientConnect => KEY_ON_CLIENT_CONNECT,
+            ConnectionHook::ServerConnect => KEY_ON_SERVER_CONNECT,
+            ConnectionHook::TlsEstablished => KEY_ON_TLS_ESTABLISHED,
+        }
+    }
+}
+
+impl Display for ConnectionHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.key())
+    }
+}
+
+/// What connection hooks see of a client's connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub client_addr: SocketAddr,
+    /// The host the client asked for, `None` on connect.
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// The client's TLS with Roxy, from [`ConnectionHook::TlsEstablished`] on.
+    pub tls: Option<FlowTls>,
+}
+
+impl ConnectionInfo {
+    pub fn new(client_addr: SocketAddr) -> Self {
+        Self {
+            client_addr,
+            host: None,
+            port: None,
+            tls: None,
+        }
+    }
+
+    pub fn with_target(mut self, host: impl Into<String>, port: u16) -> Self {
+        self.host = Some(host.into());
+        self.port = Some(port);
+        self
+    }
+
+    pub fn with_tls(mut self, tls: FlowTls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+}
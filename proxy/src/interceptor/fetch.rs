@@ -0,0 +1,186 @@
+use std::{sync::mpsc, time::Duration};
+
+use bytes::Bytes;
+use cow_utils::CowUtils;
+use http::{Method, Request, header::HOST};
+use http_body_util::{Full, combinators::BoxBody};
+use once_cell::sync::Lazy;
+use roxy_shared::{
+    body::BytesBody, client::ClientContext, http::HttpResponse, version::HttpVersion,
+};
+use tokio::runtime::Runtime;
+use tracing::debug;
+
+use crate::{flow::InterceptedResponse, interceptor::Error};
+
+const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Script handlers are synchronous, so requests made from them run here
+/// instead of on the runtime that is waiting for the handler to return.
+static RUNTIME: Lazy<std::io::Result<Runtime>> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("roxy-fetch")
+        .enable_all()
+        .build()
+});
+
+/// The optional second argument of `fetch` in every engine.
+#[derive(Debug, Default)]
+pub(crate) struct FetchOptions {
+    pub(crate) method: Option<String>,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Option<Bytes>,
+    pub(crate) timeout: Option<Duration>,
+}
+
+/// Sends a request to `url` and blocks until the whole response arrived or
+/// the timeout passed. The request goes straight to the server, it does not
+/// pass through the proxy and its scripts again.
+pub(crate) fn fetch(url: &str, options: FetchOptions) -> Result<InterceptedResponse, Error> {
+    let timeout = options.timeout.unwrap_or(DEFAULT_FETCH_TIMEOUT);
+    let request = build_request(url, options)?;
+    let runtime = RUNTIME
+        .as_ref()
+        .map_err(|e| Error::Other(format!("fetch runtime failed to start: {e}")))?;
+
+    debug!("fetch {} {}", request.method(), request.uri());
+    let (tx, rx) = mpsc::channel();
+    runtime.spawn(async move {
+        let client = ClientContext::builder().build();
+        let result = tokio::time::timeout(timeout, client.request(request)).await;
+        let _ = tx.send(result);
+    });
+
+    match rx.recv() {
+        Ok(Ok(Ok(response))) => Ok(into_intercepted(response)),
+        Ok(Ok(Err(err))) => Err(Error::Other(format!("fetch {url} failed: {err}"))),
+        Ok(Err(_)) => Err(Error::Other(format!(
+            "fetch {url} timed out after {}ms",
+            timeout.as_millis()
+        ))),
+        Err(_) => Err(Error::Other(format!("fetch {url} was cancelled"))),
+    }
+}
+
+fn build_request(url: &str, options: FetchOptions) -> Result<Request<BytesBody>, Error> {
+    let method = match options.method {
+        Some(method) => Method::from_bytes(method.cow_to_ascii_uppercase().as_bytes())
+            .map_err(|e| Error::Other(format!("invalid fetch method {method}: {e}")))?,
+        None => Method::GET,
+    };
+    let uri: http::Uri = url
+        .parse()
+        .map_err(|e| Error::Other(format!("invalid fetch url {url}: {e}")))?;
+    if uri.host().is_none() {
+        return Err(Error::Other(format!("fetch url {url} has no host")));
+    }
+
+    let mut builder = Request::builder().method(method);
+    let has_host = options
+        .headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case(HOST.as_str()));
+    if !has_host && let Some(authority) = uri.authority() {
+        builder = builder.header(HOST, authority.as_str());
+    }
+    for (name, value) in options.headers {
+        builder = builder.header(name, value);
+    }
+    builder
+        .uri(uri)
+        .body(BoxBody::new(Full::new(options.body.unwrap_or_default())))
+        .map_err(|e| Error::Other(format!("invalid fetch request: {e}")))
+}
+
+fn into_intercepted(response: HttpResponse) -> InterceptedResponse {
+    InterceptedResponse {
+        status: response.parts.status,
+        version: HttpVersion(response.parts.version),
+        headers: response.parts.headers,
+        body: response.body,
+        trailers: response.trailers,
+        ..Default::default()
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Answers one request with `response` and hands back what was received.
+    pub(crate) fn serve_once(response: &'static str) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = vec![];
+            let mut buf = [0; 1024];
+            while !String::from_utf8_lossy(&received).contains("\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                received.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            tx.send(String::from_utf8_lossy(&received).to_string())
+                .unwrap();
+        });
+        (url, rx)
+    }
+
+    #[test]
+    fn fetch_response() {
+        let (url, rx) =
+            serve_once("HTTP/1.1 201 Created\r\nx-test: yes\r\ncontent-length: 2\r\n\r\nok");
+        let response = fetch(
+            &url,
+            FetchOptions {
+                method: Some("post".to_string()),
+                headers: vec![("x-token".to_string(), "abc".to_string())],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.headers.get("x-test").unwrap(), "yes");
+        assert_eq!(response.body, "ok");
+
+        let received = rx.recv().unwrap().cow_to_ascii_lowercase().into_owned();
+        assert!(received.starts_with("post "));
+        assert!(received.contains("x-token: abc"));
+        assert!(received.contains("host: 127.0.0.1"));
+    }
+
+    #[test]
+    fn fetch_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let err = fetch(
+            &url,
+            FetchOptions {
+                timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        drop(listener);
+    }
+
+    #[test]
+    fn fetch_invalid() {
+        assert!(fetch("not a url", FetchOptions::default()).is_err());
+        assert!(fetch("/relative", FetchOptions::default()).is_err());
+        let options = FetchOptions {
+            method: Some("NOT A METHOD".to_string()),
+            ..Default::default()
+        };
+        assert!(fetch("http://localhost/", options).is_err());
+    }
+}
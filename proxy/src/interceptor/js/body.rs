@@ -2,11 +2,19 @@ use std::{cell::RefCell, rc::Rc};
 
 use boa_engine::{
     Context, JsData, JsResult, JsString, JsValue, js_error, js_string,
-    object::builtins::JsArrayBuffer, value::TryFromJs,
+    object::{
+        ObjectInitializer,
+        builtins::{JsArray, JsArrayBuffer},
+    },
+    property::Attribute,
+    value::TryFromJs,
 };
 use boa_gc::{Finalize, Trace};
 use boa_interop::{JsClass, js_class};
 use bytes::Bytes;
+use roxy_shared::content::{parse_multipart, sniff_multipart_boundary};
+use serde_json::{Map, Value};
+use url::form_urlencoded;
 
 #[derive(Debug, Clone, Trace, Finalize, JsData)]
 pub(crate) struct JsBody {
@@ -26,7 +34,7 @@ impl JsBody {
             inner: Rc::new(RefCell::new(data)),
         }
     }
-    fn new_value(value: &JsValue) -> JsResult<Self> {
+    pub(crate) fn new_value(value: &JsValue) -> JsResult<Self> {
         if value.is_undefined() || value.is_null() {
             return Ok(Self::new(Bytes::new()));
         }
@@ -58,6 +66,85 @@ impl JsBody {
     fn is_empty(&self) -> JsValue {
         JsValue::Boolean(self.inner.borrow().is_empty())
     }
+
+    fn json(&self, context: &mut Context) -> JsResult<JsValue> {
+        let value: Value = serde_json::from_slice(&self.inner.borrow())
+            .map_err(|e| js_error!(SyntaxError: "invalid JSON: {}", e))?;
+        JsValue::from_json(&value, context)
+    }
+
+    fn set_json(&self, value: &JsValue, context: &mut Context) -> JsResult<()> {
+        let json = serde_json::to_vec(&value.to_json(context)?)
+            .map_err(|e| js_error!(TypeError: "{}", e))?;
+        *self.inner.borrow_mut() = Bytes::from(json);
+        Ok(())
+    }
+
+    /// The `application/x-www-form-urlencoded` fields, a repeated name keeps
+    /// its last value.
+    fn form(&self, context: &mut Context) -> JsResult<JsValue> {
+        let fields = form_urlencoded::parse(&self.inner.borrow())
+            .map(|(key, value)| (key.into_owned(), Value::String(value.into_owned())))
+            .collect::<Map<_, _>>();
+        JsValue::from_json(&Value::Object(fields), context)
+    }
+
+    /// The parts of a multipart body, `null` when it is not one. The
+    /// boundary is taken from the first line when `boundary` is not given.
+    fn multipart(&self, boundary: &JsValue, context: &mut Context) -> JsResult<JsValue> {
+        let boundary = match boundary {
+            JsValue::Undefined | JsValue::Null => None,
+            value => Some(value.to_string(context)?.to_std_string_escaped()),
+        };
+        let body = self.inner.borrow().clone();
+        let Some(parts) = boundary
+            .or_else(|| sniff_multipart_boundary(&body))
+            .and_then(|boundary| parse_multipart(&body, &boundary))
+        else {
+            return Ok(JsValue::null());
+        };
+        let string = |v: Option<String>| match v {
+            Some(v) => JsValue::from(JsString::from(v.as_str())),
+            None => JsValue::null(),
+        };
+        let array = JsArray::new(context);
+        for part in parts {
+            let headers = ObjectInitializer::new(context).build();
+            for (name, value) in &part.headers {
+                let value = String::from_utf8_lossy(value.as_bytes()).to_string();
+                headers.set(
+                    JsString::from(name.as_str()),
+                    JsString::from(value.as_str()),
+                    false,
+                    context,
+                )?;
+            }
+            let text = String::from_utf8_lossy(&part.body).to_string();
+            let raw = JsArrayBuffer::from_byte_block(part.body.to_vec(), context)?;
+            let entry = ObjectInitializer::new(context)
+                .property(js_string!("name"), string(part.name), Attribute::all())
+                .property(
+                    js_string!("filename"),
+                    string(part.filename),
+                    Attribute::all(),
+                )
+                .property(
+                    js_string!("contentType"),
+                    string(part.content_type),
+                    Attribute::all(),
+                )
+                .property(js_string!("headers"), headers, Attribute::all())
+                .property(
+                    js_string!("text"),
+                    JsString::from(text.as_str()),
+                    Attribute::all(),
+                )
+                .property(js_string!("raw"), raw, Attribute::all())
+                .build();
+            array.push(entry, context)?;
+        }
+        Ok(array.into())
+    }
 }
 
 js_class! {
@@ -120,6 +207,22 @@ js_class! {
             *this.borrow().inner.borrow_mut() = Bytes::new();
             Ok(())
         }
+
+        fn json(this: JsClass<JsBody>, context: &mut Context) -> JsResult<JsValue> {
+            this.borrow().json(context)
+        }
+
+        fn set_json as "setJson" (this: JsClass<JsBody>, value: JsValue, context: &mut Context) -> JsResult<()> {
+            this.borrow().set_json(&value, context)
+        }
+
+        fn form(this: JsClass<JsBody>, context: &mut Context) -> JsResult<JsValue> {
+            this.borrow().form(context)
+        }
+
+        fn multipart(this: JsClass<JsBody>, boundary: JsValue, context: &mut Context) -> JsResult<JsValue> {
+            this.borrow().multipart(&boundary, context)
+        }
     }
 }
 
@@ -237,6 +340,66 @@ mod tests {
         .unwrap();
     }
 
+    #[test]
+    fn body_json_and_form() {
+        let mut ctx = setup();
+        ctx.eval(Source::from_bytes(
+            r#"
+            const b = new Body('{"user":{"id":7,"name":"a"},"tags":["x","y"]}');
+            const v = b.json();
+            assertEqual(v.user.id, 7, "nested number");
+            assertEqual(v.tags[1], "y", "array");
+            v.user.name = "b";
+            b.setJson(v);
+            assertEqual(b.json().user.name, "b", "json roundtrip");
+
+            b.text = "not json";
+            let threw = false;
+            try { b.json(); } catch (e) { threw = true; }
+            assertTrue(threw, "invalid json throws");
+
+            b.text = "a=1&b=hello+world&a=2";
+            const form = b.form();
+            assertEqual(form.a, "2", "last value wins");
+            assertEqual(form.b, "hello world", "decoded");
+        "#,
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn body_multipart() {
+        let mut ctx = setup();
+        ctx.eval(Source::from_bytes(
+            r#"
+            const b = new Body([
+                "--b1",
+                'Content-Disposition: form-data; name="title"',
+                "",
+                "hello",
+                "--b1",
+                'Content-Disposition: form-data; name="file"; filename="a.txt"',
+                "Content-Type: text/plain",
+                "",
+                "contents",
+                "--b1--",
+                "",
+            ].join("\r\n"));
+            const parts = b.multipart();
+            assertEqual(parts.length, 2, "two parts");
+            assertEqual(parts[0].name, "title", "name");
+            assertEqual(parts[0].text, "hello", "text");
+            assertEqual(parts[1].filename, "a.txt", "filename");
+            assertEqual(parts[1].contentType, "text/plain", "content type");
+            assertEqual(parts[1].headers["content-type"], "text/plain", "headers");
+            assertEqual(new Uint8Array(parts[1].raw).length, 8, "raw");
+            assertEqual(b.multipart("other"), null, "wrong boundary");
+            assertEqual(new Body("plain").multipart(), null, "not multipart");
+        "#,
+        ))
+        .unwrap();
+    }
+
     #[test]
     fn body_instances_are_independent() {
         let mut ctx = setup();
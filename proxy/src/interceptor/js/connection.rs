@@ -0,0 +1,67 @@
+use boa_engine::{
+    Context, JsResult, JsString, JsValue, js_string, object::ObjectInitializer, property::Attribute,
+};
+
+use crate::interceptor::{
+    KEY_CLIENT_ADDR, KEY_CLIENT_IP, KEY_HOST, KEY_PORT, KEY_TLS,
+    connection::{ConnectionHook, ConnectionInfo},
+    js::{engine::get_extensions, flow::tls_object},
+};
+
+/// The connection as a read-only object.
+fn connection_object(conn: &ConnectionInfo, context: &mut Context) -> JsValue {
+    let attribute = Attribute::READONLY | Attribute::ENUMERABLE | Attribute::PERMANENT;
+    let host = match &conn.host {
+        Some(host) => JsValue::from(JsString::from(host.as_str())),
+        None => JsValue::null(),
+    };
+    let port = conn.port.map(JsValue::from).unwrap_or_else(JsValue::null);
+    let tls = match &conn.tls {
+        Some(tls) => tls_object(tls, context),
+        None => JsValue::null(),
+    };
+    ObjectInitializer::new(context)
+        .property(
+            JsString::from(KEY_CLIENT_ADDR),
+            JsString::from(conn.client_addr.to_string()),
+            attribute,
+        )
+        .property(
+            JsString::from(KEY_CLIENT_IP),
+            JsString::from(conn.client_addr.ip().to_string()),
+            attribute,
+        )
+        .property(JsString::from(KEY_HOST), host, attribute)
+        .property(JsString::from(KEY_PORT), port, attribute)
+        .property(JsString::from(KEY_TLS), tls, attribute)
+        .build()
+        .into()
+}
+
+/// Calls the `hook` method of every extension with the connection, false
+/// once one returns `false`.
+pub(crate) fn run_connection_handlers(
+    ctx: &mut Context,
+    hook: ConnectionHook,
+    conn: &ConnectionInfo,
+) -> JsResult<bool> {
+    let ext_arr = get_extensions(ctx)?;
+    let conn = connection_object(conn, ctx);
+    let len = ext_arr.length(ctx)?;
+    for i in 0..len {
+        let addon = ext_arr.get(i, ctx)?;
+        let Some(obj) = addon.as_object() else {
+            continue;
+        };
+        let method = obj.get(js_string!(hook.key()), ctx)?;
+        let Some(fun) = method.as_callable() else {
+            continue;
+        };
+        let allowed = fun.call(&addon, std::slice::from_ref(&conn), ctx)?;
+        ctx.run_jobs();
+        if let JsValue::Boolean(false) = allowed {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -18,11 +18,24 @@ use crate::{
     interceptor::{
         Error, FlowNotify, KEY_INTERCEPT_REQUEST, KEY_INTERCEPT_RESPONSE, KEY_NOTIFY, KEY_START,
         KEY_STOP, RoxyEngine,
+        connection::{ConnectionHook, ConnectionInfo},
         js::{
-            body::JsBody, constants::register_constants, flow::JsFlow, headers::JsHeaders,
-            logger::JsLogger, query::UrlSearchParams, request::JsRequest, response::JsResponse,
+            body::JsBody,
+            connection::run_connection_handlers,
+            constants::register_constants,
+            fetch::register_fetch,
+            flow::{JsFlow, JsTags},
+            headers::JsHeaders,
+            logger::JsLogger,
+            modules::{eval_module, is_module, new_context},
+            query::UrlSearchParams,
+            request::JsRequest,
+            response::JsResponse,
+            store::register_store,
+            timers::{Timers, register_timers},
             url::JsUrl,
         },
+        tags::ScriptTags,
     },
 };
 use tokio::sync::{mpsc, oneshot};
@@ -57,6 +70,22 @@ impl ResCmd {
     }
 }
 
+struct ConnCmd {
+    hook: ConnectionHook,
+    conn: ConnectionInfo,
+    resp: oneshot::Sender<Result<bool, Error>>,
+}
+
+impl ConnCmd {
+    fn new(
+        hook: ConnectionHook,
+        conn: ConnectionInfo,
+        resp: oneshot::Sender<Result<bool, Error>>,
+    ) -> Box<Self> {
+        Box::new(ConnCmd { hook, conn, resp })
+    }
+}
+
 struct ScriptCmd {
     script: String,
     resp: oneshot::Sender<Result<(), Error>>,
@@ -81,6 +110,7 @@ impl StopCmd {
 enum Cmd {
     InterceptReq { data: Box<ReqCmd> },
     InterceptRes { data: Box<ResCmd> },
+    OnConnection { data: Box<ConnCmd> },
     SetScript { data: Box<ScriptCmd> },
     OnStop { data: Box<StopCmd> },
 }
@@ -91,6 +121,7 @@ pub(crate) fn register_classes(ctx: &mut Context) -> JsResult<()> {
     ctx.register_global_class::<JsUrl>()?;
     ctx.register_global_class::<JsBody>()?;
     ctx.register_global_class::<JsFlow>()?;
+    ctx.register_global_class::<JsTags>()?;
     ctx.register_global_class::<JsRequest>()?;
     ctx.register_global_class::<JsResponse>()?;
     ctx.register_global_class::<JsHeaders>()?;
@@ -111,7 +142,7 @@ impl JsEngine {
                 .enable_all()
                 .build();
 
-            let mut ctx = Context::default();
+            let mut ctx = new_context();
 
             if let Err(e) = register_classes(&mut ctx) {
                 error!("Error register_classes {e}");
@@ -134,10 +165,7 @@ impl JsEngine {
                         .to_std_string_escaped();
 
                     if let Some(tx) = notify_tx.as_ref() {
-                        let _ = tx.try_send(FlowNotify {
-                            level: level.into(),
-                            msg,
-                        });
+                        let _ = tx.try_send(FlowNotify::new(level.into(), msg));
                     }
                     Ok(JsValue::Undefined)
                 })
@@ -183,11 +211,37 @@ impl JsEngine {
                 error!("Error register_global_property {err}");
             }
 
+            if let Err(err) = register_fetch(&mut ctx) {
+                error!("Error register_fetch {err}");
+            }
+
+            if let Err(err) = register_store(&mut ctx) {
+                error!("Error register_store {err}");
+            }
+
             register_constants(&mut ctx);
 
+            let timers = register_timers(&mut ctx).unwrap_or_else(|err| {
+                error!("Error register_timers {err}");
+                Timers::default()
+            });
+
             if let Ok(rt) = rt {
                 rt.block_on(async move {
-                    while let Some(cmd) = rx.recv().await {
+                    loop {
+                        let cmd = match timers.next_due() {
+                            Some(due) => tokio::select! {
+                                cmd = rx.recv() => cmd,
+                                _ = tokio::time::sleep_until(due.into()) => {
+                                    timers.run_due(&mut ctx);
+                                    continue;
+                                }
+                            },
+                            None => rx.recv().await,
+                        };
+                        let Some(cmd) = cmd else {
+                            break;
+                        };
                         match cmd {
                             Cmd::InterceptReq { data } => {
                                 let result = handle_intercept_req(&mut ctx, data.req).await;
@@ -198,11 +252,25 @@ impl JsEngine {
                                     handle_intercept_resp(&mut ctx, data.req, data.res).await;
                                 let _ = data.resp.send(result);
                             }
+                            Cmd::OnConnection { data } => {
+                                let result =
+                                    run_connection_handlers(&mut ctx, data.hook, &data.conn)
+                                        .map_err(|e| Error::Other(e.to_string()));
+                                let _ = data.resp.send(result);
+                            }
                             Cmd::SetScript { data } => {
                                 if let Err(e) = ctx.create_realm() {
                                     error!("Error creating JS realm {e}");
                                 }
-                                let result = ctx.eval(Source::from_bytes(data.script.as_bytes()));
+                                timers.clear();
+                                let result = if is_module(&data.script) {
+                                    eval_module(&mut ctx, &data.script)
+                                } else {
+                                    ctx.eval(Source::from_bytes(data.script.as_bytes()))
+                                        .map(|_| {
+                                            ctx.run_jobs();
+                                        })
+                                };
                                 if let Err(e) = &result {
                                     error!("Script error {e}");
                                 };
@@ -212,7 +280,7 @@ impl JsEngine {
 
                                 let _ = data
                                     .resp
-                                    .send(result.map(|_| ()).map_err(|_| Error::LoadError));
+                                    .send(result.map_err(|e| Error::Other(e.to_string())));
                             }
                             Cmd::OnStop { data } => {
                                 on_stop(&mut ctx).await.unwrap_or_else(|e| {
@@ -246,6 +314,8 @@ pub async fn handle_intercept_req(
     let trailers_cell = Rc::new(RefCell::new(req.trailers.clone().unwrap_or_default()));
 
     let body = JsBody::new(req.body.clone());
+    let tls = req.tls.clone();
+    let tags = ScriptTags::new(req.tags.clone());
     let req_cell = Rc::new(RefCell::new(req));
     let resp_cell = Rc::new(RefCell::new(None));
     let url_cell: Rc<RefCell<Option<JsObject>>> = Rc::new(RefCell::new(None));
@@ -271,6 +341,8 @@ pub async fn handle_intercept_req(
     let flow = JsFlow {
         request,
         response: response.clone(),
+        tls,
+        tags: tags.clone(),
     };
 
     let proto = crate::interceptor::js::util::class_proto(ctx, JsFlow::NAME)
@@ -290,6 +362,7 @@ pub async fn handle_intercept_req(
     final_req.body = body.inner.borrow().clone();
     final_req.headers = header_handle.borrow().clone();
     final_req.trailers = trailers;
+    final_req.tags = tags.list();
     if let Some(uri) = url.and_then(|u| u.downcast::<JsUrl>().ok()).and_then(|u| {
         let url_ref = u.borrow();
         let value = url_ref.data().to_string();
@@ -302,7 +375,7 @@ pub async fn handle_intercept_req(
     Ok((final_req, final_resp))
 }
 
-fn get_extensions(ctx: &mut Context) -> JsResult<JsArray> {
+pub(crate) fn get_extensions(ctx: &mut Context) -> JsResult<JsArray> {
     let ext_val = ctx.global_object().get(js_string!("extensions"), ctx)?;
     let Some(ext_obj) = ext_val.as_object() else {
         return Err(js_error!(TypeError: "`extensions` must be an Array"));
@@ -364,6 +437,7 @@ fn call_method_if_callable(
     let method = obj.get(js_string!(name), ctx)?;
     if let Some(fun) = method.as_callable() {
         let _ = fun.call(this, args, ctx)?;
+        ctx.run_jobs();
     }
     Ok(())
 }
@@ -392,6 +466,8 @@ async fn handle_intercept_resp(
     let header_cell = Rc::new(RefCell::new(res.headers.clone()));
     let body = JsBody::new(res.body.clone());
     let trailers_cell = Rc::new(RefCell::new(res.trailers.clone().unwrap_or_default()));
+    let tls = res.tls.clone();
+    let tags = ScriptTags::new(res.tags.clone());
     let req_cell = Rc::new(RefCell::new(req));
     let resp_cell = Rc::new(RefCell::new(Some(res)));
 
@@ -411,7 +487,12 @@ async fn handle_intercept_resp(
         headers: header_cell.clone(),
         trailers: trailers_cell,
     };
-    let flow = JsFlow { request, response };
+    let flow = JsFlow {
+        request,
+        response,
+        tls,
+        tags: tags.clone(),
+    };
 
     let proto = crate::interceptor::js::util::class_proto(ctx, JsFlow::NAME)
         .map_err(|_| Error::InterceptedRequest)?;
@@ -427,6 +508,7 @@ async fn handle_intercept_resp(
     final_resp.body = body.inner.borrow().clone();
     final_resp.headers = header_cell.borrow().clone();
     final_resp.trailers = trailers;
+    final_resp.tags = tags.list();
 
     Ok(final_resp)
 }
@@ -501,6 +583,22 @@ impl RoxyEngine for JsEngine {
         Ok(())
     }
 
+    async fn on_connection(
+        &self,
+        hook: ConnectionHook,
+        conn: &ConnectionInfo,
+    ) -> Result<bool, Error> {
+        let (txr, rxr) = oneshot::channel();
+        self.tx
+            .send(Cmd::OnConnection {
+                data: ConnCmd::new(hook, conn.clone(), txr),
+            })
+            .await
+            .map_err(|_| Error::Other(format!("JS engine closed before {hook}")))?;
+        rxr.await
+            .map_err(|_| Error::Other(format!("JS engine dropped {hook}")))?
+    }
+
     async fn set_script(&self, script: &str) -> Result<(), Error> {
         let (txr, rxr) = oneshot::channel();
         self.tx
@@ -509,11 +607,7 @@ impl RoxyEngine for JsEngine {
             })
             .await
             .map_err(|_| Error::LoadError)?;
-        let _resp = rxr
-            .await
-            .map_err(|_| Error::LoadError)?
-            .map_err(|_| Error::LoadError)?;
-        Ok(())
+        rxr.await.map_err(|_| Error::LoadError)?
     }
 
     async fn on_stop(&self) -> Result<(), Error> {
@@ -0,0 +1,133 @@
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use boa_engine::{
+    Context, JsObject, JsResult, JsValue, NativeFunction, class::Class, js_error, js_string,
+    object::FunctionObjectBuilder, property::Attribute,
+};
+
+use crate::interceptor::{
+    KEY_BODY, KEY_FETCH, KEY_HEADERS, KEY_METHOD, KEY_TIMEOUT,
+    fetch::{FetchOptions, fetch},
+    js::{body::JsBody, response::JsResponse},
+};
+
+/// Registers the global `fetch(url, options)`. Unlike the browser version it
+/// returns the `Response` directly and throws on failure.
+pub(crate) fn register_fetch(ctx: &mut Context) -> JsResult<()> {
+    let fetch_fn = FunctionObjectBuilder::new(
+        ctx.realm(),
+        NativeFunction::from_fn_ptr(|_this, args, ctx| -> JsResult<JsValue> {
+            let url = args
+                .first()
+                .ok_or(js_error!(TypeError: "No url provided"))?
+                .to_string(ctx)?
+                .to_std_string_escaped();
+            let options = match args.get(1).and_then(JsValue::as_object) {
+                Some(options) => fetch_options(options, ctx)?,
+                None => FetchOptions::default(),
+            };
+            let response = fetch(&url, options).map_err(|e| js_error!("{}", e))?;
+            let response = JsResponse {
+                body: JsBody::new(response.body.clone()),
+                headers: Rc::new(RefCell::new(response.headers.clone())),
+                trailers: Rc::new(RefCell::new(response.trailers.clone().unwrap_or_default())),
+                resp: Rc::new(RefCell::new(Some(response))),
+            };
+            JsResponse::from_data(response, ctx).map(JsValue::from)
+        }),
+    )
+    .length(2)
+    .name(js_string!(KEY_FETCH))
+    .build();
+
+    ctx.register_global_property(
+        js_string!(KEY_FETCH),
+        fetch_fn,
+        Attribute::WRITABLE | Attribute::NON_ENUMERABLE | Attribute::CONFIGURABLE,
+    )
+}
+
+fn fetch_options(options: &JsObject, ctx: &mut Context) -> JsResult<FetchOptions> {
+    let string = |value: JsValue, ctx: &mut Context| -> JsResult<Option<String>> {
+        if value.is_undefined() || value.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(value.to_string(ctx)?.to_std_string_escaped()))
+    };
+
+    let mut headers = vec![];
+    if let Some(init) = options.get(js_string!(KEY_HEADERS), ctx)?.as_object() {
+        for key in init.own_property_keys(ctx)? {
+            let value = init.get(key.clone(), ctx)?;
+            if let Some(value) = string(value, ctx)? {
+                headers.push((key.to_string(), value));
+            }
+        }
+    }
+
+    let body = options.get(js_string!(KEY_BODY), ctx)?;
+    let body = if body.is_undefined() || body.is_null() {
+        None
+    } else {
+        Some(JsBody::new_value(&body)?.inner.borrow().clone())
+    };
+
+    let timeout = options.get(js_string!(KEY_TIMEOUT), ctx)?;
+    let timeout = if timeout.is_undefined() || timeout.is_null() {
+        None
+    } else {
+        Some(Duration::from_millis(timeout.to_length(ctx)?))
+    };
+
+    Ok(FetchOptions {
+        method: string(options.get(js_string!(KEY_METHOD), ctx)?, ctx)?,
+        headers,
+        body,
+        timeout,
+    })
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use boa_engine::{Source, js_string, property::Attribute};
+    use cow_utils::CowUtils;
+
+    use crate::interceptor::{
+        fetch::tests::serve_once,
+        js::{fetch::register_fetch, tests::setup},
+    };
+
+    #[test]
+    fn fetch_from_script() {
+        let (url, rx) = serve_once("HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\ntoken");
+        let mut ctx = setup();
+        register_fetch(&mut ctx).unwrap();
+        ctx.register_global_property(js_string!("url"), js_string!(url), Attribute::all())
+            .unwrap();
+        ctx.eval(Source::from_bytes(
+            r#"
+            const res = fetch(url, {
+              method: "POST",
+              headers: { "x-hook": "1" },
+              body: "hello",
+              timeout: 1000,
+            });
+            assertEqual(res.status, 200);
+            assertEqual(res.body.text, "token");
+
+            let threw = false;
+            try {
+              fetch("/relative");
+            } catch (e) {
+              threw = true;
+            }
+            assertTrue(threw, "fetch throws on failure");
+            "#,
+        ))
+        .unwrap();
+        let received = rx.recv().unwrap().cow_to_ascii_lowercase().into_owned();
+        assert!(received.starts_with("post "));
+        assert!(received.contains("x-hook: 1"));
+    }
+}
@@ -1,13 +1,57 @@
-use boa_engine::{Context, JsData, JsResult, JsValue};
+use boa_engine::{
+    Context, JsData, JsResult, JsString, JsValue,
+    object::{ObjectInitializer, builtins::JsArray},
+    property::Attribute,
+    value::Convert,
+};
 use boa_gc::{Finalize, Trace};
 use boa_interop::{JsClass, js_class};
 
-use crate::interceptor::js::{request::JsRequest, response::JsResponse};
+use crate::{
+    flow::FlowTls,
+    interceptor::{
+        KEY_ALPN, KEY_CIPHER, KEY_PEER_SUBJECT, KEY_SNI, KEY_VERSION,
+        js::{request::JsRequest, response::JsResponse},
+        tags::ScriptTags,
+    },
+};
 
 #[derive(Debug, Clone, Trace, Finalize, JsData, Default)]
 pub(crate) struct JsFlow {
     pub(crate) request: JsRequest,
     pub(crate) response: JsResponse,
+    #[unsafe_ignore_trace]
+    pub(crate) tls: Option<FlowTls>,
+    #[unsafe_ignore_trace]
+    pub(crate) tags: ScriptTags,
+}
+
+/// `flow.tags`, tags can't be removed once added.
+#[derive(Debug, Clone, Trace, Finalize, JsData, Default)]
+pub(crate) struct JsTags {
+    #[unsafe_ignore_trace]
+    tags: ScriptTags,
+}
+
+/// Every property is read-only, assignments are ignored outside strict mode.
+pub(crate) fn tls_object(tls: &FlowTls, context: &mut Context) -> JsValue {
+    let value = |v: &Option<String>| match v {
+        Some(v) => JsValue::from(JsString::from(v.as_str())),
+        None => JsValue::null(),
+    };
+    let attribute = Attribute::READONLY | Attribute::ENUMERABLE | Attribute::PERMANENT;
+    ObjectInitializer::new(context)
+        .property(JsString::from(KEY_VERSION), value(&tls.version), attribute)
+        .property(JsString::from(KEY_CIPHER), value(&tls.cipher), attribute)
+        .property(JsString::from(KEY_ALPN), value(&tls.alpn), attribute)
+        .property(JsString::from(KEY_SNI), value(&tls.sni), attribute)
+        .property(
+            JsString::from(KEY_PEER_SUBJECT),
+            value(&tls.peer_subject),
+            attribute,
+        )
+        .build()
+        .into()
 }
 
 js_class! {
@@ -26,6 +70,22 @@ js_class! {
             }
         }
 
+        property tls {
+            fn get(this: JsClass<JsFlow>, context: &mut Context) -> JsResult<JsValue> {
+                match &this.borrow().tls {
+                    Some(tls) => Ok(tls_object(tls, context)),
+                    None => Ok(JsValue::null()),
+                }
+            }
+        }
+
+        property tags {
+            fn get(this: JsClass<JsFlow>, context: &mut Context) -> JsResult<JsValue> {
+                let tags = this.borrow().tags.clone();
+                JsTags::from_data(JsTags { tags }, context).map(JsValue::from)
+            }
+        }
+
         constructor() {
             Ok(Self::default())
         }
@@ -35,11 +95,47 @@ js_class! {
     }
 }
 
+js_class! {
+    class JsTags as "Tags" {
+        property length {
+            fn get(this: JsClass<JsTags>) -> JsResult<usize> {
+                Ok(this.borrow().tags.list().len())
+            }
+        }
+
+        constructor() {
+            Ok(Self::default())
+        }
+        init(_class: &mut ClassBuilder) -> JsResult<()> {
+            Ok(())
+        }
+
+        fn add(this: JsClass<JsTags>, tag: Convert<String>) -> JsResult<()> {
+            this.borrow().tags.add(&tag.0);
+            Ok(())
+        }
+
+        fn has(this: JsClass<JsTags>, tag: Convert<String>) -> JsResult<bool> {
+            Ok(this.borrow().tags.has(&tag.0))
+        }
+
+        fn list(this: JsClass<JsTags>, context: &mut Context) -> JsResult<JsValue> {
+            let tags = this
+                .borrow()
+                .tags
+                .list()
+                .into_iter()
+                .map(|t| JsValue::from(JsString::from(t.as_str())));
+            Ok(JsArray::from_iter(tags, context).into())
+        }
+    }
+}
+
 #[allow(clippy::unwrap_used, clippy::expect_used, clippy::panic)]
 #[cfg(test)]
 mod tests {
     use crate::interceptor::js::tests::setup;
-    use boa_engine::Source;
+    use boa_engine::{Source, property::Attribute};
 
     #[test]
     fn flow_constructor_creates_default_instance() {
@@ -113,4 +209,72 @@ mod tests {
         ))
         .unwrap();
     }
+
+    #[test]
+    fn flow_tls_is_read_only() {
+        use boa_engine::{JsObject, class::Class, js_string};
+
+        use crate::{
+            flow::FlowTls,
+            interceptor::js::{flow::JsFlow, util::class_proto},
+        };
+
+        let mut ctx = setup();
+        let flow = JsFlow {
+            tls: Some(FlowTls {
+                version: Some("TLSv1_3".to_string()),
+                sni: Some("example.com".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let proto = class_proto(&mut ctx, JsFlow::NAME).unwrap();
+        let flow = JsObject::from_proto_and_data(proto, flow);
+        ctx.register_global_property(js_string!("flow"), flow, Attribute::all())
+            .unwrap();
+        ctx.eval(Source::from_bytes(
+            r#"
+            assertTrue(new Flow().tls === null, "plain flows have no tls");
+            assertTrue(flow.tls.version === "TLSv1_3", "version");
+            assertTrue(flow.tls.sni === "example.com", "sni");
+            assertTrue(flow.tls.cipher === null, "cipher");
+            flow.tls.version = "TLSv1_0";
+            assertTrue(flow.tls.version === "TLSv1_3", "tls is read-only");
+            "#,
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn flow_tags() {
+        use boa_engine::{JsObject, class::Class, js_string};
+
+        use crate::interceptor::{
+            js::{flow::JsFlow, util::class_proto},
+            tags::ScriptTags,
+        };
+
+        let mut ctx = setup();
+        let tags = ScriptTags::new(vec!["seen".to_string()]);
+        let flow = JsFlow {
+            tags: tags.clone(),
+            ..Default::default()
+        };
+        let proto = class_proto(&mut ctx, JsFlow::NAME).unwrap();
+        let flow = JsObject::from_proto_and_data(proto, flow);
+        ctx.register_global_property(js_string!("flow"), flow, Attribute::all())
+            .unwrap();
+        ctx.eval(Source::from_bytes(
+            r#"
+            assertTrue(flow.tags.has("seen"), "existing tag");
+            assertFalse(flow.tags.has("auth"), "missing tag");
+            flow.tags.add("auth");
+            flow.tags.add("auth");
+            assertEqual(flow.tags.length, 2);
+            assertEqual(flow.tags.list().join(","), "seen,auth");
+            "#,
+        ))
+        .unwrap();
+        assert_eq!(tags.list(), vec!["seen", "auth"]);
+    }
 }
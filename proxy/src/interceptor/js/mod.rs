@@ -1,12 +1,17 @@
 mod body;
+mod connection;
 mod constants;
 pub mod engine;
+mod fetch;
 mod flow;
 mod headers;
 mod logger;
+mod modules;
 mod query;
 mod request;
 mod response;
+mod store;
+mod timers;
 mod url;
 mod util;
 
@@ -0,0 +1,103 @@
+use std::rc::Rc;
+
+use boa_engine::{
+    Context, JsError, JsResult, Module, Source, builtins::promise::PromiseState, js_error,
+    module::SimpleModuleLoader,
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tracing::error;
+
+use crate::interceptor::js_modules::module_root;
+
+/// A top level `import ... from` or `export`, `import(...)` works in plain
+/// scripts too.
+static MODULE_SYNTAX: Lazy<Option<Regex>> =
+    Lazy::new(|| Regex::new(r#"(?m)^\s*(import\s*[\w{*'"]|export\s)"#).ok());
+
+/// A context whose `import`s resolve against the module root. Imported files
+/// are read once and cached for the life of the engine.
+pub(crate) fn new_context() -> Context {
+    let root = module_root();
+    let loader = match SimpleModuleLoader::new(&root) {
+        Ok(loader) => loader,
+        Err(err) => {
+            error!(
+                "JS module root {} unusable, imports disabled {err}",
+                root.display()
+            );
+            return Context::default();
+        }
+    };
+    Context::builder()
+        .module_loader(Rc::new(loader))
+        .build()
+        .unwrap_or_else(|err| {
+            error!("Error building JS context {err}");
+            Context::default()
+        })
+}
+
+/// Scripts using `import` or `export` are run as modules, everything else as
+/// a classic script so top level declarations stay global.
+pub(crate) fn is_module(script: &str) -> bool {
+    MODULE_SYNTAX.as_ref().is_some_and(|re| re.is_match(script))
+}
+
+/// Loads the imports of `script`, then runs it and any jobs it queued.
+pub(crate) fn eval_module(ctx: &mut Context, script: &str) -> JsResult<()> {
+    let module = Module::parse(Source::from_bytes(script.as_bytes()), None, ctx)?;
+    let promise = module.load_link_evaluate(ctx);
+    ctx.run_jobs();
+    match promise.state() {
+        PromiseState::Fulfilled(_) => Ok(()),
+        PromiseState::Rejected(err) => Err(JsError::from_opaque(err)),
+        PromiseState::Pending => Err(js_error!("module did not finish evaluating")),
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use boa_engine::js_string;
+
+    use super::*;
+
+    #[test]
+    fn detects_module_syntax() {
+        assert!(is_module("import { double } from './helpers.js';"));
+        assert!(is_module("  import * as h from \"./h.js\""));
+        assert!(is_module("import './side_effect.js'"));
+        assert!(is_module("export const x = 1;"));
+        assert!(!is_module("const m = await import('./h.js');"));
+        assert!(!is_module("globalThis.extensions = []; // import later"));
+    }
+
+    #[test]
+    fn imports_relative_to_root() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("helpers.js"),
+            "export function double(x) { return x * 2; }",
+        )
+        .unwrap();
+        let loader = SimpleModuleLoader::new(dir.path()).unwrap();
+        let mut ctx = Context::builder()
+            .module_loader(Rc::new(loader))
+            .build()
+            .unwrap();
+
+        eval_module(
+            &mut ctx,
+            "import { double } from './helpers.js'; globalThis.result = double(21);",
+        )
+        .unwrap();
+        let result = ctx
+            .global_object()
+            .get(js_string!("result"), &mut ctx)
+            .unwrap();
+        assert_eq!(result.as_number(), Some(42.0));
+
+        assert!(eval_module(&mut ctx, "import { nope } from './missing.js';").is_err());
+    }
+}
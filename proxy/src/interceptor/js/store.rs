@@ -0,0 +1,80 @@
+use boa_engine::{
+    Context, JsResult, JsString, JsValue, NativeFunction, js_error, js_string,
+    object::ObjectInitializer, property::Attribute,
+};
+
+use crate::interceptor::{KEY_STORE, store::STORE};
+
+fn key(args: &[JsValue], ctx: &mut Context) -> JsResult<String> {
+    Ok(args
+        .first()
+        .ok_or(js_error!(TypeError: "No key provided"))?
+        .to_string(ctx)?
+        .to_std_string_escaped())
+}
+
+/// Registers the global `store` with `get`, `set` and `delete` on the shared
+/// script store. Setting `null` or `undefined` removes the key.
+pub(crate) fn register_store(ctx: &mut Context) -> JsResult<()> {
+    let get = NativeFunction::from_fn_ptr(|_this, args, ctx| {
+        Ok(match STORE.get(&key(args, ctx)?) {
+            Some(value) => JsValue::from(JsString::from(value.as_str())),
+            None => JsValue::null(),
+        })
+    });
+    let set = NativeFunction::from_fn_ptr(|_this, args, ctx| {
+        let key = key(args, ctx)?;
+        let value = match args.get(1) {
+            Some(value) if !value.is_null_or_undefined() => {
+                Some(value.to_string(ctx)?.to_std_string_escaped())
+            }
+            _ => None,
+        };
+        STORE.set(&key, value);
+        Ok(JsValue::undefined())
+    });
+    let delete = NativeFunction::from_fn_ptr(|_this, args, ctx| {
+        STORE.set(&key(args, ctx)?, None);
+        Ok(JsValue::undefined())
+    });
+
+    let store = ObjectInitializer::new(ctx)
+        .function(get, js_string!("get"), 1)
+        .function(set, js_string!("set"), 2)
+        .function(delete, js_string!("delete"), 1)
+        .build();
+    ctx.register_global_property(
+        js_string!(KEY_STORE),
+        store,
+        Attribute::WRITABLE | Attribute::NON_ENUMERABLE | Attribute::CONFIGURABLE,
+    )
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use boa_engine::Source;
+
+    use crate::interceptor::js::{store::register_store, tests::setup};
+
+    #[test]
+    fn store_from_script() {
+        let mut ctx = setup();
+        register_store(&mut ctx).unwrap();
+        ctx.eval(Source::from_bytes(
+            r#"
+            assertNull(store.get("js_count"));
+            store.set("js_count", 1);
+            store.set("js_token", "abc");
+            assertEqual(store.get("js_count"), "1");
+            store.set("js_count", Number(store.get("js_count")) + 1);
+            assertEqual(store.get("js_count"), "2");
+            store.delete("js_count");
+            store.set("js_token", null);
+            assertNull(store.get("js_count"));
+            assertNull(store.get("js_token"));
+            "#,
+        ))
+        .unwrap();
+    }
+}
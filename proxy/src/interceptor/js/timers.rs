@@ -0,0 +1,191 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use boa_engine::{
+    Context, JsObject, JsResult, JsValue, NativeFunction, job::NativeJob, js_error, js_string,
+    object::FunctionObjectBuilder, property::Attribute,
+};
+use tracing::error;
+
+use crate::interceptor::{KEY_CLEAR_TIMEOUT, KEY_QUEUE_MICROTASK, KEY_SET_TIMEOUT};
+
+struct Timer {
+    due: Instant,
+    callback: JsObject,
+    args: Vec<JsValue>,
+}
+
+#[derive(Default)]
+struct TimerQueue {
+    next_id: u32,
+    pending: BTreeMap<u32, Timer>,
+}
+
+/// Callbacks scheduled with `setTimeout`. The engine thread waits for the
+/// next one between intercepts, so a timer never runs in the middle of one.
+#[derive(Clone, Default)]
+pub(crate) struct Timers(Rc<RefCell<TimerQueue>>);
+
+impl Timers {
+    fn add(&self, callback: JsObject, delay: Duration, args: Vec<JsValue>) -> u32 {
+        let mut queue = self.0.borrow_mut();
+        queue.next_id = queue.next_id.wrapping_add(1).max(1);
+        let id = queue.next_id;
+        queue.pending.insert(
+            id,
+            Timer {
+                due: Instant::now() + delay,
+                callback,
+                args,
+            },
+        );
+        id
+    }
+
+    fn remove(&self, id: u32) {
+        self.0.borrow_mut().pending.remove(&id);
+    }
+
+    /// Drops every pending timer, used when the script is replaced.
+    pub(crate) fn clear(&self) {
+        self.0.borrow_mut().pending.clear();
+    }
+
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.0.borrow().pending.values().map(|t| t.due).min()
+    }
+
+    /// Runs the timers due by now, earliest first, each followed by the jobs
+    /// it queued. Timers scheduled by these callbacks wait for the next call.
+    pub(crate) fn run_due(&self, ctx: &mut Context) {
+        let now = Instant::now();
+        loop {
+            let next = self
+                .0
+                .borrow()
+                .pending
+                .iter()
+                .filter(|(_, t)| t.due <= now)
+                .min_by_key(|(id, t)| (t.due, **id))
+                .map(|(id, _)| *id);
+            let Some(timer) = next.and_then(|id| self.0.borrow_mut().pending.remove(&id)) else {
+                return;
+            };
+            if let Err(err) = timer.callback.call(&JsValue::undefined(), &timer.args, ctx) {
+                error!("Error in {KEY_SET_TIMEOUT} callback {err}");
+            }
+            ctx.run_jobs();
+        }
+    }
+}
+
+fn callback_arg(args: &[JsValue], name: &str) -> JsResult<JsObject> {
+    args.first()
+        .and_then(JsValue::as_callable)
+        .cloned()
+        .ok_or(js_error!(TypeError: "{} callback must be a function", name))
+}
+
+/// Registers `setTimeout`, `clearTimeout` and `queueMicrotask`, returning the
+/// timers the engine thread has to run.
+pub(crate) fn register_timers(ctx: &mut Context) -> JsResult<Timers> {
+    let timers = Timers::default();
+    let attribute = Attribute::WRITABLE | Attribute::NON_ENUMERABLE | Attribute::CONFIGURABLE;
+
+    let handle = timers.clone();
+    let set_timeout = FunctionObjectBuilder::new(ctx.realm(), unsafe {
+        NativeFunction::from_closure(move |_this, args, ctx| -> JsResult<JsValue> {
+            let callback = callback_arg(args, KEY_SET_TIMEOUT)?;
+            let delay = match args.get(1) {
+                Some(delay) => delay.to_number(ctx)?,
+                None => 0.0,
+            };
+            let delay = if delay.is_nan() {
+                0.0
+            } else {
+                delay.clamp(0.0, f64::from(i32::MAX))
+            };
+            let args = args.get(2..).unwrap_or_default().to_vec();
+            let id = handle.add(callback, Duration::from_millis(delay as u64), args);
+            Ok(JsValue::from(id))
+        })
+    })
+    .length(2)
+    .name(js_string!(KEY_SET_TIMEOUT))
+    .build();
+    ctx.register_global_property(js_string!(KEY_SET_TIMEOUT), set_timeout, attribute)?;
+
+    let handle = timers.clone();
+    let clear_timeout = FunctionObjectBuilder::new(ctx.realm(), unsafe {
+        NativeFunction::from_closure(move |_this, args, ctx| -> JsResult<JsValue> {
+            if let Some(id) = args.first().filter(|id| !id.is_undefined()) {
+                handle.remove(id.to_u32(ctx)?);
+            }
+            Ok(JsValue::undefined())
+        })
+    })
+    .length(1)
+    .name(js_string!(KEY_CLEAR_TIMEOUT))
+    .build();
+    ctx.register_global_property(js_string!(KEY_CLEAR_TIMEOUT), clear_timeout, attribute)?;
+
+    let queue_microtask = FunctionObjectBuilder::new(
+        ctx.realm(),
+        NativeFunction::from_fn_ptr(|_this, args, ctx| -> JsResult<JsValue> {
+            let callback = callback_arg(args, KEY_QUEUE_MICROTASK)?;
+            ctx.enqueue_job(NativeJob::new(move |ctx| {
+                callback.call(&JsValue::undefined(), &[], ctx)
+            }));
+            Ok(JsValue::undefined())
+        }),
+    )
+    .length(1)
+    .name(js_string!(KEY_QUEUE_MICROTASK))
+    .build();
+    ctx.register_global_property(js_string!(KEY_QUEUE_MICROTASK), queue_microtask, attribute)?;
+
+    Ok(timers)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use boa_engine::Source;
+
+    use super::*;
+
+    #[test]
+    fn runs_microtasks_and_due_timers() {
+        let mut ctx = Context::default();
+        let timers = register_timers(&mut ctx).unwrap();
+        ctx.eval(Source::from_bytes(
+            r#"
+            globalThis.log = [];
+            setTimeout((a, b) => log.push(`timeout ${a}${b}`), 0, "x", "y");
+            const cleared = setTimeout(() => log.push("cleared"), 0);
+            clearTimeout(cleared);
+            setTimeout(() => log.push("later"), 60000);
+            queueMicrotask(() => log.push("microtask"));
+            log.push("sync");
+            "#,
+        ))
+        .unwrap();
+        ctx.run_jobs();
+        timers.run_due(&mut ctx);
+
+        let log = ctx
+            .eval(Source::from_bytes("log.join(',')"))
+            .unwrap()
+            .to_string(&mut ctx)
+            .unwrap()
+            .to_std_string_escaped();
+        assert_eq!(log, "sync,microtask,timeout xy");
+        assert!(timers.next_due().is_some());
+        timers.clear();
+        assert!(timers.next_due().is_none());
+    }
+}
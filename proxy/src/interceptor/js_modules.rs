@@ -0,0 +1,26 @@
+use std::{path::PathBuf, sync::RwLock};
+
+use once_cell::sync::Lazy;
+use tracing::error;
+
+static MODULE_ROOT: Lazy<RwLock<Option<PathBuf>>> = Lazy::new(RwLock::default);
+
+/// Sets the directory JavaScript scripts `import` files from, the working
+/// directory when `None`. Engines read it as they start so it must be set
+/// before the [`ScriptEngine`](crate::interceptor::ScriptEngine) loads scripts.
+pub fn set_module_root(root: Option<PathBuf>) {
+    match MODULE_ROOT.write() {
+        Ok(mut guard) => *guard = root,
+        Err(err) => error!("JS module root lock poisoned {err}"),
+    }
+}
+
+pub(crate) fn module_root() -> PathBuf {
+    match MODULE_ROOT.read() {
+        Ok(guard) => guard.clone().unwrap_or_else(|| PathBuf::from(".")),
+        Err(err) => {
+            error!("JS module root lock poisoned {err}");
+            PathBuf::from(".")
+        }
+    }
+}
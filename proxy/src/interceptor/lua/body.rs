@@ -2,9 +2,11 @@ use std::sync::{Arc, Mutex, MutexGuard};
 
 use bytes::Bytes;
 use mlua::prelude::*;
+use roxy_shared::content::{parse_multipart, sniff_multipart_boundary};
 use tracing::error;
+use url::form_urlencoded;
 
-use crate::interceptor::lua::util::KEY_NEW;
+use crate::interceptor::lua::util::{KEY_NEW, json_to_lua, lua_to_json};
 
 #[derive(Clone, Debug)]
 pub(crate) struct LuaBody {
@@ -47,6 +49,59 @@ impl LuaBody {
         Ok(())
     }
 
+    fn json(&self, lua: &Lua) -> LuaResult<LuaValue> {
+        let g = self.lock()?;
+        let value: serde_json::Value = serde_json::from_slice(&g)
+            .map_err(|e| LuaError::external(format!("invalid JSON: {e}")))?;
+        json_to_lua(lua, &value)
+    }
+
+    fn set_json(&mut self, value: LuaValue) -> LuaResult<()> {
+        let json = serde_json::to_vec(&lua_to_json(value)?).map_err(LuaError::external)?;
+        let mut g = self.lock()?;
+        *g = Bytes::from(json);
+        Ok(())
+    }
+
+    /// The `application/x-www-form-urlencoded` fields, a repeated name keeps
+    /// its last value.
+    fn form(&self, lua: &Lua) -> LuaResult<LuaTable> {
+        let g = self.lock()?;
+        let tbl = lua.create_table()?;
+        for (key, value) in form_urlencoded::parse(&g) {
+            tbl.raw_set(key.as_ref(), value.as_ref())?;
+        }
+        Ok(tbl)
+    }
+
+    /// The parts of a multipart body, `nil` when it is not one. The boundary
+    /// is taken from the first line without `boundary`.
+    fn multipart(&self, lua: &Lua, boundary: Option<String>) -> LuaResult<LuaValue> {
+        let body = self.lock()?.clone();
+        let Some(parts) = boundary
+            .or_else(|| sniff_multipart_boundary(&body))
+            .and_then(|boundary| parse_multipart(&body, &boundary))
+        else {
+            return Ok(LuaValue::Nil);
+        };
+        let tbl = lua.create_table()?;
+        for part in parts {
+            let headers = lua.create_table()?;
+            for (name, value) in &part.headers {
+                headers.raw_set(name.as_str(), lua.create_string(value.as_bytes())?)?;
+            }
+            let entry = lua.create_table()?;
+            entry.raw_set("name", part.name)?;
+            entry.raw_set("filename", part.filename)?;
+            entry.raw_set("content_type", part.content_type)?;
+            entry.raw_set("headers", headers)?;
+            entry.raw_set("text", String::from_utf8_lossy(&part.body).to_string())?;
+            entry.raw_set("raw", lua.create_string(&part.body)?)?;
+            tbl.raw_push(entry)?;
+        }
+        Ok(LuaValue::Table(tbl))
+    }
+
     fn len(&self) -> usize {
         match self.lock() {
             Ok(g) => g.len(),
@@ -80,6 +135,12 @@ impl LuaBody {
 impl LuaUserData for LuaBody {
     fn add_methods<M: LuaUserDataMethods<Self>>(m: &mut M) {
         m.add_method("clear", |_, this, ()| Ok(this.clear()));
+        m.add_method("json", |lua, this, ()| this.json(lua));
+        m.add_method_mut("set_json", |_, this, value: LuaValue| this.set_json(value));
+        m.add_method("form", |lua, this, ()| this.form(lua));
+        m.add_method("multipart", |lua, this, boundary: Option<String>| {
+            this.multipart(lua, boundary)
+        });
 
         m.add_meta_method(LuaMetaMethod::Index, |lua, this, key: LuaValue| {
             let LuaValue::String(s) = key else {
@@ -92,7 +153,7 @@ impl LuaUserData for LuaBody {
                 }
                 "raw" => Ok(LuaValue::String(this.get_raw(lua)?)),
                 "is_empty" => Ok(LuaValue::Boolean(this.is_empty())),
-                "clear" => {
+                "clear" | "json" | "set_json" | "form" | "multipart" => {
                     let ud = lua.create_userdata(this.clone())?;
                     let f: LuaFunction = ud.get(s)?;
                     Ok(LuaValue::Function(f))
@@ -252,6 +313,72 @@ mod tests {
         });
     }
 
+    #[test]
+    fn json_and_form() {
+        with_lua(|lua| {
+            lua.load(
+                r#"
+                local b = Body.new('{"user":{"id":7,"name":"a"},"tags":["x","y"],"next":null}')
+                local v = b:json()
+                assert(v.user.id == 7)
+                assert(v.tags[2] == "y")
+                v.user.name = "b"
+                b:set_json(v)
+                local again = b:json()
+                assert(again.user.name == "b")
+                assert(#again.tags == 2)
+                assert(string.find(b.text, '"next":null', 1, true))
+
+                b:set_json({ 1, 2, 3 })
+                assert(b.text == "[1,2,3]")
+                b.text = "not json"
+                assert(not pcall(function() return b:json() end))
+
+                b.text = "a=1&b=hello+world&a=2"
+                local form = b:form()
+                assert(form.a == "2")
+                assert(form.b == "hello world")
+            "#,
+            )
+            .exec()
+        });
+    }
+
+    #[test]
+    fn multipart_parts() {
+        with_lua(|lua| {
+            lua.load(
+                r#"
+                local b = Body.new(table.concat({
+                    "--b1",
+                    'Content-Disposition: form-data; name="title"',
+                    "",
+                    "hello",
+                    "--b1",
+                    'Content-Disposition: form-data; name="file"; filename="a.txt"',
+                    "Content-Type: text/plain",
+                    "",
+                    "contents",
+                    "--b1--",
+                    "",
+                }, "\r\n"))
+                local parts = b:multipart()
+                assert(#parts == 2)
+                assert(parts[1].name == "title")
+                assert(parts[1].text == "hello")
+                assert(parts[2].filename == "a.txt")
+                assert(parts[2].content_type == "text/plain")
+                assert(parts[2].headers["content-type"] == "text/plain")
+                assert(parts[2].raw == "contents")
+                assert(#b:multipart("b1") == 2)
+                assert(b:multipart("other") == nil)
+                assert(Body.new("plain"):multipart() == nil)
+            "#,
+            )
+            .exec()
+        });
+    }
+
     #[test]
     fn b08_constructor_with_initial_bytes() {
         with_lua(|lua| {
@@ -0,0 +1,40 @@
+use mlua::prelude::*;
+
+use crate::interceptor::{
+    KEY_CLIENT_ADDR, KEY_CLIENT_IP, KEY_EXTENSIONS, KEY_HOST, KEY_PORT, KEY_TLS,
+    connection::{ConnectionHook, ConnectionInfo},
+    lua::flow::LuaTls,
+};
+
+/// The connection as a plain table, changes made to it are not read back.
+fn create_connection(lua: &Lua, conn: &ConnectionInfo) -> LuaResult<LuaTable> {
+    let table = lua.create_table()?;
+    table.set(KEY_CLIENT_ADDR, conn.client_addr.to_string())?;
+    table.set(KEY_CLIENT_IP, conn.client_addr.ip().to_string())?;
+    table.set(KEY_HOST, conn.host.clone())?;
+    table.set(KEY_PORT, conn.port)?;
+    if let Some(tls) = &conn.tls {
+        table.set(KEY_TLS, LuaTls(tls.clone()))?;
+    }
+    Ok(table)
+}
+
+/// Calls the `hook` handler of every extension with the connection, false
+/// once one returns `false`.
+pub(crate) fn run_connection_handlers(
+    lua: &Lua,
+    hook: ConnectionHook,
+    conn: &ConnectionInfo,
+) -> LuaResult<bool> {
+    let extensions: LuaTable = lua.globals().get(KEY_EXTENSIONS)?;
+    let conn = create_connection(lua, conn)?;
+    for ext in extensions.sequence_values::<LuaTable>() {
+        let Ok(handler) = ext?.get::<LuaFunction>(hook.key()) else {
+            continue;
+        };
+        if let LuaValue::Boolean(false) = handler.call::<LuaValue>(conn.clone())? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
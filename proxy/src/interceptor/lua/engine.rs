@@ -1,4 +1,7 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 
 use async_trait::async_trait;
 use mlua::{Function, Lua, Table, Value, Variadic};
@@ -8,24 +11,33 @@ use tracing::{debug, error, info, trace, warn};
 use crate::{
     flow::{InterceptedRequest, InterceptedResponse},
     interceptor::{
-        Error, FlowNotify, KEY_EXTENSIONS, KEY_INTERCEPT_REQUEST, KEY_INTERCEPT_RESPONSE,
-        KEY_START, KEY_STOP, RoxyEngine,
+        Error, FlowNotify, KEY_EXTENSIONS, KEY_FETCH, KEY_INTERCEPT_REQUEST,
+        KEY_INTERCEPT_RESPONSE, KEY_JSON, KEY_START, KEY_STOP, KEY_STORE, RoxyEngine,
+        connection::{ConnectionHook, ConnectionInfo},
         lua::{
             body::register_body,
+            connection::run_connection_handlers,
             constants::register_constants,
+            fetch::create_fetch,
             flow::{LuaFlow, register_flow},
             headers::register_headers,
+            json::create_json,
             query::register_query,
             request::{LuaRequest, register_request},
             response::{LuaResponse, register_response},
+            store::create_store,
             url::register_url,
         },
+        lua_path::lua_path,
+        tags::ScriptTags,
     },
 };
 
 const ROXY: &str = "Roxy";
 const NOTIFY: &str = "notify";
 const PRINT: &str = "print";
+const PACKAGE: &str = "package";
+const PATH: &str = "path";
 
 #[derive(Debug)]
 pub struct LuaEngine {
@@ -81,6 +93,19 @@ impl RoxyEngine for LuaEngine {
         Ok(())
     }
 
+    async fn on_connection(
+        &self,
+        hook: ConnectionHook,
+        conn: &ConnectionInfo,
+    ) -> Result<bool, Error> {
+        trace!("on_connection {hook}");
+        let guard = self.inner.lock().map_err(|_| Error::InterceptedRequest)?;
+        match &guard.lua {
+            Some(lua) => Ok(run_connection_handlers(lua, hook, conn)?),
+            None => Ok(true),
+        }
+    }
+
     async fn on_stop(&self) -> Result<(), Error> {
         debug!("on_stop");
         self.inner
@@ -121,7 +146,8 @@ impl Inner {
         self.on_stop()?;
         let lua = Lua::new();
         register_functions(&lua, self.notify_tx.clone())?;
-        lua.load(script).exec()?;
+        add_package_path(&lua, &lua_path())?;
+        lua.load(script).set_name("=roxyscript.lua").exec()?;
         let extensions: Table = lua
             .globals()
             .get(KEY_EXTENSIONS)
@@ -167,6 +193,8 @@ fn intercept_request_inner(
         return Ok(None);
     }
 
+    let tls = req.tls.clone();
+    let tags = ScriptTags::new(req.tags.clone());
     let req_arc = Arc::new(Mutex::new(std::mem::take(req)));
 
     let resp_inner = InterceptedResponse::default();
@@ -174,7 +202,12 @@ fn intercept_request_inner(
 
     let lua_req = LuaRequest::from_parts(req_arc.clone())?;
     let lua_resp = LuaResponse::from_parts(resp_arc.clone())?;
-    let flow_ud = lua.create_userdata(LuaFlow::from_views(lua_req.clone(), lua_resp.clone()))?;
+    let flow_ud = lua.create_userdata(LuaFlow::from_views(
+        lua_req.clone(),
+        lua_resp.clone(),
+        tls,
+        tags.clone(),
+    ))?;
 
     let mut handlers: Vec<Function> = Vec::new();
     for pair in extensions.pairs::<Value, Table>() {
@@ -207,6 +240,7 @@ fn intercept_request_inner(
             .lock()
             .map_err(|e| Error::Other(format!("lock: {e}")))?;
         *req = guard.clone();
+        req.tags = tags.list();
 
         req.headers = lua_req
             .headers
@@ -286,8 +320,14 @@ pub fn intercept_response_inner(
     let lua_resp = LuaResponse::from_parts(res_arc.clone())
         .map_err(|e| Error::Other(format!("LuaResponse::from_parts: {e}")))?;
 
+    let tags = ScriptTags::new(res.tags.clone());
     let flow_ud = lua
-        .create_userdata(LuaFlow::from_views(lua_req, lua_resp.clone()))
+        .create_userdata(LuaFlow::from_views(
+            lua_req,
+            lua_resp.clone(),
+            res.tls.clone(),
+            tags.clone(),
+        ))
         .map_err(|e| Error::Other(format!("create flow userdata: {e}")))?;
 
     for h in handlers {
@@ -300,6 +340,7 @@ pub fn intercept_response_inner(
             .lock()
             .map_err(|e| Error::Other(format!("lock poisoned: {e}")))?;
         *res = guard.clone();
+        res.tags = tags.list();
         res.body = lua_resp
             .body
             .inner
@@ -327,6 +368,23 @@ pub fn intercept_response_inner(
     Ok(())
 }
 
+/// Puts `?.lua` and `?/init.lua` in each of `dirs` ahead of the default
+/// `package.path`. Each load starts a fresh state, so required files are read
+/// again when the script is reloaded.
+fn add_package_path(lua: &Lua, dirs: &[PathBuf]) -> Result<(), mlua::Error> {
+    if dirs.is_empty() {
+        return Ok(());
+    }
+    let package: Table = lua.globals().get(PACKAGE)?;
+    let mut path: Vec<String> = dirs
+        .iter()
+        .flat_map(|dir| [dir.join("?.lua"), dir.join("?").join("init.lua")])
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+    path.push(package.get(PATH)?);
+    package.set(PATH, path.join(";"))
+}
+
 pub(crate) fn register_functions(
     lua: &Lua,
     notify: Option<mpsc::Sender<FlowNotify>>,
@@ -335,10 +393,7 @@ pub(crate) fn register_functions(
 
     let lua_notify = if let Some(notify) = notify {
         lua.create_function(move |_, (level, msg): (i32, String)| {
-            if let Err(e) = notify.try_send(FlowNotify {
-                level: level.into(),
-                msg,
-            }) {
+            if let Err(e) = notify.try_send(FlowNotify::new(level.into(), msg)) {
                 error!("Notify error {e}");
             }
             Ok(())
@@ -374,10 +429,14 @@ pub(crate) fn register_functions(
     })?;
 
     globals.set(KEY_EXTENSIONS, lua.create_table()?)?;
-    globals.set(
-        ROXY,
-        lua.create_table_from([(NOTIFY, lua_notify), (PRINT, print)])?,
-    )?;
+    let roxy = lua.create_table_from([
+        (NOTIFY, lua_notify),
+        (PRINT, print),
+        (KEY_FETCH, create_fetch(lua)?),
+    ])?;
+    roxy.set(KEY_STORE, create_store(lua)?)?;
+    roxy.set(KEY_JSON, create_json(lua)?)?;
+    globals.set(ROXY, roxy)?;
 
     let print_fn = lua.create_function(|_, args: Variadic<Value>| {
         let output: Vec<String> = args.iter().map(|v| format!("{v:?}")).collect();
@@ -397,3 +456,32 @@ pub(crate) fn register_functions(
 
     Ok(())
 }
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requires_from_package_path() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("helpers")).unwrap();
+        std::fs::write(
+            dir.path().join("helpers").join("init.lua"),
+            "return { double = function(x) return x * 2 end }",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("greet.lua"), "return 'hello'").unwrap();
+
+        let lua = Lua::new();
+        add_package_path(&lua, &[dir.path().to_path_buf()]).unwrap();
+        lua.load(
+            r#"
+            assert(require("helpers").double(21) == 42)
+            assert(require("greet") == "hello")
+            "#,
+        )
+        .exec()
+        .unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use mlua::prelude::*;
+
+use crate::interceptor::{
+    KEY_BODY, KEY_HEADERS, KEY_METHOD, KEY_TIMEOUT,
+    fetch::{FetchOptions, fetch},
+    lua::{response::LuaResponse, util::lua_val_to_str},
+};
+
+/// `Roxy.fetch(url, options)`, returns a `Response` or raises on failure.
+pub(crate) fn create_fetch(lua: &Lua) -> LuaResult<LuaFunction> {
+    lua.create_function(|_, (url, options): (String, Option<LuaTable>)| {
+        let options = fetch_options(options)?;
+        let response = fetch(&url, options).map_err(|e| LuaError::external(e.to_string()))?;
+        LuaResponse::from_parts(Arc::new(Mutex::new(response)))
+    })
+}
+
+fn fetch_options(options: Option<LuaTable>) -> LuaResult<FetchOptions> {
+    let Some(options) = options else {
+        return Ok(FetchOptions::default());
+    };
+    let headers = match options.get::<Option<LuaTable>>(KEY_HEADERS)? {
+        Some(headers) => headers
+            .pairs::<String, LuaValue>()
+            .map(|pair| {
+                let (name, value) = pair?;
+                Ok((name, lua_val_to_str(value)?))
+            })
+            .collect::<LuaResult<Vec<_>>>()?,
+        None => vec![],
+    };
+    Ok(FetchOptions {
+        method: options.get(KEY_METHOD)?,
+        headers,
+        body: options
+            .get::<Option<LuaString>>(KEY_BODY)?
+            .map(|s| Bytes::from(s.as_bytes().to_vec())),
+        timeout: options
+            .get::<Option<u64>>(KEY_TIMEOUT)?
+            .map(Duration::from_millis),
+    })
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use cow_utils::CowUtils;
+
+    use crate::interceptor::{fetch::tests::serve_once, lua::tests::with_lua};
+
+    #[test]
+    fn fetch_from_script() {
+        let (url, rx) = serve_once("HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\ntoken");
+        with_lua(|lua| {
+            lua.globals().set("url", url)?;
+            lua.load(
+                r#"
+                local res = Roxy.fetch(url, {
+                  method = "POST",
+                  headers = { ["x-hook"] = "1" },
+                  body = "hello",
+                  timeout = 1000,
+                })
+                assert(res.status == 200)
+                assert(res.body.text == "token")
+                "#,
+            )
+            .exec()
+        });
+        let received = rx.recv().unwrap().cow_to_ascii_lowercase().into_owned();
+        assert!(received.starts_with("post "));
+        assert!(received.contains("/hook"));
+        assert!(received.contains("x-hook: 1"));
+    }
+
+    #[test]
+    fn fetch_errors_raise() {
+        with_lua(|lua| {
+            let result = lua.load(r#"Roxy.fetch("not a url")"#).exec();
+            assert!(result.is_err());
+            let caught: bool = lua
+                .load(r#"return pcall(Roxy.fetch, "/relative") == false"#)
+                .eval()?;
+            assert!(caught);
+            Ok(())
+        });
+    }
+}
@@ -2,9 +2,14 @@ use std::sync::{Arc, Mutex};
 
 use mlua::prelude::*;
 
-use crate::interceptor::{
-    KEY_REQUEST, KEY_RESPONSE,
-    lua::{request::LuaRequest, response::LuaResponse, util::KEY_NEW},
+use crate::{
+    flow::FlowTls,
+    interceptor::{
+        KEY_ALPN, KEY_CIPHER, KEY_PEER_SUBJECT, KEY_REQUEST, KEY_RESPONSE, KEY_SNI, KEY_TAGS,
+        KEY_TLS, KEY_VERSION,
+        lua::{request::LuaRequest, response::LuaResponse, util::KEY_NEW},
+        tags::ScriptTags,
+    },
 };
 
 #[derive(Clone, Debug, Default)]
@@ -16,12 +21,24 @@ pub(crate) struct LuaFlow {
 struct FlowInner {
     request: LuaRequest,
     response: LuaResponse,
+    tls: Option<FlowTls>,
+    tags: ScriptTags,
 }
 
 impl LuaFlow {
-    pub fn from_views(request: LuaRequest, response: LuaResponse) -> Self {
+    pub fn from_views(
+        request: LuaRequest,
+        response: LuaResponse,
+        tls: Option<FlowTls>,
+        tags: ScriptTags,
+    ) -> Self {
         Self {
-            inner: Arc::new(Mutex::new(FlowInner { request, response })),
+            inner: Arc::new(Mutex::new(FlowInner {
+                request,
+                response,
+                tls,
+                tags,
+            })),
         }
     }
 
@@ -48,6 +65,17 @@ impl LuaUserData for LuaFlow {
                         let ud = lua.create_userdata(resp)?;
                         return Ok(LuaValue::UserData(ud));
                     }
+                    KEY_TLS => {
+                        if let Some(tls) = this.lock()?.tls.clone() {
+                            let ud = lua.create_userdata(LuaTls(tls))?;
+                            return Ok(LuaValue::UserData(ud));
+                        }
+                    }
+                    KEY_TAGS => {
+                        let tags = this.lock()?.tags.clone();
+                        let ud = lua.create_userdata(LuaTags(tags))?;
+                        return Ok(LuaValue::UserData(ud));
+                    }
                     _ => {}
                 }
             }
@@ -58,6 +86,42 @@ impl LuaUserData for LuaFlow {
     }
 }
 
+/// Read-only, there is no `__newindex` so assignments raise an error.
+#[derive(Clone, Debug)]
+pub(crate) struct LuaTls(pub(crate) FlowTls);
+
+impl LuaUserData for LuaTls {
+    fn add_methods<M: LuaUserDataMethods<Self>>(m: &mut M) {
+        m.add_meta_method(LuaMetaMethod::Index, |_, this, key: String| {
+            let value = match key.as_str() {
+                KEY_VERSION => &this.0.version,
+                KEY_CIPHER => &this.0.cipher,
+                KEY_ALPN => &this.0.alpn,
+                KEY_SNI => &this.0.sni,
+                KEY_PEER_SUBJECT => &this.0.peer_subject,
+                _ => return Ok(None),
+            };
+            Ok(value.clone())
+        });
+    }
+}
+
+/// `add`, `has` and `list`, tags can't be removed once added.
+#[derive(Clone, Debug)]
+struct LuaTags(ScriptTags);
+
+impl LuaUserData for LuaTags {
+    fn add_methods<M: LuaUserDataMethods<Self>>(m: &mut M) {
+        m.add_method("add", |_, this, tag: String| {
+            this.0.add(&tag);
+            Ok(())
+        });
+        m.add_method("has", |_, this, tag: String| Ok(this.0.has(&tag)));
+        m.add_method("list", |_, this, ()| Ok(this.0.list()));
+        m.add_meta_method(LuaMetaMethod::Len, |_, this, ()| Ok(this.0.list().len()));
+    }
+}
+
 pub(crate) fn register_flow(lua: &Lua) -> LuaResult<LuaTable> {
     let tbl = lua.create_table()?;
     let new = lua.create_function(move |lua, ()| {
@@ -72,7 +136,15 @@ pub(crate) fn register_flow(lua: &Lua) -> LuaResult<LuaTable> {
 #[allow(clippy::unwrap_used, clippy::expect_used, clippy::panic)]
 #[cfg(test)]
 mod tests {
-    use crate::interceptor::lua::tests::with_lua;
+    use mlua::prelude::*;
+
+    use crate::{
+        flow::FlowTls,
+        interceptor::{
+            lua::{flow::LuaFlow, request::LuaRequest, response::LuaResponse, tests::with_lua},
+            tags::ScriptTags,
+        },
+    };
 
     #[test]
     fn f01_flow_has_request_and_response_userdata() {
@@ -164,4 +236,64 @@ mod tests {
             .exec()
         });
     }
+
+    #[test]
+    fn f07_tls_is_read_only() {
+        with_lua(|lua| {
+            let tls = FlowTls {
+                version: Some("TLSv1_3".to_string()),
+                alpn: Some("h2".to_string()),
+                peer_subject: Some("CN=example.com".to_string()),
+                ..Default::default()
+            };
+            let flow = LuaFlow::from_views(
+                LuaRequest::default(),
+                LuaResponse::default(),
+                Some(tls),
+                ScriptTags::default(),
+            );
+            lua.globals().set("flow", lua.create_userdata(flow)?)?;
+            lua.load(
+                r#"
+                assert(Flow.new().tls == nil, "plain flows have no tls")
+                assert(flow.tls.version == "TLSv1_3")
+                assert(flow.tls.alpn == "h2")
+                assert(flow.tls.cipher == nil)
+                assert(flow.tls.peer_subject == "CN=example.com")
+                local ok = pcall(function() flow.tls.version = "TLSv1_0" end)
+                assert(not ok, "flow.tls must be read-only")
+            "#,
+            )
+            .exec()
+        });
+    }
+
+    #[test]
+    fn f08_tags() {
+        with_lua(|lua| {
+            let tags = ScriptTags::new(vec!["seen".to_string()]);
+            let flow = LuaFlow::from_views(
+                LuaRequest::default(),
+                LuaResponse::default(),
+                None,
+                tags.clone(),
+            );
+            lua.globals().set("flow", lua.create_userdata(flow)?)?;
+            lua.load(
+                r#"
+                assert(flow.tags:has("seen"))
+                assert(not flow.tags:has("auth"))
+                flow.tags:add("auth")
+                flow.tags:add("auth")
+                flow.tags:add("")
+                assert(#flow.tags == 2)
+                local list = flow.tags:list()
+                assert(list[1] == "seen" and list[2] == "auth")
+            "#,
+            )
+            .exec()?;
+            assert_eq!(tags.list(), vec!["seen", "auth"]);
+            Ok(())
+        });
+    }
 }
@@ -0,0 +1,53 @@
+use mlua::prelude::*;
+
+use crate::interceptor::lua::util::{json_to_lua, lua_to_json};
+
+/// `Roxy.json`, `encode(value, pretty)` and `decode(text)` with the same
+/// conversions as `Body:json`. `Roxy.json.null` is what `null` decodes to.
+pub(crate) fn create_json(lua: &Lua) -> LuaResult<LuaTable> {
+    let encode = lua.create_function(|_, (value, pretty): (LuaValue, Option<bool>)| {
+        let value = lua_to_json(value)?;
+        if pretty.unwrap_or(false) {
+            serde_json::to_string_pretty(&value)
+        } else {
+            serde_json::to_string(&value)
+        }
+        .map_err(LuaError::external)
+    })?;
+    let decode = lua.create_function(|lua, text: LuaString| {
+        let value: serde_json::Value =
+            serde_json::from_slice(&text.as_bytes()).map_err(LuaError::external)?;
+        json_to_lua(lua, &value)
+    })?;
+    let json = lua.create_table()?;
+    json.set("encode", encode)?;
+    json.set("decode", decode)?;
+    json.set("null", LuaValue::NULL)?;
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interceptor::lua::tests::with_lua;
+
+    #[test]
+    fn json_from_script() {
+        with_lua(|lua| {
+            lua.load(
+                r#"
+                local value = Roxy.json.decode('{"name":"roxy","tags":["a","b"],"gone":null}')
+                assert(value.name == "roxy")
+                assert(#value.tags == 2 and value.tags[2] == "b")
+                assert(value.gone == Roxy.json.null)
+                assert(Roxy.json.encode({ 1, 2, 3 }) == "[1,2,3]")
+                assert(Roxy.json.encode({ ok = true }) == '{"ok":true}')
+                assert(Roxy.json.encode(Roxy.json.null) == "null")
+                assert(Roxy.json.encode({ a = 1 }, true) == '{\n  "a": 1\n}')
+                assert(not pcall(Roxy.json.decode, "{"))
+                assert(not pcall(Roxy.json.encode, { f = print }))
+                "#,
+            )
+            .exec()
+        });
+    }
+}
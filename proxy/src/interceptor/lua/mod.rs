@@ -1,11 +1,15 @@
 mod body;
+mod connection;
 mod constants;
 pub mod engine;
+mod fetch;
 mod flow;
 mod headers;
+mod json;
 mod query;
 mod request;
 mod response;
+mod store;
 mod url;
 mod util;
 
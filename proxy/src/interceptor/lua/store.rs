@@ -0,0 +1,48 @@
+use mlua::prelude::*;
+
+use crate::interceptor::{lua::util::lua_val_to_str, store::STORE};
+
+/// `Roxy.store`, `get`, `set` and `delete` on the shared script store.
+/// Setting `nil` removes the key.
+pub(crate) fn create_store(lua: &Lua) -> LuaResult<LuaTable> {
+    let get = lua.create_function(|_, key: String| Ok(STORE.get(&key)))?;
+    let set = lua.create_function(|_, (key, value): (String, LuaValue)| {
+        let value = match value {
+            LuaValue::Nil => None,
+            value => Some(lua_val_to_str(value)?),
+        };
+        STORE.set(&key, value);
+        Ok(())
+    })?;
+    let delete = lua.create_function(|_, key: String| {
+        STORE.set(&key, None);
+        Ok(())
+    })?;
+    lua.create_table_from([("get", get), ("set", set), ("delete", delete)])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interceptor::lua::tests::with_lua;
+
+    #[test]
+    fn store_from_script() {
+        with_lua(|lua| {
+            lua.load(
+                r#"
+                assert(Roxy.store.get("lua_count") == nil)
+                Roxy.store.set("lua_count", 1)
+                Roxy.store.set("lua_token", "abc")
+                assert(Roxy.store.get("lua_count") == "1")
+                Roxy.store.set("lua_count", tonumber(Roxy.store.get("lua_count")) + 1)
+                assert(Roxy.store.get("lua_count") == "2")
+                Roxy.store.delete("lua_count")
+                Roxy.store.set("lua_token", nil)
+                assert(Roxy.store.get("lua_count") == nil)
+                assert(Roxy.store.get("lua_token") == nil)
+                "#,
+            )
+            .exec()
+        });
+    }
+}
@@ -1,4 +1,5 @@
 use mlua::prelude::*;
+use serde_json::{Map, Number, Value};
 
 pub(crate) const KEY_NEW: &str = "new";
 
@@ -27,3 +28,70 @@ pub(crate) fn lua_val_to_str(val: LuaValue) -> LuaResult<String> {
         }
     })
 }
+
+/// A JSON value as a Lua value, `null` becomes [`LuaValue::NULL`] so it is
+/// kept inside tables.
+pub(crate) fn json_to_lua(lua: &Lua, value: &Value) -> LuaResult<LuaValue> {
+    Ok(match value {
+        Value::Null => LuaValue::NULL,
+        Value::Bool(b) => LuaValue::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => LuaValue::Integer(i),
+            None => LuaValue::Number(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => LuaValue::String(lua.create_string(s)?),
+        Value::Array(items) => {
+            let tbl = lua.create_table()?;
+            for item in items {
+                tbl.raw_push(json_to_lua(lua, item)?)?;
+            }
+            LuaValue::Table(tbl)
+        }
+        Value::Object(map) => {
+            let tbl = lua.create_table()?;
+            for (key, item) in map {
+                tbl.raw_set(key.as_str(), json_to_lua(lua, item)?)?;
+            }
+            LuaValue::Table(tbl)
+        }
+    })
+}
+
+/// A Lua value as JSON. Tables holding only the keys `1..n` are arrays, any
+/// other table is an object.
+pub(crate) fn lua_to_json(value: LuaValue) -> LuaResult<Value> {
+    Ok(match value {
+        LuaValue::Nil => Value::Null,
+        LuaValue::LightUserData(ud) if ud.0.is_null() => Value::Null,
+        LuaValue::Boolean(b) => Value::Bool(b),
+        LuaValue::Integer(i) => Value::from(i),
+        LuaValue::Number(n) => Number::from_f64(n)
+            .map(Value::Number)
+            .ok_or_else(|| LuaError::external(format!("{n} is not a JSON number")))?,
+        LuaValue::String(s) => Value::String(s.to_str()?.to_string()),
+        LuaValue::Table(tbl) => {
+            let pairs = tbl
+                .pairs::<LuaValue, LuaValue>()
+                .collect::<LuaResult<Vec<_>>>()?;
+            let len = tbl.raw_len();
+            if len > 0 && pairs.len() == len {
+                Value::Array(
+                    (1..=len)
+                        .map(|i| tbl.raw_get::<LuaValue>(i).and_then(lua_to_json))
+                        .collect::<LuaResult<_>>()?,
+                )
+            } else {
+                let mut map = Map::new();
+                for (key, item) in pairs {
+                    map.insert(lua_val_to_str(key)?, lua_to_json(item)?);
+                }
+                Value::Object(map)
+            }
+        }
+        other => {
+            return Err(LuaError::external(format!(
+                "unsupported JSON value type: {other:?}"
+            )));
+        }
+    })
+}
@@ -0,0 +1,25 @@
+use std::{path::PathBuf, sync::RwLock};
+
+use once_cell::sync::Lazy;
+use tracing::error;
+
+static LUA_PATH: Lazy<RwLock<Vec<PathBuf>>> = Lazy::new(RwLock::default);
+
+/// Sets the directories Lua scripts `require` modules from, searched before
+/// the default `package.path`. Takes effect as scripts are next loaded.
+pub fn set_lua_path(dirs: Vec<PathBuf>) {
+    match LUA_PATH.write() {
+        Ok(mut guard) => *guard = dirs,
+        Err(err) => error!("Lua path lock poisoned {err}"),
+    }
+}
+
+pub(crate) fn lua_path() -> Vec<PathBuf> {
+    match LUA_PATH.read() {
+        Ok(guard) => guard.clone(),
+        Err(err) => {
+            error!("Lua path lock poisoned {err}");
+            vec![]
+        }
+    }
+}
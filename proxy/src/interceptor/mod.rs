@@ -1,16 +1,30 @@
-use std::fmt::Display;
+use std::{fmt::Display, path::Path};
 
 use async_trait::async_trait;
-use strum::EnumIter;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use strum::{EnumIter, IntoEnumIterator};
 
 use crate::{
-    flow::{InterceptedRequest, InterceptedResponse},
-    interceptor::{js::engine::JsEngine, lua::engine::LuaEngine, py::engine::PythonEngine},
+    flow::{Flow, FlowStore, InterceptedRequest, InterceptedResponse, ScriptLog, id::FlowId},
+    interceptor::{
+        connection::{ConnectionHook, ConnectionInfo},
+        js::engine::JsEngine,
+        lua::engine::LuaEngine,
+        py::engine::PythonEngine,
+    },
 };
 
+pub mod connection;
+mod fetch;
 mod js;
+pub mod js_modules;
 mod lua;
+pub mod lua_path;
 mod py;
+pub mod python_env;
+pub mod store;
+mod tags;
 mod util;
 
 use std::{fmt::Debug, sync::Arc};
@@ -18,15 +32,28 @@ use tokio::sync::{
     Mutex,
     mpsc::{self},
 };
-use tracing::trace;
+use tracing::{debug, error, trace, warn};
+
+/// Notifications a script can queue in one call before the rest are dropped.
+const NOTIFY_CAPACITY: usize = 256;
 
 const KEY_EXTENSIONS: &str = "Extensions";
 const KEY_NOTIFY: &str = "notify";
+const KEY_FETCH: &str = "fetch";
+const KEY_SET_TIMEOUT: &str = "setTimeout";
+const KEY_CLEAR_TIMEOUT: &str = "clearTimeout";
+const KEY_QUEUE_MICROTASK: &str = "queueMicrotask";
+const KEY_STORE: &str = "store";
+const KEY_JSON: &str = "json";
+const KEY_TIMEOUT: &str = "timeout";
 
 const KEY_START: &str = "start";
 const KEY_STOP: &str = "stop";
 const KEY_INTERCEPT_REQUEST: &str = "request";
 const KEY_INTERCEPT_RESPONSE: &str = "response";
+const KEY_ON_CLIENT_CONNECT: &str = "on_client_connect";
+const KEY_ON_SERVER_CONNECT: &str = "on_server_connect";
+const KEY_ON_TLS_ESTABLISHED: &str = "on_tls_established";
 
 const KEY_REQUEST: &str = "request";
 const KEY_RESPONSE: &str = "response";
@@ -53,6 +80,17 @@ const KEY_TRAILERS: &str = "trailers";
 
 const KEY_STATUS: &str = "status";
 
+const KEY_TLS: &str = "tls";
+const KEY_CIPHER: &str = "cipher";
+const KEY_ALPN: &str = "alpn";
+const KEY_SNI: &str = "sni";
+const KEY_PEER_SUBJECT: &str = "peer_subject";
+
+const KEY_TAGS: &str = "tags";
+
+const KEY_CLIENT_ADDR: &str = "client_addr";
+const KEY_CLIENT_IP: &str = "client_ip";
+
 #[async_trait]
 pub trait RoxyEngine: Send + Sync {
     async fn intercept_request(
@@ -66,44 +104,20 @@ pub trait RoxyEngine: Send + Sync {
         res: &mut InterceptedResponse,
     ) -> Result<(), Error>;
 
-    async fn set_script(&self, script: &str) -> Result<(), Error>;
-
-    async fn on_stop(&self) -> Result<(), Error>;
-}
-
-struct NoopEngine {}
-
-#[async_trait]
-impl RoxyEngine for NoopEngine {
-    async fn intercept_request(
+    /// Runs the `hook` handlers of the script on `conn`, false when one
+    /// returned `false` to reject the connection.
+    async fn on_connection(
         &self,
-        _req: &mut InterceptedRequest,
-    ) -> Result<Option<InterceptedResponse>, Error> {
-        trace!("Noop intercept_request");
-        Ok(None)
-    }
-
-    async fn intercept_response(
-        &self,
-        _req: &InterceptedRequest,
-        _res: &mut InterceptedResponse,
-    ) -> Result<(), Error> {
-        trace!("Noop intercept_response");
-        Ok(())
-    }
+        hook: ConnectionHook,
+        conn: &ConnectionInfo,
+    ) -> Result<bool, Error>;
 
-    async fn set_script(&self, _script: &str) -> Result<(), Error> {
-        trace!("Noop set script");
-        Ok(())
-    }
+    async fn set_script(&self, script: &str) -> Result<(), Error>;
 
-    async fn on_stop(&self) -> Result<(), Error> {
-        trace!("Noop on_stop");
-        Ok(())
-    }
+    async fn on_stop(&self) -> Result<(), Error>;
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FlowNotifyLevel {
     Info = 0,
     Warn = 1,
@@ -128,11 +142,18 @@ impl From<i32> for FlowNotifyLevel {
 pub struct FlowNotify {
     pub level: FlowNotifyLevel,
     pub msg: String,
+    /// The flow the script was intercepting when it sent this, `None` from
+    /// start and stop handlers.
+    pub flow_id: Option<FlowId>,
 }
 
 impl FlowNotify {
     fn new(level: FlowNotifyLevel, msg: String) -> Self {
-        Self { level, msg }
+        Self {
+            level,
+            msg,
+            flow_id: None,
+        }
     }
 }
 
@@ -164,7 +185,7 @@ impl From<pyo3::PyErr> for Error {
     }
 }
 
-#[derive(Debug, Clone, Copy, EnumIter)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter)]
 pub enum ScriptType {
     Js,
     Lua,
@@ -179,6 +200,12 @@ impl ScriptType {
             ScriptType::Python => "py",
         }
     }
+
+    /// The type matching the extension of `path`, if any.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?;
+        ScriptType::iter().find(|t| t.ext().eq_ignore_ascii_case(ext))
+    }
 }
 
 impl Display for ScriptType {
@@ -195,10 +222,69 @@ impl Display for Error {
     }
 }
 
+/// Finds the line in the messages of the engines: `roxyscript.lua:3:` from
+/// Lua, `(roxyscript.py, line 3)` from Python and `at line 3, col 5` from JS.
+static ERROR_LINE: Lazy<Option<Regex>> =
+    Lazy::new(|| Regex::new(r"roxyscript\.lua:(\d+):|line (\d+)").ok());
+
+/// The last error a script failed to load or run with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub message: String,
+    /// The 1 based line of the script the error points at, when the engine
+    /// reported one.
+    pub line: Option<usize>,
+}
+
+impl From<&Error> for ScriptError {
+    fn from(err: &Error) -> Self {
+        let message = match err {
+            Error::Lua(err) => err.to_string(),
+            Error::Other(message) => message.clone(),
+            err => err.to_string(),
+        };
+        let line = ERROR_LINE
+            .as_ref()
+            .and_then(|re| re.captures(&message))
+            .and_then(|c| c.get(1).or_else(|| c.get(2)))
+            .and_then(|m| m.as_str().parse().ok());
+        ScriptError { message, line }
+    }
+}
+
+/// A script loaded into a [`ScriptEngine`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptInfo {
+    pub name: String,
+    pub script_type: ScriptType,
+    pub enabled: bool,
+    /// Bodies are handed to the script without their content encoding, and
+    /// encoded again once it returns. Off, the script sees them as they went
+    /// over the wire.
+    pub decode_bodies: bool,
+    /// Set when the script last failed to reload or to run, cleared once it
+    /// loads again.
+    pub error: Option<ScriptError>,
+}
+
+struct LoadedScript {
+    info: ScriptInfo,
+    source: String,
+    engine: Box<dyn RoxyEngine>,
+}
+
+/// Runs an ordered list of scripts, possibly of different languages, on
+/// every request and response. Each script sees the changes of the ones
+/// before it, and the first to answer a request stops the rest.
 #[derive(Clone)]
 pub struct ScriptEngine {
     notify_tx: Option<mpsc::Sender<FlowNotify>>,
-    inner: Arc<Mutex<Box<dyn RoxyEngine>>>,
+    /// What the scripts send notifications on. Intercepts run one at a time,
+    /// so whatever is queued after one returns came from the flow it ran on.
+    script_notify_tx: mpsc::Sender<FlowNotify>,
+    script_notify_rx: Arc<std::sync::Mutex<mpsc::Receiver<FlowNotify>>>,
+    flow_store: Arc<std::sync::RwLock<Option<FlowStore>>>,
+    inner: Arc<Mutex<Vec<LoadedScript>>>,
 }
 
 impl Debug for ScriptEngine {
@@ -217,44 +303,290 @@ impl ScriptEngine {
     }
 
     fn new_inner(notify_tx: Option<mpsc::Sender<FlowNotify>>) -> Self {
+        let (script_notify_tx, script_notify_rx) = mpsc::channel(NOTIFY_CAPACITY);
         Self {
             notify_tx,
-            inner: Arc::new(Mutex::new(Box::new(NoopEngine {}))),
+            script_notify_tx,
+            script_notify_rx: Arc::new(std::sync::Mutex::new(script_notify_rx)),
+            flow_store: Arc::default(),
+            inner: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Sends a notification of the proxy itself on the channel the scripts'
+    /// go to, dropped when none was given or it is full.
+    pub(crate) fn notify(&self, level: FlowNotifyLevel, msg: String) {
+        if let Some(tx) = &self.notify_tx {
+            let _ = tx.try_send(FlowNotify::new(level, msg));
+        }
+    }
+
+    /// Keeps the notifications scripts send while intercepting a flow with
+    /// that flow in `flow_store`, as well as passing them on.
+    pub fn set_flow_store(&self, flow_store: FlowStore) {
+        match self.flow_store.write() {
+            Ok(mut guard) => *guard = Some(flow_store),
+            Err(err) => error!("Flow store lock poisoned {err}"),
+        }
+    }
+
+    /// Passes on the notifications queued by the scripts, tagged with
+    /// `flow_id`.
+    fn forward_notifications(&self, flow_id: Option<FlowId>) {
+        let mut rx = match self.script_notify_rx.lock() {
+            Ok(rx) => rx,
+            Err(err) => {
+                error!("Notify lock poisoned {err}");
+                return;
+            }
+        };
+        let flow_store = match self.flow_store.read() {
+            Ok(guard) => guard.clone(),
+            Err(err) => {
+                error!("Flow store lock poisoned {err}");
+                None
+            }
+        };
+        while let Ok(mut notify) = rx.try_recv() {
+            notify.flow_id = flow_id;
+            if let (Some(flow_store), Some(id)) = (&flow_store, flow_id) {
+                flow_store.log_script(id, ScriptLog::new(notify.level, notify.msg.clone()));
+            }
+            match &self.notify_tx {
+                Some(tx) => {
+                    if let Err(err) = tx.try_send(notify) {
+                        error!("Notify error {err}");
+                    }
+                }
+                None => debug!("Script notify {:?} {}", notify.level, notify.msg),
+            }
         }
     }
 
+    /// Runs the request of flow `flow_id` through the scripts.
     pub async fn intercept_request(
         &self,
+        flow_id: FlowId,
         req: &mut InterceptedRequest,
     ) -> Result<Option<InterceptedResponse>, Error> {
         trace!("intercept_request");
-        let guard = self.inner.lock().await;
-        guard.intercept_request(req).await
+        let mut guard = self.inner.lock().await;
+        for script in guard.iter_mut().filter(|s| s.info.enabled) {
+            let before = req.before_script(script.info.decode_bodies);
+            let response = script.engine.intercept_request(req).await;
+            req.after_script(before);
+            self.forward_notifications(Some(flow_id));
+            if let Err(err) = &response {
+                script.info.error = Some(err.into());
+            }
+            if let Some(response) = response? {
+                trace!("{} answered the request", script.info.name);
+                return Ok(Some(response));
+            }
+        }
+        Ok(None)
     }
 
+    /// Runs the response of flow `flow_id` through the scripts.
     pub async fn intercept_response(
         &self,
+        flow_id: FlowId,
         req: &InterceptedRequest,
         res: &mut InterceptedResponse,
     ) -> Result<(), Error> {
         trace!("intercept_response");
-        let guard = self.inner.lock().await;
-        guard.intercept_response(req, res).await
+        let mut guard = self.inner.lock().await;
+        for script in guard.iter_mut().filter(|s| s.info.enabled) {
+            let before = res.before_script(script.info.decode_bodies);
+            let result = script.engine.intercept_response(req, res).await;
+            res.after_script(before);
+            self.forward_notifications(Some(flow_id));
+            if let Err(err) = &result {
+                script.info.error = Some(err.into());
+            }
+            result?;
+        }
+        Ok(())
     }
 
+    /// Runs a recorded flow through the scripts the way the proxy would have,
+    /// leaving its request and response as the scripts made them. True when a
+    /// script answered the request, its response then replaces the recorded one.
+    pub async fn run_flow(&self, flow: &mut Flow) -> Result<bool, Error> {
+        let Some(req) = flow.request.as_mut() else {
+            return Ok(false);
+        };
+        req.unspill()
+            .await
+            .map_err(|e| Error::Other(e.to_string()))?;
+        if let Some(answer) = self.intercept_request(flow.id, req).await? {
+            flow.response = Some(answer);
+            return Ok(true);
+        }
+        if let Some(res) = flow.response.as_mut() {
+            res.unspill()
+                .await
+                .map_err(|e| Error::Other(e.to_string()))?;
+            self.intercept_response(flow.id, req, res).await?;
+        }
+        Ok(false)
+    }
+
+    /// Runs `hook` of the scripts on `conn`, false once one rejects it. A
+    /// script that fails lets the connection through.
+    pub async fn on_connection(&self, hook: ConnectionHook, conn: &ConnectionInfo) -> bool {
+        trace!("on_connection {hook}");
+        let mut guard = self.inner.lock().await;
+        for script in guard.iter_mut().filter(|s| s.info.enabled) {
+            let allowed = script.engine.on_connection(hook, conn).await;
+            self.forward_notifications(None);
+            match allowed {
+                Ok(true) => {}
+                Ok(false) => {
+                    warn!(
+                        "{} rejected {} in {hook}",
+                        script.info.name, conn.client_addr
+                    );
+                    return false;
+                }
+                Err(err) => {
+                    error!("{} failed in {hook} {err}", script.info.name);
+                    script.info.error = Some((&err).into());
+                }
+            }
+        }
+        true
+    }
+
+    /// Replaces every loaded script with `script`.
     pub async fn set_script(&mut self, script: &str, script_type: ScriptType) -> Result<(), Error> {
         trace!("set_script type={script_type} script={script}");
-        let _ = self.inner.lock().await.on_stop().await.ok();
+        self.clear_scripts().await;
+        self.add_script("script", script, script_type).await
+    }
+
+    /// Loads `script` after the others, or in place of the script already
+    /// called `name` keeping whether it is enabled and decodes bodies. When
+    /// the new version fails to load the old one keeps running, with the
+    /// error recorded against it.
+    pub async fn add_script(
+        &self,
+        name: impl Into<String>,
+        script: &str,
+        script_type: ScriptType,
+    ) -> Result<(), Error> {
+        let name = name.into();
+        trace!("add_script name={name} type={script_type}");
         let engine: Box<dyn RoxyEngine> = match script_type {
-            ScriptType::Lua => Box::new(LuaEngine::new(self.notify_tx.clone())),
-            ScriptType::Js => Box::new(JsEngine::new(self.notify_tx.clone())),
-            ScriptType::Python => Box::new(PythonEngine::new(self.notify_tx.clone())),
+            ScriptType::Lua => Box::new(LuaEngine::new(Some(self.script_notify_tx.clone()))),
+            ScriptType::Js => Box::new(JsEngine::new(Some(self.script_notify_tx.clone()))),
+            ScriptType::Python => Box::new(PythonEngine::new(Some(self.script_notify_tx.clone()))),
         };
-        engine.set_script(script).await?;
+        let loaded = engine.set_script(script).await;
+        self.forward_notifications(None);
+
         let mut guard = self.inner.lock().await;
-        *guard = engine;
+        let existing = guard.iter_mut().find(|s| s.info.name == name);
+        if let Err(err) = &loaded {
+            if let Some(existing) = existing {
+                existing.info.error = Some(err.into());
+            }
+            return loaded;
+        }
+        match existing {
+            Some(existing) => {
+                let _ = existing.engine.on_stop().await.ok();
+                self.forward_notifications(None);
+                existing.info.script_type = script_type;
+                existing.info.error = None;
+                existing.source = script.to_string();
+                existing.engine = engine;
+            }
+            None => guard.push(LoadedScript {
+                info: ScriptInfo {
+                    name,
+                    script_type,
+                    enabled: true,
+                    decode_bodies: true,
+                    error: None,
+                },
+                source: script.to_string(),
+                engine,
+            }),
+        }
         Ok(())
     }
+
+    /// Stops and unloads the script called `name`, returns whether it was loaded.
+    pub async fn remove_script(&self, name: &str) -> bool {
+        let mut guard = self.inner.lock().await;
+        let Some(index) = guard.iter().position(|s| s.info.name == name) else {
+            return false;
+        };
+        let loaded = guard.remove(index);
+        let _ = loaded.engine.on_stop().await.ok();
+        self.forward_notifications(None);
+        true
+    }
+
+    pub async fn clear_scripts(&self) {
+        let mut guard = self.inner.lock().await;
+        for loaded in guard.drain(..) {
+            let _ = loaded.engine.on_stop().await.ok();
+        }
+        self.forward_notifications(None);
+    }
+
+    /// Skips or runs the script called `name` again without reloading it,
+    /// returns whether it was loaded.
+    pub async fn set_enabled(&self, name: &str, enabled: bool) -> bool {
+        let mut guard = self.inner.lock().await;
+        match guard.iter_mut().find(|s| s.info.name == name) {
+            Some(loaded) => {
+                loaded.info.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Hands bodies to the script called `name` without or with their
+    /// content encoding, returns whether it was loaded.
+    pub async fn set_decode_bodies(&self, name: &str, decode: bool) -> bool {
+        let mut guard = self.inner.lock().await;
+        match guard.iter_mut().find(|s| s.info.name == name) {
+            Some(loaded) => {
+                loaded.info.decode_bodies = decode;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The source the script called `name` was last loaded from.
+    pub async fn source(&self, name: &str) -> Option<String> {
+        self.inner
+            .lock()
+            .await
+            .iter()
+            .find(|s| s.info.name == name)
+            .map(|s| s.source.clone())
+    }
+
+    /// Whether any loaded script is enabled, without one flows are left alone.
+    pub async fn has_enabled_scripts(&self) -> bool {
+        self.inner.lock().await.iter().any(|s| s.info.enabled)
+    }
+
+    /// The loaded scripts in the order they run.
+    pub async fn scripts(&self) -> Vec<ScriptInfo> {
+        self.inner
+            .lock()
+            .await
+            .iter()
+            .map(|s| s.info.clone())
+            .collect()
+    }
 }
 
 impl Default for ScriptEngine {
@@ -262,3 +594,45 @@ impl Default for ScriptEngine {
         Self::new()
     }
 }
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::{HeaderValue, StatusCode};
+
+    use super::*;
+    use crate::flow::FlowConnection;
+
+    #[tokio::test]
+    async fn runs_recorded_flow() {
+        let engine = ScriptEngine::new();
+        engine
+            .add_script(
+                "test",
+                r#"
+                Extensions = { {
+                    request = function(flow) flow.request.headers:set("x-seen", "1") end,
+                    response = function(flow) flow.response.status = Status.CREATED end,
+                } }
+                "#,
+                ScriptType::Lua,
+            )
+            .await
+            .unwrap();
+        let mut flow = Flow::new(
+            FlowId(1),
+            FlowConnection {
+                addr: ([127, 0, 0, 1], 0).into(),
+            },
+            Some(InterceptedRequest::default()),
+        );
+        flow.response = Some(InterceptedResponse::default());
+
+        assert!(!engine.run_flow(&mut flow).await.unwrap());
+        assert_eq!(
+            flow.request.unwrap().headers.get("x-seen"),
+            Some(&HeaderValue::from_static("1"))
+        );
+        assert_eq!(flow.response.unwrap().status, StatusCode::CREATED);
+    }
+}
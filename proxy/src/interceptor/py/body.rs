@@ -2,11 +2,13 @@ use std::sync::{Arc, Mutex, MutexGuard};
 
 use bytes::Bytes;
 use pyo3::{
-    Bound, PyResult, Python,
+    Bound, PyAny, PyResult, Python,
     exceptions::PyTypeError,
     pyclass, pymethods,
-    types::{PyBytes, PyBytesMethods},
+    types::{PyAnyMethods, PyBytes, PyBytesMethods, PyDict, PyDictMethods, PyList, PyListMethods},
 };
+use roxy_shared::content::{parse_multipart, sniff_multipart_boundary};
+use url::form_urlencoded;
 
 #[pyclass(from_py_object, name = "Body")]
 #[derive(Debug, Clone)]
@@ -76,6 +78,65 @@ impl PyBody {
         Ok(())
     }
 
+    /// The body parsed with `json.loads`.
+    fn json<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let text = self.text()?;
+        py.import("json")?.call_method1("loads", (text,))
+    }
+
+    /// Replaces the body with `value` written by `json.dumps`.
+    fn set_json(&mut self, py: Python<'_>, value: Bound<'_, PyAny>) -> PyResult<()> {
+        let json: String = py
+            .import("json")?
+            .call_method1("dumps", (value,))?
+            .extract()?;
+        self.set_text(&json)
+    }
+
+    /// The `application/x-www-form-urlencoded` fields, a repeated name keeps
+    /// its last value.
+    fn form<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let g = self.lock()?;
+        let dict = PyDict::new(py);
+        for (key, value) in form_urlencoded::parse(&g) {
+            dict.set_item(key.as_ref(), value.as_ref())?;
+        }
+        Ok(dict)
+    }
+
+    /// The parts of a multipart body as dicts, `None` when it is not one. The
+    /// boundary is taken from the first line when not given.
+    #[pyo3(signature = (boundary=None))]
+    fn multipart<'py>(
+        &self,
+        py: Python<'py>,
+        boundary: Option<String>,
+    ) -> PyResult<Option<Bound<'py, PyList>>> {
+        let body = self.lock()?.clone();
+        let Some(parts) = boundary
+            .or_else(|| sniff_multipart_boundary(&body))
+            .and_then(|boundary| parse_multipart(&body, &boundary))
+        else {
+            return Ok(None);
+        };
+        let list = PyList::empty(py);
+        for part in parts {
+            let headers = PyDict::new(py);
+            for (name, value) in &part.headers {
+                headers.set_item(name.as_str(), String::from_utf8_lossy(value.as_bytes()))?;
+            }
+            let entry = PyDict::new(py);
+            entry.set_item("name", part.name)?;
+            entry.set_item("filename", part.filename)?;
+            entry.set_item("content_type", part.content_type)?;
+            entry.set_item("headers", headers)?;
+            entry.set_item("text", String::from_utf8_lossy(&part.body))?;
+            entry.set_item("raw", PyBytes::new(py, &part.body))?;
+            list.append(entry)?;
+        }
+        Ok(Some(list))
+    }
+
     fn __len__(&self) -> PyResult<usize> {
         let g = self.lock()?;
         Ok(g.len())
@@ -143,6 +204,65 @@ assertEqual(len(b), 2)
         );
     }
 
+    #[test]
+    fn pybody_json_and_form() {
+        with_module(
+            r#"
+from roxy import Body
+b = Body('{"user": {"id": 7, "name": "a"}, "tags": ["x", "y"], "next": null}')
+v = b.json()
+assertEqual(v["user"]["id"], 7)
+assertEqual(v["tags"][1], "y")
+assertEqual(v["next"], None)
+v["user"]["name"] = "b"
+b.set_json(v)
+assertEqual(b.json()["user"]["name"], "b")
+
+b.text = "not json"
+try:
+    b.json()
+    assert False, "invalid json should raise"
+except ValueError:
+    pass
+
+b.text = "a=1&b=hello+world&a=2"
+assertEqual(b.form(), {"a": "2", "b": "hello world"})
+"#,
+        );
+    }
+
+    #[test]
+    fn pybody_multipart() {
+        with_module(
+            r#"
+from roxy import Body
+b = Body("\r\n".join([
+    "--b1",
+    'Content-Disposition: form-data; name="title"',
+    "",
+    "hello",
+    "--b1",
+    'Content-Disposition: form-data; name="file"; filename="a.txt"',
+    "Content-Type: text/plain",
+    "",
+    "contents",
+    "--b1--",
+    "",
+]))
+parts = b.multipart()
+assertEqual(len(parts), 2)
+assertEqual(parts[0]["name"], "title")
+assertEqual(parts[0]["text"], "hello")
+assertEqual(parts[1]["filename"], "a.txt")
+assertEqual(parts[1]["content_type"], "text/plain")
+assertEqual(parts[1]["headers"]["content-type"], "text/plain")
+assertEqual(parts[1]["raw"], b"contents")
+assertEqual(b.multipart("other"), None)
+assertEqual(Body("plain").multipart(), None)
+"#,
+        );
+    }
+
     #[test]
     fn pybody_repr_contains_len_and_preview() {
         with_module(
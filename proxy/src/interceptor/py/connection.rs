@@ -0,0 +1,41 @@
+use pyo3::{PyResult, pyclass, pymethods};
+
+use crate::interceptor::{connection::ConnectionInfo, py::flow::PyTls};
+
+/// Read-only view of [`ConnectionInfo`].
+#[derive(Debug, Clone)]
+#[pyclass(frozen, from_py_object, name = "Connection")]
+pub(crate) struct PyConnection {
+    #[pyo3(get)]
+    client_addr: String,
+    #[pyo3(get)]
+    client_ip: String,
+    #[pyo3(get)]
+    host: Option<String>,
+    #[pyo3(get)]
+    port: Option<u16>,
+    #[pyo3(get)]
+    tls: Option<PyTls>,
+}
+
+impl From<&ConnectionInfo> for PyConnection {
+    fn from(conn: &ConnectionInfo) -> Self {
+        Self {
+            client_addr: conn.client_addr.to_string(),
+            client_ip: conn.client_addr.ip().to_string(),
+            host: conn.host.clone(),
+            port: conn.port,
+            tls: conn.tls.as_ref().map(PyTls::from),
+        }
+    }
+}
+
+#[pymethods]
+impl PyConnection {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "Connection(client_addr={:?}, host={:?}, port={:?})",
+            self.client_addr, self.host, self.port
+        ))
+    }
+}
@@ -1,5 +1,9 @@
 use http::StatusCode;
-use pyo3::{exceptions::PyTypeError, prelude::*, types::PyList};
+use pyo3::{
+    exceptions::PyTypeError,
+    prelude::*,
+    types::{PyBool, PyList},
+};
 use roxy_shared::uri::RUri;
 use std::{ffi::CString, ops::Deref, str::FromStr, sync::Arc};
 
@@ -7,7 +11,8 @@ use crate::{
     flow::{InterceptedRequest, InterceptedResponse},
     interceptor::{
         KEY_REQUEST, KEY_RESPONSE, KEY_START, KEY_STOP,
-        py::{init_python, notify},
+        connection::{ConnectionHook, ConnectionInfo},
+        py::{add_site_dirs, connection::PyConnection, init_python, notify},
     },
 };
 
@@ -26,6 +31,7 @@ pub(crate) struct PythonEngine {
 impl PythonEngine {
     pub fn new(notify_tx: Option<Sender<FlowNotify>>) -> Self {
         init_python();
+        add_site_dirs();
         notify::init_notify(notify_tx);
         Self {
             addons: Arc::new(Mutex::new(Vec::new())),
@@ -41,10 +47,7 @@ struct Notifier {
 impl Notifier {
     #[pyo3(name = "__call__")]
     fn __call__(&self, level: i32, msg: String) -> PyResult<()> {
-        let _ = self.tx.try_send(FlowNotify {
-            level: level.into(),
-            msg,
-        });
+        let _ = self.tx.try_send(FlowNotify::new(level.into(), msg));
         Ok(())
     }
 
@@ -100,6 +103,29 @@ impl RoxyEngine for PythonEngine {
         })
     }
 
+    async fn on_connection(
+        &self,
+        hook: ConnectionHook,
+        conn: &ConnectionInfo,
+    ) -> Result<bool, Error> {
+        let addons = self.addons.lock().await;
+        Python::attach(|py| {
+            let conn = Py::new(py, PyConnection::from(conn))?;
+            for a in addons.iter() {
+                let obj = a.obj.bind(py);
+                if !obj.hasattr(hook.key())? {
+                    continue;
+                }
+                let allowed = obj.call_method1(hook.key(), (&conn,))?;
+                if allowed.is_instance_of::<PyBool>() && !allowed.is_truthy()? {
+                    trace!("Addon `{}` rejected the connection in {hook}", a.name);
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        })
+    }
+
     async fn set_script(&self, script: &str) -> Result<(), Error> {
         self.on_stop().await.ok();
         let mut guard = self.addons.lock().await;
@@ -226,6 +252,7 @@ fn update_request<'py>(
             .clone();
         if t.is_empty() { None } else { Some(t) }
     };
+    req.tags = flow_cell.borrow().tags.inner.list();
 
     let mut resp = InterceptedResponse::default();
     update_response(flow_obj, &mut resp)?;
@@ -293,6 +320,7 @@ fn update_response<'py>(
             .clone();
         if t.is_empty() { None } else { Some(t) }
     };
+    res.tags = flow_cell.borrow().tags.inner.list();
 
     Ok(())
 }
@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use pyo3::{
+    Bound, PyAny, PyResult, Python,
+    exceptions::{PyRuntimeError, PyTypeError},
+    pyfunction,
+    types::{PyAnyMethods, PyBytes, PyBytesMethods, PyDict, PyDictMethods},
+};
+
+use crate::interceptor::{
+    fetch::{FetchOptions, fetch as fetch_blocking},
+    py::response::PyResponse,
+};
+
+/// `roxy.fetch(url, method=None, headers=None, body=None, timeout=None)`,
+/// `timeout` is in milliseconds. Raises `RuntimeError` when the request fails.
+#[pyfunction]
+#[pyo3(signature = (url, method=None, headers=None, body=None, timeout=None))]
+pub(crate) fn fetch(
+    py: Python<'_>,
+    url: String,
+    method: Option<String>,
+    headers: Option<Bound<'_, PyDict>>,
+    body: Option<Bound<'_, PyAny>>,
+    timeout: Option<u64>,
+) -> PyResult<PyResponse> {
+    let headers = match headers {
+        Some(headers) => headers
+            .iter()
+            .map(|(name, value)| Ok((name.extract::<String>()?, value.str()?.to_string())))
+            .collect::<PyResult<Vec<_>>>()?,
+        None => vec![],
+    };
+    let body = match body {
+        Some(body) => Some(body_bytes(&body)?),
+        None => None,
+    };
+    let options = FetchOptions {
+        method,
+        headers,
+        body,
+        timeout: timeout.map(Duration::from_millis),
+    };
+    let response = py
+        .detach(|| fetch_blocking(&url, options))
+        .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+    Ok(PyResponse::from_resp(&response))
+}
+
+fn body_bytes(body: &Bound<'_, PyAny>) -> PyResult<Bytes> {
+    if let Ok(bytes) = body.cast::<PyBytes>() {
+        return Ok(Bytes::copy_from_slice(bytes.as_bytes()));
+    }
+    if let Ok(text) = body.extract::<String>() {
+        return Ok(Bytes::from(text));
+    }
+    Err(PyTypeError::new_err("fetch body must be str or bytes"))
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use cow_utils::CowUtils;
+
+    use crate::interceptor::{fetch::tests::serve_once, py::with_module};
+
+    #[test]
+    fn fetch_from_script() {
+        let (url, rx) = serve_once("HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\ntoken");
+        with_module(&format!(
+            r#"
+import roxy
+
+res = roxy.fetch("{url}", method="POST", headers={{"x-hook": "1"}}, body=b"hello", timeout=1000)
+assertEqual(res.status, 200)
+assertEqual(res.body.text, "token")
+
+try:
+    roxy.fetch("/relative")
+    assertTrue(False, "fetch raises on failure")
+except RuntimeError:
+    pass
+"#
+        ));
+        let received = rx.recv().unwrap().cow_to_ascii_lowercase().into_owned();
+        assert!(received.starts_with("post "));
+        assert!(received.contains("x-hook: 1"));
+    }
+}
@@ -1,10 +1,85 @@
 use pyo3::{Py, PyResult, Python, pyclass, pymethods};
 
 use crate::{
-    flow::{InterceptedRequest, InterceptedResponse},
-    interceptor::py::{request::PyRequest, response::PyResponse},
+    flow::{FlowTls, InterceptedRequest, InterceptedResponse},
+    interceptor::{
+        py::{request::PyRequest, response::PyResponse},
+        tags::ScriptTags,
+    },
 };
 
+/// Read-only view of [`FlowTls`].
+#[derive(Debug, Clone, Default)]
+#[pyclass(frozen, from_py_object, name = "Tls")]
+pub(crate) struct PyTls {
+    #[pyo3(get)]
+    version: Option<String>,
+    #[pyo3(get)]
+    cipher: Option<String>,
+    #[pyo3(get)]
+    alpn: Option<String>,
+    #[pyo3(get)]
+    sni: Option<String>,
+    #[pyo3(get)]
+    peer_subject: Option<String>,
+}
+
+impl From<&FlowTls> for PyTls {
+    fn from(tls: &FlowTls) -> Self {
+        Self {
+            version: tls.version.clone(),
+            cipher: tls.cipher.clone(),
+            alpn: tls.alpn.clone(),
+            sni: tls.sni.clone(),
+            peer_subject: tls.peer_subject.clone(),
+        }
+    }
+}
+
+#[pymethods]
+impl PyTls {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "Tls(version={:?}, cipher={:?}, alpn={:?}, sni={:?}, peer_subject={:?})",
+            self.version, self.cipher, self.alpn, self.sni, self.peer_subject
+        ))
+    }
+}
+
+/// Tags on the flow, scripts can add to them but not remove them.
+#[derive(Debug, Clone, Default)]
+#[pyclass(frozen, from_py_object, name = "Tags")]
+pub(crate) struct PyTags {
+    pub(crate) inner: ScriptTags,
+}
+
+#[pymethods]
+impl PyTags {
+    fn add(&self, tag: &str) {
+        self.inner.add(tag);
+    }
+
+    fn has(&self, tag: &str) -> bool {
+        self.inner.has(tag)
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.inner.list()
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.list().len()
+    }
+
+    fn __contains__(&self, tag: &str) -> bool {
+        self.inner.has(tag)
+    }
+
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!("Tags({:?})", self.inner.list()))
+    }
+}
+
 #[derive(Debug, Clone)]
 #[pyclass(from_py_object, name = "Flow")]
 #[derive(Default)]
@@ -13,6 +88,10 @@ pub(crate) struct PyFlow {
     pub(crate) request: PyRequest,
     #[pyo3(get)]
     pub(crate) response: PyResponse,
+    #[pyo3(get)]
+    pub(crate) tls: Option<PyTls>,
+    #[pyo3(get)]
+    pub(crate) tags: PyTags,
 }
 
 impl PyFlow {
@@ -25,9 +104,29 @@ impl PyFlow {
             .as_ref()
             .cloned()
             .unwrap_or(InterceptedResponse::default());
+        let tls = match resp_opt {
+            Some(resp) => resp.tls.as_ref(),
+            None => req.tls.as_ref(),
+        };
+        let tls = tls.map(PyTls::from);
+        let tags = match resp_opt {
+            Some(resp) => resp.tags.clone(),
+            None => req.tags.clone(),
+        };
+        let tags = PyTags {
+            inner: ScriptTags::new(tags),
+        };
         let request = PyRequest::from_req(req);
         let response = PyResponse::from_resp(&resp);
-        Py::new(py, PyFlow { request, response })
+        Py::new(
+            py,
+            PyFlow {
+                request,
+                response,
+                tls,
+                tags,
+            },
+        )
     }
 }
 
@@ -140,4 +239,88 @@ assert hasattr(u, "href")
 "#,
         );
     }
+
+    #[test]
+    fn pyflow_tls_is_read_only() {
+        use pyo3::{Python, types::PyDict};
+
+        use crate::{
+            flow::{FlowTls, InterceptedRequest},
+            interceptor::py::{flow::PyFlow, init_python},
+        };
+
+        with_module(
+            r#"
+from roxy import Flow
+assert Flow().tls is None
+"#,
+        );
+
+        init_python();
+        let req = InterceptedRequest {
+            tls: Some(FlowTls {
+                version: Some("TLSv1_3".to_string()),
+                alpn: Some("h2".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        Python::attach(|py| {
+            let locals = PyDict::new(py);
+            locals
+                .set_item("flow", PyFlow::from_data(py, &req, &None).unwrap())
+                .unwrap();
+            py.run(
+                c"
+assert flow.tls.version == 'TLSv1_3'
+assert flow.tls.alpn == 'h2'
+assert flow.tls.cipher is None
+try:
+    flow.tls.version = 'TLSv1_0'
+    raise AssertionError('tls must be read-only')
+except AttributeError:
+    pass
+",
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+        });
+    }
+
+    #[test]
+    fn pyflow_tags() {
+        use pyo3::{Python, types::PyDict};
+
+        use crate::{
+            flow::InterceptedRequest,
+            interceptor::py::{flow::PyFlow, init_python},
+        };
+
+        init_python();
+        let req = InterceptedRequest {
+            tags: vec!["seen".to_string()],
+            ..Default::default()
+        };
+        Python::attach(|py| {
+            let flow = PyFlow::from_data(py, &req, &None).unwrap();
+            let locals = PyDict::new(py);
+            locals.set_item("flow", flow.clone_ref(py)).unwrap();
+            py.run(
+                c"
+assert flow.tags.has('seen')
+assert 'seen' in flow.tags
+flow.tags.add('auth')
+flow.tags.add('auth')
+flow.tags.add('  ')
+assert len(flow.tags) == 2
+assert flow.tags.list() == ['seen', 'auth']
+",
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+            assert_eq!(flow.borrow(py).tags.inner.list(), vec!["seen", "auth"]);
+        });
+    }
 }
@@ -1,22 +1,28 @@
 pub mod body;
+mod connection;
 mod constants;
 pub mod engine;
 mod extension;
+mod fetch;
 mod flow;
 mod headers;
 mod notify;
 mod query;
 mod request;
 mod response;
+mod store;
 mod url;
 mod writer;
 
 use std::sync::Once;
 
 use pyo3::{PyResult, Python, pymodule, types::PyAnyMethods};
-use tracing::error;
+use tracing::{debug, error, warn};
 
-use crate::interceptor::py::writer::{WriterStdErr, WriterStdOut};
+use crate::interceptor::{
+    py::writer::{WriterStdErr, WriterStdOut},
+    python_env::python_env,
+};
 #[pymodule]
 mod roxy {
 
@@ -29,6 +35,15 @@ mod roxy {
     #[pymodule_export]
     use super::flow::PyFlow;
 
+    #[pymodule_export]
+    use super::flow::PyTls;
+
+    #[pymodule_export]
+    use super::connection::PyConnection;
+
+    #[pymodule_export]
+    use super::flow::PyTags;
+
     #[pymodule_export]
     use super::headers::PyHeaders;
 
@@ -58,6 +73,19 @@ mod roxy {
 
     #[pymodule_export]
     use super::notify::notify;
+
+    #[pymodule_export]
+    use super::fetch::fetch;
+
+    #[pymodule_export]
+    use super::store::PyStore;
+
+    #[pymodule_init]
+    fn init(m: &pyo3::Bound<'_, pyo3::types::PyModule>) -> pyo3::PyResult<()> {
+        use pyo3::types::PyModuleMethods;
+
+        m.add(crate::interceptor::KEY_STORE, super::store::PyStore)
+    }
 }
 
 static INIT: Once = Once::new();
@@ -79,6 +107,49 @@ pub(crate) fn init_python() {
     });
 }
 
+/// Adds the package directories of the Python env the interpreter does not
+/// have yet, running their `.pth` files as a `site-packages` would.
+pub(crate) fn add_site_dirs() {
+    let paths = python_env().paths();
+    if paths.is_empty() {
+        return;
+    }
+    if let Err(err) = Python::attach::<_, PyResult<()>>(|py| {
+        let sys = py.import("sys")?;
+        let site = py.import("site")?;
+        let version_info = sys.getattr("version_info")?;
+        let version = format!(
+            "python{}.{}",
+            version_info.getattr("major")?.extract::<u8>()?,
+            version_info.getattr("minor")?.extract::<u8>()?
+        );
+        let sys_path: Vec<String> = sys.getattr("path")?.extract()?;
+        for path in paths {
+            let dir = path.to_string_lossy().into_owned();
+            if sys_path.contains(&dir) {
+                continue;
+            }
+            if !path.is_dir() {
+                warn!("Python package directory {dir} not found");
+                continue;
+            }
+            if let Some(built_for) = path
+                .parent()
+                .and_then(|p| p.file_name())
+                .map(|n| n.to_string_lossy())
+                .filter(|n| n.starts_with("python") && *n != version)
+            {
+                warn!("{dir} is for {built_for}, scripts run on {version}");
+            }
+            site.call_method1("addsitedir", (&dir,))?;
+            debug!("Added Python packages {dir}");
+        }
+        Ok(())
+    }) {
+        error!("Error adding Python packages {err}");
+    }
+}
+
 #[cfg(test)]
 #[allow(clippy::panic, clippy::expect_used)]
 pub(crate) fn with_module(code: &str) {
@@ -127,3 +198,30 @@ def assertFalse(a, msg=None):
         panic!("Python code failed");
     }
 }
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interceptor::python_env::{PythonEnv, set_python_env};
+
+    #[test]
+    fn imports_from_site_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("roxy_site_test.py"), "VALUE = 42\n").unwrap();
+        set_python_env(PythonEnv {
+            venv: None,
+            site_packages: vec![dir.path().to_path_buf()],
+        });
+        init_python();
+        add_site_dirs();
+        set_python_env(PythonEnv::default());
+        with_module(
+            r#"
+import roxy_site_test
+
+assertEqual(roxy_site_test.VALUE, 42)
+"#,
+        );
+    }
+}
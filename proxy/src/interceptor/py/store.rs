@@ -0,0 +1,53 @@
+use pyo3::{Bound, PyAny, PyResult, pyclass, pymethods, types::PyAnyMethods};
+
+use crate::interceptor::store::STORE;
+
+/// `roxy.store`, the shared script store. Setting `None` removes the key.
+#[pyclass(name = "Store")]
+#[derive(Debug, Default)]
+pub(crate) struct PyStore;
+
+#[pymethods]
+impl PyStore {
+    fn get(&self, key: &str) -> Option<String> {
+        STORE.get(key)
+    }
+
+    fn set(&self, key: &str, value: Option<Bound<PyAny>>) -> PyResult<()> {
+        let value = match value {
+            Some(value) if !value.is_none() => Some(value.str()?.to_string()),
+            _ => None,
+        };
+        STORE.set(key, value);
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) {
+        STORE.set(key, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interceptor::py::with_module;
+
+    #[test]
+    fn store_from_script() {
+        with_module(
+            r#"
+import roxy
+
+assertEqual(roxy.store.get("py_count"), None)
+roxy.store.set("py_count", 1)
+roxy.store.set("py_token", "abc")
+assertEqual(roxy.store.get("py_count"), "1")
+roxy.store.set("py_count", int(roxy.store.get("py_count")) + 1)
+assertEqual(roxy.store.get("py_count"), "2")
+roxy.store.delete("py_count")
+roxy.store.set("py_token", None)
+assertEqual(roxy.store.get("py_count"), None)
+assertEqual(roxy.store.get("py_token"), None)
+"#,
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use once_cell::sync::Lazy;
+use tracing::error;
+
+static PYTHON_ENV: Lazy<RwLock<PythonEnv>> = Lazy::new(RwLock::default);
+
+/// Sets where Python scripts import third-party packages from. The
+/// directories are added to the interpreter as scripts are loaded, ones
+/// already added stay until Roxy restarts.
+pub fn set_python_env(env: PythonEnv) {
+    match PYTHON_ENV.write() {
+        Ok(mut guard) => *guard = env,
+        Err(err) => error!("Python env lock poisoned {err}"),
+    }
+}
+
+pub(crate) fn python_env() -> PythonEnv {
+    match PYTHON_ENV.read() {
+        Ok(guard) => guard.clone(),
+        Err(err) => {
+            error!("Python env lock poisoned {err}");
+            PythonEnv::default()
+        }
+    }
+}
+
+/// Package directories on top of what the embedded interpreter finds itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PythonEnv {
+    /// A virtualenv made by `python -m venv`, its `site-packages` is added.
+    pub venv: Option<PathBuf>,
+    /// Directories added as they are, e.g. one made by `pip install --target`.
+    pub site_packages: Vec<PathBuf>,
+}
+
+impl PythonEnv {
+    /// The `site-packages` directories of the virtualenv followed by
+    /// `site_packages`.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.venv
+            .as_deref()
+            .map(venv_site_packages)
+            .unwrap_or_default()
+            .into_iter()
+            .chain(self.site_packages.iter().cloned())
+            .collect()
+    }
+}
+
+/// `lib/pythonX.Y/site-packages` of a Unix virtualenv, or
+/// `Lib/site-packages` of a Windows one.
+fn venv_site_packages(venv: &Path) -> Vec<PathBuf> {
+    let windows = venv.join("Lib").join("site-packages");
+    if windows.is_dir() {
+        return vec![windows];
+    }
+    let Ok(entries) = fs::read_dir(venv.join("lib")) else {
+        return vec![];
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|e| e.file_name().to_string_lossy().starts_with("python"))
+        .map(|e| e.path().join("site-packages"))
+        .filter(|p| p.is_dir())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_venv_site_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let venv = dir.path().join("venv");
+        let site = venv.join("lib").join("python3.12").join("site-packages");
+        fs::create_dir_all(&site).unwrap();
+        fs::create_dir_all(venv.join("lib").join("pkgconfig")).unwrap();
+        let extra = dir.path().join("target");
+
+        let env = PythonEnv {
+            venv: Some(venv),
+            site_packages: vec![extra.clone()],
+        };
+        assert_eq!(env.paths(), vec![site, extra]);
+        assert!(
+            PythonEnv {
+                venv: Some(dir.path().join("missing")),
+                ..Default::default()
+            }
+            .paths()
+            .is_empty()
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use once_cell::sync::Lazy;
+use tracing::{error, warn};
+
+/// Shared by every script in every engine.
+pub(crate) static STORE: Lazy<ScriptStore> = Lazy::new(ScriptStore::default);
+
+/// Sets the file scripts' `store` is saved to, `None` keeps values in memory
+/// only. Values are read from the new file the next time a script uses them.
+pub fn set_store_path(path: Option<PathBuf>) {
+    STORE.set_path(path);
+}
+
+/// String values kept by scripts across requests and, when a path is set,
+/// restarts. The whole store is rewritten on every change, it is meant for
+/// tokens and counters rather than bodies.
+#[derive(Debug, Default)]
+pub(crate) struct ScriptStore {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    path: Option<PathBuf>,
+    values: Option<BTreeMap<String, String>>,
+}
+
+impl ScriptStore {
+    fn set_path(&self, path: Option<PathBuf>) {
+        match self.inner.lock() {
+            Ok(mut inner) => {
+                inner.path = path;
+                inner.values = None;
+            }
+            Err(err) => error!("Script store lock poisoned {err}"),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<String> {
+        match self.inner.lock() {
+            Ok(mut inner) => inner.values().get(key).cloned(),
+            Err(err) => {
+                error!("Script store lock poisoned {err}");
+                None
+            }
+        }
+    }
+
+    /// Stores `value` under `key`, `None` removes the key.
+    pub(crate) fn set(&self, key: &str, value: Option<String>) {
+        match self.inner.lock() {
+            Ok(mut inner) => {
+                let changed = match value {
+                    Some(value) => {
+                        inner.values().insert(key.to_string(), value.clone()) != Some(value)
+                    }
+                    None => inner.values().remove(key).is_some(),
+                };
+                if changed {
+                    inner.save();
+                }
+            }
+            Err(err) => error!("Script store lock poisoned {err}"),
+        }
+    }
+}
+
+impl Inner {
+    fn values(&mut self) -> &mut BTreeMap<String, String> {
+        let path = &self.path;
+        self.values.get_or_insert_with(|| match path {
+            Some(path) => load(path),
+            None => BTreeMap::new(),
+        })
+    }
+
+    fn save(&self) {
+        let (Some(path), Some(values)) = (&self.path, &self.values) else {
+            return;
+        };
+        let written = serde_json::to_vec_pretty(values)
+            .map_err(std::io::Error::other)
+            .and_then(|json| {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let tmp = path.with_extension("tmp");
+                fs::write(&tmp, json)?;
+                fs::rename(&tmp, path)
+            });
+        if let Err(err) = written {
+            warn!("Failed to write script store {} {err}", path.display());
+        }
+    }
+}
+
+fn load(path: &Path) -> BTreeMap<String, String> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return BTreeMap::new(),
+        Err(err) => {
+            warn!("Failed to read script store {} {err}", path.display());
+            return BTreeMap::new();
+        }
+    };
+    serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+        warn!("Invalid script store {} {err}", path.display());
+        BTreeMap::new()
+    })
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_get_and_remove() {
+        let store = ScriptStore::default();
+        assert_eq!(store.get("token"), None);
+        store.set("token", Some("abc".to_string()));
+        assert_eq!(store.get("token").unwrap(), "abc");
+        store.set("token", None);
+        assert_eq!(store.get("token"), None);
+    }
+
+    #[test]
+    fn persists_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roxy").join("store.json");
+        let store = ScriptStore::default();
+        store.set_path(Some(path.clone()));
+        store.set("count", Some("1".to_string()));
+        store.set("token", Some("abc".to_string()));
+        store.set("token", None);
+
+        let restarted = ScriptStore::default();
+        restarted.set_path(Some(path.clone()));
+        assert_eq!(restarted.get("count").unwrap(), "1");
+        assert_eq!(restarted.get("token"), None);
+
+        fs::write(&path, "not json").unwrap();
+        let corrupt = ScriptStore::default();
+        corrupt.set_path(Some(path));
+        assert_eq!(corrupt.get("count"), None);
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::{Arc, Mutex};
+
+use tracing::error;
+
+/// The tags of the flow a handler is running for, shared between the script's
+/// `flow.tags` and the request or response they are copied back to.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ScriptTags(Arc<Mutex<Vec<String>>>);
+
+impl ScriptTags {
+    pub(crate) fn new(tags: Vec<String>) -> Self {
+        Self(Arc::new(Mutex::new(tags)))
+    }
+
+    /// Adds `tag` unless it is empty or already there.
+    pub(crate) fn add(&self, tag: &str) {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            return;
+        }
+        match self.0.lock() {
+            Ok(mut tags) => {
+                if !tags.iter().any(|t| t == tag) {
+                    tags.push(tag.to_string());
+                }
+            }
+            Err(err) => error!("Tags lock poisoned {err}"),
+        }
+    }
+
+    pub(crate) fn has(&self, tag: &str) -> bool {
+        self.list().iter().any(|t| t == tag)
+    }
+
+    pub(crate) fn list(&self) -> Vec<String> {
+        match self.0.lock() {
+            Ok(tags) => tags.clone(),
+            Err(err) => {
+                error!("Tags lock poisoned {err}");
+                vec![]
+            }
+        }
+    }
+}
@@ -1,12 +1,38 @@
 #![deny(clippy::unwrap_used, clippy::expect_used, clippy::panic)]
+pub mod acl;
+mod api;
+pub mod auth;
+pub mod breakpoint;
+pub mod cache;
+pub mod cert_portal;
+pub mod diagnostics;
+mod error_page;
+pub mod filter;
 pub mod flow;
+pub mod flow_log;
 mod h3;
+mod head_recorder;
+pub mod hook;
+mod host_check;
 mod http;
 pub mod interceptor;
+pub mod limits;
+pub mod listener;
+mod masque;
+pub mod metrics;
 
+mod passthrough;
 mod peek_stream;
 pub mod proxy;
-mod ws;
+pub mod retry;
+pub mod rewrite;
+pub mod scenario;
+pub mod settings;
+mod socks;
+pub mod supervisor;
+mod tunnel;
+pub mod version;
+pub mod ws;
 
 use once_cell::sync::OnceCell;
 use tracing_subscriber::EnvFilter;
@@ -0,0 +1,243 @@
+use std::{
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use hyper::server::conn::{http1, http2};
+use hyper_util::rt::TokioExecutor;
+use roxy_shared::http::ResponseLimits;
+use tokio::time::{Instant, error::Elapsed, timeout_at};
+use tracing::error;
+
+/// hyper refuses HTTP/1 read buffers smaller than this.
+const MIN_H1_BUF_SIZE: usize = 8192;
+
+/// Bounds on a single flow, nothing is bounded by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Largest request head accepted from clients, larger ones are answered
+    /// with `431 Request Header Fields Too Large`. HTTP/1 heads are never
+    /// limited below 8 KiB.
+    pub max_header_size: Option<usize>,
+    /// Largest request or response body buffered for a flow.
+    pub max_body_size: Option<usize>,
+    /// Longest wait for a new upstream connection to be ready.
+    pub connect_timeout: Option<Duration>,
+    /// Longest wait for the upstream response head, and again for its body.
+    pub read_timeout: Option<Duration>,
+    /// Longest a flow may take from the first byte of the request to the
+    /// response, time held at a breakpoint does not count.
+    pub flow_timeout: Option<Duration>,
+    /// Client connections open at once, further ones are answered with `503`
+    /// and closed.
+    pub max_client_connections: Option<usize>,
+    /// Requests in flight to one upstream host at once, further ones are
+    /// answered with `503`.
+    pub max_host_connections: Option<usize>,
+    /// Requests in flight on one HTTP/2 client connection at once, further
+    /// ones are answered with `503`.
+    pub max_streams_per_connection: Option<usize>,
+}
+
+impl Limits {
+    pub(crate) fn response_limits(&self) -> ResponseLimits {
+        ResponseLimits {
+            read_timeout: self.read_timeout,
+            max_body_size: self.max_body_size,
+        }
+    }
+
+    /// Server for HTTP/1 clients, with the header size limit applied. The
+    /// case of request header names is kept to send them on as written.
+    pub(crate) fn h1_server(&self) -> http1::Builder {
+        let mut builder = http1::Builder::new();
+        builder.title_case_headers(true).preserve_header_case(true);
+        if let Some(size) = self.h1_buf_size() {
+            builder.max_buf_size(size);
+        }
+        builder
+    }
+
+    /// Server for HTTP/2 clients, with the header size limit applied.
+    pub(crate) fn h2_server(&self) -> http2::Builder<TokioExecutor> {
+        let mut builder = http2::Builder::new(TokioExecutor::new());
+        if let Some(size) = self.h2_header_list_size() {
+            builder.max_header_list_size(size);
+        }
+        builder
+    }
+
+    fn h1_buf_size(&self) -> Option<usize> {
+        self.max_header_size.map(|size| size.max(MIN_H1_BUF_SIZE))
+    }
+
+    fn h2_header_list_size(&self) -> Option<u32> {
+        self.max_header_size
+            .map(|size| u32::try_from(size).unwrap_or(u32::MAX))
+    }
+}
+
+/// Shared handle to the active [`Limits`], updates apply to the next
+/// connection for header sizes and to the next flow for everything else.
+/// Clones also share the count of connections and requests in use.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyLimits {
+    limits: Arc<RwLock<Limits>>,
+    clients: Slots,
+    hosts: Arc<DashMap<String, Slots>>,
+}
+
+impl ProxyLimits {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits: Arc::new(RwLock::new(limits)),
+            ..Self::default()
+        }
+    }
+
+    /// A slot for a new client connection, `None` when too many are open.
+    pub(crate) fn client_slot(&self) -> Option<Slot> {
+        self.clients.take(self.limits().max_client_connections)
+    }
+
+    /// A slot for a request to `host`, `None` when too many are in flight.
+    pub(crate) fn host_slot(&self, host: &str) -> Option<Slot> {
+        let Some(max) = self.limits().max_host_connections else {
+            return Some(Slot(None));
+        };
+        let slots = self.hosts.entry(host.to_string()).or_default().clone();
+        slots.take(Some(max))
+    }
+
+    pub fn limits(&self) -> Limits {
+        match self.limits.read() {
+            Ok(limits) => *limits,
+            Err(err) => {
+                error!("Limits lock poisoned {err}");
+                Limits::default()
+            }
+        }
+    }
+
+    pub fn set_limits(&self, limits: Limits) {
+        match self.limits.write() {
+            Ok(mut guard) => *guard = limits,
+            Err(err) => error!("Limits lock poisoned {err}"),
+        }
+    }
+}
+
+/// Counts connections or requests in use, clones share the count.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Slots(Arc<AtomicUsize>);
+
+impl Slots {
+    /// Takes a slot while fewer than `max` are in use, always without a `max`.
+    pub(crate) fn take(&self, max: Option<usize>) -> Option<Slot> {
+        let in_use = self.0.fetch_add(1, Ordering::AcqRel);
+        if max.is_some_and(|max| in_use >= max) {
+            self.0.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        Some(Slot(Some(self.0.clone())))
+    }
+}
+
+/// A slot in use, given back when dropped.
+#[derive(Debug)]
+pub(crate) struct Slot(Option<Arc<AtomicUsize>>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(count) = &self.0 {
+            count.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// When a flow has to be answered by.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline(Option<Instant>);
+
+impl Deadline {
+    pub(crate) fn new(timeout: Option<Duration>) -> Self {
+        Self(timeout.map(|timeout| Instant::now() + timeout))
+    }
+
+    /// Moves the deadline back by `paused`, e.g. time spent at a breakpoint.
+    pub(crate) fn extend(&mut self, paused: Duration) {
+        if let Some(at) = &mut self.0 {
+            *at += paused;
+        }
+    }
+
+    pub(crate) async fn run<F: Future>(&self, fut: F) -> Result<F::Output, Elapsed> {
+        match self.0 {
+            Some(at) => timeout_at(at, fut).await,
+            None => Ok(fut.await),
+        }
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_sizes() {
+        let limits = Limits {
+            max_header_size: Some(1024),
+            ..Limits::default()
+        };
+        assert_eq!(limits.h1_buf_size(), Some(MIN_H1_BUF_SIZE));
+        assert_eq!(limits.h2_header_list_size(), Some(1024));
+        assert_eq!(Limits::default().h1_buf_size(), None);
+
+        let handle = ProxyLimits::default();
+        handle.set_limits(limits);
+        assert_eq!(handle.limits(), limits);
+    }
+
+    #[test]
+    fn slots_are_given_back() {
+        let limits = ProxyLimits::new(Limits {
+            max_client_connections: Some(1),
+            max_host_connections: Some(2),
+            ..Limits::default()
+        });
+        let client = limits.client_slot().unwrap();
+        assert!(limits.clone().client_slot().is_none());
+        drop(client);
+        assert!(limits.client_slot().is_some());
+
+        let first = limits.host_slot("a.com").unwrap();
+        let _second = limits.host_slot("a.com").unwrap();
+        assert!(limits.host_slot("a.com").is_none());
+        assert!(limits.host_slot("b.com").is_some());
+        drop(first);
+        assert!(limits.host_slot("a.com").is_some());
+
+        let unbounded = Slots::default();
+        let held: Vec<_> = (0..10).filter_map(|_| unbounded.take(None)).collect();
+        assert_eq!(held.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn deadline_excludes_pauses() {
+        let unbounded = Deadline::new(None);
+        assert_eq!(unbounded.run(async { 1 }).await.unwrap(), 1);
+
+        let mut deadline = Deadline::new(Some(Duration::from_millis(20)));
+        let slow = tokio::time::sleep(Duration::from_millis(60));
+        assert!(deadline.run(slow).await.is_err());
+
+        deadline.extend(Duration::from_secs(10));
+        let slow = tokio::time::sleep(Duration::from_millis(20));
+        assert!(deadline.run(slow).await.is_ok());
+    }
+}
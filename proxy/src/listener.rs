@@ -0,0 +1,359 @@
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::Arc,
+};
+
+use roxy_shared::{http::HttpError, tls::TlsConfig, uri::RUri};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tracing::{debug, error, trace};
+
+use crate::{
+    filter::HostFilter,
+    h3::start_h3,
+    interceptor::connection::ConnectionHook,
+    peek_stream::PeekStream,
+    proxy::{FlowContext, ProxyContext, start_tcp, tunnel_stream},
+};
+
+/// Enough for a ClientHello with post-quantum key shares.
+const TRANSPARENT_PEEK_LEN: usize = 4096;
+
+/// How clients reach the proxy through a listener.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ListenerMode {
+    /// Clients are configured to use the proxy, with absolute urls or CONNECT.
+    #[default]
+    Regular,
+    /// Every connection is forwarded to the target, clients treat the listener
+    /// as the server itself.
+    Reverse(RUri),
+    /// Connections redirected to the listener, e.g. by firewall rules, are sent
+    /// to the host named by the TLS SNI or the HTTP Host header.
+    Transparent,
+}
+
+/// One more address the proxy accepts clients on, next to the main port.
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    addr: SocketAddr,
+    mode: ListenerMode,
+    h3: bool,
+    host_filter: Option<HostFilter>,
+    tls_config: Option<TlsConfig>,
+}
+
+impl ListenerConfig {
+    pub fn new(addr: SocketAddr, mode: ListenerMode) -> Self {
+        Self {
+            addr,
+            mode,
+            h3: false,
+            host_filter: None,
+            tls_config: None,
+        }
+    }
+
+    /// Also accepts HTTP/3 on the same UDP port, regular listeners only.
+    pub fn with_h3(mut self, h3: bool) -> Self {
+        self.h3 = h3;
+        self
+    }
+
+    /// Decides interception for this listener instead of the proxy wide filter.
+    pub fn with_host_filter(mut self, host_filter: HostFilter) -> Self {
+        self.host_filter = Some(host_filter);
+        self
+    }
+
+    /// TLS settings for this listener instead of the proxy wide ones.
+    pub fn with_tls_config(mut self, tls_config: TlsConfig) -> Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn mode(&self) -> &ListenerMode {
+        &self.mode
+    }
+
+    /// `cxt` with the policies this listener overrides.
+    fn cxt(&self, mut cxt: ProxyContext) -> ProxyContext {
+        if let Some(host_filter) = &self.host_filter {
+            cxt.host_filter = host_filter.clone();
+        }
+        if let Some(tls_config) = &self.tls_config {
+            cxt.tls_config = tls_config.clone();
+        }
+        cxt
+    }
+}
+
+/// Binds `config` and starts accepting, returns the address bound and the
+/// tasks serving it.
+pub(crate) async fn start_listener(
+    cxt: ProxyContext,
+    config: &ListenerConfig,
+) -> Result<(SocketAddr, Vec<JoinHandle<()>>), HttpError> {
+    let cxt = config.cxt(cxt);
+    let listener = TcpListener::bind(config.addr).await?;
+    let addr = listener.local_addr()?;
+    let mut handles = vec![];
+    match &config.mode {
+        ListenerMode::Regular => {
+            handles.push(start_tcp(cxt.clone(), listener).await?);
+            if config.h3 {
+                let udp_socket = UdpSocket::bind(addr)?;
+                let h3_handle = start_h3(cxt, udp_socket)
+                    .await
+                    .map_err(|_| HttpError::Alpn)?; // TODO: Wrong error
+                handles.push(h3_handle);
+            }
+        }
+        ListenerMode::Reverse(target) => {
+            handles.push(start_reverse(cxt, listener, target.clone()));
+        }
+        ListenerMode::Transparent => handles.push(start_transparent(cxt, listener)),
+    }
+    debug!("Listener {addr} started {:?}", config.mode);
+    Ok((addr, handles))
+}
+
+fn start_reverse(cxt: ProxyContext, listener: TcpListener, target: RUri) -> JoinHandle<()> {
+    let name = format!("reverse {}", addr_name(&listener));
+    let listener = Arc::new(listener);
+    let supervisor = cxt.supervisor.clone();
+    supervisor.spawn(name, move || {
+        accept_reverse(cxt.clone(), listener.clone(), target.clone())
+    })
+}
+
+async fn accept_reverse(
+    cxt: ProxyContext,
+    listener: Arc<TcpListener>,
+    target: RUri,
+) -> io::Result<()> {
+    trace!("Reverse proxy to {target} listening");
+    loop {
+        let (stream, client_addr) = listener.accept().await?;
+        let mut flow_cxt = FlowContext::new(client_addr, target.clone(), cxt.clone());
+        flow_cxt.reverse = true;
+        tokio::task::spawn(async move {
+            let cxt = &flow_cxt.proxy_cxt;
+            if !cxt.admit(client_addr).await {
+                return;
+            }
+            let Some(_slot) = cxt.limits.client_slot() else {
+                debug!("Too many client connections, refusing {client_addr}");
+                return;
+            };
+            if !flow_cxt.scripts_allow(ConnectionHook::ServerConnect).await {
+                return;
+            }
+            if let Err(err) = tunnel_stream(flow_cxt, stream).await {
+                trace!("Reverse proxy error: {err}");
+            }
+        });
+    }
+}
+
+fn start_transparent(cxt: ProxyContext, listener: TcpListener) -> JoinHandle<()> {
+    let name = format!("transparent {}", addr_name(&listener));
+    let listener = Arc::new(listener);
+    let supervisor = cxt.supervisor.clone();
+    supervisor.spawn(name, move || {
+        accept_transparent(cxt.clone(), listener.clone())
+    })
+}
+
+async fn accept_transparent(cxt: ProxyContext, listener: Arc<TcpListener>) -> io::Result<()> {
+    trace!("Transparent proxy listening");
+    loop {
+        let (stream, client_addr) = listener.accept().await?;
+        let cxt = cxt.clone();
+        tokio::task::spawn(async move {
+            if !cxt.admit(client_addr).await {
+                return;
+            }
+            let Some(_slot) = cxt.limits.client_slot() else {
+                debug!("Too many client connections, refusing {client_addr}");
+                return;
+            };
+            let (stream, peeked) = match PeekStream::new(stream, TRANSPARENT_PEEK_LEN).await {
+                Ok(peeked) => peeked,
+                Err(err) => {
+                    trace!("Transparent peek failed {client_addr} {err}");
+                    return;
+                }
+            };
+            let Some(target_uri) = transparent_target(&peeked) else {
+                error!("Transparent proxy found no host from {client_addr}");
+                return;
+            };
+            let flow_cxt = FlowContext::new(client_addr, target_uri, cxt);
+            if !flow_cxt.scripts_allow(ConnectionHook::ServerConnect).await {
+                return;
+            }
+            if let Err(err) = tunnel_stream(flow_cxt, stream).await {
+                trace!("Transparent proxy error: {err}");
+            }
+        });
+    }
+}
+
+/// The address `listener` is bound to, for naming its accept loop.
+fn addr_name(listener: &TcpListener) -> String {
+    listener
+        .local_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_default()
+}
+
+/// The host a redirected connection was meant for, from the first bytes the
+/// client sent.
+fn transparent_target(peeked: &[u8]) -> Option<RUri> {
+    let authority = match peeked.first() {
+        Some(0x16) => format!("{}:443", sni(peeked)?),
+        _ => {
+            let host = http_host(peeked)?;
+            if host.contains(':') && !host.ends_with(']') {
+                host
+            } else {
+                format!("{host}:80")
+            }
+        }
+    };
+    authority.parse().ok()
+}
+
+/// The server name of a TLS ClientHello record.
+fn sni(record: &[u8]) -> Option<String> {
+    let mut r = Reader(record);
+    // Record header, handshake header, version and random
+    r.skip(5)?;
+    if r.u8()? != 1 {
+        return None;
+    }
+    r.skip(3 + 2 + 32)?;
+    let session_id = r.u8()?;
+    r.skip(session_id.into())?;
+    let ciphers = r.u16()?;
+    r.skip(ciphers.into())?;
+    let compression = r.u8()?;
+    r.skip(compression.into())?;
+    let len = r.u16()?;
+    let mut extensions = Reader(r.take(len.into())?);
+    while let (Some(kind), Some(len)) = (extensions.u16(), extensions.u16()) {
+        let data = extensions.take(len.into())?;
+        if kind != 0 {
+            continue;
+        }
+        let mut list = Reader(data);
+        let len = list.u16()?;
+        let mut names = Reader(list.take(len.into())?);
+        while let Some(name_type) = names.u8() {
+            let len = names.u16()?;
+            let name = names.take(len.into())?;
+            if name_type == 0 {
+                return String::from_utf8(name.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+/// The Host header of a plain HTTP/1 request.
+fn http_host(request: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(request).ok()?;
+    text.split("\r\n")
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("host"))
+        .map(|(_, value)| value.trim().to_string())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_hello(host: &str) -> Vec<u8> {
+        let name = host.as_bytes();
+        let mut sni = vec![];
+        sni.extend_from_slice(&(name.len() as u16 + 3).to_be_bytes());
+        sni.push(0);
+        sni.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        sni.extend_from_slice(name);
+
+        let mut extensions = vec![];
+        // An unrelated extension first, supported versions
+        extensions.extend_from_slice(&[0x00, 0x2b, 0x00, 0x03, 0x02, 0x03, 0x04]);
+        extensions.extend_from_slice(&[0x00, 0x00]);
+        extensions.extend_from_slice(&(sni.len() as u16).to_be_bytes());
+        extensions.extend_from_slice(&sni);
+
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0; 32]);
+        hello.push(0);
+        hello.extend_from_slice(&[0x00, 0x02, 0x13, 0x01]);
+        hello.extend_from_slice(&[0x01, 0x00]);
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
+
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend_from_slice(&(hello.len() as u16 + 4).to_be_bytes());
+        record.push(1);
+        record.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+        record.extend_from_slice(&hello);
+        record
+    }
+
+    #[test]
+    fn targets_the_server_name() {
+        let target = transparent_target(&client_hello("api.example.com")).unwrap();
+        assert_eq!(target.host_port(), "api.example.com:443");
+        let hello = client_hello("api.example.com");
+        assert_eq!(sni(&hello[..hello.len() - 4]), None);
+    }
+
+    #[test]
+    fn targets_the_host_header() {
+        let target =
+            transparent_target(b"GET / HTTP/1.1\r\nAccept: */*\r\nHost: example.com\r\n\r\n")
+                .unwrap();
+        assert_eq!(target.host_port(), "example.com:80");
+        let target =
+            transparent_target(b"GET / HTTP/1.1\r\nhost: example.com:8080\r\n\r\n").unwrap();
+        assert_eq!(target.host_port(), "example.com:8080");
+        assert!(transparent_target(b"GET / HTTP/1.1\r\n\r\nHost: example.com").is_none());
+    }
+}
@@ -0,0 +1,475 @@
+//! CONNECT-UDP tunnels as specified in RFC 9298.
+//!
+//! UDP payloads travel as HTTP datagrams (RFC 9297) on the client connection,
+//! each prefixed with the quarter stream id of the CONNECT-UDP request and a
+//! context id. Intercepted tunnels terminate the inner QUIC connection on a
+//! quinn endpoint reading from the tunnel, so inner HTTP/3 requests become
+//! flows. Tunnels to hosts rejected by the host filter, and tunnels that do not
+//! start with a QUIC handshake, are relayed as is with their datagrams recorded
+//! as flow messages.
+
+use std::{
+    error::Error,
+    io::{self, IoSliceMut},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::{Bytes, BytesMut};
+use cow_utils::CowUtils;
+use dashmap::DashMap;
+use h3::{ext::Protocol, server::RequestStream};
+use http::Method;
+use quinn::{
+    AsyncUdpSocket, EndpointConfig, SendDatagramError, TransportConfig, UdpPoller,
+    crypto::rustls::QuicServerConfig,
+    default_runtime,
+    udp::{RecvMeta, Transmit},
+};
+use roxy_shared::{
+    alpn::alp_h3,
+    datagram::{decode_udp, is_quic_initial, udp_prefix},
+    uri::RUri,
+};
+use rustls::ServerConfig;
+use tokio::{net::UdpSocket, sync::mpsc, task::JoinSet};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, trace, warn};
+
+use crate::{
+    flow::{
+        FlowEvent, WsMessage,
+        error::{FlowError, FlowErrorKind},
+    },
+    h3::serve_requests,
+    proxy::{FlowContext, ProxyContext},
+};
+
+const MASQUE_UDP_PREFIX: &str = "/.well-known/masque/udp/";
+const TUNNEL_QUEUE: usize = 256;
+const MAX_UDP_PAYLOAD: usize = 65527;
+/// Datagrams of a relayed tunnel kept as flow messages, a long lived tunnel
+/// would otherwise grow its flow without bound.
+const RECORDED_DATAGRAMS: usize = 1000;
+
+type TunnelStream = RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>;
+
+/// The target of a CONNECT-UDP request using the default masque uri template,
+/// `/.well-known/masque/udp/{target_host}/{target_port}/`.
+pub(crate) fn masque_target(req: &http::Request<()>) -> Option<RUri> {
+    if req.method() != Method::CONNECT
+        || req.extensions().get::<Protocol>() != Some(&Protocol::CONNECT_UDP)
+    {
+        return None;
+    }
+    let rest = req.uri().path().strip_prefix(MASQUE_UDP_PREFIX)?;
+    let mut parts = rest.trim_end_matches('/').split('/');
+    let host = parts
+        .next()?
+        .cow_replace("%3A", ":")
+        .cow_replace("%3a", ":")
+        .into_owned();
+    let port: u16 = parts.next()?.parse().ok()?;
+    if host.is_empty() || parts.next().is_some() {
+        return None;
+    }
+    let host = if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host
+    };
+    format!("https://{host}:{port}").parse().ok()
+}
+
+/// Serves CONNECT-UDP tunnels on `h3_conn` until the client goes away, starting
+/// with the already resolved `first` request.
+pub(crate) async fn serve_masque(
+    mut h3_conn: h3::server::Connection<h3_quinn::Connection, Bytes>,
+    conn: quinn::Connection,
+    first: (http::Request<()>, TunnelStream),
+    target_uri: RUri,
+    cxt: ProxyContext,
+) -> Result<(), Box<dyn Error>> {
+    let mut tunnels = UdpTunnels::new(conn);
+    let (_, stream) = first;
+    tunnels.open(stream, target_uri, cxt.clone());
+
+    loop {
+        match h3_conn.accept().await {
+            Ok(Some(resolver)) => {
+                let Ok((req, mut stream)) = resolver.resolve_request().await else {
+                    warn!("Failed to resolve_request");
+                    continue;
+                };
+                match masque_target(&req) {
+                    Some(target_uri) => tunnels.open(stream, target_uri, cxt.clone()),
+                    None => {
+                        let response = http::Response::builder()
+                            .status(http::StatusCode::BAD_REQUEST)
+                            .body(())?;
+                        stream.send_response(response).await?;
+                        stream.finish().await?;
+                    }
+                }
+            }
+            Ok(None) => break,
+            Err(err) => {
+                error!("error on accept {}", err);
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Routes datagrams on one client connection to its open tunnels. Dropping it
+/// closes every tunnel.
+struct UdpTunnels {
+    conn: quinn::Connection,
+    routes: Arc<DashMap<u64, mpsc::Sender<Bytes>>>,
+    tasks: JoinSet<()>,
+}
+
+impl UdpTunnels {
+    fn new(conn: quinn::Connection) -> Self {
+        let routes = Arc::new(DashMap::new());
+        let mut tasks = JoinSet::new();
+        tasks.spawn(route_datagrams(conn.clone(), routes.clone()));
+        Self {
+            conn,
+            routes,
+            tasks,
+        }
+    }
+
+    fn open(&mut self, mut stream: TunnelStream, target_uri: RUri, cxt: ProxyContext) {
+        let quarter_id = stream.id().index();
+        let (tx, mut rx) = mpsc::channel(TUNNEL_QUEUE);
+        self.routes.insert(quarter_id, tx);
+
+        let routes = self.routes.clone();
+        let sender = TunnelSender::new(self.conn.clone(), quarter_id);
+        let client_addr = self.conn.remote_address();
+        self.tasks.spawn(async move {
+            debug!("CONNECT-UDP {target_uri}");
+            let accepted = http::Response::builder()
+                .status(http::StatusCode::OK)
+                .header("capsule-protocol", "?1")
+                .body(());
+            let sent = match accepted {
+                Ok(response) => stream.send_response(response).await.is_ok(),
+                Err(_) => false,
+            };
+
+            if sent {
+                let flow_cxt = FlowContext::new(client_addr, target_uri.clone(), cxt);
+                // Only QUIC can be terminated, DNS, STUN and the like are relayed
+                let peeked = rx.recv().await;
+                let quic = peeked.as_deref().is_some_and(is_quic_initial);
+                let inbox = Inbox { peeked, rx };
+                if quic
+                    && flow_cxt
+                        .proxy_cxt
+                        .host_filter
+                        .should_intercept(target_uri.host())
+                {
+                    if let Err(err) = intercept(sender, inbox, flow_cxt).await {
+                        warn!("CONNECT-UDP {target_uri} failed {err}");
+                    }
+                } else {
+                    relay(sender, inbox, flow_cxt).await;
+                }
+            }
+
+            routes.remove(&quarter_id);
+            let _ = stream.finish().await;
+            trace!("CONNECT-UDP {target_uri} closed");
+        });
+    }
+}
+
+async fn route_datagrams(conn: quinn::Connection, routes: Arc<DashMap<u64, mpsc::Sender<Bytes>>>) {
+    while let Ok(datagram) = conn.read_datagram().await {
+        let Some((quarter_id, payload)) = decode_udp(datagram) else {
+            continue;
+        };
+        if let Some(route) = routes.get(&quarter_id) {
+            // Full queues drop the packet, like any other UDP hop
+            let _ = route.try_send(payload);
+        }
+    }
+}
+
+/// The UDP payloads a client sent into one tunnel, starting with the one
+/// looked at to tell QUIC from other protocols.
+#[derive(Debug)]
+struct Inbox {
+    peeked: Option<Bytes>,
+    rx: mpsc::Receiver<Bytes>,
+}
+
+impl Inbox {
+    async fn recv(&mut self) -> Option<Bytes> {
+        match self.peeked.take() {
+            Some(packet) => Some(packet),
+            None => self.rx.recv().await,
+        }
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<Bytes>> {
+        match self.peeked.take() {
+            Some(packet) => Poll::Ready(Some(packet)),
+            None => self.rx.poll_recv(cx),
+        }
+    }
+}
+
+/// Terminates the inner QUIC connection with a certificate for the target and
+/// serves its requests like any other HTTP/3 connection.
+async fn intercept(
+    sender: TunnelSender,
+    inbox: Inbox,
+    flow_cxt: FlowContext,
+) -> Result<(), Box<dyn Error>> {
+    let leaf = flow_cxt.proxy_cxt.ca.leaf_for_uri(&flow_cxt.target_uri)?;
+    let mut tls_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![leaf.cert_der.clone()], leaf.key())?;
+    tls_config.alpn_protocols = alp_h3();
+    if let Some(key_log) = flow_cxt.proxy_cxt.tls_config.key_log() {
+        tls_config.key_log = key_log;
+    }
+
+    // Inner packets have to fit in a datagram frame of the outer connection
+    let mut transport = TransportConfig::default();
+    transport.mtu_discovery_config(None);
+    let mut server_config =
+        quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(tls_config)?));
+    server_config.transport_config(Arc::new(transport));
+
+    let runtime = default_runtime().ok_or_else(|| io::Error::other("no async runtime found"))?;
+    let socket = Arc::new(TunnelSocket {
+        peer: flow_cxt.client_addr,
+        sender,
+        inbox: Mutex::new(inbox),
+    });
+    let endpoint = quinn::Endpoint::new_with_abstract_socket(
+        EndpointConfig::default(),
+        Some(server_config),
+        socket,
+        runtime,
+    )?;
+
+    let Some(incoming) = endpoint.accept().await else {
+        return Ok(());
+    };
+    let inner = incoming.await?;
+    trace!("CONNECT-UDP inner QUIC {}", flow_cxt.target_uri);
+    let h3_conn = h3::server::Connection::new(h3_quinn::Connection::new(inner)).await?;
+    serve_requests(h3_conn, flow_cxt).await
+}
+
+/// Forwards UDP payloads to the target untouched, recorded as a passthrough
+/// flow with the first datagrams each way as its messages.
+async fn relay(sender: TunnelSender, mut inbox: Inbox, flow_cxt: FlowContext) {
+    let flow_store = &flow_cxt.proxy_cxt.flow_store;
+    let flow_id = flow_store.new_passthrough_flow(&flow_cxt).await;
+    let mut recorded = 0;
+    let mut record = |message: WsMessage| {
+        if recorded < RECORDED_DATAGRAMS {
+            recorded += 1;
+            flow_store.post_event(flow_id, FlowEvent::WsMessage(message));
+        }
+    };
+
+    let result: io::Result<()> = async {
+        let target = tokio::net::lookup_host(flow_cxt.target_uri.host_port())
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::other("DNS lookup failed"))?;
+        let bind = if target.is_ipv4() {
+            SocketAddr::from(([0, 0, 0, 0], 0))
+        } else {
+            SocketAddr::from(([0u16; 8], 0))
+        };
+        let socket = UdpSocket::bind(bind).await?;
+        socket.connect(target).await?;
+
+        let mut buf = vec![0u8; MAX_UDP_PAYLOAD];
+        loop {
+            tokio::select! {
+                packet = inbox.recv() => match packet {
+                    Some(packet) => {
+                        socket.send(&packet).await?;
+                        record(WsMessage::client(Message::Binary(packet)));
+                    }
+                    None => return Ok(()),
+                },
+                read = socket.recv(&mut buf) => {
+                    let packet = &buf[..read?];
+                    sender.send(packet)?;
+                    record(WsMessage::server(Message::Binary(Bytes::copy_from_slice(packet))));
+                }
+            }
+        }
+    }
+    .await;
+    flow_store.post_event(
+        flow_id,
+        FlowEvent::TunnelClosed(
+            result
+                .err()
+                .map(|e| FlowError::new(FlowErrorKind::Connect, e.to_string())),
+        ),
+    );
+}
+
+/// Sends UDP payloads back to the client as HTTP datagrams of one tunnel.
+#[derive(Debug, Clone)]
+struct TunnelSender {
+    conn: quinn::Connection,
+    prefix: Bytes,
+}
+
+impl TunnelSender {
+    fn new(conn: quinn::Connection, quarter_id: u64) -> Self {
+        Self {
+            conn,
+            prefix: udp_prefix(quarter_id),
+        }
+    }
+
+    fn send(&self, payload: &[u8]) -> io::Result<()> {
+        let mut datagram = BytesMut::with_capacity(self.prefix.len() + payload.len());
+        datagram.extend_from_slice(&self.prefix);
+        datagram.extend_from_slice(payload);
+        match self.conn.send_datagram(datagram.freeze()) {
+            Ok(()) => Ok(()),
+            Err(SendDatagramError::TooLarge) => {
+                trace!("Dropping {} byte tunnel datagram", payload.len());
+                Ok(())
+            }
+            Err(err) => Err(io::Error::other(err)),
+        }
+    }
+}
+
+/// A UDP socket for quinn backed by a tunnel, every packet comes from and goes
+/// to the client.
+#[derive(Debug)]
+struct TunnelSocket {
+    peer: SocketAddr,
+    sender: TunnelSender,
+    inbox: Mutex<Inbox>,
+}
+
+#[derive(Debug)]
+struct AlwaysWritable;
+
+impl UdpPoller for AlwaysWritable {
+    fn poll_writable(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncUdpSocket for TunnelSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(AlwaysWritable)
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let segment_size = transmit
+            .segment_size
+            .unwrap_or(transmit.contents.len())
+            .max(1);
+        for packet in transmit.contents.chunks(segment_size) {
+            self.sender.send(packet)?;
+        }
+        Ok(())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut inbox = match self.inbox.lock() {
+            Ok(inbox) => inbox,
+            Err(err) => return Poll::Ready(Err(io::Error::other(err.to_string()))),
+        };
+        match inbox.poll_recv(cx) {
+            Poll::Ready(Some(packet)) => {
+                let (Some(buf), Some(meta)) = (bufs.first_mut(), meta.first_mut()) else {
+                    return Poll::Ready(Ok(0));
+                };
+                let len = packet.len().min(buf.len());
+                buf[..len].copy_from_slice(&packet[..len]);
+                meta.addr = self.peer;
+                meta.len = len;
+                meta.stride = len;
+                meta.ecn = None;
+                meta.dst_ip = None;
+                Poll::Ready(Ok(1))
+            }
+            Poll::Ready(None) => Poll::Ready(Err(io::ErrorKind::ConnectionAborted.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        let ip = self
+            .sender
+            .conn
+            .local_ip()
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        Ok(SocketAddr::new(ip, 0))
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connect_udp(uri: &str) -> http::Request<()> {
+        http::Request::builder()
+            .method(Method::CONNECT)
+            .uri(uri)
+            .extension(Protocol::CONNECT_UDP)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn masque_targets() {
+        let target = masque_target(&connect_udp(
+            "https://proxy/.well-known/masque/udp/example.com/443/",
+        ))
+        .unwrap();
+        assert_eq!(target.host_port(), "example.com:443");
+
+        let target = masque_target(&connect_udp(
+            "https://proxy/.well-known/masque/udp/2001%3Adb8%3A%3A1/8443/",
+        ))
+        .unwrap();
+        assert_eq!(target.port(), 8443);
+        assert_eq!(target.host(), "[2001:db8::1]");
+
+        assert!(masque_target(&connect_udp("https://proxy/")).is_none());
+        assert!(
+            masque_target(&connect_udp(
+                "https://proxy/.well-known/masque/udp/example.com/x/"
+            ))
+            .is_none()
+        );
+        let not_udp = http::Request::builder()
+            .method(Method::CONNECT)
+            .uri("https://proxy/.well-known/masque/udp/example.com/443/")
+            .body(())
+            .unwrap();
+        assert!(masque_target(&not_udp).is_none());
+    }
+}
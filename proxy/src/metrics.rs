@@ -0,0 +1,394 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    convert::Infallible,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use cow_utils::CowUtils;
+use http::{Method, Request, Response, StatusCode, header::CONTENT_TYPE};
+use http_body_util::Full;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use roxy_shared::http::HttpError;
+use time::OffsetDateTime;
+use tokio::{net::TcpListener, task::JoinHandle};
+use tracing::{error, trace};
+
+use crate::flow::Flow;
+
+/// Latencies kept per host for the percentiles, the oldest are dropped first.
+const MAX_SAMPLES_PER_HOST: usize = 1024;
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Counters over every completed flow, shared by clones.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Mutex<MetricsInner>>,
+}
+
+#[derive(Debug, Default)]
+struct MetricsInner {
+    requests: u64,
+    errors: u64,
+    request_bytes: u64,
+    response_bytes: u64,
+    status_codes: BTreeMap<u16, u64>,
+    hosts: BTreeMap<String, HostLatency>,
+    task_restarts: u64,
+    task_panics: u64,
+}
+
+#[derive(Debug, Default)]
+struct HostLatency {
+    count: u64,
+    sum: Duration,
+    samples: VecDeque<Duration>,
+}
+
+/// The metrics at one point in time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Flows that completed, with a response or an error.
+    pub requests: u64,
+    pub errors: u64,
+    /// Request body bytes received from clients.
+    pub request_bytes: u64,
+    /// Response body bytes received from servers.
+    pub response_bytes: u64,
+    /// Responses per status code, lowest code first.
+    pub status_codes: Vec<(u16, u64)>,
+    pub hosts: Vec<HostStats>,
+    /// Accept loops restarted after failing, panics included.
+    pub task_restarts: u64,
+    /// Accept loops restarted after panicking.
+    pub task_panics: u64,
+}
+
+/// Time from receiving a request to the end of its response, for one host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostStats {
+    pub host: String,
+    pub count: u64,
+    pub sum: Duration,
+    /// Percentiles over the last [`MAX_SAMPLES_PER_HOST`] requests.
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a flow that completed, called once per flow.
+    pub(crate) fn record(&self, flow: &Flow) {
+        let Some(request) = &flow.request else {
+            return;
+        };
+        let latency: Duration = (OffsetDateTime::now_utc() - request.timestamp)
+            .try_into()
+            .unwrap_or_default();
+
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(err) => {
+                error!("Metrics lock poisoned {err}");
+                return;
+            }
+        };
+        inner.requests += 1;
+        if flow.error.is_some() {
+            inner.errors += 1;
+        }
+        inner.request_bytes += request.body_len() as u64;
+        if let Some(response) = &flow.response {
+            inner.response_bytes += response.body_len() as u64;
+            *inner
+                .status_codes
+                .entry(response.status.as_u16())
+                .or_default() += 1;
+        }
+        let host = inner
+            .hosts
+            .entry(request.uri.host().to_string())
+            .or_default();
+        host.count += 1;
+        host.sum += latency;
+        if host.samples.len() == MAX_SAMPLES_PER_HOST {
+            host.samples.pop_front();
+        }
+        host.samples.push_back(latency);
+    }
+
+    /// Counts an accept loop being restarted.
+    pub(crate) fn record_restart(&self, panicked: bool) {
+        match self.inner.lock() {
+            Ok(mut inner) => {
+                inner.task_restarts += 1;
+                if panicked {
+                    inner.task_panics += 1;
+                }
+            }
+            Err(err) => error!("Metrics lock poisoned {err}"),
+        }
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(err) => {
+                error!("Metrics lock poisoned {err}");
+                return MetricsSnapshot::default();
+            }
+        };
+        MetricsSnapshot {
+            requests: inner.requests,
+            errors: inner.errors,
+            request_bytes: inner.request_bytes,
+            response_bytes: inner.response_bytes,
+            status_codes: inner
+                .status_codes
+                .iter()
+                .map(|(code, count)| (*code, *count))
+                .collect(),
+            hosts: inner
+                .hosts
+                .iter()
+                .map(|(host, latency)| {
+                    let mut samples: Vec<Duration> = latency.samples.iter().copied().collect();
+                    samples.sort();
+                    HostStats {
+                        host: host.clone(),
+                        count: latency.count,
+                        sum: latency.sum,
+                        p50: percentile(&samples, 0.5),
+                        p90: percentile(&samples, 0.9),
+                        p99: percentile(&samples, 0.99),
+                    }
+                })
+                .collect(),
+            task_restarts: inner.task_restarts,
+            task_panics: inner.task_panics,
+        }
+    }
+
+    pub fn reset(&self) {
+        match self.inner.lock() {
+            Ok(mut inner) => *inner = MetricsInner::default(),
+            Err(err) => error!("Metrics lock poisoned {err}"),
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted `samples`.
+fn percentile(samples: &[Duration], quantile: f64) -> Duration {
+    if samples.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (quantile * samples.len() as f64).ceil() as usize;
+    samples[rank.clamp(1, samples.len()) - 1]
+}
+
+/// Escapes a Prometheus label value.
+fn label(value: &str) -> String {
+    value
+        .cow_replace('\\', "\\\\")
+        .cow_replace('"', "\\\"")
+        .cow_replace('\n', "\\n")
+        .into_owned()
+}
+
+impl MetricsSnapshot {
+    /// The metrics in Prometheus' text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "roxy_requests_total",
+                "Requests completed with a response or an error.",
+                self.requests,
+            ),
+            ("roxy_errors_total", "Requests that failed.", self.errors),
+            (
+                "roxy_request_bytes_total",
+                "Request body bytes received from clients.",
+                self.request_bytes,
+            ),
+            (
+                "roxy_response_bytes_total",
+                "Response body bytes received from servers.",
+                self.response_bytes,
+            ),
+            (
+                "roxy_task_restarts_total",
+                "Accept loops restarted after failing.",
+                self.task_restarts,
+            ),
+            (
+                "roxy_task_panics_total",
+                "Accept loops restarted after panicking.",
+                self.task_panics,
+            ),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {value}");
+        }
+
+        let _ = writeln!(out, "# HELP roxy_responses_total Responses by status code.");
+        let _ = writeln!(out, "# TYPE roxy_responses_total counter");
+        for (code, count) in &self.status_codes {
+            let _ = writeln!(out, "roxy_responses_total{{code=\"{code}\"}} {count}");
+        }
+
+        let name = "roxy_request_duration_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Time from the request to the end of its response, by host."
+        );
+        let _ = writeln!(out, "# TYPE {name} summary");
+        for host in &self.hosts {
+            let host_label = label(&host.host);
+            for (quantile, value) in [("0.5", host.p50), ("0.9", host.p90), ("0.99", host.p99)] {
+                let _ = writeln!(
+                    out,
+                    "{name}{{host=\"{host_label}\",quantile=\"{quantile}\"}} {}",
+                    value.as_secs_f64()
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{name}_sum{{host=\"{host_label}\"}} {}",
+                host.sum.as_secs_f64()
+            );
+            let _ = writeln!(out, "{name}_count{{host=\"{host_label}\"}} {}", host.count);
+        }
+        out
+    }
+}
+
+/// Serves the metrics on `GET /metrics` to Prometheus scrapers.
+pub(crate) async fn start_metrics(
+    metrics: Metrics,
+    listener: TcpListener,
+) -> Result<JoinHandle<()>, HttpError> {
+    let addr = listener.local_addr()?;
+    let handle = tokio::spawn(async move {
+        trace!("Metrics listening on {addr}");
+        while let Ok((stream, _)) = listener.accept().await {
+            let metrics = metrics.clone();
+            tokio::task::spawn(async move {
+                let io = TokioIo::new(stream);
+                if let Err(err) = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(io, service_fn(|req| serve(metrics.clone(), req)))
+                    .await
+                {
+                    error!("Failed to serve metrics connection: {:?}", err);
+                }
+            });
+        }
+        error!("Metrics server finished");
+    });
+    Ok(handle)
+}
+
+async fn serve<B>(metrics: Metrics, req: Request<B>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let response = if req.method() == Method::GET && req.uri().path() == "/metrics" {
+        Response::builder()
+            .header(CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)
+            .body(Full::new(Bytes::from(metrics.snapshot().to_prometheus())))
+    } else {
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::new()))
+    };
+    Ok(response.unwrap_or_default())
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use crate::flow::{
+        error::{FlowError, FlowErrorKind},
+        test_flow,
+    };
+
+    use super::*;
+
+    fn flow(host: &str, status: Option<StatusCode>, body: &'static [u8]) -> Flow {
+        let uri = format!("https://{host}/");
+        let mut flow = test_flow(1, Method::GET, &uri, status.unwrap_or_default(), body);
+        flow.request.as_mut().unwrap().body = Bytes::from_static(b"ping");
+        if status.is_none() {
+            flow.response = None;
+            flow.error = Some(FlowError::new(FlowErrorKind::Connect, "refused"));
+        }
+        flow
+    }
+
+    #[test]
+    fn counts_flows() {
+        let metrics = Metrics::new();
+        metrics.record(&flow("a.com", Some(StatusCode::OK), b"hello"));
+        metrics.record(&flow("a.com", Some(StatusCode::OK), b"hi"));
+        metrics.record(&flow("b.com", Some(StatusCode::NOT_FOUND), b""));
+        metrics.record(&flow("b.com", None, b""));
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.requests, 4);
+        assert_eq!(snapshot.errors, 1);
+        assert_eq!(snapshot.request_bytes, 16);
+        assert_eq!(snapshot.response_bytes, 7);
+        assert_eq!(snapshot.status_codes, vec![(200, 2), (404, 1)]);
+        let hosts: Vec<(&str, u64)> = snapshot
+            .hosts
+            .iter()
+            .map(|h| (h.host.as_str(), h.count))
+            .collect();
+        assert_eq!(hosts, vec![("a.com", 2), ("b.com", 2)]);
+
+        metrics.reset();
+        assert_eq!(metrics.snapshot(), MetricsSnapshot::default());
+    }
+
+    #[test]
+    fn percentiles() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 0.5), Duration::from_millis(50));
+        assert_eq!(percentile(&samples, 0.99), Duration::from_millis(99));
+        assert_eq!(percentile(&samples[..1], 0.9), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 0.5), Duration::ZERO);
+    }
+
+    #[test]
+    fn prometheus_format() {
+        let snapshot = MetricsSnapshot {
+            requests: 2,
+            status_codes: vec![(200, 2)],
+            hosts: vec![HostStats {
+                host: "a\"b".to_string(),
+                count: 2,
+                sum: Duration::from_millis(300),
+                p50: Duration::from_millis(100),
+                p90: Duration::from_millis(200),
+                p99: Duration::from_millis(200),
+            }],
+            ..Default::default()
+        };
+        let text = snapshot.to_prometheus();
+        assert!(text.contains("# TYPE roxy_requests_total counter\nroxy_requests_total 2\n"));
+        assert!(text.contains("roxy_task_restarts_total 0\n"));
+        assert!(text.contains("roxy_responses_total{code=\"200\"} 2\n"));
+        assert!(
+            text.contains("roxy_request_duration_seconds{host=\"a\\\"b\",quantile=\"0.5\"} 0.1\n")
+        );
+        assert!(text.contains("roxy_request_duration_seconds_sum{host=\"a\\\"b\"} 0.3\n"));
+        assert!(text.contains("roxy_request_duration_seconds_count{host=\"a\\\"b\"} 2\n"));
+    }
+}
@@ -0,0 +1,61 @@
+use roxy_shared::upstream::connect_upstream;
+use tokio::io::{AsyncRead, AsyncWrite, copy_bidirectional};
+use tracing::trace;
+
+use crate::{
+    flow::{
+        FlowEvent, FlowEventEmitter,
+        error::{FlowError, FlowErrorKind},
+    },
+    proxy::FlowContext,
+};
+
+/// Tunnels `client_stream` to the target without terminating TLS or parsing
+/// anything, the flow only records the connection.
+pub async fn handle_passthrough<S>(
+    flow_cxt: FlowContext,
+    mut client_stream: S,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let target = &flow_cxt.target_uri;
+    trace!("Passthrough {target}");
+    let flow_store = &flow_cxt.proxy_cxt.flow_store;
+    let flow_id = flow_store.new_passthrough_flow(&flow_cxt).await;
+
+    let upstream = flow_cxt.proxy_cxt.upstream.resolve(target.host());
+    let emitter = FlowEventEmitter::new(flow_id, flow_store.clone());
+    let connected = connect_upstream(
+        upstream.as_ref(),
+        &flow_cxt.proxy_cxt.dns,
+        target.host(),
+        target.port(),
+        &emitter,
+    )
+    .await;
+    let mut server_stream = match connected {
+        Ok(stream) => stream,
+        Err(err) => {
+            flow_store.post_event(
+                flow_id,
+                FlowEvent::TunnelClosed(Some(FlowError::from(&err))),
+            );
+            return Err(Box::new(err));
+        }
+    };
+
+    let result = copy_bidirectional(&mut client_stream, &mut server_stream).await;
+    trace!("Passthrough {target} closed {result:?}");
+    flow_store.post_event(
+        flow_id,
+        FlowEvent::TunnelClosed(
+            result
+                .as_ref()
+                .err()
+                .map(|e| FlowError::new(FlowErrorKind::Connect, e.to_string())),
+        ),
+    );
+    result?;
+    Ok(())
+}
@@ -1,10 +1,75 @@
 use bytes::Bytes;
+use cow_utils::CowUtils;
 use std::io;
 use std::{
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::time::timeout;
+
+/// How HTTP/2 clients with prior knowledge open a connection.
+pub(crate) const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const TLS_HANDSHAKE: u8 = 0x16;
+const WS_UPGRADE: &str = "upgrade: websocket";
+
+/// What a client speaks, told from the first bytes it sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PeekedProtocol {
+    /// An HTTP/1 request, or nothing at all from a client that hung up.
+    Http1,
+    /// An HTTP/1 request upgrading to a WebSocket.
+    WebSocket,
+    Http2,
+    /// The start of a TLS handshake.
+    Tls,
+    /// Anything else, e.g. SMTP commands or a binary protocol.
+    Unknown,
+}
+
+impl PeekedProtocol {
+    pub(crate) fn detect(preview: &[u8]) -> Self {
+        if preview.is_empty() {
+            PeekedProtocol::Http1
+        } else if preview.starts_with(H2_PREFACE) {
+            PeekedProtocol::Http2
+        } else if preview.first() == Some(&TLS_HANDSHAKE) {
+            PeekedProtocol::Tls
+        } else if !looks_like_http(preview) {
+            PeekedProtocol::Unknown
+        } else if preview.starts_with(b"GET ") && is_ws_upgrade(preview) {
+            PeekedProtocol::WebSocket
+        } else {
+            PeekedProtocol::Http1
+        }
+    }
+}
+
+/// Whether `preview` starts with an HTTP/1 request line: a method token, and
+/// the version once the whole line was read.
+fn looks_like_http(preview: &[u8]) -> bool {
+    let method_len = preview
+        .iter()
+        .take_while(|b| b.is_ascii_uppercase() || **b == b'-')
+        .count();
+    if method_len == 0 || preview.get(method_len) != Some(&b' ') {
+        return false;
+    }
+    match preview.iter().position(|b| *b == b'\n') {
+        Some(end) => {
+            let line = preview[..end].trim_ascii_end();
+            line.len() > 8 && line[line.len() - 8..].starts_with(b"HTTP/1.")
+        }
+        None => true,
+    }
+}
+
+fn is_ws_upgrade(preview: &[u8]) -> bool {
+    std::str::from_utf8(preview)
+        .map(|p| p.cow_to_ascii_lowercase().contains(WS_UPGRADE))
+        .unwrap_or(false)
+}
 
 /// This is required because hyper does not support peeking into the stream
 pub struct PeekStream<S> {
@@ -27,6 +92,31 @@ impl<S: AsyncRead + AsyncWrite + Unpin> PeekStream<S> {
         };
         Ok((wrapped, bytes))
     }
+
+    /// Like [`PeekStream::new`] but gives up after `wait`, `None` when the
+    /// peer sent nothing by then. Clients of protocols where the server
+    /// speaks first, such as SMTP, stay silent.
+    pub async fn with_timeout(
+        mut stream: S,
+        peek_len: usize,
+        wait: Duration,
+    ) -> io::Result<(Self, Option<Bytes>)> {
+        let mut buf = vec![0u8; peek_len];
+        let peeked = match timeout(wait, stream.read(&mut buf)).await {
+            Ok(n) => {
+                buf.truncate(n?);
+                Some(Bytes::from(buf))
+            }
+            Err(_) => None,
+        };
+
+        let wrapped = Self {
+            stream,
+            buffer: peeked.clone().unwrap_or_default(),
+            consumed: 0,
+        };
+        Ok((wrapped, peeked))
+    }
 }
 
 impl<S: AsyncRead + Unpin> AsyncRead for PeekStream<S> {
@@ -64,3 +154,29 @@ impl<S: AsyncWrite + Unpin> AsyncWrite for PeekStream<S> {
         Pin::new(&mut self.stream).poll_shutdown(cx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_protocols() {
+        let detect = PeekedProtocol::detect;
+        assert_eq!(
+            detect(b"GET / HTTP/1.1\r\nHost: a\r\n"),
+            PeekedProtocol::Http1
+        );
+        assert_eq!(detect(b"M-SEARCH * HTTP/1.1\r\n"), PeekedProtocol::Http1);
+        assert_eq!(detect(b"POST /very/long/path?q="), PeekedProtocol::Http1);
+        assert_eq!(detect(b""), PeekedProtocol::Http1);
+        assert_eq!(
+            detect(b"GET /chat HTTP/1.1\r\nUpgrade: websocket\r\n"),
+            PeekedProtocol::WebSocket
+        );
+        assert_eq!(detect(H2_PREFACE), PeekedProtocol::Http2);
+        assert_eq!(detect(&[0x16, 0x03, 0x01]), PeekedProtocol::Tls);
+        assert_eq!(detect(b"EHLO example.com\r\n"), PeekedProtocol::Unknown);
+        assert_eq!(detect(b"a001 LOGIN user pass\r\n"), PeekedProtocol::Unknown);
+        assert_eq!(detect(&[0x00, 0x01, 0x02]), PeekedProtocol::Unknown);
+    }
+}
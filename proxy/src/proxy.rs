@@ -3,7 +3,8 @@ use http::HeaderMap;
 use http::StatusCode;
 use http::Uri;
 use http::Version;
-use http::header::HOST;
+use http::header::{HOST, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION};
+use http::uri::Scheme;
 use http_body_util::Empty;
 use http_body_util::combinators::BoxBody;
 use hyper_util::rt::TokioIo;
@@ -11,52 +12,111 @@ use roxy_shared::RoxyCA;
 use roxy_shared::alpn::AlpnProtocol;
 use roxy_shared::alpn::alp_h1_h2;
 use roxy_shared::cert::ServerTlsConnectionData;
+use roxy_shared::client::{ClientContext, RClientBuilder};
+use roxy_shared::dns::DnsConfig;
 use roxy_shared::http::HttpError;
+use roxy_shared::pool::{ConnectionPool, PoolStats};
 use roxy_shared::tls::RustlsServerConfig;
 use roxy_shared::tls::TlsConfig;
+use roxy_shared::upstream::UpstreamConfig;
 use roxy_shared::uri::RUri;
 use rustls::sign::CertifiedKey;
-use tokio::net::TcpListener;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Notify, watch};
 use tokio::task::JoinHandle;
 use tracing::debug;
 use tracing::error;
 use tracing::trace;
 
-use rustls::pki_types::PrivateKeyDer;
-
-type ServerBuilder = hyper::server::conn::http1::Builder;
 use hyper::service::service_fn;
 use hyper::upgrade::Upgraded;
 use hyper::{Method, Request, Response};
 use std::convert::Infallible;
 use std::io;
-use std::net::SocketAddr;
 use std::net::UdpSocket;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use time::OffsetDateTime;
 use tokio_rustls::TlsAcceptor;
 
+use crate::acl::ClientAcl;
+use crate::api::{ApiState, start_api};
+use crate::auth::ProxyAuth;
+use crate::breakpoint::Breakpoints;
+use crate::cache::ResponseCache;
+use crate::cert_portal;
+use crate::diagnostics::{Diagnostics, ProblemKind};
+use crate::filter::HostFilter;
 use crate::flow::FlowCerts;
 use crate::flow::FlowStore;
+use crate::flow::FlowTls;
+use crate::flow::id::FlowId;
+use crate::flow::replay::ReplayError;
+use crate::flow_log::{FlowLog, start_flow_log};
 use crate::h3::start_h3;
-use crate::http::handle_h2;
-use crate::http::{handle_http, handle_https};
+use crate::head_recorder::HeadRecorder;
+use crate::hook::{Hooks, start_hooks};
+use crate::http::{capped, handle_h2, handle_h2c};
+use crate::http::{handle_http, handle_http_stream, handle_https, record_folded};
 use crate::interceptor::ScriptEngine;
-use crate::peek_stream::PeekStream;
-use crate::ws::{handle_ws, handle_wss};
+use crate::interceptor::connection::{ConnectionHook, ConnectionInfo};
+use crate::limits::{ProxyLimits, Slot, Slots};
+use crate::listener::{ListenerConfig, start_listener};
+use crate::metrics::{Metrics, start_metrics};
+use crate::passthrough::handle_passthrough;
+use crate::peek_stream::{H2_PREFACE, PeekStream, PeekedProtocol};
+use crate::retry::Retries;
+use crate::rewrite::Rewrites;
+use crate::scenario::{Scenario, ScenarioReport, run_scenario};
+use crate::settings::ProxySettings;
+use crate::socks::start_socks;
+use crate::supervisor::{ProxyHealth, Supervisor};
+use crate::tunnel::handle_unknown_protocol;
+use crate::version::Versions;
+use crate::ws::{WsSessions, handle_ws, handle_wss, replay_ws};
 
-const GET_BYTES: &[u8] = b"GET ";
+/// How long a tunneled client may stay silent before the server is assumed to
+/// speak first, as in SMTP, and the tunnel is treated as an unknown protocol.
+const SERVER_FIRST_WAIT: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone)]
 pub struct ProxyManager {
-    port_tcp: u16,
-    port_udp: u16,
+    port: u16,
+    bind: IpAddr,
+    main: Arc<Mutex<MainListener>>,
     ca: RoxyCA,
     script_engine: ScriptEngine,
     tls_config: TlsConfig,
+    upstream: UpstreamConfig,
+    dns: DnsConfig,
+    pool: ConnectionPool,
+    host_filter: HostFilter,
+    acl: ClientAcl,
+    auth: ProxyAuth,
+    limits: ProxyLimits,
+    retries: Retries,
+    versions: Versions,
+    breakpoints: Breakpoints,
+    rewrites: Rewrites,
+    cache: ResponseCache,
+    ws_sessions: WsSessions,
+    diagnostics: Diagnostics,
+    hooks: Hooks,
+    listeners: Vec<ListenerConfig>,
+    shutdown: Arc<Notify>,
+    supervisor: Supervisor,
     pub flow_store: FlowStore,
-    http_handle: Option<Arc<JoinHandle<()>>>,
-    h3_handle: Option<Arc<JoinHandle<()>>>,
+    socks_handle: Option<Arc<JoinHandle<()>>>,
+    metrics_handle: Option<Arc<JoinHandle<()>>>,
+    hooks_handle: Option<Arc<JoinHandle<()>>>,
+    flow_log_handle: Option<Arc<JoinHandle<()>>>,
+    api_handle: Option<Arc<JoinHandle<()>>>,
+    settings_handle: Option<Arc<JoinHandle<()>>>,
+    listener_handles: Vec<Arc<JoinHandle<()>>>,
+    listener_addrs: Vec<SocketAddr>,
 }
 
 impl ProxyManager {
@@ -67,52 +127,289 @@ impl ProxyManager {
         tls_config: TlsConfig,
         flow_store: FlowStore,
     ) -> Self {
+        script_engine.set_flow_store(flow_store.clone());
+        let supervisor = Supervisor::new(script_engine.clone(), flow_store.metrics());
         ProxyManager {
-            port_tcp: port,
-            port_udp: port,
+            port,
+            bind: Ipv4Addr::LOCALHOST.into(),
+            main: Arc::default(),
             ca,
             script_engine,
             tls_config,
+            upstream: UpstreamConfig::default(),
+            dns: DnsConfig::default(),
+            pool: ConnectionPool::new(),
+            host_filter: HostFilter::default(),
+            acl: ClientAcl::default(),
+            auth: ProxyAuth::default(),
+            limits: ProxyLimits::default(),
+            retries: Retries::default(),
+            versions: Versions::default(),
+            breakpoints: Breakpoints::default(),
+            rewrites: Rewrites::default(),
+            cache: ResponseCache::default(),
+            ws_sessions: WsSessions::default(),
+            diagnostics: Diagnostics::default(),
+            hooks: Hooks::default(),
+            listeners: vec![],
+            shutdown: Arc::new(Notify::new()),
+            supervisor,
             flow_store,
-            http_handle: None,
-            h3_handle: None,
+            socks_handle: None,
+            metrics_handle: None,
+            hooks_handle: None,
+            flow_log_handle: None,
+            api_handle: None,
+            settings_handle: None,
+            listener_handles: vec![],
+            listener_addrs: vec![],
         }
     }
 
+    /// Binds the main port on `bind` instead of `127.0.0.1`, e.g. `0.0.0.0` or
+    /// `::` to accept clients from other machines.
+    pub fn with_bind_addr(mut self, bind: IpAddr) -> Self {
+        self.bind = bind;
+        self
+    }
+
+    /// Chains outbound connections through the proxies in `upstream`.
+    pub fn with_upstream(mut self, upstream: UpstreamConfig) -> Self {
+        self.upstream = upstream;
+        self
+    }
+
+    /// Resolves the hosts of direct outbound connections through `dns`.
+    pub fn with_dns(mut self, dns: DnsConfig) -> Self {
+        self.dns = dns;
+        self
+    }
+
+    /// Counters of the upstream connection pool.
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.stats()
+    }
+
+    /// Tunnels hosts rejected by `host_filter` without interception.
+    pub fn with_host_filter(mut self, host_filter: HostFilter) -> Self {
+        self.host_filter = host_filter;
+        self
+    }
+
+    /// Handle to the running filter, rules set through it apply to new connections.
+    pub fn host_filter(&self) -> HostFilter {
+        self.host_filter.clone()
+    }
+
+    /// Refuses connections from clients outside the ranges of `acl`.
+    pub fn with_acl(mut self, acl: ClientAcl) -> Self {
+        self.acl = acl;
+        self
+    }
+
+    /// Handle to the allowed client ranges, updates apply to new connections.
+    pub fn acl(&self) -> ClientAcl {
+        self.acl.clone()
+    }
+
+    /// Requires clients to send one of the credentials of `auth`.
+    pub fn with_auth(mut self, auth: ProxyAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Handle to the accepted credentials, updates apply to the next request.
+    pub fn auth(&self) -> ProxyAuth {
+        self.auth.clone()
+    }
+
+    /// Bounds header and body sizes and how long flows may take.
+    pub fn with_limits(mut self, limits: ProxyLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Handle to the active limits.
+    pub fn limits(&self) -> ProxyLimits {
+        self.limits.clone()
+    }
+
+    /// Sends idempotent requests again after connection failures and timeouts.
+    pub fn with_retries(mut self, retries: Retries) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Handle to the active retry policy.
+    pub fn retries(&self) -> Retries {
+        self.retries.clone()
+    }
+
+    /// Picks the HTTP version requests are sent upstream with by host.
+    pub fn with_versions(mut self, versions: Versions) -> Self {
+        self.versions = versions;
+        self
+    }
+
+    /// Handle to the active version rules.
+    pub fn versions(&self) -> Versions {
+        self.versions.clone()
+    }
+
+    /// Pauses requests matching `breakpoints` before they are forwarded.
+    pub fn with_breakpoints(mut self, breakpoints: Breakpoints) -> Self {
+        self.breakpoints = breakpoints;
+        self
+    }
+
+    /// Handle used to resume or drop paused flows.
+    pub fn breakpoints(&self) -> Breakpoints {
+        self.breakpoints.clone()
+    }
+
+    /// Handle used to send messages over live WebSocket connections.
+    pub fn ws_sessions(&self) -> WsSessions {
+        self.ws_sessions.clone()
+    }
+
+    /// Hosts whose clients could not be intercepted, e.g. for pinning.
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.diagnostics.clone()
+    }
+
+    /// Redirects requests matching `rewrites` before scripts run.
+    pub fn with_rewrites(mut self, rewrites: Rewrites) -> Self {
+        self.rewrites = rewrites;
+        self
+    }
+
+    /// Handle to the active rewrite rules, updates apply to new requests.
+    pub fn rewrites(&self) -> Rewrites {
+        self.rewrites.clone()
+    }
+
+    /// Stores and serves responses through `cache` depending on its mode.
+    pub fn with_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Handle used to switch the cache mode while running.
+    pub fn cache(&self) -> ResponseCache {
+        self.cache.clone()
+    }
+
+    /// Notifies webhooks and commands of completed flows matching `hooks`.
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Handle to the active hook rules, updates apply to flows completing afterwards.
+    pub fn hooks(&self) -> Hooks {
+        self.hooks.clone()
+    }
+
+    /// Accepts clients on `listener` too once started, with its own mode and
+    /// policies.
+    pub fn with_listener(mut self, listener: ListenerConfig) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    /// Addresses the extra listeners are bound to, in the order they were added.
+    pub fn listener_addrs(&self) -> Vec<SocketAddr> {
+        self.listener_addrs.clone()
+    }
+
+    /// Port of the main proxy listener, `0` until it is started.
+    pub fn port(&self) -> u16 {
+        self.addr().map(|addr| addr.port()).unwrap_or_default()
+    }
+
+    /// Address the main proxy listener is bound to, `None` until it is started.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        lock_main(&self.main).and_then(|m| m.addr)
+    }
+
+    /// Starts the main proxy port and every extra listener.
     pub async fn start_all(&mut self) -> Result<(), HttpError> {
-        let tcp_listener =
-            TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], self.port_tcp))).await?;
-        let udp_socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], self.port_udp)))?;
+        bind_main(
+            self.cxt(),
+            &self.main,
+            SocketAddr::new(self.bind, self.port),
+        )
+        .await?;
 
-        let http_handle = start_tcp(self.cxt(), tcp_listener)
-            .await
-            .map_err(|_| HttpError::Alpn)?; // TODO: Wrong error
-        let h3_handle = start_h3(self.cxt(), udp_socket)
-            .await
-            .map_err(|_| HttpError::Alpn)?; // TODO: Wrong error
-        self.h3_handle = Some(Arc::new(h3_handle));
-        self.http_handle = Some(Arc::new(http_handle));
+        for listener in self.listeners.clone() {
+            self.bind_listener(&listener).await?;
+        }
 
         Ok(())
     }
 
-    fn cxt(&self) -> ProxyContext {
+    /// Starts `listener` next to the running ones, returns the address it is
+    /// bound to. It is not restarted by `start_all`.
+    pub async fn start_listener(
+        &mut self,
+        listener: ListenerConfig,
+    ) -> Result<SocketAddr, HttpError> {
+        self.bind_listener(&listener).await
+    }
+
+    async fn bind_listener(&mut self, listener: &ListenerConfig) -> Result<SocketAddr, HttpError> {
+        let (addr, handles) = start_listener(self.cxt(), listener).await?;
+        self.listener_addrs.push(addr);
+        self.listener_handles
+            .extend(handles.into_iter().map(Arc::new));
+        Ok(addr)
+    }
+
+    /// Stops accepting clients on the main port and every extra listener.
+    /// Connections already accepted are left to finish.
+    pub fn stop_all(&mut self) {
+        if let Some(mut main) = lock_main(&self.main) {
+            main.abort();
+        }
+        for h in self.listener_handles.drain(..) {
+            h.abort();
+        }
+        self.listener_addrs.clear();
+    }
+
+    /// Shared state handed to every connection, for callers issuing requests outside the proxy.
+    pub fn cxt(&self) -> ProxyContext {
         ProxyContext {
             ca: self.ca.clone(),
             script_engine: self.script_engine.clone(),
             flow_store: self.flow_store.clone(),
             tls_config: self.tls_config.clone(),
+            upstream: self.upstream.clone(),
+            dns: self.dns.clone(),
+            pool: self.pool.clone(),
+            host_filter: self.host_filter.clone(),
+            acl: self.acl.clone(),
+            auth: self.auth.clone(),
+            limits: self.limits.clone(),
+            retries: self.retries.clone(),
+            versions: self.versions.clone(),
+            breakpoints: self.breakpoints.clone(),
+            rewrites: self.rewrites.clone(),
+            cache: self.cache.clone(),
+            ws_sessions: self.ws_sessions.clone(),
+            diagnostics: self.diagnostics.clone(),
+            supervisor: self.supervisor.clone(),
         }
     }
 
     pub async fn start_udp(&mut self, udp_socket: UdpSocket) -> Result<(), HttpError> {
-        let addr = udp_socket.local_addr()?;
         let h3_handle = start_h3(self.cxt(), udp_socket)
             .await
             .map_err(|_| HttpError::Alpn)?; // TODO: Wrong error
 
-        self.port_udp = addr.port();
-        self.h3_handle = Some(Arc::new(h3_handle));
+        if let Some(mut main) = lock_main(&self.main) {
+            main.set_h3(h3_handle);
+        }
 
         Ok(())
     }
@@ -120,30 +417,308 @@ impl ProxyManager {
         let addr = tcp_listeneter.local_addr()?;
         let http_handle = start_tcp(self.cxt(), tcp_listeneter).await?;
 
-        self.port_tcp = addr.port();
-        self.http_handle = Some(Arc::new(http_handle));
+        if let Some(mut main) = lock_main(&self.main) {
+            main.set_http(addr, http_handle);
+        }
+
+        Ok(())
+    }
+
+    /// Accepts SOCKS5 clients on `listener`, intercepting them like CONNECT tunnels.
+    pub async fn start_socks(&mut self, listener: TcpListener) -> Result<(), HttpError> {
+        let socks_handle = start_socks(self.cxt(), listener).await?;
+        self.socks_handle = Some(Arc::new(socks_handle));
+        Ok(())
+    }
 
+    /// Serves Prometheus metrics of the proxied traffic on `listener`, at `/metrics`.
+    pub async fn start_metrics(&mut self, listener: TcpListener) -> Result<(), HttpError> {
+        let metrics_handle = start_metrics(self.metrics(), listener).await?;
+        self.metrics_handle = Some(Arc::new(metrics_handle));
         Ok(())
     }
+
+    /// Starts firing the hooks, flows completing before this are not notified.
+    pub fn start_hooks(&mut self) {
+        let hooks_handle = start_hooks(self.cxt(), self.hooks());
+        self.hooks_handle = Some(Arc::new(hooks_handle));
+    }
+
+    /// Writes flows completing from now on to `log`, replacing any log
+    /// started before.
+    pub fn start_flow_log(&mut self, log: FlowLog) {
+        if let Some(h) = self.flow_log_handle.take() {
+            h.abort();
+        }
+        let flow_log_handle = start_flow_log(self.flow_store.clone(), log);
+        self.flow_log_handle = Some(Arc::new(flow_log_handle));
+    }
+
+    /// Serves the JSON control API on `listener` to clients presenting `token`,
+    /// see the book for the routes.
+    pub async fn start_api(
+        &mut self,
+        listener: TcpListener,
+        token: String,
+    ) -> Result<(), HttpError> {
+        let state = ApiState {
+            flow_store: self.flow_store.clone(),
+            script_engine: self.script_engine.clone(),
+            host_filter: self.host_filter(),
+            ws_sessions: self.ws_sessions.clone(),
+            shutdown: self.shutdown.clone(),
+            supervisor: self.supervisor.clone(),
+            token,
+        };
+        let api_handle = start_api(state, listener).await?;
+        self.api_handle = Some(Arc::new(api_handle));
+        Ok(())
+    }
+
+    /// Applies every value sent on `settings` to the running proxy. Rules and
+    /// modes take effect for new connections and requests. A new port is bound
+    /// before the old one is released, a failed bind keeps the current port.
+    pub fn watch_settings(&mut self, mut settings: watch::Receiver<ProxySettings>) {
+        let cxt = self.cxt();
+        let hooks = self.hooks();
+        let main = self.main.clone();
+        let settings_handle = tokio::spawn(async move {
+            while settings.changed().await.is_ok() {
+                let update = settings.borrow_and_update().clone();
+                apply_settings(&cxt, &hooks, &main, update).await;
+            }
+        });
+        self.settings_handle = Some(Arc::new(settings_handle));
+    }
+
+    /// Resolves once a shutdown was requested through the control API.
+    pub fn shutdown_requested(&self) -> impl Future<Output = ()> + use<> {
+        let shutdown = self.shutdown.clone();
+        async move { shutdown.notified().await }
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        self.flow_store.metrics()
+    }
+
+    /// Whether the accept loops of every port are running. A loop that fails
+    /// or panics is restarted with a backoff and reported here meanwhile.
+    pub fn health(&self) -> ProxyHealth {
+        self.supervisor.health()
+    }
+
+    /// Whether new flows are recorded, traffic is proxied either way.
+    pub fn capturing(&self) -> bool {
+        self.flow_store.capturing()
+    }
+
+    pub fn set_capturing(&self, capturing: bool) {
+        self.flow_store.set_capturing(capturing);
+    }
+
+    /// Re-sends the request of flow `id` as a new flow, using the same rewrite
+    /// rules, upstream and TLS setup as intercepted traffic. Rewrite rules let
+    /// imported flows be sent to another server than the one they were
+    /// recorded against. WebSocket flows are replayed by sending the messages
+    /// their client sent over a new connection. The future does not borrow the
+    /// manager so it can be spawned.
+    pub fn replay(&self, id: FlowId) -> impl Future<Output = Result<FlowId, ReplayError>> + use<> {
+        let cxt = self.cxt();
+        async move {
+            let flow = cxt
+                .flow_store
+                .get_flow_by_id(id)
+                .await
+                .ok_or(ReplayError::NotFound(id))?;
+            let guard = flow.read().await;
+            let client_connection = guard.client_connection;
+            let mut request = guard.request.clone().ok_or(ReplayError::NoRequest)?;
+            drop(guard);
+            if matches!(request.uri.scheme_str(), Some("ws" | "wss")) {
+                return replay_ws(&cxt, id).await;
+            }
+            cxt.rewrites.apply(&mut request);
+            let client = cxt.client_builder(request.uri.host());
+            cxt.flow_store
+                .replay_request(client_connection, request, client)
+                .await
+        }
+    }
+
+    /// Replays `scenario` and compares each replay with its recording. The
+    /// future does not borrow the manager so it can be spawned.
+    pub fn run_scenario(&self, scenario: Scenario) -> impl Future<Output = ScenarioReport> + use<> {
+        let cxt = self.cxt();
+        async move { run_scenario(&cxt, scenario).await }
+    }
 }
 
 impl Drop for ProxyManager {
     fn drop(&mut self) {
-        if let Some(h) = &self.http_handle {
+        if let Some(mut main) = lock_main(&self.main) {
+            main.abort();
+        }
+        if let Some(h) = &self.socks_handle {
+            h.abort();
+        }
+        if let Some(h) = &self.metrics_handle {
+            h.abort();
+        }
+        if let Some(h) = &self.hooks_handle {
+            h.abort();
+        }
+        if let Some(h) = &self.flow_log_handle {
+            h.abort();
+        }
+        if let Some(h) = &self.api_handle {
+            h.abort();
+        }
+        if let Some(h) = &self.settings_handle {
+            h.abort();
+        }
+        for h in &self.listener_handles {
+            h.abort();
+        }
+    }
+}
+
+/// The main proxy port, shared with the settings watcher so it can be rebound.
+#[derive(Debug, Default)]
+struct MainListener {
+    addr: Option<SocketAddr>,
+    http_handle: Option<JoinHandle<()>>,
+    h3_handle: Option<JoinHandle<()>>,
+}
+
+impl MainListener {
+    fn set_http(&mut self, addr: SocketAddr, handle: JoinHandle<()>) {
+        self.addr = Some(addr);
+        if let Some(old) = self.http_handle.replace(handle) {
+            old.abort();
+        }
+    }
+
+    fn set_h3(&mut self, handle: JoinHandle<()>) {
+        if let Some(old) = self.h3_handle.replace(handle) {
+            old.abort();
+        }
+    }
+
+    fn abort(&mut self) {
+        if let Some(h) = self.http_handle.take() {
             h.abort();
         }
-        if let Some(h) = &self.h3_handle {
+        if let Some(h) = self.h3_handle.take() {
             h.abort();
         }
     }
 }
 
+fn lock_main(main: &Mutex<MainListener>) -> Option<MutexGuard<'_, MainListener>> {
+    match main.lock() {
+        Ok(guard) => Some(guard),
+        Err(err) => {
+            error!("Main listener lock poisoned {err}");
+            None
+        }
+    }
+}
+
+/// Binds TCP and UDP on `addr`, then swaps them in for the running main
+/// listeners. Returns the address bound, which differs from `addr` when its
+/// port is `0`.
+async fn bind_main(
+    cxt: ProxyContext,
+    main: &Mutex<MainListener>,
+    addr: SocketAddr,
+) -> Result<SocketAddr, HttpError> {
+    let tcp_listener = TcpListener::bind(addr).await?;
+    let addr = tcp_listener.local_addr()?;
+    let udp_socket = UdpSocket::bind(addr)?;
+
+    let http_handle = start_tcp(cxt.clone(), tcp_listener)
+        .await
+        .map_err(|_| HttpError::Alpn)?; // TODO: Wrong error
+    let h3_handle = match start_h3(cxt, udp_socket).await {
+        Ok(h3_handle) => h3_handle,
+        Err(_) => {
+            http_handle.abort();
+            return Err(HttpError::Alpn); // TODO: Wrong error
+        }
+    };
+    match lock_main(main) {
+        Some(mut main) => {
+            main.set_http(addr, http_handle);
+            main.set_h3(h3_handle);
+        }
+        None => {
+            http_handle.abort();
+            h3_handle.abort();
+        }
+    }
+    Ok(addr)
+}
+
+async fn apply_settings(
+    cxt: &ProxyContext,
+    hooks: &Hooks,
+    main: &Mutex<MainListener>,
+    settings: ProxySettings,
+) {
+    cxt.upstream.set_rules(settings.upstream);
+    cxt.dns.set(settings.dns_overrides, settings.dns_resolver);
+    cxt.host_filter.set_rules(settings.host_filter);
+    cxt.acl.set_rules(settings.acl);
+    cxt.auth.set_credentials(settings.auth);
+    cxt.limits.set_limits(settings.limits);
+    cxt.flow_store.set_retention(settings.retention).await;
+    cxt.flow_store.set_capture_profile(settings.capture_profile);
+    cxt.retries.set_policy(settings.retry);
+    cxt.versions.set_rules(settings.versions);
+    cxt.versions.set_alt_svc_upgrade(settings.alt_svc_upgrade);
+    cxt.breakpoints.set_rules(settings.breakpoints);
+    cxt.rewrites.set_rules(settings.rewrites);
+    hooks.set_rules(settings.hooks);
+    cxt.cache.set_mode(settings.cache_mode);
+    for script in &settings.scripts {
+        cxt.script_engine
+            .set_enabled(&script.name, script.enabled)
+            .await;
+        cxt.script_engine
+            .set_decode_bodies(&script.name, script.decode_bodies)
+            .await;
+    }
+
+    let Some(current) = lock_main(main).and_then(|m| m.addr) else {
+        return;
+    };
+    let port = match settings.port {
+        0 => current.port(),
+        port => port,
+    };
+    let addr = SocketAddr::new(settings.bind.unwrap_or(current.ip()), port);
+    if addr == current {
+        return;
+    }
+    match bind_main(cxt.clone(), main, addr).await {
+        Ok(addr) => debug!("Proxy moved to {addr}"),
+        Err(err) => error!("Failed to move proxy to {addr} {err}"),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FlowContext {
     pub proxy_cxt: ProxyContext,
     pub client_addr: SocketAddr,
     pub target_uri: RUri,
     pub certs: FlowCerts,
+    /// Requests go to `target_uri` whatever the client asked for, set on
+    /// reverse proxy listeners.
+    pub reverse: bool,
+    /// When the client connected, shared by every flow over the connection.
+    pub connected: OffsetDateTime,
+    /// When the TLS handshake with the client finished, if it used TLS.
+    pub tls_established: Option<OffsetDateTime>,
 }
 
 impl FlowContext {
@@ -153,8 +728,28 @@ impl FlowContext {
             client_addr,
             target_uri,
             certs: FlowCerts::default(),
+            reverse: false,
+            connected: OffsetDateTime::now_utc(),
+            tls_established: None,
         }
     }
+
+    /// Runs the scripts' `hook` on the connection of this flow, false when
+    /// one rejected it.
+    pub(crate) async fn scripts_allow(&self, hook: ConnectionHook) -> bool {
+        let mut conn = ConnectionInfo::new(self.client_addr)
+            .with_target(self.target_uri.host(), self.target_uri.port());
+        if let Some(tls) = &self.certs.client_tls {
+            conn = conn.with_tls(FlowTls::from_client(
+                tls,
+                self.certs.client_verification.as_ref(),
+            ));
+        }
+        self.proxy_cxt
+            .script_engine
+            .on_connection(hook, &conn)
+            .await
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -163,48 +758,162 @@ pub struct ProxyContext {
     pub script_engine: ScriptEngine,
     pub flow_store: FlowStore,
     pub tls_config: TlsConfig,
+    pub upstream: UpstreamConfig,
+    pub dns: DnsConfig,
+    /// Upstream connections shared by every flow.
+    pub pool: ConnectionPool,
+    pub host_filter: HostFilter,
+    pub acl: ClientAcl,
+    pub auth: ProxyAuth,
+    pub limits: ProxyLimits,
+    pub retries: Retries,
+    pub versions: Versions,
+    pub breakpoints: Breakpoints,
+    pub rewrites: Rewrites,
+    pub cache: ResponseCache,
+    pub ws_sessions: WsSessions,
+    /// Interception failures per host.
+    pub diagnostics: Diagnostics,
+    pub(crate) supervisor: Supervisor,
 }
 
 impl ProxyContext {
+    /// Whether the client at `addr` may connect, by the ACL and then the
+    /// scripts' `on_client_connect`.
+    pub(crate) async fn admit(&self, addr: SocketAddr) -> bool {
+        self.acl.admit(&self.flow_store, addr).await
+            && self
+                .script_engine
+                .on_connection(ConnectionHook::ClientConnect, &ConnectionInfo::new(addr))
+                .await
+    }
+
     pub fn new_flow(&self, client_addr: SocketAddr, target_uri: RUri) -> FlowContext {
         FlowContext::new(client_addr, target_uri, self.clone())
     }
     pub fn new_flow_upgrade(&self, client_addr: SocketAddr, target_uri: RUri) -> FlowContext {
         FlowContext::new(client_addr, target_uri, self.clone())
     }
+
+    /// Client for requests to `host`, through its upstream proxy if one matches.
+    pub fn client_builder(&self, host: &str) -> RClientBuilder {
+        let limits = self.limits.limits();
+        ClientContext::builder()
+            .with_roxy_ca(self.ca.clone())
+            .with_tls_config(self.tls_config.clone())
+            .with_upstream(self.upstream.resolve(host))
+            .with_dns(self.dns.clone())
+            .with_pool(self.pool.clone())
+            .with_connect_timeout(limits.connect_timeout)
+            .with_response_limits(limits.response_limits())
+    }
 }
 
-async fn start_tcp(
+pub(crate) async fn start_tcp(
     cxt: ProxyContext,
     tcp_listeneter: TcpListener,
 ) -> Result<JoinHandle<()>, HttpError> {
     let addr = tcp_listeneter.local_addr()?;
-    let handle = tokio::spawn(async move {
-        trace!("TCP listening on {addr}");
-        while let Ok((stream, addr)) = tcp_listeneter.accept().await {
-            let cxt = cxt.clone();
-            tokio::task::spawn(async move {
-                let io = TokioIo::new(stream);
-                if let Err(err) = ServerBuilder::new()
-                    .title_case_headers(true)
-                    .serve_connection(io, service_fn(|req| proxy(cxt.clone(), addr, req)))
-                    .with_upgrades()
+    let listener = Arc::new(tcp_listeneter);
+    let supervisor = cxt.supervisor.clone();
+    Ok(supervisor.spawn(format!("proxy {addr}"), move || {
+        accept_tcp(cxt.clone(), listener.clone())
+    }))
+}
+
+async fn accept_tcp(cxt: ProxyContext, listener: Arc<TcpListener>) -> io::Result<()> {
+    trace!("TCP listening on {}", listener.local_addr()?);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let cxt = cxt.clone();
+        tokio::task::spawn(async move {
+            if !cxt.admit(addr).await {
+                return;
+            }
+            let Some(slot) = cxt.limits.client_slot() else {
+                debug!("Too many client connections, refusing {addr}");
+                refuse_busy(stream).await;
+                return;
+            };
+            // Tunnels outlive the connection future, so they hold the slot too.
+            let slot = Arc::new(slot);
+            let (stream, peeked) = match PeekStream::new(stream, H2_PREFACE.len()).await {
+                Ok(peeked) => peeked,
+                Err(err) => {
+                    debug!("Failed to read from {addr} {err}");
+                    return;
+                }
+            };
+            let limits = cxt.limits.limits();
+            let served = if peeked.starts_with(H2_PREFACE) {
+                trace!("Cleartext HTTP/2 from {addr}");
+                let streams = Slots::default();
+                limits
+                    .h2_server()
+                    .serve_connection(
+                        TokioIo::new(stream),
+                        service_fn(|req| {
+                            capped(
+                                streams.clone(),
+                                limits.max_streams_per_connection,
+                                proxy(cxt.clone(), addr, slot.clone(), req),
+                            )
+                        }),
+                    )
                     .await
-                {
-                    error!("Failed to serve connection: {:?}", err);
+            } else {
+                let (stream, heads) = HeadRecorder::new(stream);
+                let served = limits
+                    .h1_server()
+                    .serve_connection(
+                        TokioIo::new(stream),
+                        service_fn(|mut req| {
+                            heads.attach(&mut req, cxt.flow_store.raw_capture());
+                            proxy(cxt.clone(), addr, slot.clone(), req)
+                        }),
+                    )
+                    .with_upgrades()
+                    .await;
+                if served.is_err() {
+                    let flow_cxt = cxt.new_flow(addr, RUri::default());
+                    record_folded(&flow_cxt, Scheme::HTTP, &heads).await;
                 }
-            });
-        }
-        error!("TCP proxy finished");
-    });
-    Ok(handle)
+                served
+            };
+            if let Err(err) = served {
+                error!("Failed to serve connection: {:?}", err);
+            }
+        });
+    }
+}
+
+/// Answers a client over the connection limit before closing on it.
+async fn refuse_busy(mut stream: TcpStream) {
+    let _ = stream.write_all(BUSY_RESPONSE).await;
+    let _ = stream.shutdown().await;
 }
 
+const BUSY_RESPONSE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\n\
+    Content-Length: 0\r\n\
+    Connection: close\r\n\r\n";
+
 async fn proxy(
     cxt: ProxyContext,
     socket_addr: SocketAddr,
-    req: Request<hyper::body::Incoming>,
+    slot: Arc<Slot>,
+    mut req: Request<hyper::body::Incoming>,
 ) -> Result<Response<BoxBody<Bytes, Infallible>>, HttpError> {
+    // The CA is public, devices fetch it before they are set up for anything
+    if cert_portal::is_portal_request(&req) {
+        debug!("CA portal request {socket_addr} {}", req.uri());
+        return Ok(cert_portal::serve(&cxt.ca, &req));
+    }
+    if !cxt.auth.verify(req.headers()) {
+        debug!("Proxy authentication failed {socket_addr}");
+        return auth_required_response(&cxt.auth).map_err(|_| HttpError::ProxyConnect);
+    }
+    req.headers_mut().remove(PROXY_AUTHORIZATION);
+
     if Method::CONNECT == req.method() {
         trace!("CONNECT request: {:?}", req.uri());
         if !validate_connect_uri(req.version(), req.uri(), req.headers()) {
@@ -214,7 +923,11 @@ async fn proxy(
 
         let uri: RUri = RUri::new(req.uri().clone());
         let flow_cxt = FlowContext::new(socket_addr, uri, cxt.clone());
-        tokio::spawn(async {
+        if !flow_cxt.scripts_allow(ConnectionHook::ServerConnect).await {
+            return forbidden_response().map_err(|_| HttpError::ProxyConnect);
+        }
+        tokio::spawn(async move {
+            let _slot = slot;
             match hyper::upgrade::on(req).await {
                 Ok(upgraded) => {
                     if let Err(e) = tunnel(flow_cxt, upgraded).await {
@@ -231,7 +944,11 @@ async fn proxy(
             .status(StatusCode::OK)
             .body(BoxBody::new(Empty::<Bytes>::new()))?)
     } else {
-        handle_http(FlowContext::new(socket_addr, req.uri().into(), cxt), req).await
+        let flow_cxt = FlowContext::new(socket_addr, req.uri().into(), cxt);
+        if !flow_cxt.scripts_allow(ConnectionHook::ServerConnect).await {
+            return forbidden_response().map_err(|_| HttpError::ProxyConnect);
+        }
+        handle_http(flow_cxt, req).await
     }
 }
 
@@ -290,28 +1007,79 @@ fn bad_connect_response() -> Result<Response<BoxBody<Bytes, Infallible>>, http::
         .body(BoxBody::new(Empty::<Bytes>::new()))
 }
 
+fn forbidden_response() -> Result<Response<BoxBody<Bytes, Infallible>>, http::Error> {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(BoxBody::new(Empty::<Bytes>::new()))
+}
+
+fn auth_required_response(
+    auth: &ProxyAuth,
+) -> Result<Response<BoxBody<Bytes, Infallible>>, http::Error> {
+    let mut builder = Response::builder().status(StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+    for challenge in auth.challenges() {
+        builder = builder.header(PROXY_AUTHENTICATE, challenge);
+    }
+    builder.body(BoxBody::new(Empty::<Bytes>::new()))
+}
+
 async fn tunnel(
-    mut flow_cxt: FlowContext,
+    flow_cxt: FlowContext,
     upgraded: Upgraded,
 ) -> Result<(), Box<dyn std::error::Error>> {
     trace!("Providing tunnel");
-    let client_stream = TokioIo::new(upgraded);
+    tunnel_stream(flow_cxt, TokioIo::new(upgraded)).await
+}
 
-    let (client_stream, peeked_bytes) = PeekStream::new(client_stream, 1024).await?;
-    if peeked_bytes.starts_with(GET_BYTES) {
-        return handle_ws(flow_cxt, client_stream).await;
+/// Intercepts a raw client stream to `flow_cxt.target_uri`, whether it arrived
+/// through an HTTP CONNECT or a SOCKS5 CONNECT.
+pub(crate) async fn tunnel_stream<S>(
+    mut flow_cxt: FlowContext,
+    client_stream: S,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    if !flow_cxt
+        .proxy_cxt
+        .host_filter
+        .should_intercept(flow_cxt.target_uri.host())
+    {
+        return handle_passthrough(flow_cxt, client_stream).await;
+    }
+    let (client_stream, peeked_bytes) =
+        PeekStream::with_timeout(client_stream, 1024, SERVER_FIRST_WAIT).await?;
+    let Some(peeked_bytes) = peeked_bytes else {
+        trace!("Client is silent, treating as an unknown protocol");
+        return handle_unknown_protocol(flow_cxt, client_stream, false).await;
+    };
+    match PeekedProtocol::detect(&peeked_bytes) {
+        PeekedProtocol::WebSocket => return handle_ws(flow_cxt, client_stream).await,
+        PeekedProtocol::Http2 => {
+            trace!("Peek looks like cleartext HTTP/2");
+            return handle_h2c(flow_cxt, client_stream).await;
+        }
+        PeekedProtocol::Http1 => {
+            trace!("Peek looks like plain HTTP");
+            return handle_http_stream(flow_cxt, client_stream).await;
+        }
+        PeekedProtocol::Unknown => {
+            trace!("Peek is not HTTP, treating as an unknown protocol");
+            return handle_unknown_protocol(flow_cxt, client_stream, false).await;
+        }
+        PeekedProtocol::Tls => {}
     }
     trace!("Peek looks like TLS");
 
-    let (leaf, key_pair) = flow_cxt
+    let leaf = flow_cxt
         .proxy_cxt
         .ca
-        .sign_leaf_uri(&flow_cxt.target_uri)
+        .leaf_for_uri(&flow_cxt.target_uri)
         .map_err(|e| io::Error::other(format!("Failed to sign leaf certificate: {e}")))?;
 
-    let pk_der = PrivateKeyDer::try_from(key_pair.serialize_der())?;
     let provider = flow_cxt.proxy_cxt.tls_config.crypto_provider();
-    let certified_key = CertifiedKey::from_der(vec![leaf.der().clone()], pk_der, provider.deref())?;
+    let certified_key =
+        CertifiedKey::from_der(vec![leaf.cert_der.clone()], leaf.key(), provider.deref())?;
 
     let RustlsServerConfig {
         resolver,
@@ -324,10 +1092,22 @@ async fn tunnel(
     server_config.alpn_protocols = alp_h1_h2();
 
     trace!("Creating TLS acceptor for client stream");
-    let client_tls = TlsAcceptor::from(Arc::new(server_config))
+    let client_tls = match TlsAcceptor::from(Arc::new(server_config))
         .accept(client_stream)
         .await
-        .map_err(|e| io::Error::other(format!("Client TLS handshake failed: {e}")))?;
+    {
+        Ok(client_tls) => client_tls,
+        Err(err) => {
+            flow_cxt.proxy_cxt.diagnostics.record(
+                flow_cxt.target_uri.host(),
+                ProblemKind::of_handshake(&err),
+                err.to_string(),
+            );
+            return Err(Box::new(io::Error::other(format!(
+                "Client TLS handshake failed: {err}"
+            ))));
+        }
+    };
 
     let client_hello = resolver
         .client_hello
@@ -340,33 +1120,48 @@ async fn tunnel(
 
     flow_cxt.certs.client_hello = client_hello;
     flow_cxt.certs.client_tls = Some(client_tls_session);
+    flow_cxt.tls_established = Some(OffsetDateTime::now_utc());
+    if !flow_cxt.scripts_allow(ConnectionHook::TlsEstablished).await {
+        return Ok(());
+    }
 
     match alpn {
         AlpnProtocol::Http2 => handle_h2(flow_cxt, client_tls).await,
-        AlpnProtocol::Http1 => {
-            trace!("Using ALPN protocol: http/1.1");
-            let (peekable, bytes) = PeekStream::new(client_tls, 1024).await?;
-            let preview = std::str::from_utf8(&bytes).unwrap_or_default();
-            if preview.contains("Upgrade: websocket") {
-                handle_wss(flow_cxt, peekable).await
-            } else {
-                handle_https(flow_cxt, peekable).await
-            }
-        }
-        AlpnProtocol::Unknown(alpn_bytes) => {
-            trace!(
-                "No ALPN protocol negotiated {alpn_bytes:?}, defaulting to http/2 which can downgrade"
-            );
-
-            handle_h2(flow_cxt, client_tls).await
-        }
         AlpnProtocol::Http3 => {
             error!("H3 negotiated over TCP, reverting to http/1.1");
             Err(Box::new(HttpError::Alpn)) // TODO: make secific
         }
-        AlpnProtocol::None => {
-            error!("No alpn negotiated");
-            handle_https(flow_cxt, client_tls).await
+        AlpnProtocol::Http1 | AlpnProtocol::Unknown(_) | AlpnProtocol::None => {
+            trace!("ALPN {alpn:?} negotiated, peeking at what the client sends");
+            let diagnostics = &flow_cxt.proxy_cxt.diagnostics;
+            let host = flow_cxt.target_uri.host();
+            let (peekable, bytes) =
+                match PeekStream::with_timeout(client_tls, 1024, SERVER_FIRST_WAIT).await {
+                    Ok(peeked) => peeked,
+                    Err(err) => {
+                        diagnostics.record(
+                            host,
+                            ProblemKind::ClosedAfterHandshake,
+                            err.to_string(),
+                        );
+                        return Err(Box::new(err));
+                    }
+                };
+            if bytes.as_ref().is_some_and(|bytes| bytes.is_empty()) {
+                diagnostics.record(
+                    host,
+                    ProblemKind::ClosedAfterHandshake,
+                    "Closed the connection without sending a request",
+                );
+            }
+            match bytes.as_deref().map(PeekedProtocol::detect) {
+                Some(PeekedProtocol::WebSocket) => handle_wss(flow_cxt, peekable).await,
+                Some(PeekedProtocol::Http1) => handle_https(flow_cxt, peekable).await,
+                Some(PeekedProtocol::Http2) => handle_h2(flow_cxt, peekable).await,
+                Some(PeekedProtocol::Tls | PeekedProtocol::Unknown) | None => {
+                    handle_unknown_protocol(flow_cxt, peekable, true).await
+                }
+            }
         }
     }
 }
@@ -0,0 +1,104 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use http::Method;
+use tracing::error;
+
+use crate::flow::error::FlowError;
+
+/// Sends idempotent requests again when they fail before the server answered,
+/// e.g. on a refused connection or a timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first, `0` never retries.
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for every one after it.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            backoff: Duration::from_millis(250),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry `attempt`, counting from `0`, or `None`
+    /// when a `method` request failing with `error` is not sent again.
+    pub fn delay(&self, method: &Method, error: &FlowError, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_retries || !method.is_idempotent() || !error.kind.is_transient() {
+            return None;
+        }
+        Some(self.backoff.saturating_mul(2_u32.saturating_pow(attempt)))
+    }
+}
+
+/// Shared handle to the active [`RetryPolicy`], updates apply to the next
+/// request sent.
+#[derive(Debug, Clone, Default)]
+pub struct Retries {
+    policy: Arc<RwLock<RetryPolicy>>,
+}
+
+impl Retries {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy: Arc::new(RwLock::new(policy)),
+        }
+    }
+
+    pub fn policy(&self) -> RetryPolicy {
+        match self.policy.read() {
+            Ok(policy) => *policy,
+            Err(err) => {
+                error!("Retry policy lock poisoned {err}");
+                RetryPolicy::default()
+            }
+        }
+    }
+
+    pub fn set_policy(&self, policy: RetryPolicy) {
+        match self.policy.write() {
+            Ok(mut guard) => *guard = policy,
+            Err(err) => error!("Retry policy lock poisoned {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flow::error::FlowErrorKind;
+
+    use super::*;
+
+    #[test]
+    fn retries_idempotent_transient_failures() {
+        let policy = RetryPolicy {
+            max_retries: 2,
+            backoff: Duration::from_millis(100),
+        };
+        let refused = FlowError::new(FlowErrorKind::Connect, "refused");
+        assert_eq!(
+            policy.delay(&Method::GET, &refused, 0),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            policy.delay(&Method::PUT, &refused, 1),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(policy.delay(&Method::GET, &refused, 2), None);
+        assert_eq!(policy.delay(&Method::POST, &refused, 0), None);
+
+        let invalid = FlowError::new(FlowErrorKind::Protocol, "invalid header");
+        assert_eq!(policy.delay(&Method::GET, &invalid, 0), None);
+        assert_eq!(
+            RetryPolicy::default().delay(&Method::GET, &refused, 0),
+            None
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use std::sync::{Arc, RwLock};
+
+use http::{HeaderValue, Uri, header::HOST};
+use regex::Regex;
+use roxy_shared::uri::{RUri, Scheme};
+use tracing::{debug, error, warn};
+
+use crate::flow::InterceptedRequest;
+
+/// Redirects requests whose host and path match to another scheme, host, port
+/// or path.
+///
+/// Matchers are regexes, unset ones match anything. Replacements may refer to
+/// capture groups of their matcher, e.g. path `^/v1/(.*)` with `/v2/$1`.
+#[derive(Debug, Clone)]
+pub struct RewriteRule {
+    host: Option<Regex>,
+    path: Option<Regex>,
+    to_scheme: Option<Scheme>,
+    to_host: Option<String>,
+    to_port: Option<u16>,
+    to_path: Option<String>,
+}
+
+impl RewriteRule {
+    pub fn new(host: Option<&str>, path: Option<&str>) -> Result<Self, regex::Error> {
+        Ok(Self {
+            host: host.map(Regex::new).transpose()?,
+            path: path.map(Regex::new).transpose()?,
+            to_scheme: None,
+            to_host: None,
+            to_port: None,
+            to_path: None,
+        })
+    }
+
+    pub fn with_scheme(mut self, scheme: Scheme) -> Self {
+        self.to_scheme = Some(scheme);
+        self
+    }
+
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.to_host = Some(host.into());
+        self
+    }
+
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.to_port = Some(port);
+        self
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.to_path = Some(path.into());
+        self
+    }
+
+    pub fn matches(&self, uri: &RUri) -> bool {
+        self.host.as_ref().is_none_or(|h| h.is_match(uri.host()))
+            && self.path.as_ref().is_none_or(|p| p.is_match(uri.path()))
+    }
+
+    /// The rewritten uri, `None` when the rule does not match.
+    pub fn apply(&self, uri: &RUri) -> Option<Result<RUri, http::Error>> {
+        if !self.matches(uri) {
+            return None;
+        }
+        let scheme = self.to_scheme.clone().unwrap_or(uri.scheme());
+        let host = replace(self.host.as_ref(), uri.host(), self.to_host.as_deref());
+        let path = replace(self.path.as_ref(), uri.path(), self.to_path.as_deref());
+        let port = self.to_port.or(uri.port_or_none());
+
+        let authority = match port {
+            Some(port) => format!("{host}:{port}"),
+            None => host,
+        };
+        let path_and_query = match uri.inner.query() {
+            Some(query) => format!("{path}?{query}"),
+            None => path,
+        };
+        Some(
+            Uri::builder()
+                .scheme(scheme.to_string().as_str())
+                .authority(authority.as_str())
+                .path_and_query(path_and_query.as_str())
+                .build()
+                .map(RUri::new),
+        )
+    }
+}
+
+fn replace(matcher: Option<&Regex>, value: &str, to: Option<&str>) -> String {
+    match (matcher, to) {
+        (Some(matcher), Some(to)) => matcher.replace(value, to).to_string(),
+        (None, Some(to)) => to.to_string(),
+        (_, None) => value.to_string(),
+    }
+}
+
+/// Shared handle to the active rewrite rules, applied to requests before
+/// scripts see them. The first matching rule wins.
+#[derive(Debug, Clone, Default)]
+pub struct Rewrites {
+    rules: Arc<RwLock<Vec<RewriteRule>>>,
+}
+
+impl Rewrites {
+    pub fn new(rules: Vec<RewriteRule>) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+        }
+    }
+
+    pub fn set_rules(&self, rules: Vec<RewriteRule>) {
+        match self.rules.write() {
+            Ok(mut guard) => *guard = rules,
+            Err(err) => error!("Rewrite lock poisoned {err}"),
+        }
+    }
+
+    /// Rewrites the uri of `request` and its host header, returns `true` if
+    /// a rule matched.
+    pub fn apply(&self, request: &mut InterceptedRequest) -> bool {
+        let rewritten = match self.rules.read() {
+            Ok(rules) => rules.iter().find_map(|r| r.apply(&request.uri)),
+            Err(_) => None,
+        };
+        let uri = match rewritten {
+            Some(Ok(uri)) => uri,
+            Some(Err(err)) => {
+                warn!("Rewrite of {} failed {err}", request.uri);
+                return false;
+            }
+            None => return false,
+        };
+        debug!("Rewrite {} to {uri}", request.uri);
+        if request.headers.contains_key(HOST)
+            && let Some(authority) = uri.inner.authority()
+            && let Ok(host) = HeaderValue::from_str(authority.as_str())
+        {
+            request.headers.insert(HOST, host);
+        }
+        request.uri = uri;
+        true
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(s: &str) -> RUri {
+        RUri::new(s.parse().unwrap())
+    }
+
+    #[test]
+    fn rewrites_matching_parts() {
+        let rule = RewriteRule::new(Some(r"^api\.example\.com$"), Some("^/v1/(.*)"))
+            .unwrap()
+            .with_scheme(Scheme::Http)
+            .with_host("localhost")
+            .with_port(8080)
+            .with_path("/v2/$1");
+        let rewritten = rule
+            .apply(&uri("https://api.example.com/v1/users?id=1"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(rewritten.to_string(), "http://localhost:8080/v2/users?id=1");
+        assert!(rule.apply(&uri("https://example.com/v1/users")).is_none());
+        assert!(
+            rule.apply(&uri("https://api.example.com/v2/users"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn keeps_unset_parts() {
+        let rule = RewriteRule::new(Some("example"), None)
+            .unwrap()
+            .with_host("test");
+        let rewritten = rule
+            .apply(&uri("https://www.example.com:8443/a"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(rewritten.to_string(), "https://www.test.com:8443/a");
+    }
+
+    #[test]
+    fn updates_host_header() {
+        let rewrites = Rewrites::new(vec![
+            RewriteRule::new(Some("^example.com$"), None)
+                .unwrap()
+                .with_host("localhost")
+                .with_port(3000),
+        ]);
+        let mut request = InterceptedRequest {
+            uri: uri("http://example.com/"),
+            ..Default::default()
+        };
+        request
+            .headers
+            .insert(HOST, HeaderValue::from_static("example.com"));
+        assert!(rewrites.apply(&mut request));
+        assert_eq!(request.uri.to_string(), "http://localhost:3000/");
+        assert_eq!(request.headers.get(HOST).unwrap(), "localhost:3000");
+
+        let mut other = InterceptedRequest {
+            uri: uri("http://other.com/"),
+            ..Default::default()
+        };
+        assert!(!rewrites.apply(&mut other));
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(RewriteRule::new(Some("("), None).is_err());
+    }
+}
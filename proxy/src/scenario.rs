@@ -0,0 +1,394 @@
+use std::path::Path;
+
+use bytes::Bytes;
+use cow_utils::CowUtils;
+use futures_util::future::join_all;
+use http::{HeaderName, HeaderValue, StatusCode};
+use roxy_shared::{cookie::CookieJar, uri::RUri};
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::{
+    flow::{
+        Flow, FlowConnection, InterceptedRequest, InterceptedResponse,
+        diff::FlowDiff,
+        error::{FlowError, FlowErrorKind},
+        id::FlowId,
+        session::{SessionError, read_session},
+    },
+    proxy::ProxyContext,
+};
+
+/// Where a captured value is read from in a response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureSource {
+    Header(HeaderName),
+    /// A JSON pointer into the body, e.g. `/auth/token`.
+    Json(String),
+}
+
+/// A value the server hands out that later requests send back, such as an
+/// auth token or a created id. When the recorded and replayed responses hold
+/// different values, the recorded one is replaced by the new one in every
+/// request after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capture {
+    pub name: String,
+    pub source: CaptureSource,
+}
+
+impl Capture {
+    pub fn header(name: impl Into<String>, header: HeaderName) -> Self {
+        Self {
+            name: name.into(),
+            source: CaptureSource::Header(header),
+        }
+    }
+
+    pub fn json(name: impl Into<String>, pointer: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            source: CaptureSource::Json(pointer.into()),
+        }
+    }
+
+    fn value(&self, response: &InterceptedResponse) -> Option<String> {
+        match &self.source {
+            CaptureSource::Header(name) => response
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            CaptureSource::Json(pointer) => {
+                let body = response.read_body().ok()?;
+                match serde_json::from_slice::<Value>(&body)
+                    .ok()?
+                    .pointer(pointer)?
+                {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Null => None,
+                    other => Some(other.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// One recorded exchange of a scenario.
+#[derive(Debug, Clone)]
+pub struct ScenarioStep {
+    /// The flow the step was recorded as.
+    pub id: FlowId,
+    pub client_connection: FlowConnection,
+    pub request: InterceptedRequest,
+    /// What the server answered when recorded, `None` if it did not.
+    pub response: Option<InterceptedResponse>,
+}
+
+impl ScenarioStep {
+    /// `None` for flows without a request, such as passthrough tunnels.
+    pub fn from_flow(flow: &Flow) -> Option<Self> {
+        Some(Self {
+            id: flow.id,
+            client_connection: flow.client_connection,
+            request: flow.request.clone()?,
+            response: flow.response.clone(),
+        })
+    }
+}
+
+/// Recorded flows replayed in order and checked against what they got the
+/// first time, see [`crate::proxy::ProxyManager::run_scenario`].
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    steps: Vec<ScenarioStep>,
+    concurrency: usize,
+    /// Recorded text and what to send in its place.
+    variables: Vec<(String, String)>,
+    captures: Vec<Capture>,
+    cookies: Option<CookieJar>,
+}
+
+impl Scenario {
+    pub fn new(steps: Vec<ScenarioStep>) -> Self {
+        Self {
+            steps,
+            concurrency: 1,
+            variables: vec![],
+            captures: vec![],
+            cookies: None,
+        }
+    }
+
+    /// The flows with a request, in the order given.
+    pub fn from_flows<'a>(flows: impl IntoIterator<Item = &'a Flow>) -> Self {
+        Self::new(
+            flows
+                .into_iter()
+                .filter_map(ScenarioStep::from_flow)
+                .collect(),
+        )
+    }
+
+    /// The flows of a saved session, in the order they were recorded.
+    pub fn load_session(path: &Path) -> Result<Self, SessionError> {
+        Ok(Self::from_flows(&read_session(path)?))
+    }
+
+    /// How many steps are sent at once, `1` sends one after the other.
+    /// Values captured by a step apply to the batches after its own.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Replaces `recorded` with `value` in the url, headers and body of every
+    /// request, e.g. a fresh auth token.
+    pub fn with_variable(mut self, recorded: impl Into<String>, value: impl Into<String>) -> Self {
+        self.variables.push((recorded.into(), value.into()));
+        self
+    }
+
+    pub fn with_capture(mut self, capture: Capture) -> Self {
+        self.captures.push(capture);
+        self
+    }
+
+    /// Keeps the cookies replayed responses set and sends them on the steps
+    /// after, in place of the recorded ones of the same name.
+    pub fn with_cookie_jar(mut self, cookies: CookieJar) -> Self {
+        self.cookies = Some(cookies);
+        self
+    }
+
+    pub fn steps(&self) -> &[ScenarioStep] {
+        &self.steps
+    }
+}
+
+/// How one step went.
+#[derive(Debug, Clone)]
+pub struct StepReport {
+    /// The recorded flow.
+    pub id: FlowId,
+    /// The flow of the replay, `None` when the request could not be built.
+    pub replay_id: Option<FlowId>,
+    pub expected_status: Option<StatusCode>,
+    pub status: Option<StatusCode>,
+    pub error: Option<FlowError>,
+    /// From the recorded flow to the replayed one.
+    pub diff: FlowDiff,
+}
+
+impl StepReport {
+    /// Whether the replay got the same status as the recording.
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.status == self.expected_status
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScenarioReport {
+    pub steps: Vec<StepReport>,
+}
+
+impl ScenarioReport {
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(StepReport::passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &StepReport> {
+        self.steps.iter().filter(|s| !s.passed())
+    }
+}
+
+/// Runs `scenario` through the same rewrite rules, upstreams and TLS setup as
+/// intercepted traffic, each replay is recorded as a new flow.
+pub(crate) async fn run_scenario(cxt: &ProxyContext, scenario: Scenario) -> ScenarioReport {
+    let mut substitutions = scenario.variables.clone();
+    let mut report = ScenarioReport::default();
+    for batch in scenario.steps.chunks(scenario.concurrency) {
+        let runs = batch
+            .iter()
+            .map(|step| run_step(cxt, step, &substitutions, scenario.cookies.as_ref()));
+        let results = join_all(runs).await;
+        for (step, (step_report, response)) in batch.iter().zip(results) {
+            if let (Some(recorded), Some(replayed)) = (&step.response, &response) {
+                for capture in &scenario.captures {
+                    if let (Some(old), Some(new)) =
+                        (capture.value(recorded), capture.value(replayed))
+                        && old != new
+                    {
+                        debug!("Scenario captured {} from flow {}", capture.name, step.id);
+                        substitutions.push((old, new));
+                    }
+                }
+            }
+            report.steps.push(step_report);
+        }
+    }
+    report
+}
+
+async fn run_step(
+    cxt: &ProxyContext,
+    step: &ScenarioStep,
+    substitutions: &[(String, String)],
+    cookies: Option<&CookieJar>,
+) -> (StepReport, Option<InterceptedResponse>) {
+    let mut report = StepReport {
+        id: step.id,
+        replay_id: None,
+        expected_status: step.response.as_ref().map(|r| r.status),
+        status: None,
+        error: None,
+        diff: FlowDiff::default(),
+    };
+    let mut request = step.request.clone();
+    if let Err(err) = request.unspill().await {
+        report.error = Some(FlowError::new(
+            FlowErrorKind::Other,
+            format!("Failed to read body {err}"),
+        ));
+        return (report, None);
+    }
+    substitute(&mut request, substitutions);
+    cxt.rewrites.apply(&mut request);
+
+    let mut client = cxt.client_builder(request.uri.host());
+    if let Some(cookies) = cookies {
+        client = client.with_cookie_jar(cookies.clone());
+    }
+    let sent = request.clone();
+    let (id, response) = match cxt
+        .flow_store
+        .send_replay(step.client_connection, request, client)
+        .await
+    {
+        Ok(replayed) => replayed,
+        Err(err) => {
+            report.error = Some(FlowError::new(FlowErrorKind::Other, err.to_string()));
+            return (report, None);
+        }
+    };
+    report.replay_id = Some(id);
+    let response = match response {
+        Ok(response) => Some(response),
+        Err(err) => {
+            report.error = Some(FlowError::from(&err));
+            None
+        }
+    };
+    report.status = response.as_ref().map(|r| r.status);
+
+    let mut recorded = Flow::new(step.id, step.client_connection, Some(step.request.clone()));
+    recorded.response = step.response.clone();
+    let mut replayed = Flow::new(id, step.client_connection, Some(sent));
+    replayed.response = response.clone();
+    report.diff = recorded.diff(&replayed);
+    (report, response)
+}
+
+/// Swaps every recorded value for its replacement in the url, header values
+/// and body of `request`.
+fn substitute(request: &mut InterceptedRequest, substitutions: &[(String, String)]) {
+    for (from, to) in substitutions.iter().filter(|(from, _)| !from.is_empty()) {
+        let uri = request.uri.to_string();
+        if uri.contains(from.as_str()) {
+            match uri.cow_replace(from.as_str(), to).parse::<RUri>() {
+                Ok(uri) => request.uri = uri,
+                Err(err) => warn!("Substituted url is invalid {err}"),
+            }
+        }
+        for value in request.headers.values_mut() {
+            if let Ok(text) = value.to_str()
+                && text.contains(from.as_str())
+                && let Ok(new) = HeaderValue::from_str(&text.cow_replace(from.as_str(), to))
+            {
+                *value = new;
+            }
+        }
+        request.body = replace_bytes(&request.body, from.as_bytes(), to.as_bytes());
+    }
+}
+
+fn replace_bytes(haystack: &Bytes, from: &[u8], to: &[u8]) -> Bytes {
+    if from.is_empty() || !haystack.windows(from.len()).any(|w| w == from) {
+        return haystack.clone();
+    }
+    let mut out = Vec::with_capacity(haystack.len());
+    let mut rest = &haystack[..];
+    while let Some(at) = rest.windows(from.len()).position(|w| w == from) {
+        out.extend_from_slice(&rest[..at]);
+        out.extend_from_slice(to);
+        rest = &rest[at + from.len()..];
+    }
+    out.extend_from_slice(rest);
+    Bytes::from(out)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::header::AUTHORIZATION;
+
+    use super::*;
+
+    #[test]
+    fn substitutes_recorded_values() {
+        let mut request = InterceptedRequest {
+            uri: "https://api.example.com/orders/old-id?session=abc"
+                .parse()
+                .unwrap(),
+            body: Bytes::from_static(b"{\"token\":\"abc\",\"again\":\"abc\"}"),
+            ..Default::default()
+        };
+        request
+            .headers
+            .insert(AUTHORIZATION, HeaderValue::from_static("Bearer abc"));
+
+        substitute(
+            &mut request,
+            &[
+                ("abc".to_string(), "xyz".to_string()),
+                ("old-id".to_string(), "new-id".to_string()),
+            ],
+        );
+        assert_eq!(
+            request.uri.to_string(),
+            "https://api.example.com/orders/new-id?session=xyz"
+        );
+        assert_eq!(request.headers[AUTHORIZATION], "Bearer xyz");
+        assert_eq!(
+            request.body,
+            Bytes::from_static(b"{\"token\":\"xyz\",\"again\":\"xyz\"}")
+        );
+    }
+
+    #[test]
+    fn captures_values() {
+        let mut response = InterceptedResponse {
+            body: Bytes::from_static(b"{\"auth\":{\"token\":\"t1\",\"ttl\":60}}"),
+            ..Default::default()
+        };
+        response
+            .headers
+            .insert("x-request-id", HeaderValue::from_static("r1"));
+
+        let header = Capture::header("request id", HeaderName::from_static("x-request-id"));
+        assert_eq!(header.value(&response).as_deref(), Some("r1"));
+        assert_eq!(
+            Capture::json("token", "/auth/token")
+                .value(&response)
+                .as_deref(),
+            Some("t1")
+        );
+        assert_eq!(
+            Capture::json("ttl", "/auth/ttl")
+                .value(&response)
+                .as_deref(),
+            Some("60")
+        );
+        assert_eq!(Capture::json("missing", "/nope").value(&response), None);
+    }
+}
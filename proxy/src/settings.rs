@@ -0,0 +1,59 @@
+use std::net::IpAddr;
+
+use roxy_shared::{
+    dns::{DnsOverride, DnsResolver},
+    upstream::UpstreamRule,
+};
+
+use crate::{
+    acl::ClientAclRules,
+    auth::ProxyCredential,
+    breakpoint::BreakpointRule,
+    cache::CacheMode,
+    filter::HostFilterRules,
+    flow::{filter::CaptureProfile, retention::Retention},
+    hook::HookRule,
+    limits::Limits,
+    retry::RetryPolicy,
+    rewrite::RewriteRule,
+    version::VersionRule,
+};
+
+/// The parts of the proxy that can change while it runs, sent to
+/// `ProxyManager::watch_settings` whenever the configuration does.
+#[derive(Debug, Clone, Default)]
+pub struct ProxySettings {
+    /// Main proxy port, `0` keeps the port currently bound.
+    pub port: u16,
+    /// Address the main port is bound on, `None` keeps the current one.
+    pub bind: Option<IpAddr>,
+    pub upstream: Vec<UpstreamRule>,
+    pub dns_overrides: Vec<DnsOverride>,
+    pub dns_resolver: DnsResolver,
+    pub host_filter: HostFilterRules,
+    pub acl: ClientAclRules,
+    pub auth: Vec<ProxyCredential>,
+    pub limits: Limits,
+    pub retention: Retention,
+    pub capture_profile: Option<CaptureProfile>,
+    pub retry: RetryPolicy,
+    pub versions: Vec<VersionRule>,
+    /// Sends hosts without a version rule over HTTP/3 once they advertise it.
+    pub alt_svc_upgrade: bool,
+    pub breakpoints: Vec<BreakpointRule>,
+    pub rewrites: Vec<RewriteRule>,
+    pub hooks: Vec<HookRule>,
+    pub cache_mode: CacheMode,
+    /// Whether each loaded script runs and how it sees bodies, by name.
+    /// Scripts not listed are left as they are.
+    pub scripts: Vec<ScriptSettings>,
+}
+
+/// The parts of a loaded script's [`crate::interceptor::ScriptInfo`] that
+/// follow the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptSettings {
+    pub name: String,
+    pub enabled: bool,
+    pub decode_bodies: bool,
+}
@@ -0,0 +1,67 @@
+use std::{io, sync::Arc};
+
+use roxy_shared::{
+    http::HttpError,
+    socks::{SocksVerify, socks5_accept},
+    uri::RUri,
+};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tracing::{debug, error, trace};
+
+use crate::{
+    interceptor::connection::ConnectionHook,
+    proxy::{FlowContext, ProxyContext, tunnel_stream},
+};
+
+pub(crate) async fn start_socks(
+    cxt: ProxyContext,
+    listener: TcpListener,
+) -> Result<JoinHandle<()>, HttpError> {
+    let addr = listener.local_addr()?;
+    let listener = Arc::new(listener);
+    let supervisor = cxt.supervisor.clone();
+    Ok(supervisor.spawn(format!("socks {addr}"), move || {
+        accept_socks(cxt.clone(), listener.clone())
+    }))
+}
+
+async fn accept_socks(cxt: ProxyContext, listener: Arc<TcpListener>) -> io::Result<()> {
+    trace!("SOCKS5 listening on {}", listener.local_addr()?);
+    loop {
+        let (mut stream, client_addr) = listener.accept().await?;
+        let cxt = cxt.clone();
+        tokio::task::spawn(async move {
+            if !cxt.admit(client_addr).await {
+                return;
+            }
+            let Some(_slot) = cxt.limits.client_slot() else {
+                debug!("Too many client connections, refusing {client_addr}");
+                return;
+            };
+            let verify = |user: &str, password: &str| cxt.auth.verify_user(user, password);
+            let verify: SocksVerify<'_> = &verify;
+            let required = cxt.auth.is_required().then_some(verify);
+            let (host, port) = match socks5_accept(&mut stream, required).await {
+                Ok(target) => target,
+                Err(err) => {
+                    error!("SOCKS5 handshake failed {client_addr} {err}");
+                    return;
+                }
+            };
+            let target_uri: RUri = match format!("{host}:{port}").parse() {
+                Ok(uri) => uri,
+                Err(err) => {
+                    error!("SOCKS5 invalid target {host}:{port} {err}");
+                    return;
+                }
+            };
+            let flow_cxt = FlowContext::new(client_addr, target_uri, cxt);
+            if !flow_cxt.scripts_allow(ConnectionHook::ServerConnect).await {
+                return;
+            }
+            if let Err(err) = tunnel_stream(flow_cxt, stream).await {
+                trace!("SOCKS5 tunnel error: {err}");
+            }
+        });
+    }
+}
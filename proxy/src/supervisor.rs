@@ -0,0 +1,239 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    panic::AssertUnwindSafe,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use futures_util::FutureExt;
+use serde::Serialize;
+use tokio::{
+    task::JoinHandle,
+    time::{Instant, sleep},
+};
+use tracing::{debug, error};
+
+use crate::{
+    interceptor::{FlowNotifyLevel, ScriptEngine},
+    metrics::Metrics,
+};
+
+/// Wait before the first restart, doubled for each failure in a row.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+/// Longest wait between restarts, a task that ran this long before failing
+/// starts over from [`INITIAL_BACKOFF`].
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// How one accept loop is doing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TaskHealth {
+    /// What the loop accepts on, e.g. `proxy 127.0.0.1:8080`.
+    pub name: String,
+    /// `false` while waiting to be restarted or after stopping for good.
+    pub running: bool,
+    /// Times it was started again after failing or panicking.
+    pub restarts: u64,
+    pub last_error: Option<String>,
+}
+
+/// The accept loops of a proxy at one point in time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ProxyHealth {
+    pub tasks: Vec<TaskHealth>,
+}
+
+impl ProxyHealth {
+    /// Whether every accept loop is running.
+    pub fn healthy(&self) -> bool {
+        self.tasks.iter().all(|task| task.running)
+    }
+}
+
+/// Restarts the accept loops it spawns and tracks how they are doing,
+/// shared by clones. Failures are counted in `metrics` and sent to the
+/// notification channel of `script_engine`.
+#[derive(Debug, Clone)]
+pub(crate) struct Supervisor {
+    script_engine: ScriptEngine,
+    metrics: Metrics,
+    next_id: Arc<AtomicU64>,
+    tasks: Arc<Mutex<BTreeMap<u64, TaskHealth>>>,
+}
+
+impl Supervisor {
+    pub(crate) fn new(script_engine: ScriptEngine, metrics: Metrics) -> Self {
+        Self {
+            script_engine,
+            metrics,
+            next_id: Arc::default(),
+            tasks: Arc::default(),
+        }
+    }
+
+    /// Runs the accept loop `start` makes until it returns `Ok`, starting a
+    /// new one with a growing backoff each time it fails or panics.
+    pub(crate) fn spawn<F, Fut>(&self, name: String, start: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = io::Result<()>> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let entry = supervisor.register(name.clone());
+            let mut backoff = INITIAL_BACKOFF;
+            loop {
+                let started = Instant::now();
+                let (panicked, err) = match AssertUnwindSafe(start()).catch_unwind().await {
+                    Ok(Ok(())) => {
+                        debug!("{name} stopped");
+                        let msg = format!("{name} stopped accepting");
+                        supervisor.script_engine.notify(FlowNotifyLevel::Warn, msg);
+                        supervisor.update(entry.id, |task| task.running = false);
+                        return;
+                    }
+                    Ok(Err(err)) => (false, err.to_string()),
+                    Err(panic) => (true, panic_message(panic.as_ref())),
+                };
+                if started.elapsed() >= MAX_BACKOFF {
+                    backoff = INITIAL_BACKOFF;
+                }
+                let msg = format!("{name} failed, restarting in {backoff:?}: {err}");
+                error!("{msg}");
+                supervisor.script_engine.notify(FlowNotifyLevel::Error, msg);
+                supervisor.metrics.record_restart(panicked);
+                supervisor.update(entry.id, |task| {
+                    task.running = false;
+                    task.last_error = Some(err);
+                });
+
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                supervisor.update(entry.id, |task| {
+                    task.running = true;
+                    task.restarts += 1;
+                });
+            }
+        })
+    }
+
+    pub(crate) fn health(&self) -> ProxyHealth {
+        match self.tasks.lock() {
+            Ok(tasks) => ProxyHealth {
+                tasks: tasks.values().cloned().collect(),
+            },
+            Err(err) => {
+                error!("Supervisor lock poisoned {err}");
+                ProxyHealth::default()
+            }
+        }
+    }
+
+    fn update(&self, id: u64, update: impl FnOnce(&mut TaskHealth)) {
+        match self.tasks.lock() {
+            Ok(mut tasks) => {
+                if let Some(task) = tasks.get_mut(&id) {
+                    update(task);
+                }
+            }
+            Err(err) => error!("Supervisor lock poisoned {err}"),
+        }
+    }
+
+    fn register(&self, name: String) -> TaskEntry {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let task = TaskHealth {
+            name,
+            running: true,
+            restarts: 0,
+            last_error: None,
+        };
+        match self.tasks.lock() {
+            Ok(mut tasks) => {
+                tasks.insert(id, task);
+            }
+            Err(err) => error!("Supervisor lock poisoned {err}"),
+        }
+        TaskEntry {
+            supervisor: self.clone(),
+            id,
+        }
+    }
+}
+
+/// Removes a task from the health report once its supervisor is dropped,
+/// aborted ones included.
+struct TaskEntry {
+    supervisor: Supervisor,
+    id: u64,
+}
+
+impl Drop for TaskEntry {
+    fn drop(&mut self) {
+        match self.supervisor.tasks.lock() {
+            Ok(mut tasks) => {
+                tasks.remove(&self.id);
+            }
+            Err(err) => error!("Supervisor lock poisoned {err}"),
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        format!("panicked: {msg}")
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        format!("panicked: {msg}")
+    } else {
+        "panicked".to_string()
+    }
+}
+
+#[allow(clippy::unwrap_used, clippy::panic)]
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn restarts_failed_tasks() {
+        let metrics = Metrics::new();
+        let supervisor = Supervisor::new(ScriptEngine::new(), metrics.clone());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counted = runs.clone();
+        let handle = supervisor.spawn("test".to_string(), move || {
+            let run = counted.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match run {
+                    0 => Err(io::Error::other("accept failed")),
+                    1 => panic!("boom"),
+                    _ => std::future::pending().await,
+                }
+            }
+        });
+
+        for _ in 0..100 {
+            if runs.load(Ordering::SeqCst) == 3 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        let health = supervisor.health();
+        assert!(health.healthy());
+        assert_eq!(health.tasks[0].restarts, 2);
+        assert_eq!(
+            health.tasks[0].last_error.as_deref(),
+            Some("panicked: boom")
+        );
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.task_restarts, snapshot.task_panics), (2, 1));
+
+        handle.abort();
+        let _ = handle.await;
+        assert!(supervisor.health().tasks.is_empty());
+    }
+}
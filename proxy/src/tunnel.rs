@@ -0,0 +1,188 @@
+use bytes::Bytes;
+use hyper_util::rt::{TokioIo, tokio::WithHyperIo};
+use roxy_shared::{http::HttpError, tls::client_tls, upstream::connect_upstream};
+use rustls::pki_types::ServerName;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, split};
+use tracing::trace;
+
+use crate::{
+    filter::UnknownProtocol,
+    flow::{
+        FlowEvent, FlowEventEmitter, FlowStore,
+        error::{FlowError, FlowErrorKind},
+        id::FlowId,
+        tunnel::TunnelChunk,
+    },
+    passthrough::handle_passthrough,
+    proxy::FlowContext,
+};
+
+/// Bytes read from either side at a time.
+const READ_LEN: usize = 16 * 1024;
+
+/// Relays `client_stream`, which does not speak HTTP, to the target as the
+/// [`UnknownProtocol`] policy of the host filter says. When `tls` the client's
+/// TLS was terminated by Roxy and the server is spoken to over TLS too.
+pub async fn handle_unknown_protocol<S>(
+    flow_cxt: FlowContext,
+    client_stream: S,
+    tls: bool,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    match flow_cxt.proxy_cxt.host_filter.unknown_protocol() {
+        UnknownProtocol::Capture => handle_raw_tunnel(flow_cxt, client_stream, tls, true).await,
+        UnknownProtocol::Passthrough if !tls => handle_passthrough(flow_cxt, client_stream).await,
+        UnknownProtocol::Passthrough => {
+            handle_raw_tunnel(flow_cxt, client_stream, tls, false).await
+        }
+    }
+}
+
+/// Relays `client_stream` to the target, recording the bytes each side sends
+/// when `capture`, otherwise only the connection.
+async fn handle_raw_tunnel<S>(
+    flow_cxt: FlowContext,
+    client_stream: S,
+    tls: bool,
+    capture: bool,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let target = &flow_cxt.target_uri;
+    trace!("Raw tunnel {target} tls {tls} capture {capture}");
+    let flow_store = &flow_cxt.proxy_cxt.flow_store;
+    let flow_id = if capture {
+        flow_store.new_tunnel_flow(&flow_cxt, tls).await
+    } else {
+        flow_store.new_passthrough_flow(&flow_cxt).await
+    };
+    let recorder = capture.then_some(flow_store);
+
+    let emitter = FlowEventEmitter::new(flow_id, flow_store.clone());
+    let connected = connect_upstream(
+        flow_cxt.proxy_cxt.upstream.resolve(target.host()).as_ref(),
+        &flow_cxt.proxy_cxt.dns,
+        target.host(),
+        target.port(),
+        &emitter,
+    )
+    .await;
+    let server_stream = match connected {
+        Ok(stream) => stream,
+        Err(err) => {
+            flow_store.post_event(
+                flow_id,
+                FlowEvent::TunnelClosed(Some(FlowError::from(&err))),
+            );
+            return Err(Box::new(err));
+        }
+    };
+
+    let result = if tls {
+        let connected = match ServerName::try_from(target.host().to_string()) {
+            Ok(server_name) => {
+                client_tls(
+                    server_name,
+                    WithHyperIo::new(server_stream),
+                    vec![],
+                    flow_cxt.proxy_cxt.ca.roots(),
+                    &emitter,
+                    &flow_cxt.proxy_cxt.tls_config,
+                )
+                .await
+            }
+            Err(err) => Err(HttpError::from(err)),
+        };
+        match connected {
+            Ok((server_tls, _)) => {
+                relay(recorder, flow_id, client_stream, TokioIo::new(server_tls)).await
+            }
+            Err(err) => {
+                flow_store.post_event(
+                    flow_id,
+                    FlowEvent::TunnelClosed(Some(FlowError::from(&err))),
+                );
+                return Err(Box::new(err));
+            }
+        }
+    } else {
+        relay(recorder, flow_id, client_stream, server_stream).await
+    };
+
+    trace!("Raw tunnel {target} closed {result:?}");
+    flow_store.post_event(
+        flow_id,
+        FlowEvent::TunnelClosed(
+            result
+                .as_ref()
+                .err()
+                .map(|e| FlowError::new(FlowErrorKind::Connect, e.to_string())),
+        ),
+    );
+    result?;
+    Ok(())
+}
+
+/// Copies both ways until both sides finished sending, a side that finished
+/// has the other's write half shut down. The bytes are posted to `recorder`
+/// when set.
+async fn relay<C, S>(
+    recorder: Option<&FlowStore>,
+    flow_id: FlowId,
+    client: C,
+    server: S,
+) -> std::io::Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut client_read, mut client_write) = split(client);
+    let (mut server_read, mut server_write) = split(server);
+    tokio::try_join!(
+        copy_recorded(
+            recorder,
+            flow_id,
+            &mut client_read,
+            &mut server_write,
+            TunnelChunk::client
+        ),
+        copy_recorded(
+            recorder,
+            flow_id,
+            &mut server_read,
+            &mut client_write,
+            TunnelChunk::server
+        ),
+    )?;
+    Ok(())
+}
+
+async fn copy_recorded<R, W>(
+    recorder: Option<&FlowStore>,
+    flow_id: FlowId,
+    reader: &mut R,
+    writer: &mut W,
+    chunk: fn(Bytes) -> TunnelChunk,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; READ_LEN];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return writer.shutdown().await;
+        }
+        writer.write_all(&buf[..n]).await?;
+        if let Some(flow_store) = recorder {
+            flow_store.post_event(
+                flow_id,
+                FlowEvent::TunnelData(chunk(Bytes::copy_from_slice(&buf[..n]))),
+            );
+        }
+    }
+}
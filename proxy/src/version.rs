@@ -0,0 +1,366 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use cow_utils::CowUtils;
+use http::{HeaderMap, Version, header::ALT_SVC};
+use roxy_shared::{alpn::AlpnProtocol, upstream::host_matches, uri::RUri, version::HttpVersion};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+use tracing::{error, trace};
+
+/// How long an `Alt-Svc` alternative without `ma` stays valid, RFC 7838 3.
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+/// Origins whose HTTP/3 alternatives are remembered at once.
+const MAX_ALT_SVC: usize = 1024;
+
+/// Which HTTP version requests are sent to the server with.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Display, EnumString,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum VersionPreference {
+    /// TLS servers pick HTTP/2 or HTTP/1.1 with ALPN, plain HTTP and HTTP/3
+    /// keep the client's version.
+    #[default]
+    Auto,
+    /// Always HTTP/1.1, HTTP/1.0 requests stay HTTP/1.0.
+    Http1,
+    /// HTTP/2 offered first over TLS, HTTP/1.1 when the server refuses it.
+    /// Plain HTTP is sent as h2c with prior knowledge.
+    Http2,
+    /// HTTP/3 once the server advertised it on the same port with `Alt-Svc`,
+    /// `auto` until then.
+    Http3,
+}
+
+/// Sends requests to hosts matching `pattern` with `version`. Patterns use
+/// the syntax of upstream rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionRule {
+    pub pattern: String,
+    pub version: VersionPreference,
+}
+
+impl VersionRule {
+    pub fn new(pattern: impl Into<String>, version: VersionPreference) -> Self {
+        Self {
+            pattern: pattern.into(),
+            version,
+        }
+    }
+}
+
+/// The version a flow's client asked for next to the one the server answered
+/// with, and the preference that decided between them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionChoice {
+    pub preference: VersionPreference,
+    pub requested: HttpVersion,
+    pub chosen: HttpVersion,
+    /// Sent over HTTP/3 because the server advertised it in `Alt-Svc`.
+    pub alt_svc: bool,
+}
+
+/// How one request is sent, `alpns` of `None` offers the client's defaults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Negotiated {
+    pub preference: VersionPreference,
+    pub version: Version,
+    pub alpns: Option<Vec<AlpnProtocol>>,
+    /// `version` is HTTP/3 from an `Alt-Svc` alternative, the client's
+    /// version is used instead if it can not be reached.
+    pub alt_svc: bool,
+}
+
+/// Shared handle to the active [`VersionRule`]s, the first rule matching a
+/// host wins. Also remembers which servers advertised HTTP/3.
+#[derive(Debug, Clone, Default)]
+pub struct Versions {
+    rules: Arc<RwLock<Vec<VersionRule>>>,
+    /// When the HTTP/3 alternative of each host and port expires.
+    alt_svc: Arc<Mutex<HashMap<(String, u16), Instant>>>,
+    /// Hosts without a rule are treated as `http3`.
+    alt_svc_upgrade: Arc<AtomicBool>,
+}
+
+impl Versions {
+    pub fn new(rules: Vec<VersionRule>) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+            ..Default::default()
+        }
+    }
+
+    /// Upgrades every host without a rule to HTTP/3 once it advertised it.
+    pub fn with_alt_svc_upgrade(self, upgrade: bool) -> Self {
+        self.set_alt_svc_upgrade(upgrade);
+        self
+    }
+
+    pub fn alt_svc_upgrade(&self) -> bool {
+        self.alt_svc_upgrade.load(Ordering::Relaxed)
+    }
+
+    pub fn set_alt_svc_upgrade(&self, upgrade: bool) {
+        self.alt_svc_upgrade.store(upgrade, Ordering::Relaxed);
+    }
+
+    pub fn rules(&self) -> Vec<VersionRule> {
+        match self.rules.read() {
+            Ok(rules) => rules.clone(),
+            Err(err) => {
+                error!("Version rules lock poisoned {err}");
+                vec![]
+            }
+        }
+    }
+
+    pub fn set_rules(&self, rules: Vec<VersionRule>) {
+        match self.rules.write() {
+            Ok(mut guard) => *guard = rules,
+            Err(err) => error!("Version rules lock poisoned {err}"),
+        }
+    }
+
+    /// The version of the first rule matching `host`, `http3` without one
+    /// when upgrading with `Alt-Svc` is on.
+    pub fn preference(&self, host: &str) -> VersionPreference {
+        let rule = match self.rules.read() {
+            Ok(rules) => rules
+                .iter()
+                .find(|r| host_matches(&r.pattern, host))
+                .map(|r| r.version),
+            Err(err) => {
+                error!("Version rules lock poisoned {err}");
+                None
+            }
+        };
+        match rule {
+            Some(version) => version,
+            None if self.alt_svc_upgrade() => VersionPreference::Http3,
+            None => VersionPreference::Auto,
+        }
+    }
+
+    /// How a `requested` version request to `uri` is sent.
+    pub fn negotiate(&self, uri: &RUri, requested: Version) -> Negotiated {
+        let preference = self.preference(uri.host());
+        let alt_svc = preference == VersionPreference::Http3
+            && requested != Version::HTTP_3
+            && uri.is_tls()
+            && self.has_h3(uri);
+        let (version, alpns) = match preference {
+            VersionPreference::Auto => (requested, None),
+            VersionPreference::Http1 => (
+                requested.min(Version::HTTP_11),
+                Some(vec![AlpnProtocol::Http1]),
+            ),
+            VersionPreference::Http2 if uri.is_tls() => (
+                Version::HTTP_2,
+                Some(vec![AlpnProtocol::Http2, AlpnProtocol::Http1]),
+            ),
+            VersionPreference::Http2 => (Version::HTTP_2, None),
+            VersionPreference::Http3 if alt_svc => (Version::HTTP_3, None),
+            VersionPreference::Http3 => (requested, None),
+        };
+        trace!("Sending {requested:?} to {uri} as {version:?}, {preference}");
+        Negotiated {
+            preference,
+            version,
+            alpns,
+            alt_svc,
+        }
+    }
+
+    /// Remembers whether the `Alt-Svc` of a response from `uri` offers HTTP/3
+    /// on the same port. Alternatives on other hosts or ports are ignored, as
+    /// are plain HTTP origins which must not be upgraded to TLS this way.
+    pub fn learn_alt_svc(&self, uri: &RUri, headers: &HeaderMap) {
+        if !uri.is_tls() {
+            return;
+        }
+        let Some(value) = headers.get(ALT_SVC).and_then(|v| v.to_str().ok()) else {
+            return;
+        };
+        let key = alt_svc_key(uri);
+        let mut alt_svc = match self.alt_svc.lock() {
+            Ok(alt_svc) => alt_svc,
+            Err(err) => {
+                error!("Alt-Svc lock poisoned {err}");
+                return;
+            }
+        };
+        match h3_max_age(value, key.1) {
+            Some(max_age) => {
+                if alt_svc.len() >= MAX_ALT_SVC {
+                    let now = Instant::now();
+                    alt_svc.retain(|_, expires| *expires > now);
+                }
+                if alt_svc.len() < MAX_ALT_SVC || alt_svc.contains_key(&key) {
+                    alt_svc.insert(key, Instant::now() + max_age);
+                }
+            }
+            None => {
+                alt_svc.remove(&key);
+            }
+        }
+    }
+
+    /// Drops the HTTP/3 alternative of `uri` after it could not be reached,
+    /// RFC 7838 2.4 falls back to the origin.
+    pub fn forget_alt_svc(&self, uri: &RUri) {
+        let key = alt_svc_key(uri);
+        match self.alt_svc.lock() {
+            Ok(mut alt_svc) => {
+                alt_svc.remove(&key);
+            }
+            Err(err) => error!("Alt-Svc lock poisoned {err}"),
+        }
+    }
+
+    fn has_h3(&self, uri: &RUri) -> bool {
+        let key = alt_svc_key(uri);
+        match self.alt_svc.lock() {
+            Ok(alt_svc) => alt_svc
+                .get(&key)
+                .is_some_and(|expires| *expires > Instant::now()),
+            Err(err) => {
+                error!("Alt-Svc lock poisoned {err}");
+                false
+            }
+        }
+    }
+}
+
+/// Origins are remembered by lowercase host and port.
+fn alt_svc_key(uri: &RUri) -> (String, u16) {
+    (uri.host().cow_to_ascii_lowercase().into_owned(), uri.port())
+}
+
+/// How long the `h3` alternative on `port` of the origin in an `Alt-Svc`
+/// value stays valid, `None` without one or for `clear`.
+fn h3_max_age(alt_svc: &str, port: u16) -> Option<Duration> {
+    alt_svc.split(',').find_map(|alternative| {
+        let mut params = alternative.split(';');
+        let (protocol, authority) = params.next()?.trim().split_once('=')?;
+        let authority = authority.trim().trim_matches('"');
+        let (host, alt_port) = authority.rsplit_once(':')?;
+        if protocol != "h3" || !host.is_empty() || alt_port.parse::<u16>().ok()? != port {
+            return None;
+        }
+        let max_age = params
+            .filter_map(|param| param.trim().strip_prefix("ma="))
+            .find_map(|ma| ma.parse::<u64>().ok())
+            .map_or(DEFAULT_MAX_AGE, Duration::from_secs);
+        Some(max_age)
+    })
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn alt_svc(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ALT_SVC, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn parses_alt_svc() {
+        assert_eq!(
+            h3_max_age("h3=\":443\"; ma=3600, h2=\":443\"", 443),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(
+            h3_max_age("h3-29=\":443\", h3=\":443\"", 443),
+            Some(DEFAULT_MAX_AGE)
+        );
+        assert_eq!(h3_max_age("h3=\":8443\"", 443), None);
+        assert_eq!(h3_max_age("h3=\"alt.example.com:443\"", 443), None);
+        assert_eq!(h3_max_age("clear", 443), None);
+    }
+
+    #[test]
+    fn applies_rules() {
+        let versions = Versions::new(vec![
+            VersionRule::new("legacy.example.com", VersionPreference::Http1),
+            VersionRule::new("*.internal", VersionPreference::Http2),
+            VersionRule::new("*", VersionPreference::Http3),
+        ]);
+
+        let legacy: RUri = "https://legacy.example.com/".parse().unwrap();
+        let negotiated = versions.negotiate(&legacy, Version::HTTP_2);
+        assert_eq!(negotiated.version, Version::HTTP_11);
+        assert_eq!(negotiated.alpns, Some(vec![AlpnProtocol::Http1]));
+        assert_eq!(
+            versions.negotiate(&legacy, Version::HTTP_10).version,
+            Version::HTTP_10
+        );
+
+        let internal: RUri = "http://api.internal/".parse().unwrap();
+        let negotiated = versions.negotiate(&internal, Version::HTTP_11);
+        assert_eq!(negotiated.version, Version::HTTP_2);
+        assert_eq!(negotiated.alpns, None);
+
+        let other: RUri = "https://example.com/".parse().unwrap();
+        assert_eq!(
+            versions.negotiate(&other, Version::HTTP_11).version,
+            Version::HTTP_11
+        );
+        versions.learn_alt_svc(&other, &alt_svc("h3=\":443\"; ma=60"));
+        let negotiated = versions.negotiate(&other, Version::HTTP_11);
+        assert_eq!(negotiated.version, Version::HTTP_3);
+        assert!(negotiated.alt_svc);
+        versions.learn_alt_svc(&other, &alt_svc("clear"));
+        assert_eq!(
+            versions.negotiate(&other, Version::HTTP_11).version,
+            Version::HTTP_11
+        );
+    }
+
+    #[test]
+    fn upgrades_with_alt_svc() {
+        let versions = Versions::new(vec![VersionRule::new(
+            "legacy.example.com",
+            VersionPreference::Http1,
+        )]);
+        let uri: RUri = "https://example.com/".parse().unwrap();
+        let plain: RUri = "http://example.com/".parse().unwrap();
+        let legacy: RUri = "https://legacy.example.com/".parse().unwrap();
+        for origin in [&uri, &plain, &legacy] {
+            versions.learn_alt_svc(origin, &alt_svc("h3=\":443\", h3=\":80\""));
+        }
+        assert!(!versions.negotiate(&uri, Version::HTTP_2).alt_svc);
+
+        versions.set_alt_svc_upgrade(true);
+        let negotiated = versions.negotiate(&uri, Version::HTTP_2);
+        assert_eq!(negotiated.version, Version::HTTP_3);
+        assert!(negotiated.alt_svc);
+        assert_eq!(
+            versions.negotiate(&plain, Version::HTTP_11).version,
+            Version::HTTP_11
+        );
+        assert_eq!(
+            versions.negotiate(&legacy, Version::HTTP_2).version,
+            Version::HTTP_11
+        );
+        assert!(!versions.negotiate(&uri, Version::HTTP_3).alt_svc);
+
+        versions.forget_alt_svc(&uri);
+        assert_eq!(
+            versions.negotiate(&uri, Version::HTTP_2).version,
+            Version::HTTP_2
+        );
+    }
+}
@@ -1,22 +1,93 @@
-use std::{io::Error, sync::Arc};
+use std::{io::Error, sync::Arc, time::Duration};
 
+use dashmap::DashMap;
 use futures_util::{SinkExt, StreamExt};
-use roxy_shared::tls::RustlsClientConfig;
+use roxy_shared::{tls::RustlsClientConfig, upstream::connect_upstream, uri::RUri};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     net::TcpStream,
+    sync::mpsc,
+    time::timeout,
 };
 use tokio_tungstenite::{
-    Connector, WebSocketStream, accept_async, connect_async_tls_with_config,
-    tungstenite::client::IntoClientRequest,
+    Connector, MaybeTlsStream, WebSocketStream, accept_hdr_async, client_async_tls_with_config,
+    tungstenite::{
+        Message,
+        client::IntoClientRequest,
+        handshake::server::{ErrorResponse, Request, Response},
+    },
 };
-use tracing::trace;
+use tracing::{debug, trace};
 
 use crate::{
-    flow::{FlowConnection, FlowEvent, WsMessage},
-    proxy::FlowContext,
+    flow::{
+        FlowConnection, FlowEvent, FlowEventEmitter, InterceptedRequest, WsDirection, WsMessage,
+        id::FlowId, replay::ReplayError,
+    },
+    proxy::{FlowContext, ProxyContext},
 };
 
+/// How long a replay waits for the server to send something before closing.
+const REPLAY_IDLE: Duration = Duration::from_secs(2);
+
+/// Live intercepted WebSocket connections by flow id, messages injected into
+/// one are forwarded and recorded as if its client or server had sent them.
+#[derive(Debug, Clone, Default)]
+pub struct WsSessions {
+    sessions: Arc<DashMap<FlowId, WsSession>>,
+}
+
+#[derive(Debug, Clone)]
+struct WsSession {
+    to_server: mpsc::UnboundedSender<Message>,
+    to_client: mpsc::UnboundedSender<Message>,
+}
+
+impl WsSessions {
+    /// Sends `message` over the connection of flow `flow_id` as if `from`
+    /// had sent it, false when the connection is not open.
+    pub fn inject(&self, flow_id: FlowId, from: WsDirection, message: Message) -> bool {
+        let Some(session) = self.sessions.get(&flow_id) else {
+            return false;
+        };
+        let sent = match from {
+            WsDirection::Client => session.to_server.send(message),
+            WsDirection::Server => session.to_client.send(message),
+        };
+        sent.is_ok()
+    }
+
+    /// Whether the connection of flow `flow_id` is open.
+    pub fn is_live(&self, flow_id: FlowId) -> bool {
+        self.sessions.contains_key(&flow_id)
+    }
+
+    /// Opens flow `flow_id` to injection, returning the messages injected
+    /// for its server and its client.
+    pub(crate) fn register(
+        &self,
+        flow_id: FlowId,
+    ) -> (
+        mpsc::UnboundedReceiver<Message>,
+        mpsc::UnboundedReceiver<Message>,
+    ) {
+        let (to_server, to_server_rx) = mpsc::unbounded_channel();
+        let (to_client, to_client_rx) = mpsc::unbounded_channel();
+        self.sessions.insert(
+            flow_id,
+            WsSession {
+                to_server,
+                to_client,
+            },
+        );
+        (to_server_rx, to_client_rx)
+    }
+
+    pub(crate) fn remove(&self, flow_id: FlowId) {
+        self.sessions.remove(&flow_id);
+    }
+}
+
 pub async fn handle_ws<S>(
     flow_cxt: FlowContext,
     stream: S,
@@ -35,8 +106,16 @@ where
         .await;
 
     trace!("Client accept");
-    let ws_client = accept_async(stream).await.map_err(Error::other)?;
-    let server_stream = TcpStream::connect(&flow_cxt.target_uri.host_port()).await?;
+    let ws_client = accept_client(&flow_cxt, flow_id, "ws", stream).await?;
+    let target = &flow_cxt.target_uri;
+    let server_stream = connect_upstream(
+        flow_cxt.proxy_cxt.upstream.resolve(target.host()).as_ref(),
+        &flow_cxt.proxy_cxt.dns,
+        target.host(),
+        target.port(),
+        &FlowEventEmitter::new(flow_id, flow_cxt.proxy_cxt.flow_store.clone()),
+    )
+    .await?;
 
     trace!("ws server connect");
     let ws_server = tokio_tungstenite::client_async("ws://fake", server_stream)
@@ -63,7 +142,7 @@ where
         })
         .await;
 
-    let ws_client = accept_async(stream).await.map_err(Error::other)?;
+    let ws_client = accept_client(&flow_cxt, flow_id, "wss", stream).await?;
 
     let RustlsClientConfig {
         cert_logger: _,
@@ -72,15 +151,25 @@ where
     } = flow_cxt
         .proxy_cxt
         .tls_config
-        .rustls_client_config(flow_cxt.proxy_cxt.ca.roots());
+        .rustls_client_config(flow_cxt.proxy_cxt.ca.roots(), flow_cxt.target_uri.host());
 
     let url = format!("wss://{}", flow_cxt.target_uri);
     let req = url.clone().into_client_request().map_err(Error::other)?;
 
-    let (ws_server, _) = connect_async_tls_with_config(
+    let target = &flow_cxt.target_uri;
+    let server_stream = connect_upstream(
+        flow_cxt.proxy_cxt.upstream.resolve(target.host()).as_ref(),
+        &flow_cxt.proxy_cxt.dns,
+        target.host(),
+        target.port(),
+        &FlowEventEmitter::new(flow_id, flow_cxt.proxy_cxt.flow_store.clone()),
+    )
+    .await?;
+
+    let (ws_server, _) = client_async_tls_with_config(
         req,
+        server_stream,
         None,
-        false,
         Some(Connector::Rustls(Arc::new(client_config))),
     )
     .await
@@ -90,8 +179,51 @@ where
     Ok(())
 }
 
+/// Completes the client's handshake, recording the path it asked for as the
+/// flow's request so the connection can be replayed.
+async fn accept_client<S>(
+    flow_cxt: &FlowContext,
+    flow_id: FlowId,
+    scheme: &str,
+    stream: S,
+) -> Result<WebSocketStream<S>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut path = None;
+    let ws = accept_hdr_async(
+        stream,
+        |req: &Request, resp: Response| -> Result<Response, ErrorResponse> {
+            path = req.uri().path_and_query().map(|p| p.to_string());
+            Ok(resp)
+        },
+    )
+    .await
+    .map_err(Error::other)?;
+
+    let target = &flow_cxt.target_uri;
+    let url = format!(
+        "{scheme}://{}:{}{}",
+        target.host(),
+        target.port(),
+        path.as_deref().unwrap_or("/")
+    );
+    match url.parse::<RUri>() {
+        Ok(uri) => flow_cxt.proxy_cxt.flow_store.post_event(
+            flow_id,
+            FlowEvent::Request(InterceptedRequest {
+                uri,
+                method: http::Method::GET,
+                ..Default::default()
+            }),
+        ),
+        Err(err) => debug!("Not recording ws request {url} {err}"),
+    }
+    Ok(ws)
+}
+
 async fn process_ws<S, T>(
-    flow_id: i64,
+    flow_id: FlowId,
     flow_cxt: FlowContext,
     ws_client: WebSocketStream<S>,
     ws_server: WebSocketStream<T>,
@@ -103,9 +235,18 @@ where
     let (mut client_write, mut client_read) = ws_client.split();
     let (mut server_write, mut server_read) = ws_server.split();
 
+    let sessions = &flow_cxt.proxy_cxt.ws_sessions;
+    let (mut injected_to_server, mut injected_to_client) = sessions.register(flow_id);
+
     let client_to_server = async {
-        while let Some(msg) = client_read.next().await {
-            let msg = msg.map_err(Error::other)?;
+        loop {
+            let msg = tokio::select! {
+                msg = client_read.next() => match msg {
+                    Some(msg) => msg.map_err(Error::other)?,
+                    None => break,
+                },
+                Some(msg) = injected_to_server.recv() => msg,
+            };
             flow_cxt.proxy_cxt.flow_store.post_event(
                 flow_id,
                 FlowEvent::WsMessage(WsMessage::client(msg.clone())),
@@ -116,8 +257,14 @@ where
     };
 
     let server_to_client = async {
-        while let Some(msg) = server_read.next().await {
-            let msg = msg.map_err(Error::other)?;
+        loop {
+            let msg = tokio::select! {
+                msg = server_read.next() => match msg {
+                    Some(msg) => msg.map_err(Error::other)?,
+                    None => break,
+                },
+                Some(msg) = injected_to_client.recv() => msg,
+            };
             flow_cxt.proxy_cxt.flow_store.post_event(
                 flow_id,
                 FlowEvent::WsMessage(WsMessage::server(msg.clone())),
@@ -127,10 +274,89 @@ where
         Ok::<_, Error>(())
     };
 
-    tokio::select! {
+    let res = tokio::select! {
         res = client_to_server => res,
         res = server_to_client => res,
-    }
-    .map_err(Box::new)?;
+    };
+    sessions.remove(flow_id);
+    res.map_err(Box::new)?;
     Ok(())
 }
+
+/// Opens a new connection to the server of WebSocket flow `flow_id` and sends
+/// the messages its client sent, in order, recording the exchange as a new
+/// flow whose id is returned. The connection is closed once the server has
+/// been quiet for a moment.
+pub async fn replay_ws(cxt: &ProxyContext, flow_id: FlowId) -> Result<FlowId, ReplayError> {
+    let flow = cxt
+        .flow_store
+        .get_flow_by_id(flow_id)
+        .await
+        .ok_or(ReplayError::NotFound(flow_id))?;
+    let (client_connection, request, sent) = {
+        let flow = flow.read().await;
+        let sent: Vec<Message> = flow
+            .messages
+            .iter()
+            .filter(|m| m.direction == WsDirection::Client && !m.message.is_close())
+            .map(|m| m.message.clone())
+            .collect();
+        (flow.client_connection, flow.request.clone(), sent)
+    };
+    let request = request.ok_or(ReplayError::NoRequest)?;
+
+    let id = cxt.flow_store.new_ws_flow(client_connection).await;
+    cxt.flow_store
+        .post_event(id, FlowEvent::Request(request.clone()));
+    let (mut write, mut read) = connect_server(cxt, id, &request.uri).await?.split();
+    let record = |msg: WsMessage| cxt.flow_store.post_event(id, FlowEvent::WsMessage(msg));
+
+    for msg in sent {
+        record(WsMessage::client(msg.clone()));
+        write.send(msg).await.map_err(Error::other)?;
+    }
+    while let Ok(Some(msg)) = timeout(REPLAY_IDLE, read.next()).await {
+        let msg = msg.map_err(Error::other)?;
+        let closed = msg.is_close();
+        record(WsMessage::server(msg));
+        if closed {
+            return Ok(id);
+        }
+    }
+    let close = Message::Close(None);
+    record(WsMessage::client(close.clone()));
+    write.send(close).await.map_err(Error::other)?;
+    debug!("Replayed ws flow {flow_id} as {id}");
+    Ok(id)
+}
+
+async fn connect_server(
+    cxt: &ProxyContext,
+    flow_id: FlowId,
+    uri: &RUri,
+) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, ReplayError> {
+    let stream = connect_upstream(
+        cxt.upstream.resolve(uri.host()).as_ref(),
+        &cxt.dns,
+        uri.host(),
+        uri.port(),
+        &FlowEventEmitter::new(flow_id, cxt.flow_store.clone()),
+    )
+    .await?;
+    let RustlsClientConfig { client_config, .. } = cxt
+        .tls_config
+        .rustls_client_config(cxt.ca.roots(), uri.host());
+    let req = uri
+        .to_string()
+        .into_client_request()
+        .map_err(Error::other)?;
+    let (ws, _) = client_async_tls_with_config(
+        req,
+        stream,
+        None,
+        Some(Connector::Rustls(Arc::new(client_config))),
+    )
+    .await
+    .map_err(Error::other)?;
+    Ok(ws)
+}
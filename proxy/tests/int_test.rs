@@ -1,28 +1,46 @@
 use bytes::Bytes;
+use cow_utils::CowUtils;
 use futures_util::{SinkExt, StreamExt};
 use http::header::{
-    ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, DATE, HOST, SET_COOKIE, TE,
-    TRANSFER_ENCODING,
+    ACCEPT_ENCODING, CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, DATE, HOST,
+    SET_COOKIE, TE, TRANSFER_ENCODING,
 };
-use http::{HeaderName, Method, Uri, Version};
+use http::{HeaderName, Method, StatusCode, Uri, Version};
+use http_body_util::BodyExt;
 use http_body_util::Empty;
 use http_body_util::Full;
 use http_body_util::combinators::BoxBody;
 use itertools::Itertools;
-use roxy_proxy::flow::FlowStore;
-use roxy_proxy::interceptor::{ScriptEngine, ScriptType};
+use roxy_proxy::acl::ClientAclRules;
+use roxy_proxy::auth::ProxyCredential;
+use roxy_proxy::filter::{HostFilterRules, UnknownProtocol};
+use roxy_proxy::flow::error::FlowErrorKind;
+use roxy_proxy::flow::id::FlowId;
+use roxy_proxy::flow::replay::ReplayError;
+use roxy_proxy::flow::{FlowStore, WsDirection};
+use roxy_proxy::interceptor::{FlowNotifyLevel, ScriptEngine, ScriptType};
+use roxy_proxy::limits::Limits;
+use roxy_proxy::listener::{ListenerConfig, ListenerMode};
 use roxy_proxy::proxy::ProxyManager;
+use roxy_proxy::rewrite::RewriteRule;
+use roxy_proxy::scenario::{Scenario, ScenarioStep};
+use roxy_proxy::settings::ProxySettings;
+use roxy_proxy::version::{VersionPreference, VersionRule};
+use roxy_servers::h2::h2c_server;
+use roxy_servers::serve::EVENT_COUNT;
 use roxy_servers::web_transport::h3_wt;
 use roxy_servers::ws::{start_ws_server, start_wss_server};
-use roxy_servers::{HttpServers, load_asset};
+use roxy_servers::{H2_BODY, HttpServers, load_asset};
 use roxy_shared::cert::LoggingServerVerifier;
 use roxy_shared::client::ClientContext;
 use roxy_shared::content::{
     ContentType, Encodings, content_type_ext, decode_body, encode_body, ext_to_content_type,
 };
-use roxy_shared::h3_client::client_h3_wt;
-use roxy_shared::http::HttpResponse;
+use roxy_shared::h3_client::{client_h3_wt, connect_udp};
+use roxy_shared::http::{HttpResponse, HttpStreamResponse};
 use roxy_shared::io::local_tcp_listener;
+use roxy_shared::pool::ConnectionPool;
+use roxy_shared::retry::RetryPolicy;
 use roxy_shared::tls::TlsConfig;
 use roxy_shared::uri::RUri;
 use roxy_shared::{RoxyCA, generate_roxy_root_ca_with_path};
@@ -36,6 +54,7 @@ use strum::VariantArray;
 use time::OffsetDateTime;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
 use tokio::time::timeout;
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
@@ -48,7 +67,7 @@ struct TestContext {
     proxy_socket_addr: SocketAddr,
     proxy_addr: RUri,
     roxy_ca: RoxyCA,
-    _proxy_manager: ProxyManager,
+    proxy_manager: ProxyManager,
     script_engine: ScriptEngine,
     tls_config: TlsConfig,
     flow_store: FlowStore,
@@ -88,7 +107,7 @@ impl TestContext {
         TestContext {
             proxy_socket_addr,
             proxy_addr: proxy_uri,
-            _proxy_manager: proxy_manager,
+            proxy_manager,
             roxy_ca,
             script_engine,
             tls_config: TlsConfig::default(),
@@ -300,6 +319,947 @@ async fn test_http_proxy_request() {
     assert_eq!(cxt.flow_store.flows.len(), 6)
 }
 
+/// Reads one response off a connection that stays open, its body framed by
+/// `Content-Length`.
+async fn read_response(stream: &mut TcpStream) -> (String, String) {
+    let mut buf = vec![];
+    let head_end = loop {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await.unwrap();
+        buf.push(byte[0]);
+        if buf.ends_with(b"\r\n\r\n") {
+            break buf.len();
+        }
+    };
+    let head = String::from_utf8(buf[..head_end].to_vec()).unwrap();
+    let len: usize = head
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse().unwrap())
+        })
+        .unwrap();
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).await.unwrap();
+    (head, String::from_utf8(body).unwrap())
+}
+
+#[tokio::test]
+async fn test_http10_keep_alive() {
+    let cxt = TestContext::new().await;
+    let set = [HttpServers::H10, HttpServers::H11].into_iter().collect();
+    let servers = HttpServers::start_set(set, &cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+
+    for s in &servers {
+        let target = s.target.host_port();
+        let mut stream = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+        for _ in 0..2 {
+            stream
+                .write_all(
+                    format!(
+                        "GET http://{target}/ HTTP/1.0\r\nHost: {target}\r\nConnection: keep-alive\r\n\r\n"
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .unwrap();
+            let (head, body) = timeout(Duration::from_millis(TIMEOUT), read_response(&mut stream))
+                .await
+                .unwrap();
+            let head = head.cow_to_ascii_lowercase();
+            assert!(head.starts_with("http/1.0 200"), "{head}");
+            assert!(head.contains("connection: keep-alive"), "{head}");
+            assert!(!head.contains("transfer-encoding"), "{head}");
+            assert_eq!(body, format!("Hello, {}", s.server.marker()));
+        }
+
+        // Without keep-alive the connection is closed after the response
+        stream
+            .write_all(
+                format!("GET http://{target}/ HTTP/1.0\r\nHost: {target}\r\n\r\n").as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        timeout(
+            Duration::from_millis(TIMEOUT),
+            stream.read_to_string(&mut response),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.0 200"), "{response}");
+        assert!(
+            response
+                .cow_to_ascii_lowercase()
+                .contains("content-length: "),
+            "{response}"
+        );
+        assert!(response.ends_with(&format!("Hello, {}", s.server.marker())));
+    }
+
+    assert_eq!(cxt.flow_store.flows.len(), servers.len() * 3);
+    for entry in cxt.flow_store.flows.iter() {
+        let flow = entry.value().read().await;
+        assert!(
+            !flow
+                .request
+                .as_ref()
+                .unwrap()
+                .headers
+                .contains_key(CONNECTION)
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_h2c() {
+    let cxt = TestContext::new().await;
+    let (addr, server) = h2c_server(HttpServers::H2).await.unwrap();
+
+    // Prior knowledge on both sides of the proxy
+    let stream = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+    let (mut sender, conn) = hyper::client::conn::http2::handshake(
+        hyper_util::rt::TokioExecutor::new(),
+        hyper_util::rt::TokioIo::new(stream),
+    )
+    .await
+    .unwrap();
+    tokio::spawn(conn);
+    for _ in 0..2 {
+        let request = hyper::Request::builder()
+            .uri(format!("http://{addr}/"))
+            .version(Version::HTTP_2)
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = timeout(Duration::from_millis(TIMEOUT), sender.send_request(request))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), Version::HTTP_2);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, format!("Hello, {H2_BODY}"));
+    }
+    let stats = cxt.proxy_manager.pool_stats();
+    assert_eq!(stats.opened, 1);
+    assert_eq!(stats.reused, 1);
+
+    // An HTTP/1.1 upgrade to h2c is answered over HTTP/1.1
+    let h11 = HttpServers::H11
+        .start(&cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+    let target = h11.target.host_port();
+    let mut stream = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+    stream
+        .write_all(
+            format!(
+                "GET http://{target}/ HTTP/1.1\r\nHost: {target}\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+                 Upgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQCAAAAAAIAAAAA\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let (head, body) = timeout(Duration::from_millis(TIMEOUT), read_response(&mut stream))
+        .await
+        .unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    assert_eq!(body, format!("Hello, {}", h11.server.marker()));
+
+    assert_eq!(cxt.flow_store.flows.len(), 3);
+    for entry in cxt.flow_store.flows.iter() {
+        let flow = entry.value().read().await;
+        let request = flow.request.as_ref().unwrap();
+        assert!(!request.headers.contains_key("upgrade"));
+        assert!(!request.headers.contains_key("http2-settings"));
+        let expected = if request.uri.port() == addr.port() {
+            Version::HTTP_2
+        } else {
+            Version::HTTP_11
+        };
+        assert_eq!(request.version.0, expected);
+    }
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_version_rules() {
+    let cxt = TestContext::new().await;
+    cxt.proxy_manager
+        .versions()
+        .set_rules(vec![VersionRule::new(
+            "127.0.0.1",
+            VersionPreference::Http2,
+        )]);
+    let (addr, server) = h2c_server(HttpServers::H2).await.unwrap();
+
+    let mut stream = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+    stream
+        .write_all(format!("GET http://{addr}/ HTTP/1.1\r\nHost: {addr}\r\n\r\n").as_bytes())
+        .await
+        .unwrap();
+    let (head, body) = timeout(Duration::from_millis(TIMEOUT), read_response(&mut stream))
+        .await
+        .unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    assert_eq!(body, format!("Hello, {H2_BODY}"));
+
+    assert_eq!(cxt.flow_store.flows.len(), 1);
+    let flow = cxt.flow_store.flows.iter().next().unwrap().value().clone();
+    let choice = timeout(Duration::from_millis(TIMEOUT), async {
+        loop {
+            if let Some(choice) = flow.read().await.version_choice {
+                break choice;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(choice.preference, VersionPreference::Http2);
+    assert_eq!(choice.requested.0, Version::HTTP_11);
+    assert_eq!(choice.chosen.0, Version::HTTP_2);
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_early_hints() {
+    let cxt = TestContext::new().await;
+    let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = upstream.accept().await.unwrap();
+        let mut buf = vec![];
+        while !buf.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await.unwrap();
+            buf.push(byte[0]);
+        }
+        stream
+            .write_all(
+                b"HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n\
+                  HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello",
+            )
+            .await
+            .unwrap();
+    });
+
+    let mut stream = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+    stream
+        .write_all(format!("GET http://{target}/ HTTP/1.1\r\nHost: {target}\r\n\r\n").as_bytes())
+        .await
+        .unwrap();
+    let (head, body) = timeout(Duration::from_millis(TIMEOUT), read_response(&mut stream))
+        .await
+        .unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    assert_eq!(body, "hello");
+
+    assert_eq!(cxt.flow_store.flows.len(), 1);
+    let flow = cxt.flow_store.flows.iter().next().unwrap().value().clone();
+    // Connection events are recorded by the flow's own task
+    let interim = timeout(Duration::from_millis(TIMEOUT), async {
+        loop {
+            let interim = flow.read().await.interim_responses.clone();
+            if !interim.is_empty() {
+                break interim;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(interim.len(), 1);
+    assert_eq!(interim[0].status, StatusCode::EARLY_HINTS);
+    assert_eq!(interim[0].headers["link"], "</style.css>; rel=preload");
+}
+
+#[tokio::test]
+async fn test_socks_proxy_request() {
+    let mut cxt = TestContext::new().await;
+    let socks_listener = local_tcp_listener(None).await.unwrap();
+    let socks_port = socks_listener.local_addr().unwrap().port();
+    cxt.proxy_manager.start_socks(socks_listener).await.unwrap();
+    let socks_uri: RUri = format!("socks5://127.0.0.1:{socks_port}").parse().unwrap();
+
+    let mut set = HttpServers::set_all();
+    set.remove(&HttpServers::H3);
+    let servers = HttpServers::start_set(set, &cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+
+    for s in &servers {
+        let req = http::Request::builder()
+            .method(Method::GET)
+            .version(s.server.version())
+            .uri(s.target.clone())
+            .header(HOST, s.target.host())
+            .body(BoxBody::new(Empty::new()))
+            .unwrap();
+
+        let client = ClientContext::builder()
+            .with_proxy(socks_uri.clone())
+            .with_roxy_ca(cxt.roxy_ca.clone())
+            .with_alpns(vec![s.server.alpn()])
+            .build();
+
+        let HttpResponse { parts, body, .. } =
+            timeout(Duration::from_millis(TIMEOUT), client.request(req))
+                .await
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(parts.version, s.server.version());
+        assert_eq!(parts.status, 200);
+        assert_eq!(body, format!("Hello, {}", s.server.marker()));
+    }
+
+    assert_eq!(cxt.flow_store.flows.len(), servers.len())
+}
+
+#[tokio::test]
+async fn test_socks_proxy_auth() {
+    let mut cxt = TestContext::new().await;
+    cxt.proxy_manager.auth().set_credentials(vec![
+        ProxyCredential::Basic {
+            user: "alice".into(),
+            password: "secret".into(),
+        },
+        ProxyCredential::Bearer("token".into()),
+    ]);
+    let socks_listener = local_tcp_listener(None).await.unwrap();
+    let socks_port = socks_listener.local_addr().unwrap().port();
+    cxt.proxy_manager.start_socks(socks_listener).await.unwrap();
+
+    let set = [HttpServers::H11].into_iter().collect();
+    let servers = HttpServers::start_set(set, &cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+    let s = &servers[0];
+
+    let send = |user_info: &str| {
+        let socks_uri: RUri = format!("socks5://{user_info}127.0.0.1:{socks_port}")
+            .parse()
+            .unwrap();
+        let req = http::Request::builder()
+            .method(Method::GET)
+            .version(s.server.version())
+            .uri(s.target.clone())
+            .header(HOST, s.target.host())
+            .body(BoxBody::new(Empty::new()))
+            .unwrap();
+        let client = ClientContext::builder()
+            .with_proxy(socks_uri)
+            .with_roxy_ca(cxt.roxy_ca.clone())
+            .with_alpns(vec![s.server.alpn()])
+            .build();
+        async move {
+            timeout(Duration::from_millis(TIMEOUT), client.request(req))
+                .await
+                .unwrap()
+        }
+    };
+
+    assert!(send("").await.is_err());
+    assert!(send("alice:guess@").await.is_err());
+    assert!(send(":token@").await.is_err());
+    let HttpResponse { parts, .. } = send("alice:secret@").await.unwrap();
+    assert_eq!(parts.status, 200);
+    assert_eq!(cxt.flow_store.flows.len(), 1)
+}
+
+#[tokio::test]
+async fn test_h3_skips_upstream() {
+    let cxt = TestContext::new().await;
+    let s = HttpServers::H3
+        .start(&cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+    // Nothing listens on the proxy port, a request chained through it fails
+    let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = closed.local_addr().unwrap().port();
+    drop(closed);
+
+    for proxy in [
+        format!("socks5://127.0.0.1:{port}"),
+        format!("http://127.0.0.1:{port}"),
+    ] {
+        let req = http::Request::builder()
+            .method(Method::GET)
+            .version(Version::HTTP_3)
+            .uri(s.target.clone())
+            .body(BoxBody::new(Empty::new()))
+            .unwrap();
+
+        let client = ClientContext::builder()
+            .with_upstream(Some(proxy.parse().unwrap()))
+            .with_roxy_ca(cxt.roxy_ca.clone())
+            .with_alpns(vec![s.server.alpn()])
+            .build();
+
+        let HttpResponse { parts, body, .. } =
+            timeout(Duration::from_millis(TIMEOUT), client.request(req))
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(parts.version, Version::HTTP_3);
+        assert_eq!(parts.status, 200);
+        assert_eq!(body, format!("Hello, {}", s.server.marker()));
+    }
+}
+
+#[tokio::test]
+async fn test_reverse_listener() {
+    let mut cxt = TestContext::new().await;
+    let set = [HttpServers::H11].into_iter().collect();
+    let servers = HttpServers::start_set(set, &cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+    let s = &servers[0];
+
+    let listener = ListenerConfig::new(
+        SocketAddr::from(([127, 0, 0, 1], 0)),
+        ListenerMode::Reverse(s.target.clone()),
+    );
+    let addr = cxt.proxy_manager.start_listener(listener).await.unwrap();
+    assert_eq!(cxt.proxy_manager.listener_addrs(), vec![addr]);
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            format!("GET / HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\r\n").as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut response = String::new();
+    timeout(
+        Duration::from_millis(TIMEOUT),
+        stream.read_to_string(&mut response),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.ends_with(&format!("Hello, {}", s.server.marker())));
+
+    assert_eq!(cxt.flow_store.flows.len(), 1);
+    for entry in cxt.flow_store.flows.iter() {
+        let flow = entry.value().read().await;
+        let request = flow.request.as_ref().unwrap();
+        assert_eq!(request.uri.host_port(), s.target.host_port());
+        assert_eq!(
+            request.headers[HOST],
+            s.target.inner().authority().unwrap().as_str()
+        );
+    }
+
+    cxt.proxy_manager.stop_all();
+    assert!(cxt.proxy_manager.listener_addrs().is_empty());
+}
+
+#[tokio::test]
+async fn test_settings_update() {
+    let mut cxt = TestContext::new().await;
+    let old_port = cxt.proxy_manager.port();
+    let free = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let new_port = free.local_addr().unwrap().port();
+    drop(free);
+
+    let (settings_tx, settings_rx) = watch::channel(ProxySettings::default());
+    cxt.proxy_manager.watch_settings(settings_rx);
+    settings_tx
+        .send(ProxySettings {
+            port: new_port,
+            host_filter: HostFilterRules::new(vec![], vec!["*".into()]),
+            ..Default::default()
+        })
+        .unwrap();
+
+    timeout(Duration::from_millis(TIMEOUT), async {
+        while cxt.proxy_manager.port() != new_port
+            || TcpStream::connect(("127.0.0.1", old_port)).await.is_ok()
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    TcpStream::connect(("127.0.0.1", new_port)).await.unwrap();
+    assert!(
+        !cxt.proxy_manager
+            .host_filter()
+            .should_intercept("example.com")
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_auth() {
+    let cxt = TestContext::new().await;
+    cxt.proxy_manager
+        .auth()
+        .set_credentials(vec![ProxyCredential::Bearer("token".into())]);
+
+    async fn connect(addr: SocketAddr, authorization: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n{authorization}\r\n"
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut buf = vec![0; 1024];
+        let len = timeout(Duration::from_millis(TIMEOUT), stream.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        String::from_utf8_lossy(&buf[..len]).to_string()
+    }
+
+    let response = connect(cxt.proxy_socket_addr, "").await;
+    assert!(response.starts_with("HTTP/1.1 407"), "{response}");
+    assert!(response.contains("Proxy-Authenticate: Bearer realm=\"roxy\""));
+
+    let response = connect(
+        cxt.proxy_socket_addr,
+        "Proxy-Authorization: Bearer nope\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 407"), "{response}");
+
+    let response = connect(
+        cxt.proxy_socket_addr,
+        "Proxy-Authorization: Bearer token\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+}
+
+#[tokio::test]
+async fn test_client_acl() {
+    let cxt = TestContext::new().await;
+    cxt.proxy_manager.acl().set_rules(
+        ClientAclRules::new(vec!["10.0.0.0/8".parse().unwrap()]).with_record_rejected(true),
+    );
+
+    let mut stream = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+    let _ = stream
+        .write_all(b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n")
+        .await;
+    let mut buf = vec![0; 1024];
+    let read = timeout(Duration::from_millis(TIMEOUT), stream.read(&mut buf))
+        .await
+        .unwrap();
+    assert!(matches!(read, Ok(0) | Err(_)), "{read:?}");
+
+    assert_eq!(cxt.flow_store.flows.len(), 1);
+    for entry in cxt.flow_store.flows.iter() {
+        let flow = entry.value().read().await;
+        assert_eq!(
+            flow.error.as_ref().map(|e| e.message.as_str()),
+            Some("Client 127.0.0.1 is not allowed")
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_limits() {
+    let cxt = TestContext::new().await;
+    cxt.proxy_manager.limits().set_limits(Limits {
+        max_body_size: Some(5),
+        ..Limits::default()
+    });
+    let mut set = HashSet::new();
+    set.insert(HttpServers::H11);
+    let servers = HttpServers::start_set(set, &cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+    let s = &servers[0];
+    let client = ClientContext::builder()
+        .with_proxy(cxt.proxy_addr.clone())
+        .with_roxy_ca(cxt.roxy_ca.clone())
+        .with_alpns(vec![s.server.alpn()])
+        .build();
+
+    let req = http::Request::builder()
+        .method(Method::POST)
+        .uri(s.target.clone())
+        .header(HOST, s.target.host())
+        .body(BoxBody::new(Full::new(Bytes::from_static(b"too large"))))
+        .unwrap();
+    let response = timeout(Duration::from_millis(TIMEOUT), client.request(req))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.parts.status, 413);
+
+    // The server says hello in more than five bytes
+    let req = http::Request::builder()
+        .method(Method::GET)
+        .uri(s.target.clone())
+        .header(HOST, s.target.host())
+        .body(BoxBody::new(Empty::new()))
+        .unwrap();
+    let response = timeout(Duration::from_millis(TIMEOUT), client.request(req))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.parts.status, 502);
+
+    // Errors are recorded in the background
+    let mut errors = vec![];
+    for _ in 0..100 {
+        errors.clear();
+        for flow in cxt.flow_store.flows.iter() {
+            if let Some(error) = flow.value().read().await.error.clone() {
+                errors.push(error.message);
+            }
+        }
+        if errors.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    errors.sort();
+    assert_eq!(
+        errors,
+        vec![
+            "Request body larger than 5 bytes",
+            "Response body larger than 5 bytes"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_upstream_error_page() {
+    let cxt = TestContext::new().await;
+    // Nothing listens on the port once the listener is dropped
+    let closed = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let mut stream = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+    let request = format!(
+        "GET http://{closed}/missing HTTP/1.1\r\nHost: {closed}\r\nAccept: application/json\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let (head, body) = timeout(Duration::from_millis(TIMEOUT), read_response(&mut stream))
+        .await
+        .unwrap();
+    assert!(head.starts_with("HTTP/1.1 502"), "{head}");
+    assert!(
+        head.cow_to_ascii_lowercase()
+            .contains("x-roxy-error: connect"),
+        "{head}"
+    );
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["error"]["kind"], "connect");
+    assert_eq!(body["error"]["target"], format!("http://{closed}/missing"));
+
+    let mut recorded = None;
+    for _ in 0..100 {
+        if let Some(entry) = cxt.flow_store.flows.iter().next() {
+            let flow = entry.value().read().await;
+            if flow.error.is_some() {
+                recorded = Some((
+                    flow.error.clone().unwrap().kind,
+                    flow.response.as_ref().map(|r| r.status),
+                ));
+                break;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        recorded,
+        Some((FlowErrorKind::Connect, Some(StatusCode::BAD_GATEWAY)))
+    );
+}
+
+#[tokio::test]
+async fn test_client_connection_limit() {
+    let cxt = TestContext::new().await;
+    cxt.proxy_manager.limits().set_limits(Limits {
+        max_client_connections: Some(1),
+        ..Limits::default()
+    });
+    let h11 = HttpServers::H11
+        .start(&cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+    let target = h11.target.host_port();
+    let request = format!("GET http://{target}/ HTTP/1.1\r\nHost: {target}\r\n\r\n");
+
+    // The first connection is served and kept open
+    let mut first = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+    first.write_all(request.as_bytes()).await.unwrap();
+    let (head, _) = timeout(Duration::from_millis(TIMEOUT), read_response(&mut first))
+        .await
+        .unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+
+    // The second is turned away without reading a request
+    let mut second = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+    let (head, _) = timeout(Duration::from_millis(TIMEOUT), read_response(&mut second))
+        .await
+        .unwrap();
+    assert!(head.starts_with("HTTP/1.1 503"), "{head}");
+
+    // Closing the first gives its slot back
+    drop(first);
+    let mut served = false;
+    for _ in 0..100 {
+        let mut next = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+        next.write_all(request.as_bytes()).await.unwrap();
+        let (head, _) = timeout(Duration::from_millis(TIMEOUT), read_response(&mut next))
+            .await
+            .unwrap();
+        if head.starts_with("HTTP/1.1 200") {
+            served = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(served);
+}
+
+#[tokio::test]
+async fn test_passthrough_host() {
+    let cxt = TestContext::new().await;
+    cxt.proxy_manager
+        .host_filter()
+        .set_rules(HostFilterRules::new(vec![], vec!["*".into()]));
+
+    let set = [HttpServers::H10S, HttpServers::H11S, HttpServers::H2]
+        .into_iter()
+        .collect();
+    let servers = HttpServers::start_set(set, &cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+
+    for s in &servers {
+        let req = http::Request::builder()
+            .method(Method::GET)
+            .version(s.server.version())
+            .uri(s.target.clone())
+            .header(HOST, s.target.host())
+            .body(BoxBody::new(Empty::new()))
+            .unwrap();
+
+        let client = ClientContext::builder()
+            .with_proxy(cxt.proxy_addr.clone())
+            .with_roxy_ca(cxt.roxy_ca.clone())
+            .with_alpns(vec![s.server.alpn()])
+            .build();
+
+        let HttpResponse { parts, body, .. } =
+            timeout(Duration::from_millis(TIMEOUT), client.request(req))
+                .await
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(parts.status, 200);
+        assert_eq!(body, format!("Hello, {}", s.server.marker()));
+    }
+
+    assert_eq!(cxt.flow_store.flows.len(), servers.len());
+    for entry in cxt.flow_store.flows.iter() {
+        let flow = entry.value().read().await;
+        assert!(flow.passthrough);
+        assert!(flow.response.is_none());
+        assert_eq!(flow.request.as_ref().unwrap().method, Method::CONNECT);
+    }
+}
+
+#[tokio::test]
+async fn test_replay_flow() {
+    let cxt = TestContext::new().await;
+    let mut set = HttpServers::set_all();
+    set.remove(&HttpServers::H3);
+    let servers = HttpServers::start_set(set, &cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+
+    for s in &servers {
+        let req = http::Request::builder()
+            .method(Method::GET)
+            .version(s.server.version())
+            .uri(s.target.clone())
+            .header(HOST, s.target.host())
+            .body(BoxBody::new(Empty::new()))
+            .unwrap();
+
+        let client = ClientContext::builder()
+            .with_proxy(cxt.proxy_addr.clone())
+            .with_roxy_ca(cxt.roxy_ca.clone())
+            .with_alpns(vec![s.server.alpn()])
+            .build();
+
+        timeout(Duration::from_millis(TIMEOUT), client.request(req))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let original_id = *cxt.flow_store.ordered_ids.read().await.last().unwrap();
+        let replay_id = timeout(
+            Duration::from_millis(TIMEOUT),
+            cxt.proxy_manager.replay(original_id),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_ne!(original_id, replay_id);
+
+        let original = cxt.flow_store.get_flow_by_id(original_id).await.unwrap();
+        let replayed = cxt.flow_store.get_flow_by_id(replay_id).await.unwrap();
+        let original = original.read().await;
+        let replayed = replayed.read().await;
+
+        let original_request = original.request.as_ref().unwrap();
+        let replayed_request = replayed.request.as_ref().unwrap();
+        assert_eq!(replayed_request.uri, original_request.uri);
+        assert_eq!(replayed_request.method, original_request.method);
+        assert_eq!(replayed_request.headers, original_request.headers);
+        assert!(original_request.timestamp < replayed_request.timestamp);
+    }
+
+    assert_eq!(cxt.flow_store.flows.len(), servers.len() * 2);
+    assert!(matches!(
+        cxt.proxy_manager.replay(FlowId(0)).await,
+        Err(ReplayError::NotFound(FlowId(0)))
+    ));
+}
+
+#[tokio::test]
+async fn test_run_scenario() {
+    let cxt = TestContext::new().await;
+    let s = HttpServers::H11
+        .start(&cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+    let client = ClientContext::builder()
+        .with_proxy(cxt.proxy_addr.clone())
+        .with_roxy_ca(cxt.roxy_ca.clone())
+        .build();
+    for _ in 0..3 {
+        let req = http::Request::builder()
+            .method(Method::GET)
+            .uri(s.target.clone())
+            .header(HOST, s.target.host())
+            .body(BoxBody::new(Empty::new()))
+            .unwrap();
+        timeout(Duration::from_millis(TIMEOUT), client.request(req))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    // Responses are recorded in the background
+    let mut steps = vec![];
+    for _ in 0..100 {
+        let mut flows = vec![];
+        for flow in cxt.flow_store.flows.iter() {
+            let flow = flow.value().read().await;
+            if flow.response.is_some() {
+                flows.extend(ScenarioStep::from_flow(&flow));
+            }
+        }
+        if flows.len() == 3 {
+            steps = flows;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(steps.len(), 3);
+    if let Some(response) = steps[2].response.as_mut() {
+        response.status = StatusCode::CREATED;
+    }
+
+    let scenario = Scenario::new(steps).with_concurrency(2);
+    let report = timeout(
+        Duration::from_millis(TIMEOUT),
+        cxt.proxy_manager.run_scenario(scenario),
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.steps.len(), 3);
+    assert!(!report.passed());
+    assert!(report.steps[..2].iter().all(|s| s.passed()));
+
+    let failed: Vec<_> = report.failures().collect();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].expected_status, Some(StatusCode::CREATED));
+    assert_eq!(failed[0].status, Some(StatusCode::OK));
+    assert!(failed[0].diff.fields.iter().any(|f| f.name == "status"));
+    assert!(report.steps.iter().all(|s| s.replay_id.is_some()));
+    assert_eq!(cxt.flow_store.flows.len(), 6);
+}
+
+#[tokio::test]
+async fn test_replay_imported_har() {
+    let cxt = TestContext::new().await;
+    let s = HttpServers::H11
+        .start(&cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+    cxt.proxy_manager.rewrites().set_rules(vec![
+        RewriteRule::new(Some("^staging\\.example\\.com$"), None)
+            .unwrap()
+            .with_host(s.target.host())
+            .with_port(s.target.port()),
+    ]);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("recorded.har");
+    let har = r#"{"log": {"entries": [{
+        "startedDateTime": "2024-05-01T10:00:00.000Z",
+        "time": 12,
+        "request": {
+            "method": "GET",
+            "url": "http://staging.example.com/",
+            "httpVersion": "HTTP/1.1",
+            "headers": [{"name": "Host", "value": "staging.example.com"}]
+        },
+        "response": {"status": 200, "httpVersion": "HTTP/1.1", "headers": [], "content": {}}
+    }]}}"#;
+    std::fs::write(&path, har).unwrap();
+
+    assert_eq!(cxt.flow_store.import_har(&path).await.unwrap(), 1);
+    let imported_id = *cxt.flow_store.ordered_ids.read().await.last().unwrap();
+    let replay_id = timeout(
+        Duration::from_millis(TIMEOUT),
+        cxt.proxy_manager.replay(imported_id),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    let replayed = cxt.flow_store.get_flow_by_id(replay_id).await.unwrap();
+    let mut body = None;
+    for _ in 0..100 {
+        if let Some(response) = &replayed.read().await.response {
+            body = Some(response.body.clone());
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(body.unwrap(), format!("Hello, {}", s.server.marker()));
+
+    let replayed = replayed.read().await;
+    let request = replayed.request.as_ref().unwrap();
+    assert_eq!(request.uri.host(), s.target.host());
+    assert_eq!(request.uri.port(), s.target.port());
+}
+
 #[tokio::test]
 async fn test_http_get_asset() {
     let cxt = TestContext::new().await;
@@ -751,12 +1711,171 @@ async fn test_http_proxy_chunked() {
             .unwrap();
 
         assert_eq!(parts.status, 200);
-        assert_eq!(parts.version, s.server.version());
-        assert_eq!(
-            body,
-            format!("Hello, {}, pong {}", s.server.marker(), body_str)
-        );
-        assert!(trailers.is_none());
+        assert_eq!(parts.version, s.server.version());
+        assert_eq!(
+            body,
+            format!("Hello, {}, pong {}", s.server.marker(), body_str)
+        );
+        assert!(trailers.is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_skip_bodies() {
+    let cxt = TestContext::new().await;
+    cxt.proxy_manager
+        .host_filter()
+        .set_rules(HostFilterRules::default().with_skip_bodies(vec!["*".into()]));
+    let mut set = HashSet::new();
+    set.insert(HttpServers::H11);
+    set.insert(HttpServers::H11S);
+    let servers = HttpServers::start_set(set, &cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+
+    for s in &servers {
+        let mut parts = s.target.inner.clone().into_parts();
+        parts.path_and_query = Some(http::uri::PathAndQuery::from_static("/chunked"));
+        let body_str = OffsetDateTime::now_utc().to_string();
+
+        let req = http::Request::builder()
+            .method(Method::POST)
+            .version(s.server.version())
+            .uri(Uri::from_parts(parts).unwrap())
+            .header(HOST, s.target.host())
+            .body(BoxBody::new(Full::new(Bytes::from(body_str.clone()))))
+            .unwrap();
+
+        let client = ClientContext::builder()
+            .with_proxy(cxt.proxy_addr.clone())
+            .with_roxy_ca(cxt.roxy_ca.clone())
+            .with_alpns(vec![s.server.alpn()])
+            .build();
+
+        let HttpResponse { parts, body, .. } =
+            timeout(Duration::from_millis(TIMEOUT), client.request(req))
+                .await
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(parts.status, 200);
+        assert_eq!(
+            body,
+            format!("Hello, {}, pong {}", s.server.marker(), body_str)
+        );
+    }
+
+    // Flows are completed in the background
+    let mut complete = 0;
+    for _ in 0..100 {
+        complete = 0;
+        for flow in cxt.flow_store.flows.iter() {
+            if flow.value().read().await.timing.response_complete.is_some() {
+                complete += 1;
+            }
+        }
+        if complete == servers.len() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(complete, servers.len());
+    for entry in cxt.flow_store.flows.iter() {
+        let flow = entry.value().read().await;
+        assert_eq!(flow.request.as_ref().unwrap().method, Method::POST);
+        assert!(flow.request.as_ref().unwrap().body.is_empty());
+        let response = flow.response.as_ref().unwrap();
+        assert_eq!(response.status, 200);
+        assert!(response.body.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_event_stream() {
+    let cxt = TestContext::new().await;
+    let mut set = HashSet::new();
+    set.insert(HttpServers::H11);
+    set.insert(HttpServers::H11S);
+    set.insert(HttpServers::H2);
+    let servers = HttpServers::start_set(set, &cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+
+    for s in &servers {
+        let mut parts = s.target.inner.clone().into_parts();
+        parts.path_and_query = Some(http::uri::PathAndQuery::from_static("/events"));
+        let req = http::Request::builder()
+            .method(Method::GET)
+            .version(s.server.version())
+            .uri(Uri::from_parts(parts).unwrap())
+            .header(HOST, s.target.host())
+            .body(BoxBody::new(Empty::new()))
+            .unwrap();
+
+        let client = ClientContext::builder()
+            .with_proxy(cxt.proxy_addr.clone())
+            .with_roxy_ca(cxt.roxy_ca.clone())
+            .with_alpns(vec![s.server.alpn()])
+            .build();
+
+        let HttpStreamResponse::EventStream(parts, mut body) =
+            timeout(Duration::from_millis(TIMEOUT), client.request_stream(req))
+                .await
+                .unwrap()
+                .unwrap()
+        else {
+            panic!("Expected an event stream");
+        };
+        assert_eq!(parts.status, 200);
+
+        // The first event is visible in the flow before the stream ends
+        let first = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        let expected = format!("id: 0\ndata: {}\n\n", s.server.marker());
+        assert!(first.starts_with(expected.as_bytes()));
+        let id = *cxt.flow_store.ordered_ids.read().await.last().unwrap();
+        let mut rx = cxt.flow_store.subscribe();
+        timeout(Duration::from_millis(TIMEOUT), async {
+            loop {
+                let flow = cxt.flow_store.get_flow_by_id(id).await.unwrap();
+                let flow = flow.read().await;
+                if let Some(response) = &flow.response
+                    && response.body.starts_with(expected.as_bytes())
+                {
+                    assert!(flow.timing.response_complete.is_none());
+                    break;
+                }
+                drop(flow);
+                rx.changed().await.unwrap();
+            }
+        })
+        .await
+        .unwrap();
+
+        let mut received = first.to_vec();
+        while let Some(frame) = body.frame().await {
+            if let Ok(data) = frame.unwrap().into_data() {
+                received.extend_from_slice(&data);
+            }
+        }
+        let all = (0..EVENT_COUNT)
+            .map(|i| format!("id: {i}\ndata: {}\n\n", s.server.marker()))
+            .collect::<String>();
+        assert_eq!(received, all.as_bytes());
+
+        timeout(Duration::from_millis(TIMEOUT), async {
+            loop {
+                let flow = cxt.flow_store.get_flow_by_id(id).await.unwrap();
+                let flow = flow.read().await;
+                if flow.timing.response_complete.is_some() {
+                    assert_eq!(flow.response.as_ref().unwrap().body, all);
+                    break;
+                }
+                drop(flow);
+                rx.changed().await.unwrap();
+            }
+        })
+        .await
+        .unwrap();
     }
 }
 
@@ -866,6 +1985,102 @@ async fn test_early_return_with_body() {
     }
 }
 
+static UNAVAILABLE_EVERY_OTHER: &str = r#"
+local count = 0
+Extensions = {
+  {
+  function (flow)
+    count = count + 1
+    if count % 2 == 1 then
+      flow.response.status = Status.SERVICE_UNAVAILABLE
+    end
+  end,
+  },
+}
+"#;
+
+#[tokio::test]
+async fn test_client_retries() {
+    let mut cxt = TestContext::new().await;
+    cxt.set_script(UNAVAILABLE_EVERY_OTHER).await.unwrap();
+    let servers = HttpServers::start_all(&cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+
+    for s in &servers {
+        let req = http::Request::builder()
+            .method(Method::GET)
+            .version(s.server.version())
+            .uri(s.target.clone())
+            .header(HOST, s.target.host())
+            .body(BoxBody::new(Empty::new()))
+            .unwrap();
+
+        let client = ClientContext::builder()
+            .with_proxy(cxt.proxy_addr.clone())
+            .with_roxy_ca(cxt.roxy_ca.clone())
+            .with_alpns(vec![s.server.alpn()])
+            .with_retries(RetryPolicy {
+                base_delay: Duration::from_millis(10),
+                ..Default::default()
+            })
+            .build();
+
+        let HttpResponse { parts, body, .. } =
+            timeout(Duration::from_millis(TIMEOUT), client.request(req))
+                .await
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(parts.version, s.server.version());
+        assert_eq!(parts.status, 200);
+        assert_eq!(body, format!("Hello, {}", s.server.marker()));
+    }
+
+    // Each server was answered with a 503 first, then the retry went through
+    assert_eq!(cxt.flow_store.flows.len(), servers.len() * 2);
+}
+
+#[tokio::test]
+async fn test_h3_connection_reuse() {
+    let cxt = TestContext::new().await;
+    let s = HttpServers::H3
+        .start(&cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+
+    let pool = ConnectionPool::new();
+    let client = ClientContext::builder()
+        .with_proxy(cxt.proxy_addr.clone())
+        .with_roxy_ca(cxt.roxy_ca.clone())
+        .with_alpns(vec![s.server.alpn()])
+        .with_pool(pool.clone())
+        .build();
+
+    for _ in 0..2 {
+        let req = http::Request::builder()
+            .method(Method::GET)
+            .version(Version::HTTP_3)
+            .uri(s.target.clone())
+            .body(BoxBody::new(Empty::new()))
+            .unwrap();
+
+        let HttpResponse { parts, body, .. } =
+            timeout(Duration::from_millis(TIMEOUT), client.request(req))
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(parts.status, 200);
+        assert_eq!(body, format!("Hello, {}", s.server.marker()));
+    }
+
+    // The second request went over the QUIC connection of the first
+    let stats = pool.stats();
+    assert_eq!(stats.opened, 1);
+    assert_eq!(stats.reused, 1);
+    assert_eq!(cxt.flow_store.flows.len(), 2);
+}
+
 static GSUB_BODY_SCRIPT: &str = r#"
 function req(flow) 
     flow.request.body.text = string.gsub(flow.request.body.text, "replaceme", "gone")
@@ -1150,6 +2365,149 @@ Extensions = {
     }
 }
 
+#[tokio::test]
+async fn test_script_log_on_flow() {
+    let script = r#"
+Extensions = {
+  {
+    request = function(flow)
+      Roxy.notify(1, "request " .. flow.request.url.path)
+    end,
+    response = function(flow)
+      Roxy.notify(2, "response " .. flow.response.status)
+    end,
+  }
+}
+"#;
+    let mut cxt = TestContext::new().await;
+    cxt.set_script(script).await.unwrap();
+    let servers = HttpServers::start_all(&cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+    let s = &servers[0];
+    let req = http::Request::builder()
+        .method(Method::GET)
+        .version(s.server.version())
+        .uri(format!("{}/", s.target.host_port_scheme()))
+        .body(BoxBody::new(Empty::new()))
+        .unwrap();
+    let client = ClientContext::builder()
+        .with_proxy(cxt.proxy_addr.clone())
+        .with_roxy_ca(cxt.roxy_ca.clone())
+        .build();
+    timeout(Duration::from_millis(TIMEOUT), client.request(req))
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Logged in the background once the response is intercepted
+    let mut log = vec![];
+    for _ in 0..100 {
+        if let Some(flow) = cxt.flow_store.flows.iter().next() {
+            log = flow.value().read().await.script_log.clone();
+        }
+        if log.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let messages: Vec<_> = log.iter().map(|l| l.msg.as_str()).collect();
+    assert_eq!(messages, vec!["request /", "response 200"]);
+    assert_eq!(log[1].level, FlowNotifyLevel::Error);
+}
+
+#[tokio::test]
+async fn test_flow_timing_waterfall() {
+    let cxt = TestContext::new().await;
+    let set = [HttpServers::H11S].into_iter().collect();
+    let servers = HttpServers::start_set(set, &cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+    let s = &servers[0];
+    let req = http::Request::builder()
+        .method(Method::GET)
+        .uri(format!("{}/", s.target.host_port_scheme()))
+        .body(BoxBody::new(Empty::new()))
+        .unwrap();
+    let client = ClientContext::builder()
+        .with_proxy(cxt.proxy_addr.clone())
+        .with_roxy_ca(cxt.roxy_ca.clone())
+        .build();
+    timeout(Duration::from_millis(TIMEOUT), client.request(req))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut timing = None;
+    for _ in 0..100 {
+        if let Some(flow) = cxt.flow_store.flows.iter().next() {
+            let flow = flow.value().read().await;
+            if flow.timing.response_complete.is_some() {
+                timing = Some(flow.timing.clone());
+                break;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let timing = timing.unwrap();
+    assert!(timing.client_conn_established.is_some());
+    assert!(timing.client_conn_tls_handshake.is_some());
+    assert!(timing.server_conn_initiated.is_some());
+    assert!(timing.first_request_bytes.is_some());
+
+    let phases = timing.waterfall();
+    let names: Vec<_> = phases.iter().map(|p| p.name).collect();
+    assert_eq!(
+        names,
+        vec!["intercept", "dns", "connect", "tls", "waiting", "download"]
+    );
+    assert!(phases.iter().all(|p| p.end >= p.start));
+}
+
+#[tokio::test]
+async fn test_rewrite_rule_path() {
+    let cxt = TestContext::new().await;
+    cxt.proxy_manager.rewrites().set_rules(vec![
+        RewriteRule::new(None, Some("^/missing$"))
+            .unwrap()
+            .with_path("/"),
+    ]);
+    let servers = HttpServers::start_all(&cxt.roxy_ca, &cxt.tls_config)
+        .await
+        .unwrap();
+
+    for s in &servers {
+        let target_uri: RUri = format!("{}/missing", s.target.host_port_scheme())
+            .parse()
+            .unwrap();
+        let req = http::Request::builder()
+            .method(Method::GET)
+            .version(s.server.version())
+            .uri(target_uri.clone())
+            .body(BoxBody::new(Empty::new()))
+            .unwrap();
+
+        let client = ClientContext::builder()
+            .with_proxy(cxt.proxy_addr.clone())
+            .with_roxy_ca(cxt.roxy_ca.clone())
+            .build();
+
+        let HttpResponse { parts, body, .. } =
+            timeout(Duration::from_millis(TIMEOUT), client.request(req))
+                .await
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(parts.status, 200);
+        assert_eq!(body, format!("Hello, {}", s.server.marker()));
+    }
+
+    for entry in cxt.flow_store.flows.iter() {
+        let flow = entry.value().read().await;
+        assert_eq!(flow.request.as_ref().unwrap().uri.path(), "/");
+    }
+}
+
 #[tokio::test]
 async fn test_add_header_http_request() {
     let script = r#"
@@ -1334,6 +2692,199 @@ async fn ws_test() {
     handle.abort();
 }
 
+#[tokio::test]
+async fn ws_inject_and_replay() {
+    let cxt = TestContext::new().await;
+
+    let tcp = local_tcp_listener(None).await.unwrap();
+    let port = tcp.local_addr().unwrap().port();
+    let handle = start_ws_server(tcp).await.unwrap();
+    let target_host = format!("127.0.0.1:{port}");
+
+    let mut proxy_stream = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+    let connect_req = format!("CONNECT {target_host} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+    proxy_stream
+        .write_all(connect_req.as_bytes())
+        .await
+        .unwrap();
+    let mut buf = [0u8; 4096];
+    let n = proxy_stream.read(&mut buf).await.unwrap();
+    assert!(String::from_utf8_lossy(&buf[..n]).contains("200 "));
+
+    let (mut ws_stream, _) = client_async(format!("ws://{target_host}/chat"), proxy_stream)
+        .await
+        .unwrap();
+    ws_stream.send(Message::text("first")).await.unwrap();
+    assert_eq!(
+        ws_stream.next().await.unwrap().unwrap(),
+        Message::text("hello")
+    );
+
+    let id = *cxt.flow_store.ordered_ids.read().await.last().unwrap();
+    let sessions = cxt.proxy_manager.ws_sessions();
+    assert!(sessions.is_live(id));
+    assert!(sessions.inject(id, WsDirection::Server, Message::text("injected")));
+    assert_eq!(
+        ws_stream.next().await.unwrap().unwrap(),
+        Message::text("injected")
+    );
+    assert!(sessions.inject(id, WsDirection::Client, Message::text("second")));
+    assert_eq!(
+        ws_stream.next().await.unwrap().unwrap(),
+        Message::text("hello")
+    );
+
+    let replay_id = timeout(Duration::from_secs(5), cxt.proxy_manager.replay(id))
+        .await
+        .unwrap()
+        .unwrap();
+    let replayed = cxt.flow_store.get_flow_by_id(replay_id).await.unwrap();
+    for _ in 0..100 {
+        if replayed.read().await.messages.len() == 5 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let replayed = replayed.read().await;
+    assert_eq!(
+        replayed.request.as_ref().unwrap().uri.to_string(),
+        format!("ws://{target_host}/chat")
+    );
+    let sent: Vec<_> = replayed
+        .messages
+        .iter()
+        .filter(|m| m.direction == WsDirection::Client)
+        .map(|m| m.message.clone())
+        .collect();
+    assert_eq!(
+        sent,
+        vec![
+            Message::text("first"),
+            Message::text("second"),
+            Message::Close(None)
+        ]
+    );
+    assert_eq!(
+        replayed
+            .messages
+            .iter()
+            .filter(|m| m.direction == WsDirection::Server)
+            .count(),
+        2
+    );
+
+    drop(ws_stream);
+    handle.abort();
+    assert!(!sessions.inject(replay_id, WsDirection::Client, Message::text("late")));
+}
+
+/// Speaks first like SMTP: sends a banner, echoes one read and closes.
+async fn start_banner_server(tcp: TcpListener) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = tcp.accept().await {
+            tokio::spawn(async move {
+                stream.write_all(b"220 ready\r\n").await.unwrap();
+                let mut buf = [0u8; 64];
+                let n = stream.read(&mut buf).await.unwrap();
+                stream.write_all(&buf[..n]).await.unwrap();
+            });
+        }
+    })
+}
+
+#[tokio::test]
+async fn raw_tunnel_test() {
+    let cxt = TestContext::new().await;
+    let tcp = local_tcp_listener(None).await.unwrap();
+    let port = tcp.local_addr().unwrap().port();
+    let handle = start_banner_server(tcp).await;
+
+    // Silent until the banner, then a client that sends binary right away
+    for first in [None, Some(&b"\x00\x01ping"[..])] {
+        let mut stream = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+        let connect_req = format!("CONNECT 127.0.0.1:{port} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+        stream.write_all(connect_req.as_bytes()).await.unwrap();
+        let mut buf = [0u8; 4096];
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(String::from_utf8_lossy(&buf[..n]).contains("200 "));
+
+        let sent = first.unwrap_or(b"QUIT\r\n");
+        if first.is_some() {
+            stream.write_all(sent).await.unwrap();
+        }
+        let mut banner = [0u8; 11];
+        stream.read_exact(&mut banner).await.unwrap();
+        assert_eq!(&banner, b"220 ready\r\n");
+        if first.is_none() {
+            stream.write_all(sent).await.unwrap();
+        }
+        let mut echoed = vec![];
+        timeout(
+            Duration::from_millis(TIMEOUT),
+            stream.read_to_end(&mut echoed),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(echoed, sent);
+        drop(stream);
+
+        let id = *cxt.flow_store.ordered_ids.read().await.last().unwrap();
+        let flow = cxt.flow_store.get_flow_by_id(id).await.unwrap();
+        for _ in 0..100 {
+            if flow.read().await.timing.client_conn_closed.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let flow = flow.read().await;
+        assert!(flow.error.is_none(), "{:?}", flow.error);
+        let tunnel = flow.tunnel.as_ref().unwrap();
+        assert!(!tunnel.tls);
+        assert_eq!(tunnel.bytes(&WsDirection::Client), sent);
+        let mut from_server = b"220 ready\r\n".to_vec();
+        from_server.extend_from_slice(sent);
+        assert_eq!(tunnel.bytes(&WsDirection::Server), from_server);
+    }
+    handle.abort();
+}
+
+#[tokio::test]
+async fn unknown_protocol_passthrough_test() {
+    let cxt = TestContext::new().await;
+    cxt.proxy_manager
+        .host_filter()
+        .set_rules(HostFilterRules::default().with_unknown_protocol(UnknownProtocol::Passthrough));
+    let tcp = local_tcp_listener(None).await.unwrap();
+    let port = tcp.local_addr().unwrap().port();
+    let handle = start_banner_server(tcp).await;
+
+    let mut stream = TcpStream::connect(cxt.proxy_socket_addr).await.unwrap();
+    let connect_req = format!("CONNECT 127.0.0.1:{port} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n");
+    stream.write_all(connect_req.as_bytes()).await.unwrap();
+    let mut buf = [0u8; 4096];
+    let n = stream.read(&mut buf).await.unwrap();
+    assert!(String::from_utf8_lossy(&buf[..n]).contains("200 "));
+
+    stream.write_all(b"\x00\x01ping").await.unwrap();
+    let mut echoed = vec![];
+    timeout(
+        Duration::from_millis(TIMEOUT),
+        stream.read_to_end(&mut echoed),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(echoed, b"220 ready\r\n\x00\x01ping");
+
+    let id = *cxt.flow_store.ordered_ids.read().await.last().unwrap();
+    let flow = cxt.flow_store.get_flow_by_id(id).await.unwrap();
+    let flow = flow.read().await;
+    assert!(flow.passthrough);
+    assert!(flow.tunnel.is_none());
+    handle.abort();
+}
+
 #[tokio::test]
 async fn wss_test() {
     let cxt = TestContext::new().await;
@@ -1463,3 +3014,50 @@ async fn test_wt() {
         .await
         .unwrap();
 }
+
+#[tokio::test]
+async fn test_connect_udp_datagrams() {
+    let cxt = TestContext::new().await;
+
+    let echo = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let echo_port = echo.local_addr().unwrap().port();
+    let echo_handle = tokio::spawn(async move {
+        let mut buf = [0u8; 1500];
+        while let Ok((len, peer)) = echo.recv_from(&mut buf).await {
+            let _ = echo.send_to(&buf[..len], peer).await;
+        }
+    });
+
+    let target: RUri = format!("https://127.0.0.1:{echo_port}").parse().unwrap();
+    let tunnel = connect_udp(&cxt.proxy_addr, &target, cxt.roxy_ca.roots())
+        .await
+        .unwrap();
+
+    // Not QUIC, so relayed even though the host is intercepted
+    tunnel.send(b"ping").await.unwrap();
+    let echoed = timeout(Duration::from_millis(TIMEOUT), tunnel.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(echoed, "ping");
+
+    let mut messages = vec![];
+    for _ in 0..100 {
+        if let Some(flow) = cxt.flow_store.flows.iter().next() {
+            messages = flow.value().read().await.messages.clone();
+        }
+        if messages.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].direction, WsDirection::Client);
+    assert_eq!(
+        messages[0].message,
+        Message::Binary(Bytes::from_static(b"ping"))
+    );
+    assert_eq!(messages[1].direction, WsDirection::Server);
+
+    echo_handle.abort();
+}
@@ -0,0 +1,16 @@
+/// <reference path="../../script_libs/js/index.d.ts" />
+/** @type {Extension} */
+const connection_hooks = {
+  on_client_connect(conn) {
+    return conn.client_ip !== "10.0.0.1";
+  },
+  on_server_connect(conn) {
+    if (conn.host === "blocked.example.com") {
+      return false;
+    }
+  },
+  on_tls_established(conn) {
+    return conn.tls.sni !== "blocked.example.com";
+  }
+}
+globalThis.extensions = [connection_hooks];
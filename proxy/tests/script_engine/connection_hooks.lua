@@ -0,0 +1,16 @@
+pcall(require, "../../script_libs/lua/roxy.lua")
+---@type Extension
+local connection_hooks = {
+	on_client_connect = function(conn)
+		return conn.client_ip ~= "10.0.0.1"
+	end,
+	on_server_connect = function(conn)
+		if conn.host == "blocked.example.com" then
+			return false
+		end
+	end,
+	on_tls_established = function(conn)
+		return conn.tls.sni ~= "blocked.example.com"
+	end,
+}
+Extensions = { connection_hooks }
@@ -0,0 +1,16 @@
+from roxy import Extension
+
+
+class ConnectionHooks(Extension):
+    def on_client_connect(self, conn):
+        return conn.client_ip != "10.0.0.1"
+
+    def on_server_connect(self, conn):
+        if conn.host == "blocked.example.com":
+            return False
+
+    def on_tls_established(self, conn):
+        return conn.tls.sni != "blocked.example.com"
+
+
+Extensions = [ConnectionHooks()]
@@ -0,0 +1,6 @@
+// Served by the test servers as text/javascript
+const greeting = "hello";
+
+export function greet(name) {
+  return `${greeting} ${name}`;
+}
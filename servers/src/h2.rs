@@ -56,6 +56,34 @@ pub async fn h2_server_listener(
 
     Ok((addr, h))
 }
+
+/// HTTP/2 without TLS, for clients with prior knowledge.
+pub async fn h2c_server(
+    server: HttpServers,
+) -> Result<(SocketAddr, JoinHandle<()>), Box<dyn Error>> {
+    let tcp_listener = local_tcp_listener(None).await?;
+    let addr = tcp_listener.local_addr()?;
+    let h = tokio::spawn(async move {
+        info!("{server} h2c listening on {}", addr);
+        while let Ok((stream, _addr)) = tcp_listener.accept().await {
+            info!("{server} accepting request from {_addr}");
+            tokio::task::spawn(async move {
+                if let Err(err) = H2ServerBuilder::new(TokioExecutor::new())
+                    .serve_connection(
+                        TokioIo::new(stream),
+                        service_fn(|req| crate::serve::serve(req, server)),
+                    )
+                    .await
+                {
+                    error!("{server} server error: {err:?}");
+                }
+            });
+        }
+        warn!("{server} stopped");
+    });
+    Ok((addr, h))
+}
+
 pub async fn h2_h1_server(
     roxy_ca: &RoxyCA,
     server: HttpServers,
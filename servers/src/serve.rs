@@ -1,4 +1,4 @@
-use std::convert::Infallible;
+use std::{convert::Infallible, time::Duration};
 
 use bytes::Bytes;
 use http::{
@@ -7,18 +7,24 @@ use http::{
     request::Parts,
 };
 use http_body_util::{BodyExt, Empty, Full, combinators::BoxBody};
+use hyper::body::Frame;
 use itertools::Itertools;
 use roxy_shared::{
-    body::BufferedBody,
+    body::{BufferedBody, ChannelBody},
     content::{
-        decode_body, encode_body, ext_to_content_type, get_accept_enconding, get_content_encoding,
+        ContentType, decode_body, encode_body, ext_to_content_type, get_accept_enconding,
+        get_content_encoding,
     },
 };
+use tokio::sync::mpsc;
 use tracing::{debug, info};
 use url::Url;
 
 use crate::{HttpServers, load_asset};
 
+/// Events sent by `/events`, one every 50ms.
+pub const EVENT_COUNT: usize = 3;
+
 pub async fn serve(
     request: Request<hyper::body::Incoming>,
     server: HttpServers,
@@ -59,6 +65,7 @@ pub async fn serve_internal(
     match path {
         "/chunked" => handle_chunked(body, server),
         "/trailers" => handle_trailers(),
+        "/events" => handle_events(server),
         "/compress" => handle_compress(parts, body, trailers, server),
         "/cookies" => handle_cookie(parts, body, trailers, server),
         "/query" => handle_query(parts, body, trailers, server),
@@ -86,6 +93,23 @@ fn handle_trailers() -> http::Result<Response<BoxBody<Bytes, Infallible>>> {
         .body(BoxBody::new(body))
 }
 
+fn handle_events(server: HttpServers) -> http::Result<Response<BoxBody<Bytes, Infallible>>> {
+    let (tx, rx) = mpsc::channel(1);
+    let marker = server.marker().to_string();
+    tokio::spawn(async move {
+        for id in 0..EVENT_COUNT {
+            let event = format!("id: {id}\ndata: {marker}\n\n");
+            if tx.send(Frame::data(Bytes::from(event))).await.is_err() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    });
+    Response::builder()
+        .header(CONTENT_TYPE, ContentType::EventStream.to_default_str())
+        .body(ChannelBody::new(rx))
+}
+
 fn handle_chunked(
     body: Bytes,
     server: HttpServers,
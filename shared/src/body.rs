@@ -7,6 +7,7 @@ use hyper::body::{Body, Frame, SizeHint};
 use std::convert::Infallible;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use tokio::sync::mpsc;
 use tracing::error;
 
 use pin_project_lite::pin_project;
@@ -138,6 +139,126 @@ impl Body for BufferedBody {
     }
 }
 
+/// A body fed one frame at a time, it ends when the sender is dropped.
+pub struct ChannelBody {
+    rx: mpsc::Receiver<Frame<Bytes>>,
+}
+
+impl ChannelBody {
+    pub fn new(rx: mpsc::Receiver<Frame<Bytes>>) -> BoxBody<Bytes, Infallible> {
+        BoxBody::new(Self { rx })
+    }
+}
+
+impl Body for ChannelBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.get_mut().rx.poll_recv(cx).map(|frame| frame.map(Ok))
+    }
+}
+
+/// How a [`PassBody`] finished.
+#[derive(Debug)]
+pub enum PassEnd {
+    /// Every frame was passed on, with the trailers if there were any.
+    Complete(Option<HeaderMap>),
+    /// The body failed or was dropped before it ended.
+    Failed(String),
+}
+
+type OnEnd = Box<dyn FnOnce(PassEnd) + Send + Sync>;
+
+/// Hands the frames of another body on as they are polled, without buffering
+/// or copying them. An error ends the body early, the side receiving it sees
+/// a body cut short. `on_end` is told how it finished, once.
+pub struct PassBody<B> {
+    body: B,
+    trailers: Option<HeaderMap>,
+    on_end: Option<OnEnd>,
+}
+
+impl<B> PassBody<B>
+where
+    B: Body<Data = Bytes> + Unpin + Send + Sync + 'static,
+    B::Error: std::fmt::Display,
+{
+    pub fn new(
+        body: B,
+        on_end: impl FnOnce(PassEnd) + Send + Sync + 'static,
+    ) -> BoxBody<Bytes, Infallible> {
+        BoxBody::new(Self {
+            body,
+            trailers: None,
+            on_end: Some(Box::new(on_end)),
+        })
+    }
+}
+
+impl<B> PassBody<B> {
+    fn end(&mut self, end: PassEnd) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(end);
+        }
+    }
+}
+
+impl<B> Body for PassBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+    B::Error: std::fmt::Display,
+{
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let me = self.get_mut();
+        match std::task::ready!(Pin::new(&mut me.body).poll_frame(cx)) {
+            Some(Ok(frame)) => {
+                if let Some(trailers) = frame.trailers_ref() {
+                    me.trailers = Some(trailers.clone());
+                }
+                // The receiver may stop polling once the end is reported
+                if me.body.is_end_stream() {
+                    let trailers = me.trailers.take();
+                    me.end(PassEnd::Complete(trailers));
+                }
+                Poll::Ready(Some(Ok(frame)))
+            }
+            Some(Err(err)) => {
+                me.end(PassEnd::Failed(err.to_string()));
+                Poll::Ready(None)
+            }
+            None => {
+                let trailers = me.trailers.take();
+                me.end(PassEnd::Complete(trailers));
+                Poll::Ready(None)
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+impl<B> Drop for PassBody<B> {
+    fn drop(&mut self) {
+        self.end(PassEnd::Failed("body dropped before it ended".to_string()));
+    }
+}
+
 pub fn create_http_body(
     body: Bytes,
     encoding: Option<Vec<Encodings>>,
@@ -159,3 +280,47 @@ pub fn create_http_body(
         None => BoxBody::new(Full::new(body)),
     }
 }
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    fn recorder() -> (Arc<Mutex<Vec<PassEnd>>>, impl FnOnce(PassEnd) + Send + Sync) {
+        let ends = Arc::new(Mutex::new(vec![]));
+        let sink = ends.clone();
+        (ends, move |end| sink.lock().unwrap().push(end))
+    }
+
+    #[tokio::test]
+    async fn passes_frames_on() {
+        let (ends, on_end) = recorder();
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", "0".parse().unwrap());
+        let inner = BufferedBody::with_trailers(Bytes::from_static(b"hello"), trailers);
+        let collected = PassBody::new(inner, on_end).collect().await.unwrap();
+        assert_eq!(collected.trailers().unwrap()["grpc-status"], "0");
+        assert_eq!(collected.to_bytes(), "hello");
+
+        let ends = ends.lock().unwrap();
+        assert_eq!(ends.len(), 1);
+        assert!(matches!(&ends[0], PassEnd::Complete(Some(_))));
+    }
+
+    #[tokio::test]
+    async fn reports_dropped_bodies() {
+        let (ends, on_end) = recorder();
+        drop(PassBody::new(
+            Full::new(Bytes::from_static(b"hello")),
+            on_end,
+        ));
+        assert!(matches!(
+            ends.lock().unwrap().as_slice(),
+            [PassEnd::Failed(_)]
+        ));
+    }
+}
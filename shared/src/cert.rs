@@ -1,7 +1,14 @@
 use bytes::Bytes;
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
 use rustls::crypto::{CryptoProvider, aws_lc_rs};
+use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
+use x509_parser::error::X509Error;
+use x509_parser::extensions::GeneralName;
+use x509_parser::nom;
+use x509_parser::parse_x509_certificate;
+use x509_parser::public_key::PublicKey;
 
 use crate::alpn::AlpnProtocol;
 use rustls::client::{EchStatus, ResolvesClientCert, WebPkiServerVerifier};
@@ -44,6 +51,145 @@ pub struct VerifyServerCert {
     pub error: Option<rustls::Error>,
 }
 
+impl VerifyServerCert {
+    /// The certificates the server sent, end entity first.
+    pub fn chain(&self) -> Vec<Bytes> {
+        std::iter::once(self.end_entity.clone())
+            .chain(self.intermediates.iter().cloned())
+            .collect()
+    }
+
+    /// [`Self::chain`] parsed, certificates that fail to parse are skipped.
+    pub fn parsed_chain(&self) -> Vec<ParsedCert> {
+        self.chain()
+            .into_iter()
+            .filter_map(|der| ParsedCert::from_der(der).ok())
+            .collect()
+    }
+}
+
+/// The fields of an X.509 certificate worth showing, along with its DER.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedCert {
+    pub der: Bytes,
+    pub version: u32,
+    pub serial: Vec<u8>,
+    pub signature_oid: String,
+    pub issuer: String,
+    pub subject: String,
+    pub issuer_cn: Option<String>,
+    pub subject_cn: Option<String>,
+    /// DNS names and IP addresses, other names in their debug form.
+    pub san: Vec<String>,
+    pub not_before: OffsetDateTime,
+    pub not_after: OffsetDateTime,
+    /// e.g. `RSA 2048`, `ECDSA P-256` or `Ed25519`.
+    pub key_type: String,
+    pub public_key: Vec<u8>,
+    pub signature_value: Vec<u8>,
+    pub is_ca: bool,
+}
+
+impl ParsedCert {
+    pub fn from_der(der: impl Into<Bytes>) -> Result<Self, nom::Err<X509Error>> {
+        let der = der.into();
+        let (_, cert) = parse_x509_certificate(&der)?;
+        let tbs = &cert.tbs_certificate;
+
+        let common_name = |name: &x509_parser::x509::X509Name| {
+            name.iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(str::to_string)
+        };
+
+        let san = tbs
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .map(|ext| {
+                ext.value
+                    .general_names
+                    .iter()
+                    .map(|name| match name {
+                        GeneralName::DNSName(dns) => dns.to_string(),
+                        GeneralName::IPAddress(ip) => ip_address(ip),
+                        other => format!("{other:?}"),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let is_ca = tbs
+            .basic_constraints()
+            .ok()
+            .flatten()
+            .is_some_and(|ext| ext.value.ca);
+
+        Ok(Self {
+            version: tbs.version.0,
+            serial: tbs.serial.to_bytes_be(),
+            signature_oid: tbs.signature.algorithm.to_id_string(),
+            issuer: tbs.issuer.to_string(),
+            subject: tbs.subject.to_string(),
+            issuer_cn: common_name(&tbs.issuer),
+            subject_cn: common_name(&tbs.subject),
+            san,
+            not_before: tbs.validity.not_before.to_datetime(),
+            not_after: tbs.validity.not_after.to_datetime(),
+            key_type: key_type(&tbs.subject_pki),
+            public_key: tbs.subject_pki.subject_public_key.data.to_vec(),
+            signature_value: cert.signature_value.data.to_vec(),
+            is_ca,
+            der: der.clone(),
+        })
+    }
+
+    /// Whether `at` falls within the validity period.
+    pub fn is_valid_at(&self, at: OffsetDateTime) -> bool {
+        self.not_before <= at && at <= self.not_after
+    }
+}
+
+fn ip_address(bytes: &[u8]) -> String {
+    let ip = match bytes.len() {
+        4 => <[u8; 4]>::try_from(bytes).ok().map(IpAddr::from),
+        16 => <[u8; 16]>::try_from(bytes).ok().map(IpAddr::from),
+        _ => None,
+    };
+    match ip {
+        Some(ip) => ip.to_string(),
+        None => format!("{bytes:02x?}"),
+    }
+}
+
+fn key_type(spki: &x509_parser::x509::SubjectPublicKeyInfo) -> String {
+    match spki.algorithm.algorithm.to_id_string().as_str() {
+        "1.2.840.113549.1.1.1" => match spki.parsed() {
+            Ok(PublicKey::RSA(rsa)) => format!("RSA {}", rsa.key_size()),
+            _ => "RSA".to_string(),
+        },
+        "1.2.840.10045.2.1" => {
+            let curve = spki
+                .algorithm
+                .parameters
+                .as_ref()
+                .and_then(|p| p.as_oid().ok())
+                .map(|oid| oid.to_id_string());
+            match curve.as_deref() {
+                Some("1.2.840.10045.3.1.7") => "ECDSA P-256".to_string(),
+                Some("1.3.132.0.34") => "ECDSA P-384".to_string(),
+                Some("1.3.132.0.35") => "ECDSA P-521".to_string(),
+                Some(curve) => format!("ECDSA {curve}"),
+                None => "ECDSA".to_string(),
+            }
+        }
+        "1.3.101.112" => "Ed25519".to_string(),
+        "1.3.101.113" => "Ed448".to_string(),
+        oid => oid.to_string(),
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub enum TlsVerify {
     Tls13(TlsCapture),
@@ -473,3 +619,51 @@ impl ResolvesClientCert for LoggingResolvesClientCert {
         true
     }
 }
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair, PKCS_ECDSA_P256_SHA256};
+
+    use super::*;
+
+    #[test]
+    fn parse_cert() {
+        let ca_key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Test CA");
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let parsed = ParsedCert::from_der(ca.der().to_vec()).unwrap();
+        assert!(parsed.is_ca);
+        assert_eq!(parsed.subject_cn.as_deref(), Some("Test CA"));
+        assert_eq!(parsed.key_type, "ECDSA P-256");
+        assert!(parsed.is_valid_at(OffsetDateTime::now_utc()));
+
+        let leaf_key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let leaf_params =
+            CertificateParams::new(vec!["example.com".to_string(), "127.0.0.1".to_string()])
+                .unwrap();
+        let issuer = rcgen::Issuer::new(ca_params, ca_key);
+        let leaf = leaf_params.signed_by(&leaf_key, &issuer).unwrap();
+
+        let capture = VerifyServerCert {
+            end_entity: leaf.der().to_vec().into(),
+            intermediates: vec![ca.der().to_vec().into(), Bytes::from_static(b"junk")],
+            server_name: ServerName::try_from("example.com").unwrap(),
+            ocsp_response: Bytes::new(),
+            now: UnixTime::now(),
+            error: None,
+        };
+        assert_eq!(capture.chain().len(), 3);
+        let chain = capture.parsed_chain();
+        assert_eq!(chain.len(), 2);
+        assert!(!chain[0].is_ca);
+        assert_eq!(chain[0].issuer_cn.as_deref(), Some("Test CA"));
+        assert_eq!(chain[0].san, vec!["example.com", "127.0.0.1"]);
+        assert_eq!(chain[1], parsed);
+    }
+}
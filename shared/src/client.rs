@@ -1,43 +1,80 @@
 use crate::RoxyCA;
 use crate::alpn::AlpnProtocol;
 use crate::body::BytesBody;
+use crate::body::create_http_body;
+use crate::cert::ClientTlsConnectionData;
+use crate::cert::ServerVerificationCapture;
+use crate::cookie::CookieJar;
+use crate::dns::DnsConfig;
 use crate::http::HttpEmitter;
 use crate::http::HttpError;
+use crate::http::HttpEvent;
 use crate::http::HttpResponse;
+use crate::http::HttpStreamResponse;
 use crate::http::NoOpListener;
-use crate::http::connect_proxy;
-use crate::http::upstream_h2;
-use crate::http::upstream_https;
-use crate::http::uptstream_http;
-use crate::http::uptstream_http_with_proxy;
+use crate::http::ResponseBody;
+use crate::http::ResponseLimits;
+use crate::http::within;
+use crate::pool::ConnectionInfo;
+use crate::pool::ConnectionPool;
+use crate::pool::PoolKey;
+use crate::pool::Sender;
+use crate::pool::handshake_http1;
+use crate::pool::handshake_http2;
+use crate::redirect::RedirectPolicy;
+use crate::retry::RetryPolicy;
+use crate::socks::socks5_connect;
+use crate::system_roots;
 use crate::tls::TlsConfig;
 use crate::tls::client_tls;
 use crate::tls::client_tls_native;
+use crate::upstream::UpstreamProxy;
+use crate::upstream::connect_upstream;
 use crate::uri::RUri;
+use bytes::Bytes;
+use http::HeaderMap;
 use http::Request;
+use http::Response;
 use http::Version;
+use http::request::Parts;
 use http::uri::Scheme;
+use http_body_util::BodyExt;
 use hyper_util::rt::tokio::WithHyperIo;
+use rustls::RootCertStore;
 use rustls::pki_types::ServerName;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 use tokio::net::TcpStream;
+use tracing::debug;
+use tracing::error;
 use tracing::warn;
 
 use crate::h3_client::h3_with_proxy;
 
 #[derive(Debug)]
 pub struct RClientBuilder {
-    proxy_uri: Option<RUri>,
+    upstream: Option<UpstreamProxy>,
+    h3_proxy: Option<RUri>,
     roxy_ca: Option<RoxyCA>,
     emitter: Option<Box<dyn HttpEmitter>>,
     alpns: Vec<AlpnProtocol>,
     use_rustls: bool,
     tls_config: Option<TlsConfig>,
+    dns: DnsConfig,
+    pool: Option<ConnectionPool>,
+    connect_timeout: Option<Duration>,
+    limits: ResponseLimits,
+    cookies: Option<CookieJar>,
+    redirects: Option<RedirectPolicy>,
+    retries: Option<RetryPolicy>,
 }
 
 impl RClientBuilder {
     fn new() -> Self {
         Self {
-            proxy_uri: None,
+            upstream: None,
+            h3_proxy: None,
             roxy_ca: None,
             emitter: None,
             use_rustls: true,
@@ -47,6 +84,13 @@ impl RClientBuilder {
                 AlpnProtocol::Http3,
             ],
             tls_config: None,
+            dns: DnsConfig::default(),
+            pool: None,
+            connect_timeout: None,
+            limits: ResponseLimits::default(),
+            cookies: None,
+            redirects: None,
+            retries: None,
         }
     }
 
@@ -54,8 +98,20 @@ impl RClientBuilder {
         self.use_rustls = false;
         self
     }
+    /// Sends requests through the proxy at `uri`. An HTTP proxy is also sent
+    /// HTTP/3 requests over QUIC, as Roxy serves both on its port.
     pub fn with_proxy(mut self, uri: RUri) -> Self {
-        self.proxy_uri = Some(uri);
+        let upstream = UpstreamProxy::from(uri);
+        if let UpstreamProxy::Http(uri) = &upstream {
+            self.h3_proxy = Some(uri.clone());
+        }
+        self.upstream = Some(upstream);
+        self
+    }
+    /// Chains connections through `upstream`, HTTP/3 requests connect
+    /// directly as QUIC can not be tunneled through either proxy type.
+    pub fn with_upstream(mut self, upstream: Option<UpstreamProxy>) -> Self {
+        self.upstream = upstream;
         self
     }
     pub fn with_roxy_ca(mut self, roxy_ca: RoxyCA) -> Self {
@@ -74,27 +130,84 @@ impl RClientBuilder {
         self.tls_config = Some(tls_config);
         self
     }
+    pub fn with_dns(mut self, dns: DnsConfig) -> Self {
+        self.dns = dns;
+        self
+    }
+    /// Keeps connections in `pool` for later requests, without one every
+    /// request opens and closes its own.
+    pub fn with_pool(mut self, pool: ConnectionPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+    /// Longest wait for a new connection to be ready, TLS and upstream proxy
+    /// included. Fails with [`HttpError::ConnectTimeout`].
+    pub fn with_connect_timeout(mut self, connect_timeout: Option<Duration>) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+    pub fn with_response_limits(mut self, limits: ResponseLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sends the jar's cookies with every request and keeps the ones
+    /// responses set.
+    pub fn with_cookie_jar(mut self, cookies: CookieJar) -> Self {
+        self.cookies = Some(cookies);
+        self
+    }
+
+    /// Follows redirects as `policy` allows, each hop is reported to the
+    /// emitter as [`HttpEvent::Redirected`].
+    pub fn with_redirects(mut self, policy: RedirectPolicy) -> Self {
+        self.redirects = Some(policy);
+        self
+    }
+
+    /// Sends idempotent requests again as `policy` allows, over HTTP/1, HTTP/2
+    /// and HTTP/3 alike.
+    pub fn with_retries(mut self, policy: RetryPolicy) -> Self {
+        self.retries = Some(policy);
+        self
+    }
 
     pub fn build(self) -> ClientContext {
         ClientContext {
-            proxy_uri: self.proxy_uri,
+            upstream: self.upstream,
+            h3_proxy: self.h3_proxy,
             roxy_ca: self.roxy_ca,
             use_rustls: self.use_rustls,
-            emitter: self.emitter.unwrap_or(Box::new(NoOpListener {})),
+            emitter: Arc::from(self.emitter.unwrap_or(Box::new(NoOpListener {}))),
             alpns: self.alpns.iter().map(|f| f.to_bytes().to_vec()).collect(),
             tls_config: self.tls_config.unwrap_or_default(),
+            dns: self.dns,
+            pool: self.pool,
+            connect_timeout: self.connect_timeout,
+            limits: self.limits,
+            cookies: self.cookies,
+            redirects: self.redirects,
+            retries: self.retries,
         }
     }
 }
 
 #[derive(Debug)]
 pub struct ClientContext {
-    proxy_uri: Option<RUri>,
+    upstream: Option<UpstreamProxy>,
+    h3_proxy: Option<RUri>,
     use_rustls: bool,
     roxy_ca: Option<RoxyCA>,
-    emitter: Box<dyn HttpEmitter>,
+    emitter: Arc<dyn HttpEmitter>,
     alpns: Vec<Vec<u8>>,
     tls_config: TlsConfig,
+    dns: DnsConfig,
+    pool: Option<ConnectionPool>,
+    connect_timeout: Option<Duration>,
+    limits: ResponseLimits,
+    cookies: Option<CookieJar>,
+    redirects: Option<RedirectPolicy>,
+    retries: Option<RetryPolicy>,
 }
 
 impl ClientContext {
@@ -103,49 +216,297 @@ impl ClientContext {
     }
 
     pub async fn request(&self, request: Request<BytesBody>) -> Result<HttpResponse, HttpError> {
-        if request.version() == Version::HTTP_3 {
-            self.h3_client_call(request).await
-        } else if request.uri().scheme() == Some(&Scheme::HTTPS) {
-            self.do_tls(request).await
-        } else if let Some(proxy_uri) = &self.proxy_uri {
-            uptstream_http_with_proxy(proxy_uri, request, self.emitter.as_ref()).await
-        } else {
-            uptstream_http(request, self.emitter.as_ref()).await
+        self.request_stream(request).await?.buffered().await
+    }
+
+    /// Like [`Self::request`] but returns event streams as soon as their
+    /// headers arrive. HTTP/3 responses are always buffered.
+    pub async fn request_stream(
+        &self,
+        request: Request<BytesBody>,
+    ) -> Result<HttpStreamResponse, HttpError> {
+        self.send_following(request, false).await
+    }
+
+    /// Returns every response as soon as its headers arrive, the body is read
+    /// from the connection as it is polled so large downloads are never held
+    /// in memory whole. The read timeout only bounds the headers and the body
+    /// size limit does not apply. HTTP/3 responses are always buffered.
+    pub async fn request_streaming(
+        &self,
+        request: Request<BytesBody>,
+    ) -> Result<Response<ResponseBody>, HttpError> {
+        Ok(self.send_following(request, true).await?.into_streaming())
+    }
+
+    /// Sends `request`, following redirects and retrying as configured.
+    async fn send_following(
+        &self,
+        request: Request<BytesBody>,
+        streaming: bool,
+    ) -> Result<HttpStreamResponse, HttpError> {
+        if self.redirects.is_none() && self.retries.is_none() {
+            return self.send_with_cookies(request, streaming).await;
+        }
+
+        // Kept to send again on retries and redirects that keep the body
+        let (mut parts, body) = request.into_parts();
+        let collected = match body.collect().await {
+            Ok(collected) => collected,
+            Err(never) => match never {},
+        };
+        let mut trailers = collected.trailers().cloned();
+        let mut body = collected.to_bytes();
+
+        let mut hops = 0;
+        loop {
+            let response = self
+                .send_with_retries(&parts, &body, &trailers, streaming)
+                .await?;
+            let Some(policy) = &self.redirects else {
+                return Ok(response);
+            };
+            if hops == policy.max_hops {
+                return Ok(response);
+            }
+            let head = response.parts();
+            let Some((redirect, keep_body)) = policy.follow(&mut parts, head.status, &head.headers)
+            else {
+                return Ok(response);
+            };
+            if !keep_body {
+                body = Bytes::new();
+                trailers = None;
+            }
+            hops += 1;
+            self.emitter.emit(HttpEvent::Redirected(redirect));
         }
     }
 
-    async fn do_tls(&self, request: Request<BytesBody>) -> Result<HttpResponse, HttpError> {
-        let roxy_ca = self.roxy_ca.as_ref().ok_or_else(|| HttpError::Alpn)?;
-        let stream = if let Some(proxy_uri) = &self.proxy_uri {
-            connect_proxy(proxy_uri, request.uri()).await?
-        } else {
-            let addr = format!(
-                "{}:{}",
-                request.uri().host().unwrap_or("localhost"),
-                request.uri().port_u16().unwrap_or(443)
+    /// Sends the request once, or until the retry policy gives up on it.
+    async fn send_with_retries(
+        &self,
+        parts: &Parts,
+        body: &Bytes,
+        trailers: &Option<HeaderMap>,
+        streaming: bool,
+    ) -> Result<HttpStreamResponse, HttpError> {
+        let mut attempt = 0;
+        loop {
+            let mut request = Request::new(create_http_body(body.clone(), None, trailers.clone()));
+            *request.method_mut() = parts.method.clone();
+            *request.uri_mut() = parts.uri.clone();
+            *request.version_mut() = parts.version;
+            *request.headers_mut() = parts.headers.clone();
+            *request.extensions_mut() = parts.extensions.clone();
+
+            let result = self.send_with_cookies(request, streaming).await;
+            let Some(delay) = self
+                .retries
+                .as_ref()
+                .and_then(|policy| policy.delay(&parts.method, &result, attempt))
+            else {
+                return result;
+            };
+            attempt += 1;
+            debug!(
+                "Retrying {} {} in {delay:?}, attempt {attempt}",
+                parts.method, parts.uri
             );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn send_with_cookies(
+        &self,
+        mut request: Request<BytesBody>,
+        streaming: bool,
+    ) -> Result<HttpStreamResponse, HttpError> {
+        let Some(cookies) = &self.cookies else {
+            return self.send_request(request, streaming).await;
+        };
+        let uri = request.uri().clone();
+        cookies.add_cookie_header(&uri, request.headers_mut());
+        let response = self.send_request(request, streaming).await?;
+        cookies.store(&uri, &response.parts().headers);
+        Ok(response)
+    }
+
+    async fn send_request(
+        &self,
+        mut request: Request<BytesBody>,
+        streaming: bool,
+    ) -> Result<HttpStreamResponse, HttpError> {
+        if request.version() == Version::HTTP_3 {
+            return self
+                .h3_client_call(request)
+                .await
+                .map(HttpStreamResponse::Buffered);
+        }
+        let emitter = self.emitter.clone();
+        hyper::ext::on_informational(&mut request, move |res| {
+            emitter.emit(HttpEvent::Informational(
+                res.status(),
+                res.headers().clone(),
+            ));
+        });
+        let host = request.uri().host().unwrap_or("localhost").to_string();
+        if request.uri().scheme() == Some(&Scheme::HTTPS) {
+            let port = request.uri().port_u16().unwrap_or(443);
+            let key = self.pool_key("https", &host, port);
+            return self
+                .send(key, request, streaming, || self.connect_tls(host, port))
+                .await;
+        }
+        let port = request.uri().port_u16().unwrap_or(80);
+        // Cleartext HTTP/2 is spoken with prior knowledge, an HTTP proxy is
+        // always sent HTTP/1.1
+        let h2c = request.version() == Version::HTTP_2;
+        let scheme = if h2c { "h2c" } else { "http" };
+        match &self.upstream {
+            // Requests are sent in absolute form, one proxy connection serves every host
+            Some(UpstreamProxy::Http(proxy_uri)) => {
+                let host = proxy_uri.host().to_string();
+                let port = proxy_uri.port();
+                let key = self.pool_key("http", &host, port);
+                self.send(key, request, streaming, || async move {
+                    let stream = TcpStream::connect(proxy_uri.host_port()).await?;
+                    self.connect_plain(stream, false).await
+                })
+                .await
+            }
+            Some(UpstreamProxy::Socks5(proxy_uri)) => {
+                let key = self.pool_key(scheme, &host, port);
+                self.send(key, request, streaming, || async move {
+                    let stream = socks5_connect(proxy_uri, &host, port).await?;
+                    self.connect_plain(stream, h2c).await
+                })
+                .await
+            }
+            None => {
+                let key = self.pool_key(scheme, &host, port);
+                self.send(key, request, streaming, || async move {
+                    let stream = self.dns.connect(&host, port, self.emitter.as_ref()).await?;
+                    self.connect_plain(stream, h2c).await
+                })
+                .await
+            }
+        }
+    }
+
+    /// Roots servers are verified against, the system ones when no CA was set.
+    fn roots(&self) -> Arc<RootCertStore> {
+        match &self.roxy_ca {
+            Some(roxy_ca) => roxy_ca.roots(),
+            None => system_roots(),
+        }
+    }
+
+    fn pool_key(&self, scheme: &'static str, host: &str, port: u16) -> PoolKey {
+        let https = matches!(scheme, "https" | "h3");
+        PoolKey {
+            scheme,
+            host: host.to_string(),
+            port,
+            alpns: if https { self.alpns.clone() } else { vec![] },
+            upstream: self.upstream.as_ref().map(ToString::to_string),
+            tls: if https {
+                Some((self.use_rustls, self.tls_config.fingerprint()))
+            } else {
+                None
+            },
+        }
+    }
 
-            WithHyperIo::new(TcpStream::connect(addr).await?)
+    /// Sends over the pool when there is one, otherwise over a connection of
+    /// its own.
+    async fn send<F, Fut>(
+        &self,
+        key: PoolKey,
+        request: Request<BytesBody>,
+        streaming: bool,
+        connect: F,
+    ) -> Result<HttpStreamResponse, HttpError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(Sender, ConnectionInfo), HttpError>>,
+    {
+        let connect = || async move {
+            within(self.connect_timeout, connect())
+                .await
+                .map_err(|_| HttpError::ConnectTimeout)?
         };
+        match &self.pool {
+            Some(pool) => {
+                pool.send(
+                    key,
+                    request,
+                    self.emitter.as_ref(),
+                    &self.limits,
+                    streaming,
+                    connect,
+                )
+                .await
+            }
+            None => {
+                let (mut sender, _) = connect().await?;
+                let response = self.limits.head(sender.send(request)).await??;
+                self.limits.body(response, streaming).await
+            }
+        }
+    }
+
+    /// Starts HTTP/1.1, or HTTP/2 with prior knowledge when `h2c` is set, on
+    /// a connection without TLS.
+    async fn connect_plain(
+        &self,
+        stream: TcpStream,
+        h2c: bool,
+    ) -> Result<(Sender, ConnectionInfo), HttpError> {
+        let info = ConnectionInfo {
+            addr: stream.peer_addr().ok(),
+            tls: None,
+        };
+        let io = WithHyperIo::new(stream);
+        let sender = if h2c {
+            handshake_http2(io, self.emitter.as_ref()).await?
+        } else {
+            handshake_http1(io, self.emitter.as_ref()).await?
+        };
+        Ok((sender, info))
+    }
+
+    async fn connect_tls(
+        &self,
+        host: String,
+        port: u16,
+    ) -> Result<(Sender, ConnectionInfo), HttpError> {
+        let stream = connect_upstream(
+            self.upstream.as_ref(),
+            &self.dns,
+            &host,
+            port,
+            self.emitter.as_ref(),
+        )
+        .await?;
+        let addr = stream.peer_addr().ok();
+        let stream = WithHyperIo::new(stream);
 
-        let server_name: ServerName = request
-            .uri()
-            .host()
-            .unwrap_or("localhost")
-            .to_string()
-            .try_into()?;
+        let server_name: ServerName = host.try_into()?;
+        let recorder = TlsRecorder::new(self.emitter.clone());
 
         let (stream, alpn) = if self.use_rustls {
             client_tls(
                 server_name,
                 stream,
                 self.alpns.clone(),
-                roxy_ca.roots(),
-                self.emitter.as_ref(),
+                self.roots(),
+                &recorder,
                 &self.tls_config,
             )
             .await?
         } else {
+            let roxy_ca = self.roxy_ca.as_ref().ok_or_else(|| HttpError::Alpn)?;
             let alpns: Vec<String> = self
                 .alpns
                 .iter()
@@ -157,31 +518,88 @@ impl ClientContext {
                 stream,
                 alpns.as_slice(),
                 roxy_ca.clone(),
-                self.emitter.as_ref(),
+                &recorder,
             )
             .await?
         };
 
-        match alpn {
-            AlpnProtocol::Http2 => upstream_h2(stream, request, self.emitter.as_ref()).await,
-            AlpnProtocol::Http1 => upstream_https(stream, request, self.emitter.as_ref()).await,
+        let sender = match alpn {
+            AlpnProtocol::Http2 => handshake_http2(stream, self.emitter.as_ref()).await?,
+            AlpnProtocol::Http1 => handshake_http1(stream, self.emitter.as_ref()).await?,
             _ => {
                 warn!("Unknow alpn negotiated {:?}", alpn);
-                upstream_https(stream, request, self.emitter.as_ref()).await
+                handshake_http1(stream, self.emitter.as_ref()).await?
             }
-        }
+        };
+        let info = ConnectionInfo {
+            addr,
+            tls: recorder.take(),
+        };
+        Ok((sender, info))
     }
+
     pub async fn h3_client_call(
         &self,
         request: Request<BytesBody>,
     ) -> Result<HttpResponse, HttpError> {
-        let roxy_ca = self.roxy_ca.as_ref().ok_or_else(|| HttpError::Alpn)?;
+        if let (Some(upstream), None) = (&self.upstream, &self.h3_proxy) {
+            debug!(
+                "HTTP/3 to {} is not chained through {upstream}, connecting directly",
+                request.uri()
+            );
+        }
+        let pool = self.pool.as_ref().map(|pool| {
+            let host = request.uri().host().unwrap_or("localhost");
+            let port = request.uri().port_u16().unwrap_or(443);
+            (pool, self.pool_key("h3", host, port))
+        });
         h3_with_proxy(
-            self.proxy_uri.as_ref(),
-            roxy_ca.roots(),
+            self.h3_proxy.as_ref(),
+            &self.dns,
+            &self.tls_config,
+            self.roots(),
+            pool,
             request,
             self.emitter.as_ref(),
         )
         .await
     }
 }
+
+/// Passes events on, keeping the TLS details a reused connection reports.
+#[derive(Debug)]
+struct TlsRecorder {
+    inner: Arc<dyn HttpEmitter>,
+    tls: Mutex<Option<(ClientTlsConnectionData, ServerVerificationCapture)>>,
+}
+
+impl TlsRecorder {
+    fn new(inner: Arc<dyn HttpEmitter>) -> Self {
+        Self {
+            inner,
+            tls: Mutex::new(None),
+        }
+    }
+
+    fn take(&self) -> Option<(ClientTlsConnectionData, ServerVerificationCapture)> {
+        match self.tls.lock() {
+            Ok(mut tls) => tls.take(),
+            Err(err) => {
+                error!("TLS recorder lock poisoned {err}");
+                None
+            }
+        }
+    }
+}
+
+impl HttpEmitter for TlsRecorder {
+    fn emit(&self, event: HttpEvent) {
+        if let HttpEvent::ClientTlsConn(data, verification) = &event {
+            match self.tls.lock() {
+                Ok(mut tls) => *tls = Some((data.clone(), verification.clone())),
+                Err(err) => error!("TLS recorder lock poisoned {err}"),
+            }
+        }
+        self.inner.emit(event);
+    }
+}
@@ -15,7 +15,7 @@ use flate2::{
 };
 use http::{
     HeaderMap, HeaderName,
-    header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE},
+    header::{ACCEPT_ENCODING, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_TYPE},
 };
 use strum::VariantArray;
 
@@ -23,9 +23,12 @@ use strum::VariantArray;
 pub enum ContentType {
     Bmp,
     Csv,
+    EventStream,
     Gif,
+    Grpc,
     Html,
     Jpeg,
+    Js,
     Json,
     Md,
     Png,
@@ -41,10 +44,16 @@ pub enum ContentType {
 }
 
 const MIME_APPLICATION_CSV: &str = "application/csv";
+const MIME_APPLICATION_GRPC: &str = "application/grpc";
+const MIME_APPLICATION_GRPC_PROTO: &str = "application/grpc+proto";
+const MIME_APPLICATION_GRPC_WEB: &str = "application/grpc-web";
+const MIME_APPLICATION_GRPC_WEB_PROTO: &str = "application/grpc-web+proto";
+const MIME_APPLICATION_JAVASCRIPT: &str = "application/javascript";
 const MIME_APPLICATION_JSON: &str = "application/json";
 const MIME_APPLICATION_OCTECT_STREAM: &str = "application/octet-stream";
 const MIME_APPLICATION_TOML: &str = "application/toml";
 const MIME_APPLICATION_TSV: &str = "application/tsv";
+const MIME_APPLICATION_X_JAVASCRIPT: &str = "application/x-javascript";
 const MIME_APPLICATION_XML: &str = "application/xml";
 const MIME_APPLICATION_YAML: &str = "application/yaml";
 const MIME_IMAGE_BMP: &str = "image/bmp";
@@ -55,7 +64,9 @@ const MIME_IMAGE_JPEG: &str = "image/jpeg";
 const MIME_IMAGE_PNG: &str = "image/png";
 const MIME_IMAGE_SVG_XML: &str = "image/svg+xml";
 const MIME_IMAGE_WEBP: &str = "image/webp";
+const MIME_TEXT_EVENT_STREAM: &str = "text/event-stream";
 const MIME_TEXT_HTML: &str = "text/html";
+const MIME_TEXT_JAVASCRIPT: &str = "text/javascript";
 const MIME_TEXT_MARKDOWN: &str = "text/markdown";
 const MIME_TEXT_PLAIN: &str = "text/plain";
 
@@ -64,9 +75,12 @@ impl ContentType {
         match self {
             ContentType::Bmp => MIME_IMAGE_BMP,
             ContentType::Csv => MIME_APPLICATION_CSV,
+            ContentType::EventStream => MIME_TEXT_EVENT_STREAM,
             ContentType::Gif => MIME_IMAGE_GIF,
+            ContentType::Grpc => MIME_APPLICATION_GRPC,
             ContentType::Html => MIME_TEXT_HTML,
             ContentType::Jpeg => MIME_IMAGE_JPEG,
+            ContentType::Js => MIME_TEXT_JAVASCRIPT,
             ContentType::Json => MIME_APPLICATION_JSON,
             ContentType::Md => MIME_TEXT_MARKDOWN,
             ContentType::OctetStream => MIME_APPLICATION_OCTECT_STREAM,
@@ -85,12 +99,15 @@ impl ContentType {
 
 const EXT_BMP: &str = "bmp";
 const EXT_CSV: &str = "csv";
+const EXT_EVENT_STREAM: &str = "sse";
 const EXT_GIF: &str = "gif";
+const EXT_GRPC: &str = "grpc";
 const EXT_HTML: &str = "html";
 const EXT_ICNS: &str = "icns";
 const EXT_ICO: &str = "ico";
 const EXT_JPG: &str = "jpg";
 const EXT_JPEG: &str = "jpeg";
+const EXT_JS: &str = "js";
 const EXT_JSON: &str = "json";
 const EXT_MD: &str = "md";
 const EXT_OCTET_STREAM: &str = "oct";
@@ -107,12 +124,15 @@ pub fn ext_to_content_type(ext: &str) -> Option<ContentType> {
     match ext {
         EXT_BMP => Some(ContentType::Bmp),
         EXT_CSV => Some(ContentType::Csv),
+        EXT_EVENT_STREAM => Some(ContentType::EventStream),
         EXT_GIF => Some(ContentType::Gif),
+        EXT_GRPC => Some(ContentType::Grpc),
         EXT_HTML => Some(ContentType::Html),
         EXT_ICNS => Some(ContentType::XIcon),
         EXT_ICO => Some(ContentType::XIcon),
         EXT_JPG => Some(ContentType::Jpeg),
         EXT_JPEG => Some(ContentType::Jpeg),
+        EXT_JS => Some(ContentType::Js),
         EXT_JSON => Some(ContentType::Json),
         EXT_MD => Some(ContentType::Md),
         EXT_OCTET_STREAM => Some(ContentType::OctetStream),
@@ -131,9 +151,12 @@ pub fn content_type_ext(content_type: &ContentType) -> &'static str {
     match content_type {
         ContentType::Bmp => EXT_BMP,
         ContentType::Csv => EXT_CSV,
+        ContentType::EventStream => EXT_EVENT_STREAM,
         ContentType::Gif => EXT_GIF,
+        ContentType::Grpc => EXT_GRPC,
         ContentType::Html => EXT_HTML,
         ContentType::Jpeg => EXT_JPEG,
+        ContentType::Js => EXT_JS,
         ContentType::Json => EXT_JSON,
         ContentType::Md => EXT_MD,
         ContentType::Png => EXT_PNG,
@@ -151,7 +174,8 @@ pub fn content_type_ext(content_type: &ContentType) -> &'static str {
 
 pub fn parse_content_type(content_type: &str) -> Option<ContentType> {
     let ct = content_type.cow_to_ascii_lowercase();
-    match ct.as_ref() {
+    // Parameters such as `charset` don't change how a body is rendered
+    match ct.split(';').next().unwrap_or_default().trim() {
         MIME_APPLICATION_JSON => Some(ContentType::Json),
         MIME_IMAGE_BMP => Some(ContentType::Bmp),
         MIME_APPLICATION_XML => Some(ContentType::Xml),
@@ -159,6 +183,9 @@ pub fn parse_content_type(content_type: &str) -> Option<ContentType> {
         MIME_APPLICATION_TSV => Some(ContentType::Tsv),
         MIME_TEXT_MARKDOWN => Some(ContentType::Md),
         MIME_TEXT_HTML => Some(ContentType::Html),
+        MIME_TEXT_JAVASCRIPT | MIME_APPLICATION_JAVASCRIPT | MIME_APPLICATION_X_JAVASCRIPT => {
+            Some(ContentType::Js)
+        }
         MIME_APPLICATION_TOML => Some(ContentType::Toml),
         MIME_APPLICATION_YAML => Some(ContentType::Yaml),
         MIME_IMAGE_PNG => Some(ContentType::Png),
@@ -169,6 +196,11 @@ pub fn parse_content_type(content_type: &str) -> Option<ContentType> {
         MIME_IMAGE_XICON => Some(ContentType::XIcon),
         MIME_IMAGE_SVG_XML => Some(ContentType::Svg),
         MIME_TEXT_PLAIN => Some(ContentType::Text),
+        MIME_TEXT_EVENT_STREAM => Some(ContentType::EventStream),
+        MIME_APPLICATION_GRPC
+        | MIME_APPLICATION_GRPC_PROTO
+        | MIME_APPLICATION_GRPC_WEB
+        | MIME_APPLICATION_GRPC_WEB_PROTO => Some(ContentType::Grpc),
         _ => None,
     }
 }
@@ -335,3 +367,167 @@ pub fn encode_body_opt(
         None => Ok(body),
     }
 }
+
+/// One part of a `multipart/form-data` body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartPart {
+    /// The form field, from `Content-Disposition`.
+    pub name: Option<String>,
+    /// Set for uploaded files.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// The `boundary` of a `multipart/*` content type, `None` for other types.
+pub fn multipart_boundary(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+    if !mime.cow_to_ascii_lowercase().starts_with("multipart/") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+            .filter(|boundary| !boundary.is_empty())
+    })
+}
+
+/// The boundary of the first delimiter line of a multipart body, for when
+/// its `Content-Type` is not at hand. Bodies with a preamble are not
+/// recognised.
+pub fn sniff_multipart_boundary(body: &[u8]) -> Option<String> {
+    let line = &body[..find(body, b"\r\n")?];
+    let boundary = std::str::from_utf8(line.strip_prefix(b"--")?)
+        .ok()?
+        .trim_end();
+    (!boundary.is_empty()).then(|| boundary.to_string())
+}
+
+/// Splits a multipart body on `boundary`, `None` when it is malformed or the
+/// closing delimiter is missing.
+pub fn parse_multipart(body: &Bytes, boundary: &str) -> Option<Vec<MultipartPart>> {
+    let delimiter = format!("--{boundary}");
+    // Everything before the first delimiter is a preamble to ignore
+    let start = find(body, delimiter.as_bytes())?;
+    let mut rest = body.slice(start + delimiter.len()..);
+    let delimiter = format!("\r\n{delimiter}");
+    let mut parts = vec![];
+    loop {
+        if rest.starts_with(b"--") {
+            return Some(parts);
+        }
+        // Delimiters may be followed by whitespace before their line break
+        let line_end = find(&rest, b"\r\n")?;
+        rest = rest.slice(line_end + 2..);
+        let end = find(&rest, delimiter.as_bytes())?;
+        parts.push(parse_part(rest.slice(..end)));
+        rest = rest.slice(end + delimiter.len()..);
+    }
+}
+
+fn parse_part(part: Bytes) -> MultipartPart {
+    let (head, body) = if part.starts_with(b"\r\n") {
+        (Bytes::new(), part.slice(2..))
+    } else {
+        match find(&part, b"\r\n\r\n") {
+            Some(end) => (part.slice(..end), part.slice(end + 4..)),
+            None => (part, Bytes::new()),
+        }
+    };
+    let mut headers = HeaderMap::new();
+    for line in String::from_utf8_lossy(&head).split("\r\n") {
+        if let Some((name, value)) = line.split_once(':')
+            && let Ok(name) = HeaderName::from_bytes(name.trim().as_bytes())
+            && let Ok(value) = value.trim().parse()
+        {
+            headers.append(name, value);
+        }
+    }
+    let disposition = headers
+        .get(CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    MultipartPart {
+        name: disposition_param(disposition, "name"),
+        filename: disposition_param(disposition, "filename"),
+        content_type: headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string),
+        headers,
+        body,
+    }
+}
+
+fn disposition_param(disposition: &str, name: &str) -> Option<String> {
+    disposition.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn parses_multipart() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("multipart/form-data; boundary=\"XyZ\""),
+        );
+        let boundary = multipart_boundary(&headers).unwrap();
+        assert_eq!(boundary, "XyZ");
+
+        let body = Bytes::from_static(
+            b"preamble\r\n--XyZ\r\n\
+              Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+              hello\r\n\
+              --XyZ  \r\n\
+              Content-Disposition: form-data; name=\"upload\"; filename=\"a.bin\"\r\n\
+              Content-Type: application/octet-stream\r\n\r\n\
+              \x00\r\n--X\x01\r\n\
+              --XyZ--\r\n",
+        );
+        let parts = parse_multipart(&body, &boundary).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name.as_deref(), Some("title"));
+        assert_eq!(parts[0].filename, None);
+        assert_eq!(parts[0].body, "hello");
+        assert_eq!(parts[1].filename.as_deref(), Some("a.bin"));
+        assert_eq!(
+            parts[1].content_type.as_deref(),
+            Some("application/octet-stream")
+        );
+        assert_eq!(parts[1].body, &b"\x00\r\n--X\x01"[..]);
+
+        assert_eq!(
+            parse_multipart(&Bytes::from_static(b"--XyZ\r\n"), "XyZ"),
+            None
+        );
+        assert_eq!(sniff_multipart_boundary(&body), None);
+        assert_eq!(
+            sniff_multipart_boundary(&body.slice(10..)).as_deref(),
+            Some("XyZ")
+        );
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        assert_eq!(multipart_boundary(&headers), None);
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use cow_utils::CowUtils;
+use http::{
+    HeaderMap, HeaderValue, Uri,
+    header::{COOKIE, SET_COOKIE},
+    uri::Scheme,
+};
+use time::{Duration, OffsetDateTime, format_description::well_known::Rfc2822};
+use tracing::{debug, error};
+
+/// A cookie as stored by a [`CookieJar`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercase, without a leading dot.
+    pub domain: String,
+    /// Set without a `Domain` attribute, only sent back to the exact host.
+    pub host_only: bool,
+    pub path: String,
+    pub secure: bool,
+    /// `None` for session cookies, kept as long as the jar.
+    pub expires: Option<OffsetDateTime>,
+}
+
+impl Cookie {
+    /// Parses a `Set-Cookie` value received from `host` for `path`, `None`
+    /// when it is malformed or its domain is not one `host` may set.
+    fn parse(set_cookie: &str, host: &str, path: &str, now: OffsetDateTime) -> Option<Self> {
+        let mut attributes = set_cookie.split(';');
+        let (name, value) = attributes.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.trim().trim_matches('"').to_string(),
+            domain: host.to_string(),
+            host_only: true,
+            path: default_path(path),
+            secure: false,
+            expires: None,
+        };
+
+        let mut max_age = None;
+        for attribute in attributes {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().cow_to_ascii_lowercase().as_ref() {
+                "domain" => {
+                    let domain = value
+                        .trim_start_matches('.')
+                        .cow_to_ascii_lowercase()
+                        .into_owned();
+                    if !domain.is_empty() {
+                        if !domain_matches(host, &domain) {
+                            debug!("Ignoring cookie {name} for {domain} set by {host}");
+                            return None;
+                        }
+                        cookie.domain = domain;
+                        cookie.host_only = false;
+                    }
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "max-age" => max_age = value.parse::<i64>().ok(),
+                "expires" => {
+                    // Older servers write `21-Oct-2015`
+                    if let Ok(expires) =
+                        OffsetDateTime::parse(&value.cow_replace('-', " "), &Rfc2822)
+                    {
+                        cookie.expires = Some(expires);
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Max-Age wins over Expires
+        if let Some(max_age) = max_age {
+            cookie.expires = Some(now + Duration::seconds(max_age));
+        }
+        Some(cookie)
+    }
+
+    fn is_expired(&self, now: OffsetDateTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn matches(&self, host: &str, path: &str, secure: bool) -> bool {
+        let domain = if self.host_only {
+            self.domain == host
+        } else {
+            domain_matches(host, &self.domain)
+        };
+        domain && path_matches(path, &self.path) && (secure || !self.secure)
+    }
+}
+
+/// Cookies received in `Set-Cookie` headers, sent back on later requests to
+/// the domains and paths they were set for. Clones share the same cookies.
+///
+/// There is no public suffix list, so a server may set a cookie for a whole
+/// top level domain it belongs to.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Arc<Mutex<Vec<Cookie>>>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cookies currently held, expired ones included until the next
+    /// request or response prunes them.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        match self.cookies.lock() {
+            Ok(cookies) => cookies.clone(),
+            Err(err) => {
+                error!("Cookie jar lock poisoned {err}");
+                vec![]
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        match self.cookies.lock() {
+            Ok(mut cookies) => cookies.clear(),
+            Err(err) => error!("Cookie jar lock poisoned {err}"),
+        }
+    }
+
+    /// Stores the `Set-Cookie` headers of a response to `uri`. A cookie
+    /// replaces the one with the same name, domain and path, and removes it
+    /// when already expired.
+    pub fn store(&self, uri: &Uri, headers: &HeaderMap) {
+        let Some(host) = uri.host().map(normalize_host) else {
+            return;
+        };
+        let now = OffsetDateTime::now_utc();
+        let received = headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| Cookie::parse(value, &host, uri.path(), now))
+            .collect::<Vec<_>>();
+        if received.is_empty() {
+            return;
+        }
+        let mut cookies = match self.cookies.lock() {
+            Ok(cookies) => cookies,
+            Err(err) => {
+                error!("Cookie jar lock poisoned {err}");
+                return;
+            }
+        };
+        for cookie in received {
+            cookies.retain(|c| {
+                !(c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path)
+            });
+            if !cookie.is_expired(now) {
+                cookies.push(cookie);
+            }
+        }
+    }
+
+    /// Adds the cookies for `uri` to the `Cookie` header of `headers`. A
+    /// cookie already in the header is replaced by the jar's one of the same
+    /// name, so replayed requests pick up fresh values.
+    pub fn add_cookie_header(&self, uri: &Uri, headers: &mut HeaderMap) {
+        let Some(host) = uri.host().map(normalize_host) else {
+            return;
+        };
+        let secure = uri.scheme() == Some(&Scheme::HTTPS);
+        let path = if uri.path().is_empty() {
+            "/"
+        } else {
+            uri.path()
+        };
+        let now = OffsetDateTime::now_utc();
+
+        let mut matching = match self.cookies.lock() {
+            Ok(mut cookies) => {
+                cookies.retain(|c| !c.is_expired(now));
+                cookies
+                    .iter()
+                    .filter(|c| c.matches(&host, path, secure))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            }
+            Err(err) => {
+                error!("Cookie jar lock poisoned {err}");
+                return;
+            }
+        };
+        if matching.is_empty() {
+            return;
+        }
+        // More specific paths first
+        matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+
+        let mut pairs = headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .map(str::trim)
+            .filter(|pair| {
+                let name = pair.split_once('=').map_or(*pair, |(name, _)| name);
+                !pair.is_empty() && !matching.iter().any(|c| c.name == name)
+            })
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        pairs.extend(matching.iter().map(|c| format!("{}={}", c.name, c.value)));
+
+        match HeaderValue::from_str(&pairs.join("; ")) {
+            Ok(value) => {
+                headers.insert(COOKIE, value);
+            }
+            Err(err) => error!("Invalid cookie header {err}"),
+        }
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .cow_to_ascii_lowercase()
+        .into_owned()
+}
+
+/// Whether `host` is `domain` or a subdomain of it, IP addresses only match
+/// themselves.
+fn domain_matches(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    host.parse::<IpAddr>().is_err()
+        && host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The directory of `path`, where a cookie without a `Path` applies.
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(end) if path.starts_with('/') => path[..end].to_string(),
+        Some(_) => "/".to_string(),
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(SET_COOKIE, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn cookie_header(jar: &CookieJar, uri: &str) -> Option<String> {
+        let mut headers = HeaderMap::new();
+        jar.add_cookie_header(&uri.parse().unwrap(), &mut headers);
+        headers.get(COOKIE).map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn domain_path_and_secure() {
+        let jar = CookieJar::new();
+        jar.store(
+            &"https://www.example.com/account/login".parse().unwrap(),
+            &response(&[
+                "host=1",
+                "wide=2; Domain=.example.com; Path=/",
+                "secret=3; Path=/account; Secure",
+                "other=4; Domain=evil.com",
+            ]),
+        );
+        assert_eq!(jar.cookies().len(), 3);
+
+        assert_eq!(
+            cookie_header(&jar, "https://www.example.com/account/settings").as_deref(),
+            Some("host=1; secret=3; wide=2")
+        );
+        assert_eq!(
+            cookie_header(&jar, "http://www.example.com/account").as_deref(),
+            Some("host=1; wide=2")
+        );
+        assert_eq!(
+            cookie_header(&jar, "https://api.example.com/accounts").as_deref(),
+            Some("wide=2")
+        );
+        assert_eq!(cookie_header(&jar, "https://example.org/"), None);
+    }
+
+    #[test]
+    fn replaces_and_expires() {
+        let jar = CookieJar::new();
+        let uri: Uri = "https://example.com/".parse().unwrap();
+        jar.store(&uri, &response(&["token=old", "gone=1"]));
+        jar.store(
+            &uri,
+            &response(&[
+                "token=new",
+                "gone=; Max-Age=0",
+                "past=1; Expires=Wed, 21-Oct-2015 07:28:00 GMT",
+            ]),
+        );
+        assert_eq!(
+            cookie_header(&jar, "https://example.com/").as_deref(),
+            Some("token=new")
+        );
+    }
+
+    #[test]
+    fn overrides_request_cookies() {
+        let jar = CookieJar::new();
+        let uri: Uri = "https://example.com/".parse().unwrap();
+        jar.store(&uri, &response(&["session=fresh"]));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_static("theme=dark; session=stale"),
+        );
+        jar.add_cookie_header(&uri, &mut headers);
+        assert_eq!(headers[COOKIE], "theme=dark; session=fresh");
+    }
+}
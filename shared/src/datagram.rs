@@ -0,0 +1,124 @@
+//! HTTP datagrams (RFC 9297) carrying the UDP payloads of CONNECT-UDP
+//! tunnels (RFC 9298).
+//!
+//! Every QUIC datagram starts with the quarter stream id of the CONNECT-UDP
+//! request it belongs to, then a context id. Context `0` carries a UDP
+//! payload, other contexts are extensions nobody here negotiates.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+/// Context id of datagrams carrying UDP payloads, others are extensions.
+pub const UDP_PAYLOAD_CONTEXT: u64 = 0;
+
+/// The header every UDP payload of the tunnel opened by the request on
+/// stream `quarter_id * 4` starts with.
+pub fn udp_prefix(quarter_id: u64) -> Bytes {
+    let mut prefix = BytesMut::new();
+    put_varint(&mut prefix, quarter_id);
+    put_varint(&mut prefix, UDP_PAYLOAD_CONTEXT);
+    prefix.freeze()
+}
+
+/// Splits a datagram into its quarter stream id and UDP payload, `None` for
+/// malformed datagrams and other contexts.
+pub fn decode_udp(mut datagram: Bytes) -> Option<(u64, Bytes)> {
+    let quarter_id = read_varint(&mut datagram)?;
+    if read_varint(&mut datagram)? != UDP_PAYLOAD_CONTEXT {
+        return None;
+    }
+    Some((quarter_id, datagram))
+}
+
+/// Whether `packet` looks like the start of a QUIC v1 or v2 connection rather
+/// than some other UDP protocol such as DNS or STUN.
+pub fn is_quic_initial(packet: &[u8]) -> bool {
+    let [first, version @ ..] = packet else {
+        return false;
+    };
+    // Long header with the fixed bit set
+    if first & 0xc0 != 0xc0 || version.len() < 4 {
+        return false;
+    }
+    matches!(
+        u32::from_be_bytes([version[0], version[1], version[2], version[3]]),
+        0x0000_0001 | 0x6b33_43cf
+    )
+}
+
+/// Reads a QUIC variable-length integer from the front of `buf`.
+pub fn read_varint(buf: &mut Bytes) -> Option<u64> {
+    let first = *buf.first()?;
+    let len = 1 << (first >> 6);
+    if buf.len() < len {
+        return None;
+    }
+    let value = buf[1..len]
+        .iter()
+        .fold(u64::from(first & 0x3f), |v, b| (v << 8) | u64::from(*b));
+    buf.advance(len);
+    Some(value)
+}
+
+pub fn put_varint(buf: &mut BytesMut, value: u64) {
+    match value {
+        ..=0x3f => buf.put_u8(value as u8),
+        ..=0x3fff => buf.put_u16(0x4000 | value as u16),
+        ..=0x3fff_ffff => buf.put_u32(0x8000_0000 | value as u32),
+        _ => buf.put_u64(0xc000_0000_0000_0000 | value),
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint_round_trip() {
+        for value in [
+            0,
+            37,
+            0x3f,
+            0x40,
+            15293,
+            0x3fff_ffff,
+            0x4000_0000,
+            151288809941952652,
+        ] {
+            let mut buf = BytesMut::new();
+            put_varint(&mut buf, value);
+            buf.put_slice(b"rest");
+            let mut bytes = buf.freeze();
+            assert_eq!(read_varint(&mut bytes), Some(value));
+            assert_eq!(bytes, "rest");
+        }
+        assert_eq!(read_varint(&mut Bytes::from_static(&[0x40])), None);
+    }
+
+    #[test]
+    fn udp_payloads() {
+        let mut datagram = BytesMut::from(&udp_prefix(300)[..]);
+        datagram.put_slice(b"payload");
+        assert_eq!(
+            decode_udp(datagram.freeze()),
+            Some((300, Bytes::from_static(b"payload")))
+        );
+
+        let mut other_context = BytesMut::new();
+        put_varint(&mut other_context, 300);
+        put_varint(&mut other_context, 2);
+        assert_eq!(decode_udp(other_context.freeze()), None);
+    }
+
+    #[test]
+    fn sniffs_quic() {
+        assert!(is_quic_initial(&[0xc3, 0, 0, 0, 1, 8]));
+        assert!(is_quic_initial(&[0xd0, 0x6b, 0x33, 0x43, 0xcf]));
+        // A DNS query for example.com
+        assert!(!is_quic_initial(&[
+            0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+        ]));
+        assert!(!is_quic_initial(&[0xc3, 0xde, 0xad, 0xbe, 0xef]));
+        assert!(!is_quic_initial(&[0xc3, 0, 0]));
+    }
+}
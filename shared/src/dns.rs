@@ -0,0 +1,472 @@
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use http::{
+    Method, Request, Uri,
+    header::{ACCEPT, CONTENT_TYPE, HOST},
+    uri::Scheme,
+};
+use http_body_util::{Full, combinators::BoxBody};
+use hyper_util::rt::tokio::WithHyperIo;
+use tokio::net::TcpStream;
+use tracing::{debug, error, trace};
+
+use crate::{
+    alpn::AlpnProtocol,
+    http::{
+        HttpEmitter, HttpError, HttpEvent, NoOpListener, upstream_h2, upstream_https,
+        uptstream_http,
+    },
+    system_roots,
+    tls::{TlsConfig, client_tls},
+    upstream::host_matches,
+};
+
+const SYSTEM: &str = "system";
+const DNS_MESSAGE: &str = "application/dns-message";
+const DOH_TIMEOUT: Duration = Duration::from_secs(10);
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+/// Where host names without an override are looked up.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DnsResolver {
+    /// The operating system resolver, which honours `/etc/hosts`.
+    #[default]
+    System,
+    /// A DNS-over-HTTPS server, e.g. `https://1.1.1.1/dns-query`.
+    Doh(Uri),
+}
+
+impl Display for DnsResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DnsResolver::System => write!(f, "{SYSTEM}"),
+            DnsResolver::Doh(uri) => write!(f, "{uri}"),
+        }
+    }
+}
+
+impl FromStr for DnsResolver {
+    type Err = HttpError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() || s.eq_ignore_ascii_case(SYSTEM) {
+            return Ok(DnsResolver::System);
+        }
+        let uri: Uri = s.parse()?;
+        if uri.host().is_none() || uri.scheme().is_none() {
+            return Err(HttpError::Uri);
+        }
+        Ok(DnsResolver::Doh(uri))
+    }
+}
+
+/// Resolves hosts matching `pattern` to `addr` without asking a resolver,
+/// `pattern` takes the same form as upstream rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsOverride {
+    pub pattern: String,
+    pub addr: IpAddr,
+}
+
+impl DnsOverride {
+    pub fn new(pattern: impl Into<String>, addr: IpAddr) -> Self {
+        Self {
+            pattern: pattern.into(),
+            addr,
+        }
+    }
+
+    pub fn parse(pattern: &str, addr: &str) -> Result<Self, HttpError> {
+        let addr = addr
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .map_err(|_| HttpError::Dns(format!("invalid address {addr}")))?;
+        Ok(Self::new(pattern.trim(), addr))
+    }
+
+    pub fn matches(&self, host: &str) -> bool {
+        host_matches(&self.pattern, host)
+    }
+}
+
+/// What a lookup returned and how long it took.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolved {
+    pub addrs: Vec<SocketAddr>,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+struct DnsRules {
+    overrides: Vec<DnsOverride>,
+    resolver: DnsResolver,
+}
+
+/// Overrides checked in order before `resolver` is asked. Clones share the
+/// rules so they can be changed while connections are made.
+#[derive(Debug, Clone, Default)]
+pub struct DnsConfig {
+    rules: Arc<RwLock<DnsRules>>,
+}
+
+impl DnsConfig {
+    pub fn new(overrides: Vec<DnsOverride>, resolver: DnsResolver) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(DnsRules {
+                overrides,
+                resolver,
+            })),
+        }
+    }
+
+    pub fn set(&self, overrides: Vec<DnsOverride>, resolver: DnsResolver) {
+        match self.rules.write() {
+            Ok(mut guard) => {
+                *guard = DnsRules {
+                    overrides,
+                    resolver,
+                }
+            }
+            Err(err) => error!("DNS lock poisoned {err}"),
+        }
+    }
+
+    fn rules(&self) -> DnsRules {
+        match self.rules.read() {
+            Ok(rules) => rules.clone(),
+            Err(err) => {
+                error!("DNS lock poisoned {err}");
+                DnsRules::default()
+            }
+        }
+    }
+
+    pub fn overrides(&self) -> Vec<DnsOverride> {
+        self.rules().overrides
+    }
+
+    pub fn resolver(&self) -> DnsResolver {
+        self.rules().resolver
+    }
+
+    pub async fn resolve(&self, host: &str, port: u16) -> Result<Resolved, HttpError> {
+        let start = Instant::now();
+        let bare = host.trim_start_matches('[').trim_end_matches(']');
+        let rules = self.rules();
+        let addrs = if let Ok(ip) = bare.parse::<IpAddr>() {
+            vec![SocketAddr::new(ip, port)]
+        } else if let Some(o) = rules.overrides.iter().find(|o| o.matches(host)) {
+            trace!("DNS override {host} {}", o.addr);
+            vec![SocketAddr::new(o.addr, port)]
+        } else {
+            match &rules.resolver {
+                DnsResolver::System => tokio::net::lookup_host((bare, port)).await?.collect(),
+                DnsResolver::Doh(uri) => doh_lookup(uri, bare)
+                    .await?
+                    .into_iter()
+                    .map(|ip| SocketAddr::new(ip, port))
+                    .collect(),
+            }
+        };
+        if addrs.is_empty() {
+            return Err(HttpError::Dns(format!("no addresses for {host}")));
+        }
+        let elapsed = start.elapsed();
+        debug!("Resolved {host} to {addrs:?} in {elapsed:?}");
+        Ok(Resolved { addrs, elapsed })
+    }
+
+    /// Connects to the first address of `host` that accepts, emitting the
+    /// lookup and the address that was connected to.
+    pub async fn connect(
+        &self,
+        host: &str,
+        port: u16,
+        emitter: &dyn HttpEmitter,
+    ) -> Result<TcpStream, HttpError> {
+        emitter.emit(HttpEvent::ConnectStarted);
+        let resolved = self.resolve(host, port).await?;
+        emitter.emit(HttpEvent::DnsResolved(resolved.elapsed));
+        let mut last_err = None;
+        for addr in resolved.addrs {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => {
+                    emitter.emit(HttpEvent::TcpConnect(addr));
+                    return Ok(stream);
+                }
+                Err(err) => {
+                    trace!("Connect {addr} failed {err}");
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.map_or(
+            HttpError::Dns(format!("no addresses for {host}")),
+            HttpError::Io,
+        ))
+    }
+}
+
+/// Looks up A records and then AAAA records when there are none.
+async fn doh_lookup(server: &Uri, host: &str) -> Result<Vec<IpAddr>, HttpError> {
+    let mut ips = vec![];
+    for record_type in [TYPE_A, TYPE_AAAA] {
+        let response = tokio::time::timeout(
+            DOH_TIMEOUT,
+            doh_query(server, encode_query(host, record_type)?),
+        )
+        .await??;
+        ips = parse_answers(&response)?;
+        if !ips.is_empty() {
+            break;
+        }
+    }
+    Ok(ips)
+}
+
+/// Sends `query` as an RFC 8484 POST. The server itself is found with the
+/// system resolver.
+async fn doh_query(server: &Uri, query: Vec<u8>) -> Result<Bytes, HttpError> {
+    let host = server.host().ok_or(HttpError::Uri)?;
+    let authority = server.authority().ok_or(HttpError::Uri)?.as_str();
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(server.clone())
+        .header(HOST, authority)
+        .header(CONTENT_TYPE, DNS_MESSAGE)
+        .header(ACCEPT, DNS_MESSAGE)
+        .body(BoxBody::new(Full::new(Bytes::from(query))))?;
+
+    let emitter = NoOpListener {};
+    let response = if server.scheme() == Some(&Scheme::HTTPS) {
+        let port = server.port_u16().unwrap_or(443);
+        let stream = WithHyperIo::new(TcpStream::connect((host, port)).await?);
+        let (stream, alpn) = client_tls(
+            host.to_string().try_into()?,
+            stream,
+            vec![
+                AlpnProtocol::Http2.to_bytes().to_vec(),
+                AlpnProtocol::Http1.to_bytes().to_vec(),
+            ],
+            system_roots(),
+            &emitter,
+            &TlsConfig::default(),
+        )
+        .await?;
+        match alpn {
+            AlpnProtocol::Http2 => upstream_h2(stream, request, &emitter).await?,
+            _ => upstream_https(stream, request, &emitter).await?,
+        }
+    } else {
+        uptstream_http(request, &emitter).await?
+    }
+    .buffered()
+    .await?;
+
+    if !response.parts.status.is_success() {
+        return Err(HttpError::Dns(format!(
+            "DNS-over-HTTPS server answered {}",
+            response.parts.status
+        )));
+    }
+    Ok(response.body)
+}
+
+fn encode_query(host: &str, record_type: u16) -> Result<Vec<u8>, HttpError> {
+    // Id 0 as RFC 8484 recommends, recursion desired, one question
+    let mut query = vec![0, 0, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(HttpError::InvalidDnsName);
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// The A and AAAA records in the answer section of a DNS message.
+fn parse_answers(message: &[u8]) -> Result<Vec<IpAddr>, HttpError> {
+    let invalid = || HttpError::Dns("invalid DNS message".to_string());
+    let u16_at = |pos: usize| -> Result<u16, HttpError> {
+        message
+            .get(pos..pos + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or_else(invalid)
+    };
+
+    let rcode = u16_at(2)? & 0x000f;
+    if rcode != 0 {
+        return Err(HttpError::Dns(format!("DNS error code {rcode}")));
+    }
+    let questions = u16_at(4)?;
+    let answers = u16_at(6)?;
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(message, pos).ok_or_else(invalid)? + 4;
+    }
+    let mut ips = vec![];
+    for _ in 0..answers {
+        pos = skip_name(message, pos).ok_or_else(invalid)?;
+        let record_type = u16_at(pos)?;
+        let len = u16_at(pos + 8)? as usize;
+        let data = message.get(pos + 10..pos + 10 + len).ok_or_else(invalid)?;
+        match (
+            record_type,
+            <[u8; 4]>::try_from(data),
+            <[u8; 16]>::try_from(data),
+        ) {
+            (TYPE_A, Ok(v4), _) => ips.push(IpAddr::V4(Ipv4Addr::from(v4))),
+            (TYPE_AAAA, _, Ok(v6)) => ips.push(IpAddr::V6(Ipv6Addr::from(v6))),
+            _ => {}
+        }
+        pos += 10 + len;
+    }
+    Ok(ips)
+}
+
+/// The position after the name starting at `pos`, names may end in a
+/// compression pointer.
+fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Some(pos + 2),
+            len => pos += 1 + len as usize,
+        }
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    use cow_utils::CowUtils;
+
+    use super::*;
+
+    /// A response to `query` with one A record per address.
+    fn answer(query: &[u8], addrs: &[[u8; 4]]) -> Vec<u8> {
+        let mut message = query.to_vec();
+        message[2] = 0x81;
+        message[3] = 0x80;
+        message[7] = addrs.len() as u8;
+        for addr in addrs {
+            // Name as a pointer to the question
+            message.extend_from_slice(&[0xc0, 12]);
+            message.extend_from_slice(&TYPE_A.to_be_bytes());
+            message.extend_from_slice(&CLASS_IN.to_be_bytes());
+            message.extend_from_slice(&60u32.to_be_bytes());
+            message.extend_from_slice(&4u16.to_be_bytes());
+            message.extend_from_slice(addr);
+        }
+        message
+    }
+
+    #[test]
+    fn parse_config() {
+        assert_eq!(
+            "system".parse::<DnsResolver>().unwrap(),
+            DnsResolver::System
+        );
+        assert_eq!("".parse::<DnsResolver>().unwrap(), DnsResolver::System);
+        assert!(matches!(
+            "https://1.1.1.1/dns-query".parse::<DnsResolver>().unwrap(),
+            DnsResolver::Doh(_)
+        ));
+        assert!("dns-query".parse::<DnsResolver>().is_err());
+
+        let o = DnsOverride::parse("*.staging.test", "[::1]").unwrap();
+        assert_eq!(o.addr, IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert!(o.matches("api.staging.test"));
+        assert!(DnsOverride::parse("a.test", "nope").is_err());
+    }
+
+    #[test]
+    fn query_and_answers() {
+        let query = encode_query("example.com", TYPE_A).unwrap();
+        assert_eq!(&query[12..25], b"\x07example\x03com\x00");
+        assert!(encode_query("a..b", TYPE_A).is_err());
+
+        let ips = parse_answers(&answer(&query, &[[10, 0, 0, 1], [10, 0, 0, 2]])).unwrap();
+        assert_eq!(
+            ips,
+            vec![
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))
+            ]
+        );
+
+        let mut failed = answer(&query, &[]);
+        failed[3] = 0x83;
+        assert!(parse_answers(&failed).is_err());
+        assert!(parse_answers(&query[..20]).is_err());
+    }
+
+    #[tokio::test]
+    async fn overrides_win() {
+        let dns = DnsConfig::new(
+            vec![DnsOverride::parse("*.staging.test", "10.1.2.3").unwrap()],
+            DnsResolver::System,
+        );
+        let resolved = dns.resolve("api.staging.test", 8443).await.unwrap();
+        assert_eq!(resolved.addrs, vec!["10.1.2.3:8443".parse().unwrap()]);
+        let literal = dns.resolve("[::1]", 80).await.unwrap();
+        assert_eq!(literal.addrs, vec!["[::1]:80".parse().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn doh_resolver() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = format!("http://{}/dns-query", listener.local_addr().unwrap());
+        let query_len = encode_query("service.test", TYPE_A).unwrap().len();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = vec![];
+            let mut buf = [0; 1024];
+            let query = loop {
+                let n = stream.read(&mut buf).unwrap();
+                received.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&received);
+                let text = text.cow_to_ascii_lowercase();
+                if let Some(end) = text.find("\r\n\r\n")
+                    && received.len() >= end + 4 + query_len
+                {
+                    break received[end + 4..].to_vec();
+                }
+            };
+            let body = answer(&query, &[[127, 0, 0, 9]]);
+            let head = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: {DNS_MESSAGE}\r\ncontent-length: {}\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+        });
+
+        let dns = DnsConfig::new(vec![], server.parse().unwrap());
+        let resolved = dns.resolve("service.test", 80).await.unwrap();
+        assert_eq!(resolved.addrs, vec!["127.0.0.9:80".parse().unwrap()]);
+    }
+}
@@ -1,90 +1,133 @@
-use std::{error::Error, io, sync::Arc};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, io,
+    net::{Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+};
 
 use bytes::{Buf, Bytes, BytesMut};
+use cow_utils::CowUtils;
 use futures_util::future;
-use h3::{client::RequestStream, error::StreamError, ext::Protocol};
-use http_body_util::BodyExt;
-use quinn::{VarInt, crypto::rustls::QuicClientConfig};
+use h3::{
+    client::{RequestStream, SendRequest},
+    error::StreamError,
+    ext::Protocol,
+};
+use http_body_util::{BodyExt, Full, combinators::BoxBody};
+use hyper::body::Body;
+use quinn::{VarInt, ZeroRttAccepted, crypto::rustls::QuicClientConfig};
+use tokio::task::JoinHandle;
 
 use crate::{
     alpn::alp_h3,
     body::BytesBody,
-    http::{HttpEmitter, HttpError, HttpResponse},
+    datagram::{decode_udp, udp_prefix},
+    dns::DnsConfig,
+    http::{HttpEmitter, HttpError, HttpEvent, HttpResponse},
+    pool::{ConnectionInfo, ConnectionPool, PoolKey},
+    tls::TlsConfig,
     uri::RUri,
 };
 use http::{
     Method, Request,
     header::{HOST, TE, TRAILER},
+    request::Parts,
+};
+use rustls::{
+    RootCertStore,
+    client::{ClientSessionMemoryCache, ClientSessionStore, Resumption},
 };
-use rustls::RootCertStore;
 use tracing::{debug, error, trace};
 
-use h3_quinn::{BidiStream, quinn};
+use h3_quinn::{BidiStream, OpenStreams, quinn};
+
+/// Servers a pool keeps session tickets for.
+const MAX_SESSIONS: usize = 256;
 
-pub async fn h3_with_proxy(
+/// Sends `request` over a QUIC connection open in `pool`, or a new one to
+/// its authority or the upstream proxy.
+pub(crate) async fn h3_with_proxy(
     proxy_uri: Option<&RUri>,
+    dns: &DnsConfig,
+    tls: &TlsConfig,
     roots: Arc<RootCertStore>,
+    pool: Option<(&ConnectionPool, PoolKey)>,
     request: Request<BytesBody>,
     emitter: &dyn HttpEmitter,
 ) -> Result<HttpResponse, HttpError> {
-    h3_with_proxy_inner(proxy_uri, roots, request, emitter)
+    h3_with_proxy_inner(proxy_uri, dns, tls, roots, pool, request, emitter)
         .await
         .map_err(|_| HttpError::ProxyConnect)
 }
 
 async fn h3_with_proxy_inner(
     proxy_uri: Option<&RUri>,
+    dns: &DnsConfig,
+    tls: &TlsConfig,
     roots: Arc<RootCertStore>,
+    pool: Option<(&ConnectionPool, PoolKey)>,
     request: Request<BytesBody>,
     emitter: &dyn HttpEmitter,
-) -> Result<HttpResponse, Box<dyn Error>> {
+) -> Result<HttpResponse, Box<dyn Error + Send + Sync>> {
     debug!("Proxy_addr  {:?}", proxy_uri);
     debug!("Target_addr {}", request.uri());
 
-    let connect_uri = proxy_uri.map(|uri| uri.host_port()).unwrap_or(format!(
-        "{}:{}",
-        request.uri().host().unwrap_or("localhost"),
-        request.uri().port_u16().unwrap_or(443)
-    ));
+    let (mut parts, body) = request.into_parts();
+    parts.headers.remove(TE); // TODO: SOMETHING funky here
+    // Host needs to be removed for H3 to work
+    parts.headers.remove(HOST);
+
+    if let Some((pool, key)) = &pool
+        && let Some((send_request, info)) = pool.checkout_h3(key)
+    {
+        trace!("Reused QUIC connection to {}:{}", key.host, key.port);
+        emitter.emit(HttpEvent::ConnectionReused(info));
+        return send(send_request, head(&parts), body).await;
+    }
 
     let host_name = proxy_uri.map(|uri| uri.host()).unwrap_or("localhost");
-    let socket_addr = tokio::net::lookup_host(connect_uri).await?;
+    emitter.emit(HttpEvent::ConnectStarted);
+    let socket_addr: Vec<_> = match proxy_uri {
+        Some(uri) => tokio::net::lookup_host(uri.host_port()).await?.collect(),
+        None => {
+            let resolved = dns
+                .resolve(
+                    parts.uri.host().unwrap_or("localhost"),
+                    parts.uri.port_u16().unwrap_or(443),
+                )
+                .await?;
+            emitter.emit(HttpEvent::DnsResolved(resolved.elapsed));
+            resolved.addrs
+        }
+    };
 
-    let mut tls_config = rustls::ClientConfig::builder()
-        .with_root_certificates(roots)
-        .with_no_client_auth();
+    let mut tls_config = tls.rustls_client_config(roots, host_name).client_config;
 
     tls_config.enable_early_data = true;
     tls_config.alpn_protocols = alp_h3();
-
-    let mut quinn_endpoint = h3_quinn::quinn::Endpoint::client("[::]:0".parse()?)?;
-    let client_config = quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(tls_config)?));
-    quinn_endpoint.set_default_client_config(client_config);
-
-    let mut connection = None;
-    for addr in socket_addr {
-        emitter.emit(crate::http::HttpEvent::TcpConnect(addr));
-        if let Ok(conn) = quinn_endpoint.connect(addr, host_name)?.await {
-            connection = Some(conn);
-            break;
+    let endpoint = match &pool {
+        Some((pool, _)) => {
+            // Tickets from earlier connections let new ones resume
+            tls_config.resumption = Resumption::store(pool.h3().sessions());
+            pool.h3().endpoint()?
         }
-    }
-
-    let conn = connection.ok_or(io::Error::other(format!(
-        "DNS look up for {host_name} failed"
-    )))?;
-
-    let (mut driver, mut send_request) = h3::client::builder()
-        .enable_extended_connect(true)
-        .enable_datagram(true)
-        .send_grease(true)
-        .build(h3_quinn::Connection::new(conn))
-        .await?;
+        None => quinn::Endpoint::client("[::]:0".parse()?)?,
+    };
+    let client_config = quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(tls_config)?));
 
-    let drive = tokio::spawn(async move {
-        let res = future::poll_fn(|cx| driver.poll_close(cx)).await;
-        error!("Connection close {res}");
-    });
+    // Early data can be replayed, only requests without side effects go out as 0-RTT
+    let early_data = proxy_uri.is_none() && parts.method.is_safe() && body.is_end_stream();
+    let (mut conn, zero_rtt) = connect(
+        &endpoint,
+        &client_config,
+        &socket_addr,
+        host_name,
+        early_data,
+        emitter,
+    )
+    .await?;
+    let (mut send_request, mut drive) = handshake(conn.clone()).await?;
 
     if proxy_uri.is_some() {
         let req = http::Request::builder()
@@ -97,17 +140,124 @@ async fn h3_with_proxy_inner(
         stream.finish().await?;
         let resp = stream.recv_response().await?;
         if resp.into_parts().0.status != 200 {
+            drive.abort();
             return Err(Box::new(HttpError::ProxyConnect));
         }
     }
 
     debug!("REQUEST ...");
-    let (mut parts, mut body) = request.into_parts();
-    parts.headers.remove(TE); // TODO: SOMETHING funky here
-    // Host needs to be removed for H3 to work
-    parts.headers.remove(HOST);
+    let mut response = send(send_request.clone(), head(&parts), body).await;
+    if let Some(accepted) = zero_rtt
+        && !accepted.await
+    {
+        // Streams opened before the handshake are lost, along with the h3 control streams
+        debug!("0-RTT rejected by {host_name}, sending again");
+        drive.abort();
+        (conn, _) = connect(
+            &endpoint,
+            &client_config,
+            &socket_addr,
+            host_name,
+            false,
+            emitter,
+        )
+        .await?;
+        (send_request, drive) = handshake(conn.clone()).await?;
+        response = send(
+            send_request.clone(),
+            head(&parts),
+            BoxBody::new(Full::new(Bytes::new())),
+        )
+        .await;
+    }
+
+    match pool {
+        Some((pool, key)) if response.is_ok() => {
+            let info = ConnectionInfo {
+                addr: Some(conn.remote_address()),
+                tls: None,
+            };
+            pool.checkin_h3(
+                key,
+                H3Connection {
+                    conn,
+                    send_request,
+                    info,
+                    driver: drive,
+                },
+            );
+        }
+        _ => drive.abort(),
+    }
+    response
+}
+
+/// Connects to the first of `addrs` that answers. With `early_data` the
+/// connection is returned before the handshake completes when a session can
+/// be resumed, along with whether the server accepted the early data.
+async fn connect(
+    endpoint: &quinn::Endpoint,
+    client_config: &quinn::ClientConfig,
+    addrs: &[SocketAddr],
+    host_name: &str,
+    early_data: bool,
+    emitter: &dyn HttpEmitter,
+) -> Result<(quinn::Connection, Option<ZeroRttAccepted>), Box<dyn Error + Send + Sync>> {
+    for addr in addrs {
+        emitter.emit(HttpEvent::TcpConnect(*addr));
+        let connecting = endpoint.connect_with(client_config.clone(), *addr, host_name)?;
+        let connecting = if early_data {
+            match connecting.into_0rtt() {
+                Ok((conn, accepted)) => {
+                    trace!("Sending 0-RTT to {host_name}");
+                    return Ok((conn, Some(accepted)));
+                }
+                Err(connecting) => connecting,
+            }
+        } else {
+            connecting
+        };
+        if let Ok(conn) = connecting.await {
+            return Ok((conn, None));
+        }
+    }
+    Err(Box::new(io::Error::other(format!(
+        "DNS look up for {host_name} failed"
+    ))))
+}
 
-    let req = Request::from_parts(parts, ());
+async fn handshake(
+    conn: quinn::Connection,
+) -> Result<(H3Sender, JoinHandle<()>), Box<dyn Error + Send + Sync>> {
+    let (mut driver, send_request) = h3::client::builder()
+        .enable_extended_connect(true)
+        .enable_datagram(true)
+        .send_grease(true)
+        .build(h3_quinn::Connection::new(conn))
+        .await?;
+
+    let drive = tokio::spawn(async move {
+        let res = future::poll_fn(|cx| driver.poll_close(cx)).await;
+        debug!("Connection close {res}");
+    });
+    Ok((send_request, drive))
+}
+
+/// The head of a request, kept to send it again.
+fn head(parts: &Parts) -> Request<()> {
+    let mut request = Request::new(());
+    *request.method_mut() = parts.method.clone();
+    *request.uri_mut() = parts.uri.clone();
+    *request.version_mut() = parts.version;
+    *request.headers_mut() = parts.headers.clone();
+    request
+}
+
+async fn send(
+    mut send_request: H3Sender,
+    req: Request<()>,
+    mut body: BytesBody,
+) -> Result<HttpResponse, Box<dyn Error + Send + Sync>> {
     let mut stream = send_request.send_request(req).await?;
 
     while let Some(Ok(frame)) = body.frame().await {
@@ -134,8 +284,6 @@ async fn h3_with_proxy_inner(
         None
     };
 
-    drive.abort();
-
     Ok(HttpResponse {
         parts: response_parts,
         body,
@@ -143,6 +291,214 @@ async fn h3_with_proxy_inner(
     })
 }
 
+pub(crate) type H3Sender = SendRequest<OpenStreams, Bytes>;
+
+/// An open QUIC connection kept by a [`ConnectionPool`], requests to its
+/// authority are multiplexed over it until the server or the idle timeout
+/// closes it.
+pub(crate) struct H3Connection {
+    conn: quinn::Connection,
+    send_request: H3Sender,
+    info: ConnectionInfo,
+    driver: JoinHandle<()>,
+}
+
+impl Drop for H3Connection {
+    fn drop(&mut self) {
+        self.driver.abort();
+    }
+}
+
+/// The HTTP/3 side of a [`ConnectionPool`]: one UDP socket for every
+/// connection, the open connections and the session tickets servers issued.
+pub(crate) struct H3Pool {
+    endpoint: Mutex<Option<quinn::Endpoint>>,
+    connections: Mutex<HashMap<PoolKey, H3Connection>>,
+    sessions: Arc<ClientSessionMemoryCache>,
+}
+
+impl Default for H3Pool {
+    fn default() -> Self {
+        Self {
+            endpoint: Mutex::default(),
+            connections: Mutex::default(),
+            sessions: Arc::new(ClientSessionMemoryCache::new(MAX_SESSIONS)),
+        }
+    }
+}
+
+impl fmt::Debug for H3Pool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("H3Pool")
+            .field("connections", &self.count())
+            .finish_non_exhaustive()
+    }
+}
+
+impl H3Pool {
+    fn endpoint(&self) -> io::Result<quinn::Endpoint> {
+        let mut endpoint = self
+            .endpoint
+            .lock()
+            .map_err(|err| io::Error::other(format!("QUIC endpoint lock poisoned {err}")))?;
+        if let Some(endpoint) = endpoint.as_ref() {
+            return Ok(endpoint.clone());
+        }
+        let created = quinn::Endpoint::client(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)))?;
+        *endpoint = Some(created.clone());
+        Ok(created)
+    }
+
+    fn sessions(&self) -> Arc<dyn ClientSessionStore> {
+        self.sessions.clone()
+    }
+
+    pub(crate) fn checkout(&self, key: &PoolKey) -> Option<(H3Sender, ConnectionInfo)> {
+        let mut connections = match self.connections.lock() {
+            Ok(connections) => connections,
+            Err(err) => {
+                error!("Connection pool lock poisoned {err}");
+                return None;
+            }
+        };
+        let connection = connections.get(key)?;
+        if connection.conn.close_reason().is_some() {
+            connections.remove(key);
+            return None;
+        }
+        Some((connection.send_request.clone(), connection.info.clone()))
+    }
+
+    /// Keeps `connection` for `key`, one connection per key is enough as
+    /// every request gets a stream of its own.
+    pub(crate) fn put(&self, key: PoolKey, connection: H3Connection) {
+        match self.connections.lock() {
+            Ok(mut connections) => {
+                connections.retain(|_, c| c.conn.close_reason().is_none());
+                connections.entry(key).or_insert(connection);
+            }
+            Err(err) => error!("Connection pool lock poisoned {err}"),
+        }
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        match self.connections.lock() {
+            Ok(connections) => connections.len(),
+            Err(err) => {
+                error!("Connection pool lock poisoned {err}");
+                0
+            }
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        match self.connections.lock() {
+            Ok(mut connections) => connections.clear(),
+            Err(err) => error!("Connection pool lock poisoned {err}"),
+        }
+    }
+}
+
+/// A CONNECT-UDP tunnel (RFC 9298) through an HTTP/3 proxy. UDP payloads
+/// to and from the target travel as HTTP datagrams of the proxy connection,
+/// the tunnel closes when it is dropped.
+pub struct UdpTunnel {
+    conn: quinn::Connection,
+    quarter_id: u64,
+    prefix: Bytes,
+    _stream: RequestStream<BidiStream<Bytes>, Bytes>,
+    driver: JoinHandle<()>,
+}
+
+impl UdpTunnel {
+    /// Sends one UDP payload to the target, waiting while the connection's
+    /// datagram buffer is full.
+    pub async fn send(&self, payload: &[u8]) -> io::Result<()> {
+        let mut datagram = BytesMut::with_capacity(self.prefix.len() + payload.len());
+        datagram.extend_from_slice(&self.prefix);
+        datagram.extend_from_slice(payload);
+        self.conn
+            .send_datagram_wait(datagram.freeze())
+            .await
+            .map_err(io::Error::other)
+    }
+
+    /// The next UDP payload from the target, datagrams of other tunnels on the
+    /// same connection are skipped.
+    pub async fn recv(&self) -> io::Result<Bytes> {
+        loop {
+            let datagram = self.conn.read_datagram().await.map_err(io::Error::other)?;
+            if let Some((quarter_id, payload)) = decode_udp(datagram)
+                && quarter_id == self.quarter_id
+            {
+                return Ok(payload);
+            }
+        }
+    }
+}
+
+impl Drop for UdpTunnel {
+    fn drop(&mut self) {
+        self.driver.abort();
+        self.conn.close(VarInt::from_u32(0x100), &[]);
+    }
+}
+
+/// Opens a CONNECT-UDP tunnel to `target_uri`'s host and port through the
+/// HTTP/3 proxy at `proxy_uri`, using the default masque uri template.
+pub async fn connect_udp(
+    proxy_uri: &RUri,
+    target_uri: &RUri,
+    roots: Arc<RootCertStore>,
+) -> Result<UdpTunnel, Box<dyn Error + Send + Sync>> {
+    let addr = tokio::net::lookup_host(proxy_uri.host_port())
+        .await?
+        .next()
+        .ok_or("dns found no addresses")?;
+
+    let mut tls_config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    tls_config.alpn_protocols = alp_h3();
+
+    let mut endpoint = quinn::Endpoint::client("[::]:0".parse()?)?;
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+        QuicClientConfig::try_from(tls_config)?,
+    )));
+    let conn = endpoint.connect(addr, proxy_uri.host())?.await?;
+    let (mut send_request, driver) = handshake(conn.clone()).await?;
+
+    let host = target_uri
+        .host()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let req = http::Request::builder()
+        .method(Method::CONNECT)
+        .extension(Protocol::CONNECT_UDP)
+        .uri(format!(
+            "https://{}/.well-known/masque/udp/{}/{}/",
+            proxy_uri.host_port(),
+            host.cow_replace(':', "%3A"),
+            target_uri.port()
+        ))
+        .header("capsule-protocol", "?1")
+        .body(())?;
+    let mut stream = send_request.send_request(req).await?;
+    let resp = stream.recv_response().await?;
+    if resp.status() != 200 {
+        driver.abort();
+        return Err(Box::new(HttpError::ProxyConnect));
+    }
+
+    Ok(UdpTunnel {
+        conn,
+        quarter_id: stream.id().index(),
+        prefix: udp_prefix(stream.id().index()),
+        _stream: stream,
+        driver,
+    })
+}
+
 pub async fn client_h3_wt(
     proxy_uri: Option<&RUri>,
     target_uri: &RUri,
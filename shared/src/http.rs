@@ -4,9 +4,16 @@ use http::Request;
 use http::Response;
 use http::Uri;
 use http::uri::InvalidUri;
-use http::{Method, header::HOST, response::Parts};
+use http::{
+    Method, StatusCode,
+    header::{CONTENT_ENCODING, CONTENT_LENGTH, HOST},
+    response::Parts,
+};
 use http_body_util::BodyExt;
+use http_body_util::Collected;
 use http_body_util::Empty;
+use http_body_util::Limited;
+use hyper::body::{Body, Frame, Incoming, SizeHint};
 use hyper::client::conn::http1;
 use hyper::rt::Read;
 use hyper::rt::Write;
@@ -15,6 +22,8 @@ use hyper_util::rt::tokio::WithHyperIo;
 use rustls::pki_types::InvalidDnsNameError;
 use std::error::Error;
 use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
 use std::time::Duration;
 use tokio::time::error::Elapsed;
 use tokio::time::timeout;
@@ -24,10 +33,14 @@ use tokio::net::TcpStream;
 use tracing::error;
 
 use crate::body::BytesBody;
+use crate::body::create_http_body;
 use crate::cert::ClientTlsConnectionData;
 use crate::cert::ClientVerificationCapture;
 use crate::cert::ServerTlsConnectionData;
 use crate::cert::ServerVerificationCapture;
+use crate::content::{ContentType, content_type};
+use crate::pool::ConnectionInfo;
+use crate::redirect::Redirect;
 use crate::uri::RUri;
 type H1ClientBuilder = hyper::client::conn::http1::Builder;
 
@@ -39,8 +52,15 @@ pub struct HttpResponse {
 }
 
 pub async fn try_from(res: Response<hyper::body::Incoming>) -> Result<HttpResponse, HttpError> {
+    collect(res, None).await
+}
+
+async fn collect(
+    res: Response<hyper::body::Incoming>,
+    max_body_size: Option<usize>,
+) -> Result<HttpResponse, HttpError> {
     let (parts, body) = res.into_parts();
-    let collected = body.collect().await?;
+    let collected = collect_body(body, max_body_size).await?;
     let trailers = collected.trailers().cloned();
     let body = collected.to_bytes();
     Ok(HttpResponse {
@@ -50,8 +70,206 @@ pub async fn try_from(res: Response<hyper::body::Incoming>) -> Result<HttpRespon
     })
 }
 
+/// Reads all of `body`, failing with [`HttpError::BodyTooLarge`] once it
+/// passes `max_body_size`.
+pub async fn collect_body(
+    body: Incoming,
+    max_body_size: Option<usize>,
+) -> Result<Collected<Bytes>, HttpError> {
+    match max_body_size {
+        Some(max) => Limited::new(body, max).collect().await.map_err(|err| {
+            match err.downcast::<hyper::Error>() {
+                Ok(err) => HttpError::Hyper(*err),
+                Err(_) => HttpError::BodyTooLarge(max),
+            }
+        }),
+        None => Ok(body.collect().await?),
+    }
+}
+
+/// A response that may still be receiving its body.
+#[derive(Debug)]
+pub enum HttpStreamResponse {
+    Buffered(HttpResponse),
+    /// A `text/event-stream` of unknown length, events are read from the body
+    /// as the server sends them.
+    EventStream(Parts, Incoming),
+    /// Any other body left unread, for callers that asked to stream it.
+    Streaming(Parts, Incoming),
+}
+
+impl HttpStreamResponse {
+    pub fn parts(&self) -> &Parts {
+        match self {
+            HttpStreamResponse::Buffered(response) => &response.parts,
+            HttpStreamResponse::EventStream(parts, _) | HttpStreamResponse::Streaming(parts, _) => {
+                parts
+            }
+        }
+    }
+
+    /// Waits for the rest of the body.
+    pub async fn buffered(self) -> Result<HttpResponse, HttpError> {
+        match self {
+            HttpStreamResponse::Buffered(response) => Ok(response),
+            HttpStreamResponse::EventStream(parts, body)
+            | HttpStreamResponse::Streaming(parts, body) => {
+                try_from(Response::from_parts(parts, body)).await
+            }
+        }
+    }
+
+    /// The response with a body read as the caller polls it.
+    pub fn into_streaming(self) -> Response<ResponseBody> {
+        match self {
+            HttpStreamResponse::Buffered(response) => Response::from_parts(
+                response.parts,
+                ResponseBody::Buffered(create_http_body(response.body, None, response.trailers)),
+            ),
+            HttpStreamResponse::EventStream(parts, body)
+            | HttpStreamResponse::Streaming(parts, body) => {
+                Response::from_parts(parts, ResponseBody::Incoming(body))
+            }
+        }
+    }
+}
+
+/// The body of a response from [`ClientContext::request_streaming`], frames
+/// are read off the connection only as they are polled.
+///
+/// [`ClientContext::request_streaming`]: crate::client::ClientContext::request_streaming
+#[derive(Debug)]
+pub enum ResponseBody {
+    Incoming(Incoming),
+    /// Bodies that arrived whole, e.g. over HTTP/3.
+    Buffered(BytesBody),
+}
+
+impl ResponseBody {
+    /// The next chunk of the body, `None` once it ended. Trailers are skipped.
+    pub async fn data(&mut self) -> Option<Result<Bytes, HttpError>> {
+        loop {
+            match self.frame().await? {
+                Ok(frame) => {
+                    if let Ok(data) = frame.into_data() {
+                        return Some(Ok(data));
+                    }
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+impl Body for ResponseBody {
+    type Data = Bytes;
+    type Error = HttpError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.get_mut() {
+            ResponseBody::Incoming(body) => Pin::new(body).poll_frame(cx).map_err(HttpError::Hyper),
+            ResponseBody::Buffered(body) => Pin::new(body)
+                .poll_frame(cx)
+                .map_err(|never| match never {}),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self {
+            ResponseBody::Incoming(body) => body.is_end_stream(),
+            ResponseBody::Buffered(body) => body.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self {
+            ResponseBody::Incoming(body) => body.size_hint(),
+            ResponseBody::Buffered(body) => body.size_hint(),
+        }
+    }
+}
+
+/// Leaves the body of plain, open ended event streams to the caller, every
+/// other response is buffered.
+pub async fn stream_from(res: Response<Incoming>) -> Result<HttpStreamResponse, HttpError> {
+    stream_limited(res, None).await
+}
+
+async fn stream_limited(
+    res: Response<Incoming>,
+    max_body_size: Option<usize>,
+) -> Result<HttpStreamResponse, HttpError> {
+    let headers = res.headers();
+    if content_type(headers) == Some(ContentType::EventStream)
+        && !headers.contains_key(CONTENT_LENGTH)
+        && !headers.contains_key(CONTENT_ENCODING)
+    {
+        let (parts, body) = res.into_parts();
+        return Ok(HttpStreamResponse::EventStream(parts, body));
+    }
+    collect(res, max_body_size)
+        .await
+        .map(HttpStreamResponse::Buffered)
+}
+
+/// Bounds on receiving a response, nothing is bounded by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResponseLimits {
+    /// Longest wait for the response head, and again for the rest of its body.
+    pub read_timeout: Option<Duration>,
+    /// Largest body buffered, bigger ones fail with [`HttpError::BodyTooLarge`].
+    /// Event streams are passed on as they arrive and are not bounded.
+    pub max_body_size: Option<usize>,
+}
+
+impl ResponseLimits {
+    /// Waits for the response head `send` resolves to.
+    pub(crate) async fn head<F, E>(
+        &self,
+        send: F,
+    ) -> Result<Result<Response<Incoming>, E>, HttpError>
+    where
+        F: Future<Output = Result<Response<Incoming>, E>>,
+    {
+        Ok(within(self.read_timeout, send).await?)
+    }
+
+    /// Reads the body of `res` unless it is left to stream. With `streaming`
+    /// set every body is left to the caller and neither limit applies to it.
+    pub(crate) async fn body(
+        &self,
+        res: Response<Incoming>,
+        streaming: bool,
+    ) -> Result<HttpStreamResponse, HttpError> {
+        if streaming {
+            let (parts, body) = res.into_parts();
+            return Ok(HttpStreamResponse::Streaming(parts, body));
+        }
+        within(self.read_timeout, stream_limited(res, self.max_body_size)).await?
+    }
+}
+
+/// Runs `fut` to completion, or until `limit` passes when there is one.
+pub(crate) async fn within<F: Future>(
+    limit: Option<Duration>,
+    fut: F,
+) -> Result<F::Output, Elapsed> {
+    match limit {
+        Some(limit) => timeout(limit, fut).await,
+        None => Ok(fut.await),
+    }
+}
+
 #[derive(Debug)]
 pub enum HttpEvent {
+    /// A connection to the server is being opened, its host is looked up
+    /// next.
+    ConnectStarted,
+    /// The server's host was looked up, with how long that took.
+    DnsResolved(Duration),
     TcpConnect(SocketAddr),
 
     ClientHttpHandshakeStart,
@@ -62,6 +280,17 @@ pub enum HttpEvent {
 
     ServerTlsConnInitiated,
     ServerTlsConn(ServerTlsConnectionData, ClientVerificationCapture),
+
+    /// The request went over a pooled connection, nothing was connected or
+    /// negotiated for it.
+    ConnectionReused(ConnectionInfo),
+
+    /// A redirect was followed, the request is sent again to its location.
+    Redirected(Redirect),
+
+    /// An interim 1xx response such as 103 Early Hints arrived ahead of the
+    /// final one. Only HTTP/1 connections report them.
+    Informational(StatusCode, HeaderMap),
     // pub server_conn_initiated: Option<DateTime<Utc>>,
     // pub server_conn_tcp_handshake: Option<DateTime<Utc>>,
     //
@@ -103,13 +332,34 @@ pub enum HttpError {
     Uri,
     InvalidDnsName,
     Timeout,
+    ConnectTimeout,
+    BodyTooLarge(usize),
     ProxyConnect,
     TlsError(std::io::Error),
     BadHost,
+    Dns(String),
 }
 
 impl Error for HttpError {}
 
+impl HttpError {
+    /// Whether sending the request again could succeed, i.e. the connection
+    /// failed or closed rather than the request being invalid.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            HttpError::Io(_)
+            | HttpError::Timeout
+            | HttpError::ConnectTimeout
+            | HttpError::ProxyConnect
+            | HttpError::Dns(_) => true,
+            HttpError::Hyper(err) => {
+                err.is_closed() || err.is_incomplete_message() || err.is_timeout()
+            }
+            _ => false,
+        }
+    }
+}
+
 impl std::fmt::Display for HttpError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{self:?}")
@@ -190,7 +440,7 @@ pub async fn uptstream_http_connected(
     request: Request<BytesBody>,
     stream: WithHyperIo<TcpStream>,
     emitter: &dyn HttpEmitter,
-) -> Result<HttpResponse, HttpError> {
+) -> Result<HttpStreamResponse, HttpError> {
     emitter.emit(HttpEvent::ClientHttpHandshakeStart);
     let (mut sender, conn) = H1ClientBuilder::new()
         .title_case_headers(true)
@@ -204,13 +454,13 @@ pub async fn uptstream_http_connected(
         }
     });
 
-    try_from(sender.send_request(request).await?).await
+    stream_from(sender.send_request(request).await?).await
 }
 
 pub async fn uptstream_http(
     request: Request<BytesBody>,
     emitter: &dyn HttpEmitter,
-) -> Result<HttpResponse, HttpError> {
+) -> Result<HttpStreamResponse, HttpError> {
     let connect_host = format!(
         "{}:{:?}",
         request.uri().host().unwrap_or("localhost"),
@@ -225,7 +475,7 @@ pub async fn uptstream_http_with_proxy(
     proxy_uri: &RUri,
     request: Request<BytesBody>,
     emitter: &dyn HttpEmitter,
-) -> Result<HttpResponse, HttpError> {
+) -> Result<HttpStreamResponse, HttpError> {
     let io = WithHyperIo::new(TcpStream::connect(proxy_uri.host_port()).await?);
     uptstream_http_connected(request, io, emitter).await
 }
@@ -234,7 +484,7 @@ pub async fn upstream_https<S>(
     tls: S,
     request: Request<BytesBody>,
     emitter: &dyn HttpEmitter,
-) -> Result<HttpResponse, HttpError>
+) -> Result<HttpStreamResponse, HttpError>
 where
     S: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
 {
@@ -252,14 +502,14 @@ where
             error!("Upstream HS connection error: {}", e);
         }
     });
-    try_from(sender.send_request(request).await?).await
+    stream_from(sender.send_request(request).await?).await
 }
 
 pub async fn upstream_h2<S>(
     tls: S,
     request: Request<BytesBody>,
     emitter: &dyn HttpEmitter,
-) -> Result<HttpResponse, HttpError>
+) -> Result<HttpStreamResponse, HttpError>
 where
     S: Read + Write + Unpin + Send + 'static,
 {
@@ -274,5 +524,5 @@ where
         }
     });
 
-    try_from(upstream_sender.send_request(request).await?).await
+    stream_from(upstream_sender.send_request(request).await?).await
 }
@@ -0,0 +1,99 @@
+use std::{
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use rustls::KeyLog;
+use tracing::{debug, error};
+
+/// The environment variable browsers and curl read the key log path from.
+pub const SSLKEYLOGFILE: &str = "SSLKEYLOGFILE";
+
+/// Appends TLS secrets to a file in the NSS key log format, which Wireshark
+/// uses to decrypt captured TLS traffic.
+#[derive(Debug)]
+pub struct KeyLogWriter {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl KeyLogWriter {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        debug!("Logging TLS keys to {}", path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Opens the file named by `SSLKEYLOGFILE`, `None` when it is not set.
+    pub fn from_env() -> Option<std::io::Result<Self>> {
+        let path = std::env::var_os(SSLKEYLOGFILE).filter(|p| !p.is_empty())?;
+        Some(Self::open(Path::new(&path)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// `label client_random secret`, the randoms and secrets in hex.
+fn entry(label: &str, client_random: &[u8], secret: &[u8]) -> String {
+    let mut line =
+        String::with_capacity(label.len() + 2 * (client_random.len() + secret.len()) + 3);
+    line.push_str(label);
+    line.push(' ');
+    for b in client_random {
+        let _ = write!(line, "{b:02x}");
+    }
+    line.push(' ');
+    for b in secret {
+        let _ = write!(line, "{b:02x}");
+    }
+    line.push('\n');
+    line
+}
+
+impl KeyLog for KeyLogWriter {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let line = entry(label, client_random, secret);
+        match self.file.lock() {
+            Ok(mut file) => {
+                if let Err(err) = file.write_all(line.as_bytes()) {
+                    error!("Failed to write key log {} {err}", self.path.display());
+                }
+            }
+            Err(err) => error!("Key log lock poisoned {err}"),
+        }
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_entries() {
+        let path = std::env::temp_dir().join(format!("roxy-keys-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let key_log = KeyLogWriter::open(&path).unwrap();
+        key_log.log("CLIENT_RANDOM", &[0x01, 0xab], &[0xff]);
+        key_log.log("CLIENT_TRAFFIC_SECRET_0", &[0x00], &[0x10, 0x20]);
+
+        let reopened = KeyLogWriter::open(&path).unwrap();
+        reopened.log("SERVER_TRAFFIC_SECRET_0", &[0x00], &[0x30]);
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "CLIENT_RANDOM 01ab ff\n\
+             CLIENT_TRAFFIC_SECRET_0 00 1020\n\
+             SERVER_TRAFFIC_SECRET_0 00 30\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
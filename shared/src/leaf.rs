@@ -0,0 +1,197 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use aws_lc_rs::digest::{SHA256, digest};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use tracing::{debug, error, warn};
+
+/// How many hosts are kept in memory before the cache starts evicting.
+const MAX_LEAVES: usize = 1024;
+
+/// A leaf certificate signed by the Roxy CA along with its private key.
+#[derive(Debug)]
+pub struct LeafCert {
+    pub cert_der: CertificateDer<'static>,
+    pub key_der: PrivateKeyDer<'static>,
+}
+
+impl LeafCert {
+    pub fn key(&self) -> PrivateKeyDer<'static> {
+        self.key_der.clone_key()
+    }
+
+    fn from_pem(pem: &[u8]) -> Option<Self> {
+        Some(Self {
+            cert_der: CertificateDer::from_pem_slice(pem).ok()?,
+            key_der: PrivateKeyDer::from_pem_slice(pem).ok()?,
+        })
+    }
+}
+
+/// Leaf certificates by host, so repeated connections skip key generation
+/// and signing. Optionally also kept on disk across restarts.
+#[derive(Debug, Default)]
+pub(crate) struct LeafCache {
+    leaves: Mutex<HashMap<String, Arc<LeafCert>>>,
+    dir: RwLock<Option<PathBuf>>,
+}
+
+impl LeafCache {
+    pub(crate) fn set_dir(&self, dir: Option<PathBuf>) {
+        match self.dir.write() {
+            Ok(mut guard) => *guard = dir,
+            Err(err) => error!("Leaf cache lock poisoned {err}"),
+        }
+    }
+
+    /// The cached leaf for `host`, or the one `sign` creates along with its
+    /// PEM encoding. `ca_der` and `tag` keep leaves of other CAs or key types
+    /// on disk apart.
+    pub(crate) fn get_or_sign<E>(
+        &self,
+        host: &str,
+        ca_der: &[u8],
+        tag: &str,
+        sign: impl FnOnce() -> Result<(LeafCert, String), E>,
+    ) -> Result<Arc<LeafCert>, E> {
+        if let Some(leaf) = self.get(host) {
+            return Ok(leaf);
+        }
+        let path = self.path(host, ca_der, tag);
+        let stored = path
+            .as_ref()
+            .and_then(|p| fs::read(p).ok())
+            .and_then(|pem| LeafCert::from_pem(&pem));
+        let leaf = match stored {
+            Some(leaf) => {
+                debug!("Loaded leaf certificate for {host} from disk");
+                leaf
+            }
+            None => {
+                let (leaf, pem) = sign()?;
+                if let Some(path) = &path {
+                    let written = path
+                        .parent()
+                        .map_or(Ok(()), fs::create_dir_all)
+                        .and_then(|_| fs::write(path, pem));
+                    if let Err(err) = written {
+                        warn!("Failed to write leaf certificate {} {err}", path.display());
+                    }
+                }
+                leaf
+            }
+        };
+        let leaf = Arc::new(leaf);
+        self.insert(host, leaf.clone());
+        Ok(leaf)
+    }
+
+    fn get(&self, host: &str) -> Option<Arc<LeafCert>> {
+        match self.leaves.lock() {
+            Ok(leaves) => leaves.get(host).cloned(),
+            Err(err) => {
+                error!("Leaf cache lock poisoned {err}");
+                None
+            }
+        }
+    }
+
+    fn insert(&self, host: &str, leaf: Arc<LeafCert>) {
+        match self.leaves.lock() {
+            Ok(mut leaves) => {
+                if leaves.len() >= MAX_LEAVES
+                    && let Some(evict) = leaves.keys().next().cloned()
+                {
+                    leaves.remove(&evict);
+                }
+                leaves.insert(host.to_string(), leaf);
+            }
+            Err(err) => error!("Leaf cache lock poisoned {err}"),
+        }
+    }
+
+    fn path(&self, host: &str, ca_der: &[u8], tag: &str) -> Option<PathBuf> {
+        let dir = match self.dir.read() {
+            Ok(dir) => dir.clone()?,
+            Err(err) => {
+                error!("Leaf cache lock poisoned {err}");
+                return None;
+            }
+        };
+        let ca = digest(&SHA256, ca_der)
+            .as_ref()
+            .iter()
+            .take(8)
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        let host = host
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        Some(dir.join(ca).join(format!("{host}.{tag}.pem")))
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(n: u8) -> Result<(LeafCert, String), ()> {
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec![format!("{n}.com")])
+            .unwrap()
+            .self_signed(&key_pair)
+            .unwrap();
+        let leaf = LeafCert {
+            cert_der: cert.der().clone(),
+            key_der: PrivateKeyDer::try_from(key_pair.serialize_der()).unwrap(),
+        };
+        Ok((leaf, format!("{}{}", key_pair.serialize_pem(), cert.pem())))
+    }
+
+    #[test]
+    fn reuses_leaves() {
+        let cache = LeafCache::default();
+        let first = cache.get_or_sign("a.com", b"ca", "t", || leaf(1)).unwrap();
+        let again = cache.get_or_sign("a.com", b"ca", "t", || leaf(2)).unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+        let other = cache.get_or_sign("b.com", b"ca", "t", || leaf(3)).unwrap();
+        assert_ne!(other.cert_der, first.cert_der);
+    }
+
+    #[test]
+    fn reads_leaves_from_disk() {
+        let dir = std::env::temp_dir().join(format!("roxy-leaves-{}", std::process::id()));
+        let cache = LeafCache::default();
+        cache.set_dir(Some(dir.clone()));
+        let signed = cache.get_or_sign("[::1]", b"ca", "t", || leaf(1)).unwrap();
+
+        let restarted = LeafCache::default();
+        restarted.set_dir(Some(dir.clone()));
+        let loaded = restarted
+            .get_or_sign("[::1]", b"ca", "t", || leaf(2))
+            .unwrap();
+        assert_eq!(loaded.cert_der, signed.cert_der);
+        assert_eq!(loaded.key_der.secret_der(), signed.key_der.secret_der());
+
+        let other_ca = LeafCache::default();
+        other_ca.set_dir(Some(dir.clone()));
+        let resigned = other_ca
+            .get_or_sign("[::1]", b"other", "t", || leaf(3))
+            .unwrap();
+        assert_ne!(resigned.cert_der, signed.cert_der);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
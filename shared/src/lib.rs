@@ -5,37 +5,93 @@ pub mod body;
 pub mod cert;
 pub mod client;
 pub mod content;
+pub mod cookie;
 pub mod crypto;
+pub mod datagram;
+pub mod dns;
 pub mod h3_client;
 pub mod http;
 pub mod io;
+pub mod keylog;
+pub mod leaf;
+pub mod pool;
+pub mod redirect;
+pub mod retry;
+pub mod socks;
 pub mod tls;
+pub mod upstream;
 pub mod uri;
 pub mod version;
+pub mod wire;
+use aws_lc_rs::digest::{Algorithm, SHA1_FOR_LEGACY_USE_ONLY, SHA256, digest};
 use aws_lc_rs::rand;
 
+use once_cell::sync::Lazy;
 use p12_keystore::{KeyStore, KeyStoreEntry, PrivateKeyChain};
 use rcgen::{
     Certificate, CertificateParams, DistinguishedName, DnType, IsCa, Issuer, KeyPair,
-    KeyUsagePurpose, PKCS_RSA_SHA256,
+    KeyUsagePurpose, PKCS_ECDSA_P256_SHA256, PKCS_ECDSA_P384_SHA384, PKCS_ED25519, PKCS_RSA_SHA256,
+    SignatureAlgorithm,
 };
 use rustls::{
     RootCertStore,
-    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    client::{WebPkiServerVerifier, danger::ServerCertVerifier},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject},
 };
+use serde::{Deserialize, Serialize};
 use std::{
     error::Error,
     fs,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, PoisonError, RwLock},
 };
+use strum::{Display, EnumString};
 use time::OffsetDateTime;
-use tracing::{debug, trace, warn};
+use tracing::{debug, error, trace, warn};
 
-use crate::{crypto::init_crypto, uri::RUri};
+use crate::{
+    crypto::init_crypto,
+    leaf::{LeafCache, LeafCert},
+    uri::RUri,
+};
 
 static ROXYMITM: &str = "roxymitm";
 static ROXY_PWORD: &str = "roxy";
+/// Names the local leaf is always valid for, more are added with
+/// [`RoxyCA::set_local_names`].
+const LOCAL_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+static SYSTEM_ROOTS: Lazy<Arc<RootCertStore>> = Lazy::new(|| Arc::new(load_native_certs(None)));
+
+/// Key type of generated certificates. RSA keys are slow to generate and
+/// make for larger handshakes.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Display, EnumString,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum KeyAlgorithm {
+    Rsa,
+    #[default]
+    EcdsaP256,
+    EcdsaP384,
+    Ed25519,
+}
+
+impl KeyAlgorithm {
+    pub fn signature_algorithm(self) -> &'static SignatureAlgorithm {
+        match self {
+            KeyAlgorithm::Rsa => &PKCS_RSA_SHA256,
+            KeyAlgorithm::EcdsaP256 => &PKCS_ECDSA_P256_SHA256,
+            KeyAlgorithm::EcdsaP384 => &PKCS_ECDSA_P384_SHA384,
+            KeyAlgorithm::Ed25519 => &PKCS_ED25519,
+        }
+    }
+
+    pub fn generate(self) -> Result<KeyPair, rcgen::Error> {
+        KeyPair::generate_for(self.signature_algorithm())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct RoxyCA {
@@ -47,7 +103,9 @@ struct Inner {
     pub issuer: Issuer<'static, KeyPair>,
     pub roots: Arc<RootCertStore>,
     pub ca_der: Vec<u8>,
-    pub local_leaf: LocalLeaf,
+    pub local_leaf: RwLock<LocalLeaf>,
+    pub leaf_algorithm: KeyAlgorithm,
+    pub leaf_cache: LeafCache,
 }
 
 #[derive(Debug)]
@@ -65,15 +123,18 @@ impl RoxyCA {
             CertificateDer<'static>,
             rustls::pki_types::PrivateKeyDer<'static>,
         ),
+        leaf_algorithm: KeyAlgorithm,
     ) -> Self {
         let inner = Arc::new(Inner {
             issuer,
             roots: Arc::new(roots),
             ca_der,
-            local_leaf: LocalLeaf {
+            local_leaf: RwLock::new(LocalLeaf {
                 cert_der: leaf.0,
                 pk_der: leaf.1,
-            },
+            }),
+            leaf_algorithm,
+            leaf_cache: LeafCache::default(),
         });
         Self { inner }
     }
@@ -82,6 +143,30 @@ impl RoxyCA {
         self.inner.roots.clone()
     }
 
+    /// Keeps signed leaf certificates in `dir` as well so they survive restarts.
+    pub fn set_leaf_cache_dir(&self, dir: Option<PathBuf>) {
+        self.inner.leaf_cache.set_dir(dir);
+    }
+
+    /// The leaf certificate for the host of `uri`, signed on first use and
+    /// reused for later connections.
+    pub fn leaf_for_uri(&self, uri: &RUri) -> Result<Arc<LeafCert>, rcgen::Error> {
+        self.inner.leaf_cache.get_or_sign(
+            uri.host(),
+            &self.inner.ca_der,
+            &self.inner.leaf_algorithm.to_string(),
+            || {
+                let (leaf, key_pair) = self.sign_leaf_uri(uri)?;
+                let pem = format!("{}{}", key_pair.serialize_pem(), leaf.pem());
+                let leaf = LeafCert {
+                    cert_der: leaf.der().clone(),
+                    key_der: PrivateKeyDer::Pkcs8(key_pair.serialize_der().into()),
+                };
+                Ok((leaf, pem))
+            },
+        )
+    }
+
     pub fn sign_leaf_uri(&self, uri: &RUri) -> Result<(Certificate, KeyPair), rcgen::Error> {
         let host = uri.host();
         let mut params = CertificateParams::new(vec![host.to_string()])?;
@@ -90,7 +175,7 @@ impl RoxyCA {
         params.is_ca = IsCa::NoCa;
         params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ServerAuth];
 
-        let key_pair = KeyPair::generate()?;
+        let key_pair = self.inner.leaf_algorithm.generate()?;
         let leaf = params.signed_by(&key_pair, &self.inner.issuer)?;
 
         Ok((leaf, key_pair))
@@ -107,7 +192,7 @@ impl RoxyCA {
         params.is_ca = IsCa::NoCa;
         params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ServerAuth];
 
-        let key_pair = KeyPair::generate()?;
+        let key_pair = self.inner.leaf_algorithm.generate()?;
         let leaf = params.signed_by(&key_pair, &self.inner.issuer)?;
 
         Ok((leaf, key_pair))
@@ -117,19 +202,72 @@ impl RoxyCA {
         self.inner.issuer.key()
     }
 
+    /// The CA certificate as DER, without its key.
+    pub fn ca_der(&self) -> &[u8] {
+        &self.inner.ca_der
+    }
+
+    /// SHA-1 of the CA certificate in upper case hex, how the macOS keychain
+    /// and the Windows certificate stores name certificates.
+    pub fn ca_sha1(&self) -> String {
+        hex_digest(&SHA1_FOR_LEGACY_USE_ONLY, &self.inner.ca_der, "")
+    }
+
+    /// SHA-256 of the CA certificate as colon separated upper case hex, the
+    /// fingerprint users compare to check they trust the right CA.
+    pub fn ca_sha256(&self) -> String {
+        hex_digest(&SHA256, &self.inner.ca_der, ":")
+    }
+
+    /// Certificate Roxy presents for itself, e.g. to HTTP/3 clients of the proxy.
     pub fn local_leaf(
         &self,
     ) -> (
         CertificateDer<'static>,
         rustls::pki_types::PrivateKeyDer<'static>,
     ) {
-        (
-            self.inner.local_leaf.cert_der.clone(),
-            self.inner.local_leaf.pk_der.clone_key(),
-        )
+        let leaf = self
+            .inner
+            .local_leaf
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        (leaf.cert_der.clone(), leaf.pk_der.clone_key())
+    }
+
+    /// Re-signs the local leaf so it is also valid for `names`, the host names
+    /// and addresses clients use to reach the proxy.
+    pub fn set_local_names(&self, names: &[String]) -> Result<(), CaError> {
+        let leaf = sign_local_leaf(&self.inner.issuer, self.inner.leaf_algorithm, names)?;
+        match self.inner.local_leaf.write() {
+            Ok(mut guard) => *guard = leaf,
+            Err(err) => error!("Local leaf lock poisoned {err}"),
+        }
+        Ok(())
     }
 }
 
+fn hex_digest(algorithm: &'static Algorithm, data: &[u8], separator: &str) -> String {
+    digest(algorithm, data)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// `len` random bytes as lower case hex, for secrets such as API tokens.
+pub fn random_hex(len: usize) -> Option<String> {
+    let mut bytes = vec![0u8; len];
+    rand::fill(&mut bytes).ok()?;
+    Some(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// The platform and webpki roots without the Roxy CA, for clients that are
+/// not tied to a proxy.
+pub fn system_roots() -> Arc<RootCertStore> {
+    SYSTEM_ROOTS.clone()
+}
+
 fn load_native_certs(extra: Option<CertificateDer<'static>>) -> RootCertStore {
     let mut roots = rustls::RootCertStore::empty();
 
@@ -155,6 +293,17 @@ fn load_native_certs(extra: Option<CertificateDer<'static>>) -> RootCertStore {
     roots
 }
 
+/// Reads every certificate in the PEM file at `path`, e.g. a corporate CA bundle.
+pub fn load_ca_bundle(path: &Path) -> Result<Vec<CertificateDer<'static>>, CaError> {
+    let certs = CertificateDer::pem_file_iter(path)?.collect::<Result<Vec<_>, _>>()?;
+    debug!(
+        "Loaded {} certificates from {}",
+        certs.len(),
+        path.display()
+    );
+    Ok(certs)
+}
+
 struct CaFiles {
     bundle_path_cer: PathBuf,
     bundle_path: PathBuf,
@@ -193,6 +342,7 @@ pub enum CaError {
     RustLS(rustls::Error),
     RustLSPem(rustls::pki_types::pem::Error),
     RustLSParse,
+    InvalidCa(&'static str),
 }
 
 impl Error for CaError {}
@@ -237,6 +387,17 @@ pub fn generate_roxy_root_ca() -> Result<RoxyCA, CaError> {
 }
 
 pub fn generate_roxy_root_ca_with_path(path: Option<PathBuf>) -> Result<RoxyCA, CaError> {
+    generate_roxy_root_ca_with_algorithm(path, None)
+}
+
+/// Like [`generate_roxy_root_ca_with_path`] with both the CA and leaf keys of
+/// type `algorithm`. Without one the CA is RSA and leaves are ECDSA P-256.
+///
+/// An existing CA is kept whatever its key type, only leaves follow `algorithm`.
+pub fn generate_roxy_root_ca_with_algorithm(
+    path: Option<PathBuf>,
+    algorithm: Option<KeyAlgorithm>,
+) -> Result<RoxyCA, CaError> {
     init_crypto();
     let root_dir: PathBuf = match path {
         Some(p) => p,
@@ -261,6 +422,14 @@ pub fn generate_roxy_root_ca_with_path(path: Option<PathBuf>) -> Result<RoxyCA,
 
         let pem = std::fs::read_to_string(ca_files.bundle_path.clone())?;
         let key_pair = rcgen::KeyPair::from_pem(pem.as_str())?;
+        if let Some(algorithm) = algorithm
+            && key_pair.algorithm() != algorithm.signature_algorithm()
+        {
+            warn!(
+                "Roxy root CA in {} is not {algorithm}, remove it to generate a new one",
+                home.display()
+            );
+        }
 
         let ca_cert_pem = std::fs::read_to_string(ca_files.cert_path.clone())?;
         let issuer = Issuer::from_ca_cert_pem(&ca_cert_pem, key_pair)?;
@@ -269,13 +438,118 @@ pub fn generate_roxy_root_ca_with_path(path: Option<PathBuf>) -> Result<RoxyCA,
 
         (issuer, ca_der)
     } else {
-        generate(ca_files)?
+        generate(ca_files, algorithm.unwrap_or(KeyAlgorithm::Rsa))?
+    };
+
+    build_roxy_ca(issuer, ca_cert, algorithm.unwrap_or_default())
+}
+
+/// An externally generated CA to sign leaf certificates with, instead of the
+/// one Roxy generates under `~/.roxy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaSource {
+    /// PEM files with the certificate and its private key, both may be the same file.
+    Pem { cert: PathBuf, key: PathBuf },
+    /// A PKCS#12 keystore holding the private key and its certificate.
+    Pkcs12 { path: PathBuf, password: String },
+}
+
+/// Loads the CA in `source`, checking that it is allowed to sign certificates
+/// and that the key belongs to the certificate.
+pub fn import_roxy_root_ca(
+    source: &CaSource,
+    leaf_algorithm: KeyAlgorithm,
+) -> Result<RoxyCA, CaError> {
+    init_crypto();
+    let (ca_cert, key_pair) = match source {
+        CaSource::Pem { cert, key } => {
+            debug!("Importing root CA {}", cert.display());
+            let ca_cert = CertificateDer::from_pem_file(cert)?;
+            let key = PrivateKeyDer::from_pem_file(key)?;
+            (ca_cert, KeyPair::try_from(&key)?)
+        }
+        CaSource::Pkcs12 { path, password } => {
+            debug!("Importing root CA {}", path.display());
+            let key_store = KeyStore::from_pkcs12(&fs::read(path)?, password)?;
+            let (key, cert) = key_store
+                .entries()
+                .find_map(|(_, entry)| match entry {
+                    KeyStoreEntry::PrivateKeyChain(chain) => chain
+                        .chain()
+                        .first()
+                        .map(|cert| (chain.key().to_vec(), cert.as_der().to_vec())),
+                    _ => None,
+                })
+                .ok_or(CaError::InvalidCa("keystore has no private key"))?;
+            (
+                CertificateDer::from(cert),
+                KeyPair::try_from(key.as_slice())?,
+            )
+        }
     };
 
+    let params = CertificateParams::from_ca_cert_der(&ca_cert)?;
+    if !matches!(params.is_ca, IsCa::Ca(_)) {
+        return Err(CaError::InvalidCa("certificate is not a CA"));
+    }
+    if !params.key_usages.is_empty() && !params.key_usages.contains(&KeyUsagePurpose::KeyCertSign) {
+        return Err(CaError::InvalidCa(
+            "key usage does not allow signing certificates",
+        ));
+    }
+
+    let roxy_ca = build_roxy_ca(
+        Issuer::new(params, key_pair),
+        ca_cert.clone(),
+        leaf_algorithm,
+    )?;
+    // The local leaf only verifies when the key matches the certificate
+    let verifier = WebPkiServerVerifier::builder_with_provider(
+        roxy_ca.roots(),
+        Arc::new(rustls::crypto::aws_lc_rs::default_provider()),
+    )
+    .build()
+    .map_err(|e| CaError::RustLS(rustls::Error::General(e.to_string())))?;
+    verifier.verify_server_cert(
+        &roxy_ca.local_leaf().0,
+        &[],
+        &ServerName::try_from("localhost").map_err(|_| CaError::RustLSParse)?,
+        &[],
+        UnixTime::now(),
+    )?;
+    Ok(roxy_ca)
+}
+
+fn build_roxy_ca(
+    issuer: Issuer<'static, KeyPair>,
+    ca_cert: CertificateDer<'static>,
+    leaf_algorithm: KeyAlgorithm,
+) -> Result<RoxyCA, CaError> {
     let ca_der = ca_cert.to_vec();
     let roots = load_native_certs(Some(ca_cert.clone()));
-    let mut params =
-        CertificateParams::new(vec!["localhost".to_string(), "127.0.0.1".to_string()])?;
+    let leaf = sign_local_leaf(&issuer, leaf_algorithm, &[])?;
+
+    Ok(RoxyCA::new(
+        issuer,
+        roots,
+        ca_der,
+        (leaf.cert_der, leaf.pk_der),
+        leaf_algorithm,
+    ))
+}
+
+fn sign_local_leaf(
+    issuer: &Issuer<'static, KeyPair>,
+    leaf_algorithm: KeyAlgorithm,
+    names: &[String],
+) -> Result<LocalLeaf, CaError> {
+    let mut subject_alt_names: Vec<String> = LOCAL_NAMES.iter().map(|n| n.to_string()).collect();
+    for name in names {
+        if !subject_alt_names.contains(name) {
+            subject_alt_names.push(name.clone());
+        }
+    }
+    let mut params = CertificateParams::new(subject_alt_names)?;
 
     params
         .distinguished_name
@@ -283,22 +557,21 @@ pub fn generate_roxy_root_ca_with_path(path: Option<PathBuf>) -> Result<RoxyCA,
     params.is_ca = IsCa::NoCa;
     params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ServerAuth];
 
-    let leaf_key_pair = KeyPair::generate()?;
-    let leaf_cert = params.signed_by(&leaf_key_pair, &issuer)?;
+    let leaf_key_pair = leaf_algorithm.generate()?;
+    let leaf_cert = params.signed_by(&leaf_key_pair, issuer)?;
 
     let leaf_kp_der =
         PrivateKeyDer::try_from(leaf_key_pair.serialize_der()).map_err(|_| CaError::RustLSParse)?;
 
-    Ok(RoxyCA::new(
-        issuer,
-        roots,
-        ca_der,
-        (leaf_cert.der().to_owned(), leaf_kp_der),
-    ))
+    Ok(LocalLeaf {
+        cert_der: leaf_cert.der().to_owned(),
+        pk_der: leaf_kp_der,
+    })
 }
 
 fn generate(
     ca_files: CaFiles,
+    algorithm: KeyAlgorithm,
 ) -> Result<(Issuer<'static, KeyPair>, CertificateDer<'static>), CaError> {
     let mut ca_params = CertificateParams::default();
     ca_params.is_ca = IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
@@ -319,7 +592,7 @@ fn generate(
     ca_params.not_before = OffsetDateTime::now_utc();
     ca_params.not_after = OffsetDateTime::now_utc().saturating_add(time::Duration::days(365 * 10));
 
-    let key_pair = KeyPair::generate_for(&PKCS_RSA_SHA256)?;
+    let key_pair = algorithm.generate()?;
     let ca_cert = ca_params.self_signed(&key_pair)?;
 
     let cert_pem = ca_cert.pem();
@@ -375,3 +648,124 @@ fn generate(
     let issuer = Issuer::new(ca_params, key_pair);
     Ok((issuer, ca_cert.der().clone()))
 }
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_ca(dir: &Path, name: &str, is_ca: bool) -> (PathBuf, PathBuf) {
+        let mut params = CertificateParams::new(vec![]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, name);
+        if is_ca {
+            params.is_ca = IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+            params.key_usages.push(KeyUsagePurpose::KeyCertSign);
+        }
+        let key_pair = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key_pair).unwrap();
+
+        let cert_path = dir.join(format!("{name}.pem"));
+        let key_path = dir.join(format!("{name}.key"));
+        fs::write(&cert_path, cert.pem()).unwrap();
+        fs::write(&key_path, key_pair.serialize_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    #[test]
+    fn import_pem_ca() {
+        let dir = std::env::temp_dir().join(format!("roxy-ca-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = write_ca(&dir, "team", true);
+        let (_, other_key) = write_ca(&dir, "other", true);
+        let (leaf, leaf_key) = write_ca(&dir, "leaf", false);
+
+        let roxy_ca = import_roxy_root_ca(
+            &CaSource::Pem {
+                cert: cert.clone(),
+                key,
+            },
+            KeyAlgorithm::EcdsaP256,
+        )
+        .unwrap();
+        assert!(
+            roxy_ca
+                .sign_leaf_mult("example.com", ["example.com".to_string()])
+                .is_ok()
+        );
+
+        let mismatched = import_roxy_root_ca(
+            &CaSource::Pem {
+                cert,
+                key: other_key,
+            },
+            KeyAlgorithm::EcdsaP256,
+        );
+        assert!(mismatched.is_err());
+
+        let not_ca = import_roxy_root_ca(
+            &CaSource::Pem {
+                cert: leaf,
+                key: leaf_key,
+            },
+            KeyAlgorithm::EcdsaP256,
+        );
+        assert!(matches!(not_ca, Err(CaError::InvalidCa(_))));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn local_leaf_names() {
+        let dir = std::env::temp_dir().join(format!("roxy-local-{}", std::process::id()));
+        let roxy_ca = generate_roxy_root_ca_with_path(Some(dir.clone())).unwrap();
+        let verifier = WebPkiServerVerifier::builder_with_provider(
+            roxy_ca.roots(),
+            Arc::new(rustls::crypto::aws_lc_rs::default_provider()),
+        )
+        .build()
+        .unwrap();
+        let verify = |name: &str| {
+            verifier.verify_server_cert(
+                &roxy_ca.local_leaf().0,
+                &[],
+                &ServerName::try_from(name.to_string()).unwrap(),
+                &[],
+                UnixTime::now(),
+            )
+        };
+        assert!(verify("localhost").is_ok());
+        assert!(verify("roxy.lan").is_err());
+        assert_eq!(roxy_ca.ca_sha1().len(), 40);
+        assert_eq!(roxy_ca.ca_sha256().split(':').count(), 32);
+
+        roxy_ca
+            .set_local_names(&["roxy.lan".to_string(), "192.168.1.20".to_string()])
+            .unwrap();
+        assert!(verify("localhost").is_ok());
+        assert!(verify("roxy.lan").is_ok());
+        assert!(verify("192.168.1.20").is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn key_algorithms() {
+        for algorithm in [KeyAlgorithm::EcdsaP384, KeyAlgorithm::Ed25519] {
+            let dir = std::env::temp_dir().join(format!("roxy-{algorithm}-{}", std::process::id()));
+            let roxy_ca =
+                generate_roxy_root_ca_with_algorithm(Some(dir.clone()), Some(algorithm)).unwrap();
+            assert_eq!(
+                roxy_ca.key_pair().algorithm(),
+                algorithm.signature_algorithm()
+            );
+            let (_, leaf_key) = roxy_ca
+                .sign_leaf_uri(&RUri::new("https://example.com".parse().unwrap()))
+                .unwrap();
+            assert_eq!(leaf_key.algorithm(), algorithm.signature_algorithm());
+            fs::remove_dir_all(dir).unwrap();
+        }
+        assert_eq!(
+            "ecdsa-p256".parse::<KeyAlgorithm>().unwrap(),
+            KeyAlgorithm::EcdsaP256
+        );
+    }
+}
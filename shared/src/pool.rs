@@ -0,0 +1,585 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use http::{Request, Response};
+use hyper::{
+    body::Incoming,
+    client::conn::{TrySendError, http1, http2},
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::time::timeout;
+use tracing::{error, trace, warn};
+
+use crate::{
+    body::BytesBody,
+    cert::{ClientTlsConnectionData, ServerVerificationCapture},
+    h3_client::{H3Connection, H3Pool, H3Sender},
+    http::{HttpEmitter, HttpError, HttpEvent, HttpStreamResponse, ResponseLimits},
+    wire::{WireCapture, WireRecorder, WireTarget},
+};
+
+/// Idle HTTP/1 connections kept per key, HTTP/2 keeps a single one.
+const MAX_IDLE_PER_KEY: usize = 8;
+/// Servers commonly close idle connections after a minute or two.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Connections to the same server, through the same upstream proxy, offering
+/// the same ALPN protocols and TLS settings can carry each other's requests.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PoolKey {
+    pub(crate) scheme: &'static str,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) alpns: Vec<Vec<u8>>,
+    pub(crate) upstream: Option<String>,
+    /// Whether rustls is used and the [`crate::tls::TlsConfig`] fingerprint.
+    pub(crate) tls: Option<(bool, u64)>,
+}
+
+/// What was learned while opening a connection, replayed to every flow that
+/// reuses it.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionInfo {
+    pub addr: Option<SocketAddr>,
+    pub tls: Option<(ClientTlsConnectionData, ServerVerificationCapture)>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Sender {
+    /// With where the connection's reads are captured for the request in
+    /// flight.
+    Http1(http1::SendRequest<BytesBody>, WireTarget),
+    Http2(http2::SendRequest<BytesBody>),
+}
+
+impl Sender {
+    fn is_ready(&self) -> bool {
+        match self {
+            Sender::Http1(sender, _) => sender.is_ready(),
+            Sender::Http2(sender) => sender.is_ready(),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        match self {
+            Sender::Http1(sender, _) => sender.is_closed(),
+            Sender::Http2(sender) => sender.is_closed(),
+        }
+    }
+
+    pub(crate) async fn send(
+        &mut self,
+        request: Request<BytesBody>,
+    ) -> Result<Response<Incoming>, HttpError> {
+        Ok(match self {
+            Sender::Http1(sender, target) => {
+                let capture = capture_wire(target, &request);
+                with_capture(sender.send_request(request).await?, capture)
+            }
+            Sender::Http2(sender) => sender.send_request(request).await?,
+        })
+    }
+
+    /// Like [`Self::send`] but hands the request back when the connection
+    /// closed before it was sent.
+    async fn try_send(
+        &mut self,
+        request: Request<BytesBody>,
+    ) -> Result<Response<Incoming>, TrySendError<Request<BytesBody>>> {
+        match self {
+            Sender::Http1(sender, target) => {
+                let capture = capture_wire(target, &request);
+                let response = sender.try_send_request(request).await?;
+                Ok(with_capture(response, capture))
+            }
+            Sender::Http2(sender) => sender.try_send_request(request).await,
+        }
+    }
+}
+
+/// Captures what the connection reads next for `request` when it asks for it
+/// with a [`WireCapture`] extension.
+fn capture_wire<B>(target: &WireTarget, request: &Request<B>) -> Option<WireCapture> {
+    let capture = request.extensions().get::<WireCapture>().cloned();
+    target.set(capture.clone());
+    capture
+}
+
+/// Hands the capture on with the response it holds the bytes of.
+fn with_capture(
+    mut response: Response<Incoming>,
+    capture: Option<WireCapture>,
+) -> Response<Incoming> {
+    if let Some(capture) = capture {
+        response.extensions_mut().insert(capture);
+    }
+    response
+}
+
+pub(crate) async fn handshake_http1<S>(
+    io: S,
+    emitter: &dyn HttpEmitter,
+) -> Result<Sender, HttpError>
+where
+    S: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let mut builder = http1::Builder::new();
+    // Header names recorded with their case in the request extensions are
+    // written as recorded, the others in title case
+    builder.title_case_headers(true).preserve_header_case(true);
+    let (io, target) = WireRecorder::new(TokioIo::new(io));
+
+    emitter.emit(HttpEvent::ClientHttpHandshakeStart);
+    let (sender, conn) = timeout(HANDSHAKE_TIMEOUT, builder.handshake(TokioIo::new(io))).await??;
+    emitter.emit(HttpEvent::ClientHttpHandshakeComplete);
+
+    tokio::spawn(async move {
+        if let Err(err) = conn.await {
+            warn!("Upstream connection failed: {err}");
+        }
+    });
+    Ok(Sender::Http1(sender, target))
+}
+
+pub(crate) async fn handshake_http2<S>(
+    io: S,
+    emitter: &dyn HttpEmitter,
+) -> Result<Sender, HttpError>
+where
+    S: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    emitter.emit(HttpEvent::ClientHttpHandshakeStart);
+    let (sender, conn) = http2::handshake(TokioExecutor::new(), io).await?;
+    emitter.emit(HttpEvent::ClientHttpHandshakeComplete);
+
+    tokio::spawn(async move {
+        if let Err(err) = conn.await {
+            error!("Upstream HTTP/2 connection failed: {err}");
+        }
+    });
+    Ok(Sender::Http2(sender))
+}
+
+#[derive(Debug)]
+struct Idle {
+    sender: Sender,
+    info: ConnectionInfo,
+    since: Instant,
+}
+
+/// Counters of a [`ConnectionPool`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Connections waiting for a request right now, open HTTP/3 ones included.
+    pub idle: usize,
+    pub opened: u64,
+    pub reused: u64,
+}
+
+/// Upstream connections kept open between requests, so keep-alive and
+/// HTTP/2 and HTTP/3 multiplexing are used. Clones share the same connections.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionPool {
+    inner: Arc<PoolInner>,
+}
+
+#[derive(Debug, Default)]
+struct PoolInner {
+    idle: Mutex<HashMap<PoolKey, Vec<Idle>>>,
+    h3: H3Pool,
+    opened: AtomicU64,
+    reused: AtomicU64,
+}
+
+impl ConnectionPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let idle: usize = match self.inner.idle.lock() {
+            Ok(idle) => idle.values().map(Vec::len).sum(),
+            Err(err) => {
+                error!("Connection pool lock poisoned {err}");
+                0
+            }
+        };
+        PoolStats {
+            idle: idle + self.inner.h3.count(),
+            opened: self.inner.opened.load(Ordering::Relaxed),
+            reused: self.inner.reused.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Closes every idle connection, connections in use are not kept afterwards.
+    pub fn clear(&self) {
+        match self.inner.idle.lock() {
+            Ok(mut idle) => idle.clear(),
+            Err(err) => error!("Connection pool lock poisoned {err}"),
+        }
+        self.inner.h3.clear();
+    }
+
+    pub(crate) fn h3(&self) -> &H3Pool {
+        &self.inner.h3
+    }
+
+    /// An open HTTP/3 connection for `key`, it stays available to other
+    /// requests while this one uses it.
+    pub(crate) fn checkout_h3(&self, key: &PoolKey) -> Option<(H3Sender, ConnectionInfo)> {
+        let found = self.inner.h3.checkout(key);
+        if found.is_some() {
+            self.inner.reused.fetch_add(1, Ordering::Relaxed);
+        }
+        found
+    }
+
+    pub(crate) fn checkin_h3(&self, key: PoolKey, connection: H3Connection) {
+        self.inner.opened.fetch_add(1, Ordering::Relaxed);
+        self.inner.h3.put(key, connection);
+    }
+
+    /// Sends `request` over an idle connection for `key`, or one opened with
+    /// `connect` when there is none or it closed before the request went out.
+    /// The response is received within `limits`, its body is left unread when
+    /// `streaming` is set.
+    pub(crate) async fn send<F, Fut>(
+        &self,
+        key: PoolKey,
+        mut request: Request<BytesBody>,
+        emitter: &dyn HttpEmitter,
+        limits: &ResponseLimits,
+        streaming: bool,
+        connect: F,
+    ) -> Result<HttpStreamResponse, HttpError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(Sender, ConnectionInfo), HttpError>>,
+    {
+        if let Some((mut sender, info)) = self.checkout(&key) {
+            match limits.head(sender.try_send(request)).await? {
+                Ok(response) => {
+                    trace!("Reused connection to {}:{}", key.host, key.port);
+                    self.inner.reused.fetch_add(1, Ordering::Relaxed);
+                    emitter.emit(HttpEvent::ConnectionReused(info.clone()));
+                    self.checkin(key, sender, info);
+                    return limits.body(response, streaming).await;
+                }
+                Err(mut err) => match err.take_message() {
+                    Some(unsent) => {
+                        trace!("Idle connection to {}:{} closed", key.host, key.port);
+                        request = unsent;
+                    }
+                    None => return Err(HttpError::Hyper(err.into_error())),
+                },
+            }
+        }
+
+        let (mut sender, info) = connect().await?;
+        self.inner.opened.fetch_add(1, Ordering::Relaxed);
+        let response = limits.head(sender.send(request)).await??;
+        self.checkin(key, sender, info);
+        limits.body(response, streaming).await
+    }
+
+    fn checkout(&self, key: &PoolKey) -> Option<(Sender, ConnectionInfo)> {
+        let mut idle = match self.inner.idle.lock() {
+            Ok(idle) => idle,
+            Err(err) => {
+                error!("Connection pool lock poisoned {err}");
+                return None;
+            }
+        };
+        let entries = idle.get_mut(key)?;
+        entries.retain(|e| !e.sender.is_closed() && e.since.elapsed() < IDLE_TIMEOUT);
+        let pos = entries.iter().position(|e| e.sender.is_ready())?;
+        match &mut entries[pos] {
+            // Multiplexed, the connection stays available to other requests
+            Idle {
+                sender: Sender::Http2(sender),
+                info,
+                since,
+            } => {
+                *since = Instant::now();
+                Some((Sender::Http2(sender.clone()), info.clone()))
+            }
+            Idle {
+                sender: Sender::Http1(..),
+                ..
+            } => {
+                let entry = entries.swap_remove(pos);
+                Some((entry.sender, entry.info))
+            }
+        }
+    }
+
+    /// Returns a connection once it can take another request, HTTP/1
+    /// connections only after the response body was read.
+    fn checkin(&self, key: PoolKey, sender: Sender, info: ConnectionInfo) {
+        match sender {
+            Sender::Http2(sender) => self.put(key, Sender::Http2(sender), info),
+            Sender::Http1(mut sender, target) => {
+                let pool = self.clone();
+                tokio::spawn(async move {
+                    if sender.ready().await.is_ok() {
+                        pool.put(key, Sender::Http1(sender, target), info);
+                    }
+                });
+            }
+        }
+    }
+
+    fn put(&self, key: PoolKey, sender: Sender, info: ConnectionInfo) {
+        let mut idle = match self.inner.idle.lock() {
+            Ok(idle) => idle,
+            Err(err) => {
+                error!("Connection pool lock poisoned {err}");
+                return;
+            }
+        };
+        let entries = idle.entry(key).or_default();
+        entries.retain(|e| !e.sender.is_closed());
+        let has_http2 = entries.iter().any(|e| matches!(e.sender, Sender::Http2(_)));
+        if matches!(sender, Sender::Http2(_)) && has_http2 {
+            return;
+        }
+        if entries.len() >= MAX_IDLE_PER_KEY {
+            return;
+        }
+        entries.push(Idle {
+            sender,
+            info,
+            since: Instant::now(),
+        });
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full, combinators::BoxBody};
+    use hyper_util::rt::tokio::WithHyperIo;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::http::NoOpListener;
+
+    use super::*;
+
+    /// Answers every request on every connection with `ok`, counting connections.
+    async fn keep_alive_server() -> (u16, Arc<AtomicU64>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(AtomicU64::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(async move {
+                    let mut buf = vec![0; 4096];
+                    let mut received = vec![];
+                    loop {
+                        let Ok(n) = stream.read(&mut buf).await else {
+                            return;
+                        };
+                        if n == 0 {
+                            return;
+                        }
+                        received.extend_from_slice(&buf[..n]);
+                        while let Some(end) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+                            received.drain(..end + 4);
+                            let response = "HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok";
+                            if stream.write_all(response.as_bytes()).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        (port, accepted)
+    }
+
+    fn key(port: u16) -> PoolKey {
+        PoolKey {
+            scheme: "http",
+            host: "127.0.0.1".to_string(),
+            port,
+            alpns: vec![],
+            upstream: None,
+            tls: None,
+        }
+    }
+
+    fn request(port: u16) -> Request<BytesBody> {
+        Request::builder()
+            .uri(format!("http://127.0.0.1:{port}/"))
+            .header("host", format!("127.0.0.1:{port}"))
+            .body(BoxBody::new(Full::new(Bytes::new())))
+            .unwrap()
+    }
+
+    async fn connect(port: u16) -> Result<(Sender, ConnectionInfo), HttpError> {
+        let stream = TcpStream::connect(("127.0.0.1", port)).await?;
+        let info = ConnectionInfo {
+            addr: stream.peer_addr().ok(),
+            tls: None,
+        };
+        Ok((
+            handshake_http1(WithHyperIo::new(stream), &NoOpListener {}).await?,
+            info,
+        ))
+    }
+
+    #[tokio::test]
+    async fn reuses_http1_connections() {
+        let (port, accepted) = keep_alive_server().await;
+        let pool = ConnectionPool::new();
+        for _ in 0..3 {
+            let response = pool
+                .send(
+                    key(port),
+                    request(port),
+                    &NoOpListener {},
+                    &ResponseLimits::default(),
+                    false,
+                    || connect(port),
+                )
+                .await
+                .unwrap()
+                .buffered()
+                .await
+                .unwrap();
+            assert_eq!(response.body, "ok");
+            // The connection is returned in the background once it is ready
+            for _ in 0..100 {
+                if pool.stats().idle == 1 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+        let stats = pool.stats();
+        assert_eq!(stats.opened, 1);
+        assert_eq!(stats.reused, 2);
+
+        pool.clear();
+        assert_eq!(pool.stats().idle, 0);
+        pool.send(
+            key(port),
+            request(port),
+            &NoOpListener {},
+            &ResponseLimits::default(),
+            false,
+            || connect(port),
+        )
+        .await
+        .unwrap();
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn keys_do_not_share_connections() {
+        let (port, accepted) = keep_alive_server().await;
+        let pool = ConnectionPool::new();
+        let mut other = key(port);
+        other.upstream = Some("socks5 127.0.0.1:1080".to_string());
+        for key in [key(port), other] {
+            pool.send(
+                key,
+                request(port),
+                &NoOpListener {},
+                &ResponseLimits::default(),
+                false,
+                || connect(port),
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+        assert_eq!(pool.stats().reused, 0);
+    }
+
+    #[tokio::test]
+    async fn receives_within_limits() {
+        let (port, _) = keep_alive_server().await;
+        let pool = ConnectionPool::new();
+        let limits = ResponseLimits {
+            read_timeout: None,
+            max_body_size: Some(1),
+        };
+        let result = pool
+            .send(
+                key(port),
+                request(port),
+                &NoOpListener {},
+                &limits,
+                false,
+                || connect(port),
+            )
+            .await;
+        assert!(matches!(result, Err(HttpError::BodyTooLarge(1))));
+
+        // Accepts the connection but never answers
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = silent.local_addr().unwrap().port();
+        let limits = ResponseLimits {
+            read_timeout: Some(Duration::from_millis(50)),
+            max_body_size: None,
+        };
+        let result = pool
+            .send(
+                key(port),
+                request(port),
+                &NoOpListener {},
+                &limits,
+                false,
+                || connect(port),
+            )
+            .await;
+        assert!(matches!(result, Err(HttpError::Timeout)));
+        drop(silent);
+    }
+
+    #[tokio::test]
+    async fn streams_past_limits() {
+        let (port, _) = keep_alive_server().await;
+        let pool = ConnectionPool::new();
+        let limits = ResponseLimits {
+            read_timeout: None,
+            max_body_size: Some(1),
+        };
+        let response = pool
+            .send(
+                key(port),
+                request(port),
+                &NoOpListener {},
+                &limits,
+                true,
+                || connect(port),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(response, HttpStreamResponse::Streaming(..)));
+        let body = response
+            .into_streaming()
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(body, "ok");
+    }
+}
@@ -0,0 +1,244 @@
+use http::{
+    HeaderMap, Method, StatusCode, Uri,
+    header::{
+        AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST, LOCATION, PROXY_AUTHORIZATION,
+    },
+    request::Parts,
+};
+
+/// How [`crate::client::ClientContext`] follows redirects, it returns every
+/// 3xx as is without one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedirectPolicy {
+    /// Redirects followed for one request, the last 3xx is returned once
+    /// they run out.
+    pub max_hops: usize,
+    /// Only follow redirects to the scheme, host and port of the request.
+    pub same_origin: bool,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self {
+            max_hops: 10,
+            same_origin: false,
+        }
+    }
+}
+
+/// One redirect that was followed, reported to the client's emitter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub status: StatusCode,
+    pub from: Uri,
+    pub to: Uri,
+}
+
+impl RedirectPolicy {
+    /// Turns `request` into the request for the next hop when `status` and
+    /// `headers` are a redirect this policy follows. Returns whether the body
+    /// is still sent.
+    pub(crate) fn follow(
+        &self,
+        request: &mut Parts,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Option<(Redirect, bool)> {
+        if !matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308) {
+            return None;
+        }
+        let location = headers.get(LOCATION)?.to_str().ok()?;
+        let to = resolve_location(&request.uri, location)?;
+        let cross_origin = origin(&to) != origin(&request.uri);
+        if self.same_origin && cross_origin {
+            return None;
+        }
+
+        // 303 always, and 301 and 302 after a POST like browsers do, switch to GET
+        let keep_body = match status {
+            StatusCode::SEE_OTHER => request.method == Method::HEAD,
+            StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND => request.method != Method::POST,
+            _ => true,
+        };
+        if !keep_body {
+            if request.method != Method::HEAD {
+                request.method = Method::GET;
+            }
+            request.headers.remove(CONTENT_TYPE);
+            request.headers.remove(CONTENT_LENGTH);
+        }
+        if cross_origin {
+            // Credentials for one origin are never sent to another
+            request.headers.remove(AUTHORIZATION);
+            request.headers.remove(PROXY_AUTHORIZATION);
+            request.headers.remove(COOKIE);
+        }
+        if request.headers.contains_key(HOST)
+            && let Some(authority) = to.authority()
+            && let Ok(host) = authority.as_str().parse()
+        {
+            request.headers.insert(HOST, host);
+        }
+
+        let redirect = Redirect {
+            status,
+            from: request.uri.clone(),
+            to: to.clone(),
+        };
+        request.uri = to;
+        Some((redirect, keep_body))
+    }
+}
+
+fn origin(uri: &Uri) -> (Option<&str>, Option<&str>, Option<u16>) {
+    let port = uri.port_u16().or(match uri.scheme_str() {
+        Some("https") => Some(443),
+        Some("http") => Some(80),
+        _ => None,
+    });
+    (uri.scheme_str(), uri.host(), port)
+}
+
+/// `location` relative to `base`, fragments are dropped.
+fn resolve_location(base: &Uri, location: &str) -> Option<Uri> {
+    let location = location.split('#').next().unwrap_or_default().trim();
+    if let Ok(uri) = location.parse::<Uri>()
+        && uri.scheme().is_some()
+        && uri.authority().is_some()
+    {
+        return Some(uri);
+    }
+    let scheme = base.scheme_str()?;
+    let authority = base.authority()?;
+    let resolved = if location.starts_with("//") {
+        format!("{scheme}:{location}")
+    } else if location.starts_with('/') {
+        format!("{scheme}://{authority}{location}")
+    } else if location.starts_with('?') {
+        format!("{scheme}://{authority}{}{location}", base.path())
+    } else {
+        let dir = base.path().rsplit_once('/').map_or("", |(dir, _)| dir);
+        format!("{scheme}://{authority}{dir}/{location}")
+    };
+    resolved.parse().ok()
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::{HeaderValue, Request};
+
+    use super::*;
+
+    fn parts(method: Method, uri: &str) -> Parts {
+        let mut parts = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0;
+        parts
+            .headers
+            .insert(AUTHORIZATION, HeaderValue::from_static("Bearer t"));
+        parts
+            .headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        parts
+    }
+
+    fn location(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn resolves_locations() {
+        let base: Uri = "https://example.com/a/b?q=1".parse().unwrap();
+        for (location, expected) in [
+            ("https://other.com/x", "https://other.com/x"),
+            ("//cdn.example.com/x", "https://cdn.example.com/x"),
+            ("/root#top", "https://example.com/root"),
+            ("c", "https://example.com/a/c"),
+            ("?q=2", "https://example.com/a/b?q=2"),
+        ] {
+            assert_eq!(
+                resolve_location(&base, location).unwrap().to_string(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn rewrites_methods() {
+        let policy = RedirectPolicy::default();
+
+        let mut request = parts(Method::POST, "https://example.com/orders");
+        let (redirect, keep_body) = policy
+            .follow(&mut request, StatusCode::SEE_OTHER, &location("/orders/1"))
+            .unwrap();
+        assert!(!keep_body);
+        assert_eq!(request.method, Method::GET);
+        assert_eq!(redirect.to, "https://example.com/orders/1");
+        assert!(!request.headers.contains_key(CONTENT_TYPE));
+        assert!(request.headers.contains_key(AUTHORIZATION));
+
+        let mut request = parts(Method::PUT, "https://example.com/orders");
+        let (_, keep_body) = policy
+            .follow(
+                &mut request,
+                StatusCode::TEMPORARY_REDIRECT,
+                &location("/v2/orders"),
+            )
+            .unwrap();
+        assert!(keep_body);
+        assert_eq!(request.method, Method::PUT);
+
+        let mut request = parts(Method::GET, "https://example.com/");
+        assert!(
+            policy
+                .follow(&mut request, StatusCode::NOT_MODIFIED, &location("/x"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn cross_origin() {
+        let mut request = parts(Method::GET, "https://example.com/");
+        assert!(
+            RedirectPolicy::default()
+                .follow(
+                    &mut request,
+                    StatusCode::FOUND,
+                    &location("https://login.example.org/")
+                )
+                .is_some()
+        );
+        assert!(!request.headers.contains_key(AUTHORIZATION));
+
+        let same_origin = RedirectPolicy {
+            same_origin: true,
+            ..Default::default()
+        };
+        let mut request = parts(Method::GET, "https://example.com/");
+        assert!(
+            same_origin
+                .follow(
+                    &mut request,
+                    StatusCode::FOUND,
+                    &location("https://example.org/")
+                )
+                .is_none()
+        );
+        assert!(
+            same_origin
+                .follow(
+                    &mut request,
+                    StatusCode::FOUND,
+                    &location("https://example.com:443/x")
+                )
+                .is_some()
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use http::{HeaderMap, Method, StatusCode, header::RETRY_AFTER};
+
+use crate::http::{HttpError, HttpStreamResponse};
+
+/// How [`crate::client::ClientContext`] sends idempotent requests again after
+/// a failed connection or, optionally, a 5xx response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first, `0` never retries.
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for every one after it.
+    pub base_delay: Duration,
+    /// Longest wait between two attempts, including a server's `Retry-After`.
+    pub max_delay: Duration,
+    /// Also retry responses with a 5xx status, the last one is returned when
+    /// retries run out.
+    pub retry_server_errors: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            retry_server_errors: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry `attempt`, counting from `0`, or `None`
+    /// when a `method` request that got `result` is not sent again.
+    pub fn delay(
+        &self,
+        method: &Method,
+        result: &Result<HttpStreamResponse, HttpError>,
+        attempt: u32,
+    ) -> Option<Duration> {
+        if attempt >= self.max_retries || !method.is_idempotent() {
+            return None;
+        }
+        match result {
+            Err(err) if err.is_transient() => Some(self.jittered(attempt)),
+            Ok(response)
+                if self.retry_server_errors && response.parts().status.is_server_error() =>
+            {
+                let head = response.parts();
+                let delay = match retry_after(head.status, &head.headers) {
+                    Some(retry_after) => retry_after.max(self.backoff(attempt)),
+                    None => self.jittered(attempt),
+                };
+                Some(delay.min(self.max_delay))
+            }
+            _ => None,
+        }
+    }
+
+    /// The exponential delay before retry `attempt`, without jitter.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt))
+            .min(self.max_delay)
+    }
+
+    /// Somewhere between half and all of the backoff, so clients that failed
+    /// together do not all retry at the same moment.
+    fn jittered(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff(attempt);
+        let half = backoff / 2;
+        let spread = (backoff - half).as_millis() as u64;
+        if spread == 0 {
+            return backoff;
+        }
+        let random = RandomState::new().build_hasher().finish();
+        half + Duration::from_millis(random % (spread + 1))
+    }
+}
+
+/// The seconds a 503 or 429 asks to wait, HTTP dates are not supported.
+fn retry_after(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if status != StatusCode::SERVICE_UNAVAILABLE && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use http::{HeaderValue, Response};
+
+    use crate::http::HttpResponse;
+
+    use super::*;
+
+    fn response(status: StatusCode, retry_after: Option<&'static str>) -> HttpStreamResponse {
+        let mut builder = Response::builder().status(status);
+        if let Some(retry_after) = retry_after {
+            builder = builder.header(RETRY_AFTER, HeaderValue::from_static(retry_after));
+        }
+        HttpStreamResponse::Buffered(HttpResponse {
+            parts: builder.body(()).unwrap().into_parts().0,
+            body: Default::default(),
+            trailers: None,
+        })
+    }
+
+    #[test]
+    fn backs_off_with_jitter() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+            retry_server_errors: true,
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), Duration::from_millis(300));
+
+        let refused = Err(HttpError::ConnectTimeout);
+        for attempt in 0..3 {
+            let delay = policy.delay(&Method::GET, &refused, attempt).unwrap();
+            let backoff = policy.backoff(attempt);
+            assert!(delay >= backoff / 2 && delay <= backoff, "{delay:?}");
+        }
+        assert_eq!(policy.delay(&Method::GET, &refused, 3), None);
+    }
+
+    #[test]
+    fn retries_idempotent_failures() {
+        let policy = RetryPolicy::default();
+        let refused = Err(HttpError::ConnectTimeout);
+        assert!(policy.delay(&Method::PUT, &refused, 0).is_some());
+        assert_eq!(policy.delay(&Method::POST, &refused, 0), None);
+        assert_eq!(policy.delay(&Method::GET, &Err(HttpError::Uri), 0), None);
+
+        let unavailable = Ok(response(StatusCode::BAD_GATEWAY, None));
+        assert!(policy.delay(&Method::GET, &unavailable, 0).is_some());
+        let not_found = Ok(response(StatusCode::NOT_FOUND, None));
+        assert_eq!(policy.delay(&Method::GET, &not_found, 0), None);
+
+        let no_status = RetryPolicy {
+            retry_server_errors: false,
+            ..Default::default()
+        };
+        assert_eq!(no_status.delay(&Method::GET, &unavailable, 0), None);
+    }
+
+    #[test]
+    fn honours_retry_after() {
+        let policy = RetryPolicy::default();
+        let busy = Ok(response(StatusCode::SERVICE_UNAVAILABLE, Some("3")));
+        assert_eq!(
+            policy.delay(&Method::GET, &busy, 0),
+            Some(Duration::from_secs(3))
+        );
+        let long = Ok(response(StatusCode::SERVICE_UNAVAILABLE, Some("60")));
+        assert_eq!(policy.delay(&Method::GET, &long, 0), Some(policy.max_delay));
+    }
+}
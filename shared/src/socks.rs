@@ -0,0 +1,275 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::debug;
+
+use crate::{http::HttpError, uri::RUri};
+
+pub const SOCKS_DEFAULT_PORT: u16 = 1080;
+const SOCKS_VERSION: u8 = 0x05;
+const SOCKS_AUTH_NONE: u8 = 0x00;
+const SOCKS_AUTH_USER_PASS: u8 = 0x02;
+const SOCKS_AUTH_NO_ACCEPTABLE: u8 = 0xFF;
+const SOCKS_USER_PASS_VERSION: u8 = 0x01;
+const SOCKS_USER_PASS_FAILURE: u8 = 0x01;
+const SOCKS_CMD_CONNECT: u8 = 0x01;
+const SOCKS_ATYP_IPV4: u8 = 0x01;
+const SOCKS_ATYP_DOMAIN: u8 = 0x03;
+const SOCKS_ATYP_IPV6: u8 = 0x04;
+const SOCKS_REPLY_OK: u8 = 0x00;
+const SOCKS_REPLY_CMD_NOT_SUPPORTED: u8 = 0x07;
+const SOCKS_REPLY_ATYP_NOT_SUPPORTED: u8 = 0x08;
+
+/// Opens a tcp connection to `host:port` through the SOCKS5 proxy at `proxy`.
+///
+/// Username and password are taken from the proxy uri user info when present.
+pub async fn socks5_connect(proxy: &RUri, host: &str, port: u16) -> Result<TcpStream, HttpError> {
+    debug!("SOCKS5 connect {host}:{port} via {proxy}");
+    let proxy_addr = format!(
+        "{}:{}",
+        proxy.host(),
+        proxy.port_or_none().unwrap_or(SOCKS_DEFAULT_PORT)
+    );
+    let mut stream = TcpStream::connect(proxy_addr).await?;
+    let credentials = proxy
+        .inner
+        .authority()
+        .and_then(|a| a.as_str().rsplit_once('@'))
+        .map(|(user_info, _)| match user_info.split_once(':') {
+            Some((user, pass)) => (user.to_string(), pass.to_string()),
+            None => (user_info.to_string(), String::new()),
+        });
+
+    let methods: &[u8] = if credentials.is_some() {
+        &[SOCKS_AUTH_NONE, SOCKS_AUTH_USER_PASS]
+    } else {
+        &[SOCKS_AUTH_NONE]
+    };
+    let mut greeting = vec![SOCKS_VERSION, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    stream.write_all(&greeting).await?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice[0] != SOCKS_VERSION {
+        return Err(HttpError::ProxyConnect);
+    }
+    match (choice[1], credentials) {
+        (SOCKS_AUTH_NONE, _) => {}
+        (SOCKS_AUTH_USER_PASS, Some((user, pass))) => {
+            if user.len() > u8::MAX as usize || pass.len() > u8::MAX as usize {
+                return Err(HttpError::ProxyConnect);
+            }
+            let mut auth = vec![SOCKS_USER_PASS_VERSION, user.len() as u8];
+            auth.extend_from_slice(user.as_bytes());
+            auth.push(pass.len() as u8);
+            auth.extend_from_slice(pass.as_bytes());
+            stream.write_all(&auth).await?;
+
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != SOCKS_REPLY_OK {
+                return Err(HttpError::ProxyConnect);
+            }
+        }
+        _ => return Err(HttpError::ProxyConnect),
+    }
+
+    let mut request = vec![SOCKS_VERSION, SOCKS_CMD_CONNECT, 0x00];
+    write_addr(&mut request, host)?;
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 3];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION || reply[1] != SOCKS_REPLY_OK {
+        debug!("SOCKS5 connect rejected {}", reply[1]);
+        return Err(HttpError::ProxyConnect);
+    }
+    // Bound address and port, not needed for CONNECT
+    read_addr(&mut stream).await?;
+    stream.read_exact(&mut [0u8; 2]).await?;
+
+    Ok(stream)
+}
+
+/// Checks the username and password a SOCKS5 client authenticates with.
+pub type SocksVerify<'a> = &'a (dyn Fn(&str, &str) -> bool + Sync);
+
+/// Performs the server side of a SOCKS5 handshake on `stream`.
+///
+/// Only CONNECT is supported. With `verify` clients must authenticate with a
+/// username and password (RFC 1929), without it no authentication is asked
+/// for. On success the success reply has been written and the requested
+/// `(host, port)` is returned, the caller owns the stream from here on.
+pub async fn socks5_accept<S>(
+    stream: &mut S,
+    verify: Option<SocksVerify<'_>>,
+) -> Result<(String, u16), HttpError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut greeting = [0u8; 2];
+    stream.read_exact(&mut greeting).await?;
+    if greeting[0] != SOCKS_VERSION {
+        return Err(HttpError::ProxyConnect);
+    }
+    let mut methods = vec![0u8; greeting[1] as usize];
+    stream.read_exact(&mut methods).await?;
+    let method = match verify {
+        Some(_) => SOCKS_AUTH_USER_PASS,
+        None => SOCKS_AUTH_NONE,
+    };
+    if !methods.contains(&method) {
+        stream
+            .write_all(&[SOCKS_VERSION, SOCKS_AUTH_NO_ACCEPTABLE])
+            .await?;
+        return Err(HttpError::ProxyConnect);
+    }
+    stream.write_all(&[SOCKS_VERSION, method]).await?;
+    if let Some(verify) = verify {
+        accept_user_pass(stream, verify).await?;
+    }
+
+    let mut request = [0u8; 3];
+    stream.read_exact(&mut request).await?;
+    if request[0] != SOCKS_VERSION {
+        return Err(HttpError::ProxyConnect);
+    }
+    if request[1] != SOCKS_CMD_CONNECT {
+        write_reply(stream, SOCKS_REPLY_CMD_NOT_SUPPORTED).await?;
+        return Err(HttpError::ProxyConnect);
+    }
+    let host = match read_addr(stream).await {
+        Ok(host) => host,
+        Err(err) => {
+            write_reply(stream, SOCKS_REPLY_ATYP_NOT_SUPPORTED).await?;
+            return Err(err);
+        }
+    };
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port).await?;
+    let port = u16::from_be_bytes(port);
+
+    write_reply(stream, SOCKS_REPLY_OK).await?;
+    debug!("SOCKS5 accepted {host}:{port}");
+    Ok((host, port))
+}
+
+/// Reads the username and password of a client and answers whether `verify`
+/// accepts them, a rejected client gets an error.
+async fn accept_user_pass<S>(stream: &mut S, verify: SocksVerify<'_>) -> Result<(), HttpError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut version = [0u8; 1];
+    stream.read_exact(&mut version).await?;
+    if version[0] != SOCKS_USER_PASS_VERSION {
+        return Err(HttpError::ProxyConnect);
+    }
+    let user = read_field(stream).await?;
+    let password = read_field(stream).await?;
+    let accepted = match (String::from_utf8(user), String::from_utf8(password)) {
+        (Ok(user), Ok(password)) => verify(&user, &password),
+        _ => false,
+    };
+    let status = if accepted {
+        SOCKS_REPLY_OK
+    } else {
+        SOCKS_USER_PASS_FAILURE
+    };
+    stream.write_all(&[SOCKS_USER_PASS_VERSION, status]).await?;
+    if !accepted {
+        debug!("SOCKS5 client credentials rejected");
+        return Err(HttpError::ProxyConnect);
+    }
+    Ok(())
+}
+
+/// Reads a length prefixed field.
+async fn read_field<S>(stream: &mut S) -> Result<Vec<u8>, HttpError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut len = [0u8; 1];
+    stream.read_exact(&mut len).await?;
+    let mut field = vec![0u8; len[0] as usize];
+    stream.read_exact(&mut field).await?;
+    Ok(field)
+}
+
+async fn write_reply<S>(stream: &mut S, reply: u8) -> Result<(), HttpError>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream
+        .write_all(&[
+            SOCKS_VERSION,
+            reply,
+            0x00,
+            SOCKS_ATYP_IPV4,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ])
+        .await?;
+    Ok(())
+}
+
+fn write_addr(buf: &mut Vec<u8>, host: &str) -> Result<(), HttpError> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            buf.push(SOCKS_ATYP_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            buf.push(SOCKS_ATYP_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > u8::MAX as usize {
+                return Err(HttpError::BadHost);
+            }
+            buf.push(SOCKS_ATYP_DOMAIN);
+            buf.push(host.len() as u8);
+            buf.extend_from_slice(host.as_bytes());
+        }
+    }
+    Ok(())
+}
+
+/// Reads an address type and address, ipv6 hosts are returned in brackets.
+async fn read_addr<S>(stream: &mut S) -> Result<String, HttpError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut atyp = [0u8; 1];
+    stream.read_exact(&mut atyp).await?;
+    match atyp[0] {
+        SOCKS_ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            Ok(Ipv4Addr::from(ip).to_string())
+        }
+        SOCKS_ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            Ok(format!("[{}]", Ipv6Addr::from(ip)))
+        }
+        SOCKS_ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            let mut name = vec![0u8; len[0] as usize];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|_| HttpError::BadHost)
+        }
+        _ => Err(HttpError::BadHost),
+    }
+}
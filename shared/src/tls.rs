@@ -1,15 +1,19 @@
-use std::{error::Error, sync::Arc};
+use std::{
+    error::Error,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
 
 use hyper_util::rt::tokio::WithHyperIo;
 use rustls::{
-    ClientConfig, RootCertStore, ServerConfig, SupportedCipherSuite,
+    ClientConfig, KeyLog, RootCertStore, ServerConfig, SupportedCipherSuite,
     crypto::CryptoProvider,
-    pki_types::ServerName,
+    pki_types::{CertificateDer, ServerName},
     sign::CertifiedKey,
     version::{TLS12, TLS13},
 };
 use tokio::net::TcpStream;
-use tracing::{error, trace};
+use tracing::{debug, error, trace, warn};
 
 use crate::{
     RoxyCA,
@@ -21,11 +25,16 @@ use crate::{
     crypto::init_crypto,
     http::{HttpEmitter, HttpError, HttpEvent},
     io::IOTypeNotSend,
+    keylog::KeyLogWriter,
+    upstream::host_matches,
 };
 
 #[derive(Debug, Clone)]
 pub struct TlsConfig {
     crypto_provider: Arc<CryptoProvider>,
+    extra_roots: Vec<CertificateDer<'static>>,
+    insecure_hosts: Vec<String>,
+    key_log: Option<Arc<KeyLogWriter>>,
 }
 
 impl Default for TlsConfig {
@@ -58,24 +67,99 @@ impl TlsConfig {
         };
         Self {
             crypto_provider: Arc::new(crypto_provider),
+            extra_roots: vec![],
+            insecure_hosts: vec![],
+            key_log: None,
         }
     }
 
+    /// Trusts `certs` for upstream connections on top of the system roots,
+    /// e.g. a corporate CA.
+    pub fn with_extra_roots(mut self, certs: Vec<CertificateDer<'static>>) -> Self {
+        self.extra_roots = certs;
+        self
+    }
+
+    /// Upstream certificates of hosts matching any of `patterns` are not verified.
+    pub fn with_insecure_hosts(mut self, patterns: Vec<String>) -> Self {
+        self.insecure_hosts = patterns;
+        self
+    }
+
+    /// Writes the secrets of client-facing and upstream TLS sessions to
+    /// `key_log` so captures of the traffic can be decrypted.
+    pub fn with_key_log(mut self, key_log: Option<KeyLogWriter>) -> Self {
+        self.key_log = key_log.map(Arc::new);
+        self
+    }
+
+    /// Set on rustls configs built outside of this type, e.g. for QUIC.
+    pub fn key_log(&self) -> Option<Arc<dyn KeyLog>> {
+        self.key_log
+            .clone()
+            .map(|key_log| key_log as Arc<dyn KeyLog>)
+    }
+
+    pub fn verifies(&self, host: &str) -> bool {
+        !self
+            .insecure_hosts
+            .iter()
+            .any(|pattern| host_matches(pattern, host))
+    }
+
+    /// `root_store` along with the extra roots.
+    pub fn roots(&self, root_store: Arc<RootCertStore>) -> Arc<RootCertStore> {
+        if self.extra_roots.is_empty() {
+            return root_store;
+        }
+        let mut roots = (*root_store).clone();
+        let (added, ignored) = roots.add_parsable_certificates(self.extra_roots.iter().cloned());
+        if ignored > 0 {
+            warn!("Ignored {ignored} invalid extra roots, added {added}");
+        }
+        Arc::new(roots)
+    }
+
     pub fn crypto_provider(&self) -> Arc<CryptoProvider> {
         self.crypto_provider.clone()
     }
 
-    pub fn rustls_client_config(&self, root_store: Arc<RootCertStore>) -> RustlsClientConfig {
-        let cert_logger = Arc::new(LoggingServerVerifier::with_root_store_provider(
-            root_store.clone(),
-            self.crypto_provider.clone(),
-        ));
+    /// Equal for configs that verify and negotiate the same way, clones of a
+    /// config share the provider so they always are.
+    pub(crate) fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        Arc::as_ptr(&self.crypto_provider).hash(&mut hasher);
+        self.extra_roots.hash(&mut hasher);
+        self.insecure_hosts.hash(&mut hasher);
+        self.key_log.is_some().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Client config for connecting to `host`, verification is skipped when
+    /// `host` is an insecure host.
+    pub fn rustls_client_config(
+        &self,
+        root_store: Arc<RootCertStore>,
+        host: &str,
+    ) -> RustlsClientConfig {
+        let cert_logger = if self.verifies(host) {
+            Arc::new(LoggingServerVerifier::with_root_store_provider(
+                self.roots(root_store),
+                self.crypto_provider.clone(),
+            ))
+        } else {
+            debug!("Skipping certificate verification for {host}");
+            Arc::new(LoggingServerVerifier::new())
+        };
         let resolver = Arc::new(LoggingResolvesClientCert::default());
 
-        let client_config = ClientConfig::builder()
+        let mut client_config = ClientConfig::builder()
             .dangerous()
             .with_custom_certificate_verifier(cert_logger.clone())
             .with_client_cert_resolver(resolver.clone());
+        if let Some(key_log) = self.key_log() {
+            client_config.key_log = key_log;
+        }
         RustlsClientConfig {
             cert_logger,
             resolver,
@@ -97,10 +181,13 @@ impl TlsConfig {
             })
             .collect::<Vec<_>>();
         let resolver = Arc::new(LoggingResolvesServerCert::new(certified_key));
-        let server_config = ServerConfig::builder_with_provider(self.crypto_provider.clone())
+        let mut server_config = ServerConfig::builder_with_provider(self.crypto_provider.clone())
             .with_protocol_versions(versions.as_slice())?
             .with_no_client_auth()
             .with_cert_resolver(resolver.clone());
+        if let Some(key_log) = self.key_log() {
+            server_config.key_log = key_log;
+        }
 
         Ok(RustlsServerConfig {
             resolver,
@@ -127,7 +214,7 @@ pub async fn client_tls(
         cert_logger,
         resolver: _,
         mut client_config,
-    } = tls_config.rustls_client_config(root_store);
+    } = tls_config.rustls_client_config(root_store, &server_name.to_str());
 
     client_config.enable_sni = true;
     client_config.alpn_protocols = alpn_protocols;
@@ -202,3 +289,30 @@ pub async fn client_tls_native(
     trace!("TLS end");
     Ok((Box::new(IOTypeNotSend::new_raw(tls)), alpn))
 }
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insecure_hosts() {
+        let config = TlsConfig::default().with_insecure_hosts(vec!["*.corp.internal".into()]);
+        assert!(!config.verifies("api.corp.internal"));
+        assert!(config.verifies("corp.internal"));
+        assert!(config.verifies("example.com"));
+        assert!(TlsConfig::default().verifies("api.corp.internal"));
+    }
+
+    #[test]
+    fn extra_roots() {
+        let roots = Arc::new(RootCertStore::empty());
+        let config = TlsConfig::default();
+        assert!(Arc::ptr_eq(&config.roots(roots.clone()), &roots));
+
+        let ca = rcgen::generate_simple_self_signed(vec!["corp.internal".into()]).unwrap();
+        let config = config.with_extra_roots(vec![ca.cert.der().clone()]);
+        assert_eq!(config.roots(roots.clone()).len(), 1);
+        assert!(roots.is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+//! Bytes of HTTP/1 connections exactly as they were read, before hyper
+//! parses them, kept per message while raw capture is on.
+//!
+//! A connection carries one message after another, so its reads go to the
+//! [`WireCapture`] of the message currently being read, switched through the
+//! connection's [`WireTarget`].
+
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::error;
+
+/// Bytes kept of each message.
+pub const WIRE_CAPTURE_LIMIT: usize = 1024 * 1024;
+
+/// The bytes of one message as read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WireBytes {
+    pub data: Bytes,
+    /// Bytes read, including ones past the capture limit.
+    pub total: usize,
+}
+
+impl WireBytes {
+    /// Whether more was read than kept.
+    pub fn truncated(&self) -> bool {
+        self.total > self.data.len()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Captured {
+    data: Vec<u8>,
+    total: usize,
+}
+
+/// Collects the bytes of one message while its connection reads them, shared
+/// by clones.
+#[derive(Debug, Clone, Default)]
+pub struct WireCapture(Arc<Mutex<Captured>>);
+
+impl PartialEq for WireCapture {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for WireCapture {}
+
+impl WireCapture {
+    pub fn push(&self, bytes: &[u8]) {
+        match self.0.lock() {
+            Ok(mut captured) => {
+                captured.total += bytes.len();
+                let room = WIRE_CAPTURE_LIMIT.saturating_sub(captured.data.len());
+                captured
+                    .data
+                    .extend_from_slice(&bytes[..room.min(bytes.len())]);
+            }
+            Err(err) => error!("Wire capture lock poisoned {err}"),
+        }
+    }
+
+    /// Gives back the last `len` bytes pushed, read ahead from the next
+    /// message.
+    pub fn trim_end(&self, len: usize) {
+        match self.0.lock() {
+            Ok(mut captured) => {
+                captured.total = captured.total.saturating_sub(len);
+                let total = captured.total;
+                captured.data.truncate(total);
+            }
+            Err(err) => error!("Wire capture lock poisoned {err}"),
+        }
+    }
+
+    /// The bytes captured so far.
+    pub fn bytes(&self) -> WireBytes {
+        match self.0.lock() {
+            Ok(captured) => WireBytes {
+                data: Bytes::copy_from_slice(&captured.data),
+                total: captured.total,
+            },
+            Err(err) => {
+                error!("Wire capture lock poisoned {err}");
+                WireBytes::default()
+            }
+        }
+    }
+}
+
+/// The capture the reads of a connection currently go to, none when raw
+/// capture is off.
+#[derive(Debug, Clone, Default)]
+pub struct WireTarget(Arc<Mutex<Option<WireCapture>>>);
+
+impl WireTarget {
+    /// Sends further reads to `capture`, returning the one they went to.
+    pub fn set(&self, capture: Option<WireCapture>) -> Option<WireCapture> {
+        match self.0.lock() {
+            Ok(mut target) => std::mem::replace(&mut *target, capture),
+            Err(err) => {
+                error!("Wire target lock poisoned {err}");
+                None
+            }
+        }
+    }
+
+    pub fn push(&self, bytes: &[u8]) {
+        match self.0.lock() {
+            Ok(target) => {
+                if let Some(capture) = target.as_ref() {
+                    capture.push(bytes);
+                }
+            }
+            Err(err) => error!("Wire target lock poisoned {err}"),
+        }
+    }
+}
+
+/// Passes a stream through, copying what is read from it to a [`WireTarget`].
+pub struct WireRecorder<S> {
+    stream: S,
+    target: WireTarget,
+}
+
+impl<S> WireRecorder<S> {
+    pub fn new(stream: S) -> (Self, WireTarget) {
+        let target = WireTarget::default();
+        (
+            Self {
+                stream,
+                target: target.clone(),
+            },
+            target,
+        )
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for WireRecorder<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let polled = Pin::new(&mut self.stream).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = polled {
+            self.target.push(&buf.filled()[before..]);
+        }
+        polled
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WireRecorder<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_go_to_the_current_capture() {
+        let (client, mut server) = duplex(64);
+        let (mut recorder, target) = WireRecorder::new(client);
+        let first = WireCapture::default();
+        let second = WireCapture::default();
+        let mut buf = [0u8; 64];
+
+        target.set(Some(first.clone()));
+        server.write_all(b"HTTP/1.1 200 OK\r\n").await.unwrap();
+        let n = recorder.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"HTTP/1.1 200 OK\r\n");
+
+        assert_eq!(target.set(Some(second.clone())), Some(first.clone()));
+        server.write_all(b"next").await.unwrap();
+        recorder.read(&mut buf).await.unwrap();
+
+        assert_eq!(first.bytes().data, "HTTP/1.1 200 OK\r\n");
+        assert_eq!(second.bytes().data, "next");
+    }
+
+    #[test]
+    fn keeps_up_to_the_limit() {
+        let capture = WireCapture::default();
+        capture.push(&vec![1u8; WIRE_CAPTURE_LIMIT - 1]);
+        capture.push(b"abc");
+        let bytes = capture.bytes();
+        assert_eq!(bytes.data.len(), WIRE_CAPTURE_LIMIT);
+        assert_eq!(bytes.total, WIRE_CAPTURE_LIMIT + 2);
+        assert!(bytes.truncated());
+
+        capture.trim_end(4);
+        let bytes = capture.bytes();
+        assert_eq!(bytes.total, WIRE_CAPTURE_LIMIT - 2);
+        assert_eq!(bytes.data.len(), WIRE_CAPTURE_LIMIT - 2);
+        assert!(!bytes.truncated());
+    }
+}